@@ -0,0 +1,34 @@
+//! Arbitrary drawing into piet-svg shouldn't panic, and the document should
+//! grow with the drawing, not with the values of its coordinates.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use piet_fuzz::{draw, Op, MAX_OPS};
+
+/// The most bytes of SVG for each unit of [`Op::size`]: enough for a curve
+/// with six numbers at their longest, its transform, clip and style.
+const BYTES_PER_OP: usize = 1024;
+
+fuzz_target!(|ops: Vec<Op>| {
+    let mut rc = piet_svg::RenderContext::new((256.0, 256.0).into());
+    draw(&mut rc, &ops);
+    let mut svg = Vec::new();
+    rc.write(&mut svg).unwrap();
+    let svg = String::from_utf8(svg).unwrap();
+
+    // embedded fonts are as big as their files, however much text is drawn.
+    let fonts: usize = svg
+        .match_indices("@font-face")
+        .map(|(start, _)| svg[start..].find('}').unwrap_or(0))
+        .sum();
+    let size: usize = ops.iter().take(MAX_OPS).map(Op::size).sum();
+    assert!(
+        svg.len() - fonts <= 1024 + size * BYTES_PER_OP,
+        "{} bytes of SVG, and {} of fonts, from {} ops of size {}",
+        svg.len() - fonts,
+        fonts,
+        ops.len(),
+        size
+    );
+});
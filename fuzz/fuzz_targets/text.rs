@@ -0,0 +1,44 @@
+//! Laying out arbitrary text with piet-svg shouldn't panic, and the layout
+//! should only return offsets into the text, on character boundaries.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use piet::kurbo::Point;
+use piet::{Text, TextAttribute, TextLayout, TextLayoutBuilder};
+
+fuzz_target!(|input: (String, f64, f64, Vec<(f64, f64)>)| {
+    let (text, size, max_width, points) = input;
+    let layout = match piet_svg::Text::new()
+        .new_text_layout(text.clone())
+        .default_attribute(TextAttribute::FontSize(size))
+        .max_width(max_width)
+        .build()
+    {
+        Ok(layout) => layout,
+        Err(_) => return,
+    };
+    let on_boundary = |idx: usize| idx <= text.len() && text.is_char_boundary(idx);
+
+    let lines = layout.line_count();
+    assert!(lines <= text.len() + 1, "{} lines", lines);
+    for line in 0..lines {
+        let metric = layout.line_metric(line).unwrap();
+        assert!(on_boundary(metric.start_offset), "{:?}", metric);
+        assert!(on_boundary(metric.end_offset), "{:?}", metric);
+        assert!(layout.line_text(line).is_some());
+    }
+
+    for idx in (0..=text.len()).filter(|idx| text.is_char_boundary(*idx)) {
+        let position = layout.hit_test_text_position(idx);
+        assert!(position.line <= lines, "{:?}", position);
+        assert!(on_boundary(layout.next_caret(idx)));
+        assert!(on_boundary(layout.prev_caret(idx)));
+    }
+    for &(x, y) in points.iter().take(16) {
+        let hit = layout.hit_test_point(Point::new(x, y));
+        assert!(on_boundary(hit.idx), "{:?}", hit);
+    }
+    layout.rects_for_range(..);
+    layout.image_bounds();
+});
@@ -0,0 +1,16 @@
+//! Arbitrary drawing into piet-tiny-skia shouldn't panic.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use piet_fuzz::{draw, Op};
+use piet_tiny_skia::tiny_skia::Pixmap;
+use piet_tiny_skia::TinySkiaRenderContext;
+
+fuzz_target!(|input: (bool, Vec<Op>)| {
+    let (scaled, ops) = input;
+    let mut pixmap = Pixmap::new(64, 64).unwrap();
+    let scale = if scaled { 2.0 } else { 1.0 };
+    let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), scale);
+    draw(&mut rc, &ops);
+});
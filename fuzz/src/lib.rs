@@ -0,0 +1,226 @@
+//! Drawing made from arbitrary input, for the fuzz targets.
+//!
+//! Coordinates, widths and transforms are arbitrary `f64`s, so they are
+//! often NaN, infinite or huge; paths can have empty subpaths and repeated
+//! closes. The only thing that is always true is that paths begin with a
+//! `MoveTo`, because that is required by kurbo.
+
+use arbitrary::Arbitrary;
+use piet::kurbo::{Affine, BezPath, Circle, Line, PathEl, Point, Rect, RoundedRect};
+use piet::{
+    Color, LineCap, LineJoin, RenderContext, StrokeStyle, Text, TextAttribute, TextLayoutBuilder,
+};
+
+/// The most operations drawn from one input, so that each run is quick.
+pub const MAX_OPS: usize = 64;
+
+#[derive(Arbitrary, Clone, Copy, Debug)]
+pub struct Pt(f64, f64);
+
+#[derive(Arbitrary, Clone, Debug)]
+pub enum Element {
+    MoveTo(Pt),
+    LineTo(Pt),
+    QuadTo(Pt, Pt),
+    CurveTo(Pt, Pt, Pt),
+    ClosePath,
+}
+
+#[derive(Arbitrary, Clone, Debug)]
+pub struct Path {
+    start: Pt,
+    elements: Vec<Element>,
+}
+
+#[derive(Arbitrary, Clone, Debug)]
+pub enum Shape {
+    Path(Path),
+    Rect(Pt, Pt),
+    RoundedRect(Pt, Pt, f64),
+    Circle(Pt, f64),
+    Line(Pt, Pt),
+}
+
+#[derive(Arbitrary, Clone, Debug)]
+pub struct Style {
+    join: u8,
+    miter_limit: f64,
+    cap: u8,
+    dashes: Vec<f64>,
+    dash_offset: f64,
+}
+
+#[derive(Arbitrary, Clone, Debug)]
+pub enum Op {
+    Fill(Shape, u32),
+    FillEvenOdd(Shape, u32),
+    Stroke(Shape, u32, f64, Option<Style>),
+    Clip(Shape),
+    Transform([f64; 6]),
+    Save,
+    Restore,
+    Text {
+        text: String,
+        size: f64,
+        max_width: f64,
+        bold: bool,
+        origin: Pt,
+    },
+}
+
+impl From<Pt> for Point {
+    fn from(pt: Pt) -> Point {
+        Point::new(pt.0, pt.1)
+    }
+}
+
+impl Path {
+    pub fn to_bez_path(&self) -> BezPath {
+        let mut elements = vec![PathEl::MoveTo(self.start.into())];
+        elements.extend(self.elements.iter().map(|el| match *el {
+            Element::MoveTo(p) => PathEl::MoveTo(p.into()),
+            Element::LineTo(p) => PathEl::LineTo(p.into()),
+            Element::QuadTo(p1, p2) => PathEl::QuadTo(p1.into(), p2.into()),
+            Element::CurveTo(p1, p2, p3) => PathEl::CurveTo(p1.into(), p2.into(), p3.into()),
+            Element::ClosePath => PathEl::ClosePath,
+        }));
+        BezPath::from_vec(elements)
+    }
+}
+
+impl Shape {
+    /// The number of elements in the shape's path, roughly.
+    fn size(&self) -> usize {
+        match self {
+            Shape::Path(path) => path.elements.len() + 1,
+            _ => 8,
+        }
+    }
+}
+
+impl Style {
+    fn to_stroke_style(&self) -> StrokeStyle {
+        let join = match self.join % 3 {
+            0 => LineJoin::Miter {
+                limit: self.miter_limit,
+            },
+            1 => LineJoin::Round,
+            _ => LineJoin::Bevel,
+        };
+        let cap = match self.cap % 3 {
+            0 => LineCap::Butt,
+            1 => LineCap::Round,
+            _ => LineCap::Square,
+        };
+        let mut style = StrokeStyle::new()
+            .line_join(join)
+            .line_cap(cap)
+            .dash_offset(self.dash_offset);
+        style.set_dash_pattern(self.dashes.as_slice());
+        style
+    }
+}
+
+impl Op {
+    /// A rough measure of how much drawing the operation is, for bounding
+    /// the size of the output.
+    pub fn size(&self) -> usize {
+        match self {
+            Op::Fill(shape, _) | Op::FillEvenOdd(shape, _) | Op::Clip(shape) => shape.size(),
+            Op::Stroke(shape, _, _, style) => {
+                shape.size() + style.as_ref().map_or(0, |style| style.dashes.len())
+            }
+            Op::Text { text, .. } => text.chars().count() + 1,
+            _ => 1,
+        }
+    }
+}
+
+/// Evaluate `$body` with `$s` bound to the kurbo shape of `$shape`, so
+/// that the backends' special cases for rectangles and circles are used.
+macro_rules! with_shape {
+    ($shape:expr, |$s:ident| $body:expr) => {
+        match *$shape {
+            Shape::Path(ref path) => {
+                let $s = path.to_bez_path();
+                $body
+            }
+            Shape::Rect(p0, p1) => {
+                let $s = Rect::from_points(p0, p1);
+                $body
+            }
+            Shape::RoundedRect(p0, p1, radius) => {
+                let $s = RoundedRect::from_rect(Rect::from_points(p0, p1), radius);
+                $body
+            }
+            Shape::Circle(center, radius) => {
+                let $s = Circle::new(center, radius);
+                $body
+            }
+            Shape::Line(p0, p1) => {
+                let $s = Line::new(p0, p1);
+                $body
+            }
+        }
+    };
+}
+
+/// Draw the first [`MAX_OPS`] operations into `rc`, ignoring errors, which
+/// are expected for input like this.
+pub fn draw(rc: &mut impl RenderContext, ops: &[Op]) {
+    for op in ops.iter().take(MAX_OPS) {
+        match op {
+            Op::Fill(shape, color) => {
+                let color = Color::from_rgba32_u32(*color);
+                with_shape!(shape, |s| rc.fill(s, &color))
+            }
+            Op::FillEvenOdd(shape, color) => {
+                let color = Color::from_rgba32_u32(*color);
+                with_shape!(shape, |s| rc.fill_even_odd(s, &color))
+            }
+            Op::Stroke(shape, color, width, style) => {
+                let color = Color::from_rgba32_u32(*color);
+                match style {
+                    Some(style) => {
+                        let style = style.to_stroke_style();
+                        with_shape!(shape, |s| rc.stroke_styled(s, &color, *width, &style))
+                    }
+                    None => with_shape!(shape, |s| rc.stroke(s, &color, *width)),
+                }
+            }
+            Op::Clip(shape) => with_shape!(shape, |s| rc.clip(s)),
+            Op::Transform(coeffs) => rc.transform(Affine::new(*coeffs)),
+            Op::Save => {
+                let _ = rc.save();
+            }
+            Op::Restore => {
+                let _ = rc.restore();
+            }
+            Op::Text {
+                text,
+                size,
+                max_width,
+                bold,
+                origin,
+            } => {
+                let mut builder = rc
+                    .text()
+                    .new_text_layout(text.clone())
+                    .default_attribute(TextAttribute::FontSize(*size))
+                    .max_width(*max_width);
+                if *bold {
+                    let mut half = text.len() / 2;
+                    while !text.is_char_boundary(half) {
+                        half -= 1;
+                    }
+                    builder = builder
+                        .range_attribute(..half, TextAttribute::Weight(piet::FontWeight::BOLD));
+                }
+                if let Ok(layout) = builder.build() {
+                    rc.draw_text(&layout, *origin);
+                }
+            }
+        }
+    }
+    let _ = rc.finish();
+}
@@ -0,0 +1,61 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use piet::kurbo::Size;
+use piet::RenderContext;
+use piet_bench::{Fixture, Workload};
+use piet_tiny_skia::tiny_skia::Pixmap;
+use piet_tiny_skia::TinySkiaRenderContext;
+
+/// The size of the bitmaps drawn into.
+const SIZE: Size = Size::new(400.0, 400.0);
+
+/// Benchmark each workload, comparing the backends in each group.
+pub fn bench_backends(c: &mut Criterion) {
+    let fixture = Fixture::new();
+    for workload in Workload::ALL {
+        let mut group = c.benchmark_group(workload.name());
+
+        group.bench_function("tiny-skia", |b| {
+            let mut pixmap = Pixmap::new(SIZE.width as u32, SIZE.height as u32).unwrap();
+            let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+            b.iter(|| workload.run(&mut rc, &fixture).unwrap());
+            rc.finish().unwrap();
+        });
+
+        // the svg and recording contexts keep everything that is drawn, so
+        // each iteration has a new one, to keep them from growing.
+        group.bench_function("svg", |b| {
+            b.iter_batched_ref(
+                || piet_svg::RenderContext::new(SIZE),
+                |rc| workload.run(rc, &fixture).unwrap(),
+                BatchSize::SmallInput,
+            );
+        });
+
+        group.bench_function("recorder", |b| {
+            b.iter_batched_ref(
+                || piet_recorder::RecordingRenderContext::new(piet_svg::Text::new()),
+                |rc| workload.run(rc, &fixture).unwrap(),
+                BatchSize::SmallInput,
+            );
+        });
+
+        #[cfg(feature = "cairo")]
+        group.bench_function("cairo", |b| {
+            use cairo::{Context, Format, ImageSurface};
+            use piet_cairo::CairoRenderContext;
+
+            let surface =
+                ImageSurface::create(Format::ARgb32, SIZE.width as i32, SIZE.height as i32)
+                    .unwrap();
+            let cr = Context::new(&surface).unwrap();
+            let mut rc = CairoRenderContext::new(&cr);
+            b.iter(|| workload.run(&mut rc, &fixture).unwrap());
+            rc.finish().unwrap();
+        });
+
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_backends);
+criterion_main!(benches);
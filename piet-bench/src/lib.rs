@@ -0,0 +1,273 @@
+//! Benchmarks of the hot paths of piet backends.
+//!
+//! Each [`Workload`] exercises one part of the [`RenderContext`] API that
+//! applications call many times a frame, such as converting paths and
+//! uploading images, and can be run against any backend, so that backends
+//! can be compared and regressions caught.
+//!
+//! The `backends` benchmark runs every workload against the tiny-skia, svg
+//! and recorder backends with [criterion], and against cairo with the
+//! `cairo` feature:
+//!
+//! ```sh
+//! cargo bench -p piet-bench --features cairo
+//! ```
+//!
+//! Criterion doesn't run in browsers, so for piet-web, and other backends
+//! that only run in wasm hosts, [`measure`] times workloads with a clock
+//! that the host provides; see `piet-web/tests/bench.rs`.
+//!
+//! [criterion]: https://docs.rs/criterion
+
+#![deny(clippy::trivially_copy_pass_by_ref)]
+
+use std::sync::Arc;
+
+use piet::kurbo::{BezPath, Line, Point, Rect};
+use piet::{
+    Color, Error, FixedLinearGradient, FixedRadialGradient, FontFamily, GradientStop, ImageFormat,
+    RenderContext, StrokeStyle, Text, TextLayoutBuilder,
+};
+
+/// The width and height of the images made by [`Workload::MakeImage`].
+pub const IMAGE_SIZE: usize = 512;
+
+/// The number of cubic segments in the path drawn by [`Workload::SetPath`].
+pub const PATH_SEGMENTS: usize = 1000;
+
+/// The number of lines in each direction of the grid drawn by
+/// [`Workload::Grid`].
+pub const GRID_LINES: usize = 100;
+
+/// The image formats, in the order of the [`Workload::MakeImage`] workloads
+/// in [`Workload::ALL`].
+const FORMATS: [ImageFormat; 4] = [
+    ImageFormat::Grayscale,
+    ImageFormat::Rgb,
+    ImageFormat::RgbaSeparate,
+    ImageFormat::RgbaPremul,
+];
+
+/// Something to time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Workload {
+    /// Fill and stroke a path of [`PATH_SEGMENTS`] curves, which backends
+    /// convert into their own paths.
+    SetPath,
+    /// Stroke a grid of [`GRID_LINES`] thin dashed lines each way, all with
+    /// the same style, as in a chart or an editor's background.
+    Grid,
+    /// Make linear and radial gradient brushes.
+    Gradients,
+    /// Lay out a few paragraphs of text, wrapped to a width.
+    TextLayout,
+    /// Make an image of [`IMAGE_SIZE`] pixels square from pixels in a
+    /// format, which most backends convert.
+    MakeImage(ImageFormat),
+}
+
+/// The data that the workloads draw, made once so that making it isn't
+/// timed.
+#[derive(Clone, Debug)]
+pub struct Fixture {
+    path: BezPath,
+    text: Arc<str>,
+    pixels: Vec<Vec<u8>>,
+}
+
+/// How long a workload took, as timed by [`measure`].
+#[derive(Clone, Copy, Debug)]
+pub struct Measurement {
+    /// The workload that was timed.
+    pub workload: Workload,
+    /// The number of times the workload ran.
+    pub iterations: usize,
+    /// The total time taken, in milliseconds.
+    pub total_ms: f64,
+}
+
+impl Workload {
+    /// Every workload, with image uploads in every format.
+    pub const ALL: [Workload; 8] = [
+        Workload::SetPath,
+        Workload::Grid,
+        Workload::Gradients,
+        Workload::TextLayout,
+        Workload::MakeImage(FORMATS[0]),
+        Workload::MakeImage(FORMATS[1]),
+        Workload::MakeImage(FORMATS[2]),
+        Workload::MakeImage(FORMATS[3]),
+    ];
+
+    /// The name of the workload, such as `"make_image_RgbaPremul"`.
+    pub fn name(&self) -> String {
+        match self {
+            Workload::SetPath => "set_path".into(),
+            Workload::Grid => "grid".into(),
+            Workload::Gradients => "gradients".into(),
+            Workload::TextLayout => "text_layout".into(),
+            Workload::MakeImage(format) => format!("make_image_{:?}", format),
+        }
+    }
+
+    /// Run the workload once against `rc`.
+    pub fn run<R: RenderContext>(&self, rc: &mut R, fixture: &Fixture) -> Result<(), Error> {
+        match *self {
+            Workload::SetPath => {
+                rc.fill(&fixture.path, &Color::rgb8(0x40, 0x80, 0xc0));
+                rc.stroke(&fixture.path, &Color::BLACK, 2.0);
+            }
+            Workload::Grid => {
+                let style = StrokeStyle::new().dash_pattern(&[2.0, 2.0]);
+                let color = Color::grey8(0xc0);
+                for i in 0..=GRID_LINES {
+                    let offset = i as f64 * 4.0;
+                    let extent = GRID_LINES as f64 * 4.0;
+                    let horizontal = Line::new((0.0, offset), (extent, offset));
+                    let vertical = Line::new((offset, 0.0), (offset, extent));
+                    rc.stroke_styled(horizontal, &color, 0.5, &style);
+                    rc.stroke_styled(vertical, &color, 0.5, &style);
+                }
+            }
+            Workload::Gradients => {
+                let stops = vec![
+                    GradientStop {
+                        pos: 0.0,
+                        color: Color::RED,
+                    },
+                    GradientStop {
+                        pos: 0.5,
+                        color: Color::rgba8(0, 0xff, 0, 0x80),
+                    },
+                    GradientStop {
+                        pos: 1.0,
+                        color: Color::BLUE,
+                    },
+                ];
+                rc.gradient(FixedLinearGradient {
+                    start: Point::new(0.0, 0.0),
+                    end: Point::new(100.0, 50.0),
+                    stops: stops.clone(),
+                })?;
+                rc.gradient(FixedRadialGradient {
+                    center: Point::new(50.0, 50.0),
+                    origin_offset: (10.0, 0.0).into(),
+                    radius: 50.0,
+                    stops,
+                })?;
+            }
+            Workload::TextLayout => {
+                rc.text()
+                    .new_text_layout(fixture.text.clone())
+                    .font(FontFamily::SANS_SERIF, 14.0)
+                    .max_width(400.0)
+                    .build()?;
+            }
+            Workload::MakeImage(format) => {
+                let index = FORMATS.iter().position(|f| *f == format).unwrap();
+                rc.make_image(IMAGE_SIZE, IMAGE_SIZE, &fixture.pixels[index], format)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Fixture {
+    /// Make the paths, text and pixels that the workloads draw.
+    pub fn new() -> Fixture {
+        let mut random = Lcg(123456789);
+
+        let bounds = Rect::new(0.0, 0.0, 400.0, 400.0);
+        let mut point = || Point::new(random.next_f64() * bounds.x1, random.next_f64() * bounds.y1);
+        let mut path = BezPath::new();
+        path.move_to(point());
+        for _ in 0..PATH_SEGMENTS {
+            path.curve_to(point(), point(), point());
+        }
+        path.close_path();
+
+        let paragraph = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do \
+            eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim \
+            veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo \
+            consequat.";
+        let text = [paragraph; 4].join("\n").into();
+
+        let pixels = FORMATS
+            .iter()
+            .map(|format| {
+                let len = IMAGE_SIZE * IMAGE_SIZE * format.bytes_per_pixel();
+                let mut data: Vec<u8> = (0..len).map(|_| random.next_u8()).collect();
+                if *format == ImageFormat::RgbaPremul {
+                    // premultiplied colors can't be brighter than their alpha.
+                    for pixel in data.chunks_exact_mut(4) {
+                        let alpha = pixel[3];
+                        for channel in &mut pixel[..3] {
+                            *channel = (*channel).min(alpha);
+                        }
+                    }
+                }
+                data
+            })
+            .collect();
+
+        Fixture { path, text, pixels }
+    }
+}
+
+impl Default for Fixture {
+    fn default() -> Self {
+        Fixture::new()
+    }
+}
+
+impl Measurement {
+    /// The mean time of one run, in milliseconds.
+    pub fn mean_ms(&self) -> f64 {
+        if self.iterations == 0 {
+            0.0
+        } else {
+            self.total_ms / self.iterations as f64
+        }
+    }
+}
+
+/// Run `workload` against `rc` `iterations` times, timed by `now`, which
+/// returns the time in milliseconds, such as `performance.now()` in a
+/// browser.
+///
+/// The workload is run once before it is timed, to warm up any caches.
+pub fn measure<R: RenderContext>(
+    rc: &mut R,
+    workload: Workload,
+    fixture: &Fixture,
+    iterations: usize,
+    mut now: impl FnMut() -> f64,
+) -> Result<Measurement, Error> {
+    workload.run(rc, fixture)?;
+    let start = now();
+    for _ in 0..iterations {
+        workload.run(rc, fixture)?;
+    }
+    Ok(Measurement {
+        workload,
+        iterations,
+        total_ms: now() - start,
+    })
+}
+
+/// A simple LCG with parameters from Wikipedia, so that the fixture is the
+/// same on every run. See glibc/ ANSI C, CodeWarrior, ... in
+/// <https://en.wikipedia.org/w/index.php?title=Linear_congruential_generator&oldid=1028647893#Parameters_in_common_use>
+struct Lcg(u32);
+
+impl Lcg {
+    fn next_u8(&mut self) -> u8 {
+        self.0 = self.0.wrapping_mul(1103515245).wrapping_add(12345) % (1 << 31);
+        // Take a higher byte since it is more random than the low bytes
+        (self.0 >> 16) as u8
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        self.next_u8() as f64 / 255.0
+    }
+}
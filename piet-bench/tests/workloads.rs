@@ -0,0 +1,33 @@
+use piet_bench::{measure, Fixture, Workload};
+use piet_tiny_skia::tiny_skia::Pixmap;
+use piet_tiny_skia::TinySkiaRenderContext;
+
+#[test]
+fn workloads_run_on_software_backends() {
+    let fixture = Fixture::new();
+    let mut pixmap = Pixmap::new(100, 100).unwrap();
+    let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+    let mut recorder = piet_recorder::RecordingRenderContext::new(piet_svg::Text::new());
+    for workload in Workload::ALL {
+        workload.run(&mut rc, &fixture).unwrap();
+        workload.run(&mut recorder, &fixture).unwrap();
+    }
+}
+
+#[test]
+fn measurements_use_the_clock() {
+    let fixture = Fixture::new();
+    let mut rc = piet_svg::RenderContext::new((100.0, 100.0).into());
+    let mut time = 0.0;
+    let measurement = measure(&mut rc, Workload::Gradients, &fixture, 4, || {
+        time += 10.0;
+        time
+    })
+    .unwrap();
+    assert_eq!(measurement.iterations, 4);
+    assert_eq!(measurement.total_ms, 10.0);
+    assert_eq!(measurement.mean_ms(), 2.5);
+
+    let names: Vec<_> = Workload::ALL.iter().map(Workload::name).collect();
+    assert!(names.contains(&"make_image_RgbaPremul".to_string()));
+}
@@ -5,13 +5,19 @@
 mod text;
 
 use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::f64::consts::TAU;
+use std::rc::Rc;
 
-use cairo::{Context, Filter, Format, ImageSurface, Matrix, Rectangle, SurfacePattern};
+use cairo::{
+    Context, Extend, Filter, Format, ImageSurface, Matrix, Rectangle, Surface, SurfacePattern,
+};
 
-use piet::kurbo::{Affine, PathEl, Point, QuadBez, Rect, Shape, Size};
+use piet::kurbo::{Affine, BezPath, PathEl, Point, QuadBez, Rect, Shape, Size, Vec2};
 use piet::{
-    Color, Error, FixedGradient, Image, ImageFormat, InterpolationMode, IntoBrush, LineCap,
-    LineJoin, RenderContext, StrokeStyle,
+    BlendMode, Capabilities, Color, Error, FixedGradient, GradientOptions, Hinting, HitTester,
+    Image, ImageFilter, ImageFormat, InterpolationMode, IntoBrush, LineCap, LineJoin,
+    RenderContext, StrokeDash, StrokeStyle, Text, TextShapingQuality, DEFAULT_TOLERANCE,
 };
 
 pub use cairo;
@@ -28,7 +34,41 @@ pub struct CairoRenderContext<'a> {
     // by cairo. Instead we maintain our own stack, which will contain
     // only those transforms applied by us.
     transform_stack: Vec<Affine>,
+    /// The stroke parameters set on the context, for each level of save, so
+    /// that they're only set again when they change. This is `None` until
+    /// the first stroke, because the context may have been used before it
+    /// was handed to us.
+    stroke_states: Vec<Option<StrokeState>>,
+    /// The global alpha, for each level of save.
+    global_alphas: Vec<f64>,
+    /// The tolerance that shapes are converted to paths with, for each
+    /// level of save.
+    tolerances: Vec<f64>,
+    /// The opacity masks that are being drawn through, innermost last.
+    opacity_masks: Vec<OpacityMask>,
     error: Result<(), cairo::Error>,
+    hit_tester: HitTester,
+}
+
+/// An opacity mask that is being drawn through. What is drawn through it is
+/// drawn into a cairo group, which is masked when the mask is ended.
+struct OpacityMask {
+    mask: Surface,
+    size: Size,
+    rect: Rect,
+    /// The number of saved states when the mask was begun, including the
+    /// one that it saved.
+    depth: usize,
+}
+
+/// The stroke parameters of a cairo context.
+#[derive(Clone)]
+struct StrokeState {
+    line_width: f64,
+    line_join: LineJoin,
+    line_cap: LineCap,
+    dash_pattern: StrokeDash,
+    dash_offset: f64,
 }
 
 #[derive(Clone)]
@@ -36,10 +76,17 @@ pub enum Brush {
     Solid(u32),
     Linear(cairo::LinearGradient),
     Radial(cairo::RadialGradient),
+    /// A gradient that is dithered where it is drawn into an image surface,
+    /// and drawn with the brush elsewhere.
+    Dithered(Box<Brush>, Rc<FixedGradient>),
 }
 
 #[derive(Clone)]
-pub struct CairoImage(ImageSurface);
+pub struct CairoImage {
+    surface: ImageSurface,
+    /// The area of the surface that is the image, if it is a sub-image.
+    view: Option<Rect>,
+}
 
 // we call this with different types of gradient that have `add_color_stop_rgba` fns,
 // and there's no trait for this behaviour so we use a macro. ¯\_(ツ)_/¯
@@ -120,20 +167,70 @@ impl<'a> RenderContext for CairoRenderContext<'a> {
         }
     }
 
+    fn gradient_with_options(
+        &mut self,
+        gradient: impl Into<FixedGradient>,
+        options: GradientOptions,
+    ) -> Result<Brush, Error> {
+        let gradient = piet::util::interpolated_gradient(gradient.into(), options.interpolation);
+        let brush = self.gradient(gradient.clone())?;
+        if options.dither {
+            Ok(Brush::Dithered(Box::new(brush), Rc::new(gradient)))
+        } else {
+            Ok(brush)
+        }
+    }
+
     fn fill(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>) {
         let brush = brush.make_brush(self, || shape.bounding_box());
-        self.set_path(shape);
-        self.set_brush(&brush);
-        self.ctx.set_fill_rule(cairo::FillRule::Winding);
-        self.error = self.ctx.fill();
+        self.with_global_alpha(|rc| {
+            rc.set_path(shape);
+            rc.set_brush(&brush);
+            rc.ctx.set_fill_rule(cairo::FillRule::Winding);
+            rc.error = rc.ctx.fill();
+        });
     }
 
     fn fill_even_odd(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>) {
         let brush = brush.make_brush(self, || shape.bounding_box());
-        self.set_path(shape);
-        self.set_brush(&brush);
-        self.ctx.set_fill_rule(cairo::FillRule::EvenOdd);
-        self.error = self.ctx.fill();
+        self.with_global_alpha(|rc| {
+            rc.set_path(shape);
+            rc.set_brush(&brush);
+            rc.ctx.set_fill_rule(cairo::FillRule::EvenOdd);
+            rc.error = rc.ctx.fill();
+        });
+    }
+
+    fn fill_with_mask(
+        &mut self,
+        shape: impl Shape,
+        brush: &impl IntoBrush<Self>,
+        mask: &Self::Image,
+    ) {
+        let brush = brush.make_brush(self, || shape.bounding_box());
+        let bounds = shape.bounding_box();
+        let size = mask.size();
+        if bounds.is_empty() || size.is_empty() {
+            return;
+        }
+        let surface = match mask.pattern_surface() {
+            Ok(surface) => surface,
+            Err(e) => return self.error = Err(e),
+        };
+        self.with_global_alpha(|rc| {
+            let _ = rc.with_save_and_clip(shape, |rc| {
+                // the brush is set before the transform, which only applies to the mask.
+                rc.set_brush(&brush);
+                rc.ctx.translate(bounds.x0, bounds.y0);
+                rc.ctx
+                    .scale(bounds.width() / size.width, bounds.height() / size.height);
+                // the edges of the mask are padded, so they don't fade out.
+                let pattern = SurfacePattern::create(&surface);
+                pattern.set_extend(Extend::Pad);
+                rc.error = rc.ctx.mask(&pattern);
+                Ok(())
+            });
+        });
     }
 
     fn clip(&mut self, shape: impl Shape) {
@@ -144,10 +241,12 @@ impl<'a> RenderContext for CairoRenderContext<'a> {
 
     fn stroke(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>, width: f64) {
         let brush = brush.make_brush(self, || shape.bounding_box());
-        self.set_path(shape);
-        self.set_stroke(width, None);
-        self.set_brush(&brush);
-        self.error = self.ctx.stroke();
+        self.with_global_alpha(|rc| {
+            rc.set_path(shape);
+            rc.set_stroke(width, None);
+            rc.set_brush(&brush);
+            rc.error = rc.ctx.stroke();
+        });
     }
 
     fn stroke_styled(
@@ -157,11 +256,99 @@ impl<'a> RenderContext for CairoRenderContext<'a> {
         width: f64,
         style: &StrokeStyle,
     ) {
+        if piet::util::stroke_dash_caps(self, &shape, brush, width, style) {
+            return;
+        }
         let brush = brush.make_brush(self, || shape.bounding_box());
+        self.with_global_alpha(|rc| {
+            rc.set_path(shape);
+            rc.set_brush(&brush);
+            rc.stroke_path(width, style);
+        });
+    }
+
+    fn fill_many(
+        &mut self,
+        shapes: impl IntoIterator<Item = impl Shape>,
+        brush: &impl IntoBrush<Self>,
+    ) {
+        let shapes: Vec<_> = shapes.into_iter().collect();
+        if shapes.is_empty() {
+            return;
+        }
+        let brush = brush.make_brush(self, || piet::util::union_bounds(&shapes));
+        self.with_global_alpha(|rc| {
+            rc.ctx.new_path();
+            for shape in shapes {
+                rc.append_path(shape);
+            }
+            rc.set_brush(&brush);
+            rc.ctx.set_fill_rule(cairo::FillRule::Winding);
+            rc.error = rc.ctx.fill();
+        });
+    }
+
+    fn stroke_many(
+        &mut self,
+        shapes: impl IntoIterator<Item = impl Shape>,
+        brush: &impl IntoBrush<Self>,
+        width: f64,
+        style: &StrokeStyle,
+    ) {
+        let shapes: Vec<_> = shapes.into_iter().collect();
+        if shapes.is_empty() {
+            return;
+        }
+        // dashes with their own caps are drawn by filling them.
+        if style.effective_dash_cap() != style.line_cap && !style.dash_pattern.is_empty() {
+            let tolerance = self.tolerance();
+            let mut path = BezPath::new();
+            for shape in shapes {
+                path.extend(shape.path_elements(tolerance));
+            }
+            self.stroke_styled(path, brush, width, style);
+            return;
+        }
+        let brush = brush.make_brush(self, || piet::util::union_bounds(&shapes));
+        self.with_global_alpha(|rc| {
+            rc.ctx.new_path();
+            for shape in shapes {
+                rc.append_path(shape);
+            }
+            rc.set_brush(&brush);
+            rc.stroke_path(width, style);
+        });
+    }
+
+    fn fill_and_stroke(
+        &mut self,
+        shape: impl Shape,
+        fill_brush: &impl IntoBrush<Self>,
+        stroke_brush: &impl IntoBrush<Self>,
+        width: f64,
+        style: &StrokeStyle,
+    ) {
+        // in one group for the global alpha, the stroke would hide the fill
+        // under it, rather than being blended over it; dashes with their own
+        // caps are drawn by filling them.
+        let separate = self.global_alpha() < 1.0
+            || (style.effective_dash_cap() != style.line_cap && !style.dash_pattern.is_empty());
+        if separate {
+            self.fill(&shape, fill_brush);
+            self.stroke_styled(&shape, stroke_brush, width, style);
+            return;
+        }
+        let fill_brush = fill_brush.make_brush(self, || shape.bounding_box());
+        let stroke_brush = stroke_brush.make_brush(self, || shape.bounding_box());
         self.set_path(shape);
-        self.set_stroke(width, Some(style));
-        self.set_brush(&brush);
-        self.error = self.ctx.stroke();
+        self.set_brush(&fill_brush);
+        self.ctx.set_fill_rule(cairo::FillRule::Winding);
+        let filled = self.ctx.fill_preserve();
+        self.set_brush(&stroke_brush);
+        self.stroke_path(width, style);
+        if filled.is_err() {
+            self.error = filled;
+        }
     }
 
     fn text(&mut self) -> &mut Self::Text {
@@ -171,19 +358,53 @@ impl<'a> RenderContext for CairoRenderContext<'a> {
     fn draw_text(&mut self, layout: &Self::TextLayout, pos: impl Into<Point>) {
         let pos = pos.into();
         let offset = layout.pango_offset();
-        self.ctx.move_to(pos.x - offset.x, pos.y - offset.y);
-        pangocairo::show_layout(self.ctx, layout.pango_layout());
+        self.with_global_alpha(|rc| {
+            if !layout.has_custom_line_spacing() {
+                rc.ctx.move_to(pos.x - offset.x, pos.y - offset.y);
+                pangocairo::show_layout(rc.ctx, layout.pango_layout());
+                return;
+            }
+
+            // our line metrics don't match pango's, so we position each line ourselves.
+            let lines = layout.line_metrics().iter().zip(layout.x_offsets());
+            for (i, (lm, x_offset)) in lines.enumerate() {
+                if let Some(line) = layout.pango_layout().line_readonly(i as i32) {
+                    let x = pos.x - offset.x + *x_offset as f64 / pango::SCALE as f64;
+                    let y = pos.y - offset.y + lm.y_offset + lm.baseline;
+                    rc.ctx.move_to(x, y);
+                    pangocairo::show_layout_line(rc.ctx, &line);
+                }
+            }
+        });
     }
 
     fn save(&mut self) -> Result<(), Error> {
         self.ctx.save().map_err(convert_error)?;
         let state = self.transform_stack.last().copied().unwrap_or_default();
         self.transform_stack.push(state);
+        let stroke = self.stroke_states.last().cloned().flatten();
+        self.stroke_states.push(stroke);
+        self.global_alphas.push(self.global_alpha());
+        self.tolerances.push(self.tolerance());
         Ok(())
     }
 
     fn restore(&mut self) -> Result<(), Error> {
+        // the state saved by an opacity mask is restored by ending it, since
+        // cairo's group has its own saved state.
+        if self.opacity_masks.last().map(|mask| mask.depth) == Some(self.transform_stack.len()) {
+            return Err(Error::StackUnbalance);
+        }
         if self.transform_stack.pop().is_some() {
+            if self.stroke_states.len() > 1 {
+                self.stroke_states.pop();
+            }
+            if self.global_alphas.len() > 1 {
+                self.global_alphas.pop();
+            }
+            if self.tolerances.len() > 1 {
+                self.tolerances.pop();
+            }
             // we're defensive about calling restore on the inner context,
             // because an unbalanced call will trigger a panic in cairo-rs
             self.ctx.restore().map_err(convert_error)
@@ -210,8 +431,116 @@ impl<'a> RenderContext for CairoRenderContext<'a> {
         self.transform_stack.last().copied().unwrap_or_default()
     }
 
-    // allows e.g. raw_data[dst_off + x * 4 + 2] = buf[src_off + x * 4 + 0];
-    #[allow(clippy::identity_op)]
+    fn capabilities(&self) -> Capabilities {
+        let mut capabilities = Capabilities::default();
+        capabilities.supports_blend_modes = true;
+        capabilities.supports_blur = true;
+        capabilities.supports_global_alpha = true;
+        // the largest image surface that cairo makes.
+        capabilities.max_image_size = i16::MAX as usize;
+        capabilities.text_shaping_quality = TextShapingQuality::Full;
+        capabilities
+    }
+
+    fn set_global_alpha(&mut self, alpha: f64) {
+        // NaN is treated as transparent.
+        let alpha = if alpha >= 0.0 { alpha.min(1.0) } else { 0.0 };
+        *self.global_alphas.last_mut().unwrap() = alpha;
+    }
+
+    fn global_alpha(&self) -> f64 {
+        *self.global_alphas.last().unwrap()
+    }
+
+    // the operator is part of cairo's saved state, so it is kept there.
+    fn set_blend_mode(&mut self, mode: BlendMode) {
+        self.ctx.set_operator(convert_blend_mode(mode));
+    }
+
+    fn blend_mode(&self) -> BlendMode {
+        match self.ctx.operator() {
+            cairo::Operator::Multiply => BlendMode::Multiply,
+            cairo::Operator::Screen => BlendMode::Screen,
+            cairo::Operator::Overlay => BlendMode::Overlay,
+            cairo::Operator::Darken => BlendMode::Darken,
+            cairo::Operator::Lighten => BlendMode::Lighten,
+            cairo::Operator::ColorDodge => BlendMode::ColorDodge,
+            cairo::Operator::ColorBurn => BlendMode::ColorBurn,
+            cairo::Operator::HardLight => BlendMode::HardLight,
+            cairo::Operator::SoftLight => BlendMode::SoftLight,
+            cairo::Operator::Difference => BlendMode::Difference,
+            cairo::Operator::Exclusion => BlendMode::Exclusion,
+            cairo::Operator::HslHue => BlendMode::Hue,
+            cairo::Operator::HslSaturation => BlendMode::Saturation,
+            cairo::Operator::HslColor => BlendMode::Color,
+            cairo::Operator::HslLuminosity => BlendMode::Luminosity,
+            _ => BlendMode::Normal,
+        }
+    }
+
+    fn set_tolerance(&mut self, tolerance: f64) {
+        if tolerance > 0.0 && tolerance.is_finite() {
+            *self.tolerances.last_mut().unwrap() = tolerance;
+        }
+    }
+
+    fn tolerance(&self) -> f64 {
+        *self.tolerances.last().unwrap()
+    }
+
+    fn begin_opacity_mask(
+        &mut self,
+        mask: &Self::Image,
+        rect: impl Into<Rect>,
+    ) -> Result<(), Error> {
+        let surface = mask.pattern_surface().map_err(convert_error)?;
+        self.save()?;
+        self.ctx.push_group();
+        self.opacity_masks.push(OpacityMask {
+            mask: surface,
+            size: mask.size(),
+            rect: rect.into().abs(),
+            depth: self.transform_stack.len(),
+        });
+        Ok(())
+    }
+
+    fn end_opacity_mask(&mut self) -> Result<(), Error> {
+        match self.opacity_masks.last() {
+            Some(mask) if mask.depth == self.transform_stack.len() => (),
+            _ => return Err(Error::StackUnbalance),
+        }
+        let OpacityMask {
+            mask, size, rect, ..
+        } = self.opacity_masks.pop().unwrap();
+        // popping the group restores the state that it was pushed in, which
+        // has the transform that the rect is in.
+        let masked = self.ctx.pop_group().and_then(|group| {
+            // an empty mask hides everything.
+            if rect.is_empty() || size.is_empty() {
+                return Ok(());
+            }
+            self.ctx
+                .rectangle(rect.x0, rect.y0, rect.width(), rect.height());
+            self.ctx.clip();
+            // the group is set before the transform, which only applies to
+            // the mask.
+            self.ctx.set_source(&group)?;
+            self.ctx.translate(rect.x0, rect.y0);
+            self.ctx
+                .scale(rect.width() / size.width, rect.height() / size.height);
+            // the edges of the mask are padded, so they don't fade out.
+            let pattern = SurfacePattern::create(&mask);
+            pattern.set_extend(Extend::Pad);
+            self.ctx.mask(&pattern)
+        });
+        masked.map_err(convert_error).and(self.restore())
+    }
+
+    fn hit_tester(&mut self) -> Option<&mut HitTester> {
+        Some(&mut self.hit_tester)
+    }
+
     fn make_image(
         &mut self,
         width: usize,
@@ -219,90 +548,7 @@ impl<'a> RenderContext for CairoRenderContext<'a> {
         buf: &[u8],
         format: ImageFormat,
     ) -> Result<Self::Image, Error> {
-        let cairo_fmt = match format {
-            ImageFormat::Rgb | ImageFormat::Grayscale => Format::Rgb24,
-            ImageFormat::RgbaSeparate | ImageFormat::RgbaPremul => Format::ARgb32,
-            _ => return Err(Error::NotSupported),
-        };
-        let width_int = width as i32;
-        let height_int = height as i32;
-        let mut image = ImageSurface::create(cairo_fmt, width_int, height_int)
-            .map_err(|e| Error::BackendError(Box::new(e)))?;
-
-        // early-return if the image has no data in it
-        if width_int == 0 || height_int == 0 {
-            return Ok(CairoImage(image));
-        }
-
-        // Confident no borrow errors because we just created it.
-        let bytes_per_pixel = format.bytes_per_pixel();
-        let bytes_per_row = width * bytes_per_pixel;
-        let stride = image.stride() as usize;
-        {
-            let mut data = image.data().map_err(|e| Error::BackendError(Box::new(e)))?;
-            for y in 0..height {
-                let src_off = y * bytes_per_row;
-                let data = &mut data[y * stride..];
-                match format {
-                    ImageFormat::Rgb => {
-                        for x in 0..width {
-                            write_rgb(
-                                data,
-                                x,
-                                buf[src_off + x * 3 + 0],
-                                buf[src_off + x * 3 + 1],
-                                buf[src_off + x * 3 + 2],
-                            );
-                        }
-                    }
-                    ImageFormat::RgbaPremul => {
-                        // It's annoying that Cairo exposes only ARGB. Ah well. Let's
-                        // hope that LLVM generates pretty good code for this.
-                        // TODO: consider adding BgraPremul format.
-                        for x in 0..width {
-                            write_rgba(
-                                data,
-                                x,
-                                buf[src_off + x * 4 + 0],
-                                buf[src_off + x * 4 + 1],
-                                buf[src_off + x * 4 + 2],
-                                buf[src_off + x * 4 + 3],
-                            );
-                        }
-                    }
-                    ImageFormat::RgbaSeparate => {
-                        fn premul(x: u8, a: u8) -> u8 {
-                            let y = (x as u16) * (a as u16);
-                            ((y + (y >> 8) + 0x80) >> 8) as u8
-                        }
-                        for x in 0..width {
-                            let a = buf[src_off + x * 4 + 3];
-                            write_rgba(
-                                data,
-                                x,
-                                premul(buf[src_off + x * 4 + 0], a),
-                                premul(buf[src_off + x * 4 + 1], a),
-                                premul(buf[src_off + x * 4 + 2], a),
-                                a,
-                            );
-                        }
-                    }
-                    ImageFormat::Grayscale => {
-                        for x in 0..width {
-                            write_rgb(
-                                data,
-                                x,
-                                buf[src_off + x],
-                                buf[src_off + x],
-                                buf[src_off + x],
-                            );
-                        }
-                    }
-                    _ => return Err(Error::NotSupported),
-                }
-            }
-        }
-        Ok(CairoImage(image))
+        make_surface(width, height, buf, format).map(CairoImage::new)
     }
 
     #[inline]
@@ -312,7 +558,8 @@ impl<'a> RenderContext for CairoRenderContext<'a> {
         dst_rect: impl Into<Rect>,
         interp: InterpolationMode,
     ) {
-        self.draw_image_inner(&image.0, None, dst_rect.into(), interp);
+        let src_rect = image.size().to_rect();
+        self.draw_image_inner(image, src_rect, dst_rect.into(), interp);
     }
 
     #[inline]
@@ -323,7 +570,45 @@ impl<'a> RenderContext for CairoRenderContext<'a> {
         dst_rect: impl Into<Rect>,
         interp: InterpolationMode,
     ) {
-        self.draw_image_inner(&image.0, Some(src_rect.into()), dst_rect.into(), interp);
+        self.draw_image_inner(image, src_rect.into(), dst_rect.into(), interp);
+    }
+
+    fn draw_image_filtered(
+        &mut self,
+        image: &Self::Image,
+        dst_rect: impl Into<Rect>,
+        filters: &[ImageFilter],
+    ) {
+        let dst_rect = dst_rect.into();
+        let view = image.view();
+        let (width, height) = (view.width() as usize, view.height() as usize);
+        if width == 0 || height == 0 || dst_rect.is_empty() {
+            return;
+        }
+        let rgba = match surface_rgba(&image.surface, view) {
+            Some(rgba) => rgba,
+            None => return self.draw_image(image, dst_rect, InterpolationMode::Bilinear),
+        };
+        let size = Size::new(width as f64, height as f64);
+        let scale = Vec2::new(
+            size.width / dst_rect.width(),
+            size.height / dst_rect.height(),
+        );
+        let filtered = piet::util::filter_pixels(width, height, &rgba, filters, scale);
+        let filtered_rect = filtered.dst_rect(size, dst_rect);
+        match make_surface(
+            filtered.width,
+            filtered.height,
+            &filtered.data,
+            ImageFormat::RgbaPremul,
+        ) {
+            Ok(filtered) => self.draw_image(
+                &CairoImage::new(filtered),
+                filtered_rect,
+                InterpolationMode::Bilinear,
+            ),
+            Err(_) => self.error = Err(cairo::Error::NoMemory),
+        }
     }
 
     fn capture_image_area(&mut self, src_rect: impl Into<Rect>) -> Result<Self::Image, Error> {
@@ -366,19 +651,19 @@ impl<'a> RenderContext for CairoRenderContext<'a> {
         target_ctx.rectangle(0.0, 0.0, device_rect.width(), device_rect.height());
         target_ctx.fill().map_err(convert_error)?;
 
-        Ok(CairoImage(target_surface))
+        Ok(CairoImage::new(target_surface))
     }
 
     fn blurred_rect(&mut self, rect: Rect, blur_radius: f64, brush: &impl IntoBrush<Self>) {
         let brush = brush.make_brush(self, || rect);
         match compute_blurred_rect(rect, blur_radius) {
-            Ok((image, origin)) => {
-                self.set_brush(&brush);
-                self.error = self
+            Ok((image, origin)) => self.with_global_alpha(|rc| {
+                rc.set_brush(&brush);
+                rc.error = rc
                     .ctx
                     .mask_surface(&image, origin.x, origin.y)
                     .map_err(cairo::Error::into);
-            }
+            }),
             Err(err) => self.error = Err(err),
         }
     }
@@ -394,9 +679,89 @@ impl<'a> IntoBrush<CairoRenderContext<'a>> for Brush {
     }
 }
 
+impl CairoImage {
+    fn new(surface: ImageSurface) -> CairoImage {
+        CairoImage {
+            surface,
+            view: None,
+        }
+    }
+
+    /// The surface to draw the image from, which is a view of the area of
+    /// the surface if the image is a sub-image, so that the pixels around
+    /// it aren't drawn.
+    fn pattern_surface(&self) -> Result<Surface, cairo::Error> {
+        match self.view {
+            Some(view) => self.surface.create_for_rectangle(Rectangle::new(
+                view.x0,
+                view.y0,
+                view.width(),
+                view.height(),
+            )),
+            None => Ok((*self.surface).clone()),
+        }
+    }
+
+    /// The area of the surface that is the image.
+    fn view(&self) -> Rect {
+        self.view.unwrap_or_else(|| {
+            Rect::new(
+                0.,
+                0.,
+                self.surface.width().into(),
+                self.surface.height().into(),
+            )
+        })
+    }
+}
+
 impl Image for CairoImage {
     fn size(&self) -> Size {
-        Size::new(self.0.width().into(), self.0.height().into())
+        self.view().size()
+    }
+
+    fn sub_image(&self, rect: impl Into<Rect>) -> Self {
+        CairoImage {
+            surface: self.surface.clone(),
+            view: Some(piet::util::sub_image_view(self.view(), rect.into())),
+        }
+    }
+
+    fn update_region(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        buf: &[u8],
+        format: ImageFormat,
+    ) -> Result<(), Error> {
+        let view = self.view();
+        if (x + width) as f64 > view.width()
+            || (y + height) as f64 > view.height()
+            || buf.len() < width * height * format.bytes_per_pixel()
+        {
+            return Err(Error::InvalidInput);
+        }
+        let (x, y) = (x + view.x0 as usize, y + view.y0 as usize);
+        if width == 0 || height == 0 {
+            return Ok(());
+        }
+        let region = make_surface(width, height, buf, format)?;
+        // draw the pixels, because cairo only allows writing to the data of
+        // surfaces that aren't shared.
+        let ctx = Context::new(&self.surface).map_err(|e| Error::BackendError(Box::new(e)))?;
+        ctx.set_operator(cairo::Operator::Source);
+        ctx.set_source_surface(&region, x as f64, y as f64)
+            .map_err(|e| Error::BackendError(Box::new(e)))?;
+        ctx.rectangle(x as f64, y as f64, width as f64, height as f64);
+        ctx.fill().map_err(|e| Error::BackendError(Box::new(e)))
+    }
+
+    fn to_rgba_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut rgba = surface_rgba(&self.surface, self.view()).ok_or(Error::NotSupported)?;
+        piet::util::unpremultiply_rgba(&mut rgba);
+        Ok(rgba)
     }
 }
 
@@ -411,10 +776,74 @@ impl<'a> CairoRenderContext<'a> {
             ctx,
             text: CairoText::new(),
             transform_stack: Vec::new(),
+            stroke_states: vec![None],
+            global_alphas: vec![1.0],
+            tolerances: vec![DEFAULT_TOLERANCE],
+            opacity_masks: Vec::new(),
+            error: Ok(()),
+            hit_tester: HitTester::new(),
+        }
+    }
+
+    /// Create a new Cairo back-end that draws to a vector surface, such as a
+    /// PDF, SVG or PostScript surface.
+    ///
+    /// Anything that can't be drawn as vectors, such as a blurred rect, is
+    /// rasterized at `fallback_dpi`. Text is laid out without hinting, so that
+    /// glyph positions don't depend on a pixel grid, and is drawn with the
+    /// fonts embedded in the output, so it remains selectable.
+    pub fn new_vector(ctx: &Context, fallback_dpi: f64) -> CairoRenderContext {
+        ctx.target()
+            .set_fallback_resolution(fallback_dpi, fallback_dpi);
+        let mut text = CairoText::new();
+        text.set_hinting(Hinting::Subpixel);
+        CairoRenderContext {
+            ctx,
+            text,
+            transform_stack: Vec::new(),
+            stroke_states: vec![None],
+            global_alphas: vec![1.0],
+            tolerances: vec![DEFAULT_TOLERANCE],
+            opacity_masks: Vec::new(),
+            error: Ok(()),
+            hit_tester: HitTester::new(),
+        }
+    }
+
+    /// Create a new Cairo back-end that draws to an existing context, of any
+    /// surface type, that has `scale` device pixels per unit, with fonts
+    /// sized for `text_dpi`.
+    ///
+    /// This is for drawing into contexts made by a toolkit, such as those
+    /// from a GTK4 snapshot, which are already scaled for the display; the
+    /// context isn't scaled again, but text is laid out for the device
+    /// pixels and the toolkit's text resolution, so that it is measured the
+    /// same as it is drawn, and is the same size as the toolkit's own text.
+    /// See [`CairoText::new_scaled`].
+    pub fn new_scaled(ctx: &Context, scale: f64, text_dpi: f64) -> CairoRenderContext<'_> {
+        CairoRenderContext {
+            ctx,
+            text: CairoText::new_scaled(scale, text_dpi),
+            transform_stack: Vec::new(),
+            stroke_states: vec![None],
+            global_alphas: vec![1.0],
+            tolerances: vec![DEFAULT_TOLERANCE],
+            opacity_masks: Vec::new(),
             error: Ok(()),
+            hit_tester: HitTester::new(),
         }
     }
 
+    /// Notify the context that the stroke parameters of the cairo context,
+    /// such as its line width or dashes, were changed outside of piet.
+    ///
+    /// The parameters are only set on the cairo context when they change;
+    /// if you set them directly, call this so that they are set again by the
+    /// next stroke.
+    pub fn invalidate_stroke_state(&mut self) {
+        *self.stroke_states.last_mut().unwrap() = None;
+    }
+
     /// Set the source pattern to the brush.
     ///
     /// Cairo is super stateful, and we're trying to have more retained stuff.
@@ -429,30 +858,169 @@ impl<'a> CairoRenderContext<'a> {
             ),
             Brush::Linear(ref linear) => self.error = self.ctx.set_source(linear),
             Brush::Radial(ref radial) => self.error = self.ctx.set_source(radial),
+            Brush::Dithered(ref brush, ref gradient) => match self.dithered_pattern(gradient) {
+                Some(pattern) => self.error = self.ctx.set_source(&pattern),
+                None => self.set_brush(brush),
+            },
+        }
+    }
+
+    /// A pattern of `gradient`, dithered, over the pixels of the target that
+    /// the current path can draw.
+    ///
+    /// This is `None` if the target isn't an image surface, since documents
+    /// are dithered, if at all, by whatever shows them.
+    fn dithered_pattern(&self, gradient: &FixedGradient) -> Option<SurfacePattern> {
+        let target = ImageSurface::try_from(self.ctx.target()).ok()?;
+        let extents = |(x0, y0, x1, y1)| Rect::new(x0, y0, x1, y1);
+        let clip = extents(self.ctx.clip_extents().ok()?);
+        // the extents of the path don't include strokes, which reach out by
+        // about the line width; further than that, the pattern pads.
+        let path = extents(self.ctx.path_extents().ok()?);
+        let width = self.ctx.line_width();
+        let bounds = if path.is_empty() {
+            clip
+        } else {
+            path.inflate(width, width).intersect(clip)
+        };
+        let m = self.ctx.matrix();
+        let device = Affine::new([m.xx(), m.yx(), m.xy(), m.yy(), m.x0(), m.y0()]);
+        let target_rect = Rect::new(0.0, 0.0, target.width() as f64, target.height() as f64);
+        let bounds = device
+            .transform_rect_bbox(bounds)
+            .expand()
+            .intersect(target_rect);
+        if bounds.is_empty() || device.determinant() == 0.0 {
+            return None;
+        }
+        let (width, height) = (bounds.width() as usize, bounds.height() as usize);
+        let to_user = device.inverse();
+        let data = piet::util::dithered_gradient_pixels(
+            gradient,
+            to_user,
+            bounds.x0 as i32,
+            bounds.y0 as i32,
+            width,
+            height,
+        );
+        let surface = make_surface(width, height, &data, ImageFormat::RgbaPremul).ok()?;
+        let pattern = SurfacePattern::create(&surface);
+        pattern.set_extend(Extend::Pad);
+        pattern.set_filter(Filter::Nearest);
+        // the pattern's matrix maps user space to the pattern.
+        let to_pattern = Affine::translate(-bounds.origin().to_vec2()) * device;
+        pattern.set_matrix(affine_to_matrix(to_pattern));
+        Some(pattern)
+    }
+
+    /// Draw with `draw`, faded by the global alpha.
+    ///
+    /// Cairo has no global alpha, so unless it is opaque, the drawing is done
+    /// in a group, which is then painted with the alpha.
+    fn with_global_alpha(&mut self, draw: impl FnOnce(&mut Self)) {
+        let alpha = self.global_alpha();
+        if alpha >= 1.0 {
+            draw(self);
+            return;
+        }
+        if alpha <= 0.0 {
+            return;
+        }
+        self.ctx.push_group();
+        // the group has its own copy of the cairo state, which is thrown
+        // away with it, along with any stroke parameters set in it.
+        let stroke = self.stroke_states.last().cloned().flatten();
+        self.stroke_states.push(stroke);
+        draw(self);
+        self.stroke_states.pop();
+        self.error = self
+            .ctx
+            .pop_group_to_source()
+            .and_then(|()| self.ctx.paint_with_alpha(alpha));
+    }
+
+    /// Set the stroke parameters that have changed since they were last set.
+    /// Stroke the current path with the current source.
+    fn stroke_path(&mut self, width: f64, style: &StrokeStyle) {
+        if style.scale {
+            self.set_stroke(width, Some(style));
+            self.error = self.ctx.stroke();
+        } else {
+            // The path and the source pattern are locked in device space, but the
+            // stroke parameters are interpreted in the current user space; undo
+            // our transform (but not GTK's) before stroking.
+            let _ = self.with_save(|rc| {
+                rc.ctx
+                    .transform(affine_to_matrix(rc.current_transform().inverse()));
+                rc.set_stroke(width, Some(style));
+                rc.error = rc.ctx.stroke();
+                Ok(())
+            });
         }
     }
 
-    /// Set the stroke parameters.
     fn set_stroke(&mut self, width: f64, style: Option<&StrokeStyle>) {
         let default_style = StrokeStyle::default();
         let style = style.unwrap_or(&default_style);
+        let ctx = self.ctx;
+        let known = self.stroke_states.last_mut().unwrap();
+        let unknown = known.is_none();
+        let state = known.get_or_insert_with(|| StrokeState {
+            line_width: width,
+            line_join: style.line_join,
+            line_cap: style.line_cap,
+            dash_pattern: style.dash_pattern.clone(),
+            dash_offset: style.dash_offset,
+        });
+
+        if unknown || width != state.line_width {
+            ctx.set_line_width(width);
+            state.line_width = width;
+        }
+
+        if unknown || style.line_join != state.line_join {
+            ctx.set_line_join(convert_line_join(style.line_join));
+            if let Some(limit) = style.miter_limit() {
+                ctx.set_miter_limit(limit);
+            }
+            state.line_join = style.line_join;
+        }
 
-        self.ctx.set_line_width(width);
-        self.ctx.set_line_join(convert_line_join(style.line_join));
-        self.ctx.set_line_cap(convert_line_cap(style.line_cap));
+        if unknown || style.line_cap != state.line_cap {
+            ctx.set_line_cap(convert_line_cap(style.line_cap));
+            state.line_cap = style.line_cap;
+        }
 
-        if let Some(limit) = style.miter_limit() {
-            self.ctx.set_miter_limit(limit);
+        if unknown
+            || style.dash_pattern != state.dash_pattern
+            || style.dash_offset != state.dash_offset
+        {
+            ctx.set_dash(&style.dash_pattern, style.dash_offset);
+            state.dash_pattern = style.dash_pattern.clone();
+            state.dash_offset = style.dash_offset;
         }
-        self.ctx.set_dash(&style.dash_pattern, style.dash_offset);
     }
 
     fn set_path(&mut self, shape: impl Shape) {
         // This shouldn't be necessary, we always leave the context in no-path
         // state. But just in case, and it should be harmless.
         self.ctx.new_path();
+        self.append_path(shape);
+    }
+
+    /// Add the subpaths of `shape` to the current path.
+    fn append_path(&mut self, shape: impl Shape) {
+        // cairo draws arcs at the resolution of the device, so circles stay
+        // round however far they are zoomed in.
+        if let Some(circle) = shape.as_circle().filter(|circle| circle.radius >= 0.0) {
+            let center = circle.center;
+            self.ctx.new_sub_path();
+            self.ctx.arc(center.x, center.y, circle.radius, 0.0, TAU);
+            self.ctx.close_path();
+            return;
+        }
         let mut last = Point::ZERO;
-        for el in shape.path_elements(1e-3) {
+        for el in shape.path_elements(self.tolerance()) {
             match el {
                 PathEl::MoveTo(p) => {
                     self.ctx.move_to(p.x, p.y);
@@ -480,26 +1048,30 @@ impl<'a> CairoRenderContext<'a> {
 
     fn draw_image_inner(
         &mut self,
-        image: &ImageSurface,
-        src_rect: Option<Rect>,
+        image: &CairoImage,
+        src_rect: Rect,
         dst_rect: Rect,
         interp: InterpolationMode,
     ) {
-        let src_rect = match src_rect {
-            Some(src_rect) => src_rect,
-            None => Size::new(image.width() as f64, image.height() as f64).to_rect(),
-        };
         // Cairo returns an error if we try to paint an empty image, causing us to panic. We check if
         // either the source or destination is empty, and early-return if so.
         if src_rect.is_empty() || dst_rect.is_empty() {
             return;
         }
 
+        let surface = match image.pattern_surface() {
+            Ok(surface) => surface,
+            Err(e) => return self.error = Err(e),
+        };
         let _ = self.with_save(|rc| {
-            let surface_pattern = SurfacePattern::create(image);
+            let surface_pattern = SurfacePattern::create(&surface);
             let filter = match interp {
                 InterpolationMode::NearestNeighbor => Filter::Nearest,
                 InterpolationMode::Bilinear => Filter::Bilinear,
+                // cairo's good and best filters also filter the image when it
+                // is scaled down.
+                InterpolationMode::Bicubic => Filter::Good,
+                InterpolationMode::HighQuality => Filter::Best,
             };
             surface_pattern.set_filter(filter);
             let scale_x = dst_rect.width() / src_rect.width();
@@ -511,7 +1083,10 @@ impl<'a> CairoRenderContext<'a> {
             );
             rc.ctx.scale(scale_x, scale_y);
             rc.error = rc.ctx.set_source(&surface_pattern);
-            rc.error = rc.ctx.paint();
+            rc.error = match rc.global_alpha() {
+                alpha if alpha < 1.0 => rc.ctx.paint_with_alpha(alpha),
+                _ => rc.ctx.paint(),
+            };
             Ok(())
         });
     }
@@ -534,6 +1109,27 @@ fn convert_line_cap(line_cap: LineCap) -> cairo::LineCap {
     }
 }
 
+fn convert_blend_mode(mode: BlendMode) -> cairo::Operator {
+    match mode {
+        BlendMode::Normal => cairo::Operator::Over,
+        BlendMode::Multiply => cairo::Operator::Multiply,
+        BlendMode::Screen => cairo::Operator::Screen,
+        BlendMode::Overlay => cairo::Operator::Overlay,
+        BlendMode::Darken => cairo::Operator::Darken,
+        BlendMode::Lighten => cairo::Operator::Lighten,
+        BlendMode::ColorDodge => cairo::Operator::ColorDodge,
+        BlendMode::ColorBurn => cairo::Operator::ColorBurn,
+        BlendMode::HardLight => cairo::Operator::HardLight,
+        BlendMode::SoftLight => cairo::Operator::SoftLight,
+        BlendMode::Difference => cairo::Operator::Difference,
+        BlendMode::Exclusion => cairo::Operator::Exclusion,
+        BlendMode::Hue => cairo::Operator::HslHue,
+        BlendMode::Saturation => cairo::Operator::HslSaturation,
+        BlendMode::Color => cairo::Operator::HslColor,
+        BlendMode::Luminosity => cairo::Operator::HslLuminosity,
+    }
+}
+
 fn convert_line_join(line_join: LineJoin) -> cairo::LineJoin {
     match line_join {
         LineJoin::Miter { .. } => cairo::LineJoin::Miter,
@@ -578,18 +1174,549 @@ fn convert_error(err: cairo::Error) -> Error {
     Error::BackendError(err.into())
 }
 
-fn write_rgba(data: &mut [u8], column: usize, r: u8, g: u8, b: u8, a: u8) {
-    // From the cairo docs for CAIRO_FORMAT_ARGB32:
-    // > each pixel is a 32-bit quantity, with alpha in the upper 8 bits, then red,
-    // > then green, then blue. The 32-bit quantities are stored native-endian.
-    let (a, r, g, b) = (u32::from(a), u32::from(r), u32::from(g), u32::from(b));
-    let pixel = a << 24 | r << 16 | g << 8 | b;
+/// Make an image surface from pixels in `format`.
+fn make_surface(
+    width: usize,
+    height: usize,
+    buf: &[u8],
+    format: ImageFormat,
+) -> Result<ImageSurface, Error> {
+    let cairo_fmt = match format {
+        ImageFormat::Rgb | ImageFormat::Grayscale => Format::Rgb24,
+        ImageFormat::RgbaSeparate
+        | ImageFormat::RgbaPremul
+        | ImageFormat::BgraSeparate
+        | ImageFormat::BgraPremul => Format::ARgb32,
+        ImageFormat::Rgba16 => {
+            let buf = piet::util::rgba16_to_rgba8(buf);
+            return make_surface(width, height, &buf, ImageFormat::RgbaSeparate);
+        }
+        _ => return Err(Error::NotSupported),
+    };
+    let width_int = width as i32;
+    let height_int = height as i32;
+    let mut image = ImageSurface::create(cairo_fmt, width_int, height_int)
+        .map_err(|e| Error::BackendError(Box::new(e)))?;
+
+    // early-return if the image has no data in it
+    if width_int == 0 || height_int == 0 {
+        return Ok(image);
+    }
 
-    data[4 * column..4 * (column + 1)].copy_from_slice(&pixel.to_ne_bytes());
+    // Confident no borrow errors because we just created it.
+    let bytes_per_row = width * format.bytes_per_pixel();
+    let stride = image.stride() as usize;
+    {
+        let mut data = image.data().map_err(|e| Error::BackendError(Box::new(e)))?;
+        for y in 0..height {
+            let src = &buf[y * bytes_per_row..][..bytes_per_row];
+            let row = &mut data[y * stride..][..width * 4];
+            // Cairo's pixels are native-endian ARGB words, which are BGRA bytes on
+            // little-endian machines. The unused byte of RGB24 is set as well.
+            match format {
+                ImageFormat::Rgb => {
+                    piet::util::rgb_to_rgba(src, row);
+                    piet::util::swap_red_blue(row);
+                }
+                ImageFormat::RgbaPremul => {
+                    row.copy_from_slice(src);
+                    piet::util::swap_red_blue(row);
+                }
+                ImageFormat::RgbaSeparate => {
+                    row.copy_from_slice(src);
+                    piet::util::premultiply_rgba(row);
+                    piet::util::swap_red_blue(row);
+                }
+                ImageFormat::BgraPremul => row.copy_from_slice(src),
+                ImageFormat::BgraSeparate => {
+                    row.copy_from_slice(src);
+                    piet::util::premultiply_rgba(row);
+                }
+                ImageFormat::Grayscale => piet::util::gray_to_rgba(src, row),
+                _ => return Err(Error::NotSupported),
+            }
+            if cfg!(target_endian = "big") {
+                for px in row.chunks_exact_mut(4) {
+                    px.reverse();
+                }
+            }
+        }
+    }
+    Ok(image)
+}
+
+/// The pixels of the `area` of an image surface as premultiplied RGBA, or
+/// `None` if its format isn't one that piet makes.
+///
+/// `area` is in whole pixels, inside the surface.
+fn surface_rgba(surface: &ImageSurface, area: Rect) -> Option<Vec<u8>> {
+    let opaque = match surface.format() {
+        Format::ARgb32 => false,
+        Format::Rgb24 => true,
+        _ => return None,
+    };
+    let (x0, y0) = (area.x0 as usize, area.y0 as usize);
+    let (width, height) = (area.width() as usize, area.height() as usize);
+    let stride = surface.stride() as usize;
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    surface
+        .with_data(|data| {
+            for y in y0..y0 + height {
+                rgba.extend_from_slice(&data[y * stride + x0 * 4..][..width * 4]);
+            }
+        })
+        .ok()?;
+    if cfg!(target_endian = "big") {
+        for px in rgba.chunks_exact_mut(4) {
+            px.reverse();
+        }
+    }
+    piet::util::swap_red_blue(&mut rgba);
+    if opaque {
+        for px in rgba.chunks_exact_mut(4) {
+            px[3] = 255;
+        }
+    }
+    Some(rgba)
 }
 
-fn write_rgb(data: &mut [u8], column: usize, r: u8, g: u8, b: u8) {
-    // From the cairo docs for CAIRO_FORMAT_RGB24:
-    //  each pixel is a 32-bit quantity, with the upper 8 bits unused.
-    write_rgba(data, column, r, g, b, 0);
+#[cfg(test)]
+mod test {
+    use super::*;
+    use piet::kurbo::{Circle, Line};
+    use piet::{Symbol, TextLayoutBuilder};
+
+    #[test]
+    fn pdf_text_is_selectable() {
+        let surface = cairo::PdfSurface::for_stream(200., 100., Vec::new()).unwrap();
+        let ctx = Context::new(&surface).unwrap();
+        let mut rc = CairoRenderContext::new_vector(&ctx, 300.);
+        let layout = rc
+            .text()
+            .new_text_layout("Invoice")
+            .text_color(Color::BLACK)
+            .build()
+            .unwrap();
+        rc.draw_text(&layout, (10., 10.));
+        rc.finish().unwrap();
+        std::mem::drop(rc);
+        std::mem::drop(ctx);
+
+        let data = surface.finish_output_stream().unwrap();
+        let data = data.downcast::<Vec<u8>>().unwrap();
+        let pdf = String::from_utf8_lossy(&data);
+        // the text is drawn with an embedded font that maps back to characters,
+        // rather than as an image.
+        assert!(pdf.contains("/ToUnicode"));
+        assert!(!pdf.contains("/Subtype /Image"));
+    }
+
+    #[test]
+    fn image_regions_are_updated() {
+        let red = [255, 0, 0, 255, 255, 0, 0, 255];
+        let mut image = make_surface(2, 1, &red, ImageFormat::RgbaSeparate)
+            .map(CairoImage::new)
+            .unwrap();
+        let shared = image.clone();
+        image
+            .update_region(1, 0, 1, 1, &[0, 0, 255], ImageFormat::Rgb)
+            .unwrap();
+        assert!(image
+            .update_region(1, 0, 2, 1, &[0; 6], ImageFormat::Rgb)
+            .is_err());
+        std::mem::drop(shared);
+
+        let data = image.surface.data().unwrap();
+        let pixel = |x: usize| {
+            u32::from_ne_bytes([
+                data[x * 4],
+                data[x * 4 + 1],
+                data[x * 4 + 2],
+                data[x * 4 + 3],
+            ])
+        };
+        assert_eq!(pixel(0), 0xffff0000);
+        assert_eq!(pixel(1), 0xff0000ff);
+    }
+
+    #[test]
+    fn images_are_read_back() {
+        let rgba = [255, 0, 0, 255, 0, 0, 255, 102];
+        let image = make_surface(2, 1, &rgba, ImageFormat::RgbaSeparate)
+            .map(CairoImage::new)
+            .unwrap();
+        let shared = image.clone();
+        assert_eq!(shared.to_rgba_bytes().unwrap(), rgba);
+        let rgb = make_surface(1, 1, &[0, 128, 255], ImageFormat::Rgb)
+            .map(CairoImage::new)
+            .unwrap();
+        assert_eq!(rgb.to_rgba_bytes().unwrap(), [0, 128, 255, 255]);
+    }
+
+    #[test]
+    fn masked_fills_use_mask_alpha() {
+        let opaque_then_clear = [0, 0, 255, 255, 0, 0, 255, 0];
+        let mask = make_surface(2, 1, &opaque_then_clear, ImageFormat::RgbaSeparate)
+            .map(CairoImage::new)
+            .unwrap();
+        let mut surface = ImageSurface::create(Format::ARgb32, 4, 1).unwrap();
+        let ctx = Context::new(&surface).unwrap();
+        let mut rc = CairoRenderContext::new(&ctx);
+        rc.fill_with_mask(Rect::new(0., 0., 4., 1.), &Color::RED, &mask);
+        rc.finish().unwrap();
+        std::mem::drop(rc);
+        std::mem::drop(ctx);
+
+        let data = surface.data().unwrap();
+        let pixel = |x: usize| {
+            u32::from_ne_bytes([
+                data[x * 4],
+                data[x * 4 + 1],
+                data[x * 4 + 2],
+                data[x * 4 + 3],
+            ])
+        };
+        assert_eq!(pixel(0), 0xffff0000);
+        assert_eq!(pixel(3), 0);
+    }
+
+    #[test]
+    fn sub_images_are_masks_of_their_area() {
+        let clear_then_opaque = [0, 0, 255, 0, 0, 0, 255, 255];
+        let sheet = make_surface(2, 1, &clear_then_opaque, ImageFormat::RgbaSeparate)
+            .map(CairoImage::new)
+            .unwrap();
+        let mask = sheet.sub_image(Rect::new(1., 0., 2., 1.));
+        assert_eq!(mask.size(), Size::new(1., 1.));
+        assert_eq!(mask.to_rgba_bytes().unwrap(), [0, 0, 255, 255]);
+        let mut surface = ImageSurface::create(Format::ARgb32, 4, 1).unwrap();
+        let ctx = Context::new(&surface).unwrap();
+        let mut rc = CairoRenderContext::new(&ctx);
+        rc.fill_with_mask(Rect::new(0., 0., 4., 1.), &Color::RED, &mask);
+        rc.finish().unwrap();
+        std::mem::drop(rc);
+        std::mem::drop(ctx);
+
+        let data = surface.data().unwrap();
+        for px in data.chunks_exact(4) {
+            assert_eq!(u32::from_ne_bytes([px[0], px[1], px[2], px[3]]), 0xffff0000);
+        }
+    }
+
+    #[test]
+    fn opacity_masks_fade_what_is_drawn_as_one_layer() {
+        let mask = [0, 0, 0, 255, 0, 0, 0, 255, 0, 0, 0, 128, 0, 0, 0, 0];
+        let mask = make_surface(4, 1, &mask, ImageFormat::RgbaSeparate)
+            .map(CairoImage::new)
+            .unwrap();
+        let mut surface = ImageSurface::create(Format::ARgb32, 8, 1).unwrap();
+        let ctx = Context::new(&surface).unwrap();
+        let mut rc = CairoRenderContext::new(&ctx);
+        rc.fill(Rect::new(0., 0., 8., 1.), &Color::BLUE);
+        rc.with_opacity_mask(&mask, Rect::new(0., 0., 4., 1.), |rc| {
+            // overlapping fills are masked together, not one by one.
+            rc.fill(Rect::new(0., 0., 8., 1.), &Color::RED);
+            rc.fill(Rect::new(0., 0., 8., 1.), &Color::RED);
+            rc.save()?;
+            rc.restore()
+        })
+        .unwrap();
+        rc.begin_opacity_mask(&mask, Rect::new(0., 0., 1., 1.))
+            .unwrap();
+        assert!(matches!(rc.restore(), Err(Error::StackUnbalance)));
+        rc.end_opacity_mask().unwrap();
+        assert!(matches!(rc.end_opacity_mask(), Err(Error::StackUnbalance)));
+        rc.finish().unwrap();
+        std::mem::drop(rc);
+        std::mem::drop(ctx);
+
+        let data = surface.data().unwrap();
+        let pixel = |x: usize| {
+            u32::from_ne_bytes([
+                data[x * 4],
+                data[x * 4 + 1],
+                data[x * 4 + 2],
+                data[x * 4 + 3],
+            ])
+        };
+        assert_eq!(pixel(0), 0xffff0000);
+        assert_eq!(pixel(2), 0xff80007f);
+        // nothing is drawn where the mask is transparent, or outside of it.
+        assert_eq!(pixel(3), 0xff0000ff);
+        assert_eq!(pixel(6), 0xff0000ff);
+    }
+
+    #[test]
+    fn global_alpha_fades_what_is_drawn() {
+        let mut surface = ImageSurface::create(Format::ARgb32, 4, 1).unwrap();
+        let ctx = Context::new(&surface).unwrap();
+        let mut rc = CairoRenderContext::new(&ctx);
+        rc.with_save(|rc| {
+            rc.set_global_alpha(0.5);
+            rc.fill(Rect::new(0., 0., 1., 1.), &Color::RED);
+            rc.stroke(Line::new((1., 0.5), (2., 0.5)), &Color::RED, 1.);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(rc.global_alpha(), 1.0);
+        rc.fill(Rect::new(2., 0., 3., 1.), &Color::RED);
+        rc.fill_with_alpha(Rect::new(3., 0., 4., 1.), &Color::RED, 0.0);
+        rc.finish().unwrap();
+        std::mem::drop(rc);
+        std::mem::drop(ctx);
+
+        let data = surface.data().unwrap();
+        let pixel = |x: usize| {
+            u32::from_ne_bytes([
+                data[x * 4],
+                data[x * 4 + 1],
+                data[x * 4 + 2],
+                data[x * 4 + 3],
+            ])
+        };
+        assert_eq!(pixel(0), 0x80800000);
+        assert_eq!(pixel(1), 0x80800000);
+        assert_eq!(pixel(2), 0xffff0000);
+        assert_eq!(pixel(3), 0);
+    }
+
+    #[test]
+    fn dithered_gradients_mix_neighboring_colors() {
+        let mut surface = ImageSurface::create(Format::ARgb32, 16, 4).unwrap();
+        let ctx = Context::new(&surface).unwrap();
+        let mut rc = CairoRenderContext::new(&ctx);
+        let gradient = piet::FixedLinearGradient {
+            start: Point::new(0., 0.),
+            end: Point::new(16., 0.),
+            stops: vec![
+                piet::GradientStop {
+                    pos: 0.0,
+                    color: Color::rgb8(10, 10, 10),
+                },
+                piet::GradientStop {
+                    pos: 1.0,
+                    color: Color::rgb8(12, 12, 12),
+                },
+            ],
+        };
+        let options = GradientOptions {
+            dither: true,
+            ..GradientOptions::default()
+        };
+        let brush = rc.gradient_with_options(gradient, options).unwrap();
+        rc.fill(Rect::new(0., 0., 16., 4.), &brush);
+        rc.finish().unwrap();
+        std::mem::drop(rc);
+        std::mem::drop(ctx);
+
+        let stride = surface.stride() as usize;
+        let data = surface.data().unwrap();
+        // a quarter of the way along, the color is 10.5.
+        let column: Vec<u8> = (0..4).map(|y| data[y * stride + 4 * 4 + 1]).collect();
+        assert!(column.contains(&10) && column.contains(&11), "{:?}", column);
+        assert!((0..4).all(|y| data[y * stride + 4 * 4 + 3] == 255));
+    }
+
+    #[test]
+    fn fill_and_stroke_matches_fill_then_stroke() {
+        let draw = |combined: bool, alpha: f64| {
+            let mut surface = ImageSurface::create(Format::ARgb32, 12, 12).unwrap();
+            let ctx = Context::new(&surface).unwrap();
+            let mut rc = CairoRenderContext::new(&ctx);
+            rc.set_global_alpha(alpha);
+            let circle = Circle::new((6., 6.), 4.);
+            let style = StrokeStyle::new();
+            if combined {
+                rc.fill_and_stroke(circle, &Color::RED, &Color::BLUE, 2., &style);
+            } else {
+                rc.fill(circle, &Color::RED);
+                rc.stroke_styled(circle, &Color::BLUE, 2., &style);
+            }
+            rc.finish().unwrap();
+            std::mem::drop(rc);
+            std::mem::drop(ctx);
+            let data = surface.data().unwrap().to_vec();
+            data
+        };
+        for alpha in [1.0, 0.5] {
+            assert!(draw(true, alpha) == draw(false, alpha));
+        }
+    }
+
+    #[test]
+    fn circles_match_their_paths() {
+        let draw = |as_path: bool| {
+            let mut surface = ImageSurface::create(Format::ARgb32, 20, 20).unwrap();
+            let ctx = Context::new(&surface).unwrap();
+            let mut rc = CairoRenderContext::new(&ctx);
+            // zoomed in, where a circle is drawn as an arc in device space.
+            rc.transform(Affine::scale(1000.));
+            let circle = Circle::new((0.01, 0.01), 0.008);
+            if as_path {
+                let path = circle.to_path(1e-9);
+                rc.fill(&path, &Color::RED);
+                rc.stroke(&path, &Color::BLUE, 0.001);
+            } else {
+                rc.fill(circle, &Color::RED);
+                rc.stroke(circle, &Color::BLUE, 0.001);
+            }
+            rc.finish().unwrap();
+            std::mem::drop(rc);
+            std::mem::drop(ctx);
+            let data = surface.data().unwrap().to_vec();
+            data
+        };
+        let (arcs, paths) = (draw(false), draw(true));
+        assert!(arcs.iter().any(|&byte| byte != 0));
+        for (a, b) in arcs.iter().zip(&paths) {
+            assert!((*a as i32 - *b as i32).abs() <= 8, "{} != {}", a, b);
+        }
+    }
+
+    #[test]
+    fn fill_many_matches_separate_fills() {
+        let draw = |many: bool| {
+            let mut surface = ImageSurface::create(Format::ARgb32, 24, 12).unwrap();
+            let ctx = Context::new(&surface).unwrap();
+            let mut rc = CairoRenderContext::new(&ctx);
+            let circles = [Circle::new((6., 6.), 3.), Circle::new((18., 6.), 3.)];
+            let style = StrokeStyle::new();
+            if many {
+                rc.fill_many(circles, &Color::RED);
+                rc.stroke_many(circles, &Color::BLUE, 1., &style);
+            } else {
+                for circle in circles {
+                    rc.fill(circle, &Color::RED);
+                    rc.stroke_styled(circle, &Color::BLUE, 1., &style);
+                }
+            }
+            rc.finish().unwrap();
+            std::mem::drop(rc);
+            std::mem::drop(ctx);
+            let data = surface.data().unwrap().to_vec();
+            data
+        };
+        assert!(draw(true) == draw(false));
+    }
+
+    #[test]
+    fn symbol_instances_match_separate_fills() {
+        let instances = [
+            (Affine::translate((4., 4.)), None),
+            (Affine::translate((12., 4.)), Some(Color::BLUE)),
+            (Affine::translate((20., 4.)) * Affine::scale(0.5), None),
+        ];
+        let draw = |stamped: bool| {
+            let mut surface = ImageSurface::create(Format::ARgb32, 24, 8).unwrap();
+            let ctx = Context::new(&surface).unwrap();
+            let mut rc = CairoRenderContext::new(&ctx);
+            let circle = Circle::new((0., 0.), 3.);
+            if stamped {
+                let symbol = rc.prepare_shape(circle);
+                rc.draw_symbol_instances(Symbol::Shape(&symbol), &Color::RED, &instances);
+            } else {
+                for (transform, color) in instances {
+                    let circle = transform * circle.to_path(1e-3);
+                    rc.fill(circle, &color.unwrap_or(Color::RED));
+                }
+            }
+            rc.finish().unwrap();
+            std::mem::drop(rc);
+            std::mem::drop(ctx);
+            let data = surface.data().unwrap().to_vec();
+            data
+        };
+        assert!(draw(true) == draw(false));
+    }
+
+    /// Stroke lines across rows of a 20 pixel wide surface, and return
+    /// which pixels of each row were drawn.
+    fn stroke_rows(
+        draw: impl FnOnce(&mut CairoRenderContext, &dyn Fn(usize) -> Line),
+    ) -> Vec<String> {
+        let mut surface = ImageSurface::create(Format::Rgb24, 20, 3).unwrap();
+        let ctx = Context::new(&surface).unwrap();
+        let mut rc = CairoRenderContext::new(&ctx);
+        let row = |y: usize| Line::new((0., y as f64 + 0.5), (20., y as f64 + 0.5));
+        draw(&mut rc, &row);
+        rc.finish().unwrap();
+        std::mem::drop(rc);
+        std::mem::drop(ctx);
+
+        let stride = surface.stride() as usize;
+        let data = surface.data().unwrap();
+        (0..3)
+            .map(|y| {
+                (0..20)
+                    .map(|x| {
+                        if data[y * stride + x * 4] > 128 {
+                            '#'
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn dash_offsets_shift_dashes() {
+        let rows = stroke_rows(|rc, row| {
+            let dashed = StrokeStyle::new().dash_pattern(&[4., 4.]);
+            rc.stroke_styled(row(0), &Color::WHITE, 1., &dashed);
+            let shifted = dashed.clone().dash_offset(2.);
+            rc.stroke_styled(row(1), &Color::WHITE, 1., &shifted);
+            // the same pattern again, which is only set if the offset is.
+            rc.stroke_styled(row(2), &Color::WHITE, 1., &dashed);
+        });
+        assert_eq!(rows[0], "####....####....####");
+        assert_eq!(rows[1], "##....####....####..");
+        assert_eq!(rows[2], rows[0]);
+    }
+
+    #[test]
+    fn stroke_state_is_restored() {
+        let rows = stroke_rows(|rc, row| {
+            let dashed = StrokeStyle::new().dash_pattern(&[4., 4.]);
+            rc.stroke_styled(row(0), &Color::WHITE, 1., &dashed);
+            rc.with_save(|rc| {
+                rc.stroke(row(1), &Color::WHITE, 1.);
+                Ok(())
+            })
+            .unwrap();
+            // cairo restored the dashes, so the solid stroke has to set them
+            // again, although it was the last one drawn.
+            rc.stroke(row(2), &Color::WHITE, 1.);
+        });
+        assert_eq!(rows[0], "####....####....####");
+        assert_eq!(rows[1], "####################");
+        assert_eq!(rows[2], rows[1]);
+    }
+
+    #[test]
+    fn high_quality_images_are_filtered_when_scaled_down() {
+        // alternating black and white columns.
+        let stripes = [0, 255].repeat(8 * 8);
+        let image = make_surface(16, 8, &stripes, ImageFormat::Grayscale)
+            .map(CairoImage::new)
+            .unwrap();
+        let sample = |interp| {
+            let mut surface = ImageSurface::create(Format::Rgb24, 1, 1).unwrap();
+            let ctx = Context::new(&surface).unwrap();
+            let mut rc = CairoRenderContext::new(&ctx);
+            rc.draw_image(&image, Rect::new(0., 0., 1., 1.), interp);
+            rc.finish().unwrap();
+            std::mem::drop(rc);
+            std::mem::drop(ctx);
+            let data = surface.data().unwrap();
+            data[0]
+        };
+        assert!(matches!(
+            sample(InterpolationMode::NearestNeighbor),
+            0 | 255
+        ));
+        let filtered = sample(InterpolationMode::HighQuality);
+        assert!((32..224).contains(&filtered), "{}", filtered);
+    }
 }
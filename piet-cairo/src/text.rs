@@ -5,14 +5,20 @@ use std::fmt;
 use std::ops::{Range, RangeBounds};
 use std::rc::Rc;
 
+use cairo::{FontOptions, HintMetrics, HintStyle};
 use pango::prelude::FontMapExt;
-use pango::{AttrColor, AttrInt, AttrList, AttrSize, AttrString};
+use pango::{
+    AttrColor, AttrFloat, AttrFontDesc, AttrFontFeatures, AttrInt, AttrList, AttrShape, AttrSize,
+    AttrString, FontDescription,
+};
 use pangocairo::FontMap;
+use unicode_segmentation::UnicodeSegmentation;
 
-use piet::kurbo::{Point, Rect, Size, Vec2};
+use piet::kurbo::{Affine, Point, Rect, Size, Vec2};
 use piet::{
-    util, Error, FontFamily, FontStyle, HitTestPoint, HitTestPosition, LineMetric, Text,
-    TextAlignment, TextAttribute, TextLayout, TextLayoutBuilder, TextStorage,
+    util, BaselineAlignment, Error, FontFamily, FontStyle, Hinting, HitTestPoint, HitTestPosition,
+    LineHeight, LineMetric, Overflow, ScriptPosition, Text, TextAlignment, TextAttribute,
+    TextLayout, TextLayoutBuilder, TextStorage,
 };
 
 type PangoLayout = pango::Layout;
@@ -26,9 +32,15 @@ type PangoAlignment = pango::Alignment;
 const PANGO_SCALE: f64 = pango::SCALE as f64;
 const UNBOUNDED_WRAP_WIDTH: i32 = -1;
 
+/// The text resolution that font sizes are relative to; at this resolution,
+/// a font size is the size in units of the context.
+const DEFAULT_DPI: f64 = 96.0;
+
 #[derive(Clone)]
 pub struct CairoText {
     pango_context: PangoContext,
+    /// How much font sizes are scaled by, for the text resolution.
+    font_scale: f64,
 }
 
 #[derive(Clone)]
@@ -43,6 +55,17 @@ pub struct CairoTextLayout {
     line_metrics: Rc<[LineMetric]>,
     x_offsets: Rc<[i32]>,
     pango_layout: PangoLayout,
+
+    font_size: f64,
+    line_height: LineHeight,
+    paragraph_spacing: f64,
+    max_height: f64,
+    overflow: Overflow,
+
+    placeholders: Placeholders,
+    /// The pango index, size and ascent of each inline box, in the order
+    /// they were added.
+    inline_boxes: Rc<[(usize, Size, f64)]>,
 }
 
 pub struct CairoTextLayoutBuilder {
@@ -51,6 +74,15 @@ pub struct CairoTextLayoutBuilder {
     attributes: Vec<AttributeWithRange>,
     last_range_start_pos: usize,
     width_constraint: f64,
+    line_height: LineHeight,
+    paragraph_spacing: f64,
+    max_height: f64,
+    overflow: Overflow,
+    tab_stops: Vec<f64>,
+    tab_width: Option<f64>,
+    /// The text position, size and alignment of each inline box.
+    inline_boxes: Vec<(usize, Size, BaselineAlignment)>,
+    font_scale: f64,
     pango_layout: PangoLayout,
 }
 
@@ -60,20 +92,25 @@ struct AttributeWithRange {
 }
 
 impl AttributeWithRange {
-    fn into_pango(self) -> PangoAttribute {
-        let mut pango_attribute: PangoAttribute = match &self.attribute {
+    /// Convert to pango attributes; empty if pango has no equivalent.
+    ///
+    /// `font_size` is the layout's default font size, which superscripts and
+    /// subscripts are positioned relative to, and `font_scale` is how much
+    /// font sizes are scaled by.
+    fn into_pango(self, font_size: f64, font_scale: f64) -> Vec<PangoAttribute> {
+        let mut pango_attributes: Vec<PangoAttribute> = match &self.attribute {
             TextAttribute::FontFamily(family) => {
                 let family = family.name();
                 /*
                  * NOTE: If the family fails to resolve we just don't apply the attribute.
                  * That allows Pango to use its default font of choice to render that text
                  */
-                AttrString::new_family(family).into()
+                vec![AttrString::new_family(family).into()]
             }
 
             TextAttribute::FontSize(size) => {
-                let size = (size * PANGO_SCALE) as i32;
-                AttrSize::new_size_absolute(size).into()
+                let size = (size * font_scale * PANGO_SCALE) as i32;
+                vec![AttrSize::new_size_absolute(size).into()]
             }
 
             TextAttribute::Weight(weight) => {
@@ -104,17 +141,31 @@ impl AttributeWithRange {
                     }
                 }
 
-                AttrInt::new_weight(pango_weights[closest_index].1).into()
+                vec![AttrInt::new_weight(pango_weights[closest_index].1).into()]
             }
 
             TextAttribute::TextColor(text_color) => {
                 let (r, g, b, _) = text_color.as_rgba8();
-                AttrColor::new_foreground(
+                vec![AttrColor::new_foreground(
                     (r as u16 * 256) + (r as u16),
                     (g as u16 * 256) + (g as u16),
                     (b as u16 * 256) + (b as u16),
                 )
-                .into()
+                .into()]
+            }
+
+            TextAttribute::BackgroundColor(color) if color.as_rgba8().3 == 0 => Vec::new(),
+            TextAttribute::BackgroundColor(color) => {
+                let (r, g, b, a) = color.as_rgba8();
+                vec![
+                    AttrColor::new_background(
+                        (r as u16 * 256) + (r as u16),
+                        (g as u16 * 256) + (g as u16),
+                        (b as u16 * 256) + (b as u16),
+                    )
+                    .into(),
+                    AttrInt::new_background_alpha((a as u16 * 256) + (a as u16)).into(),
+                ]
             }
 
             TextAttribute::Style(style) => {
@@ -122,7 +173,7 @@ impl AttributeWithRange {
                     FontStyle::Regular => PangoStyle::Normal,
                     FontStyle::Italic => PangoStyle::Italic,
                 };
-                AttrInt::new_style(style).into()
+                vec![AttrInt::new_style(style).into()]
             }
 
             &TextAttribute::Underline(underline) => {
@@ -131,20 +182,70 @@ impl AttributeWithRange {
                 } else {
                     PangoUnderline::None
                 };
-                AttrInt::new_underline(underline).into()
+                vec![AttrInt::new_underline(underline).into()]
             }
 
             &TextAttribute::Strikethrough(strikethrough) => {
-                AttrInt::new_strikethrough(strikethrough).into()
+                vec![AttrInt::new_strikethrough(strikethrough).into()]
+            }
+
+            TextAttribute::LetterSpacing(spacing) => {
+                let spacing = (spacing * PANGO_SCALE) as i32;
+                vec![AttrInt::new_letter_spacing(spacing).into()]
+            }
+
+            // pango's rise does not accumulate, so when a range has both a baseline
+            // shift and a script position, whichever was added last wins.
+            TextAttribute::BaselineShift(shift) => {
+                let rise = (shift * PANGO_SCALE) as i32;
+                vec![AttrInt::new_rise(rise).into()]
+            }
+
+            TextAttribute::ScriptPosition(ScriptPosition::Normal) => Vec::new(),
+            &TextAttribute::ScriptPosition(script) => {
+                let rise = (script.baseline_shift(font_size) * PANGO_SCALE) as i32;
+                vec![
+                    AttrFloat::new_scale(script.font_scale()).into(),
+                    AttrInt::new_rise(rise).into(),
+                ]
+            }
+
+            // pango has no notion of word spacing; it is added as letter
+            // spacing on the spaces, by `word_spacing_attributes`.
+            TextAttribute::WordSpacing(_) => Vec::new(),
+
+            TextAttribute::FontFeatures(features) if features.is_empty() => Vec::new(),
+            TextAttribute::FontFeatures(features) => {
+                // pango takes features in the syntax of css `font-feature-settings`
+                let features = features
+                    .iter()
+                    .filter_map(|f| Some(format!("\"{}\" {}", f.tag_str()?, f.value)))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                vec![AttrFontFeatures::new(&features).into()]
+            }
+
+            TextAttribute::FontVariations(variations) if variations.is_empty() => Vec::new(),
+            TextAttribute::FontVariations(variations) => {
+                let variations = variations
+                    .iter()
+                    .filter_map(|var| Some(format!("{}={}", var.tag_str()?, var.value)))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                let mut desc = FontDescription::new();
+                desc.set_variations(Some(&variations));
+                vec![AttrFontDesc::new(&desc).into()]
             }
         };
 
         if let Some(range) = self.range {
-            pango_attribute.set_start_index(range.start.try_into().unwrap());
-            pango_attribute.set_end_index(range.end.try_into().unwrap());
+            for pango_attribute in &mut pango_attributes {
+                pango_attribute.set_start_index(range.start.try_into().unwrap());
+                pango_attribute.set_end_index(range.end.try_into().unwrap());
+            }
         }
 
-        pango_attribute
+        pango_attributes
     }
 }
 
@@ -155,6 +256,26 @@ impl CairoText {
         let fontmap = FontMap::default();
         CairoText {
             pango_context: fontmap.create_context(),
+            font_scale: 1.0,
+        }
+    }
+
+    /// Create a new factory for text that is drawn to a context with `scale`
+    /// device pixels per unit, and with fonts sized for `text_dpi`.
+    ///
+    /// Glyphs are hinted and positioned for the device pixels, so that
+    /// layouts measure the same as the text that is drawn. Font sizes are
+    /// relative to a resolution of 96 DPI, so at 120 DPI, for example, a
+    /// font size of 10 is drawn 12.5 units high; this matches the font
+    /// scaling that toolkits like GTK apply to their own text.
+    pub fn new_scaled(scale: f64, text_dpi: f64) -> CairoText {
+        let text = CairoText::new();
+        let matrix = pango::Matrix::new(scale, 0.0, 0.0, scale, 0.0, 0.0);
+        text.pango_context.set_matrix(Some(&matrix));
+        pangocairo::context_set_resolution(&text.pango_context, text_dpi);
+        CairoText {
+            font_scale: text_dpi / DEFAULT_DPI,
+            ..text
         }
     }
 }
@@ -179,6 +300,23 @@ impl Text for CairoText {
         Err(Error::NotSupported)
     }
 
+    fn set_hinting(&mut self, hinting: Hinting) {
+        let (hint_style, hint_metrics, round_positions) = match hinting {
+            Hinting::Default => (HintStyle::Default, HintMetrics::Default, true),
+            Hinting::Pixel => (HintStyle::Full, HintMetrics::On, true),
+            Hinting::Subpixel => (HintStyle::None, HintMetrics::Off, false),
+        };
+        let mut options = match FontOptions::new() {
+            Ok(options) => options,
+            Err(_) => return,
+        };
+        options.set_hint_style(hint_style);
+        options.set_hint_metrics(hint_metrics);
+        pangocairo::context_set_font_options(&self.pango_context, Some(&options));
+        self.pango_context
+            .set_round_glyph_positions(round_positions);
+    }
+
     fn new_text_layout(&mut self, text: impl TextStorage) -> Self::TextLayoutBuilder {
         let pango_layout = PangoLayout::new(&self.pango_context);
         pango_layout.set_text(text.as_str());
@@ -192,6 +330,14 @@ impl Text for CairoText {
             attributes: Vec::new(),
             last_range_start_pos: 0,
             width_constraint: f64::INFINITY,
+            line_height: LineHeight::Normal,
+            paragraph_spacing: 0.0,
+            max_height: f64::INFINITY,
+            overflow: Overflow::Visible,
+            tab_stops: Vec::new(),
+            tab_width: None,
+            inline_boxes: Vec::new(),
+            font_scale: self.font_scale,
             pango_layout,
         }
     }
@@ -211,6 +357,45 @@ impl TextLayoutBuilder for CairoTextLayoutBuilder {
         self
     }
 
+    fn max_height(mut self, height: f64) -> Self {
+        self.max_height = height;
+        self
+    }
+
+    fn overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    fn line_height(mut self, line_height: LineHeight) -> Self {
+        self.line_height = line_height;
+        self
+    }
+
+    fn paragraph_spacing(mut self, spacing: f64) -> Self {
+        self.paragraph_spacing = spacing;
+        self
+    }
+
+    fn tab_stops(mut self, stops: &[f64]) -> Self {
+        self.tab_stops = stops.to_vec();
+        self
+    }
+
+    fn tab_width(mut self, width: f64) -> Self {
+        self.tab_width = Some(width);
+        self
+    }
+
+    fn inline_box(mut self, index: usize, size: Size, alignment: BaselineAlignment) -> Self {
+        let mut index = index.min(self.text.len());
+        while !self.text.is_char_boundary(index) {
+            index -= 1;
+        }
+        self.inline_boxes.push((index, size, alignment));
+        self
+    }
+
     fn alignment(self, alignment: TextAlignment) -> Self {
         /*
          * NOTE: Pango has `auto_dir` enabled by default. This means that
@@ -278,62 +463,62 @@ impl TextLayoutBuilder for CairoTextLayoutBuilder {
         let pango_attributes = AttrList::new();
 
         pango_attributes.insert(pango::AttrInt::new_insert_hyphens(false));
-        pango_attributes.insert(
-            AttributeWithRange {
-                attribute: TextAttribute::FontFamily(self.defaults.font),
-                range: None,
-            }
-            .into_pango(),
-        );
-        pango_attributes.insert(
-            AttributeWithRange {
-                attribute: TextAttribute::FontSize(self.defaults.font_size),
-                range: None,
-            }
-            .into_pango(),
-        );
-        pango_attributes.insert(
-            AttributeWithRange {
-                attribute: TextAttribute::Weight(self.defaults.weight),
-                range: None,
-            }
-            .into_pango(),
-        );
-        pango_attributes.insert(
-            AttributeWithRange {
-                attribute: TextAttribute::TextColor(self.defaults.fg_color),
-                range: None,
-            }
-            .into_pango(),
-        );
-        pango_attributes.insert(
-            AttributeWithRange {
-                attribute: TextAttribute::Style(self.defaults.style),
-                range: None,
-            }
-            .into_pango(),
-        );
-        pango_attributes.insert(
-            AttributeWithRange {
-                attribute: TextAttribute::Underline(self.defaults.underline),
-                range: None,
-            }
-            .into_pango(),
-        );
-        pango_attributes.insert(
-            AttributeWithRange {
-                attribute: TextAttribute::Strikethrough(self.defaults.strikethrough),
+        let font_scale = self.font_scale;
+        let font_size = self.defaults.font_size * font_scale;
+        let (placeholders, box_indices) =
+            Placeholders::new(self.inline_boxes.iter().map(|(index, ..)| *index));
+        let (shapes, inline_boxes) = self.inline_box_attributes(&box_indices, font_size);
+        let word_spacing = self.word_spacing_attributes(&placeholders);
+        let baseline_shift =
+            self.defaults.baseline_shift + self.defaults.script.baseline_shift(font_size);
+        let defaults = [
+            TextAttribute::FontFamily(self.defaults.font),
+            TextAttribute::FontSize(self.defaults.font_size),
+            TextAttribute::Weight(self.defaults.weight),
+            TextAttribute::TextColor(self.defaults.fg_color),
+            TextAttribute::Style(self.defaults.style),
+            TextAttribute::Underline(self.defaults.underline),
+            TextAttribute::Strikethrough(self.defaults.strikethrough),
+            TextAttribute::LetterSpacing(self.defaults.letter_spacing),
+            TextAttribute::FontVariations(self.defaults.variations),
+            TextAttribute::FontFeatures(self.defaults.features),
+            TextAttribute::BackgroundColor(self.defaults.bg_color),
+            TextAttribute::ScriptPosition(self.defaults.script),
+            // this comes after the script position, so that its rise includes both.
+            TextAttribute::BaselineShift(baseline_shift),
+        ];
+        for attribute in defaults {
+            let attribute = AttributeWithRange {
+                attribute,
                 range: None,
+            };
+            for attribute in attribute.into_pango(font_size, font_scale) {
+                pango_attributes.insert(attribute);
             }
-            .into_pango(),
-        );
+        }
 
-        for attribute in self.attributes {
-            pango_attributes.insert(attribute.into_pango());
+        for mut attribute in self.attributes {
+            attribute.range = attribute.range.map(|range| placeholders.pango_range(range));
+            for attribute in attribute.into_pango(font_size, font_scale) {
+                pango_attributes.insert(attribute);
+            }
+        }
+        // these come last, so that they win over the letter spacing of the
+        // spaces.
+        for attribute in word_spacing.into_iter().chain(shapes) {
+            pango_attributes.insert(attribute);
+        }
+        if !placeholders.is_empty() {
+            self.pango_layout
+                .set_text(&placeholders.pango_text(self.text.as_str()));
         }
 
         self.pango_layout.set_attributes(Some(&pango_attributes));
         self.pango_layout.set_wrap(pango::WrapMode::WordChar);
+        if !self.tab_stops.is_empty() || self.tab_width.is_some() {
+            let tabs = pango_tab_array(&self.tab_stops, self.tab_width);
+            self.pango_layout.set_tabs(Some(&tabs));
+        }
         self.pango_layout.set_ellipsize(pango::EllipsizeMode::None);
 
         // invalid until update_width() is called
@@ -347,13 +532,103 @@ impl TextLayoutBuilder for CairoTextLayoutBuilder {
             line_metrics: Rc::new([]),
             x_offsets: Rc::new([]),
             pango_layout: self.pango_layout,
+            font_size,
+            line_height: self.line_height,
+            paragraph_spacing: self.paragraph_spacing,
+            max_height: self.max_height,
+            overflow: self.overflow,
+            placeholders,
+            inline_boxes: inline_boxes.into(),
         };
 
         layout.update_width(self.width_constraint);
+        layout.apply_ellipsis(self.width_constraint);
         Ok(layout)
     }
 }
 
+impl CairoTextLayoutBuilder {
+    /// Shape attributes that give the placeholder of each inline box the
+    /// box's size, and the pango index, size and ascent of each box.
+    ///
+    /// `box_indices` are the pango indices of the placeholders, and boxes are
+    /// aligned to the metrics of the default font.
+    fn inline_box_attributes(
+        &self,
+        box_indices: &[usize],
+        font_size: f64,
+    ) -> (Vec<PangoAttribute>, Vec<(usize, Size, f64)>) {
+        if self.inline_boxes.is_empty() {
+            return Default::default();
+        }
+        let mut font = FontDescription::new();
+        font.set_family(self.defaults.font.name());
+        font.set_absolute_size(font_size * PANGO_SCALE);
+        let metrics = self.pango_layout.context().metrics(Some(&font), None);
+        let ascent = metrics.ascent() as f64 / PANGO_SCALE;
+        let descent = metrics.descent() as f64 / PANGO_SCALE;
+        self.inline_boxes
+            .iter()
+            .zip(box_indices)
+            .map(|(&(_, size, alignment), &idx)| {
+                let box_ascent = alignment.box_ascent(size.height, ascent, descent);
+                let rect = pango::Rectangle::new(
+                    0,
+                    (-box_ascent * PANGO_SCALE) as i32,
+                    (size.width * PANGO_SCALE) as i32,
+                    (size.height * PANGO_SCALE) as i32,
+                );
+                let mut attribute: PangoAttribute = AttrShape::new(&rect, &rect).into();
+                attribute.set_start_index(idx as u32);
+                attribute.set_end_index((idx + PLACEHOLDER.len_utf8()) as u32);
+                (attribute, (idx, size, box_ascent))
+            })
+            .unzip()
+    }
+
+    /// Letter spacing for each space that has word spacing.
+    ///
+    /// Pango spreads the letter spacing of a character over both of its
+    /// sides, so a space with the letter spacing of the text around it plus
+    /// the word spacing is wider by the word spacing.
+    fn word_spacing_attributes(&self, placeholders: &Placeholders) -> Vec<PangoAttribute> {
+        let spacing_at = |idx: usize| {
+            let mut letter = self.defaults.letter_spacing;
+            let mut word = self.defaults.word_spacing;
+            let attributes = self
+                .attributes
+                .iter()
+                .filter(|attribute| match &attribute.range {
+                    Some(range) => range.contains(&idx),
+                    None => true,
+                });
+            for attribute in attributes {
+                match attribute.attribute {
+                    TextAttribute::LetterSpacing(spacing) => letter = spacing,
+                    TextAttribute::WordSpacing(spacing) => word = spacing,
+                    _ => (),
+                }
+            }
+            (letter, word)
+        };
+        self.text
+            .match_indices(' ')
+            .filter_map(|(idx, _)| {
+                let (letter, word) = spacing_at(idx);
+                if word == 0.0 {
+                    return None;
+                }
+                let spacing = ((letter + word) * PANGO_SCALE) as i32;
+                let mut attribute: PangoAttribute = AttrInt::new_letter_spacing(spacing).into();
+                let idx = placeholders.pango_index(idx);
+                attribute.set_start_index(idx as u32);
+                attribute.set_end_index(idx as u32 + 1);
+                Some(attribute)
+            })
+            .collect()
+    }
+}
+
 impl fmt::Debug for CairoTextLayoutBuilder {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("CairoTextLayoutBuilder").finish()
@@ -413,12 +688,18 @@ impl TextLayout for CairoTextLayout {
 
         let hitpos = line.x_to_index(x as i32);
         let rel_idx = if hitpos.is_inside() {
-            let idx = hitpos.index() as usize - line_start_idx;
-            let trailing_len: usize = line_text[idx..]
-                .chars()
-                .take(hitpos.trailing() as usize)
-                .map(char::len_utf8)
-                .sum();
+            let pango_idx = hitpos.index() as usize;
+            let idx = self.placeholders.text_offset(pango_idx) - line_start_idx;
+            // both sides of an inline box are before the text after it.
+            let trailing_len: usize = if self.placeholders.is_placeholder(pango_idx) {
+                0
+            } else {
+                line_text[idx..]
+                    .chars()
+                    .take(hitpos.trailing() as usize)
+                    .map(char::len_utf8)
+                    .sum()
+            };
             idx + trailing_len
         } else {
             let hit_is_left = x <= 0;
@@ -436,7 +717,15 @@ impl TextLayout for CairoTextLayout {
 
         let is_inside_y = point.y >= 0. && point.y <= self.size.height;
 
-        HitTestPoint::new(line_start_idx + rel_idx, hitpos.is_inside() && is_inside_y)
+        // the line metrics are in pango's space, which is offset from ours.
+        util::hit_test_point_on_line(
+            self,
+            Affine::translate(-self.pango_offset),
+            point,
+            line_number,
+            line_start_idx + rel_idx,
+            hitpos.is_inside() && is_inside_y,
+        )
     }
 
     fn hit_test_text_position(&self, idx: usize) -> HitTestPosition {
@@ -456,6 +745,7 @@ impl TextLayout for CairoTextLayout {
         // https://gitlab.gnome.org/GNOME/pango/-/issues/544
 
         let hack_around_eol = self.is_rtl && idx == self.text.len();
+        let idx = self.placeholders.pango_index(idx);
         let idx = if hack_around_eol {
             // pango doesn't care if this is a char boundary
             idx.saturating_sub(1)
@@ -472,11 +762,64 @@ impl TextLayout for CairoTextLayout {
 
         let point = Point::new(
             (x as f64 / PANGO_SCALE) - self.pango_offset.x,
-            (pos_rect.y() as f64 / PANGO_SCALE) + metric.baseline - self.pango_offset.y,
+            metric.y_offset + metric.baseline - self.pango_offset.y,
         );
 
         HitTestPosition::new(point, line_number)
     }
+
+    fn rects_for_range(&self, range: impl RangeBounds<usize>) -> Vec<Rect> {
+        let range = util::resolve_range(range, self.text.len());
+        let mut rects = Vec::new();
+        for (line_number, metric) in self.line_metrics.iter().enumerate() {
+            let start = range.start.max(metric.start_offset);
+            let end = range.end.min(metric.end_offset);
+            if start >= end {
+                continue;
+            }
+            let line = match self.pango_layout.line(line_number as i32) {
+                Some(line) => line,
+                None => continue,
+            };
+            // `LayoutLine::x_ranges` returns only half of the values that
+            // pango gives it, so the edges of each character are found
+            // instead, and merged into one span per bidi run.
+            let chars = self.text[metric.range()]
+                .char_indices()
+                .map(|(i, _)| metric.start_offset + i)
+                .filter(|idx| (start..end).contains(idx))
+                .map(|idx| self.placeholders.pango_index(idx));
+            let spans = chars
+                .chain(self.placeholders.between(start, end))
+                .map(|idx| {
+                    let leading = line.index_to_x(idx as i32, false) as f64 / PANGO_SCALE;
+                    let trailing = line.index_to_x(idx as i32, true) as f64 / PANGO_SCALE;
+                    (leading.min(trailing), leading.max(trailing))
+                });
+            for (x0, x1) in util::merge_spans(spans) {
+                let x0 = x0 - self.pango_offset.x;
+                let x1 = x1 - self.pango_offset.x;
+                let y0 = metric.y_offset;
+                rects.push(Rect::new(x0, y0, x1, y0 + metric.height));
+            }
+        }
+        rects
+    }
+
+    fn inline_boxes(&self) -> Vec<Rect> {
+        self.inline_boxes
+            .iter()
+            .filter_map(|&(idx, size, ascent)| {
+                let (line_number, _) = self.pango_layout.index_to_line_x(idx as i32, false);
+                // boxes on lines removed by overflow aren't drawn.
+                let metric = self.line_metrics.get(line_number as usize)?;
+                let pos = self.pango_layout.index_to_pos(idx as i32);
+                let x = pos.x().min(pos.x() + pos.width()) as f64 / PANGO_SCALE;
+                let y = metric.y_offset + metric.baseline - ascent;
+                Some(Rect::from_origin_size((x, y), size) - self.pango_offset)
+            })
+            .collect()
+    }
 }
 
 impl CairoTextLayout {
@@ -488,6 +831,27 @@ impl CairoTextLayout {
         self.pango_offset
     }
 
+    /// Returns `true` if our line metrics differ from pango's, in which case
+    /// lines must be drawn individually.
+    pub(crate) fn has_custom_line_spacing(&self) -> bool {
+        self.line_height != LineHeight::Normal || self.paragraph_spacing != 0.0 || self.is_clipped()
+    }
+
+    /// Returns `true` if lines have been removed because they overflow.
+    fn is_clipped(&self) -> bool {
+        self.overflow != Overflow::Visible
+            && self.line_metrics.len() < self.pango_layout.line_count() as usize
+    }
+
+    /// The horizontal offset of each line, in pango units.
+    pub(crate) fn x_offsets(&self) -> &[i32] {
+        &self.x_offsets
+    }
+
+    pub(crate) fn line_metrics(&self) -> &[LineMetric] {
+        &self.line_metrics
+    }
+
     fn update_width(&mut self, new_width: impl Into<Option<f64>>) {
         let new_width = new_width
             .into()
@@ -498,21 +862,23 @@ impl CairoTextLayout {
         let mut line_metrics = Vec::new();
         let mut x_offsets = Vec::new();
         let mut y_offset = 0.;
+        let mut natural_y_offset = 0.;
         let mut widest_logical_width = 0;
         let mut widest_whitespaceless_width = 0;
         let mut iterator = self.pango_layout.iter();
         loop {
             let line = iterator.line_readonly().unwrap();
 
-            let start_offset: usize = line.start_index().try_into().unwrap();
+            let pango_start: usize = line.start_index().try_into().unwrap();
             let length: usize = line.length().try_into().unwrap();
-            let end_offset = start_offset + length;
+            let start_offset = self.placeholders.text_offset(pango_start);
+            let line_end = self.placeholders.text_offset(pango_start + length);
 
             // Pango likes to give us the line range *without* the newline char(s).
-            let end_offset = match self.text.as_bytes()[end_offset..] {
-                [b'\r', b'\n', ..] => end_offset + 2,
-                [b'\r', ..] | [b'\n', ..] => end_offset + 1,
-                _ => end_offset,
+            let end_offset = match self.text.as_bytes()[line_end..] {
+                [b'\r', b'\n', ..] => line_end + 2,
+                [b'\r', ..] | [b'\n', ..] => line_end + 1,
+                _ => line_end,
             };
 
             let logical_rect = iterator.line_extents().1;
@@ -527,28 +893,50 @@ impl CairoTextLayout {
             //HACK: This check for RTL is to work around https://gitlab.gnome.org/GNOME/pango/-/issues/544
             let non_ws_width = if trailing_whitespace != 0 && !self.is_rtl {
                 //FIXME: this probably isn't correct for RTL
-                line.index_to_x((start_offset + trimmed_len) as i32, false)
+                let trimmed_end = self.placeholders.pango_index(start_offset + trimmed_len);
+                line.index_to_x(trimmed_end as i32, false)
             } else {
                 logical_rect.width()
             };
             widest_whitespaceless_width = widest_whitespaceless_width.max(non_ws_width);
 
+            let natural_height = logical_rect.height() as f64 / PANGO_SCALE;
+            let natural_baseline = (iterator.baseline() as f64 / PANGO_SCALE) - natural_y_offset;
+            let height = self.line_height.resolve(self.font_size, natural_height);
+            let is_hard_break = end_offset > line_end;
+
             x_offsets.push(logical_rect.x());
             line_metrics.push(LineMetric {
                 start_offset,
                 end_offset,
                 trailing_whitespace,
-                baseline: (iterator.baseline() as f64 / PANGO_SCALE) - y_offset,
-                height: logical_rect.height() as f64 / PANGO_SCALE,
+                baseline: natural_baseline + (height - natural_height) / 2.0,
+                height,
                 y_offset,
             });
-            y_offset += logical_rect.height() as f64 / PANGO_SCALE;
+            natural_y_offset += natural_height;
+            y_offset += height;
+            if is_hard_break {
+                y_offset += self.paragraph_spacing;
+            }
 
             if !iterator.next_line() {
                 break;
             }
         }
 
+        if self.overflow != Overflow::Visible {
+            // we always keep at least one line
+            let n_lines = line_metrics
+                .iter()
+                .skip(1)
+                .take_while(|lm| lm.y_offset + lm.height <= self.max_height)
+                .count()
+                + 1;
+            line_metrics.truncate(n_lines);
+            x_offsets.truncate(n_lines);
+        }
+
         //NOTE: Pango appears to always give us at least one line even with empty input
         self.line_metrics = line_metrics.into();
         self.x_offsets = x_offsets.into();
@@ -557,15 +945,174 @@ impl CairoTextLayout {
         let ink_extent = to_kurbo_rect(ink_extent);
         let logical_extent = to_kurbo_rect(logical_extent);
 
-        self.size = Size::new(
-            widest_whitespaceless_width as f64 / PANGO_SCALE,
-            logical_extent.height(),
-        );
+        let height = if self.has_custom_line_spacing() {
+            self.line_metrics
+                .last()
+                .map(|lm| lm.y_offset + lm.height)
+                .unwrap_or_default()
+        } else {
+            logical_extent.height()
+        };
+        self.size = Size::new(widest_whitespaceless_width as f64 / PANGO_SCALE, height);
 
         self.ink_rect = ink_extent;
         self.pango_offset = logical_extent.origin().to_vec2();
         self.trailing_ws_width = widest_logical_width as f64 / PANGO_SCALE;
     }
+
+    /// If lines were removed because they overflow, truncate the text after
+    /// the last line that is left, and end it with an ellipsis that fits in
+    /// `width`.
+    ///
+    /// Pango can ellipsize text itself, but its ellipsis is only drawn; it
+    /// isn't part of the text, which line metrics and hit testing see.
+    fn apply_ellipsis(&mut self, width: f64) {
+        let n_lines = self.line_metrics.len();
+        if self.overflow != Overflow::Ellipsis || !self.is_clipped() {
+            return;
+        }
+        let last = self.line_metrics[n_lines - 1].clone();
+        let prefix = &self.text[..last.start_offset];
+        let line = self.text[last.range()].trim_end();
+        // the x of the end of each grapheme of the line, which doesn't move
+        // when the text after it is removed.
+        let pango_line = self.pango_layout.line_readonly(n_lines as i32 - 1).unwrap();
+        let line_start = pango_line.index_to_x(pango_line.start_index(), false);
+        let ends = line
+            .grapheme_indices(true)
+            .map(|(idx, grapheme)| {
+                let idx = last.start_offset + idx;
+                let x = pango_line.index_to_x(self.placeholders.pango_index(idx) as i32, true);
+                (idx + grapheme.len(), (x - line_start) as f64 / PANGO_SCALE)
+            })
+            .collect::<Vec<_>>();
+
+        let mut text = format!("{}{}{}", prefix, line, ELLIPSIS);
+        self.placeholders.truncate(prefix.len() + line.len());
+        self.pango_layout
+            .set_text(&self.placeholders.pango_text(&text));
+        let ellipsis = self
+            .pango_layout
+            .index_to_pos(self.placeholders.pango_index(text.len() - ELLIPSIS.len()) as i32);
+        let ellipsis_width = ellipsis.width() as f64 / PANGO_SCALE;
+
+        // drop graphemes until the line and the ellipsis fit in the width.
+        let end = ends
+            .iter()
+            .rev()
+            .find(|(_, x)| x + ellipsis_width <= width)
+            .map(|(end, _)| end - last.start_offset)
+            .unwrap_or(0);
+        if end < line.len() {
+            text = format!("{}{}{}", prefix, &line[..end], ELLIPSIS);
+            self.placeholders.truncate(prefix.len() + end);
+            self.pango_layout
+                .set_text(&self.placeholders.pango_text(&text));
+        }
+        let placeholders = &self.placeholders;
+        self.inline_boxes = self
+            .inline_boxes
+            .iter()
+            .filter(|(idx, ..)| placeholders.is_placeholder(*idx))
+            .copied()
+            .collect();
+        self.text = Rc::new(text);
+        self.update_width(width);
+    }
+}
+
+const ELLIPSIS: &str = "\u{2026}";
+
+/// The character in pango's text in place of each inline box, which is given
+/// the box's size by a shape attribute.
+const PLACEHOLDER: char = '\u{FFFC}';
+
+/// The text offsets of the inline boxes of a layout, in order.
+///
+/// Pango's text has a placeholder before the text at the offset of each box,
+/// so indices into it are converted to and from indices into ours.
+#[derive(Clone, Default)]
+struct Placeholders(Rc<[usize]>);
+
+impl Placeholders {
+    /// The placeholders for boxes at `offsets`, and the pango index of the
+    /// placeholder of each box. Boxes at the same offset keep their order.
+    fn new(offsets: impl Iterator<Item = usize>) -> (Placeholders, Vec<usize>) {
+        let offsets = offsets.collect::<Vec<_>>();
+        let mut order = (0..offsets.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&n| offsets[n]);
+        let mut indices = vec![0; offsets.len()];
+        for (k, &n) in order.iter().enumerate() {
+            indices[n] = offsets[n] + k * PLACEHOLDER.len_utf8();
+        }
+        let sorted = order.iter().map(|&n| offsets[n]).collect();
+        (Placeholders(sorted), indices)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The pango indices of the placeholders, in order.
+    fn indices(&self) -> impl Iterator<Item = usize> + '_ {
+        (self.0.iter())
+            .enumerate()
+            .map(|(k, offset)| offset + k * PLACEHOLDER.len_utf8())
+    }
+
+    /// The pango index of the text at `idx`, which is after any boxes there.
+    fn pango_index(&self, idx: usize) -> usize {
+        idx + self.0.partition_point(|&offset| offset <= idx) * PLACEHOLDER.len_utf8()
+    }
+
+    /// The pango range of the text in `range`, which leaves out the boxes at
+    /// both of its ends.
+    fn pango_range(&self, range: Range<usize>) -> Range<usize> {
+        let start = self.pango_index(range.start);
+        let before_end = self.0.partition_point(|&offset| offset < range.end);
+        let end = range.end + before_end * PLACEHOLDER.len_utf8();
+        start..end.max(start)
+    }
+
+    /// The offset in our text of the pango index `idx`. Both sides of a
+    /// placeholder are at the offset of its box.
+    fn text_offset(&self, idx: usize) -> usize {
+        let before = self.indices().take_while(|&placeholder| placeholder < idx);
+        idx - before.count() * PLACEHOLDER.len_utf8()
+    }
+
+    fn is_placeholder(&self, idx: usize) -> bool {
+        self.indices().any(|placeholder| placeholder == idx)
+    }
+
+    /// The pango indices of the placeholders of the boxes between the text
+    /// offsets `start` and `end`, not including the boxes at either.
+    fn between(&self, start: usize, end: usize) -> impl Iterator<Item = usize> + '_ {
+        (self.0.iter())
+            .zip(self.indices())
+            .filter(move |(&offset, _)| start < offset && offset < end)
+            .map(|(_, idx)| idx)
+    }
+
+    /// `text`, with the placeholders inserted.
+    fn pango_text(&self, text: &str) -> String {
+        let mut pango_text =
+            String::with_capacity(text.len() + self.0.len() * PLACEHOLDER.len_utf8());
+        let mut last = 0;
+        for &offset in self.0.iter() {
+            pango_text.push_str(&text[last..offset]);
+            pango_text.push(PLACEHOLDER);
+            last = offset;
+        }
+        pango_text.push_str(&text[last..]);
+        pango_text
+    }
+
+    /// Remove the boxes after the text offset `offset`.
+    fn truncate(&mut self, offset: usize) {
+        let n = self.0.partition_point(|&box_offset| box_offset <= offset);
+        self.0 = self.0[..n].into();
+    }
 }
 
 fn to_kurbo_rect(r: pango::Rectangle) -> Rect {
@@ -578,6 +1125,26 @@ fn to_kurbo_rect(r: pango::Rectangle) -> Rect {
     )
 }
 
+/// Build the pango tabs for `stops`, followed by a stop at every multiple of `tab_width`.
+fn pango_tab_array(stops: &[f64], tab_width: Option<f64>) -> pango::TabArray {
+    let mut positions = stops.to_vec();
+    if let Some(width) = tab_width.filter(|width| *width > 0.0) {
+        // past the last tab, pango repeats the interval between the last two.
+        let last = positions.last().copied().unwrap_or(0.0);
+        let next = util::next_tab_stop(last, &[], width);
+        positions.extend([next, next + width]);
+    }
+    let mut tabs = pango::TabArray::new(positions.len() as i32, false);
+    for (i, position) in positions.iter().enumerate() {
+        tabs.set_tab(
+            i as i32,
+            pango::TabAlign::Left,
+            (position * PANGO_SCALE) as i32,
+        );
+    }
+    tabs
+}
+
 impl fmt::Debug for CairoTextLayout {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("CairoTextLayout").finish()
@@ -619,6 +1186,197 @@ mod test {
         assert_close!(line.height, 12.0, 3.0);
     }
 
+    #[test]
+    fn scaled_text_is_measured_in_units() {
+        let text = "A line of text to measure";
+        let measure = |mut factory: CairoText, size: f64| {
+            let layout = factory
+                .new_text_layout(text)
+                .font(FontFamily::SANS_SERIF, size)
+                .build()
+                .unwrap();
+            (layout.size(), layout.line_metric(0).unwrap().baseline)
+        };
+        let (size, baseline) = measure(CairoText::new(), 20.0);
+
+        // hinting for more device pixels doesn't change the size much.
+        let (scaled_size, scaled_baseline) = measure(CairoText::new_scaled(2.0, 96.0), 20.0);
+        assert_close!(scaled_size.width, size.width, size.width * 0.05);
+        assert_close!(scaled_size.height, size.height, 1.0);
+        assert_close!(scaled_baseline, baseline, 1.0);
+
+        // fonts are sized for the resolution.
+        let (hidpi_size, hidpi_baseline) = measure(CairoText::new_scaled(2.0, 192.0), 10.0);
+        assert_close!(hidpi_size.width, size.width, size.width * 0.05);
+        assert_close!(hidpi_size.height, size.height, 1.0);
+        assert_close!(hidpi_baseline, baseline, 1.0);
+    }
+
+    #[test]
+    fn inline_boxes_are_gaps_in_the_text() {
+        let layout = |boxes: &[(usize, Size)]| {
+            let mut builder = CairoText::new()
+                .new_text_layout("ab cd")
+                .font(FontFamily::MONOSPACE, 12.0);
+            for &(index, size) in boxes {
+                builder = builder.inline_box(index, size, BaselineAlignment::Bottom);
+            }
+            builder.build().unwrap()
+        };
+        let plain = layout(&[]);
+        let size = Size::new(20.0, 30.0);
+        let boxed = layout(&[(3, size)]);
+        assert_eq!(boxed.text(), "ab cd");
+        assert_eq!(boxed.line_text(0), Some("ab cd"));
+        assert_close!(boxed.size().width, plain.size().width + 20.0, 0.5);
+
+        // the box sits on the baseline, and the line is tall enough for it.
+        let metric = boxed.line_metric(0).unwrap();
+        let rects = boxed.inline_boxes();
+        assert_eq!(rects.len(), 1);
+        let rect = rects[0];
+        assert_close!(rect.width(), size.width, 0.01);
+        assert_close!(rect.height(), size.height, 0.01);
+        assert_close!(rect.y1, metric.baseline, 0.5);
+        assert!(rect.y0 >= -0.5 && metric.baseline >= 30.0);
+
+        // the text after the box starts after it, and the box is at its offset.
+        let after = boxed.hit_test_text_position(3).point.x;
+        assert_close!(rect.x1, after, 0.5);
+        assert_close!(rect.x0, plain.hit_test_text_position(3).point.x, 0.5);
+        let hit = boxed.hit_test_point(rect.center());
+        assert_eq!(hit.idx, 3);
+        assert!(hit.is_inside);
+        let hit = boxed.hit_test_point(Point::new(after + 1.0, metric.baseline));
+        assert_eq!(hit.idx, 3);
+        assert_eq!(boxed.rects_for_range(..).len(), 1);
+        assert_close!(
+            boxed.rects_for_range(..)[0].width(),
+            boxed.size().width,
+            0.5
+        );
+
+        // boxes at the same offset are placed in the order they were added.
+        let two = layout(&[
+            (5, size),
+            (0, Size::new(5.0, 5.0)),
+            (5, Size::new(10.0, 10.0)),
+        ]);
+        let rects = two.inline_boxes();
+        assert_close!(rects[1].x0, 0.0, 0.01);
+        assert_close!(rects[0].x0, rects[1].x1 + plain.size().width, 0.5);
+        assert_close!(rects[2].x0, rects[0].x1, 0.01);
+        assert_eq!(two.line_metric(0).unwrap().end_offset, 5);
+
+        // a box is wrapped with the text after it.
+        let wrapped = CairoText::new()
+            .new_text_layout("ab cd")
+            .font(FontFamily::MONOSPACE, 12.0)
+            .inline_box(3, size, BaselineAlignment::Bottom)
+            .max_width(plain.size().width)
+            .build()
+            .unwrap();
+        assert_eq!(wrapped.line_count(), 2);
+        assert_eq!(wrapped.line_text(1), Some("cd"));
+        let second = wrapped.line_metric(1).unwrap();
+        let rect = wrapped.inline_boxes()[0];
+        assert_close!(rect.x0, 0.0, 0.01);
+        assert_close!(rect.y1, second.y_offset + second.baseline, 0.5);
+    }
+
+    /// A layout of three lines of monospace text, `max_height` high, with
+    /// room for nine characters on each line.
+    fn overflowing_layout(max_height: f64, overflow: Overflow) -> CairoTextLayout {
+        let mut factory = CairoText::new();
+        let char_width = factory
+            .new_text_layout("a")
+            .font(FontFamily::MONOSPACE, 12.0)
+            .build()
+            .unwrap()
+            .size()
+            .width;
+        factory
+            .new_text_layout("one line\ntwo lines\nthree lines")
+            .font(FontFamily::MONOSPACE, 12.0)
+            .max_width(char_width * 9.5)
+            .max_height(max_height)
+            .overflow(overflow)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn overflowing_lines_are_clipped() {
+        let line_height = overflowing_layout(f64::INFINITY, Overflow::Visible)
+            .line_metric(0)
+            .unwrap()
+            .height;
+        let visible = overflowing_layout(line_height * 2.5, Overflow::Visible);
+        assert_eq!(visible.line_count(), 4);
+
+        let clipped = overflowing_layout(line_height * 2.5, Overflow::Clip);
+        assert_eq!(clipped.line_count(), 2);
+        assert_eq!(clipped.line_text(1), Some("two lines\n"));
+        assert_close!(clipped.size().height, line_height * 2.0, 0.5);
+
+        // at least one line is kept.
+        let clipped = overflowing_layout(1.0, Overflow::Clip);
+        assert_eq!(clipped.line_count(), 1);
+    }
+
+    #[test]
+    fn ellipsis_is_part_of_the_text() {
+        let line_height = overflowing_layout(f64::INFINITY, Overflow::Visible)
+            .line_metric(0)
+            .unwrap()
+            .height;
+        let layout = overflowing_layout(line_height * 2.5, Overflow::Ellipsis);
+        assert_eq!(layout.line_count(), 2);
+        assert_eq!(layout.line_text(0), Some("one line\n"));
+        // the ellipsis takes the place of the last character that fits.
+        assert_eq!(layout.line_text(1), Some("two line\u{2026}"));
+        assert_eq!(layout.text(), "one line\ntwo line\u{2026}");
+        assert_close!(layout.size().height, line_height * 2.0, 0.5);
+
+        // the ellipsis can be hit.
+        let ellipsis = layout.text().len() - "\u{2026}".len();
+        let rect = layout.rects_for_range(ellipsis..)[0];
+        assert!(rect.width() > 0.0);
+        let hit = layout.hit_test_point(Point::new(rect.x0 + 1.0, rect.center().y));
+        assert_eq!(hit.idx, ellipsis);
+        assert_eq!(hit.line, 1);
+
+        // text that fits isn't changed.
+        let layout = overflowing_layout(line_height * 10.0, Overflow::Ellipsis);
+        assert_eq!(layout.line_count(), 4);
+        assert_eq!(layout.text(), "one line\ntwo lines\nthree lines");
+    }
+
+    #[test]
+    fn boxes_after_an_ellipsis_are_removed() {
+        let mut factory = CairoText::new();
+        let line_height = factory
+            .new_text_layout("a")
+            .font(FontFamily::MONOSPACE, 12.0)
+            .build()
+            .unwrap()
+            .size()
+            .height;
+        let layout = factory
+            .new_text_layout("one line\ntwo lines\nthree lines")
+            .font(FontFamily::MONOSPACE, 12.0)
+            .inline_box(10, Size::new(5.0, 5.0), BaselineAlignment::Bottom)
+            .inline_box(22, Size::new(5.0, 5.0), BaselineAlignment::Bottom)
+            .max_height(line_height * 2.5)
+            .overflow(Overflow::Ellipsis)
+            .build()
+            .unwrap();
+        assert_eq!(layout.text(), "one line\ntwo lines\u{2026}");
+        let rects = layout.inline_boxes();
+        assert_eq!(rects.len(), 1);
+        assert_close!(rects[0].x1, layout.hit_test_text_position(10).point.x, 0.5);
+    }
+
     #[test]
     #[cfg(any(target_os = "linux", target_os = "openbsd"))]
     fn test_hit_test_point_complex_1() {
@@ -16,7 +16,7 @@ const DPI: f64 = 96.;
 /// Feature "png" needed for save_to_file() and it's disabled by default for optional dependencies
 /// cargo run --example mondrian --features png
 fn main() {
-    let mut device = Device::new().unwrap();
+    let device = Device::new().unwrap();
     let mut bitmap = device.bitmap_target(WIDTH, HEIGHT, 1.0).unwrap();
     let mut rc = bitmap.render_context();
     Mondrian {
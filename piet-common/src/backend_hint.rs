@@ -0,0 +1,78 @@
+//! Choosing a backend at runtime.
+
+use crate::backend::BACKEND;
+use crate::Device;
+
+/// The backend that a [`Device`] draws with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Backend {
+    /// Cairo, on Linux and the BSDs.
+    Cairo,
+    /// Core Graphics, on macOS and iOS.
+    CoreGraphics,
+    /// Direct2D, on Windows.
+    Direct2D,
+    /// The 2D canvas of browsers.
+    Web,
+    /// WebGL2, in browsers.
+    WebGl,
+    /// tiny-skia, a software renderer.
+    TinySkia,
+}
+
+/// The kind of backend that is wanted from [`Device::with_backend`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum BackendHint {
+    /// Whichever backend piet-common was built with.
+    Default,
+    /// A backend built on the platform's own graphics library, which draws
+    /// and shapes text like the rest of the platform.
+    Platform,
+    /// A backend that draws on the CPU, in this process, which draws the
+    /// same pixels on every platform.
+    Software,
+    /// A backend that draws on the GPU, which is faster for scenes with
+    /// many shapes.
+    Gpu,
+}
+
+impl Backend {
+    /// Whether this backend is of the kind that `hint` asks for.
+    pub fn matches(self, hint: BackendHint) -> bool {
+        match hint {
+            BackendHint::Default => true,
+            BackendHint::Platform => matches!(
+                self,
+                Backend::Cairo | Backend::CoreGraphics | Backend::Direct2D | Backend::Web
+            ),
+            BackendHint::Software => matches!(self, Backend::TinySkia),
+            // Direct2D draws with Direct3D.
+            BackendHint::Gpu => matches!(self, Backend::Direct2D | Backend::WebGl),
+        }
+    }
+}
+
+impl Device {
+    /// Create a new device, if the backend is of the kind that `hint` asks
+    /// for.
+    ///
+    /// The backend is chosen by piet-common's features and the target when
+    /// it is built, so this returns
+    /// [`Error::NotSupported`](piet::Error::NotSupported) if that backend
+    /// doesn't match, and the app can fall back to [`Device::new`]. What a
+    /// backend can draw is given by
+    /// [`RenderContext::capabilities`](piet::RenderContext::capabilities).
+    pub fn with_backend(hint: BackendHint) -> Result<Device, piet::Error> {
+        if !BACKEND.matches(hint) {
+            return Err(piet::Error::NotSupported);
+        }
+        Device::new()
+    }
+
+    /// The backend that this device draws with.
+    pub fn backend(&self) -> Backend {
+        BACKEND
+    }
+}
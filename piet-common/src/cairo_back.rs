@@ -3,22 +3,21 @@
 
 //! Support for piet Cairo back-end.
 
-use cairo::{Context, Format, ImageSurface};
-#[cfg(feature = "png")]
-use png::{ColorType, Encoder};
-#[cfg(feature = "png")]
+use cairo::{Context, Format, ImageSurface, PdfSurface, PsSurface, Surface};
 use std::fs::File;
-#[cfg(feature = "png")]
-use std::io::BufWriter;
+use std::io::{BufWriter, Write};
 use std::marker::PhantomData;
 use std::path::Path;
 
-#[cfg(feature = "png")]
-use piet::util;
-use piet::{ImageBuf, ImageFormat};
+use piet::kurbo::{Rect, Size};
+use piet::{util, ImageBuf, ImageFormat};
 #[doc(hidden)]
 pub use piet_cairo::*;
 
+use crate::encode::{self, FileFormat};
+use crate::readback::{self, Region};
+use crate::{Backend, DocumentFormat};
+
 /// The `RenderContext` for the Cairo backend, which is selected.
 pub type Piet<'a> = CairoRenderContext<'a>;
 
@@ -47,6 +46,9 @@ pub type PietTextLayoutBuilder = CairoTextLayoutBuilder;
 /// This type matches `RenderContext::Image`
 pub type PietImage = CairoImage;
 
+/// The backend that this module provides.
+pub(crate) const BACKEND: Backend = Backend::Cairo;
+
 /// A struct that can be used to create bitmap render contexts.
 ///
 /// In the case of Cairo, being a software renderer, no state is needed.
@@ -65,6 +67,19 @@ pub struct BitmapTarget<'a> {
     phantom: PhantomData<&'a ()>,
 }
 
+/// A multi-page document, which provides a `RenderContext` for each page.
+pub struct DocumentTarget<'a> {
+    surface: DocumentSurface,
+    cr: Context,
+    has_page: bool,
+    phantom: PhantomData<&'a ()>,
+}
+
+enum DocumentSurface {
+    Pdf(PdfSurface),
+    Ps(PsSurface),
+}
+
 impl Device {
     /// Create a new device.
     pub fn new() -> Result<Device, piet::Error> {
@@ -74,8 +89,10 @@ impl Device {
     }
 
     /// Create a new bitmap target.
+    ///
+    /// Any number of targets can be used at the same time.
     pub fn bitmap_target(
-        &mut self,
+        &self,
         width: usize,
         height: usize,
         pix_scale: f64,
@@ -90,6 +107,80 @@ impl Device {
             phantom,
         })
     }
+
+    /// Create a new document target, which writes a document in `format` to
+    /// `writer`.
+    ///
+    /// Backends that can't produce multi-page documents return
+    /// [`Error::NotSupported`](piet::Error::NotSupported).
+    pub fn document_target(
+        &mut self,
+        writer: impl Write + 'static,
+        format: DocumentFormat,
+    ) -> Result<DocumentTarget, piet::Error> {
+        // the real size is set when each page is started.
+        let surface = match format {
+            DocumentFormat::Pdf => {
+                DocumentSurface::Pdf(PdfSurface::for_stream(1.0, 1.0, writer).map_err(cairo_err)?)
+            }
+            DocumentFormat::PostScript => {
+                DocumentSurface::Ps(PsSurface::for_stream(1.0, 1.0, writer).map_err(cairo_err)?)
+            }
+        };
+        let cr = Context::new(surface.surface()).map_err(cairo_err)?;
+        Ok(DocumentTarget {
+            surface,
+            cr,
+            has_page: false,
+            phantom: PhantomData,
+        })
+    }
+}
+
+impl<'a> DocumentTarget<'a> {
+    /// Start a new page of the given size, in points, and get a piet
+    /// `RenderContext` for it. This completes the previous page, if any.
+    ///
+    /// Note: caller is responsible for calling `finish` on the render
+    /// context at the end of rendering the page.
+    pub fn new_page(&mut self, size: Size) -> Result<CairoRenderContext, piet::Error> {
+        if self.has_page {
+            self.cr.show_page().map_err(cairo_err)?;
+        }
+        self.has_page = true;
+        match &self.surface {
+            DocumentSurface::Pdf(surface) => surface
+                .set_size(size.width, size.height)
+                .map_err(cairo_err)?,
+            DocumentSurface::Ps(surface) => surface.set_size(size.width, size.height),
+        }
+        Ok(CairoRenderContext::new(&self.cr))
+    }
+
+    /// Complete the last page, and write the rest of the document.
+    pub fn finish(self) -> Result<(), piet::Error> {
+        if self.has_page {
+            self.cr.show_page().map_err(cairo_err)?;
+        }
+        self.surface
+            .surface()
+            .finish_output_stream()
+            .map_err(|err| piet::Error::BackendError(err.error.into()))?;
+        Ok(())
+    }
+}
+
+impl DocumentSurface {
+    fn surface(&self) -> &Surface {
+        match self {
+            DocumentSurface::Pdf(surface) => surface,
+            DocumentSurface::Ps(surface) => surface,
+        }
+    }
+}
+
+fn cairo_err(err: cairo::Error) -> piet::Error {
+    piet::Error::BackendError(Box::new(err))
 }
 
 impl<'a> BitmapTarget<'a> {
@@ -101,6 +192,36 @@ impl<'a> BitmapTarget<'a> {
         CairoRenderContext::new(&self.cr)
     }
 
+    /// The size of the bitmap, in pixels.
+    pub fn pixel_size(&self) -> (usize, usize) {
+        (
+            self.surface.width() as usize,
+            self.surface.height() as usize,
+        )
+    }
+
+    /// Change the size of the bitmap to `width` by `height` pixels, and clear
+    /// it to transparent, so that it can be drawn again.
+    ///
+    /// The surface is only reallocated if its size in pixels changes.
+    pub fn resize(
+        &mut self,
+        width: usize,
+        height: usize,
+        pix_scale: f64,
+    ) -> Result<(), piet::Error> {
+        if self.pixel_size() != (width, height) {
+            self.surface = ImageSurface::create(Format::ARgb32, width as i32, height as i32)
+                .map_err(cairo_err)?;
+        }
+        self.cr = Context::new(&self.surface).map_err(cairo_err)?;
+        self.cr.set_operator(cairo::Operator::Clear);
+        self.cr.paint().map_err(cairo_err)?;
+        self.cr.set_operator(cairo::Operator::Over);
+        self.cr.scale(pix_scale, pix_scale);
+        Ok(())
+    }
+
     /// Get raw RGBA pixels from the bitmap by copying them into `buf`. If all the pixels were
     /// copied, returns the number of bytes written. If `buf` wasn't big enough, returns an error
     /// and doesn't write anything.
@@ -169,6 +290,46 @@ impl<'a> BitmapTarget<'a> {
         Ok(dst_len)
     }
 
+    /// Copy the pixels in `src_rect` into `buf`, in `fmt`, with rows `stride` bytes apart.
+    ///
+    /// `src_rect` is in pixels, and is rounded out to whole pixels; it must be inside the bitmap.
+    /// This is cheaper than copying the whole bitmap and cropping it, and lets the pixels be
+    /// written straight into a buffer that is laid out for something else, such as a
+    /// compositor's texture. Returns the number of bytes from the start of `buf` to the end of
+    /// the last pixel written.
+    ///
+    /// The formats with four bytes per pixel are supported; others return
+    /// [`Error::NotSupported`](piet::Error::NotSupported).
+    pub fn raw_pixels_into(
+        &mut self,
+        src_rect: impl Into<Rect>,
+        fmt: ImageFormat,
+        buf: &mut [u8],
+        stride: usize,
+    ) -> Result<usize, piet::Error> {
+        self.surface.flush();
+        let (width, height) = self.pixel_size();
+        let region = Region::new(src_rect.into(), width, height)?;
+        let src_stride = self.surface.stride() as usize;
+        // cairo stores native-endian ARGB, which is BGRA in memory on the
+        // little-endian machines we support.
+        let mut result = Ok(0);
+        self.surface
+            .with_data(|src| {
+                result = readback::copy_region(
+                    &src[region.offset(src_stride)..],
+                    src_stride,
+                    ImageFormat::BgraPremul,
+                    region,
+                    buf,
+                    stride,
+                    fmt,
+                );
+            })
+            .map_err(|err| piet::Error::BackendError(Box::new(err)))?;
+        result
+    }
+
     /// Get an in-memory pixel buffer from the bitmap.
     ///
     /// Note: caller is responsible for making sure the requested `ImageFormat` is supported.
@@ -184,28 +345,41 @@ impl<'a> BitmapTarget<'a> {
     }
 
     /// Save bitmap to RGBA PNG file
-    #[cfg(feature = "png")]
     pub fn save_to_file<P: AsRef<Path>>(mut self, path: P) -> Result<(), piet::Error> {
+        let file = BufWriter::new(File::create(path).map_err(Into::<Box<_>>::into)?);
+        self.save_to_writer(file, FileFormat::default())
+    }
+
+    /// Encode the bitmap as `format`, and write it to `writer`.
+    pub fn save_to_writer(
+        &mut self,
+        writer: impl Write,
+        format: FileFormat,
+    ) -> Result<(), piet::Error> {
         let width = self.surface.width() as usize;
         let height = self.surface.height() as usize;
         let mut data = vec![0; width * height * 4];
         self.copy_raw_pixels(ImageFormat::RgbaPremul, &mut data)?;
         util::unpremultiply_rgba(&mut data);
-        let file = BufWriter::new(File::create(path).map_err(Into::<Box<_>>::into)?);
-        let mut encoder = Encoder::new(file, width as u32, height as u32);
-        encoder.set_color(ColorType::Rgba);
-        encoder.set_depth(png::BitDepth::Eight);
-        encoder
-            .write_header()
-            .map_err(Into::<Box<_>>::into)?
-            .write_image_data(&data)
-            .map_err(Into::<Box<_>>::into)?;
-        Ok(())
+        encode::write_image(writer, &data, width, height, format)
+    }
+
+    /// Encode the bitmap as a JPEG, with a `quality` from 1 to 100.
+    ///
+    /// JPEG has no alpha channel, so the image is composited over white.
+    /// This requires the `jpeg` feature.
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_jpeg(&mut self, quality: u8) -> Result<Vec<u8>, piet::Error> {
+        let mut buf = Vec::new();
+        self.save_to_writer(&mut buf, FileFormat::Jpeg(quality))?;
+        Ok(buf)
     }
 
-    /// Stub for feature is missing
-    #[cfg(not(feature = "png"))]
-    pub fn save_to_file<P: AsRef<Path>>(self, _path: P) -> Result<(), piet::Error> {
-        Err(piet::Error::Unimplemented)
+    /// Encode the bitmap as a lossless WebP. This requires the `webp` feature.
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_webp(&mut self) -> Result<Vec<u8>, piet::Error> {
+        let mut buf = Vec::new();
+        self.save_to_writer(&mut buf, FileFormat::WebP)?;
+        Ok(buf)
     }
 }
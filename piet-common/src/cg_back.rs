@@ -3,21 +3,26 @@
 
 //! Support for piet CoreGraphics back-end.
 
+use std::fs::File;
+use std::io::{BufWriter, Write};
 use std::marker::PhantomData;
 use std::path::Path;
-#[cfg(feature = "png")]
-use std::{fs::File, io::BufWriter};
 
-use core_graphics::{color_space::CGColorSpace, context::CGContext};
-#[cfg(feature = "png")]
-use png::{ColorType, Encoder};
+use core_graphics::{
+    color_space::CGColorSpace,
+    context::CGContext,
+    geometry::{CGPoint, CGRect, CGSize},
+};
 
-#[cfg(feature = "png")]
-use piet::util;
-use piet::{Error, ImageBuf, ImageFormat};
+use piet::kurbo::{Rect, Size};
+use piet::{util, Error, ImageBuf, ImageFormat};
 #[doc(hidden)]
 pub use piet_coregraphics::*;
 
+use crate::encode::{self, FileFormat};
+use crate::readback::{self, Region};
+use crate::{Backend, DocumentFormat};
+
 /// The `RenderContext` for the CoreGraphics backend, which is selected.
 pub type Piet<'a> = CoreGraphicsContext<'a>;
 
@@ -46,6 +51,9 @@ pub type PietTextLayoutBuilder = CoreGraphicsTextLayoutBuilder;
 /// This type matches `RenderContext::Image`
 pub type PietImage = CoreGraphicsImage;
 
+/// The backend that this module provides.
+pub(crate) const BACKEND: Backend = Backend::CoreGraphics;
+
 /// A struct that can be used to create bitmap render contexts.
 pub struct Device {
     // Since not all backends can support `Device: Sync`, make it non-Sync here to, for fewer
@@ -62,6 +70,14 @@ pub struct BitmapTarget<'a> {
     phantom: PhantomData<&'a ()>,
 }
 
+/// A multi-page document, which provides a `RenderContext` for each page.
+///
+/// This backend can't produce multi-page documents, so this is never created.
+pub struct DocumentTarget<'a> {
+    never: std::convert::Infallible,
+    phantom: PhantomData<&'a ()>,
+}
+
 impl Device {
     /// Create a new device.
     pub fn new() -> Result<Device, piet::Error> {
@@ -71,22 +87,15 @@ impl Device {
     }
 
     /// Create a new bitmap target.
+    ///
+    /// Any number of targets can be used at the same time.
     pub fn bitmap_target(
-        &mut self,
+        &self,
         width: usize,
         height: usize,
         pix_scale: f64,
     ) -> Result<BitmapTarget, piet::Error> {
-        let ctx = CGContext::create_bitmap_context(
-            None,
-            width,
-            height,
-            8,
-            0,
-            &CGColorSpace::create_device_rgb(),
-            core_graphics::base::kCGImageAlphaPremultipliedLast,
-        );
-        ctx.scale(pix_scale, pix_scale);
+        let ctx = bitmap_context(width, height, pix_scale);
         let height = height as f64 * pix_scale.recip();
         Ok(BitmapTarget {
             ctx,
@@ -94,6 +103,49 @@ impl Device {
             phantom: PhantomData,
         })
     }
+
+    /// Create a new document target, which writes a document in `format` to
+    /// `writer`.
+    ///
+    /// This backend can't produce multi-page documents, so this always returns
+    /// [`Error::NotSupported`](piet::Error::NotSupported).
+    pub fn document_target(
+        &mut self,
+        _writer: impl Write + 'static,
+        _format: DocumentFormat,
+    ) -> Result<DocumentTarget, piet::Error> {
+        Err(piet::Error::NotSupported)
+    }
+}
+
+/// Make a bitmap context of `width` by `height` pixels, scaled by `pix_scale`.
+fn bitmap_context(width: usize, height: usize, pix_scale: f64) -> CGContext {
+    let ctx = CGContext::create_bitmap_context(
+        None,
+        width,
+        height,
+        8,
+        0,
+        &CGColorSpace::create_device_rgb(),
+        core_graphics::base::kCGImageAlphaPremultipliedLast,
+    );
+    // saved so that `resize` can get back to the unscaled state.
+    ctx.save();
+    ctx.scale(pix_scale, pix_scale);
+    ctx
+}
+
+impl<'a> DocumentTarget<'a> {
+    /// Start a new page of the given size, in points, and get a piet
+    /// `RenderContext` for it. This completes the previous page, if any.
+    pub fn new_page(&mut self, _size: Size) -> Result<CoreGraphicsContext, piet::Error> {
+        match self.never {}
+    }
+
+    /// Complete the last page, and write the rest of the document.
+    pub fn finish(self) -> Result<(), piet::Error> {
+        match self.never {}
+    }
 }
 
 impl<'a> BitmapTarget<'a> {
@@ -105,6 +157,36 @@ impl<'a> BitmapTarget<'a> {
         CoreGraphicsContext::new_y_up(&mut self.ctx, self.height, None)
     }
 
+    /// The size of the bitmap, in pixels.
+    pub fn pixel_size(&self) -> (usize, usize) {
+        (self.ctx.width() as usize, self.ctx.height() as usize)
+    }
+
+    /// Change the size of the bitmap to `width` by `height` pixels, and clear
+    /// it to transparent, so that it can be drawn again.
+    ///
+    /// The bitmap is only reallocated if its size in pixels changes.
+    pub fn resize(
+        &mut self,
+        width: usize,
+        height: usize,
+        pix_scale: f64,
+    ) -> Result<(), piet::Error> {
+        if self.pixel_size() == (width, height) {
+            self.ctx.restore();
+            self.ctx.clear_rect(CGRect::new(
+                &CGPoint::new(0.0, 0.0),
+                &CGSize::new(width as f64, height as f64),
+            ));
+            self.ctx.save();
+            self.ctx.scale(pix_scale, pix_scale);
+        } else {
+            self.ctx = bitmap_context(width, height, pix_scale);
+        }
+        self.height = height as f64 * pix_scale.recip();
+        Ok(())
+    }
+
     /// Get an in-memory pixel buffer from the bitmap.
     ///
     /// Note: caller is responsible for making sure the requested `ImageFormat` is supported.
@@ -157,29 +239,73 @@ impl<'a> BitmapTarget<'a> {
         Ok(size)
     }
 
+    /// Copy the pixels in `src_rect` into `buf`, in `fmt`, with rows `stride` bytes apart.
+    ///
+    /// `src_rect` is in pixels, and is rounded out to whole pixels; it must be inside the bitmap.
+    /// This is cheaper than copying the whole bitmap and cropping it, and lets the pixels be
+    /// written straight into a buffer that is laid out for something else, such as a
+    /// compositor's texture. Returns the number of bytes from the start of `buf` to the end of
+    /// the last pixel written.
+    ///
+    /// The formats with four bytes per pixel are supported; others return
+    /// [`Error::NotSupported`](piet::Error::NotSupported).
+    pub fn raw_pixels_into(
+        &mut self,
+        src_rect: impl Into<Rect>,
+        fmt: ImageFormat,
+        buf: &mut [u8],
+        stride: usize,
+    ) -> Result<usize, piet::Error> {
+        let (width, height) = self.pixel_size();
+        let region = Region::new(src_rect.into(), width, height)?;
+        let src_stride = self.ctx.bytes_per_row();
+        readback::copy_region(
+            &self.ctx.data()[region.offset(src_stride)..],
+            src_stride,
+            ImageFormat::RgbaPremul,
+            region,
+            buf,
+            stride,
+            fmt,
+        )
+    }
+
     /// Save bitmap to RGBA PNG file
-    #[cfg(feature = "png")]
     pub fn save_to_file<P: AsRef<Path>>(mut self, path: P) -> Result<(), piet::Error> {
+        let file = BufWriter::new(File::create(path).map_err(Into::<Box<_>>::into)?);
+        self.save_to_writer(file, FileFormat::default())
+    }
+
+    /// Encode the bitmap as `format`, and write it to `writer`.
+    pub fn save_to_writer(
+        &mut self,
+        writer: impl Write,
+        format: FileFormat,
+    ) -> Result<(), piet::Error> {
         let width = self.ctx.width() as usize;
         let height = self.ctx.height() as usize;
         let mut data = vec![0; width * height * 4];
         self.copy_raw_pixels(ImageFormat::RgbaPremul, &mut data)?;
         util::unpremultiply_rgba(&mut data);
-        let file = BufWriter::new(File::create(path).map_err(Into::<Box<_>>::into)?);
-        let mut encoder = Encoder::new(file, width as u32, height as u32);
-        encoder.set_color(ColorType::Rgba);
-        encoder.set_depth(png::BitDepth::Eight);
-        encoder
-            .write_header()
-            .map_err(Into::<Box<_>>::into)?
-            .write_image_data(&data)
-            .map_err(Into::<Box<_>>::into)?;
-        Ok(())
+        encode::write_image(writer, &data, width, height, format)
+    }
+
+    /// Encode the bitmap as a JPEG, with a `quality` from 1 to 100.
+    ///
+    /// JPEG has no alpha channel, so the image is composited over white.
+    /// This requires the `jpeg` feature.
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_jpeg(&mut self, quality: u8) -> Result<Vec<u8>, piet::Error> {
+        let mut buf = Vec::new();
+        self.save_to_writer(&mut buf, FileFormat::Jpeg(quality))?;
+        Ok(buf)
     }
 
-    /// Stub for feature is missing
-    #[cfg(not(feature = "png"))]
-    pub fn save_to_file<P: AsRef<Path>>(self, _path: P) -> Result<(), piet::Error> {
-        Err(Error::MissingFeature("png"))
+    /// Encode the bitmap as a lossless WebP. This requires the `webp` feature.
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_webp(&mut self) -> Result<Vec<u8>, piet::Error> {
+        let mut buf = Vec::new();
+        self.save_to_writer(&mut buf, FileFormat::WebP)?;
+        Ok(buf)
     }
 }
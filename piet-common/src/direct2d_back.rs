@@ -1,18 +1,12 @@
 //! Support for piet Direct2D back-end.
 
-#[cfg(feature = "png")]
+use std::cell::RefCell;
 use std::fs::File;
-#[cfg(feature = "png")]
-use std::io::BufWriter;
+use std::io::{BufWriter, Write};
 use std::path::Path;
 
-/// For saving to file functionality
-#[cfg(feature = "png")]
-use png::{ColorType, Encoder};
-
-#[cfg(feature = "png")]
-use piet::util;
-use piet::{ImageBuf, ImageFormat};
+use piet::kurbo::{Rect, Size};
+use piet::{util, ImageBuf, ImageFormat, RenderContext};
 use piet_direct2d::d2d::{Bitmap, Brush as D2DBrush};
 use piet_direct2d::d3d::{
     D3D11Device, D3D11DeviceContext, D3D11Texture2D, TextureMode, DXGI_MAP_READ,
@@ -20,6 +14,10 @@ use piet_direct2d::d3d::{
 #[doc(hidden)]
 pub use piet_direct2d::*;
 
+use crate::encode::{self, FileFormat};
+use crate::readback::{self, Region};
+use crate::{Backend, DocumentFormat};
+
 /// The `RenderContext` for the Direct2D backend, which is selected.
 pub type Piet<'a> = D2DRenderContext<'a>;
 
@@ -48,13 +46,17 @@ pub type PietTextLayoutBuilder = D2DTextLayoutBuilder;
 /// This type matches `RenderContext::Image`
 pub type PietImage = Bitmap;
 
+/// The backend that this module provides.
+pub(crate) const BACKEND: Backend = Backend::Direct2D;
+
 /// A struct that can be used to create bitmap render contexts.
 pub struct Device {
     d2d: D2DFactory,
     dwrite: DwriteFactory,
     d3d: D3D11Device,
     d3d_ctx: D3D11DeviceContext,
-    device: D2DDevice,
+    // in a cell so that targets can be made while others are in use.
+    device: RefCell<D2DDevice>,
 }
 
 /// A struct provides a `RenderContext` and then can have its bitmap extracted.
@@ -69,6 +71,14 @@ pub struct BitmapTarget<'a> {
     context: D2DDeviceContext,
 }
 
+/// A multi-page document, which provides a `RenderContext` for each page.
+///
+/// This backend can't produce multi-page documents, so this is never created.
+pub struct DocumentTarget<'a> {
+    never: std::convert::Infallible,
+    phantom: std::marker::PhantomData<&'a ()>,
+}
+
 trait WrapError<T> {
     fn wrap(self) -> Result<T, piet::Error>;
 }
@@ -93,35 +103,21 @@ impl Device {
             dwrite,
             d3d,
             d3d_ctx,
-            device,
+            device: RefCell::new(device),
         })
     }
 
     /// Create a new bitmap target.
+    ///
+    /// Any number of targets can be used at the same time.
     pub fn bitmap_target(
-        &mut self,
+        &self,
         width: usize,
         height: usize,
         pix_scale: f64,
     ) -> Result<BitmapTarget, piet::Error> {
-        let mut context = self.device.create_device_context().unwrap();
-
-        // Create a texture to render to
-        let tex = self
-            .d3d
-            .create_texture(width as u32, height as u32, TextureMode::Target)
-            .unwrap();
-
-        // Bind the backing texture to a D2D Bitmap
-        let target = unsafe {
-            context
-                .create_bitmap_from_dxgi(&tex.as_dxgi(), pix_scale as f32)
-                .unwrap()
-        };
-
-        context.set_target(&target);
-        // TODO ask about this? it was in basic.rs, but not here
-        context.set_dpi_scale(pix_scale as f32);
+        let mut context = self.device.borrow_mut().create_device_context().unwrap();
+        let tex = target_texture(&self.d3d, &mut context, width, height, pix_scale);
         context.begin_draw();
 
         Ok(BitmapTarget {
@@ -135,6 +131,59 @@ impl Device {
             context,
         })
     }
+
+    /// Create a new document target, which writes a document in `format` to
+    /// `writer`.
+    ///
+    /// This backend can't produce multi-page documents, so this always returns
+    /// [`Error::NotSupported`](piet::Error::NotSupported).
+    pub fn document_target(
+        &mut self,
+        _writer: impl Write + 'static,
+        _format: DocumentFormat,
+    ) -> Result<DocumentTarget, piet::Error> {
+        Err(piet::Error::NotSupported)
+    }
+}
+
+/// Make a texture of `width` by `height` pixels, and make it the target of
+/// `context`.
+fn target_texture(
+    d3d: &D3D11Device,
+    context: &mut D2DDeviceContext,
+    width: usize,
+    height: usize,
+    pix_scale: f64,
+) -> D3D11Texture2D {
+    // Create a texture to render to
+    let tex = d3d
+        .create_texture(width as u32, height as u32, TextureMode::Target)
+        .unwrap();
+
+    // Bind the backing texture to a D2D Bitmap
+    let target = unsafe {
+        context
+            .create_bitmap_from_dxgi(&tex.as_dxgi(), pix_scale as f32)
+            .unwrap()
+    };
+
+    context.set_target(&target);
+    // TODO ask about this? it was in basic.rs, but not here
+    context.set_dpi_scale(pix_scale as f32);
+    tex
+}
+
+impl<'a> DocumentTarget<'a> {
+    /// Start a new page of the given size, in points, and get a piet
+    /// `RenderContext` for it. This completes the previous page, if any.
+    pub fn new_page(&mut self, _size: Size) -> Result<D2DRenderContext, piet::Error> {
+        match self.never {}
+    }
+
+    /// Complete the last page, and write the rest of the document.
+    pub fn finish(self) -> Result<(), piet::Error> {
+        match self.never {}
+    }
 }
 
 impl<'a> BitmapTarget<'a> {
@@ -147,6 +196,36 @@ impl<'a> BitmapTarget<'a> {
         D2DRenderContext::new(self.d2d, text, &mut self.context)
     }
 
+    /// The size of the bitmap, in pixels.
+    pub fn pixel_size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Change the size of the bitmap to `width` by `height` pixels, and clear
+    /// it to transparent, so that it can be drawn again.
+    ///
+    /// The texture is only reallocated if its size in pixels changes.
+    pub fn resize(
+        &mut self,
+        width: usize,
+        height: usize,
+        pix_scale: f64,
+    ) -> Result<(), piet::Error> {
+        // the target can't be changed while drawing.
+        let _ = self.context.end_draw();
+        if self.pixel_size() == (width, height) {
+            self.context.set_dpi_scale(pix_scale as f32);
+        } else {
+            self.tex = target_texture(self.d3d, &mut self.context, width, height, pix_scale);
+            self.width = width;
+            self.height = height;
+        }
+        self.context.begin_draw();
+        let mut rc = self.render_context();
+        rc.clear(None, piet::Color::TRANSPARENT);
+        rc.finish()
+    }
+
     /// Get an in-memory pixel buffer from the bitmap.
     ///
     /// Note: caller is responsible for making sure the requested `ImageFormat` is supported.
@@ -207,30 +286,91 @@ impl<'a> BitmapTarget<'a> {
         Ok(size)
     }
 
+    /// Copy the pixels in `src_rect` into `buf`, in `fmt`, with rows `stride` bytes apart.
+    ///
+    /// `src_rect` is in pixels, and is rounded out to whole pixels; it must be inside the bitmap.
+    /// This is cheaper than copying the whole bitmap and cropping it, and lets the pixels be
+    /// written straight into a buffer that is laid out for something else, such as a
+    /// compositor's texture. Returns the number of bytes from the start of `buf` to the end of
+    /// the last pixel written.
+    ///
+    /// The formats with four bytes per pixel are supported; others return
+    /// [`Error::NotSupported`](piet::Error::NotSupported).
+    pub fn raw_pixels_into(
+        &mut self,
+        src_rect: impl Into<Rect>,
+        fmt: ImageFormat,
+        buf: &mut [u8],
+        stride: usize,
+    ) -> Result<usize, piet::Error> {
+        let region = Region::new(src_rect.into(), self.width, self.height)?;
+        self.context.end_draw()?;
+        let temp_texture = self
+            .d3d
+            .create_texture(self.width as u32, self.height as u32, TextureMode::Read)
+            .unwrap();
+
+        // TODO: Have a safe way to accomplish this :D
+        unsafe {
+            self.d3d_ctx
+                .inner()
+                .CopyResource(temp_texture.as_raw() as *mut _, self.tex.as_raw() as *mut _);
+            self.d3d_ctx.inner().Flush();
+
+            let surface = temp_texture.as_dxgi();
+            let mut mapped_rect = std::mem::zeroed();
+            let _hr = surface.Map(&mut mapped_rect, DXGI_MAP_READ);
+            let src_stride = mapped_rect.Pitch as usize;
+            let src = std::slice::from_raw_parts(mapped_rect.pBits, src_stride * self.height);
+            readback::copy_region(
+                &src[region.offset(src_stride)..],
+                src_stride,
+                ImageFormat::RgbaPremul,
+                region,
+                buf,
+                stride,
+                fmt,
+            )
+        }
+    }
+
     /// Save bitmap to RGBA PNG file
-    #[cfg(feature = "png")]
     pub fn save_to_file<P: AsRef<Path>>(mut self, path: P) -> Result<(), piet::Error> {
+        let file = BufWriter::new(File::create(path).map_err(Into::<Box<_>>::into)?);
+        self.save_to_writer(file, FileFormat::default())
+    }
+
+    /// Encode the bitmap as `format`, and write it to `writer`.
+    pub fn save_to_writer(
+        &mut self,
+        writer: impl Write,
+        format: FileFormat,
+    ) -> Result<(), piet::Error> {
         let width = self.width;
         let height = self.height;
         let mut data = vec![0; width * height * 4];
         self.copy_raw_pixels(ImageFormat::RgbaPremul, &mut data)?;
         util::unpremultiply_rgba(&mut data);
-        let file = BufWriter::new(File::create(path).map_err(Into::<Box<_>>::into)?);
-        let mut encoder = Encoder::new(file, width as u32, height as u32);
-        encoder.set_color(ColorType::Rgba);
-        encoder.set_depth(png::BitDepth::Eight);
-        encoder
-            .write_header()
-            .map_err(Into::<Box<_>>::into)?
-            .write_image_data(&data)
-            .map_err(Into::<Box<_>>::into)?;
-        Ok(())
+        encode::write_image(writer, &data, width, height, format)
     }
 
-    /// Stub for feature is missing
-    #[cfg(not(feature = "png"))]
-    pub fn save_to_file<P: AsRef<Path>>(self, _path: P) -> Result<(), piet::Error> {
-        Err(piet::Error::MissingFeature("png"))
+    /// Encode the bitmap as a JPEG, with a `quality` from 1 to 100.
+    ///
+    /// JPEG has no alpha channel, so the image is composited over white.
+    /// This requires the `jpeg` feature.
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_jpeg(&mut self, quality: u8) -> Result<Vec<u8>, piet::Error> {
+        let mut buf = Vec::new();
+        self.save_to_writer(&mut buf, FileFormat::Jpeg(quality))?;
+        Ok(buf)
+    }
+
+    /// Encode the bitmap as a lossless WebP. This requires the `webp` feature.
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_webp(&mut self) -> Result<Vec<u8>, piet::Error> {
+        let mut buf = Vec::new();
+        self.save_to_writer(&mut buf, FileFormat::WebP)?;
+        Ok(buf)
     }
 }
 
@@ -248,14 +388,14 @@ mod tests {
 
     #[test]
     fn bitmap_target_drop() {
-        let mut device = Device::new().unwrap();
+        let device = Device::new().unwrap();
         let bitmap_target = device.bitmap_target(640, 480, 1.0).unwrap();
         std::mem::drop(bitmap_target);
     }
 
     #[test]
     fn to_image_buf() {
-        let mut device = Device::new().unwrap();
+        let device = Device::new().unwrap();
         let mut target = device.bitmap_target(640, 480, 1.0).unwrap();
         let mut piet = target.render_context();
         piet.clip(Rect::ZERO);
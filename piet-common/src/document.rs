@@ -0,0 +1,13 @@
+//! Multi-page documents.
+
+/// The kind of file a [`DocumentTarget`] writes.
+///
+/// [`DocumentTarget`]: crate::DocumentTarget
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DocumentFormat {
+    /// Portable Document Format.
+    Pdf,
+    /// PostScript.
+    PostScript,
+}
@@ -0,0 +1,322 @@
+//! Encoding bitmaps as image files.
+
+use std::io::Write;
+
+#[cfg(feature = "png")]
+use png::{ColorType, Encoder};
+
+use piet::Error;
+
+/// A file format that a `BitmapTarget` can be saved as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FileFormat {
+    /// PNG, with the given compression. This requires the `png` feature.
+    Png(PngCompression),
+    /// An uncompressed 32-bit BMP, with an alpha channel.
+    Bmp,
+    /// The [Quite OK Image Format](https://qoiformat.org).
+    Qoi,
+    /// JPEG, with a quality from 1 to 100. This requires the `jpeg` feature.
+    ///
+    /// JPEG has no alpha channel, so the image is composited over white.
+    Jpeg(u8),
+    /// Lossless WebP. This requires the `webp` feature.
+    WebP,
+}
+
+/// How much effort the PNG encoder spends on making the file small.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PngCompression {
+    /// A balance between speed and size.
+    #[default]
+    Default,
+    /// Encode quickly, producing larger files.
+    Fast,
+    /// Produce the smallest files, at the cost of speed.
+    Best,
+}
+
+impl Default for FileFormat {
+    fn default() -> Self {
+        FileFormat::Png(PngCompression::Default)
+    }
+}
+
+/// Encode an image as `format`, and write it to `writer`.
+///
+/// `data` is tightly packed, unpremultiplied RGBA.
+pub(crate) fn write_image(
+    mut writer: impl Write,
+    data: &[u8],
+    width: usize,
+    height: usize,
+    format: FileFormat,
+) -> Result<(), Error> {
+    debug_assert_eq!(data.len(), width * height * 4);
+    match format {
+        FileFormat::Png(compression) => write_png(writer, data, width, height, compression),
+        FileFormat::Bmp => write_bmp(&mut writer, data, width, height).map_err(io_error),
+        FileFormat::Qoi => write_qoi(&mut writer, data, width, height).map_err(io_error),
+        FileFormat::Jpeg(quality) => write_jpeg(writer, data, width, height, quality),
+        FileFormat::WebP => write_webp(writer, data, width, height),
+    }
+}
+
+fn io_error(err: std::io::Error) -> Error {
+    Error::BackendError(err.into())
+}
+
+#[cfg(feature = "png")]
+fn write_png(
+    writer: impl Write,
+    data: &[u8],
+    width: usize,
+    height: usize,
+    compression: PngCompression,
+) -> Result<(), Error> {
+    let mut encoder = Encoder::new(writer, width as u32, height as u32);
+    encoder.set_color(ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_compression(match compression {
+        PngCompression::Default => png::Compression::Default,
+        PngCompression::Fast => png::Compression::Fast,
+        PngCompression::Best => png::Compression::Best,
+    });
+    encoder
+        .write_header()
+        .map_err(Into::<Box<_>>::into)?
+        .write_image_data(data)
+        .map_err(Into::<Box<_>>::into)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "png"))]
+fn write_png(
+    _writer: impl Write,
+    _data: &[u8],
+    _width: usize,
+    _height: usize,
+    _compression: PngCompression,
+) -> Result<(), Error> {
+    Err(Error::MissingFeature("png"))
+}
+
+#[cfg(feature = "jpeg")]
+fn write_jpeg(
+    mut writer: impl Write,
+    data: &[u8],
+    width: usize,
+    height: usize,
+    quality: u8,
+) -> Result<(), Error> {
+    let rgb: Vec<u8> = data
+        .chunks_exact(4)
+        .flat_map(|px| {
+            let alpha = px[3] as u32;
+            let over_white =
+                move |c: u8| ((c as u32 * alpha + 255 * (255 - alpha) + 127) / 255) as u8;
+            [over_white(px[0]), over_white(px[1]), over_white(px[2])]
+        })
+        .collect();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut writer, quality.clamp(1, 100))
+        .encode(&rgb, width as u32, height as u32, image::ColorType::Rgb8)
+        .map_err(|e| Error::BackendError(e.into()))
+}
+
+#[cfg(not(feature = "jpeg"))]
+fn write_jpeg(
+    _writer: impl Write,
+    _data: &[u8],
+    _width: usize,
+    _height: usize,
+    _quality: u8,
+) -> Result<(), Error> {
+    Err(Error::MissingFeature("jpeg"))
+}
+
+#[cfg(feature = "webp")]
+fn write_webp(writer: impl Write, data: &[u8], width: usize, height: usize) -> Result<(), Error> {
+    image::codecs::webp::WebPEncoder::new_lossless(writer)
+        .encode(data, width as u32, height as u32, image::ColorType::Rgba8)
+        .map_err(|e| Error::BackendError(e.into()))
+}
+
+#[cfg(not(feature = "webp"))]
+fn write_webp(
+    _writer: impl Write,
+    _data: &[u8],
+    _width: usize,
+    _height: usize,
+) -> Result<(), Error> {
+    Err(Error::MissingFeature("webp"))
+}
+
+/// Write a top-down BMP with a `BITMAPV4HEADER`, which is needed to describe
+/// the alpha channel.
+fn write_bmp(
+    writer: &mut impl Write,
+    data: &[u8],
+    width: usize,
+    height: usize,
+) -> std::io::Result<()> {
+    const FILE_HEADER_SIZE: u32 = 14;
+    const INFO_HEADER_SIZE: u32 = 108;
+    const BI_BITFIELDS: u32 = 3;
+    const LCS_SRGB: u32 = u32::from_be_bytes(*b"sRGB");
+
+    let offset = FILE_HEADER_SIZE + INFO_HEADER_SIZE;
+    let image_size = data.len() as u32;
+
+    writer.write_all(b"BM")?;
+    writer.write_all(&(offset + image_size).to_le_bytes())?;
+    writer.write_all(&[0; 4])?;
+    writer.write_all(&offset.to_le_bytes())?;
+
+    writer.write_all(&INFO_HEADER_SIZE.to_le_bytes())?;
+    writer.write_all(&(width as i32).to_le_bytes())?;
+    // a negative height means the rows are stored top to bottom.
+    writer.write_all(&(-(height as i32)).to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&32u16.to_le_bytes())?;
+    writer.write_all(&BI_BITFIELDS.to_le_bytes())?;
+    writer.write_all(&image_size.to_le_bytes())?;
+    // 72 DPI, in pixels per meter.
+    writer.write_all(&2835i32.to_le_bytes())?;
+    writer.write_all(&2835i32.to_le_bytes())?;
+    writer.write_all(&[0; 8])?;
+    // red, green, blue and alpha masks, for pixels stored as BGRA.
+    for mask in [0x00ff_0000u32, 0x0000_ff00, 0x0000_00ff, 0xff00_0000] {
+        writer.write_all(&mask.to_le_bytes())?;
+    }
+    writer.write_all(&LCS_SRGB.to_le_bytes())?;
+    // endpoints and gamma, which are unused for sRGB.
+    writer.write_all(&[0; 48])?;
+
+    let mut row = Vec::with_capacity(width * 4);
+    for src in data.chunks_exact(width * 4) {
+        row.clear();
+        for px in src.chunks_exact(4) {
+            row.extend_from_slice(&[px[2], px[1], px[0], px[3]]);
+        }
+        writer.write_all(&row)?;
+    }
+    Ok(())
+}
+
+/// Write a QOI image, following the [specification](https://qoiformat.org/qoi-specification.pdf).
+fn write_qoi(
+    writer: &mut impl Write,
+    data: &[u8],
+    width: usize,
+    height: usize,
+) -> std::io::Result<()> {
+    const OP_INDEX: u8 = 0x00;
+    const OP_DIFF: u8 = 0x40;
+    const OP_LUMA: u8 = 0x80;
+    const OP_RUN: u8 = 0xc0;
+    const OP_RGB: u8 = 0xfe;
+    const OP_RGBA: u8 = 0xff;
+    const MAX_RUN: u8 = 62;
+
+    let mut out = Vec::with_capacity(14 + data.len() / 2 + 8);
+    out.extend_from_slice(b"qoif");
+    out.extend_from_slice(&(width as u32).to_be_bytes());
+    out.extend_from_slice(&(height as u32).to_be_bytes());
+    // four channels; sRGB with linear alpha.
+    out.extend_from_slice(&[4, 0]);
+
+    let mut index = [[0u8; 4]; 64];
+    let mut prev = [0, 0, 0, 255];
+    let mut run = 0;
+    let n_pixels = width * height;
+    for (i, px) in data.chunks_exact(4).enumerate() {
+        let px = [px[0], px[1], px[2], px[3]];
+        if px == prev {
+            run += 1;
+            if run == MAX_RUN || i + 1 == n_pixels {
+                out.push(OP_RUN | (run - 1));
+                run = 0;
+            }
+            continue;
+        }
+        if run > 0 {
+            out.push(OP_RUN | (run - 1));
+            run = 0;
+        }
+
+        let hash =
+            (px[0] as usize * 3 + px[1] as usize * 5 + px[2] as usize * 7 + px[3] as usize * 11)
+                % 64;
+        if index[hash] == px {
+            out.push(OP_INDEX | hash as u8);
+        } else {
+            index[hash] = px;
+            if px[3] == prev[3] {
+                let dr = px[0].wrapping_sub(prev[0]) as i8;
+                let dg = px[1].wrapping_sub(prev[1]) as i8;
+                let db = px[2].wrapping_sub(prev[2]) as i8;
+                let dr_dg = dr.wrapping_sub(dg);
+                let db_dg = db.wrapping_sub(dg);
+                if (-2..=1).contains(&dr) && (-2..=1).contains(&dg) && (-2..=1).contains(&db) {
+                    out.push(
+                        OP_DIFF | ((dr + 2) as u8) << 4 | ((dg + 2) as u8) << 2 | (db + 2) as u8,
+                    );
+                } else if (-32..=31).contains(&dg)
+                    && (-8..=7).contains(&dr_dg)
+                    && (-8..=7).contains(&db_dg)
+                {
+                    out.push(OP_LUMA | (dg + 32) as u8);
+                    out.push(((dr_dg + 8) as u8) << 4 | (db_dg + 8) as u8);
+                } else {
+                    out.extend_from_slice(&[OP_RGB, px[0], px[1], px[2]]);
+                }
+            } else {
+                out.extend_from_slice(&[OP_RGBA, px[0], px[1], px[2], px[3]]);
+            }
+        }
+        prev = px;
+    }
+    out.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+    writer.write_all(&out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bmp_layout() {
+        let data = [255, 0, 0, 128, 0, 0, 255, 255];
+        let mut out = Vec::new();
+        write_image(&mut out, &data, 2, 1, FileFormat::Bmp).unwrap();
+        assert_eq!(&out[..2], b"BM");
+        assert_eq!(out.len(), 122 + 8);
+        assert_eq!(u32::from_le_bytes([out[2], out[3], out[4], out[5]]), 130);
+        // pixels are stored as BGRA
+        assert_eq!(&out[122..], &[0, 0, 255, 128, 255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn qoi_runs_and_ops() {
+        // two pixels matching the initial previous pixel, then one new color.
+        let data = [0, 0, 0, 255, 0, 0, 0, 255, 1, 1, 1, 255];
+        let mut out = Vec::new();
+        write_image(&mut out, &data, 3, 1, FileFormat::Qoi).unwrap();
+        assert_eq!(&out[..4], b"qoif");
+        assert_eq!(&out[4..14], &[0, 0, 0, 3, 0, 0, 0, 1, 4, 0]);
+        // a run of two, then a small diff.
+        assert_eq!(&out[14..16], &[0xc1, 0x40 | 3 << 4 | 3 << 2 | 3]);
+        assert_eq!(&out[16..], &[0, 0, 0, 0, 0, 0, 0, 1]);
+    }
+
+    #[cfg(feature = "jpeg")]
+    #[test]
+    fn jpeg_drops_alpha() {
+        let data = [255, 0, 0, 0, 0, 0, 255, 255];
+        let mut out = Vec::new();
+        write_image(&mut out, &data, 2, 1, FileFormat::Jpeg(90)).unwrap();
+        // the start of image marker.
+        assert_eq!(&out[..2], &[0xff, 0xd8]);
+    }
+}
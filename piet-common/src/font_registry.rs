@@ -0,0 +1,194 @@
+//! A set of fonts that can be loaded into any backend's text factory.
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use piet::{Error, FontFamily, Text};
+
+/// Where the data for a registered font comes from.
+#[derive(Debug, Clone)]
+pub enum FontSource {
+    /// Font data that is already in memory.
+    Data(Arc<[u8]>),
+    /// The path to a font file, which is read when the font is first loaded.
+    Path(PathBuf),
+}
+
+/// Fonts registered once, and loaded into whichever text factory needs them.
+///
+/// Backends differ in how custom fonts are loaded: some register them with the
+/// system for the whole process, while others keep them with a particular text
+/// factory. The registry hides these differences; call [`load_into`] with each
+/// new [`Text`] object, and fonts that are already available to it are skipped.
+///
+/// Each font keeps the [`FontFamily`] it resolved to the first time it was
+/// loaded, so the same family is returned regardless of which text factory the
+/// font is later loaded into.
+///
+/// Cloning a registry is cheap, and the clones share their fonts.
+///
+/// [`load_into`]: FontRegistry::load_into
+#[derive(Debug, Clone, Default)]
+pub struct FontRegistry {
+    fonts: Arc<Mutex<Vec<RegisteredFont>>>,
+}
+
+#[derive(Debug)]
+struct RegisteredFont {
+    source: FontSource,
+    family: Option<FontFamily>,
+}
+
+impl FontRegistry {
+    /// Create a new, empty registry.
+    pub fn new() -> FontRegistry {
+        FontRegistry::default()
+    }
+
+    /// Add a font to the registry.
+    ///
+    /// The font is not loaded until the registry is loaded into a text factory.
+    pub fn register(&self, source: impl Into<FontSource>) {
+        self.fonts.lock().unwrap().push(RegisteredFont {
+            source: source.into(),
+            family: None,
+        });
+    }
+
+    /// The families of the registered fonts that have been loaded so far.
+    pub fn families(&self) -> Vec<FontFamily> {
+        self.fonts
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|font| font.family.clone())
+            .collect()
+    }
+
+    /// Make all registered fonts available to `text`.
+    ///
+    /// Returns the family of each font, in the order the fonts were registered.
+    ///
+    /// If a font fails to load, the error is returned; fonts registered before
+    /// it remain loaded.
+    pub fn load_into(&self, text: &mut impl Text) -> Result<Vec<FontFamily>, Error> {
+        let mut fonts = self.fonts.lock().unwrap();
+        let mut families = Vec::with_capacity(fonts.len());
+        for font in fonts.iter_mut() {
+            let family = match &font.family {
+                // already loaded for this text factory, or for the whole process.
+                Some(family) if text.font_family(family.name()).is_some() => family.clone(),
+                known => {
+                    let loaded = match &font.source {
+                        FontSource::Data(data) => text.load_font(data)?,
+                        FontSource::Path(path) => {
+                            let data =
+                                std::fs::read(path).map_err(|e| Error::BackendError(e.into()))?;
+                            text.load_font(&data)?
+                        }
+                    };
+                    known.clone().unwrap_or(loaded)
+                }
+            };
+            font.family = Some(family.clone());
+            families.push(family);
+        }
+        Ok(families)
+    }
+}
+
+impl From<Vec<u8>> for FontSource {
+    fn from(src: Vec<u8>) -> FontSource {
+        FontSource::Data(src.into())
+    }
+}
+
+impl From<&[u8]> for FontSource {
+    fn from(src: &[u8]) -> FontSource {
+        FontSource::Data(src.into())
+    }
+}
+
+impl From<Arc<[u8]>> for FontSource {
+    fn from(src: Arc<[u8]>) -> FontSource {
+        FontSource::Data(src)
+    }
+}
+
+impl From<PathBuf> for FontSource {
+    fn from(src: PathBuf) -> FontSource {
+        FontSource::Path(src)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use piet::{NullText, NullTextLayoutBuilder, TextStorage};
+
+    /// A text factory that keeps the fonts loaded into it, named by their data.
+    #[derive(Clone, Default)]
+    struct FakeText {
+        loaded: Vec<String>,
+    }
+
+    impl Text for FakeText {
+        type TextLayout = <NullText as Text>::TextLayout;
+        type TextLayoutBuilder = NullTextLayoutBuilder;
+
+        fn font_family(&mut self, family_name: &str) -> Option<FontFamily> {
+            self.loaded
+                .iter()
+                .any(|name| name == family_name)
+                .then(|| FontFamily::new_unchecked(family_name))
+        }
+
+        fn load_font(&mut self, data: &[u8]) -> Result<FontFamily, Error> {
+            let name = std::str::from_utf8(data).map_err(|_| Error::MissingFont)?;
+            self.loaded.push(name.to_owned());
+            Ok(FontFamily::new_unchecked(name))
+        }
+
+        fn new_text_layout(&mut self, text: impl TextStorage) -> Self::TextLayoutBuilder {
+            NullText.new_text_layout(text)
+        }
+    }
+
+    #[test]
+    fn fonts_are_loaded_once_per_factory() {
+        let registry = FontRegistry::new();
+        registry.register(&b"One"[..]);
+        registry.register(b"Two".to_vec());
+        assert!(registry.families().is_empty());
+
+        let mut text = FakeText::default();
+        let families = registry.load_into(&mut text).unwrap();
+        let names = families.iter().map(FontFamily::name).collect::<Vec<_>>();
+        assert_eq!(names, ["One", "Two"]);
+        assert_eq!(registry.families(), families);
+
+        registry.load_into(&mut text).unwrap();
+        assert_eq!(text.loaded, ["One", "Two"]);
+
+        // clones share their fonts, and load them into new factories.
+        let mut other = FakeText::default();
+        assert_eq!(registry.clone().load_into(&mut other).unwrap(), families);
+        assert_eq!(other.loaded, ["One", "Two"]);
+    }
+
+    #[test]
+    fn load_errors_are_returned() {
+        let registry = FontRegistry::new();
+        registry.register(&b"One"[..]);
+        registry.register(&[0xff][..]);
+        registry.register(PathBuf::from("/this/font/does/not/exist.ttf"));
+        let mut text = FakeText::default();
+        assert!(registry.load_into(&mut text).is_err());
+        assert_eq!(text.loaded, ["One"]);
+        assert_eq!(registry.families().len(), 1);
+
+        let registry = FontRegistry::new();
+        registry.register(PathBuf::from("/this/font/does/not/exist.ttf"));
+        assert!(registry.load_into(&mut text).is_err());
+    }
+}
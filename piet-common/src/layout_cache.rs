@@ -0,0 +1,274 @@
+//! Reusing text layouts, instead of building the same ones every frame.
+
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::ops::{Range, RangeBounds};
+use std::sync::Arc;
+
+use piet::{util, Error, Text, TextAlignment, TextAttribute, TextLayoutBuilder};
+
+/// The text, attributes and width of a layout, which identify it in a
+/// [`TextLayoutCache`].
+///
+/// The methods that set them are named after those of
+/// [`TextLayoutBuilder`], which the layout is built with.
+#[derive(Debug, Clone)]
+pub struct TextLayoutKey {
+    text: Arc<str>,
+    max_width: f64,
+    alignment: TextAlignment,
+    default_attributes: Vec<TextAttribute>,
+    range_attributes: Vec<(Range<usize>, TextAttribute)>,
+}
+
+impl TextLayoutKey {
+    /// The key of a layout of `text`, with no attributes, that isn't
+    /// wrapped.
+    pub fn new(text: impl Into<Arc<str>>) -> TextLayoutKey {
+        TextLayoutKey {
+            text: text.into(),
+            max_width: f64::INFINITY,
+            alignment: TextAlignment::default(),
+            default_attributes: Vec::new(),
+            range_attributes: Vec::new(),
+        }
+    }
+
+    /// Builder-style method to set the width that the text is wrapped at.
+    pub fn max_width(mut self, width: f64) -> TextLayoutKey {
+        self.max_width = width;
+        self
+    }
+
+    /// Builder-style method to set the alignment of the text.
+    pub fn alignment(mut self, alignment: TextAlignment) -> TextLayoutKey {
+        self.alignment = alignment;
+        self
+    }
+
+    /// Builder-style method to add an attribute for all of the text.
+    pub fn default_attribute(mut self, attribute: impl Into<TextAttribute>) -> TextLayoutKey {
+        self.default_attributes.push(attribute.into());
+        self
+    }
+
+    /// Builder-style method to add an attribute for a range of the text.
+    pub fn range_attribute(
+        mut self,
+        range: impl RangeBounds<usize>,
+        attribute: impl Into<TextAttribute>,
+    ) -> TextLayoutKey {
+        let range = util::resolve_range(range, self.text.len());
+        self.range_attributes.push((range, attribute.into()));
+        self
+    }
+
+    /// The text of the layout.
+    pub fn text(&self) -> &Arc<str> {
+        &self.text
+    }
+
+    fn build<T: Text>(&self, factory: &mut T) -> Result<T::TextLayout, Error> {
+        let mut builder = factory
+            .new_text_layout(self.text.clone())
+            .max_width(self.max_width)
+            .alignment(self.alignment);
+        for attribute in &self.default_attributes {
+            builder = builder.default_attribute(attribute.clone());
+        }
+        for (range, attribute) in &self.range_attributes {
+            builder = builder.range_attribute(range.clone(), attribute.clone());
+        }
+        builder.build()
+    }
+}
+
+impl PartialEq for TextLayoutKey {
+    fn eq(&self, other: &TextLayoutKey) -> bool {
+        self.text == other.text
+            && self.max_width.to_bits() == other.max_width.to_bits()
+            && self.alignment == other.alignment
+            && self.default_attributes == other.default_attributes
+            && self.range_attributes == other.range_attributes
+    }
+}
+
+impl Eq for TextLayoutKey {}
+
+impl Hash for TextLayoutKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.text.hash(state);
+        self.max_width.to_bits().hash(state);
+        mem::discriminant(&self.alignment).hash(state);
+        // attributes hold floats, so only their kinds are hashed; keys that
+        // differ only in their values are told apart by comparing them.
+        for attribute in &self.default_attributes {
+            mem::discriminant(attribute).hash(state);
+        }
+        for (range, attribute) in &self.range_attributes {
+            range.hash(state);
+            mem::discriminant(attribute).hash(state);
+        }
+    }
+}
+
+/// How often the layouts of a [`TextLayoutCache`] were found, for choosing
+/// its capacity.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TextLayoutCacheStats {
+    /// The number of layouts that were in the cache.
+    pub hits: u64,
+    /// The number of layouts that were built, because they weren't in the
+    /// cache.
+    pub misses: u64,
+    /// The number of layouts that were dropped to make room for others.
+    pub evictions: u64,
+}
+
+impl TextLayoutCacheStats {
+    /// The fraction of the layouts that were in the cache, or `0` if none
+    /// were asked for.
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
+/// A cache of text layouts, which are reused instead of being built again.
+///
+/// Immediate mode interfaces ask for the layouts of the same labels on every
+/// frame. The cache keeps up to a number of layouts of any backend, by their
+/// [`TextLayoutKey`], and when it is full, the layout that was used longest
+/// ago is dropped to make room for a new one.
+///
+/// ```
+/// # use piet_common::*;
+/// # let device = Device::new().unwrap();
+/// # let mut target = device.bitmap_target(100, 100, 1.0).unwrap();
+/// # let mut rc = target.render_context();
+/// let mut cache = TextLayoutCache::new(256);
+/// let key = TextLayoutKey::new("Save").default_attribute(TextAttribute::FontSize(14.0));
+/// let layout = cache.layout(rc.text(), &key).unwrap();
+/// rc.draw_text(&layout, (10.0, 10.0));
+/// # rc.finish().unwrap();
+/// ```
+///
+/// The stats of the cache, from [`stats`], show how often layouts are found,
+/// so that the capacity can be tuned.
+///
+/// [`stats`]: TextLayoutCache::stats
+pub struct TextLayoutCache<L> {
+    entries: HashMap<TextLayoutKey, Entry<L>>,
+    capacity: usize,
+    /// Counts the lookups, so that entries can be ordered by when they were
+    /// last used.
+    clock: u64,
+    stats: TextLayoutCacheStats,
+}
+
+struct Entry<L> {
+    layout: L,
+    last_used: u64,
+}
+
+impl<L: piet::TextLayout> TextLayoutCache<L> {
+    /// Create a new cache, which keeps up to `capacity` layouts.
+    pub fn new(capacity: usize) -> TextLayoutCache<L> {
+        TextLayoutCache {
+            entries: HashMap::new(),
+            capacity,
+            clock: 0,
+            stats: TextLayoutCacheStats::default(),
+        }
+    }
+
+    /// The layout for `key`, which is built with `factory` if it isn't in
+    /// the cache.
+    ///
+    /// Layouts that fail to build aren't cached.
+    pub fn layout<T: Text<TextLayout = L>>(
+        &mut self,
+        factory: &mut T,
+        key: &TextLayoutKey,
+    ) -> Result<L, Error> {
+        self.clock += 1;
+        if let Some(entry) = self.entries.get_mut(key) {
+            entry.last_used = self.clock;
+            self.stats.hits += 1;
+            return Ok(entry.layout.clone());
+        }
+        self.stats.misses += 1;
+        let layout = key.build(factory)?;
+        if self.capacity == 0 {
+            return Ok(layout);
+        }
+        if self.entries.len() >= self.capacity {
+            self.evict();
+        }
+        let entry = Entry {
+            layout: layout.clone(),
+            last_used: self.clock,
+        };
+        self.entries.insert(key.clone(), entry);
+        Ok(layout)
+    }
+
+    /// Drop the layout that was used longest ago.
+    fn evict(&mut self) {
+        let oldest = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(key, _)| key.clone());
+        if let Some(key) = oldest {
+            self.entries.remove(&key);
+            self.stats.evictions += 1;
+        }
+    }
+
+    /// How often layouts were found since the cache was made, or since the
+    /// stats were last reset.
+    pub fn stats(&self) -> TextLayoutCacheStats {
+        self.stats
+    }
+
+    /// Reset the stats to zero.
+    pub fn reset_stats(&mut self) {
+        self.stats = TextLayoutCacheStats::default();
+    }
+
+    /// The number of layouts that the cache keeps.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Change the number of layouts that the cache keeps, dropping those
+    /// that were used longest ago if there are more than that.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.entries.len() > capacity {
+            self.evict();
+        }
+    }
+
+    /// The number of layouts in the cache.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if there are no layouts in the cache.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Drop all of the layouts, such as after fonts are loaded, which can
+    /// change how text is laid out.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
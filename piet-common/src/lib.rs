@@ -20,6 +20,20 @@
 //! backend. The types documented below can be used portable across all
 //! backends.
 //!
+//! The `tiny-skia` feature selects a pure-Rust software renderer instead of
+//! the platform's backend. Together with `default-features = false`, this
+//! builds without any system libraries, which is useful for headless rendering
+//! and testing; note that this backend doesn't draw text yet.
+//!
+//! In browsers, the `webgl` feature selects a WebGL2 backend instead of the
+//! 2D canvas, which is faster for scenes with many shapes. Text is laid out
+//! the same way with both.
+//!
+//! Which backend was built in can be checked at runtime with
+//! [`Device::with_backend`], and what it can draw with
+//! [`RenderContext::capabilities`], so that apps can choose features for each
+//! platform without matching on the features and target themselves.
+//!
 //! [piet]: https://crates.io/crates/piet
 //! [kurbo]: https://crates.io/crates/kurbo
 //! [piet-cairo]: https://crates.io/crates/piet-cairo
@@ -32,16 +46,22 @@ pub use piet::*;
 pub use piet::kurbo;
 
 cfg_if::cfg_if! {
-     if #[cfg(any(feature = "web", target_arch = "wasm32"))] {
+     if #[cfg(feature = "webgl")] {
+        #[path = "webgl_back.rs"]
+        mod backend;
+    } else if #[cfg(any(feature = "web", target_arch = "wasm32"))] {
         #[path = "web_back.rs"]
         mod backend;
-    } else if #[cfg(any(target_os = "linux", target_os = "openbsd", target_os = "freebsd", target_os = "netbsd"))] {
+    } else if #[cfg(feature = "tiny-skia")] {
+        #[path = "tiny_skia_back.rs"]
+        mod backend;
+    } else if #[cfg(all(feature = "native", any(target_os = "linux", target_os = "openbsd", target_os = "freebsd", target_os = "netbsd")))] {
         #[path = "cairo_back.rs"]
         mod backend;
-    } else if #[cfg(any(target_os = "macos", target_os = "ios"))] {
+    } else if #[cfg(all(feature = "native", any(target_os = "macos", target_os = "ios")))] {
         #[path = "cg_back.rs"]
         mod backend;
-    } else if #[cfg(target_os = "windows")] {
+    } else if #[cfg(all(feature = "native", target_os = "windows"))] {
         #[path = "direct2d_back.rs"]
         mod backend;
     } else {
@@ -51,6 +71,21 @@ cfg_if::cfg_if! {
 
 pub use backend::*;
 
+mod backend_hint;
+mod document;
+mod encode;
+mod font_registry;
+mod layout_cache;
+mod pool;
+mod readback;
+
+pub use backend_hint::{Backend, BackendHint};
+pub use document::DocumentFormat;
+pub use encode::{FileFormat, PngCompression};
+pub use font_registry::{FontRegistry, FontSource};
+pub use layout_cache::{TextLayoutCache, TextLayoutCacheStats, TextLayoutKey};
+pub use pool::TargetPool;
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -70,4 +105,8 @@ mod test {
 
     sa::assert_impl_all!(Device: Send);
     sa::assert_not_impl_any!(Device: Sync);
+    sa::assert_impl_all!(FontRegistry: Send, Sync);
+    sa::assert_impl_all!(ImageBuf: Send, Sync);
+    sa::assert_impl_all!(SharedImage: Send, Sync);
+    sa::assert_impl_all!(FixedGradient: Send, Sync);
 }
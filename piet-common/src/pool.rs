@@ -0,0 +1,91 @@
+//! Reusing bitmap targets, instead of allocating new ones.
+
+use crate::{BitmapTarget, Device};
+
+/// A pool of bitmap targets, which are reused instead of allocating a new
+/// surface for each one.
+///
+/// This is useful when many short-lived targets are drawn, such as the tiles
+/// of a tiled renderer. Targets are taken from the pool with [`get`], and
+/// given back with [`put`] when they have been read. A target given back is
+/// reused for the next one of the same size, or resized if there are none.
+///
+/// [`get`]: TargetPool::get
+/// [`put`]: TargetPool::put
+pub struct TargetPool<'a> {
+    device: &'a Device,
+    /// The targets that aren't in use, oldest first.
+    free: Vec<BitmapTarget<'a>>,
+    capacity: usize,
+}
+
+impl<'a> TargetPool<'a> {
+    /// Create a new pool of targets made by `device`, which keeps up to
+    /// `capacity` targets that aren't in use.
+    pub fn new(device: &'a Device, capacity: usize) -> TargetPool<'a> {
+        TargetPool {
+            device,
+            free: Vec::new(),
+            capacity,
+        }
+    }
+
+    /// Get a target of `width` by `height` pixels, cleared to transparent.
+    ///
+    /// This is the same as [`Device::bitmap_target`], except that a target
+    /// that was given back to the pool is reused if there is one.
+    pub fn get(
+        &mut self,
+        width: usize,
+        height: usize,
+        pix_scale: f64,
+    ) -> Result<BitmapTarget<'a>, piet::Error> {
+        let index = self
+            .free
+            .iter()
+            .rposition(|target| target.pixel_size() == (width, height));
+        // if there are none of the same size, resizing the oldest one is
+        // still cheaper than making a new one on some backends.
+        let target = match index {
+            Some(index) => Some(self.free.remove(index)),
+            None if !self.free.is_empty() => Some(self.free.remove(0)),
+            None => None,
+        };
+        match target {
+            Some(mut target) => {
+                target.resize(width, height, pix_scale)?;
+                Ok(target)
+            }
+            None => self.device.bitmap_target(width, height, pix_scale),
+        }
+    }
+
+    /// Give a target back to the pool, so that it can be reused.
+    ///
+    /// If the pool is full, the target that was given back longest ago is
+    /// dropped.
+    pub fn put(&mut self, target: BitmapTarget<'a>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.free.len() == self.capacity {
+            self.free.remove(0);
+        }
+        self.free.push(target);
+    }
+
+    /// The number of targets in the pool that aren't in use.
+    pub fn len(&self) -> usize {
+        self.free.len()
+    }
+
+    /// Returns `true` if there are no targets in the pool that aren't in use.
+    pub fn is_empty(&self) -> bool {
+        self.free.is_empty()
+    }
+
+    /// Drop all of the targets that aren't in use.
+    pub fn clear(&mut self) {
+        self.free.clear();
+    }
+}
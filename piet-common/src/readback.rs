@@ -0,0 +1,166 @@
+//! Copying areas of a bitmap into buffers laid out by the caller.
+
+use piet::kurbo::Rect;
+use piet::{util, Error, ImageFormat};
+
+/// An area of a bitmap, in whole pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Region {
+    pub(crate) x: usize,
+    pub(crate) y: usize,
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+}
+
+impl Region {
+    /// The pixels covered by `rect`, which must be inside a bitmap of
+    /// `width` by `height` pixels.
+    pub(crate) fn new(rect: Rect, width: usize, height: usize) -> Result<Region, Error> {
+        let rect = rect.abs().expand();
+        if rect.x0 < 0.0 || rect.y0 < 0.0 || rect.x1 > width as f64 || rect.y1 > height as f64 {
+            return Err(Error::InvalidInput);
+        }
+        Ok(Region {
+            x: rect.x0 as usize,
+            y: rect.y0 as usize,
+            width: rect.width() as usize,
+            height: rect.height() as usize,
+        })
+    }
+
+    /// The offset of the region's first pixel in a buffer of 4-byte pixels
+    /// with rows `stride` bytes apart.
+    ///
+    /// The web backends only read the region itself, so they don't need it.
+    #[cfg(any(
+        test,
+        not(any(feature = "webgl", feature = "web", target_arch = "wasm32"))
+    ))]
+    pub(crate) fn offset(&self, stride: usize) -> usize {
+        self.y * stride + self.x * 4
+    }
+}
+
+/// Copy `region` from `src`, which holds 4-byte pixels in `src_format` with
+/// rows `src_stride` bytes apart, starting at the region's first pixel, to
+/// `dst`, with rows `dst_stride` bytes apart in `dst_format`.
+///
+/// Returns the number of bytes from the start of `dst` to the end of the
+/// last pixel written.
+pub(crate) fn copy_region(
+    src: &[u8],
+    src_stride: usize,
+    src_format: ImageFormat,
+    region: Region,
+    dst: &mut [u8],
+    dst_stride: usize,
+    dst_format: ImageFormat,
+) -> Result<usize, Error> {
+    if !is_four_byte(dst_format) {
+        return Err(Error::NotSupported);
+    }
+    debug_assert!(is_four_byte(src_format));
+    let row_len = region.width * 4;
+    if region.width == 0 || region.height == 0 {
+        return Ok(0);
+    }
+    let len = (region.height - 1) * dst_stride + row_len;
+    if dst_stride < row_len || dst.len() < len {
+        return Err(Error::InvalidInput);
+    }
+    debug_assert!(src.len() >= (region.height - 1) * src_stride + row_len);
+
+    let swap = is_bgra(src_format) != is_bgra(dst_format);
+    let premul = is_premul(src_format);
+    for y in 0..region.height {
+        let row = &mut dst[y * dst_stride..][..row_len];
+        row.copy_from_slice(&src[y * src_stride..][..row_len]);
+        if swap {
+            util::swap_red_blue(row);
+        }
+        match (premul, is_premul(dst_format)) {
+            (true, false) => util::unpremultiply_rgba(row),
+            (false, true) => util::premultiply_rgba(row),
+            _ => (),
+        }
+    }
+    Ok(len)
+}
+
+fn is_four_byte(format: ImageFormat) -> bool {
+    matches!(
+        format,
+        ImageFormat::RgbaPremul
+            | ImageFormat::RgbaSeparate
+            | ImageFormat::BgraPremul
+            | ImageFormat::BgraSeparate
+    )
+}
+
+fn is_bgra(format: ImageFormat) -> bool {
+    matches!(format, ImageFormat::BgraPremul | ImageFormat::BgraSeparate)
+}
+
+fn is_premul(format: ImageFormat) -> bool {
+    matches!(format, ImageFormat::RgbaPremul | ImageFormat::BgraPremul)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regions_are_converted_and_strided() {
+        // a 3 by 2 bitmap of premultiplied RGBA, with a half-transparent
+        // pixel in the second row.
+        #[rustfmt::skip]
+        let src = [
+            1, 2, 3, 255,  4, 5, 6, 255,    7, 8, 9, 255,
+            10, 11, 12, 255,  64, 0, 0, 128,  13, 14, 15, 255,
+        ];
+        let region = Region::new(Rect::new(1.0, 0.5, 3.0, 2.0), 3, 2).unwrap();
+        assert_eq!(
+            region,
+            Region {
+                x: 1,
+                y: 0,
+                width: 2,
+                height: 2
+            }
+        );
+
+        let mut dst = [0; 20];
+        let src = &src[region.offset(12)..];
+        let len = copy_region(
+            src,
+            12,
+            ImageFormat::RgbaPremul,
+            region,
+            &mut dst,
+            12,
+            ImageFormat::BgraSeparate,
+        )
+        .unwrap();
+        assert_eq!(len, 20);
+        #[rustfmt::skip]
+        assert_eq!(
+            dst,
+            [
+                6, 5, 4, 255,  9, 8, 7, 255,  0, 0, 0, 0,
+                0, 0, 128, 128,  15, 14, 13, 255,
+            ]
+        );
+
+        assert!(Region::new(Rect::new(2.0, 0.0, 4.0, 1.0), 3, 2).is_err());
+        assert!(copy_region(
+            src,
+            12,
+            ImageFormat::RgbaPremul,
+            region,
+            &mut dst,
+            4,
+            ImageFormat::RgbaPremul
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,263 @@
+//! Support for piet tiny-skia back-end.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::marker::PhantomData;
+use std::path::Path;
+
+use piet::kurbo::{Rect, Size};
+use piet::{util, ImageBuf, ImageFormat};
+use piet_tiny_skia::tiny_skia::Pixmap;
+#[doc(hidden)]
+pub use piet_tiny_skia::*;
+
+use crate::encode::{self, FileFormat};
+use crate::readback::{self, Region};
+use crate::{Backend, DocumentFormat};
+
+/// The `RenderContext` for the tiny-skia backend, which is selected.
+pub type Piet<'a> = TinySkiaRenderContext<'a>;
+
+/// The associated brush type for this backend.
+///
+/// This type matches `RenderContext::Brush`
+pub type Brush = piet_tiny_skia::Brush;
+
+/// The associated text factory for this backend.
+///
+/// This type matches `RenderContext::Text`
+pub type PietText = piet::NullText;
+
+/// The associated text layout type for this backend.
+///
+/// This type matches `RenderContext::Text::TextLayout`
+pub type PietTextLayout = piet::NullTextLayout;
+
+/// The associated text layout builder for this backend.
+///
+/// This type matches `RenderContext::Text::TextLayoutBuilder`
+pub type PietTextLayoutBuilder = piet::NullTextLayoutBuilder;
+
+/// The associated image type for this backend.
+///
+/// This type matches `RenderContext::Image`
+pub type PietImage = TinySkiaImage;
+
+/// The backend that this module provides.
+pub(crate) const BACKEND: Backend = Backend::TinySkia;
+
+/// A struct that can be used to create bitmap render contexts.
+///
+/// In the case of tiny-skia, being a software renderer, no state is needed.
+pub struct Device {
+    // Since not all backends can support `Device: Sync`, make it non-Sync here to, for fewer
+    // portability surprises.
+    marker: std::marker::PhantomData<*const ()>,
+}
+
+unsafe impl Send for Device {}
+
+/// A struct provides a `RenderContext` and then can have its bitmap extracted.
+pub struct BitmapTarget<'a> {
+    pixmap: Pixmap,
+    pix_scale: f64,
+    phantom: PhantomData<&'a ()>,
+}
+
+/// A multi-page document, which provides a `RenderContext` for each page.
+///
+/// This backend can't produce multi-page documents, so this is never created.
+pub struct DocumentTarget<'a> {
+    never: std::convert::Infallible,
+    phantom: PhantomData<&'a ()>,
+}
+
+impl Device {
+    /// Create a new device.
+    pub fn new() -> Result<Device, piet::Error> {
+        Ok(Device {
+            marker: std::marker::PhantomData,
+        })
+    }
+
+    /// Create a new bitmap target.
+    ///
+    /// Any number of targets can be used at the same time.
+    pub fn bitmap_target(
+        &self,
+        width: usize,
+        height: usize,
+        pix_scale: f64,
+    ) -> Result<BitmapTarget, piet::Error> {
+        let pixmap = Pixmap::new(width as u32, height as u32).ok_or(piet::Error::InvalidInput)?;
+        Ok(BitmapTarget {
+            pixmap,
+            pix_scale,
+            phantom: PhantomData,
+        })
+    }
+
+    /// Create a new document target, which writes a document in `format` to
+    /// `writer`.
+    ///
+    /// This backend can't produce multi-page documents, so this always returns
+    /// [`Error::NotSupported`](piet::Error::NotSupported).
+    pub fn document_target(
+        &mut self,
+        _writer: impl Write + 'static,
+        _format: DocumentFormat,
+    ) -> Result<DocumentTarget, piet::Error> {
+        Err(piet::Error::NotSupported)
+    }
+}
+
+impl<'a> DocumentTarget<'a> {
+    /// Start a new page of the given size, in points, and get a piet
+    /// `RenderContext` for it. This completes the previous page, if any.
+    pub fn new_page(&mut self, _size: Size) -> Result<TinySkiaRenderContext, piet::Error> {
+        match self.never {}
+    }
+
+    /// Complete the last page, and write the rest of the document.
+    pub fn finish(self) -> Result<(), piet::Error> {
+        match self.never {}
+    }
+}
+
+impl<'a> BitmapTarget<'a> {
+    /// Get a piet `RenderContext` for the bitmap.
+    ///
+    /// Note: caller is responsible for calling `finish` on the render
+    /// context at the end of rendering.
+    pub fn render_context(&mut self) -> TinySkiaRenderContext {
+        TinySkiaRenderContext::new(self.pixmap.as_mut(), self.pix_scale)
+    }
+
+    /// The size of the bitmap, in pixels.
+    pub fn pixel_size(&self) -> (usize, usize) {
+        (self.pixmap.width() as usize, self.pixmap.height() as usize)
+    }
+
+    /// Change the size of the bitmap to `width` by `height` pixels, and clear
+    /// it to transparent, so that it can be drawn again.
+    ///
+    /// The pixels are only reallocated if the size changes.
+    pub fn resize(
+        &mut self,
+        width: usize,
+        height: usize,
+        pix_scale: f64,
+    ) -> Result<(), piet::Error> {
+        if self.pixel_size() == (width, height) {
+            self.pixmap.fill(tiny_skia::Color::TRANSPARENT);
+        } else {
+            self.pixmap =
+                Pixmap::new(width as u32, height as u32).ok_or(piet::Error::InvalidInput)?;
+        }
+        self.pix_scale = pix_scale;
+        Ok(())
+    }
+
+    /// Get raw RGBA pixels from the bitmap by copying them into `buf`. If all the pixels were
+    /// copied, returns the number of bytes written. If `buf` wasn't big enough, returns an error
+    /// and doesn't write anything.
+    ///
+    /// Note: caller is responsible for making sure the requested `ImageFormat` is supported.
+    pub fn copy_raw_pixels(
+        &mut self,
+        fmt: ImageFormat,
+        buf: &mut [u8],
+    ) -> Result<usize, piet::Error> {
+        // TODO: convert other formats.
+        if fmt != ImageFormat::RgbaPremul {
+            return Err(piet::Error::NotSupported);
+        }
+        // tiny-skia already stores tightly packed, premultiplied RGBA.
+        let data = self.pixmap.data();
+        let dst = buf.get_mut(..data.len()).ok_or(piet::Error::InvalidInput)?;
+        dst.copy_from_slice(data);
+        Ok(data.len())
+    }
+
+    /// Copy the pixels in `src_rect` into `buf`, in `fmt`, with rows `stride` bytes apart.
+    ///
+    /// `src_rect` is in pixels, and is rounded out to whole pixels; it must be inside the bitmap.
+    /// This is cheaper than copying the whole bitmap and cropping it, and lets the pixels be
+    /// written straight into a buffer that is laid out for something else, such as a
+    /// compositor's texture. Returns the number of bytes from the start of `buf` to the end of
+    /// the last pixel written.
+    ///
+    /// The formats with four bytes per pixel are supported; others return
+    /// [`Error::NotSupported`](piet::Error::NotSupported).
+    pub fn raw_pixels_into(
+        &mut self,
+        src_rect: impl Into<Rect>,
+        fmt: ImageFormat,
+        buf: &mut [u8],
+        stride: usize,
+    ) -> Result<usize, piet::Error> {
+        let (width, height) = self.pixel_size();
+        let region = Region::new(src_rect.into(), width, height)?;
+        readback::copy_region(
+            &self.pixmap.data()[region.offset(width * 4)..],
+            width * 4,
+            ImageFormat::RgbaPremul,
+            region,
+            buf,
+            stride,
+            fmt,
+        )
+    }
+
+    /// Get an in-memory pixel buffer from the bitmap.
+    ///
+    /// Note: caller is responsible for making sure the requested `ImageFormat` is supported.
+    // Clippy complains about a to_xxx method taking &mut self. Semantically speaking, this is not
+    // really a mutation, so we'll keep the name. Consider using interior mutability in the future.
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_image_buf(&mut self, fmt: ImageFormat) -> Result<ImageBuf, piet::Error> {
+        let width = self.pixmap.width() as usize;
+        let height = self.pixmap.height() as usize;
+        let mut buf = vec![0; width * height * 4];
+        self.copy_raw_pixels(fmt, &mut buf)?;
+        Ok(ImageBuf::from_raw(buf, fmt, width, height))
+    }
+
+    /// Save bitmap to RGBA PNG file
+    pub fn save_to_file<P: AsRef<Path>>(mut self, path: P) -> Result<(), piet::Error> {
+        let file = BufWriter::new(File::create(path).map_err(Into::<Box<_>>::into)?);
+        self.save_to_writer(file, FileFormat::default())
+    }
+
+    /// Encode the bitmap as `format`, and write it to `writer`.
+    pub fn save_to_writer(
+        &mut self,
+        writer: impl Write,
+        format: FileFormat,
+    ) -> Result<(), piet::Error> {
+        let width = self.pixmap.width() as usize;
+        let height = self.pixmap.height() as usize;
+        let mut data = self.pixmap.data().to_vec();
+        util::unpremultiply_rgba(&mut data);
+        encode::write_image(writer, &data, width, height, format)
+    }
+
+    /// Encode the bitmap as a JPEG, with a `quality` from 1 to 100.
+    ///
+    /// JPEG has no alpha channel, so the image is composited over white.
+    /// This requires the `jpeg` feature.
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_jpeg(&mut self, quality: u8) -> Result<Vec<u8>, piet::Error> {
+        let mut buf = Vec::new();
+        self.save_to_writer(&mut buf, FileFormat::Jpeg(quality))?;
+        Ok(buf)
+    }
+
+    /// Encode the bitmap as a lossless WebP. This requires the `webp` feature.
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_webp(&mut self) -> Result<Vec<u8>, piet::Error> {
+        let mut buf = Vec::new();
+        self.save_to_writer(&mut buf, FileFormat::WebP)?;
+        Ok(buf)
+    }
+}
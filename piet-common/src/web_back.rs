@@ -1,22 +1,22 @@
 //! Support for piet Web back-end.
 
 use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
 use std::marker::PhantomData;
 use std::path::Path;
 
-#[cfg(feature = "png")]
-use std::fs::File;
-#[cfg(feature = "png")]
-use std::io::BufWriter;
-
-#[cfg(feature = "png")]
-use png::{ColorType, Encoder};
 use wasm_bindgen::JsCast;
 
+use piet::kurbo::{Rect, Size};
 use piet::{ImageBuf, ImageFormat};
 #[doc(hidden)]
 pub use piet_web::*;
 
+use crate::encode::{self, FileFormat};
+use crate::readback::{self, Region};
+use crate::{Backend, DocumentFormat};
+
 pub type Piet<'a> = WebRenderContext<'a>;
 
 /// The associated brush type for this backend.
@@ -44,6 +44,9 @@ pub type PietTextLayoutBuilder = WebTextLayoutBuilder;
 /// This type matches `RenderContext::Image`
 pub type PietImage = WebImage;
 
+/// The backend that this module provides.
+pub(crate) const BACKEND: Backend = Backend::Web;
+
 /// A struct that can be used to create bitmap render contexts.
 pub struct Device {
     // Since not all backends can support `Device: Sync`, make it non-Sync here to, for fewer
@@ -60,6 +63,14 @@ pub struct BitmapTarget<'a> {
     phantom: PhantomData<&'a ()>,
 }
 
+/// A multi-page document, which provides a `RenderContext` for each page.
+///
+/// This backend can't produce multi-page documents, so this is never created.
+pub struct DocumentTarget<'a> {
+    never: std::convert::Infallible,
+    phantom: PhantomData<&'a ()>,
+}
+
 impl Device {
     /// Create a new device.
     pub fn new() -> Result<Device, piet::Error> {
@@ -69,8 +80,10 @@ impl Device {
     }
 
     /// Create a new bitmap target.
+    ///
+    /// Any number of targets can be used at the same time.
     pub fn bitmap_target(
-        &mut self,
+        &self,
         width: usize,
         height: usize,
         pix_scale: f64,
@@ -98,6 +111,32 @@ impl Device {
             phantom: Default::default(),
         })
     }
+
+    /// Create a new document target, which writes a document in `format` to
+    /// `writer`.
+    ///
+    /// This backend can't produce multi-page documents, so this always returns
+    /// [`Error::NotSupported`](piet::Error::NotSupported).
+    pub fn document_target(
+        &mut self,
+        _writer: impl Write + 'static,
+        _format: DocumentFormat,
+    ) -> Result<DocumentTarget, piet::Error> {
+        Err(piet::Error::NotSupported)
+    }
+}
+
+impl<'a> DocumentTarget<'a> {
+    /// Start a new page of the given size, in points, and get a piet
+    /// `RenderContext` for it. This completes the previous page, if any.
+    pub fn new_page(&mut self, _size: Size) -> Result<WebRenderContext, piet::Error> {
+        match self.never {}
+    }
+
+    /// Complete the last page, and write the rest of the document.
+    pub fn finish(self) -> Result<(), piet::Error> {
+        match self.never {}
+    }
 }
 
 impl<'a> BitmapTarget<'a> {
@@ -106,6 +145,29 @@ impl<'a> BitmapTarget<'a> {
         WebRenderContext::new(self.context.clone(), web_sys::window().unwrap())
     }
 
+    /// The size of the bitmap, in pixels.
+    pub fn pixel_size(&self) -> (usize, usize) {
+        (self.canvas.width() as usize, self.canvas.height() as usize)
+    }
+
+    /// Change the size of the bitmap to `width` by `height` pixels, and clear
+    /// it to transparent, so that it can be drawn again.
+    ///
+    /// The canvas is reused, so no new element is made.
+    pub fn resize(
+        &mut self,
+        width: usize,
+        height: usize,
+        pix_scale: f64,
+    ) -> Result<(), piet::Error> {
+        // setting the size of a canvas clears it and resets the state of its
+        // context, even if the size doesn't change.
+        self.canvas.set_width(width as u32);
+        self.canvas.set_height(height as u32);
+        let _ = self.context.scale(pix_scale, pix_scale);
+        Ok(())
+    }
+
     /// Get raw RGBA pixels from the bitmap.
     fn raw_pixels(&mut self, fmt: ImageFormat) -> Result<Vec<u8>, piet::Error> {
         // TODO: This code is just a snippet. A thorough review and testing should be done before
@@ -127,6 +189,49 @@ impl<'a> BitmapTarget<'a> {
         Ok(img_data.data().0)
     }
 
+    /// Copy the pixels in `src_rect` into `buf`, in `fmt`, with rows `stride` bytes apart.
+    ///
+    /// `src_rect` is in pixels, and is rounded out to whole pixels; it must be inside the bitmap.
+    /// This is cheaper than copying the whole bitmap and cropping it, and lets the pixels be
+    /// written straight into a buffer that is laid out for something else, such as a
+    /// compositor's texture. Returns the number of bytes from the start of `buf` to the end of
+    /// the last pixel written.
+    ///
+    /// The formats with four bytes per pixel are supported; others return
+    /// [`Error::NotSupported`](piet::Error::NotSupported).
+    pub fn raw_pixels_into(
+        &mut self,
+        src_rect: impl Into<Rect>,
+        fmt: ImageFormat,
+        buf: &mut [u8],
+        stride: usize,
+    ) -> Result<usize, piet::Error> {
+        let (width, height) = self.pixel_size();
+        let region = Region::new(src_rect.into(), width, height)?;
+        if region.width == 0 || region.height == 0 {
+            return Ok(0);
+        }
+        let img_data = self
+            .context
+            .get_image_data(
+                region.x as f64,
+                region.y as f64,
+                region.width as f64,
+                region.height as f64,
+            )
+            .map_err(|jsv| piet::Error::BackendError(Box::new(JsError::new(jsv))))?;
+        // only the region is read, and the canvas gives separate alpha.
+        readback::copy_region(
+            &img_data.data().0,
+            region.width * 4,
+            ImageFormat::RgbaSeparate,
+            region,
+            buf,
+            stride,
+            fmt,
+        )
+    }
+
     /// Get an in-memory pixel buffer from the bitmap.
     // Clippy complains about a to_xxx method taking &mut self. Semantically speaking, this is not
     // really a mutation, so we'll keep the name. Consider using interior mutability in the future.
@@ -155,26 +260,40 @@ impl<'a> BitmapTarget<'a> {
     }
 
     /// Save bitmap to RGBA PNG file
-    #[cfg(feature = "png")]
     pub fn save_to_file<P: AsRef<Path>>(mut self, path: P) -> Result<(), piet::Error> {
-        let height = self.canvas.height();
-        let width = self.canvas.width();
-        let image = self.raw_pixels(ImageFormat::RgbaPremul)?;
         let file = BufWriter::new(File::create(path).map_err(Into::<Box<_>>::into)?);
-        let mut encoder = Encoder::new(file, width as u32, height as u32);
-        encoder.set_color(ColorType::Rgba);
-        encoder
-            .write_header()
-            .map_err(Into::<Box<_>>::into)?
-            .write_image_data(&image)
-            .map_err(Into::<Box<_>>::into)?;
-        Ok(())
+        self.save_to_writer(file, FileFormat::default())
     }
 
-    /// Stub for feature is missing
-    #[cfg(not(feature = "png"))]
-    pub fn save_to_file<P: AsRef<Path>>(self, _path: P) -> Result<(), piet::Error> {
-        Err(piet::Error::MissingFeature("png"))
+    /// Encode the bitmap as `format`, and write it to `writer`.
+    pub fn save_to_writer(
+        &mut self,
+        writer: impl Write,
+        format: FileFormat,
+    ) -> Result<(), piet::Error> {
+        let height = self.canvas.height() as usize;
+        let width = self.canvas.width() as usize;
+        let data = self.raw_pixels(ImageFormat::RgbaPremul)?;
+        encode::write_image(writer, &data, width, height, format)
+    }
+
+    /// Encode the bitmap as a JPEG, with a `quality` from 1 to 100.
+    ///
+    /// JPEG has no alpha channel, so the image is composited over white.
+    /// This requires the `jpeg` feature.
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_jpeg(&mut self, quality: u8) -> Result<Vec<u8>, piet::Error> {
+        let mut buf = Vec::new();
+        self.save_to_writer(&mut buf, FileFormat::Jpeg(quality))?;
+        Ok(buf)
+    }
+
+    /// Encode the bitmap as a lossless WebP. This requires the `webp` feature.
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_webp(&mut self) -> Result<Vec<u8>, piet::Error> {
+        let mut buf = Vec::new();
+        self.save_to_writer(&mut buf, FileFormat::WebP)?;
+        Ok(buf)
     }
 }
 
@@ -0,0 +1,309 @@
+//! Support for piet WebGL back-end.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::marker::PhantomData;
+use std::path::Path;
+
+use wasm_bindgen::JsCast;
+use web_sys::WebGl2RenderingContext as Gl;
+
+use piet::kurbo::{Rect, Size};
+use piet::{util, ImageBuf, ImageFormat};
+#[doc(hidden)]
+pub use piet_webgl::*;
+
+use crate::encode::{self, FileFormat};
+use crate::readback::{self, Region};
+use crate::{Backend, DocumentFormat};
+
+pub type Piet<'a> = WebGlRenderContext<'a>;
+
+/// The associated brush type for this backend.
+///
+/// This type matches `RenderContext::Brush`
+pub type Brush = piet_webgl::Brush;
+
+/// The associated text factory for this backend.
+///
+/// This type matches `RenderContext::Text`
+pub type PietText = WebText;
+
+/// The associated text layout type for this backend.
+///
+/// This type matches `RenderContext::Text::TextLayout`
+pub type PietTextLayout = WebTextLayout;
+
+/// The associated text layout builder for this backend.
+///
+/// This type matches `RenderContext::Text::TextLayoutBuilder`
+pub type PietTextLayoutBuilder = WebTextLayoutBuilder;
+
+/// The associated image type for this backend.
+///
+/// This type matches `RenderContext::Image`
+pub type PietImage = WebGlImage;
+
+/// The backend that this module provides.
+pub(crate) const BACKEND: Backend = Backend::WebGl;
+
+/// A struct that can be used to create bitmap render contexts.
+pub struct Device {
+    // Since not all backends can support `Device: Sync`, make it non-Sync here to, for fewer
+    // portability surprises.
+    marker: std::marker::PhantomData<*const ()>,
+}
+
+unsafe impl Send for Device {}
+
+/// A struct provides a `RenderContext` and then can have its bitmap extracted.
+pub struct BitmapTarget<'a> {
+    canvas: web_sys::HtmlCanvasElement,
+    renderer: Renderer,
+    pix_scale: f64,
+    phantom: PhantomData<&'a ()>,
+}
+
+/// A multi-page document, which provides a `RenderContext` for each page.
+///
+/// This backend can't produce multi-page documents, so this is never created.
+pub struct DocumentTarget<'a> {
+    never: std::convert::Infallible,
+    phantom: PhantomData<&'a ()>,
+}
+
+impl Device {
+    /// Create a new device.
+    pub fn new() -> Result<Device, piet::Error> {
+        Ok(Device {
+            marker: std::marker::PhantomData,
+        })
+    }
+
+    /// Create a new bitmap target.
+    ///
+    /// Each target has its own canvas and WebGL context. Browsers limit how
+    /// many contexts there can be at once, so reuse targets where possible.
+    pub fn bitmap_target(
+        &self,
+        width: usize,
+        height: usize,
+        pix_scale: f64,
+    ) -> Result<BitmapTarget, piet::Error> {
+        let document = web_sys::window().unwrap().document().unwrap();
+        let canvas = document
+            .create_element("canvas")
+            .unwrap()
+            .dyn_into::<web_sys::HtmlCanvasElement>()
+            .unwrap();
+        canvas.set_width(width as u32);
+        canvas.set_height(height as u32);
+        let renderer = Renderer::new(get_context(&canvas)?)?;
+
+        Ok(BitmapTarget {
+            canvas,
+            renderer,
+            pix_scale,
+            phantom: Default::default(),
+        })
+    }
+
+    /// Create a new document target, which writes a document in `format` to
+    /// `writer`.
+    ///
+    /// This backend can't produce multi-page documents, so this always returns
+    /// [`Error::NotSupported`](piet::Error::NotSupported).
+    pub fn document_target(
+        &mut self,
+        _writer: impl Write + 'static,
+        _format: DocumentFormat,
+    ) -> Result<DocumentTarget, piet::Error> {
+        Err(piet::Error::NotSupported)
+    }
+}
+
+impl<'a> DocumentTarget<'a> {
+    /// Start a new page of the given size, in points, and get a piet
+    /// `RenderContext` for it. This completes the previous page, if any.
+    pub fn new_page(&mut self, _size: Size) -> Result<WebGlRenderContext, piet::Error> {
+        match self.never {}
+    }
+
+    /// Complete the last page, and write the rest of the document.
+    pub fn finish(self) -> Result<(), piet::Error> {
+        match self.never {}
+    }
+}
+
+impl<'a> BitmapTarget<'a> {
+    /// Get a piet `RenderContext` for the bitmap.
+    ///
+    /// Note: caller is responsible for calling `finish` on the render
+    /// context at the end of rendering, and for reading the pixels back
+    /// before returning to the browser's event loop, after which the canvas
+    /// may be cleared.
+    pub fn render_context(&mut self) -> WebGlRenderContext {
+        WebGlRenderContext::new(&mut self.renderer, self.pix_scale)
+    }
+
+    /// The size of the bitmap, in pixels.
+    pub fn pixel_size(&self) -> (usize, usize) {
+        (self.canvas.width() as usize, self.canvas.height() as usize)
+    }
+
+    /// Change the size of the bitmap to `width` by `height` pixels, and clear
+    /// it to transparent, so that it can be drawn again.
+    ///
+    /// The canvas and its context are reused.
+    pub fn resize(
+        &mut self,
+        width: usize,
+        height: usize,
+        pix_scale: f64,
+    ) -> Result<(), piet::Error> {
+        // setting the size of a canvas clears it, even if the size doesn't
+        // change.
+        self.canvas.set_width(width as u32);
+        self.canvas.set_height(height as u32);
+        self.pix_scale = pix_scale;
+        Ok(())
+    }
+
+    /// Read the premultiplied pixels of `region`, with rows going down.
+    fn read_region(&mut self, region: Region) -> Result<Vec<u8>, piet::Error> {
+        if region.width == 0 || region.height == 0 {
+            return Ok(Vec::new());
+        }
+        let gl = self.renderer.context();
+        let (width, height) = (region.width as i32, region.height as i32);
+        let mut data = vec![0u8; region.width * region.height * 4];
+        // GL's rows go up from the bottom.
+        let y = self.canvas.height() as i32 - region.y as i32 - height;
+        gl.read_pixels_with_opt_u8_array(
+            region.x as i32,
+            y,
+            width,
+            height,
+            Gl::RGBA,
+            Gl::UNSIGNED_BYTE,
+            Some(&mut data),
+        )
+        .map_err(|_| piet::Error::BackendLost)?;
+        let row_len = region.width * 4;
+        Ok(data
+            .chunks_exact(row_len)
+            .rev()
+            .flatten()
+            .copied()
+            .collect())
+    }
+
+    /// Get raw RGBA pixels from the bitmap.
+    fn raw_pixels(&mut self, fmt: ImageFormat) -> Result<Vec<u8>, piet::Error> {
+        if fmt != ImageFormat::RgbaPremul {
+            return Err(piet::Error::NotSupported);
+        }
+        let (width, height) = self.pixel_size();
+        self.read_region(Region::new(
+            Rect::new(0.0, 0.0, width as f64, height as f64),
+            width,
+            height,
+        )?)
+    }
+
+    /// Copy the pixels in `src_rect` into `buf`, in `fmt`, with rows `stride` bytes apart.
+    ///
+    /// `src_rect` is in pixels, and is rounded out to whole pixels; it must be inside the bitmap.
+    /// This is cheaper than copying the whole bitmap and cropping it, and lets the pixels be
+    /// written straight into a buffer that is laid out for something else, such as a
+    /// compositor's texture. Returns the number of bytes from the start of `buf` to the end of
+    /// the last pixel written.
+    ///
+    /// The formats with four bytes per pixel are supported; others return
+    /// [`Error::NotSupported`](piet::Error::NotSupported).
+    pub fn raw_pixels_into(
+        &mut self,
+        src_rect: impl Into<Rect>,
+        fmt: ImageFormat,
+        buf: &mut [u8],
+        stride: usize,
+    ) -> Result<usize, piet::Error> {
+        let (width, height) = self.pixel_size();
+        let region = Region::new(src_rect.into(), width, height)?;
+        if region.width == 0 || region.height == 0 {
+            return Ok(0);
+        }
+        let data = self.read_region(region)?;
+        // only the region is read.
+        readback::copy_region(
+            &data,
+            region.width * 4,
+            ImageFormat::RgbaPremul,
+            region,
+            buf,
+            stride,
+            fmt,
+        )
+    }
+
+    /// Get an in-memory pixel buffer from the bitmap.
+    // Clippy complains about a to_xxx method taking &mut self. Semantically speaking, this is not
+    // really a mutation, so we'll keep the name. Consider using interior mutability in the future.
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_image_buf(&mut self, fmt: ImageFormat) -> Result<ImageBuf, piet::Error> {
+        let data = self.raw_pixels(fmt)?;
+        let (width, height) = self.pixel_size();
+        Ok(ImageBuf::from_raw(data, fmt, width, height))
+    }
+
+    /// Get raw RGBA pixels from the bitmap by copying them into `buf`. If all the pixels were
+    /// copied, returns the number of bytes written. If `buf` wasn't big enough, returns an error
+    /// and doesn't write anything.
+    pub fn copy_raw_pixels(
+        &mut self,
+        fmt: ImageFormat,
+        buf: &mut [u8],
+    ) -> Result<usize, piet::Error> {
+        let data = self.raw_pixels(fmt)?;
+        let dst = buf.get_mut(..data.len()).ok_or(piet::Error::InvalidInput)?;
+        dst.copy_from_slice(&data);
+        Ok(data.len())
+    }
+
+    /// Save bitmap to RGBA PNG file
+    pub fn save_to_file<P: AsRef<Path>>(mut self, path: P) -> Result<(), piet::Error> {
+        let file = BufWriter::new(File::create(path).map_err(Into::<Box<_>>::into)?);
+        self.save_to_writer(file, FileFormat::default())
+    }
+
+    /// Encode the bitmap as `format`, and write it to `writer`.
+    pub fn save_to_writer(
+        &mut self,
+        writer: impl Write,
+        format: FileFormat,
+    ) -> Result<(), piet::Error> {
+        let (width, height) = self.pixel_size();
+        let mut data = self.raw_pixels(ImageFormat::RgbaPremul)?;
+        util::unpremultiply_rgba(&mut data);
+        encode::write_image(writer, &data, width, height, format)
+    }
+
+    /// Encode the bitmap as a JPEG, with a `quality` from 1 to 100.
+    ///
+    /// JPEG has no alpha channel, so the image is composited over white.
+    /// This requires the `jpeg` feature.
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_jpeg(&mut self, quality: u8) -> Result<Vec<u8>, piet::Error> {
+        let mut buf = Vec::new();
+        self.save_to_writer(&mut buf, FileFormat::Jpeg(quality))?;
+        Ok(buf)
+    }
+
+    /// Encode the bitmap as a lossless WebP. This requires the `webp` feature.
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_webp(&mut self) -> Result<Vec<u8>, piet::Error> {
+        let mut buf = Vec::new();
+        self.save_to_writer(&mut buf, FileFormat::WebP)?;
+        Ok(buf)
+    }
+}
@@ -0,0 +1,35 @@
+use piet_common::*;
+
+#[test]
+fn with_backend_only_gives_matching_backends() {
+    let backend = Device::new().unwrap().backend();
+    assert!(Device::with_backend(BackendHint::Default).is_ok());
+    for hint in [
+        BackendHint::Platform,
+        BackendHint::Software,
+        BackendHint::Gpu,
+    ] {
+        match Device::with_backend(hint) {
+            Ok(device) => assert!(device.backend().matches(hint)),
+            Err(Error::NotSupported) => assert!(!backend.matches(hint)),
+            Err(e) => panic!("{}", e),
+        }
+    }
+}
+
+#[test]
+fn capabilities_are_reported() {
+    let device = Device::new().unwrap();
+    let mut target = device.bitmap_target(10, 10, 1.0).unwrap();
+    let mut ctx = target.render_context();
+    let capabilities = ctx.capabilities();
+    assert!(capabilities.max_image_size >= 10);
+    assert!(capabilities.supports_global_alpha);
+    // a bigger image than the backend allows can't be made.
+    if let Some(size) = capabilities.max_image_size.checked_add(1) {
+        assert!(ctx
+            .make_image(size, 1, &[], ImageFormat::Grayscale)
+            .is_err());
+    }
+    ctx.finish().unwrap();
+}
@@ -2,7 +2,7 @@ use kurbo::{Rect, Size};
 use piet_common::*;
 
 fn with_context(cb: impl FnOnce(&mut Piet) -> Result<(), String>) {
-    let mut device = Device::new().unwrap();
+    let device = Device::new().unwrap();
     let mut target = device.bitmap_target(400, 400, 2.0).unwrap();
     let mut ctx = target.render_context();
     // We don't unwrap here because at least on Windows, dropping the context before calling
@@ -0,0 +1,64 @@
+//! Tests for reusing text layouts.
+
+// The tiny-skia backend doesn't do text layout yet.
+#![cfg(not(feature = "tiny-skia"))]
+
+use piet_common::*;
+
+fn make_factory() -> PietText {
+    let device = Device::new().unwrap();
+    let mut target = device.bitmap_target(100, 100, 1.0).unwrap();
+    let mut ctx = target.render_context();
+    let text = ctx.text().to_owned();
+    let _ = ctx.finish();
+    text
+}
+
+#[test]
+fn layouts_are_reused() {
+    let mut factory = make_factory();
+    let mut cache = TextLayoutCache::new(8);
+    let key = TextLayoutKey::new("hello").default_attribute(TextAttribute::FontSize(14.0));
+    let first = cache.layout(&mut factory, &key).unwrap();
+    let second = cache.layout(&mut factory, &key.clone()).unwrap();
+    assert_eq!(first.size(), second.size());
+    assert_eq!(first.text(), "hello");
+
+    // a different width, or a different value of an attribute, is a
+    // different layout.
+    cache
+        .layout(&mut factory, &key.clone().max_width(20.0))
+        .unwrap();
+    let larger = TextLayoutKey::new("hello").default_attribute(TextAttribute::FontSize(28.0));
+    cache.layout(&mut factory, &larger).unwrap();
+    assert_eq!(cache.len(), 3);
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses, stats.evictions), (1, 3, 0));
+    assert_eq!(stats.hit_rate(), 0.25);
+    cache.reset_stats();
+    assert_eq!(cache.stats(), TextLayoutCacheStats::default());
+}
+
+#[test]
+fn least_recently_used_layouts_are_evicted() {
+    let mut factory = make_factory();
+    let mut cache = TextLayoutCache::new(2);
+    let keys = ["a", "b", "c"].map(TextLayoutKey::new);
+    cache.layout(&mut factory, &keys[0]).unwrap();
+    cache.layout(&mut factory, &keys[1]).unwrap();
+    // using the first layout again makes the second the oldest.
+    cache.layout(&mut factory, &keys[0]).unwrap();
+    cache.layout(&mut factory, &keys[2]).unwrap();
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.stats().evictions, 1);
+
+    cache.layout(&mut factory, &keys[0]).unwrap();
+    assert_eq!(cache.stats().hits, 2);
+    cache.layout(&mut factory, &keys[1]).unwrap();
+    assert_eq!(cache.stats().misses, 4);
+
+    cache.set_capacity(1);
+    assert_eq!(cache.len(), 1);
+    cache.clear();
+    assert!(cache.is_empty());
+}
@@ -0,0 +1,73 @@
+use kurbo::Rect;
+use piet_common::*;
+
+/// The premultiplied RGBA pixels of a target.
+fn pixels(target: &mut BitmapTarget) -> Vec<u8> {
+    let (width, height) = target.pixel_size();
+    let mut buf = vec![0; width * height * 4];
+    target
+        .copy_raw_pixels(ImageFormat::RgbaPremul, &mut buf)
+        .unwrap();
+    buf
+}
+
+fn fill(target: &mut BitmapTarget) {
+    let mut ctx = target.render_context();
+    ctx.fill(Rect::new(0.0, 0.0, 4.0, 4.0), &Color::WHITE);
+    ctx.finish().unwrap();
+}
+
+#[test]
+fn targets_can_be_used_at_the_same_time() {
+    let device = Device::new().unwrap();
+    let mut first = device.bitmap_target(4, 4, 1.0).unwrap();
+    let mut second = device.bitmap_target(8, 8, 1.0).unwrap();
+    fill(&mut first);
+    fill(&mut second);
+    assert!(pixels(&mut first).iter().all(|&b| b == 255));
+    // only the top left quarter of the second target was filled.
+    assert_eq!(pixels(&mut second)[..4], [255; 4]);
+    assert_eq!(pixels(&mut second)[8 * 4 * 8 - 4..], [0; 4]);
+}
+
+#[test]
+fn resized_targets_are_cleared() {
+    let device = Device::new().unwrap();
+    let mut target = device.bitmap_target(4, 4, 1.0).unwrap();
+    fill(&mut target);
+    target.resize(4, 4, 2.0).unwrap();
+    assert!(pixels(&mut target).iter().all(|&b| b == 0));
+
+    // the new scale is used, so the fill covers the whole target.
+    target.resize(8, 8, 2.0).unwrap();
+    assert_eq!(target.pixel_size(), (8, 8));
+    fill(&mut target);
+    assert!(pixels(&mut target).iter().all(|&b| b == 255));
+}
+
+#[test]
+fn pooled_targets_are_reused() {
+    let device = Device::new().unwrap();
+    let mut pool = TargetPool::new(&device, 2);
+    let mut target = pool.get(4, 4, 1.0).unwrap();
+    fill(&mut target);
+    pool.put(target);
+    assert_eq!(pool.len(), 1);
+
+    let mut target = pool.get(4, 4, 1.0).unwrap();
+    assert!(pool.is_empty());
+    assert!(pixels(&mut target).iter().all(|&b| b == 0));
+    pool.put(target);
+
+    // the oldest target is dropped when the pool is full.
+    for size in [2, 3, 5] {
+        let target = device.bitmap_target(size, size, 1.0).unwrap();
+        pool.put(target);
+    }
+    assert_eq!(pool.len(), 2);
+    let target = pool.get(3, 3, 1.0).unwrap();
+    assert_eq!(target.pixel_size(), (3, 3));
+    let target = pool.get(4, 4, 1.0).unwrap();
+    assert_eq!(target.pixel_size(), (4, 4));
+    assert!(pool.is_empty());
+}
@@ -0,0 +1,42 @@
+use kurbo::Rect;
+use piet_common::*;
+
+#[test]
+fn regions_are_read_into_strided_buffers() {
+    let device = Device::new().unwrap();
+    let mut target = device.bitmap_target(8, 8, 1.0).unwrap();
+    let mut ctx = target.render_context();
+    ctx.fill(Rect::new(4.0, 4.0, 8.0, 8.0), &Color::rgb8(255, 0, 0));
+    ctx.finish().unwrap();
+    std::mem::drop(ctx);
+
+    // a 2 by 2 area across the corner of the red square, into rows of 3
+    // pixels with a sentinel between them.
+    let mut buf = [7; 20];
+    let len = target
+        .raw_pixels_into(
+            Rect::new(3.0, 3.0, 5.0, 5.0),
+            ImageFormat::BgraPremul,
+            &mut buf,
+            12,
+        )
+        .unwrap();
+    assert_eq!(len, 20);
+    #[rustfmt::skip]
+    assert_eq!(
+        buf,
+        [
+            0, 0, 0, 0,  0, 0, 0, 0,  7, 7, 7, 7,
+            0, 0, 0, 0,  0, 0, 255, 255,
+        ]
+    );
+
+    assert!(target
+        .raw_pixels_into(
+            Rect::new(6.0, 6.0, 9.0, 9.0),
+            ImageFormat::RgbaPremul,
+            &mut buf,
+            12
+        )
+        .is_err());
+}
@@ -1,5 +1,8 @@
 //! Basic conformance testing for text.
 
+// The tiny-skia backend doesn't do text layout yet.
+#![cfg(not(feature = "tiny-skia"))]
+
 #[cfg(target_arch = "wasm32")]
 extern crate wasm_bindgen_test;
 #[cfg(target_arch = "wasm32")]
@@ -29,7 +32,7 @@ macro_rules! assert_close {
 }
 
 fn make_factory() -> PietText {
-    let mut device = Device::new().unwrap();
+    let device = Device::new().unwrap();
     let mut target = device.bitmap_target(400, 400, 2.0).unwrap();
     let mut ctx = target.render_context();
     let text = ctx.text().to_owned();
@@ -231,16 +234,22 @@ fn hit_test_point_outside() {
     let pt = layout.hit_test_point(Point::new(-1.0, 5.0));
     assert_eq!(pt.idx, 0);
     assert!(!pt.is_inside);
+    assert_eq!(pt.line, 0);
+    assert!(pt.is_left && !pt.is_right && !pt.is_above && !pt.is_below);
 
     // above the first line
     let pt = layout.hit_test_point(Point::new(1.0, -5.0));
     assert_eq!(pt.idx, 0);
     assert!(!pt.is_inside);
+    assert_eq!(pt.line, 0);
+    assert!(pt.is_above && !pt.is_below && !pt.is_left && !pt.is_right);
 
     // right of first line
     let pt = layout.hit_test_point(Point::new(unit_width * 4., 5.0));
     assert_eq!(pt.idx, 3);
     assert!(!pt.is_inside);
+    assert_eq!(pt.line, 0);
+    assert!(pt.is_right && !pt.is_left && !pt.is_above && !pt.is_below);
 
     // y position in second line
     let y2 = layout.line_metric(1).unwrap().y_offset + 1.0;
@@ -249,16 +258,51 @@ fn hit_test_point_outside() {
     let pt = layout.hit_test_point(Point::new(-1.0, y2));
     assert_eq!(pt.idx, 3);
     assert!(!pt.is_inside);
+    assert_eq!(pt.line, 1);
+    assert!(pt.is_left && !pt.is_right);
 
     // to the right of the second line
     let pt = layout.hit_test_point(Point::new(unit_width * 6.0, y2));
     assert_eq!(pt.idx, 8);
     assert!(!pt.is_inside);
+    assert_eq!(pt.line, 1);
+    assert!(pt.is_right && !pt.is_left && !pt.is_below);
 
     // below the layout, first grapheme
     let pt = layout.hit_test_point(Point::new(1.0, y2 * 3.0));
     assert_eq!(pt.idx, 3);
     assert!(!pt.is_inside);
+    assert_eq!(pt.line, 1);
+    assert!(pt.is_below && !pt.is_above && !pt.is_left && !pt.is_right);
+}
+
+#[test]
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen_test)]
+fn hit_test_point_lines() {
+    let mut factory = make_factory();
+    let unit_width = factory.get_mono_width(12.0);
+    let layout = factory.make_mono_12pt("aaaa\naa\n");
+    assert_eq!(layout.line_count(), 3);
+
+    // inside the first line
+    let pt = layout.hit_test_point(Point::new(unit_width + 1.0, 5.0));
+    assert!(pt.is_inside);
+    assert_eq!(pt.line, 0);
+    assert!(!pt.is_above && !pt.is_below && !pt.is_left && !pt.is_right);
+
+    // past the end of the shorter second line, but inside the layout
+    let y2 = layout.line_metric(1).unwrap().y_offset + 1.0;
+    let pt = layout.hit_test_point(Point::new(unit_width * 3.0, y2));
+    assert_eq!(pt.idx, 7);
+    assert_eq!(pt.line, 1);
+    assert!(pt.is_right && !pt.is_left);
+
+    // on the empty last line
+    let y3 = layout.line_metric(2).unwrap().y_offset + 1.0;
+    let pt = layout.hit_test_point(Point::new(unit_width, y3));
+    assert_eq!(pt.idx, 8);
+    assert_eq!(pt.line, 2);
+    assert!(pt.is_right && !pt.is_below);
 }
 
 #[test]
@@ -417,6 +461,19 @@ fn emergency_break_selections() {
     assert_close!(second_line.max_x(), 6.0 * mono_width, 1.0);
 }
 
+#[test]
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen_test)]
+fn rects_for_part_of_line() {
+    let mut factory = make_factory();
+    let unit_width = factory.get_mono_width(12.0);
+    let layout = factory.make_mono_12pt("aaaa");
+
+    let rects = layout.rects_for_range(1..3);
+    assert_eq!(rects.len(), 1);
+    assert_close!(rects[0].min_x(), unit_width, 1.0);
+    assert_close!(rects[0].max_x(), unit_width * 3.0, 1.0);
+}
+
 #[test]
 fn trailing_whitespace_width() {
     let mut factory = make_factory();
@@ -465,3 +522,193 @@ fn trailing_whitespace_width() {
         "trailing ws width is inclusive of other width"
     );
 }
+
+#[test]
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen_test)]
+fn caret_movement() {
+    let mut factory = make_factory();
+    // 'e' + combining acute accent is a single grapheme cluster
+    let text = "ae\u{301}\r\nb";
+    let layout = factory.make_mono_12pt(text);
+
+    assert_eq!(layout.next_caret(0), 1);
+    assert_eq!(layout.next_caret(1), 4);
+    assert_eq!(layout.next_caret(4), 6);
+    assert_eq!(layout.next_caret(6), 7);
+    assert_eq!(layout.next_caret(7), 7);
+
+    assert_eq!(layout.prev_caret(7), 6);
+    assert_eq!(layout.prev_caret(6), 4);
+    assert_eq!(layout.prev_caret(4), 1);
+    assert_eq!(layout.prev_caret(0), 0);
+
+    let (idx, pos) = layout.caret_down(0, 0.0);
+    assert_eq!(idx, 6);
+    assert_eq!(pos.line, 1);
+    let (idx, pos) = layout.caret_up(idx, 0.0);
+    assert_eq!(idx, 0);
+    assert_eq!(pos.line, 0);
+    assert_eq!(layout.caret_down(6, 0.0).0, text.len());
+}
+
+#[test]
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen_test)]
+fn letter_spacing() {
+    let mut factory = make_factory();
+    let base = factory.make_mono_12pt("aa aa").size();
+    let letters = make_factory()
+        .new_text_layout("aa aa")
+        .font(FontFamily::MONOSPACE, 12.0)
+        .default_attribute(TextAttribute::LetterSpacing(2.0))
+        .build()
+        .unwrap();
+
+    // letter spacing is added after each of the five characters, though
+    // pango leaves it out after the last one.
+    assert_close!(letters.size().width, base.width + 9.0, 1.0);
+    assert_close!(letters.size().height, base.height, 0.1);
+    assert_eq!(letters.line_count(), 1);
+}
+
+#[test]
+//FIXME: coregraphics and direct2d ignore word spacing.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen_test)]
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+fn word_spacing() {
+    let mut factory = make_factory();
+    let base = factory.make_mono_12pt("aa aa").size();
+    let words = make_factory()
+        .new_text_layout("aa aa")
+        .font(FontFamily::MONOSPACE, 12.0)
+        .default_attribute(TextAttribute::WordSpacing(6.0))
+        .build()
+        .unwrap();
+
+    // word spacing is only added to the space.
+    assert_close!(words.size().width, base.width + 6.0, 1.0);
+    let metric = words.line_metric(0).unwrap();
+    assert_eq!(metric.end_offset, 5);
+    assert_close!(metric.height, base.height, 0.1);
+}
+
+#[test]
+//FIXME: coregraphics and direct2d ignore the line height and paragraph spacing.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen_test)]
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+fn line_height_and_paragraph_spacing() {
+    let mut factory = make_factory();
+    let text = "one\ntwo\nthree";
+    let natural = factory.make_mono_12pt(text);
+    let natural_metric = natural.line_metric(0).unwrap();
+    let layout = |line_height, spacing| {
+        make_factory()
+            .new_text_layout(text)
+            .font(FontFamily::MONOSPACE, 12.0)
+            .line_height(line_height)
+            .paragraph_spacing(spacing)
+            .build()
+            .unwrap()
+    };
+
+    let absolute = layout(LineHeight::Absolute(30.0), 0.0);
+    assert_eq!(absolute.line_count(), 3);
+    for line in 0..3 {
+        let metric = absolute.line_metric(line).unwrap();
+        assert_close!(metric.height, 30.0, 0.5);
+        assert_close!(metric.y_offset, 30.0 * line as f64, 0.5);
+    }
+    assert_close!(absolute.size().height, 90.0, 0.5);
+    assert_close!(absolute.size().width, natural.size().width, 0.1);
+    // the extra space is split above and below the text.
+    let baseline = absolute.line_metric(0).unwrap().baseline;
+    let extra = 30.0 - natural_metric.height;
+    assert_close!(baseline, natural_metric.baseline + extra / 2.0, 0.5);
+
+    let relative = layout(LineHeight::Relative(2.0), 0.0);
+    assert_close!(relative.line_metric(1).unwrap().height, 24.0, 0.5);
+
+    // spacing is added after each hard break, but not after the last line.
+    let spaced = layout(LineHeight::Normal, 10.0);
+    let second = spaced.line_metric(1).unwrap();
+    let third = spaced.line_metric(2).unwrap();
+    assert_close!(second.y_offset, natural_metric.height + 10.0, 0.5);
+    assert_close!(third.y_offset, 2.0 * (natural_metric.height + 10.0), 0.5);
+    assert_close!(spaced.size().height, natural.size().height + 20.0, 0.5);
+}
+
+#[test]
+//FIXME: direct2d ignores baseline shifts, and its scripts need fonts with
+//superscript and subscript glyphs.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen_test)]
+#[cfg(not(target_os = "windows"))]
+fn superscripts_and_baseline_shifts() {
+    let text = "x2 y";
+    let layout = |attribute: TextAttribute| {
+        make_factory()
+            .new_text_layout(text)
+            .font(FontFamily::MONOSPACE, 12.0)
+            .range_attribute(1..2, attribute)
+            .build()
+            .unwrap()
+    };
+    let plain = layout(TextAttribute::ScriptPosition(ScriptPosition::Normal));
+    let sup = layout(TextAttribute::ScriptPosition(ScriptPosition::Superscript));
+    let sub = layout(TextAttribute::ScriptPosition(ScriptPosition::Subscript));
+    let shifted = layout(TextAttribute::BaselineShift(4.0));
+    let metric = |layout: &PietTextLayout| layout.line_metric(0).unwrap();
+    let char_width = |layout: &PietTextLayout| layout.rects_for_range(1..2)[0].width();
+
+    // scripts are set smaller, and so are narrower.
+    for script in [&sup, &sub] {
+        let width = char_width(script);
+        assert_close!(width, char_width(&plain) * 0.65, 1.0);
+        assert_close!(
+            script.size().width,
+            plain.size().width - char_width(&plain) + width,
+            0.1
+        );
+    }
+    // superscripts don't go below the baseline, and subscripts do.
+    assert!(metric(&sup).baseline >= metric(&plain).baseline);
+    assert_close!(metric(&sub).baseline, metric(&plain).baseline, 0.1);
+    assert!(metric(&sub).height > metric(&plain).height);
+
+    // a baseline shift moves the text without resizing it, and the line
+    // grows to fit it.
+    assert_close!(shifted.size().width, plain.size().width, 0.1);
+    assert_close!(
+        metric(&shifted).baseline,
+        metric(&plain).baseline + 4.0,
+        0.5
+    );
+    assert_close!(metric(&shifted).height, metric(&plain).height + 4.0, 0.5);
+}
+
+#[test]
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen_test)]
+fn hinting_changes_glyph_positions() {
+    let positions = |hinting| {
+        let mut factory = make_factory();
+        factory.set_hinting(hinting);
+        let layout = factory
+            .new_text_layout("piet hinting test")
+            .font(FontFamily::SANS_SERIF, 12.5)
+            .build()
+            .unwrap();
+        (0..=layout.text().len())
+            .map(|idx| layout.hit_test_text_position(idx).point.x)
+            .collect::<Vec<_>>()
+    };
+    let pixel = positions(Hinting::Pixel);
+    let subpixel = positions(Hinting::Subpixel);
+
+    // backends may ignore the setting, but those that don't snap glyphs to
+    // whole pixels for `Pixel`, and only for `Pixel`.
+    if pixel != subpixel {
+        assert!(pixel.iter().all(|x| x.fract() == 0.0));
+        assert!(subpixel.iter().any(|x| x.fract() != 0.0));
+        for (pixel, subpixel) in pixel.iter().zip(&subpixel) {
+            assert_close!(*pixel, *subpixel, 2.0);
+        }
+    }
+}
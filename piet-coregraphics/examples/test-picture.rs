@@ -19,7 +19,7 @@ fn run_sample(
     let sample = samples::get(number)?;
     let size = sample.size() * scale;
 
-    let mut device = Device::new()?;
+    let device = Device::new()?;
     let mut target = device.bitmap_target(size.width as usize, size.height as usize, scale)?;
     let mut piet_context = target.render_context();
 
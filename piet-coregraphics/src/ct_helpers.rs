@@ -2,6 +2,7 @@
 
 #![allow(clippy::upper_case_acronyms)]
 
+use std::cell::RefCell;
 use std::ffi::c_void;
 use std::rc::Rc;
 
@@ -9,6 +10,7 @@ use core_foundation::{
     array::{CFArray, CFArrayRef, CFIndex},
     attributed_string::CFMutableAttributedString,
     base::{CFTypeID, TCFType},
+    boolean::CFBoolean,
     declare_TCFType,
     dictionary::{CFDictionary, CFDictionaryRef},
     impl_TCFType,
@@ -23,7 +25,7 @@ use core_graphics::{
     data_provider::CGDataProvider,
     font::CGFont,
     geometry::{CGAffineTransform, CGPoint, CGRect, CGSize},
-    path::CGPathRef,
+    path::{CGPath, CGPathElementType, CGPathRef},
 };
 use core_text::{
     font::{
@@ -35,12 +37,13 @@ use core_text::{
     frame::CTFrame,
     framesetter::CTFramesetter,
     line::{CTLine, CTLineRef, TypographicBounds},
+    run::CTRunRef,
     string_attributes,
 };
 use foreign_types::{ForeignType, ForeignTypeRef};
 
-use piet::kurbo::{Affine, Rect};
-use piet::{util, Color, FontFamily, FontFamilyInner, TextAlignment};
+use piet::kurbo::{Affine, BezPath, Point, Rect};
+use piet::{util, Color, FontFamily, FontFamilyInner, FontFeature, TextAlignment};
 
 #[derive(Clone)]
 pub(crate) struct AttributedString {
@@ -59,6 +62,17 @@ pub(crate) struct Frame {
 #[derive(Debug, Clone)]
 pub(crate) struct Line(CTLine);
 
+/// The shaped glyphs of a single run in a [`Line`].
+pub(crate) struct LineRun {
+    pub(crate) font: Option<CTFont>,
+    pub(crate) glyphs: Vec<u16>,
+    /// Glyph origins, relative to the line origin, with y pointing up.
+    pub(crate) positions: Vec<CGPoint>,
+    pub(crate) advances: Vec<CGSize>,
+    /// The utf-16 offset of each glyph in the frame's string.
+    pub(crate) string_indices: Vec<CFIndex>,
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct FontCollection(CTFontCollection);
 
@@ -72,14 +86,20 @@ impl_TCFType!(
     CTParagraphStyleGetTypeID
 );
 
+pub enum __CTTextTab {}
+type CTTextTabRef = *const __CTTextTab;
+
+declare_TCFType!(CTTextTab, CTTextTabRef);
+impl_TCFType!(CTTextTab, CTTextTabRef, CTTextTabGetTypeID);
+
 #[repr(u32)]
 enum CTParagraphStyleSpecifier {
     Alignment = 0,
     //FirstLineHeadIndent = 1,
     //HeadIndent = 2,
     //TailIndent = 3,
-    //TabStops = 4,
-    //TabInterval = 5,
+    TabStops = 4,
+    TabInterval = 5,
     //LineBreakMode = 6,
     // there are many more of these
 }
@@ -134,14 +154,53 @@ impl AttributedString {
         AttributedString { inner, rtl }
     }
 
-    pub(crate) fn set_alignment(&mut self, alignment: TextAlignment) {
-        let alignment = CTParagraphStyleSetting::alignment(alignment, self.rtl);
-        let settings = [alignment];
+    /// Set the paragraph style of the paragraphs in `range`: the alignment,
+    /// and any tab stops.
+    ///
+    /// Past the last of `tab_stops`, tabs advance to the next multiple of `tab_width`.
+    pub(crate) fn set_paragraph_style(
+        &mut self,
+        range: CFRange,
+        alignment: TextAlignment,
+        tab_stops: &[f64],
+        tab_width: Option<f64>,
+    ) {
+        let tabs = tab_stops
+            .iter()
+            .map(|location| unsafe {
+                CTTextTab::wrap_under_create_rule(CTTextTabCreate(
+                    CTTextAlignment::Left,
+                    *location,
+                    std::ptr::null(),
+                ))
+            })
+            .collect::<Vec<_>>();
+        let tabs = CFArray::from_CFTypes(&tabs);
+        let tabs_ref = tabs.as_concrete_TypeRef();
+        let tab_interval: CGFloat = tab_width.unwrap_or(0.0);
+
+        let mut settings = vec![CTParagraphStyleSetting::alignment(alignment, self.rtl)];
+        // if either is set we replace the default stops entirely, so that only
+        // the interval applies when no explicit stops are given.
+        if !tab_stops.is_empty() || tab_width.is_some() {
+            settings.push(CTParagraphStyleSetting {
+                spec: CTParagraphStyleSpecifier::TabStops,
+                value: &tabs_ref as *const CFArrayRef as *const c_void,
+                value_size: std::mem::size_of::<CFArrayRef>(),
+            });
+        }
+        if tab_width.is_some() {
+            settings.push(CTParagraphStyleSetting {
+                spec: CTParagraphStyleSpecifier::TabInterval,
+                value: &tab_interval as *const CGFloat as *const c_void,
+                value_size: std::mem::size_of::<CGFloat>(),
+            });
+        }
         unsafe {
-            let style = CTParagraphStyleCreate(settings.as_ptr(), 1);
+            let style = CTParagraphStyleCreate(settings.as_ptr(), settings.len());
             let style = CTParagraphStyle::wrap_under_create_rule(style);
             self.inner.set_attribute(
-                self.range(),
+                range,
                 string_attributes::kCTParagraphStyleAttributeName,
                 &style,
             );
@@ -174,6 +233,44 @@ impl AttributedString {
         }
     }
 
+    /// Set the additional advance, in points, after each character.
+    pub(crate) fn set_kern(&mut self, range: CFRange, kern: f64) {
+        unsafe {
+            self.inner.set_attribute(
+                range,
+                string_attributes::kCTKernAttributeName,
+                &CFNumber::from(kern).as_CFType(),
+            )
+        }
+    }
+
+    /// Offset the baseline, in points; positive values raise the text.
+    pub(crate) fn set_baseline_offset(&mut self, range: CFRange, offset: f64) {
+        unsafe {
+            self.inner.set_attribute(
+                range,
+                kCTBaselineOffsetAttributeName,
+                &CFNumber::from(offset).as_CFType(),
+            )
+        }
+    }
+
+    /// Use vertical glyph forms, for text that will be drawn vertically.
+    pub(crate) fn set_vertical_forms(&mut self, range: CFRange, vertical: bool) {
+        let value = if vertical {
+            CFBoolean::true_value()
+        } else {
+            CFBoolean::false_value()
+        };
+        unsafe {
+            self.inner.set_attribute(
+                range,
+                string_attributes::kCTVerticalFormsAttributeName,
+                &value.as_CFType(),
+            )
+        }
+    }
+
     pub(crate) fn set_fg_color(&mut self, range: CFRange, color: Color) {
         let (r, g, b, a) = color.as_rgba();
         let color = CGColor::rgb(r, g, b, a);
@@ -276,6 +373,80 @@ impl Line {
     pub(crate) fn get_offset_for_string_index(&self, index: CFIndex) -> CGFloat {
         self.0.get_string_offset_for_string_index(index)
     }
+
+    pub(crate) fn glyph_runs(&self) -> Vec<LineRun> {
+        self.0
+            .glyph_runs()
+            .iter()
+            .map(|run| {
+                let font = run.attributes().and_then(|attrs| {
+                    let key = unsafe {
+                        CFString::wrap_under_get_rule(string_attributes::kCTFontAttributeName)
+                    };
+                    attrs.find(&key).map(|font| unsafe {
+                        CTFont::wrap_under_get_rule(font.as_CFTypeRef() as CTFontRef)
+                    })
+                });
+                let count = run.glyph_count();
+                let mut advances = vec![CGSize::new(0., 0.); count as usize];
+                unsafe {
+                    CTRunGetAdvances(
+                        run.as_concrete_TypeRef(),
+                        CFRange::init(0, 0),
+                        advances.as_mut_ptr(),
+                    )
+                };
+                LineRun {
+                    font,
+                    glyphs: run.glyphs().into_owned(),
+                    positions: run.positions().into_owned(),
+                    advances,
+                    string_indices: run.string_indices().into_owned(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Build the value of `kCTFontFeatureSettingsAttribute` for a set of OpenType features.
+pub(crate) fn opentype_feature_settings(features: &[FontFeature]) -> CFArray<CFDictionary> {
+    let settings = features
+        .iter()
+        .filter_map(|feature| {
+            let tag = CFString::new(feature.tag_str()?);
+            let value = CFNumber::from(feature.value as i32);
+            unsafe {
+                let tag_key = CFString::wrap_under_get_rule(kCTFontOpenTypeFeatureTag);
+                let value_key = CFString::wrap_under_get_rule(kCTFontOpenTypeFeatureValue);
+                Some(CFDictionary::from_CFType_pairs(&[
+                    (tag_key, tag.as_CFType()),
+                    (value_key, value.as_CFType()),
+                ]))
+            }
+        })
+        .map(|dict| dict.to_untyped())
+        .collect::<Vec<_>>();
+    CFArray::from_CFTypes(&settings)
+}
+
+/// Append the elements of a `CGPath` to a `BezPath`.
+pub(crate) fn append_cg_path(path: &mut BezPath, cg_path: &CGPath) {
+    let path = RefCell::new(path);
+    cg_path.apply(&|element| {
+        let mut path = path.borrow_mut();
+        let pts: Vec<Point> = element
+            .points()
+            .iter()
+            .map(|p| Point::new(p.x, p.y))
+            .collect();
+        match element.element_type {
+            CGPathElementType::MoveToPoint => path.move_to(pts[0]),
+            CGPathElementType::AddLineToPoint => path.line_to(pts[0]),
+            CGPathElementType::AddQuadCurveToPoint => path.quad_to(pts[0], pts[1]),
+            CGPathElementType::AddCurveToPoint => path.curve_to(pts[0], pts[1], pts[2]),
+            CGPathElementType::CloseSubpath => path.close_path(),
+        }
+    });
 }
 
 /// The apple system fonts can resolve to different concrete families at
@@ -393,6 +564,9 @@ pub(crate) fn make_font(desc: &CTFontDescriptor, pt_size: f64, affine: Affine) -
 #[link(name = "CoreText", kind = "framework")]
 extern "C" {
     static kCTFontFamilyNameKey: CFStringRef;
+    static kCTFontOpenTypeFeatureTag: CFStringRef;
+    static kCTFontOpenTypeFeatureValue: CFStringRef;
+    static kCTBaselineOffsetAttributeName: CFStringRef;
 
     pub static kCTFontVariationAxisIdentifierKey: CFStringRef;
     //static kCTFontVariationAxisMinimumValueKey: CFStringRef;
@@ -410,9 +584,16 @@ extern "C" {
         settings: *const CTParagraphStyleSetting,
         count: usize,
     ) -> CTParagraphStyleRef;
+    fn CTTextTabGetTypeID() -> CFTypeID;
+    fn CTTextTabCreate(
+        alignment: CTTextAlignment,
+        location: f64,
+        options: CFDictionaryRef,
+    ) -> CTTextTabRef;
     fn CTLineGetImageBounds(line: CTLineRef, ctx: *mut c_void) -> CGRect;
     fn CTLineDraw(line: CTLineRef, ctx: core_graphics::sys::CGContextRef);
     fn CTLineGetTrailingWhitespaceWidth(line: CTLineRef) -> f64;
+    fn CTRunGetAdvances(run: CTRunRef, range: CFRange, buffer: *mut CGSize);
     fn CTFontCollectionCreateMatchingFontDescriptorsForFamily(
         collection: CTFontCollectionRef,
         family: CFStringRef,
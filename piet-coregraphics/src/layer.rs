@@ -0,0 +1,74 @@
+//! Bindings for `CGLayer`, which is not wrapped by the core-graphics crate.
+
+use core_foundation::{
+    base::{CFTypeID, TCFType},
+    declare_TCFType,
+    dictionary::CFDictionaryRef,
+    impl_TCFType,
+};
+use core_graphics::context::CGContextRef;
+use core_graphics::geometry::{CGRect, CGSize};
+use foreign_types::ForeignTypeRef;
+
+use piet::kurbo::Size;
+
+pub enum __CGLayer {}
+type CGLayerRef = *const __CGLayer;
+
+declare_TCFType!(
+    /// A layer, which CoreGraphics keeps in whatever form is quickest to draw
+    /// to the context it was made for.
+    CGLayer,
+    CGLayerRef
+);
+impl_TCFType!(CGLayer, CGLayerRef, CGLayerGetTypeID);
+
+impl CGLayer {
+    /// Create a layer of `size` user space units, for drawing to `ctx`.
+    ///
+    /// Returns `None` if the layer can't be created, such as when it is empty.
+    pub(crate) fn new(ctx: &CGContextRef, size: Size) -> Option<CGLayer> {
+        let size = CGSize::new(size.width, size.height);
+        unsafe {
+            let layer = CGLayerCreateWithContext(ctx.as_ptr(), size, std::ptr::null());
+            if layer.is_null() {
+                None
+            } else {
+                Some(CGLayer::wrap_under_create_rule(layer))
+            }
+        }
+    }
+
+    /// The size of the layer, in the user space units it was created with.
+    pub fn size(&self) -> Size {
+        let size = unsafe { CGLayerGetSize(self.as_concrete_TypeRef()) };
+        Size::new(size.width, size.height)
+    }
+
+    /// The context that draws to the layer.
+    pub(crate) fn context(&mut self) -> &mut CGContextRef {
+        unsafe { CGContextRef::from_ptr_mut(CGLayerGetContext(self.as_concrete_TypeRef())) }
+    }
+
+    /// Draw the layer to `ctx`, scaled to fill `rect`.
+    pub(crate) fn draw_in_rect(&self, ctx: &mut CGContextRef, rect: CGRect) {
+        unsafe { CGContextDrawLayerInRect(ctx.as_ptr(), rect, self.as_concrete_TypeRef()) }
+    }
+}
+
+#[link(name = "CoreGraphics", kind = "framework")]
+extern "C" {
+    fn CGLayerGetTypeID() -> CFTypeID;
+    fn CGLayerCreateWithContext(
+        context: core_graphics::sys::CGContextRef,
+        size: CGSize,
+        auxiliary_info: CFDictionaryRef,
+    ) -> CGLayerRef;
+    fn CGLayerGetSize(layer: CGLayerRef) -> CGSize;
+    fn CGLayerGetContext(layer: CGLayerRef) -> core_graphics::sys::CGContextRef;
+    fn CGContextDrawLayerInRect(
+        context: core_graphics::sys::CGContextRef,
+        rect: CGRect,
+        layer: CGLayerRef,
+    );
+}
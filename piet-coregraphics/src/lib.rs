@@ -4,6 +4,7 @@
 
 mod ct_helpers;
 mod gradient;
+mod layer;
 mod text;
 
 use std::borrow::Cow;
@@ -23,10 +24,12 @@ use foreign_types::ForeignTypeRef;
 use piet::kurbo::{Affine, PathEl, Point, QuadBez, Rect, Shape, Size};
 
 use piet::{
-    Color, Error, FixedGradient, Image, ImageFormat, InterpolationMode, IntoBrush, LineCap,
-    LineJoin, RenderContext, RoundInto, StrokeStyle,
+    Capabilities, Color, Error, FixedGradient, Hinting, HitTester, Image, ImageFormat,
+    InterpolationMode, IntoBrush, LineCap, LineJoin, RenderContext, RoundInto, StrokeStyle,
+    TextLayout, TextShapingQuality,
 };
 
+pub use crate::layer::CGLayer;
 pub use crate::text::{CoreGraphicsText, CoreGraphicsTextLayout, CoreGraphicsTextLayoutBuilder};
 
 use gradient::Gradient;
@@ -48,8 +51,18 @@ pub struct CoreGraphicsContext<'a> {
     // by CTContextGetCTM. Instead we maintain our own stack, which will contain
     // only those transforms applied by us.
     transform_stack: Vec<Affine>,
+    /// The global alpha, which the context also keeps; ours is saved here
+    /// so that it can be returned without querying the context.
+    global_alpha: f64,
+    saved_alphas: Vec<f64>,
+    /// The tolerance that shapes are converted to paths with.
+    tolerance: f64,
+    saved_tolerances: Vec<f64>,
     y_down: bool,
     height: f64,
+    hit_tester: HitTester,
+    /// Whether `finish` flushes the context.
+    flush_on_finish: bool,
 }
 
 impl<'a> CoreGraphicsContext<'a> {
@@ -99,10 +112,111 @@ impl<'a> CoreGraphicsContext<'a> {
             ctx,
             text,
             transform_stack: Vec::new(),
+            global_alpha: 1.0,
+            saved_alphas: Vec::new(),
+            tolerance: piet::DEFAULT_TOLERANCE,
+            saved_tolerances: Vec::new(),
             y_down,
             height: height.unwrap_or_default(),
+            hit_tester: HitTester::new(),
+            flush_on_finish: true,
         }
     }
+
+    /// Set whether [`finish`] flushes the context, which is `true` by default.
+    ///
+    /// Flushing, with `CGContextFlush`, waits for drawing to reach the
+    /// context's destination, such as a window. A context that is given to
+    /// a `CALayer`'s `drawInContext:`, such as when drawing in step with a
+    /// `CADisplayLink`, has its contents committed by Core Animation, and an
+    /// extra flush only stalls the frame; such contexts should set this to
+    /// `false`, and call [`flush`] only when drawing has to be visible at
+    /// once.
+    ///
+    /// [`finish`]: RenderContext::finish
+    /// [`flush`]: CoreGraphicsContext::flush
+    pub fn set_flush_on_finish(&mut self, flush: bool) {
+        self.flush_on_finish = flush;
+    }
+
+    /// Flush the context, sending any drawing that is waiting to its
+    /// destination, such as a window.
+    pub fn flush(&mut self) {
+        self.ctx.flush();
+    }
+}
+
+impl<'a> CoreGraphicsContext<'a> {
+    /// Draw into a new image backed by a `CGLayer`, which covers `area`.
+    ///
+    /// This is like [`capture_image_area`], except that instead of copying
+    /// what has already been drawn, `draw` is called with a context that has
+    /// the same coordinates as this one, and what it draws inside `area` is
+    /// kept in the layer. CoreGraphics keeps layers in whatever form is
+    /// quickest to draw to this context, at the resolution of its device, such
+    /// as the backing scale of a window, so a layer is much quicker to stamp
+    /// many times than an image, especially for content made of many paths.
+    ///
+    /// The layer is drawn with [`draw_image`] like any other image, and should
+    /// only be drawn to this context, or to contexts like it. The current
+    /// transform is not applied to the layer's contents.
+    ///
+    /// [`capture_image_area`]: RenderContext::capture_image_area
+    /// [`draw_image`]: RenderContext::draw_image
+    pub fn capture_layer(
+        &mut self,
+        area: impl Into<Rect>,
+        draw: impl FnOnce(&mut CoreGraphicsContext) -> Result<(), Error>,
+    ) -> Result<CoreGraphicsImage, Error> {
+        let area = area.into();
+        if area.width() < 1.0 || area.height() < 1.0 {
+            return Err(Error::InvalidInput);
+        }
+        let mut layer = CGLayer::new(self.ctx, area.size()).ok_or(Error::InvalidInput)?;
+        {
+            let text = Some(self.text.clone());
+            let mut ctx = CoreGraphicsContext::new_y_up(layer.context(), area.height(), text);
+            // the layer is only drawn to this context, so it needs no flush.
+            ctx.set_flush_on_finish(false);
+            ctx.transform(Affine::translate(-area.origin().to_vec2()));
+            draw(&mut ctx)?;
+            ctx.finish()?;
+        }
+        Ok(CoreGraphicsImage::Layer(layer))
+    }
+
+    fn draw_layer(&mut self, layer: &CGLayer, rect: Rect) {
+        // layers are drawn with their top at the top of the rect in
+        // CoreGraphics' y-up space, so they are flipped like images are.
+        self.ctx.save();
+        self.ctx.translate(rect.min_x(), rect.max_y());
+        self.ctx.scale(1.0, -1.0);
+        layer.draw_in_rect(self.ctx, to_cgrect(rect.with_origin(Point::ZERO)));
+        self.ctx.restore();
+    }
+
+    /// Draw the `src_rect` area of a layer into `dst_rect`.
+    fn draw_layer_area(&mut self, layer: &CGLayer, src_rect: Rect, dst_rect: Rect) {
+        if src_rect.is_empty() {
+            return;
+        }
+        // draw the whole layer, scaled and clipped so that `src_rect` fills `dst_rect`.
+        let scale_x = dst_rect.width() / src_rect.width();
+        let scale_y = dst_rect.height() / src_rect.height();
+        let size = layer.size();
+        let origin = Point::new(
+            dst_rect.x0 - src_rect.x0 * scale_x,
+            dst_rect.y0 - src_rect.y0 * scale_y,
+        );
+        let full = Rect::from_origin_size(
+            origin,
+            Size::new(size.width * scale_x, size.height * scale_y),
+        );
+        self.ctx.save();
+        self.ctx.clip_to_rect(to_cgrect(dst_rect));
+        self.draw_layer(layer, full);
+        self.ctx.restore();
+    }
 }
 
 impl<'a> Drop for CoreGraphicsContext<'a> {
@@ -125,6 +239,10 @@ pub enum CoreGraphicsImage {
     Empty,
     YUp(CGImage),
     YDown(CGImage),
+    /// A layer, made with [`CoreGraphicsContext::capture_layer`].
+    Layer(CGLayer),
+    /// An area of a layer, made with [`Image::sub_image`].
+    LayerArea(CGLayer, Rect),
 }
 
 impl CoreGraphicsImage {
@@ -136,10 +254,19 @@ impl CoreGraphicsImage {
     }
     pub fn as_cgimage(&self) -> Option<&CGImage> {
         match self {
-            CoreGraphicsImage::Empty => None,
+            CoreGraphicsImage::Empty
+            | CoreGraphicsImage::Layer(_)
+            | CoreGraphicsImage::LayerArea(..) => None,
             CoreGraphicsImage::YUp(image) | CoreGraphicsImage::YDown(image) => Some(image),
         }
     }
+
+    pub fn as_cglayer(&self) -> Option<&CGLayer> {
+        match self {
+            CoreGraphicsImage::Layer(layer) => Some(layer),
+            _ => None,
+        }
+    }
 }
 
 impl<'a> RenderContext for CoreGraphicsContext<'a> {
@@ -159,6 +286,8 @@ impl<'a> RenderContext for CoreGraphicsContext<'a> {
         let current_xform = self.current_transform();
         let xform = current_xform.inverse();
         self.transform(xform);
+        // the global alpha doesn't apply to clearing
+        self.set_global_alpha(1.0);
 
         let region = region
             .into()
@@ -217,6 +346,42 @@ impl<'a> RenderContext for CoreGraphicsContext<'a> {
         }
     }
 
+    fn fill_with_mask(
+        &mut self,
+        shape: impl Shape,
+        brush: &impl IntoBrush<Self>,
+        mask: &Self::Image,
+    ) {
+        let (image, image_y_down) = match mask {
+            CoreGraphicsImage::YDown(img) => (img, true),
+            CoreGraphicsImage::YUp(img) => (img, false),
+            // layers can't be used as masks.
+            CoreGraphicsImage::Empty
+            | CoreGraphicsImage::Layer(_)
+            | CoreGraphicsImage::LayerArea(..) => return,
+        };
+        let bounds = shape.bounding_box();
+        if bounds.is_empty() {
+            return;
+        }
+        let alpha = alpha_mask(image);
+        self.ctx.save();
+        // the mask is flipped like images are in `draw_image`, and the flip is
+        // undone after clipping, since the clip is kept in device space.
+        if self.y_down && !image_y_down {
+            self.ctx.clip_to_mask(to_cgrect(bounds), &alpha);
+        } else {
+            self.ctx.translate(bounds.min_x(), bounds.max_y());
+            self.ctx.scale(1.0, -1.0);
+            self.ctx
+                .clip_to_mask(to_cgrect(bounds.with_origin(Point::ZERO)), &alpha);
+            self.ctx.scale(1.0, -1.0);
+            self.ctx.translate(-bounds.min_x(), -bounds.max_y());
+        }
+        self.fill(shape, brush);
+        self.ctx.restore();
+    }
+
     fn clip(&mut self, shape: impl Shape) {
         self.set_path(shape);
         self.ctx.clip();
@@ -248,21 +413,48 @@ impl<'a> RenderContext for CoreGraphicsContext<'a> {
         width: f64,
         style: &StrokeStyle,
     ) {
+        if piet::util::stroke_dash_caps(self, &shape, brush, width, style) {
+            return;
+        }
         let brush = brush.make_brush(self, || shape.bounding_box());
         self.set_path(shape);
-        self.set_stroke(width.round_into(), Some(style));
-        match brush.as_ref() {
-            Brush::Solid(color) => {
-                self.set_stroke_color(*color);
-                self.ctx.stroke_path();
+        if style.scale {
+            self.set_stroke(width.round_into(), Some(style));
+            match brush.as_ref() {
+                Brush::Solid(color) => {
+                    self.set_stroke_color(*color);
+                    self.ctx.stroke_path();
+                }
+                Brush::Gradient(grad) => {
+                    self.ctx.save();
+                    self.ctx.replace_path_with_stroked_path();
+                    self.ctx.clip();
+                    grad.fill(self.ctx, GRADIENT_DRAW_BEFORE_AND_AFTER);
+                    self.ctx.restore();
+                }
             }
-            Brush::Gradient(grad) => {
-                self.ctx.save();
-                self.ctx.replace_path_with_stroked_path();
-                self.ctx.clip();
-                grad.fill(self.ctx, GRADIENT_DRAW_BEFORE_AND_AFTER);
-                self.ctx.restore();
+        } else {
+            // The path is stored in device space, but the stroke parameters are
+            // interpreted using the CTM at stroke time; undo our transform (but
+            // not cocoa's) while we stroke.
+            let transform = self.current_transform();
+            self.ctx.save();
+            self.ctx.concat_ctm(to_cgaffine(transform.inverse()));
+            self.set_stroke(width.round_into(), Some(style));
+            match brush.as_ref() {
+                Brush::Solid(color) => {
+                    self.set_stroke_color(*color);
+                    self.ctx.stroke_path();
+                }
+                Brush::Gradient(grad) => {
+                    self.ctx.replace_path_with_stroked_path();
+                    // the gradient is specified in user space.
+                    self.ctx.concat_ctm(to_cgaffine(transform));
+                    self.ctx.clip();
+                    grad.fill(self.ctx, GRADIENT_DRAW_BEFORE_AND_AFTER);
+                }
             }
+            self.ctx.restore();
         }
     }
 
@@ -272,10 +464,30 @@ impl<'a> RenderContext for CoreGraphicsContext<'a> {
 
     fn draw_text(&mut self, layout: &Self::TextLayout, pos: impl Into<Point>) {
         let pos = pos.into();
+        for (range, color) in layout.backgrounds.iter() {
+            for rect in layout.rects_for_range(range.clone()) {
+                self.fill(rect + pos.to_vec2(), color);
+            }
+        }
         self.ctx.save();
+        match self.text.hinting {
+            Hinting::Default => (),
+            Hinting::Pixel => {
+                self.ctx.set_should_subpixel_position_fonts(false);
+                self.ctx.set_should_subpixel_quantize_fonts(true);
+            }
+            Hinting::Subpixel => {
+                self.ctx.set_allows_font_subpixel_positioning(true);
+                self.ctx.set_should_subpixel_position_fonts(true);
+                self.ctx.set_should_subpixel_quantize_fonts(false);
+            }
+        }
+        // vertical text is laid out horizontally, and rotated into place.
+        let writing_transform = Affine::translate(pos.to_vec2()) * layout.writing_transform();
+        self.ctx.concat_ctm(to_cgaffine(writing_transform));
         // inverted coordinate system; text is drawn from bottom left corner,
         // and (0, 0) in context is also bottom left.
-        self.ctx.translate(pos.x, layout.frame_size.height + pos.y);
+        self.ctx.translate(0.0, layout.frame_size.height);
         self.ctx.scale(1.0, -1.0);
         layout.draw(self.ctx);
         self.ctx.restore();
@@ -285,11 +497,18 @@ impl<'a> RenderContext for CoreGraphicsContext<'a> {
         self.ctx.save();
         let state = self.transform_stack.last().copied().unwrap_or_default();
         self.transform_stack.push(state);
+        self.saved_alphas.push(self.global_alpha);
+        self.saved_tolerances.push(self.tolerance);
         Ok(())
     }
 
     fn restore(&mut self) -> Result<(), Error> {
         if self.transform_stack.pop().is_some() {
+            self.global_alpha = self.saved_alphas.pop().unwrap_or(1.0);
+            self.tolerance = self
+                .saved_tolerances
+                .pop()
+                .unwrap_or(piet::DEFAULT_TOLERANCE);
             // we're defensive about calling restore on the inner context,
             // because an unbalanced call will trigger an assert in C
             self.ctx.restore();
@@ -300,6 +519,9 @@ impl<'a> RenderContext for CoreGraphicsContext<'a> {
     }
 
     fn finish(&mut self) -> Result<(), Error> {
+        if self.flush_on_finish {
+            self.ctx.flush();
+        }
         Ok(())
     }
 
@@ -322,6 +544,13 @@ impl<'a> RenderContext for CoreGraphicsContext<'a> {
         if width == 0 || height == 0 {
             return Ok(CoreGraphicsImage::Empty);
         }
+        if matches!(
+            format,
+            ImageFormat::BgraSeparate | ImageFormat::BgraPremul | ImageFormat::Rgba16
+        ) {
+            let (buf, format) = piet::util::to_rgba8(buf, format);
+            return self.make_image(width, height, &buf, format);
+        }
         assert!(!buf.is_empty() && buf.len() <= format.bytes_per_pixel() * width * height);
         let data = Arc::new(buf.to_owned());
         let data_provider = CGDataProvider::from_buffer(data);
@@ -372,6 +601,14 @@ impl<'a> RenderContext for CoreGraphicsContext<'a> {
                 image_y_down = false;
                 img
             }
+            CoreGraphicsImage::Layer(layer) => {
+                self.draw_layer(layer, rect.into());
+                return;
+            }
+            CoreGraphicsImage::LayerArea(layer, area) => {
+                self.draw_layer_area(layer, *area, rect.into());
+                return;
+            }
             CoreGraphicsImage::Empty => return,
         };
 
@@ -382,6 +619,8 @@ impl<'a> RenderContext for CoreGraphicsContext<'a> {
                 CGInterpolationQuality::CGInterpolationQualityNone
             }
             InterpolationMode::Bilinear => CGInterpolationQuality::CGInterpolationQualityDefault,
+            InterpolationMode::Bicubic => CGInterpolationQuality::CGInterpolationQualityMedium,
+            InterpolationMode::HighQuality => CGInterpolationQuality::CGInterpolationQualityHigh,
         };
         self.ctx.set_interpolation_quality(quality);
         let rect = rect.into();
@@ -416,6 +655,11 @@ impl<'a> RenderContext for CoreGraphicsContext<'a> {
             if let Some(cropped) = image.cropped(to_cgrect(src_rect)) {
                 self.draw_image(&CoreGraphicsImage::YUp(cropped), dst_rect, interp);
             }
+        } else if let CoreGraphicsImage::Layer(layer) = image {
+            self.draw_layer_area(layer, src_rect.into(), dst_rect.into());
+        } else if let CoreGraphicsImage::LayerArea(layer, area) = image {
+            let src_rect = src_rect.into() + area.origin().to_vec2();
+            self.draw_layer_area(layer, src_rect, dst_rect.into());
         }
     }
 
@@ -511,6 +755,44 @@ impl<'a> RenderContext for CoreGraphicsContext<'a> {
         self.transform_stack.last().copied().unwrap_or_default()
     }
 
+    fn capabilities(&self) -> Capabilities {
+        // image filters aren't drawn yet, so only blurred rects are blurred.
+        let mut capabilities = Capabilities::default();
+        capabilities.supports_global_alpha = true;
+        capabilities.text_shaping_quality = TextShapingQuality::Full;
+        capabilities
+    }
+
+    fn set_global_alpha(&mut self, alpha: f64) {
+        let alpha = if alpha.is_nan() {
+            0.0
+        } else {
+            alpha.clamp(0.0, 1.0)
+        };
+        unsafe {
+            CGContextSetAlpha(self.ctx.as_ptr(), alpha);
+        }
+        self.global_alpha = alpha;
+    }
+
+    fn global_alpha(&self) -> f64 {
+        self.global_alpha
+    }
+
+    fn set_tolerance(&mut self, tolerance: f64) {
+        if tolerance > 0.0 && tolerance.is_finite() {
+            self.tolerance = tolerance;
+        }
+    }
+
+    fn tolerance(&self) -> f64 {
+        self.tolerance
+    }
+
+    fn hit_tester(&mut self) -> Option<&mut HitTester> {
+        Some(&mut self.hit_tester)
+    }
+
     fn status(&mut self) -> Result<(), Error> {
         Ok(())
     }
@@ -536,6 +818,31 @@ impl Image for CoreGraphicsImage {
             CoreGraphicsImage::YDown(image) | CoreGraphicsImage::YUp(image) => {
                 Size::new(image.width() as f64, image.height() as f64)
             }
+            CoreGraphicsImage::Layer(layer) => layer.size(),
+            CoreGraphicsImage::LayerArea(_, area) => area.size(),
+        }
+    }
+
+    /// Images are cropped with `CGImageCreateWithImageInRect`, which keeps
+    /// the pixels of the image, and layers are drawn clipped to the area.
+    fn sub_image(&self, rect: impl Into<Rect>) -> Self {
+        let view = piet::util::sub_image_view(self.size().to_rect(), rect.into());
+        if view.is_empty() {
+            return CoreGraphicsImage::Empty;
+        }
+        let cropped = |image: &CGImage| image.cropped(to_cgrect(view));
+        match self {
+            CoreGraphicsImage::Empty => CoreGraphicsImage::Empty,
+            CoreGraphicsImage::YDown(image) => cropped(image)
+                .map(CoreGraphicsImage::YDown)
+                .unwrap_or(CoreGraphicsImage::Empty),
+            CoreGraphicsImage::YUp(image) => cropped(image)
+                .map(CoreGraphicsImage::YUp)
+                .unwrap_or(CoreGraphicsImage::Empty),
+            CoreGraphicsImage::Layer(layer) => CoreGraphicsImage::LayerArea(layer.clone(), view),
+            CoreGraphicsImage::LayerArea(layer, area) => {
+                CoreGraphicsImage::LayerArea(layer.clone(), view + area.origin().to_vec2())
+            }
         }
     }
 }
@@ -589,7 +896,7 @@ impl<'a> CoreGraphicsContext<'a> {
         // state. But just in case, and it should be harmless.
         self.ctx.begin_path();
         let mut last = Point::default();
-        for el in shape.path_elements(1e-3) {
+        for el in shape.path_elements(self.tolerance) {
             match el {
                 PathEl::MoveTo(p) => {
                     self.ctx.move_to_point(p.x, p.y);
@@ -642,6 +949,44 @@ fn compute_blurred_rect(rect: Rect, radius: f64) -> (CGImage, Rect) {
     (image, rect_exp)
 }
 
+/// The alpha of an image, as a grayscale image for `clip_to_mask`.
+fn alpha_mask(image: &CGImage) -> CGImage {
+    let (width, height) = (image.width(), image.height());
+    // drawing the image into a context converts it from any format.
+    let mut ctx = core_graphics::context::CGContext::create_bitmap_context(
+        None,
+        width,
+        height,
+        8,
+        0,
+        &CGColorSpace::create_device_rgb(),
+        kCGImageAlphaPremultipliedLast,
+    );
+    let rect = Rect::new(0.0, 0.0, width as f64, height as f64);
+    ctx.draw_image(to_cgrect(rect), image);
+    let stride = ctx.bytes_per_row();
+    let data = ctx
+        .data()
+        .chunks_exact(stride)
+        .flat_map(|row| row[..width * 4].iter().skip(3).step_by(4).copied())
+        .collect::<Vec<_>>();
+
+    let data_provider = CGDataProvider::from_buffer(Arc::new(data));
+    let color_space = CGColorSpace::create_device_gray();
+    CGImage::new(
+        width,
+        height,
+        8,
+        8,
+        width,
+        &color_space,
+        0,
+        &data_provider,
+        false,
+        0,
+    )
+}
+
 fn to_cgpoint(point: Point) -> CGPoint {
     CGPoint::new(point.x as CGFloat, point.y as CGFloat)
 }
@@ -663,6 +1008,7 @@ fn to_cgaffine(affine: Affine) -> CGAffineTransform {
 #[link(name = "CoreGraphics", kind = "framework")]
 extern "C" {
     fn CGContextResetClip(c: core_graphics::sys::CGContextRef);
+    fn CGContextSetAlpha(c: core_graphics::sys::CGContextRef, alpha: CGFloat);
 }
 
 #[cfg(test)]
@@ -767,4 +1113,59 @@ mod tests {
             InterpolationMode::Bilinear,
         );
     }
+
+    #[test]
+    fn capture_layer() {
+        let mut ctx = make_context((400.0, 400.0));
+        let mut piet = CoreGraphicsContext::new_y_up(&mut ctx, 400.0, None);
+
+        assert!(piet
+            .capture_layer(Rect::new(0.0, 0.0, 0.0, 0.0), |_| Ok(()))
+            .is_err());
+
+        let area = Rect::new(100.0, 100.0, 200.0, 200.0);
+        let layer = piet
+            .capture_layer(area, |rc| {
+                rc.fill(Rect::new(100.0, 100.0, 150.0, 200.0), &Color::RED);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(layer.size(), Size::new(100.0, 100.0));
+        assert!(layer.as_cgimage().is_none());
+
+        piet.draw_image(
+            &layer,
+            Rect::new(0.0, 0.0, 100.0, 100.0),
+            InterpolationMode::Bilinear,
+        );
+        piet.draw_image_area(
+            &layer,
+            Rect::new(0.0, 0.0, 50.0, 50.0),
+            Rect::new(200.0, 200.0, 300.0, 300.0),
+            InterpolationMode::Bilinear,
+        );
+        piet.finish().unwrap();
+        std::mem::drop(piet);
+
+        let stride = ctx.bytes_per_row();
+        let data = ctx.data();
+        let pixel = |x: usize, y: usize| &data[y * stride + x * 4..][..4];
+        assert_eq!(pixel(25, 50), [255, 0, 0, 255]);
+        assert_eq!(pixel(75, 50), [0, 0, 0, 0]);
+        // only the top left quarter of the layer is drawn, scaled up.
+        assert_eq!(pixel(250, 250), [255, 0, 0, 255]);
+        assert_eq!(pixel(250, 350), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn finish_without_flush() {
+        let mut ctx = make_context((10.0, 10.0));
+        let mut piet = CoreGraphicsContext::new_y_up(&mut ctx, 10.0, None);
+        piet.set_flush_on_finish(false);
+        piet.fill(Rect::new(0.0, 0.0, 10.0, 10.0), &Color::BLUE);
+        piet.finish().unwrap();
+        piet.flush();
+        std::mem::drop(piet);
+        assert_eq!(&ctx.data()[..4], [0, 0, 255, 255]);
+    }
 }
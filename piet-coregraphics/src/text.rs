@@ -14,7 +14,7 @@ use core_foundation::string::CFString;
 use core_foundation_sys::base::CFRange;
 use core_graphics::base::CGFloat;
 use core_graphics::context::CGContextRef;
-use core_graphics::geometry::{CGPoint, CGRect, CGSize};
+use core_graphics::geometry::{CGAffineTransform, CGPoint, CGRect, CGSize};
 use core_graphics::path::CGPath;
 use core_text::{
     font,
@@ -23,10 +23,11 @@ use core_text::{
     string_attributes,
 };
 
-use piet::kurbo::{Affine, Point, Rect, Size};
+use piet::kurbo::{Affine, BezPath, Point, Rect, Size};
 use piet::{
-    util, Error, FontFamily, FontStyle, FontWeight, HitTestPoint, HitTestPosition, LineMetric,
-    Text, TextAlignment, TextAttribute, TextLayout, TextLayoutBuilder, TextStorage,
+    util, Color, Error, FontFamily, FontFeature, FontStyle, FontVariation, FontWeight, Glyph,
+    GlyphRun, Hinting, HitTestPoint, HitTestPosition, LineMetric, ScriptPosition, Text,
+    TextAlignment, TextAttribute, TextLayout, TextLayoutBuilder, TextStorage, WritingMode,
 };
 
 use crate::ct_helpers::{self, AttributedString, FontCollection, Frame, Framesetter, Line};
@@ -37,6 +38,7 @@ const MAX_LAYOUT_CONSTRAINT: f64 = 1e9;
 #[derive(Clone)]
 pub struct CoreGraphicsText {
     shared: SharedTextState,
+    pub(crate) hinting: Hinting,
 }
 
 /// State shared by all `CoreGraphicsText` objects.
@@ -76,12 +78,26 @@ pub struct CoreGraphicsTextLayout {
     line_metrics: Rc<[LineMetric]>,
     x_offsets: Rc<[f64]>,
     trailing_ws_width: f64,
+    writing_mode: WritingMode,
+    /// The alignment of the empty line after a trailing newline, which
+    /// CoreText doesn't lay out.
+    trailing_alignment: TextAlignment,
+    /// Background colors, by utf8 range, in the order they should be painted.
+    pub(crate) backgrounds: Rc<[(Range<usize>, Color)]>,
 }
 
 /// Building text layouts for `CoreGraphics`.
 pub struct CoreGraphicsTextLayoutBuilder {
     width: f64,
     alignment: TextAlignment,
+    /// Alignments of the paragraphs that start in each range, overriding
+    /// `alignment`; later ranges win.
+    paragraph_alignments: Vec<(Range<usize>, TextAlignment)>,
+    writing_mode: WritingMode,
+    tab_stops: Vec<f64>,
+    tab_width: Option<f64>,
+    /// CoreText has no background attribute; we paint these ourselves.
+    backgrounds: Vec<(Range<usize>, Color)>,
     text: Rc<dyn TextStorage>,
     /// the end bound up to which we have already added attrs to our AttributedString
     last_resolved_pos: usize,
@@ -105,6 +121,10 @@ struct Attributes {
     size: Option<Span<f64>>,
     weight: Option<Span<FontWeight>>,
     style: Option<Span<FontStyle>>,
+    variations: Option<Span<Arc<[FontVariation]>>>,
+    features: Option<Span<Arc<[FontFeature]>>>,
+    baseline_shift: Option<Span<f64>>,
+    script: Option<Span<ScriptPosition>>,
 }
 
 #[derive(Clone)]
@@ -113,6 +133,8 @@ struct CoreTextFontKey {
     weight: FontWeight,
     italic: bool,
     size: f64,
+    variations: Arc<[FontVariation]>,
+    features: Arc<[FontFeature]>,
 }
 
 impl PartialEq for CoreTextFontKey {
@@ -121,6 +143,8 @@ impl PartialEq for CoreTextFontKey {
             && self.weight == other.weight
             && self.italic == other.italic
             && self.size.to_bits() == other.size.to_bits()
+            && self.variations == other.variations
+            && self.features == other.features
     }
 }
 
@@ -132,6 +156,11 @@ impl Hash for CoreTextFontKey {
         self.weight.hash(state);
         self.italic.hash(state);
         self.size.to_bits().hash(state);
+        for variation in self.variations.iter() {
+            variation.tag.hash(state);
+            variation.value.to_bits().hash(state);
+        }
+        self.features.hash(state);
     }
 }
 
@@ -161,10 +190,17 @@ impl CoreTextFontKey {
                 traits.set(symbolic_traits_key, symbolic_traits.as_CFType());
             }
 
-            let attributes = CFDictionary::from_CFType_pairs(&[
+            let mut attributes = vec![
                 (family_key, family_name.as_CFType()),
                 (traits_key, traits.as_CFType()),
-            ]);
+            ];
+            if !self.features.is_empty() {
+                let features_key =
+                    CFString::wrap_under_get_rule(font_descriptor::kCTFontFeatureSettingsAttribute);
+                let features = ct_helpers::opentype_feature_settings(&self.features);
+                attributes.push((features_key, features.as_CFType()));
+            }
+            let attributes = CFDictionary::from_CFType_pairs(&attributes);
             let descriptor = font_descriptor::new_from_attributes(&attributes);
             let font = font::new_from_descriptor(&descriptor, self.size);
 
@@ -196,18 +232,33 @@ impl CoreTextFontKey {
                 .unwrap_or_default();
 
             // only set weight axis if it exists, and we're not a system font (things get weird)
-            let descriptor = if variation_axes.contains(&WEIGHT_AXIS_TAG) && !self.font.is_generic()
-            {
-                let weight_axis_id: CFNumber = WEIGHT_AXIS_TAG.into();
-                let descriptor = font_descriptor::CTFontDescriptorCreateCopyWithVariation(
+            let mut descriptor =
+                if variation_axes.contains(&WEIGHT_AXIS_TAG) && !self.font.is_generic() {
+                    let weight_axis_id: CFNumber = WEIGHT_AXIS_TAG.into();
+                    let descriptor = font_descriptor::CTFontDescriptorCreateCopyWithVariation(
+                        descriptor.as_concrete_TypeRef(),
+                        weight_axis_id.as_concrete_TypeRef(),
+                        self.weight.to_raw() as _,
+                    );
+                    font_descriptor::CTFontDescriptor::wrap_under_create_rule(descriptor)
+                } else {
+                    descriptor
+                };
+
+            // explicit axis values, which may override the weight set above
+            for variation in self.variations.iter() {
+                let tag = variation.tag_u32() as i32;
+                if !variation_axes.contains(&tag) {
+                    continue;
+                }
+                let axis_id: CFNumber = tag.into();
+                let copy = font_descriptor::CTFontDescriptorCreateCopyWithVariation(
                     descriptor.as_concrete_TypeRef(),
-                    weight_axis_id.as_concrete_TypeRef(),
-                    self.weight.to_raw() as _,
+                    axis_id.as_concrete_TypeRef(),
+                    variation.value as _,
                 );
-                font_descriptor::CTFontDescriptor::wrap_under_create_rule(descriptor)
-            } else {
-                descriptor
-            };
+                descriptor = font_descriptor::CTFontDescriptor::wrap_under_create_rule(copy);
+            }
 
             ct_helpers::make_font(&descriptor, self.size, affine)
         }
@@ -258,7 +309,11 @@ impl CoreGraphicsTextLayoutBuilder {
         // immediately.
         if matches!(
             &attr,
-            TextAttribute::TextColor(_) | TextAttribute::Underline(_)
+            TextAttribute::TextColor(_)
+                | TextAttribute::Underline(_)
+                | TextAttribute::LetterSpacing(_)
+                | TextAttribute::WordSpacing(_)
+                | TextAttribute::BackgroundColor(_)
         ) {
             return self.add_immediately(attr, range);
         }
@@ -286,9 +341,17 @@ impl CoreGraphicsTextLayoutBuilder {
             .set_fg_color(whole_range, self.attrs.defaults.fg_color);
         self.attr_string
             .set_underline(whole_range, self.attrs.defaults.underline);
+        if self.attrs.defaults.letter_spacing != 0.0 {
+            self.attr_string
+                .set_kern(whole_range, self.attrs.defaults.letter_spacing);
+        }
     }
 
     fn add_immediately(&mut self, attr: TextAttribute, range: Range<usize>) {
+        if let TextAttribute::BackgroundColor(color) = attr {
+            self.backgrounds.push((range, color));
+            return;
+        }
         let utf16_start = util::count_utf16(&self.text[..range.start]);
         let utf16_len = util::count_utf16(&self.text[range]);
         let range = CFRange::init(utf16_start as isize, utf16_len as isize);
@@ -297,6 +360,9 @@ impl CoreGraphicsTextLayoutBuilder {
                 self.attr_string.set_fg_color(range, color);
             }
             TextAttribute::Underline(flag) => self.attr_string.set_underline(range, flag),
+            TextAttribute::LetterSpacing(spacing) => self.attr_string.set_kern(range, spacing),
+            // CoreText has no word spacing attribute.
+            TextAttribute::WordSpacing(_) => (),
             _ => unreachable!(),
         }
     }
@@ -326,6 +392,10 @@ impl CoreGraphicsTextLayoutBuilder {
                         &font,
                     );
                 }
+                let baseline_shift = self.attrs.baseline_shift();
+                if baseline_shift != 0.0 {
+                    self.attr_string.set_baseline_offset(range, baseline_shift);
+                }
                 self.last_resolved_pos = next_span_end;
                 self.last_resolved_utf16 += range_end_utf16;
                 self.update_after_adding_span();
@@ -353,7 +423,9 @@ impl CoreGraphicsTextLayoutBuilder {
             font: self.attrs.font().to_owned(),
             weight: self.attrs.weight(),
             italic: self.attrs.italic(),
-            size: self.attrs.size(),
+            size: self.attrs.size() * self.attrs.script().font_scale(),
+            variations: self.attrs.variations().clone(),
+            features: self.attrs.features().clone(),
         })
     }
 
@@ -374,6 +446,10 @@ impl Attributes {
             TextAttribute::Weight(w) => self.weight = Some(Span::new(w, range)),
             TextAttribute::FontSize(s) => self.size = Some(Span::new(s, range)),
             TextAttribute::Style(s) => self.style = Some(Span::new(s, range)),
+            TextAttribute::FontVariations(v) => self.variations = Some(Span::new(v, range)),
+            TextAttribute::FontFeatures(f) => self.features = Some(Span::new(f, range)),
+            TextAttribute::BaselineShift(s) => self.baseline_shift = Some(Span::new(s, range)),
+            TextAttribute::ScriptPosition(s) => self.script = Some(Span::new(s, range)),
             TextAttribute::Strikethrough(_) => { /* Unimplemented for now as coregraphics doesn't have native strikethrough support. */
             }
             _ => unreachable!(),
@@ -411,6 +487,37 @@ impl Attributes {
             .unwrap_or_else(|| &self.defaults.font)
     }
 
+    fn variations(&self) -> &Arc<[FontVariation]> {
+        self.variations
+            .as_ref()
+            .map(|t| &t.payload)
+            .unwrap_or_else(|| &self.defaults.variations)
+    }
+
+    fn features(&self) -> &Arc<[FontFeature]> {
+        self.features
+            .as_ref()
+            .map(|t| &t.payload)
+            .unwrap_or_else(|| &self.defaults.features)
+    }
+
+    fn script(&self) -> ScriptPosition {
+        self.script
+            .as_ref()
+            .map(|t| t.payload)
+            .unwrap_or(self.defaults.script)
+    }
+
+    /// The total baseline offset, including the offset for any script position.
+    fn baseline_shift(&self) -> f64 {
+        let shift = self
+            .baseline_shift
+            .as_ref()
+            .map(|t| t.payload)
+            .unwrap_or(self.defaults.baseline_shift);
+        shift + self.script().baseline_shift(self.size())
+    }
+
     fn next_span_end(&self, max: usize) -> usize {
         self.font
             .as_ref()
@@ -419,6 +526,15 @@ impl Attributes {
             .min(self.size.as_ref().map(Span::range_end).unwrap_or(max))
             .min(self.weight.as_ref().map(Span::range_end).unwrap_or(max))
             .min(self.style.as_ref().map(Span::range_end).unwrap_or(max))
+            .min(self.variations.as_ref().map(Span::range_end).unwrap_or(max))
+            .min(self.features.as_ref().map(Span::range_end).unwrap_or(max))
+            .min(
+                self.baseline_shift
+                    .as_ref()
+                    .map(Span::range_end)
+                    .unwrap_or(max),
+            )
+            .min(self.script.as_ref().map(Span::range_end).unwrap_or(max))
             .min(max)
     }
 
@@ -436,6 +552,18 @@ impl Attributes {
         if self.size.as_ref().map(Span::range_end) == Some(last_pos) {
             self.size = None;
         }
+        if self.variations.as_ref().map(Span::range_end) == Some(last_pos) {
+            self.variations = None;
+        }
+        if self.features.as_ref().map(Span::range_end) == Some(last_pos) {
+            self.features = None;
+        }
+        if self.baseline_shift.as_ref().map(Span::range_end) == Some(last_pos) {
+            self.baseline_shift = None;
+        }
+        if self.script.as_ref().map(Span::range_end) == Some(last_pos) {
+            self.script = None;
+        }
     }
 }
 
@@ -475,6 +603,7 @@ impl CoreGraphicsText {
         }));
         CoreGraphicsText {
             shared: SharedTextState { inner },
+            hinting: Hinting::Default,
         }
     }
 }
@@ -502,6 +631,10 @@ impl Text for CoreGraphicsText {
             .map(FontFamily::new_unchecked)
             .map_err(|_| Error::MissingFont)
     }
+
+    fn set_hinting(&mut self, hinting: Hinting) {
+        self.hinting = hinting;
+    }
 }
 
 impl SharedTextState {
@@ -543,6 +676,11 @@ impl CoreGraphicsTextLayoutBuilder {
             shared,
             width: MAX_LAYOUT_CONSTRAINT,
             alignment: TextAlignment::default(),
+            paragraph_alignments: Vec::new(),
+            writing_mode: WritingMode::default(),
+            tab_stops: Vec::new(),
+            tab_width: None,
+            backgrounds: Vec::new(),
             attrs: Default::default(),
             text,
             last_resolved_pos: 0,
@@ -555,6 +693,76 @@ impl CoreGraphicsTextLayoutBuilder {
     }
 }
 
+impl CoreGraphicsTextLayoutBuilder {
+    /// Set the paragraph style of each paragraph, and return the alignment of
+    /// the last paragraph, which is also used for the empty line after a
+    /// trailing newline.
+    fn set_paragraph_styles(&mut self) -> TextAlignment {
+        let range = self.attr_string.range();
+        self.attr_string.set_paragraph_style(
+            range,
+            self.alignment,
+            &self.tab_stops,
+            self.tab_width,
+        );
+        if self.paragraph_alignments.is_empty() {
+            return self.alignment;
+        }
+
+        let mut last_alignment = self.alignment;
+        let mut start_utf16 = 0;
+        for paragraph in paragraphs(&self.text) {
+            let len_utf16 = util::count_utf16(&self.text[paragraph.clone()]);
+            let alignment = self
+                .paragraph_alignments
+                .iter()
+                .rev()
+                .find(|(range, _)| range.contains(&paragraph.start))
+                .map(|(_, alignment)| *alignment);
+            if let Some(alignment) = alignment {
+                let range = CFRange::init(start_utf16 as isize, len_utf16 as isize);
+                self.attr_string.set_paragraph_style(
+                    range,
+                    alignment,
+                    &self.tab_stops,
+                    self.tab_width,
+                );
+            }
+            last_alignment = alignment.unwrap_or(self.alignment);
+            start_utf16 += len_utf16;
+        }
+        last_alignment
+    }
+}
+
+/// The byte ranges of the paragraphs of `text`, each including the line
+/// break that ends it.
+fn paragraphs(text: &str) -> impl Iterator<Item = Range<usize>> + '_ {
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+    std::iter::from_fn(move || {
+        while let Some((idx, c)) = chars.next() {
+            let ends_paragraph = match c {
+                '\n' | '\u{2029}' => true,
+                '\r' => chars.peek().map(|(_, next)| *next) != Some('\n'),
+                _ => false,
+            };
+            if ends_paragraph {
+                let end = idx + c.len_utf8();
+                let paragraph = start..end;
+                start = end;
+                return Some(paragraph);
+            }
+        }
+        if start < text.len() {
+            let paragraph = start..text.len();
+            start = text.len();
+            return Some(paragraph);
+        }
+        None
+    })
+}
+
 impl fmt::Debug for CoreGraphicsTextLayoutBuilder {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("CoreGraphicsTextLayoutBuilder").finish()
@@ -574,6 +782,31 @@ impl TextLayoutBuilder for CoreGraphicsTextLayoutBuilder {
         self
     }
 
+    fn paragraph_alignment(
+        mut self,
+        range: impl RangeBounds<usize>,
+        alignment: TextAlignment,
+    ) -> Self {
+        let range = util::resolve_range(range, self.text.len());
+        self.paragraph_alignments.push((range, alignment));
+        self
+    }
+
+    fn writing_mode(mut self, mode: WritingMode) -> Self {
+        self.writing_mode = mode;
+        self
+    }
+
+    fn tab_stops(mut self, stops: &[f64]) -> Self {
+        self.tab_stops = stops.to_vec();
+        self
+    }
+
+    fn tab_width(mut self, width: f64) -> Self {
+        self.tab_width = Some(width);
+        self
+    }
+
     fn default_attribute(mut self, attribute: impl Into<TextAttribute>) -> Self {
         debug_assert!(
             !self.has_set_default_attrs,
@@ -597,14 +830,28 @@ impl TextLayoutBuilder for CoreGraphicsTextLayoutBuilder {
 
     fn build(mut self) -> Result<Self::Out, Error> {
         self.finalize();
-        self.attr_string.set_alignment(self.alignment);
-        Ok(CoreGraphicsTextLayout::new(
+        let trailing_alignment = self.set_paragraph_styles();
+        if self.writing_mode.is_vertical() {
+            // lines are laid out horizontally and rotated when drawn; vertical
+            // forms ensure CJK glyphs end up upright.
+            let range = self.attr_string.range();
+            self.attr_string.set_vertical_forms(range, true);
+        }
+        let default_bg = self.attrs.defaults.bg_color;
+        if default_bg != Color::TRANSPARENT {
+            self.backgrounds.insert(0, (0..self.text.len(), default_bg));
+        }
+        let mut layout = CoreGraphicsTextLayout::new(
             self.text,
             self.attr_string,
             self.width,
             self.default_baseline,
             self.default_line_height,
-        ))
+            self.writing_mode,
+            trailing_alignment,
+        );
+        layout.backgrounds = self.backgrounds.into();
+        Ok(layout)
     }
 }
 
@@ -616,10 +863,12 @@ impl fmt::Debug for CoreGraphicsTextLayout {
 
 impl TextLayout for CoreGraphicsTextLayout {
     fn size(&self) -> Size {
-        Size::new(
-            self.frame_size.width,
-            self.frame_size.height + self.bonus_height,
-        )
+        let size = self.horizontal_size();
+        if self.writing_mode.is_vertical() {
+            Size::new(size.height, size.width)
+        } else {
+            size
+        }
     }
 
     fn trailing_whitespace_width(&self) -> f64 {
@@ -627,7 +876,8 @@ impl TextLayout for CoreGraphicsTextLayout {
     }
 
     fn image_bounds(&self) -> Rect {
-        self.image_bounds
+        self.writing_transform()
+            .transform_rect_bbox(self.image_bounds)
     }
 
     fn text(&self) -> &str {
@@ -649,6 +899,8 @@ impl TextLayout for CoreGraphicsTextLayout {
 
     // given a point on the screen, return an offset in the text, basically
     fn hit_test_point(&self, point: Point) -> HitTestPoint {
+        // do the hit test in the horizontal coordinate space
+        let point = self.writing_transform().inverse() * point;
         let line_num = self
             .line_metrics
             .iter()
@@ -662,7 +914,14 @@ impl TextLayout for CoreGraphicsTextLayout {
                 // if we can't find a line we're either an empty string or we're
                 // at the newline at eof
                 assert!(self.text.is_empty() || util::trailing_nlf(&self.text).is_some());
-                return HitTestPoint::new(self.text.len(), false);
+                return util::hit_test_point_on_line(
+                    self,
+                    self.writing_transform(),
+                    point,
+                    line_num,
+                    self.text.len(),
+                    false,
+                );
             }
         };
         let line_text = self.line_text(line_num).unwrap();
@@ -698,7 +957,14 @@ impl TextLayout for CoreGraphicsTextLayout {
             point_in_string_space.x >= 0. && point_in_string_space.x <= typo_bounds.width;
         let is_inside = is_inside_x && is_inside_y;
 
-        HitTestPoint::new(offset, is_inside)
+        util::hit_test_point_on_line(
+            self,
+            self.writing_transform(),
+            point,
+            line_num,
+            offset,
+            is_inside,
+        )
     }
 
     fn hit_test_text_position(&self, idx: usize) -> HitTestPosition {
@@ -712,7 +978,8 @@ impl TextLayout for CoreGraphicsTextLayout {
                 assert!(self.text.is_empty() || util::trailing_nlf(&self.text).is_some());
                 let lm = &self.line_metrics[line_num];
                 let y_pos = lm.y_offset + lm.baseline;
-                return HitTestPosition::new(Point::new(0., y_pos), line_num);
+                let point = self.writing_transform() * Point::new(0., y_pos);
+                return HitTestPosition::new(point, line_num);
             }
         };
 
@@ -726,7 +993,138 @@ impl TextLayout for CoreGraphicsTextLayout {
         let char_idx = line_range.location + off16 as isize;
         let x_pos = line.get_offset_for_string_index(char_idx) + x_offset;
         let y_pos = metric.y_offset + metric.baseline;
-        HitTestPosition::new(Point::new(x_pos, y_pos), line_num)
+        let point = self.writing_transform() * Point::new(x_pos, y_pos);
+        HitTestPosition::new(point, line_num)
+    }
+
+    fn rects_for_range(&self, range: impl RangeBounds<usize>) -> Vec<Rect> {
+        let range = util::resolve_range(range, self.text.len());
+        let transform = self.writing_transform();
+        let mut rects = Vec::new();
+        for (line_num, line) in self.unwrap_frame().lines().iter().enumerate() {
+            let metric = &self.line_metrics[line_num];
+            let start = range.start.max(metric.start_offset);
+            let end = range.end.min(metric.end_offset);
+            if start >= end {
+                continue;
+            }
+            let line_text = self.line_text(line_num).unwrap_or("");
+            let line_start16 = line.get_string_range().location;
+            let to_utf16 = |offset: usize| {
+                line_start16
+                    + util::count_utf16(&line_text[..offset - metric.start_offset]) as isize
+            };
+            let range16 = to_utf16(start)..to_utf16(end);
+
+            // the extents of the selected glyphs; in bidi text these may not be contiguous.
+            let runs = line.glyph_runs();
+            let spans = runs.iter().flat_map(|run| {
+                run.positions
+                    .iter()
+                    .zip(run.advances.iter())
+                    .zip(run.string_indices.iter())
+                    .filter(|(_, idx16)| range16.contains(*idx16))
+                    .map(|((pos, advance), _)| (pos.x, pos.x + advance.width))
+            });
+            let x_offset = self.x_offsets[line_num];
+            for (x0, x1) in util::merge_spans(spans) {
+                let rect = Rect::new(
+                    x_offset + x0,
+                    metric.y_offset,
+                    x_offset + x1,
+                    metric.y_offset + metric.height,
+                );
+                rects.push(transform.transform_rect_bbox(rect));
+            }
+        }
+        rects
+    }
+
+    fn glyph_runs(&self) -> Vec<GlyphRun> {
+        let transform = self.writing_transform();
+        let mut result = Vec::new();
+        for (line_num, line) in self.unwrap_frame().lines().iter().enumerate() {
+            let metric = &self.line_metrics[line_num];
+            let line_text = self.line_text(line_num).unwrap_or("");
+            let line_start16 = line.get_string_range().location;
+            let origin = Point::new(self.x_offsets[line_num], metric.y_offset + metric.baseline);
+
+            for run in line.glyph_runs() {
+                let font = match run.font {
+                    Some(font) => font,
+                    None => continue,
+                };
+                let traits = font.symbolic_traits();
+                let glyphs = run
+                    .glyphs
+                    .iter()
+                    .zip(run.positions.iter().zip(run.advances.iter()))
+                    .zip(run.string_indices.iter())
+                    .map(|((id, (pos, advance)), idx16)| {
+                        let rel16 = (idx16 - line_start16).max(0) as usize;
+                        let cluster = metric.start_offset
+                            + util::count_until_utf16(line_text, rel16)
+                                .unwrap_or_else(|| line_text.len());
+                        // CoreText positions are y-up, relative to the line origin
+                        let position = origin + (pos.x, -pos.y);
+                        Glyph {
+                            id: *id as u32,
+                            position: transform * position,
+                            advance: advance.width,
+                            cluster,
+                        }
+                    })
+                    .collect();
+
+                result.push(GlyphRun {
+                    font: FontFamily::new_unchecked(font.family_name()),
+                    font_size: font.pt_size(),
+                    weight: if traits.is_bold() {
+                        FontWeight::BOLD
+                    } else {
+                        FontWeight::REGULAR
+                    },
+                    style: if traits.is_italic() {
+                        FontStyle::Italic
+                    } else {
+                        FontStyle::Regular
+                    },
+                    line: line_num,
+                    glyphs,
+                });
+            }
+        }
+        result
+    }
+
+    fn outline(&self) -> BezPath {
+        let mut path = BezPath::new();
+        for (line_num, line) in self.unwrap_frame().lines().iter().enumerate() {
+            let metric = &self.line_metrics[line_num];
+            let origin = Point::new(self.x_offsets[line_num], metric.y_offset + metric.baseline);
+            for run in line.glyph_runs() {
+                let font = match run.font {
+                    Some(font) => font,
+                    None => continue,
+                };
+                for (glyph, pos) in run.glyphs.iter().zip(run.positions.iter()) {
+                    // flip the y-up glyph outline and move it to the glyph origin
+                    let matrix = CGAffineTransform::new(
+                        1.0,
+                        0.0,
+                        0.0,
+                        -1.0,
+                        origin.x + pos.x,
+                        origin.y - pos.y,
+                    );
+                    if let Ok(glyph_path) = font.create_path_for_glyph(*glyph, &matrix) {
+                        ct_helpers::append_cg_path(&mut path, &glyph_path);
+                    }
+                }
+            }
+        }
+        path.apply_affine(self.writing_transform());
+        path
     }
 }
 
@@ -737,6 +1135,8 @@ impl CoreGraphicsTextLayout {
         width_constraint: f64,
         default_baseline: f64,
         default_line_height: f64,
+        writing_mode: WritingMode,
+        trailing_alignment: TextAlignment,
     ) -> Self {
         let framesetter = Framesetter::new(&attr_string);
 
@@ -756,6 +1156,9 @@ impl CoreGraphicsTextLayout {
             line_metrics: Rc::new([]),
             x_offsets: Rc::new([]),
             trailing_ws_width: 0.0,
+            writing_mode,
+            trailing_alignment,
+            backgrounds: Rc::new([]),
         };
         layout.update_width(width_constraint);
         layout
@@ -787,6 +1190,7 @@ impl CoreGraphicsTextLayout {
             &self.text,
             self.default_line_height,
             self.default_baseline,
+            self.trailing_alignment,
         );
         self.line_metrics = layout_metrics.line_metrics.into();
         self.x_offsets = layout_metrics.x_offsets.into();
@@ -833,6 +1237,20 @@ impl CoreGraphicsTextLayout {
         }
     }
 
+    /// The size of the layout before it is rotated for vertical writing modes.
+    fn horizontal_size(&self) -> Size {
+        Size::new(
+            self.frame_size.width,
+            self.frame_size.height + self.bonus_height,
+        )
+    }
+
+    /// The transform from the horizontal space lines are laid out in, to
+    /// the space of the layout.
+    pub(crate) fn writing_transform(&self) -> Affine {
+        self.writing_mode.transform(self.horizontal_size())
+    }
+
     #[inline]
     fn unwrap_frame(&self) -> &Frame {
         self.frame.as_ref().expect("always inited in ::new")
@@ -883,6 +1301,7 @@ fn build_line_metrics(
     text: &str,
     default_line_height: f64,
     default_baseline: f64,
+    trailing_alignment: TextAlignment,
 ) -> LayoutMetrics {
     let line_origins = frame.get_line_origins(CFRange::init(0, 0));
     assert_eq!(frame.lines().len(), line_origins.len());
@@ -981,7 +1400,14 @@ fn build_line_metrics(
                 }
             })
             .unwrap();
-        let x_offset = x_offsets.last().copied().unwrap();
+        // the line is empty, so it sits where its alignment puts a caret.
+        let rtl = util::first_strong_rtl(text);
+        let x_offset = match trailing_alignment {
+            TextAlignment::Center => max_width / 2.0,
+            TextAlignment::End if !rtl => max_width,
+            TextAlignment::Start | TextAlignment::Justified if rtl => max_width,
+            _ => 0.0,
+        };
         metrics.push(newline_eof);
         x_offsets.push(x_offset);
     }
@@ -1091,6 +1517,43 @@ mod tests {
         assert!(layout.line_metric(4).is_none());
     }
 
+    #[test]
+    fn paragraph_alignments() {
+        let text = "title\na much longer paragraph\nend\n";
+        let a_font = FontFamily::new_unchecked("Helvetica");
+        let layout = CoreGraphicsText::new_with_unique_state()
+            .new_text_layout(text)
+            .font(a_font, 16.0)
+            .paragraph_alignment(..6, TextAlignment::Center)
+            .paragraph_alignment(31.., TextAlignment::End)
+            .build()
+            .unwrap();
+        let width = layout.frame_size.width;
+
+        assert_eq!(layout.line_count(), 4);
+        assert_eq!(layout.x_offsets[1], 0.0);
+        let title = layout.line_metric(0).unwrap();
+        let title_end = layout.hit_test_text_position(title.end_offset - 1).point.x;
+        let title_width = title_end - layout.x_offsets[0];
+        assert_close!(layout.x_offsets[0], (width - title_width) / 2.0, 1.0);
+        assert!(layout.x_offsets[2] > layout.x_offsets[0]);
+
+        // the empty line after the trailing newline follows the last paragraph
+        let last = layout.line_metric(3).unwrap();
+        assert_eq!(last.range(), text.len()..text.len());
+        assert_eq!(layout.x_offsets[3], width);
+        assert_eq!(last.y_offset, layout.frame_size.height);
+    }
+
+    #[test]
+    fn split_paragraphs() {
+        let text = "a\nb\r\nc\rd\u{2029}e";
+        let split = paragraphs(text).map(|p| &text[p]).collect::<Vec<_>>();
+        assert_eq!(split, ["a\n", "b\r\n", "c\r", "d\u{2029}", "e"]);
+        assert_eq!(paragraphs("").count(), 0);
+        assert_eq!(paragraphs("a\n").count(), 1);
+    }
+
     // test that at least we're landing on the correct line
     #[test]
     fn basic_hit_testing() {
@@ -5,7 +5,10 @@ use winapi::um::d2d1::{
     D2D1_DASH_STYLE_CUSTOM, D2D1_DASH_STYLE_SOLID, D2D1_ELLIPSE, D2D1_GRADIENT_STOP,
     D2D1_LINE_JOIN, D2D1_LINE_JOIN_BEVEL, D2D1_LINE_JOIN_MITER, D2D1_LINE_JOIN_ROUND,
     D2D1_MATRIX_3X2_F, D2D1_POINT_2F, D2D1_POINT_2U, D2D1_RECT_F, D2D1_RECT_U, D2D1_ROUNDED_RECT,
-    D2D1_STROKE_STYLE_PROPERTIES,
+};
+use winapi::um::d2d1_1::{
+    D2D1_STROKE_STYLE_PROPERTIES1, D2D1_STROKE_TRANSFORM_TYPE_FIXED,
+    D2D1_STROKE_TRANSFORM_TYPE_NORMAL,
 };
 
 use piet::kurbo::{Affine, Circle, Point, Rect, Vec2};
@@ -210,14 +213,20 @@ pub(crate) fn convert_stroke_style(
     let miter_limit = stroke_style
         .miter_limit()
         .unwrap_or(LineJoin::DEFAULT_MITER_LIMIT) as f32;
-    let props = D2D1_STROKE_STYLE_PROPERTIES {
+    let transform_type = if stroke_style.scale {
+        D2D1_STROKE_TRANSFORM_TYPE_NORMAL
+    } else {
+        D2D1_STROKE_TRANSFORM_TYPE_FIXED
+    };
+    let props = D2D1_STROKE_STYLE_PROPERTIES1 {
         startCap: cap,
         endCap: cap,
-        dashCap: D2D1_CAP_STYLE_FLAT,
+        dashCap: convert_line_cap(stroke_style.effective_dash_cap()),
         lineJoin: join,
         miterLimit: miter_limit,
         dashStyle: dash_style,
         dashOffset: dash_off,
+        transformType: transform_type,
     };
     Ok(factory.create_stroke_style(&props, dashes.as_deref())?)
 }
@@ -6,26 +6,30 @@
 // TODO: get rid of this when we actually do use everything
 #![allow(unused)]
 
+use std::cell::RefCell;
 use std::ffi::c_void;
 use std::fmt::{Debug, Display, Formatter};
 use std::marker::PhantomData;
 use std::ops::Deref;
 use std::ptr::{null, null_mut};
 
-use piet::kurbo::{Circle, Line, Rect, RoundedRect};
+use piet::kurbo::{Affine, Circle, Line, Rect, RoundedRect, Vec2};
+use piet::Color;
 
 use wio::com::ComPtr;
 
 use winapi::shared::dxgi::{IDXGIDevice, IDXGISurface};
-use winapi::shared::dxgiformat::DXGI_FORMAT_R8G8B8A8_UNORM;
+use winapi::shared::dxgiformat::{DXGI_FORMAT_R8G8B8A8_UNORM, DXGI_FORMAT_UNKNOWN};
+use winapi::shared::guiddef::GUID;
 use winapi::shared::minwindef::TRUE;
 use winapi::shared::winerror::{HRESULT, SUCCEEDED};
 use winapi::um::d2d1::{
-    D2D1CreateFactory, ID2D1Bitmap, ID2D1BitmapRenderTarget, ID2D1Brush, ID2D1EllipseGeometry,
-    ID2D1Geometry, ID2D1GeometrySink, ID2D1GradientStopCollection, ID2D1Image, ID2D1Layer,
-    ID2D1PathGeometry, ID2D1RectangleGeometry, ID2D1RenderTarget, ID2D1RoundedRectangleGeometry,
-    ID2D1SolidColorBrush, ID2D1StrokeStyle, D2D1_ANTIALIAS_MODE_PER_PRIMITIVE, D2D1_BEZIER_SEGMENT,
-    D2D1_BITMAP_INTERPOLATION_MODE, D2D1_BRUSH_PROPERTIES, D2D1_COLOR_F,
+    D2D1CreateFactory, ID2D1Bitmap, ID2D1BitmapBrush, ID2D1BitmapRenderTarget, ID2D1Brush,
+    ID2D1EllipseGeometry, ID2D1Geometry, ID2D1GeometrySink, ID2D1GradientStopCollection,
+    ID2D1Image, ID2D1Layer, ID2D1PathGeometry, ID2D1RectangleGeometry, ID2D1RenderTarget,
+    ID2D1RoundedRectangleGeometry, ID2D1SolidColorBrush, ID2D1StrokeStyle,
+    D2D1_ANTIALIAS_MODE_PER_PRIMITIVE, D2D1_BEZIER_SEGMENT, D2D1_BITMAP_BRUSH_PROPERTIES,
+    D2D1_BITMAP_INTERPOLATION_MODE_LINEAR, D2D1_BRUSH_PROPERTIES, D2D1_COLOR_F,
     D2D1_COMPATIBLE_RENDER_TARGET_OPTIONS_NONE, D2D1_DEBUG_LEVEL_NONE, D2D1_DEBUG_LEVEL_WARNING,
     D2D1_DRAW_TEXT_OPTIONS, D2D1_EXTEND_MODE_CLAMP, D2D1_FACTORY_OPTIONS,
     D2D1_FACTORY_TYPE_MULTI_THREADED, D2D1_FIGURE_BEGIN_FILLED, D2D1_FIGURE_BEGIN_HOLLOW,
@@ -33,21 +37,31 @@ use winapi::um::d2d1::{
     D2D1_GAMMA_2_2, D2D1_GRADIENT_STOP, D2D1_LAYER_OPTIONS_NONE, D2D1_LAYER_PARAMETERS,
     D2D1_LINEAR_GRADIENT_BRUSH_PROPERTIES, D2D1_MATRIX_3X2_F, D2D1_POINT_2F, D2D1_POINT_2U,
     D2D1_QUADRATIC_BEZIER_SEGMENT, D2D1_RADIAL_GRADIENT_BRUSH_PROPERTIES, D2D1_RECT_F, D2D1_RECT_U,
-    D2D1_SIZE_F, D2D1_SIZE_U, D2D1_STROKE_STYLE_PROPERTIES,
+    D2D1_SIZE_F, D2D1_SIZE_U, D2D1_TEXT_ANTIALIAS_MODE,
 };
 use winapi::um::d2d1_1::{
     ID2D1Bitmap1, ID2D1Device, ID2D1DeviceContext, ID2D1Effect, ID2D1Factory1,
     D2D1_BITMAP_OPTIONS_NONE, D2D1_BITMAP_OPTIONS_TARGET, D2D1_BITMAP_PROPERTIES1,
     D2D1_COMPOSITE_MODE, D2D1_DEVICE_CONTEXT_OPTIONS_NONE, D2D1_INTERPOLATION_MODE,
-    D2D1_PROPERTY_TYPE_FLOAT,
+    D2D1_PROPERTY_TYPE, D2D1_PROPERTY_TYPE_FLOAT, D2D1_PROPERTY_TYPE_MATRIX_3X2,
+    D2D1_PROPERTY_TYPE_MATRIX_5X4, D2D1_PROPERTY_TYPE_VECTOR4, D2D1_STROKE_STYLE_PROPERTIES1,
 };
 use winapi::um::d2d1_1::{D2D1_PRIMITIVE_BLEND_COPY, D2D1_PRIMITIVE_BLEND_SOURCE_OVER};
-use winapi::um::d2d1effects::{CLSID_D2D1GaussianBlur, D2D1_GAUSSIANBLUR_PROP_STANDARD_DEVIATION};
+use winapi::um::d2d1_2::ID2D1DeviceContext1;
+use winapi::um::d2d1effects::{
+    CLSID_D2D12DAffineTransform, CLSID_D2D1ColorMatrix, CLSID_D2D1Composite,
+    CLSID_D2D1GaussianBlur, CLSID_D2D1Shadow, D2D1_2DAFFINETRANSFORM_PROP_TRANSFORM_MATRIX,
+    D2D1_COLORMATRIX_PROP_COLOR_MATRIX, D2D1_GAUSSIANBLUR_PROP_STANDARD_DEVIATION,
+    D2D1_SHADOW_PROP_BLUR_STANDARD_DEVIATION, D2D1_SHADOW_PROP_COLOR,
+};
 use winapi::um::dcommon::{D2D1_ALPHA_MODE, D2D1_ALPHA_MODE_PREMULTIPLIED, D2D1_PIXEL_FORMAT};
 use winapi::Interface;
 
-use crate::conv::{circle_to_d2d, rect_to_rectf, rounded_rect_to_d2d, to_point2f};
+use crate::conv::{
+    affine_to_matrix3x2f, circle_to_d2d, rect_to_rectf, rounded_rect_to_d2d, to_point2f,
+};
 use crate::dwrite::TextLayout;
+use crate::GeometryCache;
 
 pub enum FillRule {
     EvenOdd,
@@ -62,7 +76,7 @@ pub enum Error {
 ///
 /// This struct is public only to use for system integration in piet_common and druid-shell. It is not intended
 /// that end-users directly use this struct.
-pub struct D2DFactory(ComPtr<ID2D1Factory1>);
+pub struct D2DFactory(ComPtr<ID2D1Factory1>, pub(crate) RefCell<GeometryCache>);
 
 /// A Direct2D device.
 pub struct D2DDevice(ComPtr<ID2D1Device>);
@@ -91,6 +105,7 @@ pub struct RoundedRectangleGeometry(ComPtr<ID2D1RoundedRectangleGeometry>);
 
 pub struct EllipseGeometry(ComPtr<ID2D1EllipseGeometry>);
 
+#[derive(Clone)]
 pub struct Geometry(ComPtr<ID2D1Geometry>);
 
 pub struct GeometrySink<'a> {
@@ -118,6 +133,8 @@ pub struct Brush(ComPtr<ID2D1Brush>);
 pub struct Bitmap {
     inner: ComPtr<ID2D1Bitmap1>,
     pub(crate) empty_image: bool,
+    /// The area of the bitmap that is the image, if it is a sub-image.
+    pub(crate) view: Option<Rect>,
 }
 
 #[derive(Debug)]
@@ -238,7 +255,7 @@ impl D2DFactory {
                 },
                 &mut ptr as *mut _ as *mut _,
             );
-            wrap(hr, ptr, D2DFactory)
+            wrap(hr, ptr, |ptr| D2DFactory(ptr, Default::default()))
         }
     }
 
@@ -307,20 +324,20 @@ impl D2DFactory {
 
     pub fn create_stroke_style(
         &self,
-        props: &D2D1_STROKE_STYLE_PROPERTIES,
+        props: &D2D1_STROKE_STYLE_PROPERTIES1,
         dashes: Option<&[f32]>,
     ) -> Result<StrokeStyle, Error> {
         unsafe {
             let mut ptr = null_mut();
             let dashes_len = dashes.map(|d| d.len()).unwrap_or(0);
             assert!(dashes_len <= 0xffff_ffff);
-            let hr = self.0.deref().deref().CreateStrokeStyle(
+            let hr = self.0.deref().CreateStrokeStyle(
                 props,
                 dashes.map(|d| d.as_ptr()).unwrap_or(null()),
                 dashes_len as u32,
                 &mut ptr,
             );
-            wrap(hr, ptr, StrokeStyle)
+            wrap(hr, ptr, |p| StrokeStyle(p.up()))
         }
     }
 }
@@ -377,7 +394,8 @@ impl DeviceContext {
     /// Most often, this bitmap will be used to set the target of a
     /// DeviceContext.
     ///
-    /// Assumes RGBA8 format and premultiplied alpha.
+    /// The bitmap has the format of the surface, such as RGBA8, or BGRA8
+    /// for most swap chains, and premultiplied alpha.
     ///
     /// The `unsafe` might be conservative, but we assume the `dxgi`
     /// argument is in good shape to be a target.
@@ -392,7 +410,7 @@ impl DeviceContext {
         let mut ptr = null_mut();
         let props = D2D1_BITMAP_PROPERTIES1 {
             pixelFormat: D2D1_PIXEL_FORMAT {
-                format: DXGI_FORMAT_R8G8B8A8_UNORM,
+                format: DXGI_FORMAT_UNKNOWN,
                 alphaMode: D2D1_ALPHA_MODE_PREMULTIPLIED,
             },
             dpiX: 96.0 * dpi_scale,
@@ -408,6 +426,7 @@ impl DeviceContext {
             // I'm pretty sure an empty dxgi surface will be invalid, so we can be sure the image
             // is not empty.
             empty_image: false,
+            view: None,
         })
     }
 
@@ -419,6 +438,20 @@ impl DeviceContext {
         unsafe { self.0.SetTarget(target.inner.as_raw() as *mut ID2D1Image) }
     }
 
+    /// Whether the device context can draw color fonts, such as emoji, in
+    /// color, which needs Windows 8.1 or later.
+    pub fn supports_color_fonts(&self) -> bool {
+        self.0.cast::<ID2D1DeviceContext1>().is_ok()
+    }
+
+    /// Clear the target of the device context, so that it no longer holds
+    /// a reference to it.
+    ///
+    /// This is needed before the buffers of a swap chain can be resized.
+    pub fn clear_target(&mut self) {
+        unsafe { self.0.SetTarget(null_mut()) }
+    }
+
     /// Set the dpi scale.
     ///
     /// Mostly useful when rendering into bitmaps.
@@ -493,6 +526,12 @@ impl DeviceContext {
         }
     }
 
+    pub(crate) fn set_text_antialias_mode(&mut self, mode: D2D1_TEXT_ANTIALIAS_MODE) {
+        unsafe {
+            self.0.SetTextAntialiasMode(mode);
+        }
+    }
+
     pub(crate) fn set_transform_identity(&mut self) {
         unsafe {
             self.0.SetTransform(&IDENTITY_MATRIX_3X2_F);
@@ -641,7 +680,15 @@ impl DeviceContext {
     }
 
     // Should be &mut layer?
-    pub(crate) fn push_layer_mask(&mut self, mask: &Geometry, layer: &Layer) {
+    /// Push a layer that everything drawn until it is popped is clipped to
+    /// `mask`, and multiplied by the alpha of `opacity_brush`, if there is
+    /// one.
+    pub(crate) fn push_layer_mask(
+        &mut self,
+        mask: &Geometry,
+        opacity_brush: Option<&Brush>,
+        layer: &Layer,
+    ) {
         unsafe {
             let params = D2D1_LAYER_PARAMETERS {
                 contentBounds: D2D1_RECT_F {
@@ -654,6 +701,28 @@ impl DeviceContext {
                 maskAntialiasMode: D2D1_ANTIALIAS_MODE_PER_PRIMITIVE,
                 maskTransform: IDENTITY_MATRIX_3X2_F,
                 opacity: 1.0,
+                opacityBrush: opacity_brush.map_or(null_mut(), Brush::as_raw),
+                layerOptions: D2D1_LAYER_OPTIONS_NONE,
+            };
+            self.0.deref().deref().PushLayer(&params, layer.0.as_raw());
+        }
+    }
+
+    /// Push a layer that everything drawn until it is popped is composited
+    /// through, with `opacity`.
+    pub(crate) fn push_layer_opacity(&mut self, layer: &Layer, opacity: f32) {
+        unsafe {
+            let params = D2D1_LAYER_PARAMETERS {
+                contentBounds: D2D1_RECT_F {
+                    left: std::f32::NEG_INFINITY,
+                    top: std::f32::NEG_INFINITY,
+                    right: std::f32::INFINITY,
+                    bottom: std::f32::INFINITY,
+                },
+                geometricMask: null_mut(),
+                maskAntialiasMode: D2D1_ANTIALIAS_MODE_PER_PRIMITIVE,
+                maskTransform: IDENTITY_MATRIX_3X2_F,
+                opacity,
                 opacityBrush: null_mut(),
                 layerOptions: D2D1_LAYER_OPTIONS_NONE,
             };
@@ -736,6 +805,38 @@ impl DeviceContext {
         }
     }
 
+    /// Create a brush that paints a bitmap, with `transform` mapping the
+    /// bitmap to the space that the brush is used in.
+    ///
+    /// Outside of the bitmap, the brush repeats the pixels at its edges.
+    pub(crate) fn create_bitmap_brush(
+        &mut self,
+        bitmap: &Bitmap,
+        transform: &D2D1_MATRIX_3X2_F,
+    ) -> Result<Brush, Error> {
+        let bitmap_props = D2D1_BITMAP_BRUSH_PROPERTIES {
+            extendModeX: D2D1_EXTEND_MODE_CLAMP,
+            extendModeY: D2D1_EXTEND_MODE_CLAMP,
+            interpolationMode: D2D1_BITMAP_INTERPOLATION_MODE_LINEAR,
+        };
+        let brush_props = D2D1_BRUSH_PROPERTIES {
+            opacity: 1.0,
+            transform: *transform,
+        };
+        unsafe {
+            let mut ptr: *mut ID2D1BitmapBrush = null_mut();
+            // As with the gradient stops, the DeviceContext has a method of the
+            // same name, which takes different properties.
+            let hr = self.0.deref().deref().CreateBitmapBrush(
+                bitmap.inner.as_raw() as *mut ID2D1Bitmap,
+                &bitmap_props,
+                &brush_props,
+                &mut ptr,
+            );
+            wrap(hr, ptr, |p| Brush(p.up()))
+        }
+    }
+
     // Buf is always interpreted as RGBA32 premultiplied.
     pub(crate) fn create_bitmap(
         &mut self,
@@ -776,6 +877,7 @@ impl DeviceContext {
             wrap(hr, ptr, |ptr| Bitmap {
                 inner: ptr,
                 empty_image: false,
+                view: None,
             })
         }
     }
@@ -813,6 +915,7 @@ impl DeviceContext {
             wrap(hr, ptr, |ptr| Bitmap {
                 inner: ptr,
                 empty_image: false,
+                view: None,
             })
         }
     }
@@ -846,6 +949,7 @@ impl DeviceContext {
             wrap(hr, ptr, |ptr| Bitmap {
                 inner: ptr,
                 empty_image: true,
+                view: None,
             })
         }
     }
@@ -869,18 +973,19 @@ impl DeviceContext {
         bitmap: &Bitmap,
         dst_rect: &D2D1_RECT_F,
         opacity: f32,
-        interp_mode: D2D1_BITMAP_INTERPOLATION_MODE,
+        interp_mode: D2D1_INTERPOLATION_MODE,
         src_rect: Option<&D2D1_RECT_F>,
     ) {
         unsafe {
-            // derefs are so we get RenderTarget method rather than DeviceContext method.
-            // pointer casts are partly to undo that :)
-            self.0.deref().deref().DrawBitmap(
+            // The DeviceContext method, unlike the RenderTarget one, has the cubic
+            // interpolation modes.
+            self.0.DrawBitmap(
                 bitmap.inner.as_raw() as *mut ID2D1Bitmap,
                 dst_rect,
                 opacity,
                 interp_mode,
                 src_rect.map(|r| r as *const _).unwrap_or(null()),
+                null(),
             );
         }
     }
@@ -906,6 +1011,75 @@ impl DeviceContext {
         }
     }
 
+    fn create_effect(&mut self, clsid: &GUID) -> Result<Effect, Error> {
+        unsafe {
+            let mut ptr = null_mut();
+            let hr = self.0.deref().CreateEffect(clsid, &mut ptr);
+            wrap(hr, ptr, Effect)
+        }
+    }
+
+    /// An effect that multiplies colors by a matrix, laid out like the one
+    /// of [`piet::ImageFilter::ColorMatrix`].
+    pub(crate) fn create_color_matrix_effect(
+        &mut self,
+        matrix: &[f64; 20],
+    ) -> Result<Effect, Error> {
+        let effect = self.create_effect(&CLSID_D2D1ColorMatrix)?;
+        // D2D's matrix has a row for each channel of the input, and a column
+        // for each channel of the output.
+        let mut value = [0.0f32; 20];
+        for (output, row) in matrix.chunks_exact(5).enumerate() {
+            for (input, &x) in row.iter().enumerate() {
+                value[input * 4 + output] = x as f32;
+            }
+        }
+        effect.set_value(
+            D2D1_COLORMATRIX_PROP_COLOR_MATRIX,
+            D2D1_PROPERTY_TYPE_MATRIX_5X4,
+            &value,
+        )?;
+        Ok(effect)
+    }
+
+    /// An effect that blurs the alpha of its input, in `color`.
+    pub(crate) fn create_shadow_effect(
+        &mut self,
+        radius: f64,
+        color: Color,
+    ) -> Result<Effect, Error> {
+        let effect = self.create_effect(&CLSID_D2D1Shadow)?;
+        effect.set_value(
+            D2D1_SHADOW_PROP_BLUR_STANDARD_DEVIATION,
+            D2D1_PROPERTY_TYPE_FLOAT,
+            &(radius as f32),
+        )?;
+        let (r, g, b, a) = color.as_rgba();
+        effect.set_value(
+            D2D1_SHADOW_PROP_COLOR,
+            D2D1_PROPERTY_TYPE_VECTOR4,
+            &[r as f32, g as f32, b as f32, a as f32],
+        )?;
+        Ok(effect)
+    }
+
+    /// An effect that transforms its input.
+    pub(crate) fn create_transform_effect(&mut self, transform: Affine) -> Result<Effect, Error> {
+        let effect = self.create_effect(&CLSID_D2D12DAffineTransform)?;
+        let matrix = affine_to_matrix3x2f(transform);
+        effect.set_value(
+            D2D1_2DAFFINETRANSFORM_PROP_TRANSFORM_MATRIX,
+            D2D1_PROPERTY_TYPE_MATRIX_3X2,
+            &matrix,
+        )?;
+        Ok(effect)
+    }
+
+    /// An effect that draws its second input over its first.
+    pub(crate) fn create_composite_effect(&mut self) -> Result<Effect, Error> {
+        self.create_effect(&CLSID_D2D1Composite)
+    }
+
     // This is basically equivalent to an override of ID2D1DeviceContext::DrawImage method
     // https://docs.microsoft.com/en-us/windows/win32/api/d2d1_1/nf-d2d1_1-id2d1devicecontext-drawimage(id2d1effect_constd2d1_point_2f_constd2d1_rect_f_d2d1_interpolation_mode_d2d1_composite_mode)
     pub(crate) fn draw_image_effect(
@@ -1048,6 +1222,23 @@ impl Bitmap {
         unsafe { self.inner.GetSize() }
     }
 
+    /// The area of the bitmap that is the image.
+    pub(crate) fn view_rect(&self) -> Rect {
+        self.view.unwrap_or_else(|| {
+            let size = self.get_size();
+            Rect::new(0.0, 0.0, size.width.into(), size.height.into())
+        })
+    }
+
+    /// The offset of the image in the bitmap.
+    pub(crate) fn view_origin(&self) -> Vec2 {
+        self.view.unwrap_or(Rect::ZERO).origin().to_vec2()
+    }
+
+    pub(crate) fn as_image(&self) -> &ID2D1Image {
+        &self.inner
+    }
+
     pub(crate) fn copy_from_render_target(
         &mut self,
         dest_point: D2D1_POINT_2U,
@@ -1072,6 +1263,28 @@ impl Effect {
             self.0.SetInput(index, input, TRUE);
         }
     }
+
+    /// Set the effect's input to the output of another effect.
+    pub(crate) fn set_input_effect(&self, index: u32, input: &Effect) {
+        unsafe {
+            let mut ptr = null_mut();
+            input.0.GetOutput(&mut ptr);
+            let output = ComPtr::from_raw(ptr);
+            self.set_input(index, &output);
+        }
+    }
+
+    fn set_value<T>(&self, index: u32, ty: D2D1_PROPERTY_TYPE, value: &T) -> Result<(), Error> {
+        unsafe {
+            let hr = self.0.SetValue(
+                index,
+                ty,
+                value as *const T as *const _,
+                std::mem::size_of::<T>() as u32,
+            );
+            wrap_unit(hr)
+        }
+    }
 }
 
 impl BitmapRenderTarget {
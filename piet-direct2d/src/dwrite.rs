@@ -11,28 +11,39 @@ use std::ptr::null_mut;
 use std::sync::Arc;
 
 use dwrote::FontCollection as DWFontCollection;
-use winapi::shared::minwindef::{FALSE, TRUE};
+use winapi::ctypes::c_void;
+use winapi::shared::guiddef::{IsEqualIID, REFIID};
+use winapi::shared::minwindef::{BOOL, FALSE, FLOAT, TRUE, UINT32, ULONG};
 use winapi::shared::ntdef::LOCALE_NAME_MAX_LENGTH;
-use winapi::shared::winerror::{HRESULT, SUCCEEDED, S_OK};
+use winapi::shared::winerror::{E_NOINTERFACE, HRESULT, SUCCEEDED, S_OK};
+use winapi::um::d2d1::{
+    ID2D1SimplifiedGeometrySink, ID2D1SimplifiedGeometrySinkVtbl, D2D1_BEZIER_SEGMENT,
+    D2D1_FIGURE_BEGIN, D2D1_FIGURE_END, D2D1_FIGURE_END_CLOSED, D2D1_FILL_MODE, D2D1_PATH_SEGMENT,
+    D2D1_POINT_2F,
+};
 use winapi::um::dwrite::{
-    DWriteCreateFactory, IDWriteFactory, IDWriteFontCollection, IDWriteFontFamily,
-    IDWriteLocalizedStrings, IDWriteTextFormat, IDWriteTextLayout, DWRITE_FACTORY_TYPE_SHARED,
+    DWriteCreateFactory, IDWriteFactory, IDWriteFontCollection, IDWriteFontFace, IDWriteFontFamily,
+    IDWriteInlineObject, IDWriteLocalizedStrings, IDWritePixelSnapping, IDWritePixelSnappingVtbl,
+    IDWriteTextFormat, IDWriteTextLayout, IDWriteTextRenderer, IDWriteTextRendererVtbl,
+    IDWriteTypography, DWRITE_FACTORY_TYPE_SHARED, DWRITE_FONT_FEATURE, DWRITE_FONT_STRETCH,
     DWRITE_FONT_STRETCH_NORMAL, DWRITE_FONT_STYLE, DWRITE_FONT_STYLE_ITALIC,
-    DWRITE_FONT_STYLE_NORMAL, DWRITE_FONT_WEIGHT, DWRITE_FONT_WEIGHT_NORMAL,
-    DWRITE_HIT_TEST_METRICS, DWRITE_LINE_METRICS, DWRITE_OVERHANG_METRICS,
-    DWRITE_READING_DIRECTION_RIGHT_TO_LEFT, DWRITE_TEXT_ALIGNMENT_CENTER,
+    DWRITE_FONT_STYLE_NORMAL, DWRITE_FONT_WEIGHT, DWRITE_FONT_WEIGHT_NORMAL, DWRITE_GLYPH_OFFSET,
+    DWRITE_GLYPH_RUN, DWRITE_GLYPH_RUN_DESCRIPTION, DWRITE_HIT_TEST_METRICS, DWRITE_LINE_METRICS,
+    DWRITE_MATRIX, DWRITE_MEASURING_MODE, DWRITE_OVERHANG_METRICS,
+    DWRITE_READING_DIRECTION_RIGHT_TO_LEFT, DWRITE_STRIKETHROUGH, DWRITE_TEXT_ALIGNMENT_CENTER,
     DWRITE_TEXT_ALIGNMENT_JUSTIFIED, DWRITE_TEXT_ALIGNMENT_LEADING, DWRITE_TEXT_ALIGNMENT_TRAILING,
-    DWRITE_TEXT_METRICS, DWRITE_TEXT_RANGE,
+    DWRITE_TEXT_METRICS, DWRITE_TEXT_RANGE, DWRITE_UNDERLINE,
 };
-use winapi::um::unknwnbase::IUnknown;
+use winapi::um::dwrite_1::IDWriteTextLayout1;
+use winapi::um::unknwnbase::{IUnknown, IUnknownVtbl};
 use winapi::um::winnls::GetUserDefaultLocaleName;
 use winapi::Interface;
 
 use wio::com::ComPtr;
 use wio::wide::{FromWide, ToWide};
 
-use piet::kurbo::Insets;
-use piet::{FontFamily as PietFontFamily, FontStyle, FontWeight, TextAlignment};
+use piet::kurbo::{Affine, BezPath, Insets, Point};
+use piet::{FontFamily as PietFontFamily, FontFeature, FontStyle, FontWeight, TextAlignment};
 
 use crate::Brush;
 
@@ -72,6 +83,9 @@ pub struct FontCollection(ComPtr<IDWriteFontCollection>);
 #[derive(Clone)]
 pub struct TextLayout(ComPtr<IDWriteTextLayout>);
 
+/// A set of OpenType features.
+pub struct Typography(ComPtr<IDWriteTypography>);
+
 /// A range in a windows string, represented as a start position and a length.
 #[derive(Debug, Clone, Copy)]
 pub struct Utf16Range {
@@ -142,6 +156,29 @@ impl DwriteFactory {
         self.0.as_raw()
     }
 
+    /// Create a typography object with the given OpenType features.
+    pub(crate) fn create_typography(&self, features: &[FontFeature]) -> Result<Typography, Error> {
+        let typography = unsafe {
+            let mut ptr = null_mut();
+            let hr = self.0.CreateTypography(&mut ptr);
+            wrap(hr, ptr, Typography)?
+        };
+        for feature in features {
+            let feature = DWRITE_FONT_FEATURE {
+                // dwrite tags are little-endian
+                nameTag: u32::from_le_bytes(feature.tag),
+                parameter: feature.value,
+            };
+            unsafe {
+                let hr = typography.0.AddFontFeature(feature);
+                if !SUCCEEDED(hr) {
+                    return Err(hr.into());
+                }
+            }
+        }
+        Ok(typography)
+    }
+
     pub(crate) fn system_font_collection(&self) -> Result<FontCollection, Error> {
         unsafe {
             let mut ptr = null_mut();
@@ -341,6 +378,13 @@ impl TextLayout {
         }
     }
 
+    /// Set the distance between tab stops for this entire layout.
+    pub(crate) fn set_incremental_tab_stop(&mut self, width: f64) {
+        unsafe {
+            self.0.SetIncrementalTabStop(width as f32);
+        }
+    }
+
     /// Set the weight for a range of this layout. `start` and `len` are in utf16.
     pub(crate) fn set_weight(&mut self, range: Utf16Range, weight: FontWeight) {
         let weight = weight.to_raw() as DWRITE_FONT_WEIGHT;
@@ -372,6 +416,31 @@ impl TextLayout {
         }
     }
 
+    /// Set the font stretch for a range, from a width as a percentage of normal.
+    pub(crate) fn set_stretch(&mut self, range: Utf16Range, width: f64) {
+        // the css widths of DWRITE_FONT_STRETCH_ULTRA_CONDENSED..=ULTRA_EXPANDED
+        const WIDTHS: [f64; 9] = [50.0, 62.5, 75.0, 87.5, 100.0, 112.5, 125.0, 150.0, 200.0];
+        let nearest = WIDTHS
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| {
+                let (a, b) = ((*a - width).abs(), (*b - width).abs());
+                a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .map(|(i, _)| i)
+            .unwrap_or(4);
+        unsafe {
+            self.0
+                .SetFontStretch(nearest as DWRITE_FONT_STRETCH + 1, range.into());
+        }
+    }
+
+    pub(crate) fn set_typography(&mut self, range: Utf16Range, typography: &Typography) {
+        unsafe {
+            self.0.SetTypography(typography.0.as_raw(), range.into());
+        }
+    }
+
     pub(crate) fn set_underline(&mut self, range: Utf16Range, flag: bool) {
         let flag = if flag { TRUE } else { FALSE };
         unsafe {
@@ -386,6 +455,17 @@ impl TextLayout {
         }
     }
 
+    /// Set the extra space added after each character in a range.
+    ///
+    /// This requires `IDWriteTextLayout1`, and is a no-op where that is unavailable.
+    pub(crate) fn set_character_spacing(&mut self, range: Utf16Range, spacing: f32) {
+        if let Ok(layout) = self.0.cast::<IDWriteTextLayout1>() {
+            unsafe {
+                layout.SetCharacterSpacing(0.0, spacing, 0.0, range.into());
+            }
+        }
+    }
+
     pub(crate) fn set_size(&mut self, range: Utf16Range, size: f32) {
         unsafe {
             self.0.SetFontSize(size, range.into());
@@ -519,6 +599,339 @@ impl TextLayout {
             })
         }
     }
+
+    /// Return the regions covering a range of text, with one region for each
+    /// line and bidi run in the range. `start` and `len` are in utf16.
+    pub fn hit_test_text_range(&self, range: Utf16Range) -> Vec<HitTestMetrics> {
+        let (start, len) = (range.start as u32, range.len as u32);
+        unsafe {
+            // the first call just tells us how many regions there are
+            let mut count = 0;
+            let _ = self
+                .0
+                .HitTestTextRange(start, len, 0.0, 0.0, null_mut(), 0, &mut count);
+            let mut metrics: Vec<DWRITE_HIT_TEST_METRICS> = Vec::with_capacity(count as usize);
+            let hr = self.0.HitTestTextRange(
+                start,
+                len,
+                0.0,
+                0.0,
+                metrics.as_mut_ptr(),
+                count,
+                &mut count,
+            );
+            if !SUCCEEDED(hr) {
+                return Vec::new();
+            }
+            metrics.set_len(count as usize);
+            metrics.into_iter().map(Into::into).collect()
+        }
+    }
+
+    /// Return the glyph runs of this layout, as they would be drawn at the origin.
+    pub fn glyph_runs(&self) -> Vec<GlyphRun> {
+        let mut collector = GlyphRunCollector {
+            vtbl: &GLYPH_RUN_COLLECTOR_VTBL,
+            runs: Vec::new(),
+        };
+        unsafe {
+            let renderer = &mut collector as *mut GlyphRunCollector as *mut IDWriteTextRenderer;
+            if !SUCCEEDED(self.0.Draw(null_mut(), renderer, 0.0, 0.0)) {
+                return Vec::new();
+            }
+        }
+
+        let mut runs = collector.runs;
+        for run in &mut runs {
+            if let Some(family) = self.font_family_at(run.text_position) {
+                run.font_family = family;
+            }
+            unsafe {
+                let mut weight = DWRITE_FONT_WEIGHT_NORMAL;
+                if SUCCEEDED(
+                    self.0
+                        .GetFontWeight(run.text_position, &mut weight, null_mut()),
+                ) {
+                    run.weight = FontWeight::new(weight as u16);
+                }
+                let mut style = DWRITE_FONT_STYLE_NORMAL;
+                if SUCCEEDED(
+                    self.0
+                        .GetFontStyle(run.text_position, &mut style, null_mut()),
+                ) {
+                    run.style = match style {
+                        DWRITE_FONT_STYLE_NORMAL => FontStyle::Regular,
+                        _ => FontStyle::Italic,
+                    };
+                }
+            }
+        }
+        runs
+    }
+
+    /// The font family name set at the given utf-16 position.
+    fn font_family_at(&self, position: u32) -> Option<PietFontFamily> {
+        unsafe {
+            let mut len = 0;
+            if !SUCCEEDED(
+                self.0
+                    .GetFontFamilyNameLength(position, &mut len, null_mut()),
+            ) {
+                return None;
+            }
+            let mut buf = vec![0_u16; len as usize + 1];
+            let hr = self
+                .0
+                .GetFontFamilyName(position, buf.as_mut_ptr(), len + 1, null_mut());
+            if SUCCEEDED(hr) {
+                let name = String::from_utf16_lossy(&buf[..len as usize]);
+                Some(PietFontFamily::new_unchecked(name))
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// A run of glyphs reported by DirectWrite while drawing a [`TextLayout`].
+pub struct GlyphRun {
+    /// The utf-16 position of the start of this run's text.
+    pub text_position: u32,
+    /// The number of utf-16 code units in this run's text.
+    pub text_len: u32,
+    pub font_face: ComPtr<IDWriteFontFace>,
+    pub font_family: PietFontFamily,
+    pub weight: FontWeight,
+    pub style: FontStyle,
+    pub font_size: f32,
+    pub baseline_origin: (f32, f32),
+    pub is_rtl: bool,
+    pub glyphs: Vec<u16>,
+    pub advances: Vec<f32>,
+    /// The (advance, ascender) offset of each glyph.
+    pub offsets: Vec<(f32, f32)>,
+    /// For each utf-16 code unit of the run's text, the index of the first
+    /// glyph of its cluster.
+    pub cluster_map: Vec<u16>,
+}
+
+impl GlyphRun {
+    /// Return the outlines of the glyphs in this run, positioned at the run's baseline origin.
+    pub fn outline(&self) -> BezPath {
+        let offsets: Vec<DWRITE_GLYPH_OFFSET> = self
+            .offsets
+            .iter()
+            .map(|&(advance, ascender)| DWRITE_GLYPH_OFFSET {
+                advanceOffset: advance,
+                ascenderOffset: ascender,
+            })
+            .collect();
+        let mut sink = OutlineSink {
+            vtbl: &OUTLINE_SINK_VTBL,
+            path: BezPath::new(),
+        };
+        unsafe {
+            let sink_ptr = &mut sink as *mut OutlineSink as *mut ID2D1SimplifiedGeometrySink;
+            let hr = self.font_face.GetGlyphRunOutline(
+                self.font_size,
+                self.glyphs.as_ptr(),
+                self.advances.as_ptr(),
+                offsets.as_ptr(),
+                self.glyphs.len() as u32,
+                FALSE,
+                if self.is_rtl { TRUE } else { FALSE },
+                sink_ptr,
+            );
+            if !SUCCEEDED(hr) {
+                return BezPath::new();
+            }
+        }
+        let (x, y) = self.baseline_origin;
+        let mut path = sink.path;
+        path.apply_affine(Affine::translate((x as f64, y as f64)));
+        path
+    }
+}
+
+/// A minimal `IDWriteTextRenderer` that records the glyph runs it is asked to draw.
+///
+/// This lives on the stack for the duration of a single `Draw` call, so the
+/// refcounting methods are no-ops.
+#[repr(C)]
+struct GlyphRunCollector {
+    vtbl: *const IDWriteTextRendererVtbl,
+    runs: Vec<GlyphRun>,
+}
+
+static GLYPH_RUN_COLLECTOR_VTBL: IDWriteTextRendererVtbl = IDWriteTextRendererVtbl {
+    parent: IDWritePixelSnappingVtbl {
+        parent: IUnknownVtbl {
+            QueryInterface: collector_query_interface,
+            AddRef: collector_add_ref,
+            Release: collector_release,
+        },
+        IsPixelSnappingDisabled: collector_is_pixel_snapping_disabled,
+        GetCurrentTransform: collector_get_current_transform,
+        GetPixelsPerDip: collector_get_pixels_per_dip,
+    },
+    DrawGlyphRun: collector_draw_glyph_run,
+    DrawUnderline: collector_draw_underline,
+    DrawStrikethrough: collector_draw_strikethrough,
+    DrawInlineObject: collector_draw_inline_object,
+};
+
+unsafe extern "system" fn collector_query_interface(
+    this: *mut IUnknown,
+    riid: REFIID,
+    out: *mut *mut c_void,
+) -> HRESULT {
+    let riid = &*riid;
+    if IsEqualIID(riid, &IUnknown::uuidof())
+        || IsEqualIID(riid, &IDWritePixelSnapping::uuidof())
+        || IsEqualIID(riid, &IDWriteTextRenderer::uuidof())
+    {
+        *out = this as *mut c_void;
+        S_OK
+    } else {
+        *out = null_mut();
+        E_NOINTERFACE
+    }
+}
+
+unsafe extern "system" fn collector_add_ref(_this: *mut IUnknown) -> ULONG {
+    1
+}
+
+unsafe extern "system" fn collector_release(_this: *mut IUnknown) -> ULONG {
+    1
+}
+
+unsafe extern "system" fn collector_is_pixel_snapping_disabled(
+    _this: *mut IDWritePixelSnapping,
+    _context: *mut c_void,
+    is_disabled: *mut BOOL,
+) -> HRESULT {
+    // we want the ideal positions, not the ones snapped to some device
+    *is_disabled = TRUE;
+    S_OK
+}
+
+unsafe extern "system" fn collector_get_current_transform(
+    _this: *mut IDWritePixelSnapping,
+    _context: *mut c_void,
+    transform: *mut DWRITE_MATRIX,
+) -> HRESULT {
+    *transform = DWRITE_MATRIX {
+        m11: 1.0,
+        m12: 0.0,
+        m21: 0.0,
+        m22: 1.0,
+        dx: 0.0,
+        dy: 0.0,
+    };
+    S_OK
+}
+
+unsafe extern "system" fn collector_get_pixels_per_dip(
+    _this: *mut IDWritePixelSnapping,
+    _context: *mut c_void,
+    pixels_per_dip: *mut FLOAT,
+) -> HRESULT {
+    *pixels_per_dip = 1.0;
+    S_OK
+}
+
+unsafe extern "system" fn collector_draw_glyph_run(
+    this: *mut IDWriteTextRenderer,
+    _context: *mut c_void,
+    baseline_x: FLOAT,
+    baseline_y: FLOAT,
+    _measuring_mode: DWRITE_MEASURING_MODE,
+    glyph_run: *const DWRITE_GLYPH_RUN,
+    description: *const DWRITE_GLYPH_RUN_DESCRIPTION,
+    _effect: *mut IUnknown,
+) -> HRESULT {
+    let collector = &mut *(this as *mut GlyphRunCollector);
+    let run = &*glyph_run;
+    let description = &*description;
+    let count = run.glyphCount as usize;
+
+    let glyphs = std::slice::from_raw_parts(run.glyphIndices, count).to_owned();
+    let advances = if run.glyphAdvances.is_null() {
+        vec![0.0; count]
+    } else {
+        std::slice::from_raw_parts(run.glyphAdvances, count).to_owned()
+    };
+    let offsets = if run.glyphOffsets.is_null() {
+        vec![(0.0, 0.0); count]
+    } else {
+        std::slice::from_raw_parts(run.glyphOffsets, count)
+            .iter()
+            .map(|off| (off.advanceOffset, off.ascenderOffset))
+            .collect()
+    };
+    let cluster_map = if description.clusterMap.is_null() {
+        Vec::new()
+    } else {
+        std::slice::from_raw_parts(description.clusterMap, description.stringLength as usize)
+            .to_owned()
+    };
+
+    // the run only borrows the font face, so we take our own reference
+    (*run.fontFace).AddRef();
+    let font_face = ComPtr::from_raw(run.fontFace);
+
+    collector.runs.push(GlyphRun {
+        font_face,
+        text_position: description.textPosition,
+        text_len: description.stringLength,
+        font_family: PietFontFamily::SYSTEM_UI,
+        weight: FontWeight::REGULAR,
+        style: FontStyle::Regular,
+        font_size: run.fontEmSize,
+        baseline_origin: (baseline_x, baseline_y),
+        is_rtl: run.bidiLevel % 2 == 1,
+        glyphs,
+        advances,
+        offsets,
+        cluster_map,
+    });
+    S_OK
+}
+
+unsafe extern "system" fn collector_draw_underline(
+    _this: *mut IDWriteTextRenderer,
+    _context: *mut c_void,
+    _baseline_x: FLOAT,
+    _baseline_y: FLOAT,
+    _underline: *const DWRITE_UNDERLINE,
+    _effect: *mut IUnknown,
+) -> HRESULT {
+    S_OK
+}
+
+unsafe extern "system" fn collector_draw_strikethrough(
+    _this: *mut IDWriteTextRenderer,
+    _context: *mut c_void,
+    _baseline_x: FLOAT,
+    _baseline_y: FLOAT,
+    _strikethrough: *const DWRITE_STRIKETHROUGH,
+    _effect: *mut IUnknown,
+) -> HRESULT {
+    S_OK
+}
+
+unsafe extern "system" fn collector_draw_inline_object(
+    _this: *mut IDWriteTextRenderer,
+    _context: *mut c_void,
+    _baseline_x: FLOAT,
+    _baseline_y: FLOAT,
+    _inline_object: *mut IDWriteInlineObject,
+    _is_sideways: BOOL,
+    _is_rtl: BOOL,
+    _effect: *mut IUnknown,
+) -> HRESULT {
+    S_OK
 }
 
 #[derive(Copy, Clone)]
@@ -589,6 +1002,112 @@ impl From<DWRITE_HIT_TEST_METRICS> for HitTestMetrics {
     }
 }
 
+/// A minimal `ID2D1SimplifiedGeometrySink` that builds a `BezPath`.
+///
+/// Like `GlyphRunCollector`, this only lives for the duration of a single call.
+#[repr(C)]
+struct OutlineSink {
+    vtbl: *const ID2D1SimplifiedGeometrySinkVtbl,
+    path: BezPath,
+}
+
+static OUTLINE_SINK_VTBL: ID2D1SimplifiedGeometrySinkVtbl = ID2D1SimplifiedGeometrySinkVtbl {
+    parent: IUnknownVtbl {
+        QueryInterface: sink_query_interface,
+        AddRef: collector_add_ref,
+        Release: collector_release,
+    },
+    SetFillMode: sink_set_fill_mode,
+    SetSegmentFlags: sink_set_segment_flags,
+    BeginFigure: sink_begin_figure,
+    AddLines: sink_add_lines,
+    AddBeziers: sink_add_beziers,
+    EndFigure: sink_end_figure,
+    Close: sink_close,
+};
+
+fn point_from_d2d(point: D2D1_POINT_2F) -> Point {
+    Point::new(point.x as f64, point.y as f64)
+}
+
+unsafe extern "system" fn sink_query_interface(
+    this: *mut IUnknown,
+    riid: REFIID,
+    out: *mut *mut c_void,
+) -> HRESULT {
+    let riid = &*riid;
+    if IsEqualIID(riid, &IUnknown::uuidof())
+        || IsEqualIID(riid, &ID2D1SimplifiedGeometrySink::uuidof())
+    {
+        *out = this as *mut c_void;
+        S_OK
+    } else {
+        *out = null_mut();
+        E_NOINTERFACE
+    }
+}
+
+unsafe extern "system" fn sink_set_fill_mode(
+    _this: *mut ID2D1SimplifiedGeometrySink,
+    _fill_mode: D2D1_FILL_MODE,
+) {
+}
+
+unsafe extern "system" fn sink_set_segment_flags(
+    _this: *mut ID2D1SimplifiedGeometrySink,
+    _flags: D2D1_PATH_SEGMENT,
+) {
+}
+
+unsafe extern "system" fn sink_begin_figure(
+    this: *mut ID2D1SimplifiedGeometrySink,
+    start: D2D1_POINT_2F,
+    _figure_begin: D2D1_FIGURE_BEGIN,
+) {
+    let sink = &mut *(this as *mut OutlineSink);
+    sink.path.move_to(point_from_d2d(start));
+}
+
+unsafe extern "system" fn sink_add_lines(
+    this: *mut ID2D1SimplifiedGeometrySink,
+    points: *const D2D1_POINT_2F,
+    count: UINT32,
+) {
+    let sink = &mut *(this as *mut OutlineSink);
+    for point in std::slice::from_raw_parts(points, count as usize) {
+        sink.path.line_to(point_from_d2d(*point));
+    }
+}
+
+unsafe extern "system" fn sink_add_beziers(
+    this: *mut ID2D1SimplifiedGeometrySink,
+    beziers: *const D2D1_BEZIER_SEGMENT,
+    count: UINT32,
+) {
+    let sink = &mut *(this as *mut OutlineSink);
+    for bez in std::slice::from_raw_parts(beziers, count as usize) {
+        sink.path.curve_to(
+            point_from_d2d(bez.point1),
+            point_from_d2d(bez.point2),
+            point_from_d2d(bez.point3),
+        );
+    }
+}
+
+unsafe extern "system" fn sink_end_figure(
+    this: *mut ID2D1SimplifiedGeometrySink,
+    figure_end: D2D1_FIGURE_END,
+) {
+    if figure_end == D2D1_FIGURE_END_CLOSED {
+        let sink = &mut *(this as *mut OutlineSink);
+        sink.path.close_path();
+    }
+}
+
+unsafe extern "system" fn sink_close(_this: *mut ID2D1SimplifiedGeometrySink) -> HRESULT {
+    S_OK
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
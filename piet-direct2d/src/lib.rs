@@ -1,6 +1,4 @@
 #![cfg(windows)]
-// allows for nice formatting for e.g. new_buf[i * 4 + 0] = premul(buf[i * 4 + 0, a)
-#![allow(clippy::identity_op)]
 #![deny(clippy::trivially_copy_pass_by_ref)]
 
 //! The Direct2D backend for the Piet 2D graphics abstraction.
@@ -9,30 +7,38 @@ mod conv;
 pub mod d2d;
 pub mod d3d;
 pub mod dwrite;
+mod swap_chain;
 mod text;
 
 use std::borrow::Cow;
 use std::ops::Deref;
+use std::sync::Arc;
 
-use associative_cache::{AssociativeCache, Capacity1024, HashFourWay, RoundRobinReplacement};
+use associative_cache::{
+    AssociativeCache, Capacity1024, Capacity256, HashFourWay, RoundRobinReplacement,
+};
 
 use winapi::um::d2d1::{
-    D2D1_BITMAP_INTERPOLATION_MODE_LINEAR, D2D1_BITMAP_INTERPOLATION_MODE_NEAREST_NEIGHBOR,
     D2D1_LINEAR_GRADIENT_BRUSH_PROPERTIES, D2D1_RADIAL_GRADIENT_BRUSH_PROPERTIES,
 };
-use winapi::um::d2d1_1::{D2D1_COMPOSITE_MODE_SOURCE_OVER, D2D1_INTERPOLATION_MODE_LINEAR};
+use winapi::um::d2d1_1::{
+    D2D1_COMPOSITE_MODE_SOURCE_OVER, D2D1_INTERPOLATION_MODE_CUBIC,
+    D2D1_INTERPOLATION_MODE_HIGH_QUALITY_CUBIC, D2D1_INTERPOLATION_MODE_LINEAR,
+    D2D1_INTERPOLATION_MODE_NEAREST_NEIGHBOR,
+};
 use winapi::um::dcommon::{D2D1_ALPHA_MODE_IGNORE, D2D1_ALPHA_MODE_PREMULTIPLIED};
 
-use piet::kurbo::{Affine, PathEl, Point, Rect, Shape, Size};
+use piet::kurbo::{Affine, BezPath, PathEl, Point, Rect, Shape, Size, Vec2};
 
 use piet::{
-    Color, Error, FixedGradient, Image, ImageFormat, InterpolationMode, IntoBrush, RenderContext,
-    StrokeStyle,
+    Capabilities, Color, Error, FixedGradient, HitTester, Image, ImageFilter, ImageFormat,
+    InterpolationMode, IntoBrush, PreparedShape, RenderContext, StrokeStyle, TextShapingQuality,
 };
 
 use crate::d2d::{wrap_unit, Layer};
 pub use crate::d2d::{D2DDevice, D2DFactory, DeviceContext as D2DDeviceContext};
 pub use crate::dwrite::DwriteFactory;
+pub use crate::swap_chain::SwapChainTarget;
 pub use crate::text::{D2DLoadedFonts, D2DText, D2DTextLayout, D2DTextLayoutBuilder};
 
 use crate::conv::{
@@ -49,20 +55,66 @@ pub struct D2DRenderContext<'a> {
     /// The context state stack. There is always at least one, until finishing.
     ctx_stack: Vec<CtxState>,
 
-    layers: Vec<(Geometry, Layer)>,
+    /// The layers that clips and opacity masks are pushed as, with their
+    /// geometric masks and opacity brushes.
+    layers: Vec<(Geometry, Option<Brush>, Layer)>,
 
     err: Result<(), Error>,
 
     brush_cache: AssociativeCache<u32, Brush, Capacity1024, HashFourWay, RoundRobinReplacement>,
+
+    /// Whether the device context can draw color fonts.
+    color_fonts: bool,
+
+    hit_tester: HitTester,
+}
+
+/// The geometries of prepared shapes, kept by the factory so that they
+/// outlive the render contexts, which are usually made each frame.
+///
+/// Shapes are identified by the address and length of their elements, as
+/// returned by [`Shape::as_path_slice`]. Each entry holds on to the path of
+/// its shape, so the elements can't be freed, and the address reused by
+/// another path, while the entry is in the cache.
+pub(crate) type GeometryCache = AssociativeCache<
+    (usize, usize),
+    PreparedGeometry,
+    Capacity256,
+    HashFourWay,
+    RoundRobinReplacement,
+>;
+
+/// The geometries of a prepared shape, for each fill rule.
+pub(crate) struct PreparedGeometry {
+    _path: Arc<BezPath>,
+    non_zero: Geometry,
+    even_odd: Geometry,
 }
 
-#[derive(Default)]
 struct CtxState {
     transform: Affine,
+    global_alpha: f64,
+    /// The tolerance that shapes are converted to paths with.
+    tolerance: f64,
 
     // Note: when we start pushing both layers and axis aligned clips, this will
     // need to keep track of which is which. But for now, keep it simple.
     n_layers_pop: usize,
+    /// Whether the state was saved by beginning an opacity mask, so that it
+    /// is restored by ending the mask.
+    opacity_mask: bool,
+}
+
+impl Default for CtxState {
+    fn default() -> CtxState {
+        CtxState {
+            transform: Affine::IDENTITY,
+            global_alpha: 1.0,
+            tolerance: BEZ_TOLERANCE,
+            n_layers_pop: 0,
+            opacity_mask: false,
+        }
+    }
 }
 
 impl<'b, 'a: 'b> D2DRenderContext<'a> {
@@ -77,11 +129,13 @@ impl<'b, 'a: 'b> D2DRenderContext<'a> {
         D2DRenderContext {
             factory,
             inner_text: text,
+            color_fonts: rt.supports_color_fonts(),
             rt,
             layers: vec![],
             ctx_stack: vec![CtxState::default()],
             err: Ok(()),
             brush_cache: Default::default(),
+            hit_tester: HitTester::new(),
         }
     }
 
@@ -94,6 +148,28 @@ impl<'b, 'a: 'b> D2DRenderContext<'a> {
         }
     }
 
+    /// Draw with `draw`, through a layer with the global alpha as its
+    /// opacity, if it isn't opaque.
+    fn with_global_alpha(&mut self, draw: impl FnOnce(&mut Self)) {
+        let alpha = self.global_alpha();
+        if alpha >= 1.0 {
+            return draw(self);
+        }
+        if alpha <= 0.0 {
+            return;
+        }
+        let layer = match self.rt.create_layer(None) {
+            Ok(layer) => layer,
+            Err(e) => {
+                self.err = Err(e.into());
+                return;
+            }
+        };
+        self.rt.push_layer_opacity(&layer, alpha as f32);
+        draw(self);
+        self.rt.pop_layer();
+    }
+
     /// Check whether drawing operations have finished.
     ///
     /// Clients should call this before extracting or presenting the contents of
@@ -106,17 +182,18 @@ impl<'b, 'a: 'b> D2DRenderContext<'a> {
     }
 }
 
-// The setting of 1e-3 is extremely conservative (absolutely no
+// The default setting of 1e-3 is extremely conservative (absolutely no
 // differences should be visible) but setting a looser tolerance is
 // likely a tiny performance improvement. We could fine-tune based on
 // empirical study of both quality and performance.
-const BEZ_TOLERANCE: f64 = 1e-3;
+const BEZ_TOLERANCE: f64 = piet::DEFAULT_TOLERANCE;
 
 fn geometry_from_shape(
     d2d: &D2DFactory,
     is_filled: bool,
     shape: impl Shape,
     fill_rule: FillRule,
+    tolerance: f64,
 ) -> Result<Geometry, Error> {
     // TODO: Do something special for line?
     if let Some(rect) = shape.as_rect() {
@@ -134,21 +211,27 @@ fn geometry_from_shape(
     } else if let Some(circle) = shape.as_circle() {
         Ok(d2d.create_circle_geometry(circle)?.into())
     } else {
-        path_from_shape(d2d, is_filled, shape, fill_rule)
+        path_from_shape(d2d, is_filled, shape, fill_rule, tolerance)
     }
 }
 
+/// The key of a prepared shape in the [`GeometryCache`].
+fn path_key(elements: &[PathEl]) -> (usize, usize) {
+    (elements.as_ptr() as usize, elements.len())
+}
+
 fn path_from_shape(
     d2d: &D2DFactory,
     is_filled: bool,
     shape: impl Shape,
     fill_rule: FillRule,
+    tolerance: f64,
 ) -> Result<Geometry, Error> {
     let mut path = d2d.create_path_geometry()?;
     let mut sink = path.open()?;
     sink.set_fill_mode(fill_rule);
     let mut need_close = false;
-    for el in shape.path_elements(BEZ_TOLERANCE) {
+    for el in shape.path_elements(tolerance) {
         match el {
             PathEl::MoveTo(p) => {
                 if need_close {
@@ -216,8 +299,8 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
         self.rt.set_transform(&old_transform);
 
         // Restore clippings
-        for (mask, layer) in self.layers.iter() {
-            self.rt.push_layer_mask(mask, layer);
+        for (mask, opacity_brush, layer) in self.layers.iter() {
+            self.rt.push_layer_mask(mask, opacity_brush.as_ref(), layer);
         }
     }
 
@@ -265,15 +348,55 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
     }
 
     fn fill(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>) {
-        self.fill_impl(shape, brush, FillRule::NonZero)
+        self.with_global_alpha(|rc| rc.fill_impl(shape, brush, FillRule::NonZero))
     }
 
     fn fill_even_odd(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>) {
-        self.fill_impl(shape, brush, FillRule::EvenOdd)
+        self.with_global_alpha(|rc| rc.fill_impl(shape, brush, FillRule::EvenOdd))
+    }
+
+    fn fill_with_mask(
+        &mut self,
+        shape: impl Shape,
+        brush: &impl IntoBrush<Self>,
+        mask: &Self::Image,
+    ) {
+        let brush = brush.make_brush(self, || shape.bounding_box());
+        let bounds = shape.bounding_box();
+        let size = mask.size();
+        if bounds.is_empty() || size.is_empty() {
+            return;
+        }
+        // the alpha of the opacity brush is multiplied into the fill.
+        let to_bounds = Affine::translate(bounds.origin().to_vec2())
+            * Affine::scale_non_uniform(bounds.width() / size.width, bounds.height() / size.height)
+            * Affine::translate(-mask.view_origin());
+        let mask = match self
+            .rt
+            .create_bitmap_brush(mask, &affine_to_matrix3x2f(to_bounds))
+        {
+            Ok(mask) => mask,
+            Err(e) => {
+                self.err = Err(e.into());
+                return;
+            }
+        };
+        match path_from_shape(
+            self.factory,
+            true,
+            shape,
+            FillRule::NonZero,
+            self.tolerance(),
+        ) {
+            Ok(geom) => {
+                self.with_global_alpha(|rc| rc.rt.fill_geometry(&geom, &brush, Some(&mask)))
+            }
+            Err(e) => self.err = Err(e),
+        }
     }
 
     fn stroke(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>, width: f64) {
-        self.stroke_impl(shape, brush, width, None)
+        self.with_global_alpha(|rc| rc.stroke_impl(shape, brush, width, None))
     }
 
     fn stroke_styled(
@@ -285,7 +408,7 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
     ) {
         let style = convert_stroke_style(self.factory, style, width)
             .expect("stroke style conversion failed");
-        self.stroke_impl(shape, brush, width, Some(&style));
+        self.with_global_alpha(|rc| rc.stroke_impl(shape, brush, width, Some(&style)));
     }
 
     fn clip(&mut self, shape: impl Shape) {
@@ -297,38 +420,74 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
                 return;
             }
         };
-        let geom = match geometry_from_shape(self.factory, true, shape, FillRule::NonZero) {
-            Ok(geom) => geom,
-            Err(e) => {
-                self.err = Err(e);
-                return;
-            }
-        };
-        self.rt.push_layer_mask(&geom, &layer);
-        self.layers.push((geom, layer));
+        let tolerance = self.tolerance();
+        let geom =
+            match geometry_from_shape(self.factory, true, shape, FillRule::NonZero, tolerance) {
+                Ok(geom) => geom,
+                Err(e) => {
+                    self.err = Err(e);
+                    return;
+                }
+            };
+        self.rt.push_layer_mask(&geom, None, &layer);
+        self.layers.push((geom, None, layer));
         self.ctx_stack.last_mut().unwrap().n_layers_pop += 1;
     }
 
+    fn prepare_shape(&mut self, shape: impl Shape) -> PreparedShape {
+        let prepared = PreparedShape::new(shape);
+        let tolerance = self.tolerance();
+        let geometries = path_from_shape(
+            self.factory,
+            true,
+            &prepared,
+            FillRule::NonZero,
+            tolerance,
+        )
+        .and_then(|non_zero| {
+            let even_odd =
+                path_from_shape(self.factory, true, &prepared, FillRule::EvenOdd, tolerance)?;
+            Ok(PreparedGeometry {
+                _path: prepared.shared_path().clone(),
+                non_zero,
+                even_odd,
+            })
+        });
+        match geometries {
+            Ok(geometries) => {
+                let key = path_key(prepared.path().elements());
+                self.factory.1.borrow_mut().insert(key, geometries);
+            }
+            // it can still be drawn, as a path.
+            Err(e) => self.err = Err(e),
+        }
+        prepared
+    }
+
     fn text(&mut self) -> &mut Self::Text {
         &mut self.inner_text
     }
 
     fn draw_text(&mut self, layout: &Self::TextLayout, pos: impl Into<Point>) {
         // TODO: bounding box for text
-        layout.draw(pos.into(), self);
+        let pos = pos.into();
+        self.with_global_alpha(|rc| layout.draw(pos, rc));
     }
 
     fn save(&mut self) -> Result<(), Error> {
         let new_state = CtxState {
             transform: self.current_transform(),
+            global_alpha: self.global_alpha(),
+            tolerance: self.tolerance(),
             n_layers_pop: 0,
+            opacity_mask: false,
         };
         self.ctx_stack.push(new_state);
         Ok(())
     }
 
     fn restore(&mut self) -> Result<(), Error> {
-        if self.ctx_stack.len() <= 1 {
+        if self.ctx_stack.len() <= 1 || self.ctx_stack.last().unwrap().opacity_mask {
             return Err(Error::StackUnbalance);
         }
         self.pop_state();
@@ -361,6 +520,83 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
         self.ctx_stack.last().unwrap().transform
     }
 
+    fn capabilities(&self) -> Capabilities {
+        let mut capabilities = Capabilities::default();
+        capabilities.supports_blur = true;
+        capabilities.supports_global_alpha = true;
+        capabilities.text_shaping_quality = TextShapingQuality::Full;
+        capabilities
+    }
+
+    fn set_global_alpha(&mut self, alpha: f64) {
+        let alpha = if alpha.is_nan() {
+            0.0
+        } else {
+            alpha.clamp(0.0, 1.0)
+        };
+        self.ctx_stack.last_mut().unwrap().global_alpha = alpha;
+    }
+
+    fn global_alpha(&self) -> f64 {
+        self.ctx_stack.last().unwrap().global_alpha
+    }
+
+    fn set_tolerance(&mut self, tolerance: f64) {
+        if tolerance > 0.0 && tolerance.is_finite() {
+            self.ctx_stack.last_mut().unwrap().tolerance = tolerance;
+        }
+    }
+
+    fn tolerance(&self) -> f64 {
+        self.ctx_stack.last().unwrap().tolerance
+    }
+
+    fn begin_opacity_mask(
+        &mut self,
+        mask: &Self::Image,
+        rect: impl Into<Rect>,
+    ) -> Result<(), Error> {
+        let rect = rect.into().abs();
+        let size = mask.size();
+        let layer = self.rt.create_layer(None)?;
+        // the layer is clipped to the rect, and multiplied by the alpha of
+        // an opacity brush that stretches the mask over it. An empty mask
+        // hides everything, so it is a clip to nothing.
+        let (geom, opacity_brush) = if rect.is_empty() || size.is_empty() {
+            (self.factory.create_rect_geometry(Rect::ZERO)?, None)
+        } else {
+            let to_rect = Affine::translate(rect.origin().to_vec2())
+                * Affine::scale_non_uniform(rect.width() / size.width, rect.height() / size.height)
+                * Affine::translate(-mask.view_origin());
+            let brush = self
+                .rt
+                .create_bitmap_brush(mask, &affine_to_matrix3x2f(to_rect))?;
+            (self.factory.create_rect_geometry(rect)?, Some(brush))
+        };
+        let geom = Geometry::from(geom);
+        self.save()?;
+        self.rt
+            .push_layer_mask(&geom, opacity_brush.as_ref(), &layer);
+        self.layers.push((geom, opacity_brush, layer));
+        let state = self.ctx_stack.last_mut().unwrap();
+        state.n_layers_pop += 1;
+        state.opacity_mask = true;
+        Ok(())
+    }
+
+    fn end_opacity_mask(&mut self) -> Result<(), Error> {
+        if !self.ctx_stack.last().unwrap().opacity_mask {
+            return Err(Error::StackUnbalance);
+        }
+        // popping the layer draws what was drawn through the mask.
+        self.ctx_stack.last_mut().unwrap().opacity_mask = false;
+        self.restore()
+    }
+
+    fn hit_tester(&mut self) -> Option<&mut HitTester> {
+        Some(&mut self.hit_tester)
+    }
+
     fn make_image(
         &mut self,
         width: usize,
@@ -375,6 +611,15 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
             return Ok(self.rt.create_empty_bitmap()?);
         }
 
+        // the bitmaps are made as 8-bit RGBA.
+        if matches!(
+            format,
+            ImageFormat::BgraSeparate | ImageFormat::BgraPremul | ImageFormat::Rgba16
+        ) {
+            let (buf, format) = piet::util::to_rgba8(buf, format);
+            return self.make_image(width, height, &buf, format);
+        }
+
         // TODO: this method _really_ needs error checking, so much can go wrong...
         let alpha_mode = match format {
             ImageFormat::Rgb | ImageFormat::Grayscale => D2D1_ALPHA_MODE_IGNORE,
@@ -383,28 +628,13 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
         };
         let buf = match format {
             ImageFormat::Rgb => {
-                let mut new_buf = vec![255; width * height * 4];
-                for i in 0..width * height {
-                    new_buf[i * 4 + 0] = buf[i * 3 + 0];
-                    new_buf[i * 4 + 1] = buf[i * 3 + 1];
-                    new_buf[i * 4 + 2] = buf[i * 3 + 2];
-                }
+                let mut new_buf = vec![0; width * height * 4];
+                piet::util::rgb_to_rgba(buf, &mut new_buf);
                 Cow::from(new_buf)
             }
             ImageFormat::RgbaSeparate => {
-                let mut new_buf = vec![255; width * height * 4];
-                // TODO (performance): this would be soooo much faster with SIMD
-                fn premul(x: u8, a: u8) -> u8 {
-                    let y = (x as u16) * (a as u16);
-                    ((y + (y >> 8) + 0x80) >> 8) as u8
-                }
-                for i in 0..width * height {
-                    let a = buf[i * 4 + 3];
-                    new_buf[i * 4 + 0] = premul(buf[i * 4 + 0], a);
-                    new_buf[i * 4 + 1] = premul(buf[i * 4 + 1], a);
-                    new_buf[i * 4 + 2] = premul(buf[i * 4 + 2], a);
-                    new_buf[i * 4 + 3] = a;
-                }
+                let mut new_buf = buf[..width * height * 4].to_vec();
+                piet::util::premultiply_rgba(&mut new_buf);
                 Cow::from(new_buf)
             }
             ImageFormat::RgbaPremul => Cow::from(buf),
@@ -412,12 +642,8 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
                 // it seems like there's no good way to create a 1-channel bitmap
                 // here? I am not alone:
                 // https://stackoverflow.com/questions/44270215/direct2d-fails-when-drawing-a-single-channel-bitmap
-                let mut new_buf = vec![255; width * height * 4];
-                for i in 0..width * height {
-                    new_buf[i * 4 + 0] = buf[i];
-                    new_buf[i * 4 + 1] = buf[i];
-                    new_buf[i * 4 + 2] = buf[i];
-                }
+                let mut new_buf = vec![0; width * height * 4];
+                piet::util::gray_to_rgba(buf, &mut new_buf);
                 Cow::from(new_buf)
             }
             // This should be unreachable, we caught it above.
@@ -434,7 +660,8 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
         dst_rect: impl Into<Rect>,
         interp: InterpolationMode,
     ) {
-        draw_image(self.rt, image, None, dst_rect.into(), interp);
+        let alpha = self.global_alpha();
+        draw_image(self.rt, image, None, dst_rect.into(), interp, alpha);
     }
 
     #[inline]
@@ -445,15 +672,34 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
         dst_rect: impl Into<Rect>,
         interp: InterpolationMode,
     ) {
+        let alpha = self.global_alpha();
         draw_image(
             self.rt,
             image,
             Some(src_rect.into()),
             dst_rect.into(),
             interp,
+            alpha,
         );
     }
 
+    fn draw_image_filtered(
+        &mut self,
+        image: &Self::Image,
+        dst_rect: impl Into<Rect>,
+        filters: &[ImageFilter],
+    ) {
+        let dst_rect = dst_rect.into();
+        if image.empty_image || dst_rect.is_empty() {
+            return;
+        }
+        self.with_global_alpha(|rc| {
+            if let Err(e) = rc.draw_image_filtered_raw(image, dst_rect, filters) {
+                rc.err = Err(e);
+            }
+        });
+    }
+
     fn capture_image_area(&mut self, rect: impl Into<Rect>) -> Result<Self::Image, Error> {
         let r = rect.into();
 
@@ -497,8 +743,8 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
         target_bitmap.copy_from_render_target(d2d_dest_point, self.rt, d2d_src_rect);
 
         // Restore cleared layers
-        for (mask, layer) in self.layers.iter() {
-            self.rt.push_layer_mask(mask, layer);
+        for (mask, opacity_brush, layer) in self.layers.iter() {
+            self.rt.push_layer_mask(mask, opacity_brush.as_ref(), layer);
         }
 
         Ok(target_bitmap)
@@ -506,13 +752,27 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
 
     fn blurred_rect(&mut self, rect: Rect, blur_radius: f64, brush: &impl IntoBrush<Self>) {
         let brush = brush.make_brush(self, || rect);
-        if let Err(e) = self.blurred_rect_raw(rect, blur_radius, brush) {
-            eprintln!("error in drawing blurred rect: {:?}", e);
-        }
+        self.with_global_alpha(|rc| {
+            if let Err(e) = rc.blurred_rect_raw(rect, blur_radius, brush) {
+                eprintln!("error in drawing blurred rect: {:?}", e);
+            }
+        });
     }
 }
 
 impl<'a> D2DRenderContext<'a> {
+    /// The geometry that `shape` was prepared with, by
+    /// [`prepare_shape`](RenderContext::prepare_shape), if it is a prepared
+    /// shape whose geometry is still cached.
+    fn prepared_geometry(&self, shape: &impl Shape, fill_rule: &FillRule) -> Option<Geometry> {
+        let cache = self.factory.1.borrow();
+        let prepared = cache.get(&path_key(shape.as_path_slice()?))?;
+        Some(match fill_rule {
+            FillRule::NonZero => prepared.non_zero.clone(),
+            FillRule::EvenOdd => prepared.even_odd.clone(),
+        })
+    }
+
     fn fill_impl(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>, fill_rule: FillRule) {
         let brush = brush.make_brush(self, || shape.bounding_box());
 
@@ -530,8 +790,10 @@ impl<'a> D2DRenderContext<'a> {
             )
         } else if let Some(circle) = shape.as_circle() {
             self.rt.fill_circle(circle, &brush)
+        } else if let Some(geom) = self.prepared_geometry(&shape, &fill_rule) {
+            self.rt.fill_geometry(&geom, &brush, None)
         } else {
-            match path_from_shape(self.factory, true, shape, fill_rule) {
+            match path_from_shape(self.factory, true, shape, fill_rule, self.tolerance()) {
                 Ok(geom) => self.rt.fill_geometry(&geom, &brush, None),
                 Err(e) => self.err = Err(e),
             }
@@ -565,7 +827,13 @@ impl<'a> D2DRenderContext<'a> {
             return;
         }
 
-        let geom = match path_from_shape(self.factory, false, shape, FillRule::EvenOdd) {
+        if let Some(geom) = self.prepared_geometry(&shape, &FillRule::NonZero) {
+            self.rt.draw_geometry(&geom, &brush, width, style);
+            return;
+        }
+
+        let tolerance = self.tolerance();
+        let geom = match path_from_shape(self.factory, false, shape, FillRule::EvenOdd, tolerance) {
             Ok(geom) => geom,
             Err(e) => {
                 self.err = Err(e);
@@ -576,6 +844,87 @@ impl<'a> D2DRenderContext<'a> {
         self.rt.draw_geometry(&geom, &brush, width, style);
     }
 
+    /// Draw an image through a chain of effects.
+    ///
+    /// Effects work in the pixels of the bitmap, which are drawn into
+    /// `dst_rect` by the transform that the output is drawn with.
+    fn draw_image_filtered_raw(
+        &mut self,
+        image: &Bitmap,
+        dst_rect: Rect,
+        filters: &[ImageFilter],
+    ) -> Result<(), Error> {
+        let size = image.size();
+        let scale = Vec2::new(
+            dst_rect.width() / size.width,
+            dst_rect.height() / size.height,
+        );
+        let to_pixels = |length: f64| length.abs() / (scale.x * scale.y).abs().sqrt();
+        let mut last: Option<d2d::Effect> = None;
+        let set_input = |effect: &d2d::Effect, index, last: &Option<d2d::Effect>| match last {
+            Some(last) => effect.set_input_effect(index, last),
+            None => effect.set_input(index, image.as_image()),
+        };
+        for filter in filters {
+            let effect = match *filter {
+                ImageFilter::Blur { radius } => {
+                    let blur = self.rt.create_blur_effect(to_pixels(radius))?;
+                    set_input(&blur, 0, &last);
+                    blur
+                }
+                ImageFilter::DropShadow {
+                    offset,
+                    radius,
+                    color,
+                } => {
+                    let shadow = self.rt.create_shadow_effect(to_pixels(radius), color)?;
+                    set_input(&shadow, 0, &last);
+                    let offset = Vec2::new(offset.x / scale.x, offset.y / scale.y);
+                    let moved = self.rt.create_transform_effect(Affine::translate(offset))?;
+                    moved.set_input_effect(0, &shadow);
+                    let composite = self.rt.create_composite_effect()?;
+                    composite.set_input_effect(0, &moved);
+                    set_input(&composite, 1, &last);
+                    composite
+                }
+                ImageFilter::ColorMatrix(ref matrix) => {
+                    let effect = self.rt.create_color_matrix_effect(matrix)?;
+                    set_input(&effect, 0, &last);
+                    effect
+                }
+                _ => continue,
+            };
+            last = Some(effect);
+        }
+        let effect = match last {
+            Some(effect) => effect,
+            None => {
+                // the global alpha is applied by the caller.
+                let interp = InterpolationMode::Bilinear;
+                draw_image(self.rt, image, None, dst_rect, interp, 1.0);
+                return Ok(());
+            }
+        };
+        let image_transform = Affine::new([scale.x, 0.0, 0.0, scale.y, dst_rect.x0, dst_rect.y0])
+            * Affine::translate(-image.view_origin());
+        self.rt.set_transform(&affine_to_matrix3x2f(
+            self.current_transform() * image_transform,
+        ));
+        // the effects read the whole bitmap, so the output of a sub-image
+        // is cut to its area.
+        let image_rect = image.view.map(rect_to_rectf);
+        self.rt.draw_image_effect(
+            &effect,
+            None,
+            image_rect,
+            D2D1_INTERPOLATION_MODE_LINEAR,
+            D2D1_COMPOSITE_MODE_SOURCE_OVER,
+        );
+        self.rt
+            .set_transform(&affine_to_matrix3x2f(self.current_transform()));
+        Ok(())
+    }
+
     // This is split out to unify error reporting, as there are lots of opportunities for
     // errors in resource creation.
     fn blurred_rect_raw(
@@ -640,20 +989,27 @@ fn draw_image<'a>(
     src_rect: Option<Rect>,
     dst_rect: Rect,
     interp: InterpolationMode,
+    alpha: f64,
 ) {
     if dst_rect.is_empty() || image.empty_image {
         // source or destination are empty
         return;
     }
     let interp = match interp {
-        InterpolationMode::NearestNeighbor => D2D1_BITMAP_INTERPOLATION_MODE_NEAREST_NEIGHBOR,
-        InterpolationMode::Bilinear => D2D1_BITMAP_INTERPOLATION_MODE_LINEAR,
+        InterpolationMode::NearestNeighbor => D2D1_INTERPOLATION_MODE_NEAREST_NEIGHBOR,
+        InterpolationMode::Bilinear => D2D1_INTERPOLATION_MODE_LINEAR,
+        InterpolationMode::Bicubic => D2D1_INTERPOLATION_MODE_CUBIC,
+        InterpolationMode::HighQuality => D2D1_INTERPOLATION_MODE_HIGH_QUALITY_CUBIC,
+    };
+    let src_rect = match src_rect {
+        Some(src_rect) => Some(src_rect + image.view_origin()),
+        None => image.view,
     };
     let src_rect = src_rect.map(rect_to_rectf);
     rt.draw_bitmap(
         image,
         &rect_to_rectf(dst_rect),
-        1.0,
+        alpha as f32,
         interp,
         src_rect.as_ref(),
     );
@@ -673,9 +1029,20 @@ impl Image for Bitmap {
     fn size(&self) -> Size {
         if self.empty_image {
             Size::ZERO
+        } else if let Some(view) = self.view {
+            view.size()
         } else {
             let inner = self.get_size();
             Size::new(inner.width.into(), inner.height.into())
         }
     }
+
+    fn sub_image(&self, rect: impl Into<Rect>) -> Self {
+        let view = piet::util::sub_image_view(self.view_rect(), rect.into());
+        Bitmap {
+            empty_image: self.empty_image || view.is_empty(),
+            view: Some(view),
+            ..self.clone()
+        }
+    }
 }
@@ -0,0 +1,160 @@
+//! Drawing to the back buffer of a DXGI swap chain.
+
+use std::mem::MaybeUninit;
+use std::ptr::null_mut;
+
+use winapi::shared::dxgi::{IDXGISurface, IDXGISwapChain, DXGI_SWAP_CHAIN_DESC};
+use winapi::shared::dxgiformat::DXGI_FORMAT_UNKNOWN;
+use winapi::Interface;
+
+use wio::com::ComPtr;
+
+use crate::d2d::{wrap_unit, D2DDevice, D2DFactory, DeviceContext, Error};
+use crate::{D2DRenderContext, D2DText};
+
+/// A target that draws to the back buffer of a DXGI swap chain, such as the
+/// swap chain of a window, or of a game that draws an overlay with piet.
+///
+/// Drawing goes straight to the back buffer, without being copied through a
+/// bitmap. Each frame is drawn with a [`render_context`], and shown with
+/// [`present`]:
+///
+/// ```ignore
+/// let mut target = unsafe { SwapChainTarget::new(&mut device, swap_chain, dpi_scale)? };
+/// // for each frame:
+/// let mut rc = target.render_context(&factory, text.clone());
+/// rc.clear(None, Color::BLACK);
+/// // ... draw ...
+/// rc.finish()?;
+/// drop(rc);
+/// target.present(1)?;
+/// // when the window is resized:
+/// target.resize(width, height)?;
+/// ```
+///
+/// The swap chain may be shared with Direct3D drawing, such as a game's;
+/// piet draws over what is already in the back buffer, unless it is
+/// cleared.
+///
+/// [`render_context`]: SwapChainTarget::render_context
+/// [`present`]: SwapChainTarget::present
+pub struct SwapChainTarget {
+    swap_chain: ComPtr<IDXGISwapChain>,
+    context: DeviceContext,
+    dpi_scale: f32,
+    /// Whether the device context is between `BeginDraw` and `EndDraw`.
+    drawing: bool,
+}
+
+impl SwapChainTarget {
+    /// Create a target that draws to the back buffer of `swap_chain`, with
+    /// `dpi_scale` pixels per unit.
+    ///
+    /// # Safety
+    ///
+    /// `swap_chain` must have been created for the Direct3D device that
+    /// `device` was created from, with a format that Direct2D can draw to,
+    /// such as `DXGI_FORMAT_B8G8R8A8_UNORM`, and with
+    /// `DXGI_USAGE_RENDER_TARGET_OUTPUT`.
+    pub unsafe fn new(
+        device: &mut D2DDevice,
+        swap_chain: ComPtr<IDXGISwapChain>,
+        dpi_scale: f32,
+    ) -> Result<SwapChainTarget, Error> {
+        let context = device.create_device_context()?;
+        let mut target = SwapChainTarget {
+            swap_chain,
+            context,
+            dpi_scale,
+            drawing: false,
+        };
+        target.bind_back_buffer()?;
+        Ok(target)
+    }
+
+    /// The swap chain that is drawn to.
+    pub fn swap_chain(&self) -> &ComPtr<IDXGISwapChain> {
+        &self.swap_chain
+    }
+
+    /// Get a piet `RenderContext` for the back buffer.
+    ///
+    /// The caller is responsible for calling `finish` on the render context
+    /// at the end of drawing, and then [`present`](SwapChainTarget::present)
+    /// to show what was drawn.
+    pub fn render_context<'a>(
+        &'a mut self,
+        factory: &'a D2DFactory,
+        text: D2DText,
+    ) -> D2DRenderContext<'a> {
+        if !self.drawing {
+            self.context.begin_draw();
+            self.drawing = true;
+        }
+        D2DRenderContext::new(factory, text, &mut self.context)
+    }
+
+    /// Finish drawing, and present the back buffer, after `sync_interval`
+    /// vertical blanks, or immediately if it is `0`.
+    ///
+    /// If this returns `D2DERR_RECREATE_TARGET`, or
+    /// `DXGI_ERROR_DEVICE_REMOVED`, the device was lost, and the devices,
+    /// swap chain and target all need to be created again.
+    pub fn present(&mut self, sync_interval: u32) -> Result<(), Error> {
+        self.end_draw()?;
+        unsafe { wrap_unit(self.swap_chain.Present(sync_interval, 0)) }
+    }
+
+    /// Resize the buffers of the swap chain to `width` by `height` pixels,
+    /// such as when its window is resized, and draw to the new back buffer.
+    ///
+    /// Anything that has been drawn since the last
+    /// [`present`](SwapChainTarget::present) is discarded. The format,
+    /// number of buffers, and flags of the swap chain are kept.
+    pub fn resize(&mut self, width: u32, height: u32) -> Result<(), Error> {
+        self.end_draw()?;
+        // the swap chain can only resize its buffers once nothing else holds
+        // a reference to them.
+        self.context.clear_target();
+        unsafe {
+            let mut desc = MaybeUninit::<DXGI_SWAP_CHAIN_DESC>::uninit();
+            wrap_unit(self.swap_chain.GetDesc(desc.as_mut_ptr()))?;
+            let flags = desc.assume_init().Flags;
+            wrap_unit(
+                self.swap_chain
+                    .ResizeBuffers(0, width, height, DXGI_FORMAT_UNKNOWN, flags),
+            )?;
+            self.bind_back_buffer()
+        }
+    }
+
+    /// Set the dpi scale, such as when the window moves to another monitor.
+    pub fn set_dpi_scale(&mut self, dpi_scale: f32) {
+        self.dpi_scale = dpi_scale;
+        self.context.set_dpi_scale(dpi_scale);
+    }
+
+    fn end_draw(&mut self) -> Result<(), Error> {
+        if self.drawing {
+            self.drawing = false;
+            self.context.end_draw()?;
+        }
+        Ok(())
+    }
+
+    /// Make the current back buffer the target of the device context.
+    unsafe fn bind_back_buffer(&mut self) -> Result<(), Error> {
+        let mut ptr = null_mut();
+        wrap_unit(
+            self.swap_chain
+                .GetBuffer(0, &IDXGISurface::uuidof(), &mut ptr),
+        )?;
+        let surface = ComPtr::from_raw(ptr as *mut IDXGISurface);
+        let target = self
+            .context
+            .create_bitmap_from_dxgi(&surface, self.dpi_scale)?;
+        self.context.set_target(&target);
+        self.context.set_dpi_scale(self.dpi_scale);
+        Ok(())
+    }
+}
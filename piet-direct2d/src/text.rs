@@ -12,13 +12,18 @@ use std::sync::Arc;
 pub use d2d::{D2DDevice, D2DFactory, DeviceContext as D2DDeviceContext};
 pub use dwrite::DwriteFactory;
 use dwrote::{CustomFontCollectionLoaderImpl, FontCollection, FontFile};
-use winapi::um::d2d1::D2D1_DRAW_TEXT_OPTIONS_NONE;
+use winapi::um::d2d1::{
+    D2D1_DRAW_TEXT_OPTIONS_ENABLE_COLOR_FONT, D2D1_DRAW_TEXT_OPTIONS_NONE,
+    D2D1_DRAW_TEXT_OPTIONS_NO_SNAP, D2D1_TEXT_ANTIALIAS_MODE_CLEARTYPE,
+    D2D1_TEXT_ANTIALIAS_MODE_DEFAULT, D2D1_TEXT_ANTIALIAS_MODE_GRAYSCALE,
+};
 use wio::wide::ToWide;
 
-use piet::kurbo::{Insets, Point, Rect, Size};
+use piet::kurbo::{Affine, BezPath, Insets, Point, Rect, Size};
 use piet::util;
 use piet::{
-    Color, Error, FontFamily, HitTestPoint, HitTestPosition, LineMetric, RenderContext, Text,
+    Color, Error, FontFamily, FontFeature, FontStyle, FontVariation, FontWeight, Glyph, GlyphRun,
+    Hinting, HitTestPoint, HitTestPosition, LineMetric, RenderContext, ScriptPosition, Text,
     TextAlignment, TextAttribute, TextLayout, TextLayoutBuilder, TextStorage,
 };
 
@@ -31,6 +36,8 @@ use crate::D2DRenderContext;
 pub struct D2DText {
     dwrite: DwriteFactory,
     loaded_fonts: D2DLoadedFonts,
+    hinting: Hinting,
+    color_glyphs: bool,
 }
 
 /// The set of loaded fonts, shared between `D2DText` instances.
@@ -79,9 +86,13 @@ pub struct D2DTextLayout {
     // in order to generate the brushes.
     colors: Rc<[(Utf16Range, Color)]>,
     needs_to_set_colors: Cell<bool>,
+    // DirectWrite has no background attribute, so we fill these ourselves.
+    // The ranges are utf8, for use with `rects_for_range`.
+    backgrounds: Rc<[(Range<usize>, Color)]>,
 }
 
 pub struct D2DTextLayoutBuilder {
+    dwrite: DwriteFactory,
     text: Rc<dyn TextStorage>,
     layout: Result<dwrite::TextLayout, Error>,
     len_utf16: usize,
@@ -89,6 +100,7 @@ pub struct D2DTextLayoutBuilder {
     default_font: FontFamily,
     default_font_size: f64,
     colors: Vec<(Utf16Range, Color)>,
+    backgrounds: Vec<(Range<usize>, Color)>,
     // just used to assert api is used as expected
     last_range_start_pos: usize,
 }
@@ -105,6 +117,8 @@ impl D2DText {
         D2DText {
             dwrite,
             loaded_fonts: loaded_fonts.unwrap_or_default(),
+            hinting: Hinting::Default,
+            color_glyphs: true,
         }
     }
 
@@ -142,6 +156,14 @@ impl Text for D2DText {
         self.loaded_fonts.inner.borrow_mut().add(data)
     }
 
+    fn set_hinting(&mut self, hinting: Hinting) {
+        self.hinting = hinting;
+    }
+
+    fn set_color_glyphs(&mut self, color: bool) {
+        self.color_glyphs = color;
+    }
+
     fn new_text_layout(&mut self, text: impl TextStorage) -> Self::TextLayoutBuilder {
         let text = Rc::new(text);
         let width = f32::INFINITY;
@@ -152,10 +174,12 @@ impl Text for D2DText {
             .map_err(Into::into);
 
         D2DTextLayoutBuilder {
+            dwrite: self.dwrite.clone(),
             layout,
             text,
             len_utf16: wide_str.len(),
             colors: Vec::new(),
+            backgrounds: Vec::new(),
             loaded_fonts: self.loaded_fonts.clone(),
             default_font: FontFamily::default(),
             default_font_size: piet::util::DEFAULT_FONT_SIZE,
@@ -186,6 +210,15 @@ impl TextLayoutBuilder for D2DTextLayoutBuilder {
         self
     }
 
+    // DirectWrite only supports evenly spaced tab stops, so explicit stops are
+    // ignored, and only the tab width is used.
+    fn tab_width(mut self, width: f64) -> Self {
+        if let Ok(layout) = self.layout.as_mut() {
+            layout.set_incremental_tab_stop(width);
+        }
+        self
+    }
+
     fn default_attribute(mut self, attribute: impl Into<TextAttribute>) -> Self {
         debug_assert!(
             self.last_range_start_pos == 0,
@@ -226,6 +259,7 @@ impl TextLayoutBuilder for D2DTextLayoutBuilder {
             text: self.text,
             colors: self.colors.into(),
             needs_to_set_colors: Cell::new(true),
+            backgrounds: self.backgrounds.into(),
             line_metrics: Rc::new([]),
             layout: Rc::new(RefCell::new(layout)),
             size: Size::ZERO,
@@ -249,6 +283,7 @@ impl D2DTextLayoutBuilder {
     /// used for both range and default attributes
     fn add_attribute_shared(&mut self, attr: TextAttribute, range: Option<Range<usize>>) {
         if let Ok(layout) = self.layout.as_mut() {
+            let utf8_range = range.clone().unwrap_or(0..self.text.len());
             let utf16_range = match range {
                 Some(range) => {
                     let start = util::count_utf16(&self.text[..range.start]);
@@ -282,6 +317,64 @@ impl D2DTextLayoutBuilder {
                 TextAttribute::Underline(flag) => layout.set_underline(utf16_range, flag),
                 TextAttribute::Strikethrough(flag) => layout.set_strikethrough(utf16_range, flag),
                 TextAttribute::TextColor(color) => self.colors.push((utf16_range, color)),
+                TextAttribute::BackgroundColor(color) => {
+                    if color != Color::TRANSPARENT {
+                        self.backgrounds.push((utf8_range, color));
+                    }
+                }
+                TextAttribute::LetterSpacing(spacing) => {
+                    layout.set_character_spacing(utf16_range, spacing as f32)
+                }
+                // DirectWrite has no word spacing attribute.
+                TextAttribute::WordSpacing(_) => (),
+                TextAttribute::FontFeatures(features) => {
+                    if let Ok(typography) = self.dwrite.create_typography(&features) {
+                        layout.set_typography(utf16_range, &typography);
+                    }
+                }
+                // DirectWrite can't shift the baseline without a custom renderer.
+                TextAttribute::BaselineShift(_) => (),
+                // We can't shift the baseline, so the best we can do is to ask the font
+                // for its superscript or subscript glyphs. Like `FontFeatures`, this
+                // replaces any other typography set for the range.
+                TextAttribute::ScriptPosition(script) => {
+                    let feature = match script {
+                        ScriptPosition::Normal => None,
+                        ScriptPosition::Superscript => Some(FontFeature::on(*b"sups")),
+                        ScriptPosition::Subscript => Some(FontFeature::on(*b"subs")),
+                    };
+                    if let Some(typography) =
+                        feature.and_then(|f| self.dwrite.create_typography(&[f]).ok())
+                    {
+                        layout.set_typography(utf16_range, &typography);
+                    }
+                }
+                // Setting axis values directly needs IDWriteTextLayout4, which we don't
+                // have; instead we set the equivalent font properties, and DirectWrite's
+                // font matching picks the nearest named instance of a variable font.
+                TextAttribute::FontVariations(variations) => {
+                    if let Some(wght) = FontVariation::find(&variations, FontVariation::WEIGHT) {
+                        let weight = FontWeight::new(wght.round().max(0.0) as u16);
+                        layout.set_weight(utf16_range, weight);
+                    }
+                    if let Some(wdth) = FontVariation::find(&variations, FontVariation::WIDTH) {
+                        layout.set_stretch(utf16_range, wdth);
+                    }
+                    let italic = FontVariation::find(&variations, FontVariation::ITALIC)
+                        .map(|ital| ital >= 0.5)
+                        .or_else(|| {
+                            FontVariation::find(&variations, FontVariation::SLANT)
+                                .map(|slnt| slnt != 0.0)
+                        });
+                    if let Some(italic) = italic {
+                        let style = if italic {
+                            FontStyle::Italic
+                        } else {
+                            FontStyle::Regular
+                        };
+                        layout.set_style(utf16_range, style);
+                    }
+                }
             }
         }
     }
@@ -388,7 +481,21 @@ impl TextLayout for D2DTextLayout {
         let text_position =
             util::count_until_utf16(&self.text, text_position_16).unwrap_or(self.text.len());
 
-        HitTestPoint::new(text_position, htp.is_inside)
+        let line = self
+            .line_metrics
+            .iter()
+            .position(|lm| lm.y_offset + lm.height >= point.y)
+            // if we're past the last line, use the last line
+            .unwrap_or_else(|| self.line_metrics.len().saturating_sub(1));
+
+        util::hit_test_point_on_line(
+            self,
+            Affine::IDENTITY,
+            point,
+            line,
+            text_position,
+            htp.is_inside,
+        )
     }
 
     // Can panic if text position is not at a code point boundary, or if it's out of bounds.
@@ -423,6 +530,101 @@ impl TextLayout for D2DTextLayout {
         }
         HitTestPosition::new(hit_point, line)
     }
+
+    fn rects_for_range(&self, range: impl RangeBounds<usize>) -> Vec<Rect> {
+        let range = util::resolve_range(range, self.text.len());
+        if range.start >= range.end {
+            return Vec::new();
+        }
+        let start16 = util::count_utf16(&self.text[..range.start]);
+        let len16 = util::count_utf16(&self.text[range]);
+        let regions = self
+            .layout
+            .borrow()
+            .hit_test_text_range(Utf16Range::new(start16, len16));
+        regions
+            .into_iter()
+            .map(|region| {
+                let x0 = region.left as f64;
+                let x1 = x0 + region.width as f64;
+                // use our line metrics, which account for custom line spacing
+                let offset = util::count_until_utf16(&self.text, region.text_position as usize)
+                    .unwrap_or_else(|| self.text.len());
+                let line = util::line_number_for_position(&self.line_metrics, offset);
+                match self.line_metrics.get(line) {
+                    Some(metric) => {
+                        Rect::new(x0, metric.y_offset, x1, metric.y_offset + metric.height)
+                    }
+                    None => Rect::new(
+                        x0,
+                        region.top as f64,
+                        x1,
+                        (region.top + region.height) as f64,
+                    ),
+                }
+            })
+            .collect()
+    }
+
+    fn glyph_runs(&self) -> Vec<GlyphRun> {
+        let runs = self.layout.borrow().glyph_runs();
+        runs.into_iter()
+            .map(|run| {
+                // the utf-16 offset (relative to the run) of the cluster of each glyph
+                let mut cluster_starts = vec![None; run.glyphs.len()];
+                for (i, glyph) in run.cluster_map.iter().enumerate().rev() {
+                    if let Some(start) = cluster_starts.get_mut(*glyph as usize) {
+                        *start = Some(i);
+                    }
+                }
+
+                let (mut pen, baseline) = run.baseline_origin;
+                let mut cluster16 = 0;
+                let mut glyphs = Vec::with_capacity(run.glyphs.len());
+                for (i, id) in run.glyphs.iter().enumerate() {
+                    cluster16 = cluster_starts[i].unwrap_or(cluster16);
+                    let advance = run.advances[i];
+                    let (advance_offset, ascender_offset) = run.offsets[i];
+                    // rtl runs are laid out leftwards from the origin
+                    let x = if run.is_rtl {
+                        pen -= advance;
+                        pen - advance_offset
+                    } else {
+                        let x = pen + advance_offset;
+                        pen += advance;
+                        x
+                    };
+                    let pos16 = (run.text_position as usize) + cluster16;
+                    glyphs.push(Glyph {
+                        id: *id as u32,
+                        position: Point::new(x as f64, (baseline - ascender_offset) as f64),
+                        advance: advance as f64,
+                        cluster: util::count_until_utf16(&self.text, pos16)
+                            .unwrap_or_else(|| self.text.len()),
+                    });
+                }
+
+                let start = util::count_until_utf16(&self.text, run.text_position as usize)
+                    .unwrap_or_else(|| self.text.len());
+                GlyphRun {
+                    font: run.font_family,
+                    font_size: run.font_size as f64,
+                    weight: run.weight,
+                    style: run.style,
+                    line: util::line_number_for_position(&self.line_metrics, start),
+                    glyphs,
+                }
+            })
+            .collect()
+    }
+
+    fn outline(&self) -> BezPath {
+        let mut path = BezPath::new();
+        for run in self.layout.borrow().glyph_runs() {
+            path.extend(run.outline());
+        }
+        path
+    }
 }
 
 impl D2DTextLayout {
@@ -454,10 +656,33 @@ impl D2DTextLayout {
 
     pub fn draw(&self, pos: Point, ctx: &mut D2DRenderContext) {
         if !self.text.is_empty() {
+            for (range, color) in self.backgrounds.iter() {
+                for rect in self.rects_for_range(range.clone()) {
+                    ctx.fill(rect + pos.to_vec2(), color);
+                }
+            }
             self.resolve_colors_if_needed(ctx);
             let pos = conv::to_point2f(pos);
             let black_brush = ctx.solid_brush(Color::BLACK);
-            let text_options = D2D1_DRAW_TEXT_OPTIONS_NONE;
+            // cleartype falls back to grayscale on targets with an alpha channel.
+            let (antialias_mode, mut text_options) = match ctx.inner_text.hinting {
+                Hinting::Default => (
+                    D2D1_TEXT_ANTIALIAS_MODE_DEFAULT,
+                    D2D1_DRAW_TEXT_OPTIONS_NONE,
+                ),
+                Hinting::Pixel => (
+                    D2D1_TEXT_ANTIALIAS_MODE_CLEARTYPE,
+                    D2D1_DRAW_TEXT_OPTIONS_NONE,
+                ),
+                Hinting::Subpixel => (
+                    D2D1_TEXT_ANTIALIAS_MODE_GRAYSCALE,
+                    D2D1_DRAW_TEXT_OPTIONS_NO_SNAP,
+                ),
+            };
+            if ctx.inner_text.color_glyphs && ctx.color_fonts {
+                text_options |= D2D1_DRAW_TEXT_OPTIONS_ENABLE_COLOR_FONT;
+            }
+            ctx.rt.set_text_antialias_mode(antialias_mode);
             ctx.rt
                 .draw_text_layout(pos, &self.layout.borrow(), &black_brush, text_options);
         }
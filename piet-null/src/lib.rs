@@ -0,0 +1,261 @@
+//! A measuring-only backend for the Piet 2D graphics abstraction.
+//!
+//! A [`NullRenderContext`] draws nothing, but it lays out and measures text
+//! for real, with the same shaping as the `piet-svg` backend. It needs no
+//! graphics stack, window system, or GPU, so it is meant for testing layout
+//! code on any machine, such as in CI.
+//!
+//! Only fonts that are installed on the system, or loaded with
+//! [`Text::load_font`], can be measured.
+//!
+//! [`Text::load_font`]: piet::Text::load_font
+
+#![deny(clippy::trivially_copy_pass_by_ref)]
+
+use std::borrow::Cow;
+
+use piet::kurbo::{Affine, Point, Rect, Shape, Size};
+use piet::{
+    Capabilities, Color, Error, FixedGradient, HitTester, Image, ImageFormat, InterpolationMode,
+    IntoBrush, RenderContext, StrokeStyle, TextShapingQuality,
+};
+
+pub use piet_svg::{Text, TextLayout};
+
+/// A render context that draws nothing, but measures text.
+pub struct NullRenderContext {
+    text: Text,
+    transform: Affine,
+    global_alpha: f64,
+    saved: Vec<(Affine, f64)>,
+    hit_tester: HitTester,
+}
+
+#[derive(Clone, Debug)]
+pub struct Brush;
+
+/// An image, which only remembers its size.
+#[derive(Clone, Debug)]
+pub struct NullImage(Size);
+
+impl NullRenderContext {
+    /// Create a new context, which measures text with the system's fonts.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> NullRenderContext {
+        NullRenderContext {
+            text: Text::new(),
+            transform: Affine::IDENTITY,
+            global_alpha: 1.0,
+            saved: Vec::new(),
+            hit_tester: HitTester::new(),
+        }
+    }
+}
+
+impl RenderContext for NullRenderContext {
+    type Brush = Brush;
+
+    type Text = Text;
+    type TextLayout = TextLayout;
+
+    type Image = NullImage;
+
+    fn status(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn clear(&mut self, _region: impl Into<Option<Rect>>, _color: Color) {}
+
+    fn solid_brush(&mut self, _color: Color) -> Brush {
+        Brush
+    }
+
+    fn gradient(&mut self, _gradient: impl Into<FixedGradient>) -> Result<Brush, Error> {
+        Ok(Brush)
+    }
+
+    fn fill(&mut self, _shape: impl Shape, _brush: &impl IntoBrush<Self>) {}
+
+    fn fill_even_odd(&mut self, _shape: impl Shape, _brush: &impl IntoBrush<Self>) {}
+
+    fn fill_with_mask(
+        &mut self,
+        _shape: impl Shape,
+        _brush: &impl IntoBrush<Self>,
+        _mask: &Self::Image,
+    ) {
+    }
+
+    fn clip(&mut self, _shape: impl Shape) {}
+
+    fn stroke(&mut self, _shape: impl Shape, _brush: &impl IntoBrush<Self>, _width: f64) {}
+
+    fn stroke_styled(
+        &mut self,
+        _shape: impl Shape,
+        _brush: &impl IntoBrush<Self>,
+        _width: f64,
+        _style: &StrokeStyle,
+    ) {
+    }
+
+    fn text(&mut self) -> &mut Self::Text {
+        &mut self.text
+    }
+
+    fn draw_text(&mut self, _layout: &Self::TextLayout, _pos: impl Into<Point>) {}
+
+    fn save(&mut self) -> Result<(), Error> {
+        self.saved.push((self.transform, self.global_alpha));
+        Ok(())
+    }
+
+    fn restore(&mut self) -> Result<(), Error> {
+        let (transform, global_alpha) = self.saved.pop().ok_or(Error::StackUnbalance)?;
+        self.transform = transform;
+        self.global_alpha = global_alpha;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn transform(&mut self, transform: Affine) {
+        self.transform *= transform;
+    }
+
+    fn current_transform(&self) -> Affine {
+        self.transform
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        // text is measured, but nothing is drawn.
+        let mut capabilities = Capabilities::default();
+        capabilities.text_shaping_quality = TextShapingQuality::None;
+        capabilities
+    }
+
+    fn set_global_alpha(&mut self, alpha: f64) {
+        self.global_alpha = alpha;
+    }
+
+    fn global_alpha(&self) -> f64 {
+        self.global_alpha
+    }
+
+    fn hit_tester(&mut self) -> Option<&mut HitTester> {
+        Some(&mut self.hit_tester)
+    }
+
+    fn make_image(
+        &mut self,
+        width: usize,
+        height: usize,
+        buf: &[u8],
+        format: ImageFormat,
+    ) -> Result<Self::Image, Error> {
+        // check the buffer, so that code that passes the wrong one fails
+        // here as it would with other backends.
+        if buf.len() < width * height * format.bytes_per_pixel() {
+            return Err(Error::InvalidInput);
+        }
+        Ok(NullImage(Size::new(width as f64, height as f64)))
+    }
+
+    #[inline]
+    fn draw_image(
+        &mut self,
+        _image: &Self::Image,
+        _dst_rect: impl Into<Rect>,
+        _interp: InterpolationMode,
+    ) {
+    }
+
+    #[inline]
+    fn draw_image_area(
+        &mut self,
+        _image: &Self::Image,
+        _src_rect: impl Into<Rect>,
+        _dst_rect: impl Into<Rect>,
+        _interp: InterpolationMode,
+    ) {
+    }
+
+    fn capture_image_area(&mut self, _src_rect: impl Into<Rect>) -> Result<Self::Image, Error> {
+        // Nothing has been drawn.
+        Err(Error::NotSupported)
+    }
+
+    fn blurred_rect(&mut self, _rect: Rect, _blur_radius: f64, _brush: &impl IntoBrush<Self>) {}
+}
+
+impl IntoBrush<NullRenderContext> for Brush {
+    fn make_brush<'b>(
+        &'b self,
+        _piet: &mut NullRenderContext,
+        _bbox: impl FnOnce() -> Rect,
+    ) -> Cow<'b, Brush> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl Image for NullImage {
+    fn size(&self) -> Size {
+        self.0
+    }
+
+    fn sub_image(&self, rect: impl Into<Rect>) -> Self {
+        NullImage(piet::util::sub_image_view(self.0.to_rect(), rect.into()).size())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use piet::{FontFamily, Text as _, TextLayout as _, TextLayoutBuilder as _};
+
+    #[test]
+    fn transforms_are_saved() {
+        let mut rc = NullRenderContext::new();
+        rc.save().unwrap();
+        rc.transform(Affine::scale(2.0));
+        rc.set_global_alpha(0.5);
+        assert_eq!(rc.current_transform(), Affine::scale(2.0));
+        rc.restore().unwrap();
+        assert_eq!(rc.current_transform(), Affine::IDENTITY);
+        assert_eq!(rc.global_alpha(), 1.0);
+        assert!(matches!(rc.restore(), Err(Error::StackUnbalance)));
+    }
+
+    #[test]
+    fn images_keep_their_size() {
+        let mut rc = NullRenderContext::new();
+        let image = rc
+            .make_image(3, 2, &[0; 24], ImageFormat::RgbaSeparate)
+            .unwrap();
+        assert_eq!(image.size(), Size::new(3., 2.));
+        let sprite = image.sub_image(Rect::new(1., 1., 5., 5.));
+        assert_eq!(sprite.size(), Size::new(2., 1.));
+        assert!(rc
+            .make_image(3, 3, &[0; 24], ImageFormat::RgbaSeparate)
+            .is_err());
+    }
+
+    #[test]
+    fn text_is_measured() {
+        let mut rc = NullRenderContext::new();
+        let mut layout = |text: &str| {
+            rc.text()
+                .new_text_layout(text.to_string())
+                .font(FontFamily::SANS_SERIF, 12.0)
+                .build()
+                .unwrap()
+        };
+        let short = layout("hello");
+        let long = layout("hello, world");
+        assert!(short.size().width > 0.0);
+        assert!(short.size().height > 0.0);
+        assert!(long.size().width > short.size().width);
+    }
+}
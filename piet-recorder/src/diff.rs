@@ -0,0 +1,421 @@
+//! Finding the parts of a scene that changed between two recordings.
+
+use std::f64::consts::SQRT_2;
+use std::rc::Rc;
+
+use piet::kurbo::{Affine, Rect};
+use piet::LineJoin;
+
+use crate::{Op, SceneFragment};
+
+/// Comparing more operations than this at once would take too long, so if
+/// more than this many changed, they are all assumed to be damaged.
+const MAX_COMPARISONS: usize = 1 << 20;
+
+/// How far a blur reaches, in multiples of its radius. Backends draw blurs
+/// out to about 2.5 times the radius.
+const BLUR_EXTENT: f64 = 3.0;
+
+/// The parts of a scene that differ between two recordings of it.
+///
+/// The damage is a list of rectangles that don't overlap, in the coordinate
+/// space the fragments were recorded in. They are rounded out to whole
+/// units, and include a margin for antialiasing.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Damage {
+    rects: Vec<Rect>,
+    everything: bool,
+}
+
+/// A drawing operation, with the state it is drawn in.
+struct Item<'a> {
+    op: &'a Op,
+    transform: Affine,
+    global_alpha: f64,
+    clip: Option<Rc<Clip<'a>>>,
+    /// The area the operation draws in, or `None` if it isn't known.
+    bounds: Option<Rect>,
+}
+
+/// A clip or an opacity mask, and the clips that were applied before it.
+struct Clip<'a> {
+    /// The [`Op::Clip`] or [`Op::BeginOpacityMask`].
+    op: &'a Op,
+    transform: Affine,
+    parent: Option<Rc<Clip<'a>>>,
+}
+
+#[derive(Clone)]
+struct State<'a> {
+    transform: Affine,
+    global_alpha: f64,
+    clip: Option<Rc<Clip<'a>>>,
+    /// The bounds of the clip, if there is one.
+    clip_bounds: Option<Rect>,
+}
+
+impl Damage {
+    /// Returns `true` if nothing changed.
+    pub fn is_empty(&self) -> bool {
+        !self.everything && self.rects.is_empty()
+    }
+
+    /// Returns `true` if the whole scene may have changed, such as when the
+    /// background was cleared with a different color.
+    pub fn is_everything(&self) -> bool {
+        self.everything
+    }
+
+    /// The damaged rectangles.
+    ///
+    /// This is empty if [`is_everything`] is `true`.
+    ///
+    /// [`is_everything`]: Damage::is_everything
+    pub fn rects(&self) -> &[Rect] {
+        &self.rects
+    }
+
+    fn add(&mut self, bounds: Option<Rect>) {
+        if self.everything {
+            return;
+        }
+        let mut rect = match bounds {
+            Some(rect) => rect.inflate(1.0, 1.0).expand(),
+            None => {
+                self.everything = true;
+                self.rects.clear();
+                return;
+            }
+        };
+        // merge overlapping rects, so that nothing is drawn twice.
+        let mut i = 0;
+        while i < self.rects.len() {
+            if overlaps(self.rects[i], rect) {
+                rect = rect.union(self.rects.swap_remove(i));
+                i = 0;
+            } else {
+                i += 1;
+            }
+        }
+        self.rects.push(rect);
+    }
+}
+
+pub(crate) fn damage(old: &SceneFragment, new: &SceneFragment) -> Damage {
+    let mut damage = Damage::default();
+    if old == new {
+        return damage;
+    }
+    let old = items(old);
+    let new = items(new);
+
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let (old, new) = (&old[prefix..], &new[prefix..]);
+    let suffix = old
+        .iter()
+        .rev()
+        .zip(new.iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old, new) = (&old[..old.len() - suffix], &new[..new.len() - suffix]);
+
+    let (old_kept, new_kept) = common_subsequence(old, new);
+    for (item, kept) in old.iter().zip(old_kept).chain(new.iter().zip(new_kept)) {
+        if !kept {
+            damage.add(item.bounds);
+        }
+    }
+    damage
+}
+
+/// The area that a drawing operation can change when it is drawn with
+/// `transform`, or `None` if it isn't known.
+pub(crate) fn op_bounds(op: &Op, transform: Affine) -> Option<Rect> {
+    match op {
+        // clearing ignores the transform.
+        Op::Clear(region, _) => *region,
+        Op::Fill { shape, .. } | Op::FillWithMask { shape, .. } => {
+            Some(transform.transform_rect_bbox(shape.bounding_box()))
+        }
+        Op::Stroke {
+            shape,
+            width,
+            style,
+            ..
+        } => {
+            // miters reach furthest, followed by square caps.
+            let (join, scale) = match style {
+                Some(style) => (style.line_join, style.scale),
+                None => (LineJoin::default(), true),
+            };
+            let reach = match join {
+                LineJoin::Miter { limit } => limit.max(SQRT_2),
+                _ => SQRT_2,
+            } * width
+                / 2.0;
+            let bounds = shape.bounding_box();
+            if scale {
+                Some(transform.transform_rect_bbox(bounds.inflate(reach, reach)))
+            } else {
+                Some(transform.transform_rect_bbox(bounds).inflate(reach, reach))
+            }
+        }
+        Op::DrawText { bounds, .. } => bounds.map(|bounds| transform.transform_rect_bbox(bounds)),
+        Op::DrawImage { dst_rect, .. } => Some(transform.transform_rect_bbox(*dst_rect)),
+        Op::BlurredRect {
+            rect, blur_radius, ..
+        } => {
+            let reach = BLUR_EXTENT * blur_radius;
+            Some(transform.transform_rect_bbox(rect.inflate(reach, reach)))
+        }
+        Op::Clip(_)
+        | Op::Save
+        | Op::Restore
+        | Op::Transform(_)
+        | Op::GlobalAlpha(_)
+        | Op::Fragment(_)
+        | Op::Annotate { .. }
+        | Op::BeginGroup(_)
+        | Op::EndGroup
+        | Op::BeginOpacityMask { .. }
+        | Op::EndOpacityMask
+        | Op::HitRegion { .. } => None,
+    }
+}
+
+/// Returns `true` if two rects share some area.
+pub(crate) fn overlaps(a: Rect, b: Rect) -> bool {
+    !a.intersect(b).is_empty()
+}
+
+/// The drawing operations in a fragment, including those of the fragments
+/// it draws, without any that draw nothing.
+fn items(fragment: &SceneFragment) -> Vec<Item<'_>> {
+    let mut items = Vec::new();
+    let state = State {
+        transform: Affine::IDENTITY,
+        global_alpha: 1.0,
+        clip: None,
+        clip_bounds: None,
+    };
+    flatten(&fragment.ops, state, &mut items);
+    items
+}
+
+fn flatten<'a>(ops: &'a [Op], mut state: State<'a>, items: &mut Vec<Item<'a>>) {
+    let mut saved = Vec::new();
+    for op in ops {
+        match op {
+            Op::Save => saved.push(state.clone()),
+            Op::Restore | Op::EndOpacityMask => {
+                if let Some(restored) = saved.pop() {
+                    state = restored;
+                }
+            }
+            Op::Transform(transform) => state.transform *= *transform,
+            Op::GlobalAlpha(alpha) => state.global_alpha = *alpha,
+            Op::Clip(shape) => state.clip(op, shape.bounding_box()),
+            // nothing is drawn outside of an opacity mask, so it is kept
+            // with the clips, and a change to it damages what is drawn
+            // through it.
+            Op::BeginOpacityMask { rect, .. } => {
+                saved.push(state.clone());
+                state.clip(op, *rect);
+            }
+            Op::Fragment(fragment) => flatten(&fragment.ops, state.clone(), items),
+            // these don't draw anything.
+            Op::Annotate { .. } | Op::BeginGroup(_) | Op::EndGroup | Op::HitRegion { .. } => (),
+            Op::Clear(..) => items.push(Item {
+                op,
+                transform: state.transform,
+                global_alpha: state.global_alpha,
+                clip: state.clip.clone(),
+                bounds: op_bounds(op, state.transform),
+            }),
+            _ => {
+                let bounds = match (op_bounds(op, state.transform), state.clip_bounds) {
+                    (Some(bounds), Some(clip_bounds)) => Some(bounds.intersect(clip_bounds)),
+                    (None, clip_bounds) => clip_bounds,
+                    (bounds, None) => bounds,
+                };
+                if bounds.is_some_and(|bounds| bounds.is_empty()) {
+                    continue;
+                }
+                items.push(Item {
+                    op,
+                    transform: state.transform,
+                    global_alpha: state.global_alpha,
+                    clip: state.clip.clone(),
+                    bounds,
+                });
+            }
+        }
+    }
+}
+
+impl<'a> State<'a> {
+    /// Clip to `bounds`, in user space, by the clip or mask of `op`.
+    fn clip(&mut self, op: &'a Op, bounds: Rect) {
+        let bounds = self.transform.transform_rect_bbox(bounds);
+        self.clip_bounds = Some(match self.clip_bounds {
+            Some(clip_bounds) => clip_bounds.intersect(bounds),
+            None => bounds,
+        });
+        self.clip = Some(Rc::new(Clip {
+            op,
+            transform: self.transform,
+            parent: self.clip.take(),
+        }));
+    }
+}
+
+/// Find a longest common subsequence of `old` and `new`, returning which
+/// items of each are in it.
+fn common_subsequence(old: &[Item], new: &[Item]) -> (Vec<bool>, Vec<bool>) {
+    let mut old_kept = vec![false; old.len()];
+    let mut new_kept = vec![false; new.len()];
+    if old.is_empty() || new.is_empty() || old.len() * new.len() > MAX_COMPARISONS {
+        return (old_kept, new_kept);
+    }
+    // lengths[i * stride + j] is the length of the longest common
+    // subsequence of old[i..] and new[j..].
+    let stride = new.len() + 1;
+    let mut lengths = vec![0u32; (old.len() + 1) * stride];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i * stride + j] = if old[i] == new[j] {
+                lengths[(i + 1) * stride + j + 1] + 1
+            } else {
+                lengths[(i + 1) * stride + j].max(lengths[i * stride + j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            old_kept[i] = true;
+            new_kept[j] = true;
+            i += 1;
+            j += 1;
+        } else if lengths[(i + 1) * stride + j] >= lengths[i * stride + j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    (old_kept, new_kept)
+}
+
+impl PartialEq for Item<'_> {
+    fn eq(&self, other: &Item) -> bool {
+        self.transform == other.transform
+            && self.global_alpha == other.global_alpha
+            && same_clip(&self.clip, &other.clip)
+            && (std::ptr::eq(self.op, other.op) || self.op == other.op)
+    }
+}
+
+fn same_clip(a: &Option<Rc<Clip>>, b: &Option<Rc<Clip>>) -> bool {
+    match (a, b) {
+        (None, None) => true,
+        (Some(a), Some(b)) => {
+            Rc::ptr_eq(a, b)
+                || (a.transform == b.transform && a.op == b.op && same_clip(&a.parent, &b.parent))
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::RecordingRenderContext;
+    use piet::kurbo::RoundedRect;
+    use piet::{Color, NullText, RenderContext, Text, TextLayoutBuilder};
+    use piet_tiny_skia::tiny_skia::Pixmap;
+    use piet_tiny_skia::TinySkiaRenderContext;
+
+    /// A frame of a small scene, with a rounded rect at `x`.
+    fn frame(x: f64, color: Color) -> SceneFragment {
+        let mut rc = RecordingRenderContext::new(NullText);
+        rc.clear(None, Color::WHITE);
+        rc.fill(Rect::new(0., 0., 10., 40.), &Color::BLUE);
+        rc.save().unwrap();
+        rc.transform(Affine::translate((x, 0.)));
+        rc.clip(Rect::new(0., 0., 20., 20.));
+        rc.fill(RoundedRect::new(2., 2., 18., 18., 4.), &color);
+        rc.restore().unwrap();
+        rc.stroke(Rect::new(40., 30., 60., 35.), &Color::BLACK, 2.0);
+        rc.into_fragment()
+    }
+
+    fn draw(pixmap: &mut Pixmap, f: impl FnOnce(&mut TinySkiaRenderContext)) {
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+        f(&mut rc);
+    }
+
+    #[test]
+    fn identical_frames_have_no_damage() {
+        let damage = frame(20., Color::RED).damage(&frame(20., Color::RED));
+        assert!(damage.is_empty());
+    }
+
+    #[test]
+    fn moving_a_shape_damages_where_it_was_and_is() {
+        let damage = frame(20., Color::RED).damage(&frame(30., Color::RED));
+        assert!(!damage.is_everything());
+        // the two positions of the rounded rect overlap, so they are merged.
+        assert_eq!(damage.rects(), [Rect::new(21., 1., 49., 19.)]);
+
+        let damage = frame(20., Color::RED).damage(&frame(20., Color::GREEN));
+        assert_eq!(damage.rects(), [Rect::new(21., 1., 39., 19.)]);
+    }
+
+    #[test]
+    fn fading_a_shape_damages_it() {
+        let fill = |alpha| {
+            let mut rc = RecordingRenderContext::new(NullText);
+            rc.set_global_alpha(alpha);
+            rc.fill(Rect::new(10., 10., 20., 20.), &Color::BLUE);
+            rc.into_fragment()
+        };
+        assert!(fill(1.0).damage(&fill(1.0)).is_empty());
+        assert_eq!(
+            fill(1.0).damage(&fill(0.5)).rects(),
+            [Rect::new(9., 9., 21., 21.)]
+        );
+    }
+
+    #[test]
+    fn unknown_bounds_damage_everything() {
+        let mut rc = RecordingRenderContext::new(NullText);
+        let layout = rc.text().new_text_layout("hello").build().unwrap();
+        rc.draw_text(&layout, (0., 0.));
+        let text = rc.into_fragment();
+        assert!(SceneFragment::default().damage(&text).is_everything());
+
+        let mut rc = RecordingRenderContext::new(NullText);
+        rc.clear(None, Color::BLACK);
+        assert!(frame(20., Color::RED)
+            .damage(&rc.into_fragment())
+            .is_everything());
+    }
+
+    #[test]
+    fn damaged_replay_matches_full_replay() {
+        let old = frame(20., Color::RED);
+        let new = frame(27.5, Color::GREEN);
+        let damage = old.damage(&new);
+
+        let mut full = Pixmap::new(64, 48).unwrap();
+        draw(&mut full, |rc| new.replay(rc).unwrap());
+
+        let mut partial = Pixmap::new(64, 48).unwrap();
+        draw(&mut partial, |rc| old.replay(rc).unwrap());
+        draw(&mut partial, |rc| {
+            new.replay_damaged(rc, &damage).unwrap();
+            assert!(rc.restore().is_err(), "replay left a saved state");
+        });
+        assert!(full == partial);
+    }
+}
@@ -0,0 +1,1212 @@
+//! A compact binary encoding of scene fragments.
+//!
+//! The encoding starts with [`MAGIC`] and the version of the format, which
+//! is followed by the outermost fragment. Numbers are little-endian, lengths
+//! and counts are LEB128 varints, and coordinates are kept as `f64`s, so a
+//! decoded fragment is the same as the one that was encoded.
+//!
+//! Images, gradients, text layouts, fonts and nested fragments are often
+//! shared by many operations, so each of them is only written once. Where one
+//! is used, a varint of `0` means that its definition follows, and `n + 1`
+//! refers to the `n`th one of its kind that was defined before.
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt;
+use std::ops::Range;
+use std::sync::Arc;
+
+use piet::kurbo::{Affine, BezPath, PathEl, Point, Rect, RoundedRect, RoundedRectRadii, Size};
+use piet::{
+    BaselineAlignment, Color, FixedGradient, FixedLinearGradient, FixedRadialGradient, FontFamily,
+    FontFeature, FontStyle, FontVariation, FontWeight, GradientStop, ImageFormat,
+    InterpolationMode, LineCap, LineHeight, LineJoin, Overflow, ScriptPosition, TextAlignment,
+    TextAttribute, WritingMode,
+};
+
+use crate::text::{BuilderCall, TextDescription};
+use crate::{Brush, BrushKind, Geometry, ImageData, Op, SceneFragment, Style};
+
+/// The bytes that an encoded fragment starts with.
+const MAGIC: &[u8; 4] = b"PSCN";
+
+/// The version of the encoding, which is changed whenever it changes in a
+/// way that older decoders can't read.
+pub(crate) const VERSION: u32 = 2;
+
+/// How deeply fragments may be nested in an encoding, so that decoding and
+/// replaying don't run out of stack.
+const MAX_DEPTH: usize = 256;
+
+/// The error returned when an encoded fragment can't be decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DecodeError {
+    /// The data isn't an encoded fragment.
+    NotAFragment,
+    /// The fragment was encoded with a version of the format that this
+    /// version of the crate can't read.
+    UnsupportedVersion(u32),
+    /// The data ends before the fragment does.
+    Truncated,
+    /// The data is damaged, or describes a fragment that couldn't have been
+    /// recorded.
+    Invalid,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::NotAFragment => write!(f, "not an encoded scene fragment"),
+            DecodeError::UnsupportedVersion(version) => {
+                write!(f, "unsupported scene encoding version {}", version)
+            }
+            DecodeError::Truncated => write!(f, "encoded scene fragment is truncated"),
+            DecodeError::Invalid => write!(f, "encoded scene fragment is invalid"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<DecodeError> for piet::Error {
+    fn from(e: DecodeError) -> piet::Error {
+        piet::Error::BackendError(Box::new(e))
+    }
+}
+
+pub(crate) fn encode(fragment: &SceneFragment) -> Vec<u8> {
+    let mut encoder = Encoder::default();
+    encoder.buf.extend_from_slice(MAGIC);
+    encoder.varint(VERSION as u64);
+    encoder.fragment(fragment);
+    encoder.buf
+}
+
+pub(crate) fn decode(data: &[u8]) -> Result<SceneFragment, DecodeError> {
+    let mut decoder = Decoder {
+        data,
+        ..Decoder::default()
+    };
+    if decoder.bytes(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+        return Err(DecodeError::NotAFragment);
+    }
+    let version = decoder.varint()?;
+    if version != VERSION as u64 {
+        return Err(DecodeError::UnsupportedVersion(
+            version.try_into().unwrap_or(u32::MAX),
+        ));
+    }
+    let fragment = decoder.fragment()?;
+    if !decoder.data.is_empty() {
+        return Err(DecodeError::Invalid);
+    }
+    Ok(fragment)
+}
+
+/// The index of each shared resource that has been written, by address.
+#[derive(Default)]
+struct Encoder {
+    buf: Vec<u8>,
+    fragments: HashMap<*const Op, u64>,
+    images: HashMap<*const u8, u64>,
+    gradients: HashMap<*const FixedGradient, u64>,
+    layouts: HashMap<*const TextDescription, u64>,
+    fonts: HashMap<*const u8, u64>,
+}
+
+/// Write a reference to a shared resource, returning `true` if it hasn't
+/// been written before, and its definition should follow.
+fn write_shared<T>(buf: &mut Vec<u8>, written: &mut HashMap<*const T, u64>, ptr: *const T) -> bool {
+    let n = written.len() as u64;
+    match written.get(&ptr) {
+        Some(index) => {
+            write_varint(buf, index + 1);
+            false
+        }
+        None => {
+            written.insert(ptr, n);
+            write_varint(buf, 0);
+            true
+        }
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buf.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+impl Encoder {
+    fn varint(&mut self, n: u64) {
+        write_varint(&mut self.buf, n);
+    }
+
+    fn len(&mut self, n: usize) {
+        self.varint(n as u64);
+    }
+
+    fn u8(&mut self, n: u8) {
+        self.buf.push(n);
+    }
+
+    fn bool(&mut self, b: bool) {
+        self.u8(b as u8);
+    }
+
+    fn f64(&mut self, x: f64) {
+        self.buf.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.len(bytes.len());
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn point(&mut self, p: Point) {
+        self.f64(p.x);
+        self.f64(p.y);
+    }
+
+    fn rect(&mut self, rect: Rect) {
+        self.point(rect.origin());
+        self.point(Point::new(rect.x1, rect.y1));
+    }
+
+    fn option_rect(&mut self, rect: Option<Rect>) {
+        self.bool(rect.is_some());
+        if let Some(rect) = rect {
+            self.rect(rect);
+        }
+    }
+
+    fn color(&mut self, color: Color) {
+        self.buf
+            .extend_from_slice(&color.as_rgba_u32().to_le_bytes());
+    }
+
+    fn fragment(&mut self, fragment: &SceneFragment) {
+        let ptr = fragment.ops.as_ptr();
+        if write_shared(&mut self.buf, &mut self.fragments, ptr) {
+            self.len(fragment.ops.len());
+            for op in fragment.ops.iter() {
+                self.op(op);
+            }
+        }
+    }
+
+    fn op(&mut self, op: &Op) {
+        match op {
+            Op::Clear(region, color) => {
+                self.u8(0);
+                self.option_rect(*region);
+                self.color(*color);
+            }
+            Op::Fill {
+                shape,
+                brush,
+                even_odd,
+            } => {
+                self.u8(1);
+                self.geometry(shape);
+                self.brush(brush);
+                self.bool(*even_odd);
+            }
+            Op::FillWithMask { shape, brush, mask } => {
+                self.u8(2);
+                self.geometry(shape);
+                self.brush(brush);
+                self.image(mask);
+            }
+            Op::Stroke {
+                shape,
+                brush,
+                width,
+                style,
+            } => {
+                self.u8(3);
+                self.geometry(shape);
+                self.brush(brush);
+                self.f64(*width);
+                self.bool(style.is_some());
+                if let Some(style) = style {
+                    self.style(style);
+                }
+            }
+            Op::Clip(shape) => {
+                self.u8(4);
+                self.geometry(shape);
+            }
+            Op::DrawText {
+                layout,
+                pos,
+                bounds,
+            } => {
+                self.u8(5);
+                self.layout(layout);
+                self.point(*pos);
+                self.option_rect(*bounds);
+            }
+            Op::Save => self.u8(6),
+            Op::Restore => self.u8(7),
+            Op::Transform(transform) => {
+                self.u8(8);
+                for x in transform.as_coeffs() {
+                    self.f64(x);
+                }
+            }
+            Op::GlobalAlpha(alpha) => {
+                self.u8(9);
+                self.f64(*alpha);
+            }
+            Op::DrawImage {
+                image,
+                src_rect,
+                dst_rect,
+                interp,
+            } => {
+                self.u8(10);
+                self.image(image);
+                self.option_rect(*src_rect);
+                self.rect(*dst_rect);
+                self.u8(match interp {
+                    InterpolationMode::NearestNeighbor => 0,
+                    InterpolationMode::Bilinear => 1,
+                    InterpolationMode::Bicubic => 2,
+                    InterpolationMode::HighQuality => 3,
+                });
+            }
+            Op::BlurredRect {
+                rect,
+                blur_radius,
+                brush,
+            } => {
+                self.u8(11);
+                self.rect(*rect);
+                self.f64(*blur_radius);
+                self.brush(brush);
+            }
+            Op::Fragment(fragment) => {
+                self.u8(12);
+                self.fragment(fragment);
+            }
+            Op::Annotate { key, value } => {
+                self.u8(13);
+                self.bytes(key.as_bytes());
+                self.bytes(value.as_bytes());
+            }
+            Op::BeginGroup(role) => {
+                self.u8(14);
+                self.bytes(role.as_bytes());
+            }
+            Op::EndGroup => self.u8(15),
+            Op::BeginOpacityMask { mask, rect } => {
+                self.u8(17);
+                self.image(mask);
+                self.rect(*rect);
+            }
+            Op::EndOpacityMask => self.u8(18),
+            Op::HitRegion { id, shape, stroke } => {
+                self.u8(16);
+                self.varint(*id);
+                self.geometry(shape);
+                self.bool(stroke.is_some());
+                if let Some((width, style)) = stroke {
+                    self.f64(*width);
+                    self.style(style);
+                }
+            }
+        }
+    }
+
+    fn geometry(&mut self, geometry: &Geometry) {
+        match geometry {
+            Geometry::Rect(rect) => {
+                self.u8(0);
+                self.rect(*rect);
+            }
+            Geometry::RoundedRect(rounded_rect) => {
+                self.u8(1);
+                self.rect(rounded_rect.rect());
+                let radii = rounded_rect.radii();
+                self.f64(radii.top_left);
+                self.f64(radii.top_right);
+                self.f64(radii.bottom_right);
+                self.f64(radii.bottom_left);
+            }
+            Geometry::Path(path) => {
+                self.u8(2);
+                self.len(path.elements().len());
+                for el in path.elements() {
+                    match *el {
+                        PathEl::MoveTo(p) => {
+                            self.u8(0);
+                            self.point(p);
+                        }
+                        PathEl::LineTo(p) => {
+                            self.u8(1);
+                            self.point(p);
+                        }
+                        PathEl::QuadTo(p1, p2) => {
+                            self.u8(2);
+                            self.point(p1);
+                            self.point(p2);
+                        }
+                        PathEl::CurveTo(p1, p2, p3) => {
+                            self.u8(3);
+                            self.point(p1);
+                            self.point(p2);
+                            self.point(p3);
+                        }
+                        PathEl::ClosePath => self.u8(4),
+                    }
+                }
+            }
+        }
+    }
+
+    fn brush(&mut self, brush: &Brush) {
+        match &brush.0 {
+            BrushKind::Solid(color) => {
+                self.u8(0);
+                self.color(*color);
+            }
+            BrushKind::Gradient(gradient) => {
+                self.u8(1);
+                if !write_shared(&mut self.buf, &mut self.gradients, Arc::as_ptr(gradient)) {
+                    return;
+                }
+                match &**gradient {
+                    FixedGradient::Linear(linear) => {
+                        self.u8(0);
+                        self.point(linear.start);
+                        self.point(linear.end);
+                        self.stops(&linear.stops);
+                    }
+                    FixedGradient::Radial(radial) => {
+                        self.u8(1);
+                        self.point(radial.center);
+                        self.point(radial.origin_offset.to_point());
+                        self.f64(radial.radius);
+                        self.stops(&radial.stops);
+                    }
+                }
+            }
+        }
+    }
+
+    fn stops(&mut self, stops: &[GradientStop]) {
+        self.len(stops.len());
+        for stop in stops {
+            self.buf.extend_from_slice(&stop.pos.to_le_bytes());
+            self.color(stop.color);
+        }
+    }
+
+    fn style(&mut self, style: &Style) {
+        match style.line_join {
+            LineJoin::Miter { limit } => {
+                self.u8(0);
+                self.f64(limit);
+            }
+            LineJoin::Round => self.u8(1),
+            LineJoin::Bevel => self.u8(2),
+        }
+        self.u8(line_cap_tag(style.line_cap));
+        self.u8(style.dash_cap.map(line_cap_tag).unwrap_or(3));
+        self.len(style.dash_pattern.len());
+        for x in style.dash_pattern.iter() {
+            self.f64(*x);
+        }
+        self.f64(style.dash_offset);
+        self.bool(style.scale);
+    }
+
+    fn image(&mut self, image: &Arc<ImageData>) {
+        // sub-images share the pixels of the image they are cut from, and
+        // only add their area.
+        if write_shared(&mut self.buf, &mut self.images, image.buf.as_ptr()) {
+            self.image_pixels(image);
+        }
+        self.option_rect(image.view);
+    }
+
+    fn image_pixels(&mut self, image: &ImageData) {
+        self.len(image.width);
+        self.len(image.height);
+        self.u8(match image.format {
+            ImageFormat::Grayscale => 0,
+            ImageFormat::Rgb => 1,
+            ImageFormat::RgbaSeparate => 2,
+            ImageFormat::RgbaPremul => 3,
+            ImageFormat::BgraSeparate => 4,
+            ImageFormat::BgraPremul => 5,
+            ImageFormat::Rgba16 => 6,
+            format => panic!("encoding images in {:?} is not supported", format),
+        });
+        self.bytes(&image.buf);
+    }
+
+    fn layout(&mut self, layout: &Arc<TextDescription>) {
+        if !write_shared(&mut self.buf, &mut self.layouts, Arc::as_ptr(layout)) {
+            return;
+        }
+        self.bytes(layout.text.as_bytes());
+        self.len(layout.fonts.len());
+        for font in &layout.fonts {
+            if write_shared(&mut self.buf, &mut self.fonts, font.as_ptr()) {
+                self.bytes(font);
+            }
+        }
+        self.len(layout.calls.len());
+        for call in &layout.calls {
+            self.call(call);
+        }
+    }
+
+    fn call(&mut self, call: &BuilderCall) {
+        match call {
+            BuilderCall::MaxWidth(width) => {
+                self.u8(0);
+                self.f64(*width);
+            }
+            BuilderCall::MaxHeight(height) => {
+                self.u8(1);
+                self.f64(*height);
+            }
+            BuilderCall::Overflow(overflow) => {
+                self.u8(2);
+                self.u8(match overflow {
+                    Overflow::Visible => 0,
+                    Overflow::Clip => 1,
+                    Overflow::Ellipsis => 2,
+                });
+            }
+            BuilderCall::Alignment(alignment) => {
+                self.u8(3);
+                self.u8(alignment_tag(*alignment));
+            }
+            BuilderCall::ParagraphAlignment(range, alignment) => {
+                self.u8(4);
+                self.range(range);
+                self.u8(alignment_tag(*alignment));
+            }
+            BuilderCall::WritingMode(mode) => {
+                self.u8(5);
+                self.u8(match mode {
+                    WritingMode::HorizontalTb => 0,
+                    WritingMode::VerticalRl => 1,
+                });
+            }
+            BuilderCall::LineHeight(line_height) => {
+                self.u8(6);
+                match line_height {
+                    LineHeight::Normal => self.u8(0),
+                    LineHeight::Relative(x) => {
+                        self.u8(1);
+                        self.f64(*x);
+                    }
+                    LineHeight::Absolute(x) => {
+                        self.u8(2);
+                        self.f64(*x);
+                    }
+                }
+            }
+            BuilderCall::ParagraphSpacing(spacing) => {
+                self.u8(7);
+                self.f64(*spacing);
+            }
+            BuilderCall::TabStops(stops) => {
+                self.u8(8);
+                self.len(stops.len());
+                for x in stops.iter() {
+                    self.f64(*x);
+                }
+            }
+            BuilderCall::TabWidth(width) => {
+                self.u8(9);
+                self.f64(*width);
+            }
+            BuilderCall::DefaultAttribute(attribute) => {
+                self.u8(10);
+                self.attribute(attribute);
+            }
+            BuilderCall::RangeAttribute(range, attribute) => {
+                self.u8(11);
+                self.range(range);
+                self.attribute(attribute);
+            }
+            BuilderCall::InlineBox(index, size, alignment) => {
+                self.u8(12);
+                self.len(*index);
+                self.f64(size.width);
+                self.f64(size.height);
+                match alignment {
+                    BaselineAlignment::Bottom => self.u8(0),
+                    BaselineAlignment::Center => self.u8(1),
+                    BaselineAlignment::Baseline(y) => {
+                        self.u8(2);
+                        self.f64(*y);
+                    }
+                }
+            }
+        }
+    }
+
+    fn range(&mut self, range: &Range<usize>) {
+        self.len(range.start);
+        self.len(range.end);
+    }
+
+    fn attribute(&mut self, attribute: &TextAttribute) {
+        match attribute {
+            TextAttribute::FontFamily(family) => {
+                self.u8(0);
+                let generic = [
+                    FontFamily::SERIF,
+                    FontFamily::SANS_SERIF,
+                    FontFamily::MONOSPACE,
+                    FontFamily::SYSTEM_UI,
+                ]
+                .iter()
+                .position(|generic| generic == family);
+                match generic {
+                    Some(i) => self.u8(i as u8),
+                    None => {
+                        self.u8(4);
+                        self.bytes(family.name().as_bytes());
+                    }
+                }
+            }
+            TextAttribute::FontSize(size) => {
+                self.u8(1);
+                self.f64(*size);
+            }
+            TextAttribute::Weight(weight) => {
+                self.u8(2);
+                self.varint(weight.to_raw().into());
+            }
+            TextAttribute::TextColor(color) => {
+                self.u8(3);
+                self.color(*color);
+            }
+            TextAttribute::BackgroundColor(color) => {
+                self.u8(4);
+                self.color(*color);
+            }
+            TextAttribute::Style(style) => {
+                self.u8(5);
+                self.bool(*style == FontStyle::Italic);
+            }
+            TextAttribute::Underline(underline) => {
+                self.u8(6);
+                self.bool(*underline);
+            }
+            TextAttribute::Strikethrough(strikethrough) => {
+                self.u8(7);
+                self.bool(*strikethrough);
+            }
+            TextAttribute::LetterSpacing(spacing) => {
+                self.u8(8);
+                self.f64(*spacing);
+            }
+            TextAttribute::WordSpacing(spacing) => {
+                self.u8(9);
+                self.f64(*spacing);
+            }
+            TextAttribute::FontVariations(variations) => {
+                self.u8(10);
+                self.len(variations.len());
+                for variation in variations.iter() {
+                    self.buf.extend_from_slice(&variation.tag);
+                    self.f64(variation.value);
+                }
+            }
+            TextAttribute::FontFeatures(features) => {
+                self.u8(11);
+                self.len(features.len());
+                for feature in features.iter() {
+                    self.buf.extend_from_slice(&feature.tag);
+                    self.varint(feature.value.into());
+                }
+            }
+            TextAttribute::BaselineShift(shift) => {
+                self.u8(12);
+                self.f64(*shift);
+            }
+            TextAttribute::ScriptPosition(position) => {
+                self.u8(13);
+                self.u8(match position {
+                    ScriptPosition::Normal => 0,
+                    ScriptPosition::Superscript => 1,
+                    ScriptPosition::Subscript => 2,
+                });
+            }
+        }
+    }
+}
+
+fn line_cap_tag(cap: LineCap) -> u8 {
+    match cap {
+        LineCap::Butt => 0,
+        LineCap::Round => 1,
+        LineCap::Square => 2,
+    }
+}
+
+fn alignment_tag(alignment: TextAlignment) -> u8 {
+    match alignment {
+        TextAlignment::Start => 0,
+        TextAlignment::End => 1,
+        TextAlignment::Center => 2,
+        TextAlignment::Justified => 3,
+    }
+}
+
+/// The shared resources that have been read, in the order they were defined.
+#[derive(Default)]
+struct Decoder<'a> {
+    data: &'a [u8],
+    fragments: Vec<SceneFragment>,
+    images: Vec<Arc<ImageData>>,
+    gradients: Vec<Arc<FixedGradient>>,
+    layouts: Vec<Arc<TextDescription>>,
+    fonts: Vec<Arc<[u8]>>,
+    /// The number of fragments that are being read, one inside the other.
+    depth: usize,
+}
+
+/// Read a reference to a shared resource, returning `Ok(None)` if its
+/// definition follows.
+fn read_shared<'a, T: Clone>(
+    decoder: &mut Decoder<'a>,
+    read: impl for<'d> FnOnce(&'d Decoder<'a>) -> &'d [T],
+) -> Result<Option<T>, DecodeError> {
+    match decoder.varint()? {
+        0 => Ok(None),
+        n => read(decoder)
+            .get(n as usize - 1)
+            .cloned()
+            .map(Some)
+            .ok_or(DecodeError::Invalid),
+    }
+}
+
+impl<'a> Decoder<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], DecodeError> {
+        if n > self.data.len() {
+            return Err(DecodeError::Truncated);
+        }
+        let (bytes, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, DecodeError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(DecodeError::Invalid),
+        }
+    }
+
+    fn varint(&mut self) -> Result<u64, DecodeError> {
+        let mut n = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            n |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(DecodeError::Invalid)
+    }
+
+    fn len(&mut self) -> Result<usize, DecodeError> {
+        self.varint()?.try_into().map_err(|_| DecodeError::Invalid)
+    }
+
+    /// Read a count of items that take at least `min_size` bytes each,
+    /// checking that there is room for them.
+    fn count(&mut self, min_size: usize) -> Result<usize, DecodeError> {
+        let n = self.len()?;
+        if n.saturating_mul(min_size) > self.data.len() {
+            return Err(DecodeError::Truncated);
+        }
+        Ok(n)
+    }
+
+    fn f64(&mut self) -> Result<f64, DecodeError> {
+        Ok(f64::from_le_bytes(self.array()?))
+    }
+
+    fn byte_string(&mut self) -> Result<&'a [u8], DecodeError> {
+        let n = self.len()?;
+        self.bytes(n)
+    }
+
+    fn str(&mut self) -> Result<&'a str, DecodeError> {
+        std::str::from_utf8(self.byte_string()?).map_err(|_| DecodeError::Invalid)
+    }
+
+    fn point(&mut self) -> Result<Point, DecodeError> {
+        Ok(Point::new(self.f64()?, self.f64()?))
+    }
+
+    fn rect(&mut self) -> Result<Rect, DecodeError> {
+        Ok(Rect::new(
+            self.f64()?,
+            self.f64()?,
+            self.f64()?,
+            self.f64()?,
+        ))
+    }
+
+    fn option_rect(&mut self) -> Result<Option<Rect>, DecodeError> {
+        Ok(match self.bool()? {
+            true => Some(self.rect()?),
+            false => None,
+        })
+    }
+
+    fn color(&mut self) -> Result<Color, DecodeError> {
+        Ok(Color::from_rgba32_u32(u32::from_le_bytes(self.array()?)))
+    }
+
+    fn fragment(&mut self) -> Result<SceneFragment, DecodeError> {
+        if let Some(fragment) = read_shared(self, |d| &d.fragments)? {
+            return Ok(fragment);
+        }
+        if self.depth >= MAX_DEPTH {
+            return Err(DecodeError::Invalid);
+        }
+        self.depth += 1;
+        // the fragment is numbered before the ones inside it, as it is when
+        // it is encoded.
+        let index = self.fragments.len();
+        self.fragments.push(SceneFragment::default());
+        let n = self.count(1)?;
+        let mut ops = Vec::with_capacity(n);
+        // restores and ends must follow the saves and begins that they
+        // match, as they do when they are recorded.
+        let (mut saves, mut groups) = (Vec::new(), 0usize);
+        for _ in 0..n {
+            let op = self.op()?;
+            match op {
+                Op::Save => saves.push(false),
+                Op::BeginOpacityMask { .. } => saves.push(true),
+                Op::Restore | Op::EndOpacityMask => {
+                    let mask = matches!(op, Op::EndOpacityMask);
+                    if saves.pop() != Some(mask) {
+                        return Err(DecodeError::Invalid);
+                    }
+                }
+                Op::BeginGroup(_) => groups += 1,
+                Op::EndGroup => groups = groups.checked_sub(1).ok_or(DecodeError::Invalid)?,
+                _ => (),
+            }
+            ops.push(op);
+        }
+        self.depth -= 1;
+        let fragment = SceneFragment { ops: ops.into() };
+        self.fragments[index] = fragment.clone();
+        Ok(fragment)
+    }
+
+    fn op(&mut self) -> Result<Op, DecodeError> {
+        Ok(match self.u8()? {
+            0 => Op::Clear(self.option_rect()?, self.color()?),
+            1 => Op::Fill {
+                shape: self.geometry()?,
+                brush: self.brush()?,
+                even_odd: self.bool()?,
+            },
+            2 => Op::FillWithMask {
+                shape: self.geometry()?,
+                brush: self.brush()?,
+                mask: self.image()?,
+            },
+            3 => Op::Stroke {
+                shape: self.geometry()?,
+                brush: self.brush()?,
+                width: self.f64()?,
+                style: match self.bool()? {
+                    true => Some(self.style()?),
+                    false => None,
+                },
+            },
+            4 => Op::Clip(self.geometry()?),
+            5 => Op::DrawText {
+                layout: self.layout()?,
+                pos: self.point()?,
+                bounds: self.option_rect()?,
+            },
+            6 => Op::Save,
+            7 => Op::Restore,
+            8 => {
+                let mut coeffs = [0.0; 6];
+                for x in &mut coeffs {
+                    *x = self.f64()?;
+                }
+                Op::Transform(Affine::new(coeffs))
+            }
+            9 => Op::GlobalAlpha(self.f64()?),
+            10 => Op::DrawImage {
+                image: self.image()?,
+                src_rect: self.option_rect()?,
+                dst_rect: self.rect()?,
+                interp: match self.u8()? {
+                    0 => InterpolationMode::NearestNeighbor,
+                    1 => InterpolationMode::Bilinear,
+                    2 => InterpolationMode::Bicubic,
+                    3 => InterpolationMode::HighQuality,
+                    _ => return Err(DecodeError::Invalid),
+                },
+            },
+            11 => Op::BlurredRect {
+                rect: self.rect()?,
+                blur_radius: self.f64()?,
+                brush: self.brush()?,
+            },
+            12 => Op::Fragment(self.fragment()?),
+            13 => Op::Annotate {
+                key: self.str()?.into(),
+                value: self.str()?.into(),
+            },
+            14 => Op::BeginGroup(self.str()?.into()),
+            15 => Op::EndGroup,
+            16 => Op::HitRegion {
+                id: self.varint()?,
+                shape: self.geometry()?,
+                stroke: match self.bool()? {
+                    true => Some((self.f64()?, self.style()?)),
+                    false => None,
+                },
+            },
+            17 => Op::BeginOpacityMask {
+                mask: self.image()?,
+                rect: self.rect()?,
+            },
+            18 => Op::EndOpacityMask,
+            _ => return Err(DecodeError::Invalid),
+        })
+    }
+
+    fn geometry(&mut self) -> Result<Geometry, DecodeError> {
+        Ok(match self.u8()? {
+            0 => Geometry::Rect(self.rect()?),
+            1 => {
+                let rect = self.rect()?;
+                let radii =
+                    RoundedRectRadii::new(self.f64()?, self.f64()?, self.f64()?, self.f64()?);
+                Geometry::RoundedRect(RoundedRect::from_rect(rect, radii))
+            }
+            2 => {
+                let n = self.count(1)?;
+                let mut path = BezPath::new();
+                for _ in 0..n {
+                    path.push(match self.u8()? {
+                        0 => PathEl::MoveTo(self.point()?),
+                        1 => PathEl::LineTo(self.point()?),
+                        2 => PathEl::QuadTo(self.point()?, self.point()?),
+                        3 => PathEl::CurveTo(self.point()?, self.point()?, self.point()?),
+                        4 => PathEl::ClosePath,
+                        _ => return Err(DecodeError::Invalid),
+                    });
+                }
+                Geometry::Path(path)
+            }
+            _ => return Err(DecodeError::Invalid),
+        })
+    }
+
+    fn brush(&mut self) -> Result<Brush, DecodeError> {
+        Ok(Brush(match self.u8()? {
+            0 => BrushKind::Solid(self.color()?),
+            1 => BrushKind::Gradient(self.gradient()?),
+            _ => return Err(DecodeError::Invalid),
+        }))
+    }
+
+    fn gradient(&mut self) -> Result<Arc<FixedGradient>, DecodeError> {
+        if let Some(gradient) = read_shared(self, |d| &d.gradients)? {
+            return Ok(gradient);
+        }
+        let gradient = Arc::new(match self.u8()? {
+            0 => FixedGradient::Linear(FixedLinearGradient {
+                start: self.point()?,
+                end: self.point()?,
+                stops: self.stops()?,
+            }),
+            1 => FixedGradient::Radial(FixedRadialGradient {
+                center: self.point()?,
+                origin_offset: self.point()?.to_vec2(),
+                radius: self.f64()?,
+                stops: self.stops()?,
+            }),
+            _ => return Err(DecodeError::Invalid),
+        });
+        self.gradients.push(gradient.clone());
+        Ok(gradient)
+    }
+
+    fn stops(&mut self) -> Result<Vec<GradientStop>, DecodeError> {
+        let n = self.count(8)?;
+        (0..n)
+            .map(|_| {
+                Ok(GradientStop {
+                    pos: f32::from_le_bytes(self.array()?),
+                    color: self.color()?,
+                })
+            })
+            .collect()
+    }
+
+    fn style(&mut self) -> Result<Style, DecodeError> {
+        let line_join = match self.u8()? {
+            0 => LineJoin::Miter { limit: self.f64()? },
+            1 => LineJoin::Round,
+            2 => LineJoin::Bevel,
+            _ => return Err(DecodeError::Invalid),
+        };
+        let line_cap = self.line_cap()?.ok_or(DecodeError::Invalid)?;
+        let dash_cap = self.line_cap()?;
+        let n = self.count(8)?;
+        let dash_pattern = (0..n).map(|_| self.f64()).collect::<Result<_, _>>()?;
+        Ok(Style {
+            line_join,
+            line_cap,
+            dash_cap,
+            dash_pattern,
+            dash_offset: self.f64()?,
+            scale: self.bool()?,
+        })
+    }
+
+    fn line_cap(&mut self) -> Result<Option<LineCap>, DecodeError> {
+        Ok(match self.u8()? {
+            0 => Some(LineCap::Butt),
+            1 => Some(LineCap::Round),
+            2 => Some(LineCap::Square),
+            3 => None,
+            _ => return Err(DecodeError::Invalid),
+        })
+    }
+
+    fn image(&mut self) -> Result<Arc<ImageData>, DecodeError> {
+        let image = match read_shared(self, |d| &d.images)? {
+            Some(image) => image,
+            None => self.image_pixels()?,
+        };
+        let view = match self.option_rect()? {
+            Some(view) => view,
+            None => return Ok(image),
+        };
+        if view.is_empty() || piet::util::sub_image_view(image.view(), view) != view {
+            return Err(DecodeError::Invalid);
+        }
+        Ok(Arc::new(ImageData {
+            buf: image.buf.clone(),
+            view: Some(view),
+            ..*image
+        }))
+    }
+
+    fn image_pixels(&mut self) -> Result<Arc<ImageData>, DecodeError> {
+        let width = self.len()?;
+        let height = self.len()?;
+        let format = match self.u8()? {
+            0 => ImageFormat::Grayscale,
+            1 => ImageFormat::Rgb,
+            2 => ImageFormat::RgbaSeparate,
+            3 => ImageFormat::RgbaPremul,
+            4 => ImageFormat::BgraSeparate,
+            5 => ImageFormat::BgraPremul,
+            6 => ImageFormat::Rgba16,
+            _ => return Err(DecodeError::Invalid),
+        };
+        let buf = self.byte_string()?;
+        let size = width
+            .checked_mul(height)
+            .and_then(|n| n.checked_mul(format.bytes_per_pixel()));
+        // empty images aren't recorded.
+        if size != Some(buf.len()) || buf.is_empty() {
+            return Err(DecodeError::Invalid);
+        }
+        let image = Arc::new(ImageData {
+            width,
+            height,
+            format,
+            buf: buf.into(),
+            view: None,
+        });
+        self.images.push(image.clone());
+        Ok(image)
+    }
+
+    fn layout(&mut self) -> Result<Arc<TextDescription>, DecodeError> {
+        if let Some(layout) = read_shared(self, |d| &d.layouts)? {
+            return Ok(layout);
+        }
+        let text: Arc<str> = self.str()?.into();
+        let n = self.count(1)?;
+        let mut fonts = Vec::with_capacity(n);
+        for _ in 0..n {
+            let font = match read_shared(self, |d| &d.fonts)? {
+                Some(font) => font,
+                None => {
+                    let font: Arc<[u8]> = self.byte_string()?.into();
+                    self.fonts.push(font.clone());
+                    font
+                }
+            };
+            fonts.push(font);
+        }
+        let n = self.count(1)?;
+        let mut calls = Vec::with_capacity(n);
+        for _ in 0..n {
+            calls.push(self.call(&text)?);
+        }
+        let layout = Arc::new(TextDescription { text, fonts, calls });
+        self.layouts.push(layout.clone());
+        Ok(layout)
+    }
+
+    fn call(&mut self, text: &str) -> Result<BuilderCall, DecodeError> {
+        Ok(match self.u8()? {
+            0 => BuilderCall::MaxWidth(self.f64()?),
+            1 => BuilderCall::MaxHeight(self.f64()?),
+            2 => BuilderCall::Overflow(match self.u8()? {
+                0 => Overflow::Visible,
+                1 => Overflow::Clip,
+                2 => Overflow::Ellipsis,
+                _ => return Err(DecodeError::Invalid),
+            }),
+            3 => BuilderCall::Alignment(self.alignment()?),
+            4 => BuilderCall::ParagraphAlignment(self.range(text)?, self.alignment()?),
+            5 => BuilderCall::WritingMode(match self.u8()? {
+                0 => WritingMode::HorizontalTb,
+                1 => WritingMode::VerticalRl,
+                _ => return Err(DecodeError::Invalid),
+            }),
+            6 => BuilderCall::LineHeight(match self.u8()? {
+                0 => LineHeight::Normal,
+                1 => LineHeight::Relative(self.f64()?),
+                2 => LineHeight::Absolute(self.f64()?),
+                _ => return Err(DecodeError::Invalid),
+            }),
+            7 => BuilderCall::ParagraphSpacing(self.f64()?),
+            8 => {
+                let n = self.count(8)?;
+                let stops = (0..n).map(|_| self.f64()).collect::<Result<_, _>>()?;
+                BuilderCall::TabStops(stops)
+            }
+            9 => BuilderCall::TabWidth(self.f64()?),
+            10 => BuilderCall::DefaultAttribute(self.attribute()?),
+            11 => BuilderCall::RangeAttribute(self.range(text)?, self.attribute()?),
+            12 => {
+                let index = self.len()?;
+                if index > text.len() {
+                    return Err(DecodeError::Invalid);
+                }
+                let size = Size::new(self.f64()?, self.f64()?);
+                let alignment = match self.u8()? {
+                    0 => BaselineAlignment::Bottom,
+                    1 => BaselineAlignment::Center,
+                    2 => BaselineAlignment::Baseline(self.f64()?),
+                    _ => return Err(DecodeError::Invalid),
+                };
+                BuilderCall::InlineBox(index, size, alignment)
+            }
+            _ => return Err(DecodeError::Invalid),
+        })
+    }
+
+    /// Read a range of `text`, which is within it, as ranges are when they
+    /// are recorded.
+    fn range(&mut self, text: &str) -> Result<Range<usize>, DecodeError> {
+        let range = self.len()?..self.len()?;
+        match range.start <= text.len() && range.end <= text.len() {
+            true => Ok(range),
+            false => Err(DecodeError::Invalid),
+        }
+    }
+
+    fn alignment(&mut self) -> Result<TextAlignment, DecodeError> {
+        Ok(match self.u8()? {
+            0 => TextAlignment::Start,
+            1 => TextAlignment::End,
+            2 => TextAlignment::Center,
+            3 => TextAlignment::Justified,
+            _ => return Err(DecodeError::Invalid),
+        })
+    }
+
+    fn attribute(&mut self) -> Result<TextAttribute, DecodeError> {
+        Ok(match self.u8()? {
+            0 => TextAttribute::FontFamily(match self.u8()? {
+                0 => FontFamily::SERIF,
+                1 => FontFamily::SANS_SERIF,
+                2 => FontFamily::MONOSPACE,
+                3 => FontFamily::SYSTEM_UI,
+                4 => FontFamily::new_unchecked(self.str()?),
+                _ => return Err(DecodeError::Invalid),
+            }),
+            1 => TextAttribute::FontSize(self.f64()?),
+            2 => {
+                let weight = self.varint()?.try_into().unwrap_or(u16::MAX);
+                TextAttribute::Weight(FontWeight::new(weight))
+            }
+            3 => TextAttribute::TextColor(self.color()?),
+            4 => TextAttribute::BackgroundColor(self.color()?),
+            5 => TextAttribute::Style(match self.bool()? {
+                true => FontStyle::Italic,
+                false => FontStyle::Regular,
+            }),
+            6 => TextAttribute::Underline(self.bool()?),
+            7 => TextAttribute::Strikethrough(self.bool()?),
+            8 => TextAttribute::LetterSpacing(self.f64()?),
+            9 => TextAttribute::WordSpacing(self.f64()?),
+            10 => {
+                let n = self.count(12)?;
+                let variations = (0..n)
+                    .map(|_| Ok(FontVariation::new(self.array()?, self.f64()?)))
+                    .collect::<Result<_, _>>()?;
+                TextAttribute::FontVariations(variations)
+            }
+            11 => {
+                let n = self.count(5)?;
+                let features = (0..n)
+                    .map(|_| {
+                        let tag = self.array()?;
+                        let value = self.varint()?.try_into();
+                        Ok(FontFeature::new(
+                            tag,
+                            value.map_err(|_| DecodeError::Invalid)?,
+                        ))
+                    })
+                    .collect::<Result<_, _>>()?;
+                TextAttribute::FontFeatures(features)
+            }
+            12 => TextAttribute::BaselineShift(self.f64()?),
+            13 => TextAttribute::ScriptPosition(match self.u8()? {
+                0 => ScriptPosition::Normal,
+                1 => ScriptPosition::Superscript,
+                2 => ScriptPosition::Subscript,
+                _ => return Err(DecodeError::Invalid),
+            }),
+            _ => return Err(DecodeError::Invalid),
+        })
+    }
+}
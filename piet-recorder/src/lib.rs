@@ -0,0 +1,1423 @@
+//! A recording backend for the Piet 2D graphics abstraction.
+//!
+//! A [`RecordingRenderContext`] draws nothing; instead, it records everything
+//! it is asked to draw into a [`SceneFragment`], which can be replayed into
+//! any other render context later, any number of times. Fragments are cheap
+//! to clone and can be sent between threads, so they can be used to cache
+//! parts of a scene, or to build a scene on one thread and draw it on
+//! another.
+//!
+//! Two fragments, such as recordings of consecutive frames, can be compared
+//! with [`SceneFragment::damage`] to find the areas where they differ, and
+//! then only those areas redrawn with [`SceneFragment::replay_damaged`].
+//!
+//! Fragments can be encoded as bytes with [`SceneFragment::encode`], for
+//! example to draw a scene in another process, or on another machine, and
+//! decoded again with [`SceneFragment::decode`].
+//!
+//! Text is measured by the text factory of another backend, which is passed
+//! to [`RecordingRenderContext::new`]. When a fragment is replayed, its text
+//! layouts are built again by the text factory of the context it is replayed
+//! into.
+
+#![deny(clippy::trivially_copy_pass_by_ref)]
+
+mod diff;
+mod encode;
+mod text;
+
+use std::borrow::Cow;
+use std::collections::hash_map::{Entry, HashMap};
+use std::fmt;
+use std::sync::Arc;
+
+use piet::kurbo::{Affine, BezPath, Point, Rect, RoundedRect, Shape, Size};
+use piet::{
+    Color, Error, FixedGradient, Image, ImageFormat, InterpolationMode, IntoBrush, LineCap,
+    LineJoin, NullText, RenderContext, SharedImage, StrokeStyle, Text, TextLayout,
+    DEFAULT_TOLERANCE,
+};
+
+pub use diff::Damage;
+pub use encode::DecodeError;
+pub use text::{RecordingText, RecordingTextLayout, RecordingTextLayoutBuilder};
+
+use text::TextDescription;
+
+/// A render context that records what is drawn into a [`SceneFragment`].
+///
+/// `T` is the text factory that text layouts are measured with. With the
+/// default, [`NullText`], layouts are empty.
+pub struct RecordingRenderContext<T: Text = NullText> {
+    ops: Vec<Op>,
+    text: RecordingText<T>,
+    transform: Affine,
+    global_alpha: f64,
+    /// The tolerance that shapes are converted to paths with when they are
+    /// recorded.
+    tolerance: f64,
+    saved: Vec<(Affine, f64, f64, SavedBy)>,
+    /// The number of groups that have been begun but not ended.
+    groups: usize,
+}
+
+/// What saved a state of a [`RecordingRenderContext`], which decides what
+/// must restore it.
+#[derive(Clone, Copy, PartialEq)]
+enum SavedBy {
+    Save,
+    OpacityMask,
+    /// An opacity mask with an empty image, which is recorded as a clip
+    /// that hides everything.
+    EmptyOpacityMask,
+}
+
+/// A recorded list of drawing operations.
+///
+/// Cloning a fragment doesn't copy its operations.
+#[derive(Clone, Default)]
+pub struct SceneFragment {
+    ops: Arc<[Op]>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Brush(BrushKind);
+
+#[derive(Clone, Debug, PartialEq)]
+enum BrushKind {
+    Solid(Color),
+    Gradient(Arc<FixedGradient>),
+}
+
+/// A recorded image, which holds a copy of its pixels.
+#[derive(Clone, Debug)]
+pub struct RecordingImage(Option<Arc<ImageData>>);
+
+#[derive(Debug, PartialEq)]
+struct ImageData {
+    width: usize,
+    height: usize,
+    format: ImageFormat,
+    buf: Arc<[u8]>,
+    /// The area of the pixels that is the image, if it is a sub-image.
+    view: Option<Rect>,
+}
+
+impl ImageData {
+    /// The area of the pixels that is the image.
+    fn view(&self) -> Rect {
+        self.view
+            .unwrap_or_else(|| Rect::new(0., 0., self.width as f64, self.height as f64))
+    }
+}
+
+/// A single recorded call to a [`RenderContext`] method.
+#[derive(Clone, PartialEq)]
+enum Op {
+    Clear(Option<Rect>, Color),
+    Fill {
+        shape: Geometry,
+        brush: Brush,
+        even_odd: bool,
+    },
+    FillWithMask {
+        shape: Geometry,
+        brush: Brush,
+        mask: Arc<ImageData>,
+    },
+    Stroke {
+        shape: Geometry,
+        brush: Brush,
+        width: f64,
+        style: Option<Style>,
+    },
+    Clip(Geometry),
+    DrawText {
+        layout: Arc<TextDescription>,
+        pos: Point,
+        /// The area covered by the layout, if it is known.
+        bounds: Option<Rect>,
+    },
+    Save,
+    Restore,
+    Transform(Affine),
+    GlobalAlpha(f64),
+    DrawImage {
+        image: Arc<ImageData>,
+        src_rect: Option<Rect>,
+        dst_rect: Rect,
+        interp: InterpolationMode,
+    },
+    BlurredRect {
+        rect: Rect,
+        blur_radius: f64,
+        brush: Brush,
+    },
+    Fragment(SceneFragment),
+    Annotate {
+        key: Arc<str>,
+        value: Arc<str>,
+    },
+    BeginGroup(Arc<str>),
+    EndGroup,
+    BeginOpacityMask {
+        mask: Arc<ImageData>,
+        rect: Rect,
+    },
+    EndOpacityMask,
+    HitRegion {
+        id: u64,
+        shape: Geometry,
+        /// The width and style of the stroke, if the region is the stroke of
+        /// the shape.
+        stroke: Option<(f64, Style)>,
+    },
+}
+
+/// A recorded shape. Rectangles are kept as they are, because backends can
+/// often draw them faster than paths.
+#[derive(Clone, Debug, PartialEq)]
+enum Geometry {
+    Rect(Rect),
+    RoundedRect(RoundedRect),
+    Path(BezPath),
+}
+
+/// A [`StrokeStyle`] that can be sent between threads.
+#[derive(Clone, Debug, PartialEq)]
+struct Style {
+    line_join: LineJoin,
+    line_cap: LineCap,
+    dash_cap: Option<LineCap>,
+    dash_pattern: Arc<[f64]>,
+    dash_offset: f64,
+    scale: bool,
+}
+
+impl<T: Text> RecordingRenderContext<T> {
+    /// Create a new recording context, which measures text with `text`.
+    pub fn new(text: T) -> RecordingRenderContext<T> {
+        RecordingRenderContext {
+            ops: Vec::new(),
+            text: RecordingText::new(text),
+            transform: Affine::IDENTITY,
+            global_alpha: 1.0,
+            tolerance: DEFAULT_TOLERANCE,
+            saved: Vec::new(),
+            groups: 0,
+        }
+    }
+
+    /// Draw a fragment that was recorded earlier, as if it was recorded
+    /// again here.
+    ///
+    /// This doesn't copy the fragment's operations.
+    pub fn draw_fragment(&mut self, fragment: &SceneFragment) {
+        if !fragment.is_empty() {
+            self.ops.push(Op::Fragment(fragment.clone()));
+        }
+    }
+
+    /// The fragment that has been recorded so far.
+    ///
+    /// Any saved states that haven't been restored are restored when the
+    /// fragment is replayed, and any groups and opacity masks that haven't
+    /// been ended are ended.
+    pub fn into_fragment(self) -> SceneFragment {
+        SceneFragment {
+            ops: self.ops.into(),
+        }
+    }
+
+    fn push_state(&mut self, saved_by: SavedBy) {
+        self.saved
+            .push((self.transform, self.global_alpha, self.tolerance, saved_by));
+    }
+
+    /// Go back to the last saved state, if `matches` accepts what saved it.
+    fn pop_state(&mut self, matches: impl FnOnce(SavedBy) -> bool) -> Result<SavedBy, Error> {
+        match self.saved.last() {
+            Some(&(transform, global_alpha, tolerance, saved_by)) if matches(saved_by) => {
+                self.saved.pop();
+                self.transform = transform;
+                self.global_alpha = global_alpha;
+                self.tolerance = tolerance;
+                Ok(saved_by)
+            }
+            _ => Err(Error::StackUnbalance),
+        }
+    }
+}
+
+impl<T: Text> RenderContext for RecordingRenderContext<T> {
+    type Brush = Brush;
+
+    type Text = RecordingText<T>;
+    type TextLayout = RecordingTextLayout<T>;
+
+    type Image = RecordingImage;
+
+    fn status(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn clear(&mut self, region: impl Into<Option<Rect>>, color: Color) {
+        self.ops.push(Op::Clear(region.into(), color));
+    }
+
+    fn solid_brush(&mut self, color: Color) -> Brush {
+        Brush(BrushKind::Solid(color))
+    }
+
+    fn gradient(&mut self, gradient: impl Into<FixedGradient>) -> Result<Brush, Error> {
+        Ok(Brush(BrushKind::Gradient(Arc::new(gradient.into()))))
+    }
+
+    fn fill(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>) {
+        let brush = brush.make_brush(self, || shape.bounding_box()).into_owned();
+        self.ops.push(Op::Fill {
+            shape: Geometry::new(shape, self.tolerance),
+            brush,
+            even_odd: false,
+        });
+    }
+
+    fn fill_even_odd(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>) {
+        let brush = brush.make_brush(self, || shape.bounding_box()).into_owned();
+        self.ops.push(Op::Fill {
+            shape: Geometry::new(shape, self.tolerance),
+            brush,
+            even_odd: true,
+        });
+    }
+
+    fn fill_with_mask(
+        &mut self,
+        shape: impl Shape,
+        brush: &impl IntoBrush<Self>,
+        mask: &Self::Image,
+    ) {
+        let brush = brush.make_brush(self, || shape.bounding_box()).into_owned();
+        // an empty mask hides the whole fill.
+        if let Some(mask) = &mask.0 {
+            self.ops.push(Op::FillWithMask {
+                shape: Geometry::new(shape, self.tolerance),
+                brush,
+                mask: mask.clone(),
+            });
+        }
+    }
+
+    fn clip(&mut self, shape: impl Shape) {
+        self.ops
+            .push(Op::Clip(Geometry::new(shape, self.tolerance)));
+    }
+
+    fn stroke(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>, width: f64) {
+        let brush = brush.make_brush(self, || shape.bounding_box()).into_owned();
+        self.ops.push(Op::Stroke {
+            shape: Geometry::new(shape, self.tolerance),
+            brush,
+            width,
+            style: None,
+        });
+    }
+
+    fn stroke_styled(
+        &mut self,
+        shape: impl Shape,
+        brush: &impl IntoBrush<Self>,
+        width: f64,
+        style: &StrokeStyle,
+    ) {
+        let brush = brush.make_brush(self, || shape.bounding_box()).into_owned();
+        self.ops.push(Op::Stroke {
+            shape: Geometry::new(shape, self.tolerance),
+            brush,
+            width,
+            style: Some(Style::new(style)),
+        });
+    }
+
+    fn text(&mut self) -> &mut Self::Text {
+        &mut self.text
+    }
+
+    fn draw_text(&mut self, layout: &Self::TextLayout, pos: impl Into<Point>) {
+        let pos = pos.into();
+        let bounds = layout.image_bounds().union(layout.size().to_rect()) + pos.to_vec2();
+        // Layouts measured by `NullText` are empty, so where they are drawn
+        // isn't known.
+        let known = bounds.area() > 0.0 || layout.description.text.is_empty();
+        self.ops.push(Op::DrawText {
+            layout: layout.description.clone(),
+            pos,
+            bounds: known.then_some(bounds),
+        });
+    }
+
+    fn save(&mut self) -> Result<(), Error> {
+        self.push_state(SavedBy::Save);
+        self.ops.push(Op::Save);
+        Ok(())
+    }
+
+    fn restore(&mut self) -> Result<(), Error> {
+        self.pop_state(|saved_by| saved_by == SavedBy::Save)?;
+        self.ops.push(Op::Restore);
+        Ok(())
+    }
+
+    fn annotate(&mut self, key: &str, value: &str) {
+        self.ops.push(Op::Annotate {
+            key: key.into(),
+            value: value.into(),
+        });
+    }
+
+    fn begin_group(&mut self, role: &str) -> Result<(), Error> {
+        self.groups += 1;
+        self.ops.push(Op::BeginGroup(role.into()));
+        Ok(())
+    }
+
+    fn end_group(&mut self) -> Result<(), Error> {
+        if self.groups == 0 {
+            return Err(Error::StackUnbalance);
+        }
+        self.groups -= 1;
+        self.ops.push(Op::EndGroup);
+        Ok(())
+    }
+
+    fn begin_opacity_mask(
+        &mut self,
+        mask: &Self::Image,
+        rect: impl Into<Rect>,
+    ) -> Result<(), Error> {
+        let rect = rect.into();
+        match &mask.0 {
+            Some(mask) => {
+                self.push_state(SavedBy::OpacityMask);
+                self.ops.push(Op::BeginOpacityMask {
+                    mask: mask.clone(),
+                    rect,
+                });
+            }
+            // an empty mask hides everything, as a clip to nothing does.
+            None => {
+                self.push_state(SavedBy::EmptyOpacityMask);
+                self.ops.push(Op::Save);
+                self.ops.push(Op::Clip(Geometry::Rect(Rect::ZERO)));
+            }
+        }
+        Ok(())
+    }
+
+    fn end_opacity_mask(&mut self) -> Result<(), Error> {
+        match self.pop_state(|saved_by| saved_by != SavedBy::Save)? {
+            SavedBy::EmptyOpacityMask => self.ops.push(Op::Restore),
+            _ => self.ops.push(Op::EndOpacityMask),
+        }
+        Ok(())
+    }
+
+    fn hit_region(&mut self, id: u64, shape: impl Shape) {
+        self.ops.push(Op::HitRegion {
+            id,
+            shape: Geometry::new(shape, self.tolerance),
+            stroke: None,
+        });
+    }
+
+    fn hit_region_stroke(&mut self, id: u64, shape: impl Shape, width: f64, style: &StrokeStyle) {
+        self.ops.push(Op::HitRegion {
+            id,
+            shape: Geometry::new(shape, self.tolerance),
+            stroke: Some((width, Style::new(style))),
+        });
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn transform(&mut self, transform: Affine) {
+        self.transform *= transform;
+        self.ops.push(Op::Transform(transform));
+    }
+
+    fn current_transform(&self) -> Affine {
+        self.transform
+    }
+
+    fn set_global_alpha(&mut self, alpha: f64) {
+        self.global_alpha = alpha;
+        self.ops.push(Op::GlobalAlpha(alpha));
+    }
+
+    fn global_alpha(&self) -> f64 {
+        self.global_alpha
+    }
+
+    fn set_tolerance(&mut self, tolerance: f64) {
+        if tolerance > 0.0 && tolerance.is_finite() {
+            self.tolerance = tolerance;
+        }
+    }
+
+    fn tolerance(&self) -> f64 {
+        self.tolerance
+    }
+
+    fn make_image(
+        &mut self,
+        width: usize,
+        height: usize,
+        buf: &[u8],
+        format: ImageFormat,
+    ) -> Result<Self::Image, Error> {
+        if width == 0 || height == 0 {
+            return Ok(RecordingImage(None));
+        }
+        let buf = buf
+            .get(..width * height * format.bytes_per_pixel())
+            .ok_or(Error::InvalidInput)?;
+        Ok(RecordingImage(Some(Arc::new(ImageData {
+            width,
+            height,
+            format,
+            buf: buf.into(),
+            view: None,
+        }))))
+    }
+
+    fn make_shared_image(&mut self, image: &SharedImage) -> Result<Self::Image, Error> {
+        let buf = image.image_buf();
+        if buf.width() == 0 || buf.height() == 0 {
+            return Ok(RecordingImage(None));
+        }
+        // the recording shares the pixels, instead of copying them.
+        Ok(RecordingImage(Some(Arc::new(ImageData {
+            width: buf.width(),
+            height: buf.height(),
+            format: buf.format(),
+            buf: buf.raw_pixels_shared(),
+            view: None,
+        }))))
+    }
+
+    #[inline]
+    fn draw_image(
+        &mut self,
+        image: &Self::Image,
+        dst_rect: impl Into<Rect>,
+        interp: InterpolationMode,
+    ) {
+        if let Some(image) = &image.0 {
+            self.ops.push(Op::DrawImage {
+                image: image.clone(),
+                src_rect: None,
+                dst_rect: dst_rect.into(),
+                interp,
+            });
+        }
+    }
+
+    #[inline]
+    fn draw_image_area(
+        &mut self,
+        image: &Self::Image,
+        src_rect: impl Into<Rect>,
+        dst_rect: impl Into<Rect>,
+        interp: InterpolationMode,
+    ) {
+        if let Some(image) = &image.0 {
+            self.ops.push(Op::DrawImage {
+                image: image.clone(),
+                src_rect: Some(src_rect.into()),
+                dst_rect: dst_rect.into(),
+                interp,
+            });
+        }
+    }
+
+    fn capture_image_area(&mut self, _src_rect: impl Into<Rect>) -> Result<Self::Image, Error> {
+        // Nothing has been drawn.
+        Err(Error::NotSupported)
+    }
+
+    fn blurred_rect(&mut self, rect: Rect, blur_radius: f64, brush: &impl IntoBrush<Self>) {
+        let brush = brush.make_brush(self, || rect).into_owned();
+        self.ops.push(Op::BlurredRect {
+            rect,
+            blur_radius,
+            brush,
+        });
+    }
+}
+
+impl SceneFragment {
+    /// Returns `true` if nothing was recorded.
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Draw this fragment into `rc`.
+    ///
+    /// The state of `rc` is the same afterwards as before. If this returns
+    /// an error, only part of the fragment may have been drawn.
+    pub fn replay(&self, rc: &mut impl RenderContext) -> Result<(), Error> {
+        Replay::default().fragment(rc, self)
+    }
+
+    /// Find the parts of the scene that differ between this fragment and
+    /// `newer`, which is usually a recording of the next frame.
+    ///
+    /// Only the parts in the returned [`Damage`] need to be drawn again; see
+    /// [`SceneFragment::replay_damaged`].
+    pub fn damage(&self, newer: &SceneFragment) -> Damage {
+        diff::damage(self, newer)
+    }
+
+    /// Encode this fragment as bytes, which can be decoded with
+    /// [`SceneFragment::decode`].
+    ///
+    /// The encoding is versioned, and only decoded by versions of this crate
+    /// that support the version it was encoded with. Images, gradients, text
+    /// layouts, fonts and fragments that are used more than once are only
+    /// encoded once.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the fragment holds an image in an [`ImageFormat`] that was
+    /// added after this version of the encoding.
+    pub fn encode(&self) -> Vec<u8> {
+        encode::encode(self)
+    }
+
+    /// Decode a fragment that was encoded with [`SceneFragment::encode`].
+    ///
+    /// Malformed data is an error, so fragments can be decoded from sources
+    /// that aren't trusted.
+    pub fn decode(data: &[u8]) -> Result<SceneFragment, DecodeError> {
+        encode::decode(data)
+    }
+
+    /// Draw the parts of this fragment that are inside `damage` into `rc`.
+    ///
+    /// Drawing is clipped to the damage, and operations that are entirely
+    /// outside of it are skipped. Clearing is limited to the damage as well.
+    /// Pixels in the damage that this fragment doesn't draw over keep their
+    /// old contents, so a fragment that doesn't start by clearing should
+    /// usually have its damage cleared first.
+    pub fn replay_damaged(
+        &self,
+        rc: &mut impl RenderContext,
+        damage: &Damage,
+    ) -> Result<(), Error> {
+        if damage.is_everything() {
+            return self.replay(rc);
+        }
+        if damage.is_empty() {
+            return Ok(());
+        }
+        rc.save()?;
+        match damage.rects() {
+            [rect] => rc.clip(*rect),
+            rects => {
+                let mut clip = BezPath::new();
+                for rect in rects {
+                    clip.extend(rect.path_elements(0.0));
+                }
+                rc.clip(clip);
+            }
+        }
+        let base = rc.current_transform();
+        let mut replay = Replay {
+            damage: Some(ReplayDamage {
+                rects: damage.rects().to_vec(),
+                base,
+                base_inverse: base.inverse(),
+            }),
+            ..Replay::default()
+        };
+        let result = replay.fragment(rc, self);
+        rc.restore()?;
+        result
+    }
+}
+
+/// The resources made while replaying, so that each one is only made once.
+struct Replay<R: RenderContext> {
+    gradients: HashMap<*const FixedGradient, R::Brush>,
+    /// The images made from the pixels of recorded images, which are shared
+    /// by their sub-images.
+    images: HashMap<*const u8, R::Image>,
+    layouts: HashMap<*const TextDescription, R::TextLayout>,
+    fonts: Vec<Arc<[u8]>>,
+    /// Set when only part of a fragment is replayed.
+    damage: Option<ReplayDamage>,
+}
+
+/// The saved states and groups that a fragment has left open.
+#[derive(Default)]
+struct Depth {
+    /// For each saved state, whether it was saved by beginning an opacity
+    /// mask.
+    saves: Vec<bool>,
+    groups: usize,
+}
+
+/// The damaged area that a replay is limited to.
+struct ReplayDamage {
+    /// The damage, in the coordinate space of the outermost fragment.
+    rects: Vec<Rect>,
+    /// The transform of the context when the replay started.
+    base: Affine,
+    base_inverse: Affine,
+}
+
+impl<R: RenderContext> Default for Replay<R> {
+    fn default() -> Self {
+        Replay {
+            gradients: HashMap::new(),
+            images: HashMap::new(),
+            layouts: HashMap::new(),
+            fonts: Vec::new(),
+            damage: None,
+        }
+    }
+}
+
+/// Call `$body` with `$shape` bound to the shape in a [`Geometry`].
+macro_rules! with_shape {
+    ($geometry:expr, |$shape:ident| $body:expr) => {
+        match $geometry {
+            Geometry::Rect($shape) => $body,
+            Geometry::RoundedRect($shape) => $body,
+            Geometry::Path($shape) => $body,
+        }
+    };
+}
+
+impl<R: RenderContext> Replay<R> {
+    fn fragment(&mut self, rc: &mut R, fragment: &SceneFragment) -> Result<(), Error> {
+        rc.save()?;
+        let mut depth = Depth::default();
+        let result = fragment
+            .ops
+            .iter()
+            .try_for_each(|op| self.op(rc, op, &mut depth));
+        for _ in 0..depth.groups {
+            rc.end_group()?;
+        }
+        while let Some(mask) = depth.saves.pop() {
+            match mask {
+                true => rc.end_opacity_mask()?,
+                false => rc.restore()?,
+            }
+        }
+        rc.restore()?;
+        result
+    }
+
+    fn op(&mut self, rc: &mut R, op: &Op, depth: &mut Depth) -> Result<(), Error> {
+        if let Some(damage) = &self.damage {
+            if !damage.touches(op, rc.current_transform()) {
+                return Ok(());
+            }
+        }
+        match op {
+            Op::Clear(region, color) => match &self.damage {
+                // clearing ignores the clip, so limit it to the damage here.
+                Some(damage) => {
+                    for rect in &damage.rects {
+                        let rect = damage.base.transform_rect_bbox(*rect);
+                        let rect = region.map_or(rect, |region| region.intersect(rect));
+                        if !rect.is_empty() {
+                            rc.clear(rect, *color);
+                        }
+                    }
+                }
+                None => rc.clear(*region, *color),
+            },
+            Op::Fill {
+                shape,
+                brush,
+                even_odd,
+            } => {
+                let brush = self.brush(rc, brush)?;
+                match even_odd {
+                    false => with_shape!(shape, |shape| rc.fill(shape, &brush)),
+                    true => with_shape!(shape, |shape| rc.fill_even_odd(shape, &brush)),
+                }
+            }
+            Op::FillWithMask { shape, brush, mask } => {
+                let brush = self.brush(rc, brush)?;
+                let mask = self.image(rc, mask)?;
+                with_shape!(shape, |shape| rc.fill_with_mask(shape, &brush, &mask));
+            }
+            Op::Stroke {
+                shape,
+                brush,
+                width,
+                style,
+            } => {
+                let brush = self.brush(rc, brush)?;
+                match style {
+                    None => with_shape!(shape, |shape| rc.stroke(shape, &brush, *width)),
+                    Some(style) => {
+                        let style = style.to_stroke_style();
+                        with_shape!(shape, |shape| rc
+                            .stroke_styled(shape, &brush, *width, &style))
+                    }
+                }
+            }
+            Op::Clip(shape) => with_shape!(shape, |shape| rc.clip(shape)),
+            Op::DrawText { layout, pos, .. } => {
+                let built = match self.layouts.entry(Arc::as_ptr(layout)) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => entry.insert(layout.build(rc.text(), &mut self.fonts)?),
+                };
+                rc.draw_text(built, *pos);
+            }
+            Op::Save => {
+                rc.save()?;
+                depth.saves.push(false);
+            }
+            Op::Restore => {
+                rc.restore()?;
+                depth.saves.pop();
+            }
+            Op::Transform(transform) => rc.transform(*transform),
+            Op::GlobalAlpha(alpha) => rc.set_global_alpha(*alpha),
+            Op::DrawImage {
+                image,
+                src_rect,
+                dst_rect,
+                interp,
+            } => {
+                let made = self.image(rc, image)?;
+                match src_rect {
+                    Some(src_rect) => rc.draw_image_area(&made, *src_rect, *dst_rect, *interp),
+                    None => rc.draw_image(&made, *dst_rect, *interp),
+                }
+            }
+            Op::BlurredRect {
+                rect,
+                blur_radius,
+                brush,
+            } => {
+                let brush = self.brush(rc, brush)?;
+                rc.blurred_rect(*rect, *blur_radius, &brush);
+            }
+            Op::Fragment(fragment) => self.fragment(rc, fragment)?,
+            Op::Annotate { key, value } => rc.annotate(key, value),
+            Op::BeginGroup(role) => {
+                rc.begin_group(role)?;
+                depth.groups += 1;
+            }
+            Op::EndGroup => {
+                rc.end_group()?;
+                depth.groups -= 1;
+            }
+            Op::BeginOpacityMask { mask, rect } => {
+                let mask = self.image(rc, mask)?;
+                rc.begin_opacity_mask(&mask, *rect)?;
+                depth.saves.push(true);
+            }
+            Op::EndOpacityMask => {
+                rc.end_opacity_mask()?;
+                depth.saves.pop();
+            }
+            Op::HitRegion { id, shape, stroke } => match stroke {
+                None => with_shape!(shape, |shape| rc.hit_region(*id, shape)),
+                Some((width, style)) => {
+                    let style = style.to_stroke_style();
+                    with_shape!(shape, |shape| rc
+                        .hit_region_stroke(*id, shape, *width, &style))
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn image(&mut self, rc: &mut R, image: &Arc<ImageData>) -> Result<Cow<'_, R::Image>, Error> {
+        let made = match self.images.entry(image.buf.as_ptr()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                entry.insert(rc.make_image(image.width, image.height, &image.buf, image.format)?)
+            }
+        };
+        Ok(match image.view {
+            Some(view) => Cow::Owned(made.sub_image(view)),
+            None => Cow::Borrowed(made),
+        })
+    }
+
+    fn brush(&mut self, rc: &mut R, brush: &Brush) -> Result<R::Brush, Error> {
+        match &brush.0 {
+            BrushKind::Solid(color) => Ok(rc.solid_brush(*color)),
+            BrushKind::Gradient(gradient) => {
+                let key = Arc::as_ptr(gradient);
+                if let Some(brush) = self.gradients.get(&key) {
+                    return Ok(brush.clone());
+                }
+                let brush = rc.gradient(FixedGradient::clone(gradient))?;
+                self.gradients.insert(key, brush.clone());
+                Ok(brush)
+            }
+        }
+    }
+}
+
+impl ReplayDamage {
+    /// Returns `false` if `op` draws nothing inside the damage.
+    fn touches(&self, op: &Op, transform: Affine) -> bool {
+        if let Op::Clear(..) = op {
+            // clears are limited to the damage when they are replayed.
+            return true;
+        }
+        match diff::op_bounds(op, self.base_inverse * transform) {
+            Some(bounds) => self.rects.iter().any(|rect| diff::overlaps(*rect, bounds)),
+            None => true,
+        }
+    }
+}
+
+impl Geometry {
+    fn new(shape: impl Shape, tolerance: f64) -> Geometry {
+        if let Some(rect) = shape.as_rect() {
+            Geometry::Rect(rect)
+        } else if let Some(rounded_rect) = shape.as_rounded_rect() {
+            Geometry::RoundedRect(rounded_rect)
+        } else {
+            Geometry::Path(shape.into_path(tolerance))
+        }
+    }
+
+    fn bounding_box(&self) -> Rect {
+        with_shape!(self, |shape| shape.bounding_box())
+    }
+}
+
+impl Style {
+    fn new(style: &StrokeStyle) -> Style {
+        Style {
+            line_join: style.line_join,
+            line_cap: style.line_cap,
+            dash_cap: style.dash_cap,
+            dash_pattern: style.dash_pattern.as_ref().into(),
+            dash_offset: style.dash_offset,
+            scale: style.scale,
+        }
+    }
+
+    fn to_stroke_style(&self) -> StrokeStyle {
+        let mut style = StrokeStyle::new()
+            .line_join(self.line_join)
+            .line_cap(self.line_cap)
+            .dash_offset(self.dash_offset)
+            .scale(self.scale);
+        style.set_dash_cap(self.dash_cap);
+        if !self.dash_pattern.is_empty() {
+            style.set_dash_pattern(&*self.dash_pattern);
+        }
+        style
+    }
+}
+
+impl PartialEq for SceneFragment {
+    fn eq(&self, other: &SceneFragment) -> bool {
+        Arc::ptr_eq(&self.ops, &other.ops) || self.ops == other.ops
+    }
+}
+
+impl fmt::Debug for SceneFragment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SceneFragment")
+            .field("ops", &self.ops.len())
+            .finish()
+    }
+}
+
+impl<T: Text> IntoBrush<RecordingRenderContext<T>> for Brush {
+    fn make_brush<'b>(
+        &'b self,
+        _piet: &mut RecordingRenderContext<T>,
+        _bbox: impl FnOnce() -> Rect,
+    ) -> Cow<'b, Brush> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl Image for RecordingImage {
+    fn size(&self) -> Size {
+        match &self.0 {
+            Some(data) => data.view().size(),
+            None => Size::ZERO,
+        }
+    }
+
+    /// The sub-image is recorded with its area, and is made into a sub-image
+    /// of the image that its pixels are made into when it is replayed.
+    fn sub_image(&self, rect: impl Into<Rect>) -> Self {
+        let data = match &self.0 {
+            Some(data) => data,
+            None => return RecordingImage(None),
+        };
+        let view = piet::util::sub_image_view(data.view(), rect.into());
+        if view.is_empty() {
+            return RecordingImage(None);
+        }
+        RecordingImage(Some(Arc::new(ImageData {
+            buf: data.buf.clone(),
+            view: Some(view),
+            ..*data.as_ref()
+        })))
+    }
+
+    fn to_rgba_bytes(&self) -> Result<Vec<u8>, Error> {
+        let data = match &self.0 {
+            Some(data) => data,
+            None => return Ok(Vec::new()),
+        };
+        let len = data.width * data.height;
+        let (buf, format) = piet::util::to_rgba8(&data.buf, data.format);
+        let mut rgba = vec![0; len * 4];
+        match format {
+            ImageFormat::RgbaSeparate => rgba.copy_from_slice(&buf[..len * 4]),
+            ImageFormat::RgbaPremul => {
+                rgba.copy_from_slice(&buf[..len * 4]);
+                piet::util::unpremultiply_rgba(&mut rgba);
+            }
+            ImageFormat::Rgb => piet::util::rgb_to_rgba(&buf[..len * 3], &mut rgba),
+            ImageFormat::Grayscale => piet::util::gray_to_rgba(&buf[..len], &mut rgba),
+            _ => return Err(Error::NotSupported),
+        }
+        let view = match data.view {
+            Some(view) => view,
+            None => return Ok(rgba),
+        };
+        // only the rows of the view are kept, and only its part of each.
+        let row_len = data.width * 4;
+        let (x0, width) = (view.x0 as usize * 4, view.width() as usize * 4);
+        Ok(rgba
+            .chunks_exact(row_len)
+            .skip(view.y0 as usize)
+            .take(view.height() as usize)
+            .flat_map(|row| &row[x0..x0 + width])
+            .copied()
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use piet::{samples, BaselineAlignment, CullingRenderContext, Text, TextLayoutBuilder};
+    use piet_tiny_skia::tiny_skia::Pixmap;
+    use piet_tiny_skia::TinySkiaRenderContext;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn fragments_are_send_and_sync() {
+        assert_send_sync::<SceneFragment>();
+        assert_send_sync::<RecordingImage>();
+        assert_send_sync::<Brush>();
+        assert_send_sync::<RecordingRenderContext>();
+    }
+
+    #[test]
+    fn shared_images_are_not_copied() {
+        let buf = piet::ImageBuf::from_raw(vec![0; 16], ImageFormat::RgbaSeparate, 2, 2);
+        let shared = SharedImage::new(buf.clone());
+        // build the scene on another thread, as an application might.
+        let fragment = std::thread::spawn(move || {
+            let mut rc = RecordingRenderContext::new(NullText);
+            let image = shared.to_image(&mut rc).unwrap();
+            rc.draw_image(
+                &image,
+                Rect::new(0.0, 0.0, 2.0, 2.0),
+                InterpolationMode::Bilinear,
+            );
+            rc.into_fragment()
+        })
+        .join()
+        .unwrap();
+        match &fragment.ops[..] {
+            [Op::DrawImage { image, .. }] => {
+                assert!(Arc::ptr_eq(&image.buf, &buf.raw_pixels_shared()))
+            }
+            _ => panic!("expected one image"),
+        }
+    }
+
+    #[test]
+    fn images_are_read_back() {
+        let mut rc = RecordingRenderContext::new(NullText);
+        let image = rc
+            .make_image(2, 1, &[255, 0, 0, 0, 0, 255], ImageFormat::Rgb)
+            .unwrap();
+        assert_eq!(
+            image.to_rgba_bytes().unwrap(),
+            [255, 0, 0, 255, 0, 0, 255, 255]
+        );
+    }
+
+    #[test]
+    fn sub_images_are_encoded_and_replayed() {
+        let mut recorder = RecordingRenderContext::new(NullText);
+        let sheet = recorder
+            .make_image(2, 1, &[255, 0, 0, 0, 0, 255], ImageFormat::Rgb)
+            .unwrap();
+        let blue = sheet.sub_image(Rect::new(1., 0., 2., 1.));
+        assert_eq!(blue.size(), Size::new(1., 1.));
+        assert_eq!(blue.to_rgba_bytes().unwrap(), [0, 0, 255, 255]);
+        recorder.draw_image(
+            &blue,
+            Rect::new(0., 0., 4., 4.),
+            InterpolationMode::NearestNeighbor,
+        );
+        recorder.draw_image(
+            &sheet,
+            Rect::new(0., 0., 2., 1.),
+            InterpolationMode::NearestNeighbor,
+        );
+        let fragment = recorder.into_fragment();
+
+        let encoded = fragment.encode();
+        // the sub-image shares the pixels of the sheet.
+        assert_eq!(
+            encoded
+                .windows(6)
+                .filter(|w| w == &[255, 0, 0, 0, 0, 255])
+                .count(),
+            1
+        );
+        let decoded = SceneFragment::decode(&encoded).unwrap();
+        assert_eq!(decoded, fragment);
+
+        let mut pixmap = Pixmap::new(4, 4).unwrap();
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+        decoded.replay(&mut rc).unwrap();
+        std::mem::drop(rc);
+        assert_eq!(pixmap.pixel(0, 0).unwrap().red(), 255);
+        assert_eq!(pixmap.pixel(1, 0).unwrap().blue(), 255);
+        assert_eq!(pixmap.pixel(3, 3).unwrap().blue(), 255);
+    }
+
+    /// Draw a sample directly, and by replaying a recording of it, and
+    /// compare the results.
+    fn compare_sample(number: usize) {
+        let sample = samples::get::<TinySkiaRenderContext>(number).unwrap();
+        let size = sample.size();
+        let mut direct = Pixmap::new(size.width as u32, size.height as u32).unwrap();
+        let mut rc = TinySkiaRenderContext::new(direct.as_mut(), 1.0);
+        sample.draw(&mut rc).unwrap();
+        std::mem::drop(rc);
+
+        let mut recorder = RecordingRenderContext::new(NullText);
+        let recorded = samples::get::<RecordingRenderContext>(number).unwrap();
+        recorded.draw(&mut recorder).unwrap();
+        let fragment = recorder.into_fragment();
+
+        let mut replayed = Pixmap::new(size.width as u32, size.height as u32).unwrap();
+        let mut rc = TinySkiaRenderContext::new(replayed.as_mut(), 1.0);
+        fragment.replay(&mut rc).unwrap();
+        assert!(rc.restore().is_err(), "replay left a saved state");
+        std::mem::drop(rc);
+
+        assert!(direct == replayed, "sample {} differs", number);
+    }
+
+    #[test]
+    fn replay_matches_direct_drawing() {
+        // shapes, images, strokes, gradients, blurs, and clips.
+        for number in [1, 2, 3, 4, 6, 15, 17, 18, 19, 20] {
+            compare_sample(number);
+        }
+    }
+
+    #[test]
+    fn encoded_fragments_are_decoded() {
+        // every sample, including the ones with text, except for 16, which
+        // captures images, which can't be recorded.
+        for number in (0..=20).filter(|&n| n != 16) {
+            let mut recorder = RecordingRenderContext::new(NullText);
+            let sample = samples::get::<RecordingRenderContext>(number).unwrap();
+            sample.draw(&mut recorder).unwrap();
+            let fragment = recorder.into_fragment();
+            let decoded = SceneFragment::decode(&fragment.encode()).unwrap();
+            assert!(decoded == fragment, "sample {} differs", number);
+
+            let size = sample.size();
+            let pixmaps = [&fragment, &decoded].map(|fragment| {
+                let mut pixmap = Pixmap::new(size.width as u32, size.height as u32).unwrap();
+                let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+                fragment.replay(&mut rc).unwrap();
+                std::mem::drop(rc);
+                pixmap
+            });
+            assert!(pixmaps[0] == pixmaps[1], "sample {} differs", number);
+        }
+    }
+
+    #[test]
+    fn shared_resources_are_encoded_once() {
+        let mut inner = RecordingRenderContext::new(NullText);
+        let image = inner
+            .make_image(16, 16, &[7; 16 * 16 * 4], ImageFormat::RgbaPremul)
+            .unwrap();
+        inner.draw_image(
+            &image,
+            Rect::new(0., 0., 4., 4.),
+            InterpolationMode::Bilinear,
+        );
+        inner.draw_image(
+            &image,
+            Rect::new(4., 0., 8., 4.),
+            InterpolationMode::Bilinear,
+        );
+        let inner = inner.into_fragment();
+        let mut outer = RecordingRenderContext::new(NullText);
+        outer.draw_fragment(&inner);
+        outer.draw_fragment(&inner);
+        let fragment = outer.into_fragment();
+
+        let encoded = fragment.encode();
+        assert!(encoded.len() < 2 * 16 * 16 * 4);
+        let decoded = SceneFragment::decode(&encoded).unwrap();
+        assert_eq!(decoded, fragment);
+        match (&decoded.ops[0], &decoded.ops[1]) {
+            (Op::Fragment(a), Op::Fragment(b)) => assert!(Arc::ptr_eq(&a.ops, &b.ops)),
+            _ => panic!("expected two fragments"),
+        }
+    }
+
+    #[test]
+    fn malformed_encodings_are_errors() {
+        let mut recorder = RecordingRenderContext::new(NullText);
+        samples::get::<RecordingRenderContext>(9)
+            .unwrap()
+            .draw(&mut recorder)
+            .unwrap();
+        let encoded = recorder.into_fragment().encode();
+        for len in 0..encoded.len() {
+            assert!(SceneFragment::decode(&encoded[..len]).is_err());
+        }
+        let mut damaged = encoded.clone();
+        damaged[0] = b'X';
+        assert_eq!(
+            SceneFragment::decode(&damaged).unwrap_err(),
+            DecodeError::NotAFragment
+        );
+        let mut newer = encoded.clone();
+        newer[4] = encode::VERSION as u8 + 1;
+        assert_eq!(
+            SceneFragment::decode(&newer).unwrap_err(),
+            DecodeError::UnsupportedVersion(encode::VERSION + 1)
+        );
+        let mut longer = encoded;
+        longer.push(0);
+        assert_eq!(
+            SceneFragment::decode(&longer).unwrap_err(),
+            DecodeError::Invalid
+        );
+
+        // a restore that doesn't follow a save.
+        let mut recorder = RecordingRenderContext::new(NullText);
+        recorder.save().unwrap();
+        recorder.restore().unwrap();
+        let mut encoded = recorder.into_fragment().encode();
+        encoded.swap(7, 8);
+        assert_eq!(
+            SceneFragment::decode(&encoded).unwrap_err(),
+            DecodeError::Invalid
+        );
+    }
+
+    #[test]
+    fn unbalanced_saves_are_restored() {
+        let mut recorder = RecordingRenderContext::new(NullText);
+        recorder.save().unwrap();
+        recorder.transform(Affine::scale(2.0));
+        recorder.clip(Rect::new(0., 0., 1., 1.));
+        assert!(recorder.restore().is_ok());
+        assert!(matches!(recorder.restore(), Err(Error::StackUnbalance)));
+        recorder.save().unwrap();
+        recorder.transform(Affine::translate((3., 0.)));
+        let fragment = recorder.into_fragment();
+
+        let mut outer = RecordingRenderContext::new(NullText);
+        fragment.replay(&mut outer).unwrap();
+        assert_eq!(outer.current_transform(), Affine::IDENTITY);
+        assert!(outer.restore().is_err());
+    }
+
+    #[test]
+    fn unended_groups_are_ended() {
+        let mut recorder = RecordingRenderContext::new(NullText);
+        assert!(matches!(recorder.end_group(), Err(Error::StackUnbalance)));
+        recorder.begin_group("figure").unwrap();
+        recorder.annotate("title", "A figure");
+        let fragment = recorder.into_fragment();
+
+        let mut outer = RecordingRenderContext::new(NullText);
+        fragment.replay(&mut outer).unwrap();
+        assert!(outer.end_group().is_err());
+        let replayed = outer.into_fragment();
+        let ended = replayed.ops.iter().filter(|op| matches!(op, Op::EndGroup));
+        assert_eq!(ended.count(), 1);
+    }
+
+    #[test]
+    fn nested_fragments() {
+        let mut inner = RecordingRenderContext::new(NullText);
+        inner.fill(Rect::new(0., 0., 2., 2.), &Color::RED);
+        let inner = inner.into_fragment();
+
+        let mut outer = RecordingRenderContext::new(NullText);
+        outer.transform(Affine::translate((2., 2.)));
+        outer.draw_fragment(&inner);
+        outer.draw_fragment(&SceneFragment::default());
+        let outer = outer.into_fragment();
+
+        let mut pixmap = Pixmap::new(4, 4).unwrap();
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+        outer.replay(&mut rc).unwrap();
+        std::mem::drop(rc);
+        assert_eq!(pixmap.pixel(3, 3).unwrap().red(), 255);
+        assert_eq!(pixmap.pixel(1, 1).unwrap().alpha(), 0);
+    }
+
+    #[test]
+    fn masked_fills_are_replayed() {
+        let mut recorder = RecordingRenderContext::new(NullText);
+        let mask = recorder
+            .make_image(2, 1, &[255, 0], ImageFormat::Grayscale)
+            .unwrap();
+        recorder.fill_with_mask(Rect::new(0., 0., 4., 4.), &Color::RED, &mask);
+        let fragment = recorder.into_fragment();
+
+        let mut pixmap = Pixmap::new(4, 4).unwrap();
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+        fragment.replay(&mut rc).unwrap();
+        std::mem::drop(rc);
+        // a grayscale mask is opaque, so all of the shape is filled.
+        assert_eq!(pixmap.pixel(0, 0).unwrap().red(), 255);
+        assert_eq!(pixmap.pixel(3, 3).unwrap().alpha(), 255);
+    }
+
+    #[test]
+    fn opacity_masks_are_replayed_and_encoded() {
+        fn draw<R: RenderContext>(rc: &mut R, end: bool) {
+            let mask = rc
+                .make_image(2, 1, &[0, 0, 0, 255, 0, 0, 0, 0], ImageFormat::RgbaSeparate)
+                .unwrap();
+            let empty = rc.make_image(0, 0, &[], ImageFormat::RgbaSeparate).unwrap();
+            rc.with_opacity_mask(&empty, Rect::new(0., 0., 4., 4.), |rc| {
+                rc.fill(Rect::new(0., 0., 4., 4.), &Color::BLUE);
+                Ok(())
+            })
+            .unwrap();
+            rc.begin_opacity_mask(&mask, Rect::new(0., 0., 4., 2.))
+                .unwrap();
+            rc.save().unwrap();
+            rc.fill(Rect::new(0., 0., 4., 4.), &Color::RED);
+            if end {
+                rc.restore().unwrap();
+                rc.end_opacity_mask().unwrap();
+            }
+        }
+
+        let mut recorder = RecordingRenderContext::new(NullText);
+        draw(&mut recorder, false);
+        assert!(matches!(
+            recorder.end_opacity_mask(),
+            Err(Error::StackUnbalance)
+        ));
+        recorder.restore().unwrap();
+        assert!(matches!(recorder.restore(), Err(Error::StackUnbalance)));
+        recorder.save().unwrap();
+        let fragment = recorder.into_fragment();
+        let decoded = SceneFragment::decode(&fragment.encode()).unwrap();
+        assert!(decoded == fragment);
+
+        // what is left open is ended when the fragment is replayed.
+        let mut replayed = Pixmap::new(4, 4).unwrap();
+        let mut rc = TinySkiaRenderContext::new(replayed.as_mut(), 1.0);
+        decoded.replay(&mut rc).unwrap();
+        assert!(rc.restore().is_err());
+        std::mem::drop(rc);
+        let mut drawn = Pixmap::new(4, 4).unwrap();
+        draw(&mut TinySkiaRenderContext::new(drawn.as_mut(), 1.0), true);
+        assert!(replayed == drawn);
+        assert_eq!(replayed.pixel(0, 0).unwrap().red(), 255);
+        assert_eq!(replayed.pixel(0, 3).unwrap().alpha(), 0);
+    }
+
+    #[test]
+    fn inline_boxes_are_encoded() {
+        let mut recorder = RecordingRenderContext::new(NullText);
+        let layout = recorder
+            .text()
+            .new_text_layout("a box")
+            .inline_box(2, Size::new(10., 8.), BaselineAlignment::Baseline(6.))
+            .inline_box(99, Size::new(4., 4.), BaselineAlignment::Center)
+            .build()
+            .unwrap();
+        recorder.draw_text(&layout, Point::ZERO);
+        let fragment = recorder.into_fragment();
+        let decoded = SceneFragment::decode(&fragment.encode()).unwrap();
+        assert_eq!(decoded, fragment);
+    }
+
+    #[test]
+    fn hit_regions_are_replayed() {
+        let mut recorder = RecordingRenderContext::new(NullText);
+        recorder.transform(Affine::translate((10., 0.)));
+        recorder.hit_region(7, Rect::new(0., 0., 5., 5.));
+        recorder.hit_region_stroke(8, Rect::new(0., 0., 5., 5.), 2., &StrokeStyle::new());
+        let fragment = recorder.into_fragment();
+
+        let mut pixmap = Pixmap::new(4, 4).unwrap();
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+        fragment.replay(&mut rc).unwrap();
+        let tester = std::mem::take(rc.hit_tester().unwrap());
+        assert_eq!(tester.test(Point::new(12., 2.)), Some(7));
+        assert_eq!(tester.test(Point::new(15.5, 2.)), Some(8));
+        assert_eq!(tester.test(Point::new(2., 2.)), None);
+    }
+
+    #[test]
+    fn culled_scenes_only_record_what_is_visible() {
+        let recorder = RecordingRenderContext::new(NullText);
+        let mut rc = CullingRenderContext::new(recorder, Rect::new(0., 0., 100., 100.));
+        rc.fill(Rect::new(0., 0., 10., 10.), &Color::RED);
+        rc.fill(Rect::new(20., 0., 30., 10.), &Color::RED);
+        rc.fill(Rect::new(200., 0., 210., 10.), &Color::RED);
+        rc.stroke(Rect::new(0., 200., 10., 210.), &Color::BLUE, 1.);
+        rc.fill(Rect::new(40., 0., 50., 10.), &Color::BLUE);
+        let fragment = rc.into_inner().into_fragment();
+
+        // the two red squares are merged into one fill.
+        assert_eq!(fragment.ops.len(), 2);
+        assert!(matches!(
+            &fragment.ops[0],
+            Op::Fill {
+                shape: Geometry::Path(_),
+                ..
+            }
+        ));
+        assert!(matches!(
+            &fragment.ops[1],
+            Op::Fill {
+                shape: Geometry::Rect(_),
+                ..
+            }
+        ));
+    }
+}
@@ -0,0 +1,338 @@
+//! Recording text layouts, so that they can be rebuilt by another backend.
+
+use std::ops::{Range, RangeBounds};
+use std::sync::{Arc, Mutex};
+
+use piet::kurbo::{BezPath, Point, Rect, Size};
+use piet::{
+    BaselineAlignment, Error, FontFamily, GlyphRun, Hinting, HitTestPoint, HitTestPosition,
+    LineHeight, LineMetric, Overflow, Text, TextAlignment, TextAttribute, TextLayout,
+    TextLayoutBuilder, TextStorage, WritingMode,
+};
+
+/// A text factory that measures text with another backend's factory, and
+/// records how each layout was built.
+///
+/// Layouts are measured by `T`; when a recording is replayed, they are
+/// rebuilt by the text factory of the context it is replayed into.
+#[derive(Clone)]
+pub struct RecordingText<T> {
+    inner: T,
+    /// The data of fonts loaded with `load_font`, which are loaded again
+    /// before replaying layouts. This is shared with the clones of the
+    /// factory, which may be on other threads.
+    fonts: Arc<Mutex<Vec<Arc<[u8]>>>>,
+}
+
+pub struct RecordingTextLayoutBuilder<T: Text> {
+    inner: T::TextLayoutBuilder,
+    description: TextDescription,
+}
+
+/// A text layout, and what is needed to build it again.
+#[derive(Clone)]
+pub struct RecordingTextLayout<T: Text> {
+    inner: T::TextLayout,
+    pub(crate) description: Arc<TextDescription>,
+}
+
+/// Everything needed to build a text layout with any backend.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct TextDescription {
+    pub(crate) text: Arc<str>,
+    /// Fonts that had been loaded when the layout was built.
+    pub(crate) fonts: Vec<Arc<[u8]>>,
+    pub(crate) calls: Vec<BuilderCall>,
+}
+
+/// A call to a [`TextLayoutBuilder`] method.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum BuilderCall {
+    MaxWidth(f64),
+    MaxHeight(f64),
+    Overflow(Overflow),
+    Alignment(TextAlignment),
+    ParagraphAlignment(Range<usize>, TextAlignment),
+    WritingMode(WritingMode),
+    LineHeight(LineHeight),
+    ParagraphSpacing(f64),
+    TabStops(Arc<[f64]>),
+    TabWidth(f64),
+    InlineBox(usize, Size, BaselineAlignment),
+    DefaultAttribute(TextAttribute),
+    RangeAttribute(Range<usize>, TextAttribute),
+}
+
+impl<T: Text> RecordingText<T> {
+    pub(crate) fn new(inner: T) -> RecordingText<T> {
+        RecordingText {
+            inner,
+            fonts: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+}
+
+impl<T: Text> Text for RecordingText<T> {
+    type TextLayout = RecordingTextLayout<T>;
+    type TextLayoutBuilder = RecordingTextLayoutBuilder<T>;
+
+    fn font_family(&mut self, family_name: &str) -> Option<FontFamily> {
+        self.inner.font_family(family_name)
+    }
+
+    fn load_font(&mut self, data: &[u8]) -> Result<FontFamily, Error> {
+        let family = self.inner.load_font(data)?;
+        self.fonts.lock().unwrap().push(data.into());
+        Ok(family)
+    }
+
+    fn new_text_layout(&mut self, text: impl TextStorage) -> Self::TextLayoutBuilder {
+        let description = TextDescription {
+            text: text.as_str().into(),
+            fonts: self.fonts.lock().unwrap().clone(),
+            calls: Vec::new(),
+        };
+        RecordingTextLayoutBuilder {
+            inner: self.inner.new_text_layout(text),
+            description,
+        }
+    }
+
+    fn set_hinting(&mut self, hinting: Hinting) {
+        self.inner.set_hinting(hinting);
+    }
+
+    fn set_color_glyphs(&mut self, color: bool) {
+        self.inner.set_color_glyphs(color);
+    }
+}
+
+impl<T: Text> RecordingTextLayoutBuilder<T> {
+    fn record(mut self, call: BuilderCall) -> Self {
+        self.description.calls.push(call);
+        self
+    }
+}
+
+impl<T: Text> TextLayoutBuilder for RecordingTextLayoutBuilder<T> {
+    type Out = RecordingTextLayout<T>;
+
+    fn max_width(mut self, width: f64) -> Self {
+        self.inner = self.inner.max_width(width);
+        self.record(BuilderCall::MaxWidth(width))
+    }
+
+    fn max_height(mut self, height: f64) -> Self {
+        self.inner = self.inner.max_height(height);
+        self.record(BuilderCall::MaxHeight(height))
+    }
+
+    fn overflow(mut self, overflow: Overflow) -> Self {
+        self.inner = self.inner.overflow(overflow);
+        self.record(BuilderCall::Overflow(overflow))
+    }
+
+    fn alignment(mut self, alignment: TextAlignment) -> Self {
+        self.inner = self.inner.alignment(alignment);
+        self.record(BuilderCall::Alignment(alignment))
+    }
+
+    fn paragraph_alignment(
+        mut self,
+        range: impl RangeBounds<usize>,
+        alignment: TextAlignment,
+    ) -> Self {
+        let range = piet::util::resolve_range(range, self.description.text.len());
+        self.inner = self.inner.paragraph_alignment(range.clone(), alignment);
+        self.record(BuilderCall::ParagraphAlignment(range, alignment))
+    }
+
+    fn writing_mode(mut self, mode: WritingMode) -> Self {
+        self.inner = self.inner.writing_mode(mode);
+        self.record(BuilderCall::WritingMode(mode))
+    }
+
+    fn line_height(mut self, line_height: LineHeight) -> Self {
+        self.inner = self.inner.line_height(line_height);
+        self.record(BuilderCall::LineHeight(line_height))
+    }
+
+    fn paragraph_spacing(mut self, spacing: f64) -> Self {
+        self.inner = self.inner.paragraph_spacing(spacing);
+        self.record(BuilderCall::ParagraphSpacing(spacing))
+    }
+
+    fn tab_stops(mut self, stops: &[f64]) -> Self {
+        self.inner = self.inner.tab_stops(stops);
+        self.record(BuilderCall::TabStops(stops.into()))
+    }
+
+    fn tab_width(mut self, width: f64) -> Self {
+        self.inner = self.inner.tab_width(width);
+        self.record(BuilderCall::TabWidth(width))
+    }
+
+    fn inline_box(mut self, index: usize, size: Size, alignment: BaselineAlignment) -> Self {
+        let index = index.min(self.description.text.len());
+        self.inner = self.inner.inline_box(index, size, alignment);
+        self.record(BuilderCall::InlineBox(index, size, alignment))
+    }
+
+    /// The hyphenator is used for measuring, but it isn't recorded, so
+    /// replayed layouts are not hyphenated.
+    fn hyphenator(mut self, hyphenator: impl Fn(&str) -> Vec<usize> + 'static) -> Self {
+        self.inner = self.inner.hyphenator(hyphenator);
+        self
+    }
+
+    fn default_attribute(mut self, attribute: impl Into<TextAttribute>) -> Self {
+        let attribute = attribute.into();
+        self.inner = self.inner.default_attribute(attribute.clone());
+        self.record(BuilderCall::DefaultAttribute(attribute))
+    }
+
+    fn range_attribute(
+        mut self,
+        range: impl RangeBounds<usize>,
+        attribute: impl Into<TextAttribute>,
+    ) -> Self {
+        let range = piet::util::resolve_range(range, self.description.text.len());
+        let attribute = attribute.into();
+        self.inner = self.inner.range_attribute(range.clone(), attribute.clone());
+        self.record(BuilderCall::RangeAttribute(range, attribute))
+    }
+
+    fn build(self) -> Result<Self::Out, Error> {
+        Ok(RecordingTextLayout {
+            inner: self.inner.build()?,
+            description: Arc::new(self.description),
+        })
+    }
+}
+
+impl TextDescription {
+    /// Build this layout with the text factory `text`, loading any fonts it
+    /// needs that aren't in `loaded` first.
+    pub(crate) fn build<T: Text>(
+        &self,
+        text: &mut T,
+        loaded: &mut Vec<Arc<[u8]>>,
+    ) -> Result<T::TextLayout, Error> {
+        for font in &self.fonts {
+            if !loaded.iter().any(|other| Arc::ptr_eq(font, other)) {
+                text.load_font(font)?;
+                loaded.push(font.clone());
+            }
+        }
+        let mut builder = text.new_text_layout(self.text.clone());
+        for call in &self.calls {
+            builder = match call {
+                BuilderCall::MaxWidth(width) => builder.max_width(*width),
+                BuilderCall::MaxHeight(height) => builder.max_height(*height),
+                BuilderCall::Overflow(overflow) => builder.overflow(*overflow),
+                BuilderCall::Alignment(alignment) => builder.alignment(*alignment),
+                BuilderCall::ParagraphAlignment(range, alignment) => {
+                    builder.paragraph_alignment(range.clone(), *alignment)
+                }
+                BuilderCall::WritingMode(mode) => builder.writing_mode(*mode),
+                BuilderCall::LineHeight(height) => builder.line_height(*height),
+                BuilderCall::ParagraphSpacing(spacing) => builder.paragraph_spacing(*spacing),
+                BuilderCall::TabStops(stops) => builder.tab_stops(stops),
+                BuilderCall::TabWidth(width) => builder.tab_width(*width),
+                BuilderCall::InlineBox(index, size, alignment) => {
+                    builder.inline_box(*index, *size, *alignment)
+                }
+                BuilderCall::DefaultAttribute(attribute) => {
+                    builder.default_attribute(attribute.clone())
+                }
+                BuilderCall::RangeAttribute(range, attribute) => {
+                    builder.range_attribute(range.clone(), attribute.clone())
+                }
+            };
+        }
+        builder.build()
+    }
+}
+
+impl<T: Text> TextLayout for RecordingTextLayout<T> {
+    fn size(&self) -> Size {
+        self.inner.size()
+    }
+
+    fn trailing_whitespace_width(&self) -> f64 {
+        self.inner.trailing_whitespace_width()
+    }
+
+    fn image_bounds(&self) -> Rect {
+        self.inner.image_bounds()
+    }
+
+    fn text(&self) -> &str {
+        self.inner.text()
+    }
+
+    fn line_text(&self, line_number: usize) -> Option<&str> {
+        self.inner.line_text(line_number)
+    }
+
+    fn line_metric(&self, line_number: usize) -> Option<LineMetric> {
+        self.inner.line_metric(line_number)
+    }
+
+    fn line_count(&self) -> usize {
+        self.inner.line_count()
+    }
+
+    fn hit_test_point(&self, point: Point) -> HitTestPoint {
+        self.inner.hit_test_point(point)
+    }
+
+    fn hit_test_text_position(&self, idx: usize) -> HitTestPosition {
+        self.inner.hit_test_text_position(idx)
+    }
+
+    fn rects_for_range(&self, range: impl RangeBounds<usize>) -> Vec<Rect> {
+        self.inner.rects_for_range(range)
+    }
+
+    fn glyph_runs(&self) -> Vec<GlyphRun> {
+        self.inner.glyph_runs()
+    }
+
+    fn outline(&self) -> BezPath {
+        self.inner.outline()
+    }
+
+    fn inline_boxes(&self) -> Vec<Rect> {
+        self.inner.inline_boxes()
+    }
+
+    fn relayout_width(&mut self, width: f64) -> bool {
+        if !self.inner.relayout_width(width) {
+            return false;
+        }
+        // the layout is built again with the width it has now.
+        let description = Arc::make_mut(&mut self.description);
+        description
+            .calls
+            .retain(|call| !matches!(call, BuilderCall::MaxWidth(_)));
+        description.calls.push(BuilderCall::MaxWidth(width));
+        true
+    }
+
+    fn next_caret(&self, offset: usize) -> usize {
+        self.inner.next_caret(offset)
+    }
+
+    fn prev_caret(&self, offset: usize) -> usize {
+        self.inner.prev_caret(offset)
+    }
+
+    fn caret_up(&self, offset: usize, x: f64) -> (usize, HitTestPosition) {
+        self.inner.caret_up(offset, x)
+    }
+
+    fn caret_down(&self, offset: usize, x: f64) -> (usize, HitTestPosition) {
+        self.inner.caret_down(offset, x)
+    }
+}
@@ -0,0 +1,182 @@
+//! Finding the data of fonts, among those that were loaded and, with the
+//! `system-fonts` feature, those installed on the system.
+
+use std::sync::Arc;
+
+use piet::{Error, FontStyle};
+use ttf_parser::name_id;
+
+use crate::text::FontFace;
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// The fonts that text can be laid out with.
+pub(crate) struct FontSource {
+    /// The fonts loaded with [`piet::Text::load_font`].
+    loaded: Vec<LoadedFont>,
+    #[cfg(feature = "system-fonts")]
+    system: font_kit::source::SystemSource,
+}
+
+struct LoadedFont {
+    family: String,
+    weight: u16,
+    italic: bool,
+    data: Arc<Vec<u8>>,
+}
+
+impl FontSource {
+    pub(crate) fn new() -> FontSource {
+        FontSource {
+            loaded: Vec::new(),
+            #[cfg(feature = "system-fonts")]
+            system: font_kit::source::SystemSource::new(),
+        }
+    }
+
+    /// Add a font, returning the name of its family.
+    pub(crate) fn load(&mut self, data: &[u8]) -> Result<String> {
+        let face = ttf_parser::Face::from_slice(data, 0).map_err(|_| Error::FontLoadingFailed)?;
+        // the typographic family groups more weights and styles, where a
+        // font has one.
+        let family = [name_id::TYPOGRAPHIC_FAMILY, name_id::FAMILY]
+            .iter()
+            .find_map(|id| {
+                face.names()
+                    .filter(|name| name.name_id() == *id && name.is_unicode())
+                    .find_map(|name| name.to_string())
+            })
+            .ok_or(Error::FontLoadingFailed)?;
+        self.loaded.push(LoadedFont {
+            family: family.clone(),
+            weight: face.weight().to_number(),
+            italic: face.is_italic(),
+            data: Arc::new(data.into()),
+        });
+        Ok(family)
+    }
+
+    /// Returns `true` if there is a font in the family called `name`.
+    pub(crate) fn has_family(&self, name: &str) -> bool {
+        if self
+            .loaded
+            .iter()
+            .any(|font| font.family.eq_ignore_ascii_case(name))
+        {
+            return true;
+        }
+        #[cfg(feature = "system-fonts")]
+        {
+            use font_kit::{family_name::FamilyName, properties::Properties};
+            self.system
+                .select_best_match(&[FamilyName::Title(name.into())], &Properties::new())
+                .is_ok()
+        }
+        #[cfg(not(feature = "system-fonts"))]
+        false
+    }
+
+    /// The data of the font that best matches `face`.
+    ///
+    /// Loaded fonts are preferred over those on the system. Without system
+    /// fonts, the generic families, such as [`piet::FontFamily::SANS_SERIF`], are
+    /// the first font that was loaded.
+    pub(crate) fn font_data(&self, face: &FontFace) -> Result<Arc<Vec<u8>>> {
+        let weight = face.weight.to_raw();
+        let italic = face.style == FontStyle::Italic;
+        let best = self
+            .loaded
+            .iter()
+            .filter(|font| font.family.eq_ignore_ascii_case(face.family.name()))
+            .min_by_key(|font| {
+                (
+                    font.italic != italic,
+                    font.weight.max(weight) - font.weight.min(weight),
+                )
+            });
+        if let Some(font) = best {
+            return Ok(font.data.clone());
+        }
+        #[cfg(feature = "system-fonts")]
+        {
+            system::font_data(&self.system, face)
+        }
+        #[cfg(not(feature = "system-fonts"))]
+        {
+            if face.family.is_generic() {
+                if let Some(font) = self.loaded.first() {
+                    return Ok(font.data.clone());
+                }
+            }
+            Err(Error::FontLoadingFailed)
+        }
+    }
+}
+
+#[cfg(feature = "system-fonts")]
+mod system {
+    use std::{fs, io, sync::Arc};
+
+    use font_kit::{
+        family_name::FamilyName,
+        handle::Handle,
+        properties::{Properties, Style},
+        source::SystemSource,
+    };
+    use piet::{Error, FontFamily, FontStyle};
+
+    use crate::text::FontFace;
+
+    pub(super) fn font_data(source: &SystemSource, face: &FontFace) -> Result<Arc<Vec<u8>>, Error> {
+        let handle = source
+            .select_best_match(&[to_fk_family(&face.family)], &to_props(face))
+            .map_err(|_| Error::FontLoadingFailed)?;
+        load_font_data(handle).map_err(|_| Error::FontLoadingFailed)
+    }
+
+    fn to_fk_family(family: &FontFamily) -> FamilyName {
+        if *family == FontFamily::SANS_SERIF || *family == FontFamily::SYSTEM_UI {
+            FamilyName::SansSerif
+        } else if *family == FontFamily::SERIF {
+            FamilyName::Serif
+        } else if *family == FontFamily::MONOSPACE {
+            FamilyName::Monospace
+        } else {
+            FamilyName::Title(family.name().to_owned())
+        }
+    }
+
+    fn to_props(face: &FontFace) -> Properties {
+        let mut props = Properties::new();
+        props.weight.0 = face.weight.to_raw() as f32;
+        props.style = match face.style {
+            FontStyle::Regular => Style::Normal,
+            FontStyle::Italic => Style::Italic,
+        };
+        props
+    }
+
+    fn load_font_data(handle: Handle) -> io::Result<Arc<Vec<u8>>> {
+        // Load font data
+        Ok(match handle {
+            Handle::Path { path, font_index } => {
+                if font_index > 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "font collections not supported",
+                    ));
+                }
+                Arc::new(fs::read(path)?)
+            }
+            Handle::Memory { bytes, font_index } => {
+                if font_index > 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "font collections not supported",
+                    ));
+                }
+                bytes
+            }
+        })
+    }
+}
@@ -1,18 +1,26 @@
 //! SVG output support for piet
+//!
+//! SVG has no line breaking, so text layouts are always a single line: the
+//! `max_width` of a layout doesn't wrap its text, and is only used to align
+//! it with [`TextAlignment::End`] or [`TextAlignment::Center`].
+//! [`TextAlignment::Justified`] text is drawn as [`TextAlignment::Start`],
+//! as the last line of a justified paragraph would be.
 
 #![deny(clippy::trivially_copy_pass_by_ref)]
 
 #[cfg(feature = "evcxr")]
 mod evcxr;
+mod fonts;
 mod text;
 
-use std::{borrow::Cow, fmt, fmt::Write, io, mem};
+use std::{borrow::Cow, fmt, fmt::Write, io, mem, sync::Arc};
 
 use image::{DynamicImage, GenericImageView, ImageBuffer};
-use piet::kurbo::{Affine, Point, Rect, Shape, Size};
+use piet::kurbo::{Affine, Insets, Point, Rect, Shape, Size, Vec2};
 use piet::{
-    Color, Error, FixedGradient, FontStyle, Image, ImageFormat, InterpolationMode, IntoBrush,
-    LineCap, LineJoin, StrokeStyle, TextAlignment, TextLayout as _,
+    BlendMode, Capabilities, Color, Error, FixedGradient, FontStyle, HitTester, Image, ImageFilter,
+    ImageFormat, InterpolationMode, IntoBrush, LineCap, LineJoin, Markers, StrokeStyle,
+    TextAlignment, TextLayout as _, TextShapingQuality,
 };
 use svg::node::Node;
 
@@ -28,33 +36,135 @@ type Result<T> = std::result::Result<T, Error>;
 /// `piet::RenderContext` for generating SVG images
 pub struct RenderContext {
     size: Size,
+    view_box: Rect,
+    preserve_aspect_ratio: Option<PreserveAspectRatio>,
     stack: Vec<State>,
     state: State,
     doc: svg::Document,
     next_id: u64,
     text: Text,
+    /// Extra attributes for the elements being drawn; see
+    /// [`RenderContext::with_element_attrs`].
+    element_attrs: Vec<(String, String)>,
+    /// Groups that have been begun but not ended, innermost last.
+    groups: Vec<svg::node::element::Group>,
+    hit_tester: HitTester,
+}
+
+/// Options for the document made by a [`RenderContext`].
+#[derive(Debug, Clone, Default)]
+pub struct SvgOptions {
+    view_box: Option<Rect>,
+    preserve_aspect_ratio: Option<PreserveAspectRatio>,
+    background: Option<Color>,
+}
+
+/// How the view box is fitted into the size of the SVG, when their aspect
+/// ratios differ.
+///
+/// This is the SVG `preserveAspectRatio` attribute; when it isn't set,
+/// viewers use `Meet(Align::Mid, Align::Mid)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreserveAspectRatio {
+    /// Stretch the view box to fill the SVG exactly.
+    Stretch,
+    /// Scale the view box uniformly so that all of it is visible, and align
+    /// it horizontally and vertically within the SVG.
+    Meet(Align, Align),
+    /// Scale the view box uniformly so that it covers the whole SVG, and
+    /// align it horizontally and vertically; whatever doesn't fit is cut off.
+    Slice(Align, Align),
+}
+
+/// Where the view box is placed along one axis; see [`PreserveAspectRatio`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    /// The left or top edges line up.
+    Min,
+    /// The centers line up.
+    Mid,
+    /// The right or bottom edges line up.
+    Max,
+}
+
+impl SvgOptions {
+    /// The default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the area of the drawing that is shown.
+    ///
+    /// By default, this is the rectangle from the origin to the size of the
+    /// SVG, so anything drawn outside of that is cut off.
+    pub fn view_box(mut self, view_box: Rect) -> Self {
+        self.view_box = Some(view_box);
+        self
+    }
+
+    /// Set how the view box is fitted into the size of the SVG.
+    pub fn preserve_aspect_ratio(mut self, preserve_aspect_ratio: PreserveAspectRatio) -> Self {
+        self.preserve_aspect_ratio = Some(preserve_aspect_ratio);
+        self
+    }
+
+    /// Fill the view box with `color` before anything is drawn.
+    ///
+    /// By default, the background is transparent.
+    pub fn background(mut self, color: Color) -> Self {
+        self.background = Some(color);
+        self
+    }
 }
 
 impl RenderContext {
     /// Construct an empty `RenderContext`
     pub fn new(size: Size) -> Self {
+        Self::with_options(size, SvgOptions::default())
+    }
+
+    /// Construct an empty `RenderContext` that makes a document with the
+    /// given options.
+    ///
+    /// `size` is the width and height of the SVG, in pixels.
+    pub fn with_options(size: Size, options: SvgOptions) -> Self {
+        let view_box = options.view_box.unwrap_or_else(|| size.to_rect());
+        let mut doc = svg::Document::new();
+        if let Some(color) = options.background {
+            doc.append(
+                view_box_rect(view_box)
+                    .set("fill", fmt_color(color))
+                    .set("fill-opacity", fmt_opacity(color)),
+            );
+        }
         Self {
             size,
+            view_box,
+            preserve_aspect_ratio: options.preserve_aspect_ratio,
             stack: Vec::new(),
             state: State::default(),
-            doc: svg::Document::new(),
+            doc,
             next_id: 0,
             text: Text::new(),
+            element_attrs: Vec::new(),
+            groups: Vec::new(),
+            hit_tester: HitTester::new(),
         }
     }
 
     /// The size that the SVG will render at.
-    ///
-    /// The size is used to set the view box for the svg.
     pub fn size(&self) -> Size {
         self.size
     }
 
+    /// The area of the drawing that is shown in the SVG.
+    ///
+    /// Unless it was set with [`SvgOptions::view_box`], this is the
+    /// rectangle from the origin to [`size`](RenderContext::size).
+    pub fn view_box(&self) -> Rect {
+        self.view_box
+    }
+
     /// Write graphics rendered so far to an `std::io::Write` impl, such as `std::fs::File`
     ///
     /// Additional rendering can be done afterwards.
@@ -67,11 +177,70 @@ impl RenderContext {
         &self.doc
     }
 
+    /// Run `f`, adding the attributes in `attrs` to every element it draws.
+    ///
+    /// This is meant for `class`, `id` and `data-*` attributes, so that the
+    /// SVG can be styled with CSS or scripted; for example,
+    /// `rc.with_element_attrs(&[("class", "axis")], |rc| ...)`. Calls can be
+    /// nested: classes are added to those of the enclosing calls, and other
+    /// attributes replace theirs. Because every element is tagged, an `id`
+    /// should only be set around a single drawing call. Clip paths and
+    /// gradients get ids made only of letters, so ids set here should contain
+    /// some other character, such as a hyphen or digit, to avoid clashing.
+    ///
+    /// The attributes take precedence over those that piet sets itself, such
+    /// as `fill`. Clip paths and gradients are not tagged.
+    pub fn with_element_attrs<R>(
+        &mut self,
+        attrs: &[(&str, &str)],
+        f: impl FnOnce(&mut Self) -> R,
+    ) -> R {
+        let saved = self.element_attrs.clone();
+        for &(name, value) in attrs {
+            match self
+                .element_attrs
+                .iter_mut()
+                .find(|(other, _)| other == name)
+            {
+                Some((_, old)) if name == "class" => {
+                    old.push(' ');
+                    old.push_str(value);
+                }
+                Some((_, old)) => *old = value.to_string(),
+                None => self.element_attrs.push((name.into(), value.into())),
+            }
+        }
+        let result = f(self);
+        self.element_attrs = saved;
+        result
+    }
+
     fn new_id(&mut self) -> Id {
         let x = Id(self.next_id);
         self.next_id += 1;
         x
     }
+
+    /// Add a filter of `primitives`, which are applied to whatever refers to
+    /// it within `region`, in its user space, and return its id.
+    fn add_filter(&mut self, region: Rect, primitives: Vec<svg::node::element::Element>) -> Id {
+        let id = self.new_id();
+        // blurs and color matrices are in sRGB, like those of the other
+        // backends, rather than in the linear RGB that is the default.
+        let mut filter = svg::node::element::Filter::new()
+            .set("id", id)
+            .set("filterUnits", "userSpaceOnUse")
+            .set("x", region.x0)
+            .set("y", region.y0)
+            .set("width", region.width())
+            .set("height", region.height())
+            .set("color-interpolation-filters", "sRGB");
+        for primitive in primitives {
+            filter.append(primitive);
+        }
+        self.doc.append(filter);
+        id
+    }
 }
 
 impl piet::RenderContext for RenderContext {
@@ -94,9 +263,7 @@ impl piet::RenderContext for RenderContext {
                 .set("height", rect.height())
                 .set("x", rect.x0)
                 .set("y", rect.y0),
-            None => svg::node::element::Rectangle::new()
-                .set("width", "100%")
-                .set("height", "100%"),
+            None => view_box_rect(self.view_box),
         }
         .set("fill", fmt_color(color))
         .set("fill-opacity", fmt_opacity(color));
@@ -104,7 +271,8 @@ impl piet::RenderContext for RenderContext {
         if let Some(id) = self.state.clip {
             rect.assign("clip-path", format!("url(#{})", id.to_string()));
         }
-        self.doc.append(rect);
+        apply_element_attrs(&mut rect, &self.element_attrs);
+        append_drawn(&mut self.doc, &mut self.groups, rect);
     }
 
     fn solid_brush(&mut self, color: Color) -> Brush {
@@ -114,6 +282,10 @@ impl piet::RenderContext for RenderContext {
     }
 
     fn gradient(&mut self, gradient: impl Into<FixedGradient>) -> Result<Brush> {
+        // with `userSpaceOnUse`, the gradient is in the space of the element
+        // that refers to it, including its `transform`, so it follows the
+        // transform of each fill, as it does in the other backends, and has
+        // no `gradientTransform` of its own.
         let id = self.new_id();
         match gradient.into() {
             FixedGradient::Linear(x) => {
@@ -161,12 +333,16 @@ impl piet::RenderContext for RenderContext {
 
     fn fill(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>) {
         let brush = brush.make_brush(self, || shape.bounding_box());
-        add_shape(
+        add_drawn_shape(
             &mut self.doc,
+            &mut self.groups,
             shape,
             &Attrs {
                 xf: self.state.xf,
                 clip: self.state.clip,
+                opacity: self.state.opacity(),
+                blend_mode: self.state.blend_mode,
+                element_attrs: &self.element_attrs,
                 fill: Some((brush.into_owned(), None)),
                 ..Attrs::default()
             },
@@ -175,18 +351,70 @@ impl piet::RenderContext for RenderContext {
 
     fn fill_even_odd(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>) {
         let brush = brush.make_brush(self, || shape.bounding_box());
-        add_shape(
+        add_drawn_shape(
             &mut self.doc,
+            &mut self.groups,
             shape,
             &Attrs {
                 xf: self.state.xf,
                 clip: self.state.clip,
+                opacity: self.state.opacity(),
+                blend_mode: self.state.blend_mode,
+                element_attrs: &self.element_attrs,
                 fill: Some((brush.into_owned(), Some("evenodd"))),
                 ..Attrs::default()
             },
         );
     }
 
+    fn fill_with_mask(
+        &mut self,
+        shape: impl Shape,
+        brush: &impl IntoBrush<Self>,
+        mask: &Self::Image,
+    ) {
+        let brush = brush.make_brush(self, || shape.bounding_box());
+        let bounds = shape.bounding_box();
+        let size = mask.size();
+        if bounds.is_empty() || size.is_empty() {
+            return;
+        }
+        // the mask is in the space of the shape, since it has the same transform.
+        let id = self.new_id();
+        let image = svg::node::element::Image::new()
+            .set("x", bounds.x0)
+            .set("y", bounds.y0)
+            .set("width", bounds.width())
+            .set("height", bounds.height())
+            .set("preserveAspectRatio", "none")
+            .set("href", data_url(mask));
+        let mask = svg::node::element::Mask::new()
+            .set("id", id)
+            .set("mask-type", "alpha")
+            .set("maskUnits", "userSpaceOnUse")
+            .set("x", bounds.x0)
+            .set("y", bounds.y0)
+            .set("width", bounds.width())
+            .set("height", bounds.height())
+            .add(image);
+        self.doc.append(mask);
+        add_drawn_shape(
+            &mut self.doc,
+            &mut self.groups,
+            shape,
+            &Attrs {
+                xf: self.state.xf,
+                clip: self.state.clip,
+                opacity: self.state.opacity(),
+                blend_mode: self.state.blend_mode,
+                mask: Some(id),
+                element_attrs: &self.element_attrs,
+                fill: Some((brush.into_owned(), None)),
+                ..Attrs::default()
+            },
+        );
+    }
+
     fn clip(&mut self, shape: impl Shape) {
         let id = self.new_id();
         let mut clip = svg::node::element::ClipPath::new().set("id", id);
@@ -205,12 +433,16 @@ impl piet::RenderContext for RenderContext {
 
     fn stroke(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>, width: f64) {
         let brush = brush.make_brush(self, || shape.bounding_box());
-        add_shape(
+        add_drawn_shape(
             &mut self.doc,
+            &mut self.groups,
             shape,
             &Attrs {
                 xf: self.state.xf,
                 clip: self.state.clip,
+                opacity: self.state.opacity(),
+                blend_mode: self.state.blend_mode,
+                element_attrs: &self.element_attrs,
                 stroke: Some((brush.into_owned(), width, &StrokeStyle::new())),
                 ..Attrs::default()
             },
@@ -224,13 +456,80 @@ impl piet::RenderContext for RenderContext {
         width: f64,
         style: &StrokeStyle,
     ) {
+        if piet::util::stroke_dash_caps(self, &shape, brush, width, style) {
+            return;
+        }
         let brush = brush.make_brush(self, || shape.bounding_box());
-        add_shape(
+        add_drawn_shape(
             &mut self.doc,
+            &mut self.groups,
             shape,
             &Attrs {
                 xf: self.state.xf,
                 clip: self.state.clip,
+                opacity: self.state.opacity(),
+                blend_mode: self.state.blend_mode,
+                element_attrs: &self.element_attrs,
+                stroke: Some((brush.into_owned(), width, style)),
+                ..Attrs::default()
+            },
+        );
+    }
+
+    fn stroke_with_markers(
+        &mut self,
+        shape: impl Shape,
+        brush: &impl IntoBrush<Self>,
+        width: f64,
+        style: &StrokeStyle,
+        markers: &Markers,
+    ) {
+        let brush = brush.make_brush(self, || shape.bounding_box());
+        // markers are filled in their own space, where gradients would be
+        // misplaced, and aren't kept from scaling by a non-scaling stroke.
+        let separate_dash_caps = style.effective_dash_cap() != style.line_cap;
+        if !matches!(brush.kind, BrushKind::Solid(_)) || !style.scale || separate_dash_caps {
+            piet::util::stroke_with_markers(self, shape, &*brush, width, style, markers);
+            return;
+        }
+        let layout = markers.layout(&shape.into_path(1e-3), width);
+        let mut ids = [None, None];
+        for (id, placed) in ids.iter_mut().zip([layout.start, layout.end]) {
+            let marker = match placed {
+                Some((marker, _)) => marker,
+                None => continue,
+            };
+            let mut path = svg::node::element::Path::new().set("d", marker.to_path(0.01).to_svg());
+            Attrs {
+                fill: Some((brush.clone().into_owned(), None)),
+                ..Attrs::default()
+            }
+            .apply_to(&mut path);
+            let new_id = self.new_id();
+            let mut def = svg::node::element::Marker::new()
+                .set("id", new_id)
+                .set("markerUnits", "strokeWidth")
+                .set("orient", "auto-start-reverse")
+                .set("overflow", "visible")
+                .add(path);
+            // the vertex is at the shortened end of the path.
+            if marker.setback() != 0.0 {
+                def.assign("refX", -marker.setback());
+            }
+            self.doc.append(def);
+            *id = Some(new_id);
+        }
+        add_drawn_shape(
+            &mut self.doc,
+            &mut self.groups,
+            layout.path,
+            &Attrs {
+                xf: self.state.xf,
+                clip: self.state.clip,
+                opacity: self.state.opacity(),
+                blend_mode: self.state.blend_mode,
+                markers: ids,
+                element_attrs: &self.element_attrs,
                 stroke: Some((brush.into_owned(), width, style)),
                 ..Attrs::default()
             },
@@ -253,16 +552,47 @@ impl piet::RenderContext for RenderContext {
         // SVG doesn't do multiline text, and so doesn't have a concept of text width. We can do
         // alignment though, using text-anchor. TODO eventually we should generate a separate text
         // span for each line (having laid out the multiline text ourselves.
-        let anchor = match (layout.max_width, layout.alignment) {
+        //
+        // `Justified` falls through to start alignment, see the crate docs.
+        let (align, anchor) = match (layout.max_width, layout.alignment) {
             (width, TextAlignment::End) if width.is_finite() && width > 0. => {
                 x += width;
-                "text-anchor:end"
+                (1., "text-anchor:end")
             }
             (width, TextAlignment::Center) if width.is_finite() && width > 0. => {
                 x += width * 0.5;
-                "text-anchor:middle"
+                (0.5, "text-anchor:middle")
             }
-            _ => "",
+            _ => (0., ""),
+        };
+        let text_start = x - align * layout.size().width;
+
+        if layout.background_color != Color::TRANSPARENT {
+            let brush = self.solid_brush(layout.background_color);
+            for rect in layout.rects_for_range(..) {
+                add_drawn_shape(
+                    &mut self.doc,
+                    &mut self.groups,
+                    rect + Vec2::new(text_start, pos.y),
+                    &Attrs {
+                        xf: self.state.xf,
+                        clip: self.state.clip,
+                        opacity: self.state.opacity(),
+                        blend_mode: self.state.blend_mode,
+                        element_attrs: &self.element_attrs,
+                        fill: Some((brush.clone(), None)),
+                        ..Attrs::default()
+                    },
+                );
+            }
+        }
+        // Text following a tab or an inline box is positioned explicitly, which would restart the
+        // anchoring, so in that case we align the start of the text ourselves.
+        let anchor = if layout.run_positions.is_empty() {
+            anchor
+        } else {
+            x = text_start;
+            ""
         };
 
         // If we are using a named font, then mark it for inclusion.
@@ -277,8 +607,8 @@ impl piet::RenderContext for RenderContext {
         //
         // `dominant-baseline` gets us most of the way (to the top of the ascender), so we add a
         // small fiddle factor in to cover the difference between the top of the line and the top
-        // of the ascender (currently 6% of the font height, calcuated by eye).
-        let y = pos.y + 0.06 * layout.size().height;
+        // of the ascender (currently 6% of the natural font height, calcuated by eye).
+        let y = pos.y + layout.half_leading + 0.06 * layout.natural_height - layout.baseline_shift;
         let mut text = svg::node::element::Text::new()
             .set("x", x)
             .set("y", y)
@@ -291,7 +621,12 @@ impl piet::RenderContext for RenderContext {
                         font-weight:{};\
                         font-style:{};\
                         text-decoration:{};\
+                        letter-spacing:{}pt;\
+                        word-spacing:{}pt;\
+                        {}\
+                        {}\
                         fill:{};\
+                        {}\
                         {}",
                     layout.font_size,
                     layout.font_face.family.name(),
@@ -306,11 +641,16 @@ impl piet::RenderContext for RenderContext {
                         (true, false) => "underline",
                         (true, true) => "underline line-through",
                     },
+                    layout.letter_spacing,
+                    layout.word_spacing,
+                    layout.font_variation_settings(),
+                    layout.font_feature_settings(),
                     color,
                     anchor,
+                    blend_style(self.state.blend_mode).unwrap_or_default(),
                 ),
-            )
-            .add(svg::node::Text::new(layout.text()));
+            );
+        add_text_content(&mut text, layout, x);
 
         let affine = self.current_transform();
         if affine != Affine::IDENTITY {
@@ -319,7 +659,11 @@ impl piet::RenderContext for RenderContext {
         if let Some(id) = self.state.clip {
             text.assign("clip-path", format!("url(#{})", id.to_string()));
         }
-        self.doc.append(text);
+        if let Some(opacity) = self.state.opacity() {
+            text.assign("opacity", opacity);
+        }
+        apply_element_attrs(&mut text, &self.element_attrs);
+        append_drawn(&mut self.doc, &mut self.groups, text);
     }
 
     fn save(&mut self) -> Result<()> {
@@ -333,9 +677,61 @@ impl piet::RenderContext for RenderContext {
         Ok(())
     }
 
+    fn annotate(&mut self, key: &str, value: &str) {
+        let content = svg::node::Text::new(escape_text(value));
+        match (key, self.groups.last_mut()) {
+            ("title", Some(group)) => group.append(svg::node::element::Title::new().add(content)),
+            ("title", None) => self
+                .doc
+                .append(svg::node::element::Title::new().add(content)),
+            ("description", Some(group)) => {
+                group.append(svg::node::element::Description::new().add(content))
+            }
+            ("description", None) => self
+                .doc
+                .append(svg::node::element::Description::new().add(content)),
+            (_, Some(group)) => group.assign(key, value),
+            (_, None) => self.doc.assign(key, value),
+        }
+    }
+
+    fn begin_group(&mut self, role: &str) -> Result<()> {
+        let mut group = svg::node::element::Group::new();
+        if !role.is_empty() {
+            group.assign("role", role);
+        }
+        self.groups.push(group);
+        Ok(())
+    }
+
+    fn end_group(&mut self) -> Result<()> {
+        let group = self.groups.pop().ok_or(Error::StackUnbalance)?;
+        append_drawn(&mut self.doc, &mut self.groups, group);
+        Ok(())
+    }
+
     fn finish(&mut self) -> Result<()> {
-        self.doc
-            .assign("viewBox", (0, 0, self.size.width, self.size.height));
+        // Groups that weren't ended would be missing from the output.
+        let unbalanced = !self.groups.is_empty();
+        while !self.groups.is_empty() {
+            self.end_group()?;
+        }
+
+        self.doc.assign(
+            "viewBox",
+            (
+                self.view_box.x0,
+                self.view_box.y0,
+                self.view_box.width(),
+                self.view_box.height(),
+            ),
+        );
+        self.doc.assign("width", self.size.width);
+        self.doc.assign("height", self.size.height);
+        if let Some(preserve_aspect_ratio) = self.preserve_aspect_ratio {
+            self.doc
+                .assign("preserveAspectRatio", preserve_aspect_ratio.to_string());
+        }
         self.doc.assign(
             "style",
             format!("width:{}px;height:{}px;", self.size.width, self.size.height),
@@ -376,6 +772,9 @@ impl piet::RenderContext for RenderContext {
         }
 
         seen_fonts.clear();
+        if unbalanced {
+            return Err(Error::StackUnbalance);
+        }
         Ok(())
     }
 
@@ -387,6 +786,36 @@ impl piet::RenderContext for RenderContext {
         self.state.xf
     }
 
+    fn capabilities(&self) -> Capabilities {
+        let mut capabilities = Capabilities::default();
+        capabilities.supports_blend_modes = true;
+        capabilities.supports_blur = true;
+        capabilities.supports_global_alpha = true;
+        capabilities.text_shaping_quality = TextShapingQuality::Full;
+        capabilities
+    }
+
+    fn set_global_alpha(&mut self, alpha: f64) {
+        // NaN is treated as transparent.
+        self.state.global_alpha = if alpha >= 0.0 { alpha.min(1.0) } else { 0.0 };
+    }
+
+    fn global_alpha(&self) -> f64 {
+        self.state.global_alpha
+    }
+
+    fn set_blend_mode(&mut self, mode: BlendMode) {
+        self.state.blend_mode = mode;
+    }
+
+    fn blend_mode(&self) -> BlendMode {
+        self.state.blend_mode
+    }
+
+    fn hit_tester(&mut self) -> Option<&mut HitTester> {
+        Some(&mut self.hit_tester)
+    }
+
     fn make_image(
         &mut self,
         width: usize,
@@ -394,7 +823,7 @@ impl piet::RenderContext for RenderContext {
         buf: &[u8],
         format: ImageFormat,
     ) -> Result<Self::Image> {
-        Ok(SvgImage(match format {
+        Ok(SvgImage::new(match format {
             ImageFormat::Grayscale => {
                 let image = ImageBuffer::from_raw(width as _, height as _, buf.to_owned())
                     .ok_or(Error::InvalidInput)?;
@@ -411,19 +840,26 @@ impl piet::RenderContext for RenderContext {
                 DynamicImage::ImageRgba8(image)
             }
             ImageFormat::RgbaPremul => {
-                use image::Rgba;
-                use piet::util::unpremul;
-
-                let mut image =
-                    ImageBuffer::<Rgba<u8>, _>::from_raw(width as _, height as _, buf.to_owned())
-                        .ok_or(Error::InvalidInput)?;
-                for px in image.pixels_mut() {
-                    px[0] = unpremul(px[0], px[3]);
-                    px[1] = unpremul(px[1], px[3]);
-                    px[2] = unpremul(px[2], px[3]);
-                }
+                let mut data = buf.to_owned();
+                piet::util::unpremultiply_rgba(&mut data);
+                let image = ImageBuffer::from_raw(width as _, height as _, data)
+                    .ok_or(Error::InvalidInput)?;
                 DynamicImage::ImageRgba8(image)
             }
+            ImageFormat::Rgba16 => {
+                // keep the extra precision, which PNG can store.
+                let data = buf
+                    .chunks_exact(2)
+                    .map(|c| u16::from_ne_bytes([c[0], c[1]]))
+                    .collect();
+                let image = ImageBuffer::from_raw(width as _, height as _, data)
+                    .ok_or(Error::InvalidInput)?;
+                DynamicImage::ImageRgba16(image)
+            }
+            ImageFormat::BgraSeparate | ImageFormat::BgraPremul => {
+                let (buf, format) = piet::util::to_rgba8(buf, format);
+                return self.make_image(width, height, &buf, format);
+            }
             // future-proof
             _ => return Err(Error::Unimplemented),
         }))
@@ -450,23 +886,211 @@ impl piet::RenderContext for RenderContext {
         draw_image(self, image, Some(src_rect.into()), dst_rect.into(), interp);
     }
 
+    fn draw_image_nine(
+        &mut self,
+        image: &Self::Image,
+        insets: impl Into<Insets>,
+        dst_rect: impl Into<Rect>,
+        interp: InterpolationMode,
+    ) {
+        let size = image.size();
+        let areas = piet::util::nine_patch(size, insets.into(), dst_rect.into());
+        if areas.is_empty() {
+            return;
+        }
+        // the image is defined once, and each area refers to it.
+        let id = self.new_id();
+        let image = svg::node::element::Image::new()
+            .set("id", id)
+            .set("width", size.width)
+            .set("height", size.height)
+            .set("href", data_url(image));
+        self.doc
+            .append(svg::node::element::Definitions::new().add(image));
+        for (src_rect, dst_rect) in areas {
+            let content =
+                svg::node::element::Use::new().set("href", format!("#{}", id.to_string()));
+            draw_image_view(self, content, src_rect, dst_rect, interp);
+        }
+    }
+
+    fn draw_image_filtered(
+        &mut self,
+        image: &Self::Image,
+        dst_rect: impl Into<Rect>,
+        filters: &[ImageFilter],
+    ) {
+        let dst_rect = dst_rect.into();
+        if filters.is_empty() {
+            return self.draw_image(image, dst_rect, InterpolationMode::Bilinear);
+        }
+        let mut primitives = Vec::new();
+        let mut input = String::from("SourceGraphic");
+        for (i, filter) in filters.iter().enumerate() {
+            let result = format!("f{}", i);
+            match *filter {
+                ImageFilter::Blur { radius } => primitives.push(filter_primitive(
+                    "feGaussianBlur",
+                    &[
+                        ("in", input.as_str().into()),
+                        ("stdDeviation", radius.abs().into()),
+                        ("result", result.as_str().into()),
+                    ],
+                )),
+                ImageFilter::DropShadow {
+                    offset,
+                    radius,
+                    color,
+                } => {
+                    // the shadow is the color, in the alpha of the input.
+                    let shadow = format!("{}-shadow", result);
+                    primitives.push(filter_primitive(
+                        "feFlood",
+                        &[
+                            ("flood-color", fmt_color(color).into()),
+                            ("flood-opacity", fmt_opacity(color).into()),
+                            ("result", shadow.as_str().into()),
+                        ],
+                    ));
+                    primitives.push(filter_primitive(
+                        "feComposite",
+                        &[
+                            ("in", shadow.as_str().into()),
+                            ("in2", input.as_str().into()),
+                            ("operator", "in".into()),
+                            ("result", shadow.as_str().into()),
+                        ],
+                    ));
+                    primitives.push(filter_primitive(
+                        "feGaussianBlur",
+                        &[
+                            ("in", shadow.as_str().into()),
+                            ("stdDeviation", radius.abs().into()),
+                            ("result", shadow.as_str().into()),
+                        ],
+                    ));
+                    primitives.push(filter_primitive(
+                        "feOffset",
+                        &[
+                            ("in", shadow.as_str().into()),
+                            ("dx", offset.x.into()),
+                            ("dy", offset.y.into()),
+                            ("result", shadow.as_str().into()),
+                        ],
+                    ));
+                    let mut merge =
+                        filter_primitive("feMerge", &[("result", result.as_str().into())]);
+                    merge.append(filter_primitive(
+                        "feMergeNode",
+                        &[("in", shadow.as_str().into())],
+                    ));
+                    merge.append(filter_primitive(
+                        "feMergeNode",
+                        &[("in", input.as_str().into())],
+                    ));
+                    primitives.push(merge);
+                }
+                ImageFilter::ColorMatrix(matrix) => primitives.push(filter_primitive(
+                    "feColorMatrix",
+                    &[
+                        ("in", input.as_str().into()),
+                        ("type", "matrix".into()),
+                        ("values", matrix.to_vec().into()),
+                        ("result", result.as_str().into()),
+                    ],
+                )),
+                // future-proof
+                _ => continue,
+            }
+            input = result;
+        }
+        let region = dst_rect.abs() + ImageFilter::total_outset(filters);
+        let id = self.add_filter(region, primitives);
+        let mut node = svg::node::element::Image::new()
+            .set("x", dst_rect.x0)
+            .set("y", dst_rect.y0)
+            .set("width", dst_rect.x1 - dst_rect.x0)
+            .set("height", dst_rect.y1 - dst_rect.y0)
+            .set("preserveAspectRatio", "none")
+            .set("filter", format!("url(#{})", id.to_string()))
+            .set("href", data_url(image));
+        let affine = self.state.xf;
+        if affine != Affine::IDENTITY {
+            node.assign("transform", xf_val(&affine));
+        }
+        set_image_attrs(self, &mut node, InterpolationMode::Bilinear);
+        append_drawn(&mut self.doc, &mut self.groups, node);
+    }
+
     fn capture_image_area(&mut self, _src_rect: impl Into<Rect>) -> Result<Self::Image> {
         Err(Error::Unimplemented)
     }
 
-    fn blurred_rect(&mut self, rect: Rect, _blur_radius: f64, brush: &impl IntoBrush<Self>) {
-        // TODO blur (perhaps using SVG filters)
-        self.fill(rect, brush)
+    fn blurred_rect(&mut self, rect: Rect, blur_radius: f64, brush: &impl IntoBrush<Self>) {
+        let brush = brush.make_brush(self, || rect);
+        let radius = blur_radius.abs();
+        // a blur of nothing, or of NaN, draws the rect as it is.
+        let filter = if radius > 0.0 {
+            let blur = filter_primitive("feGaussianBlur", &[("stdDeviation", radius.into())]);
+            let region = rect.abs().inflate(3.0 * radius, 3.0 * radius);
+            Some(self.add_filter(region, vec![blur]))
+        } else {
+            None
+        };
+        add_drawn_shape(
+            &mut self.doc,
+            &mut self.groups,
+            rect,
+            &Attrs {
+                xf: self.state.xf,
+                clip: self.state.clip,
+                opacity: self.state.opacity(),
+                blend_mode: self.state.blend_mode,
+                filter,
+                element_attrs: &self.element_attrs,
+                fill: Some((brush.into_owned(), None)),
+                ..Attrs::default()
+            },
+        );
     }
 }
 
 fn draw_image(
     ctx: &mut RenderContext,
     image: &<RenderContext as piet::RenderContext>::Image,
-    _src_rect: Option<Rect>,
+    src_rect: Option<Rect>,
     dst_rect: Rect,
-    _interp: InterpolationMode,
+    interp: InterpolationMode,
 ) {
+    let data_url = data_url(image);
+    let size = image.size();
+    match src_rect {
+        None => {
+            let mut node = svg::node::element::Image::new()
+                .set("x", dst_rect.x0)
+                .set("y", dst_rect.y0)
+                .set("width", dst_rect.x1 - dst_rect.x0)
+                .set("height", dst_rect.y1 - dst_rect.y0)
+                .set("href", data_url);
+            let affine = piet::RenderContext::current_transform(ctx);
+            if affine != Affine::IDENTITY {
+                node.assign("transform", xf_val(&affine));
+            }
+            set_image_attrs(ctx, &mut node, interp);
+            append_drawn(&mut ctx.doc, &mut ctx.groups, node);
+        }
+        Some(src_rect) => {
+            let node = svg::node::element::Image::new()
+                .set("width", size.width)
+                .set("height", size.height)
+                .set("href", data_url);
+            draw_image_view(ctx, node, src_rect, dst_rect, interp);
+        }
+    }
+}
+
+/// Encode an image as a PNG data URL.
+fn data_url(image: &SvgImage) -> String {
     use image::ImageEncoder as _;
 
     let mut writer = base64::write::EncoderStringWriter::from(
@@ -474,43 +1098,115 @@ fn draw_image(
         base64::STANDARD,
     );
 
+    let image = image.pixels();
     image::codecs::png::PngEncoder::new(&mut writer)
         .write_image(
-            image.0.as_bytes(),
-            image.0.width(),
-            image.0.height(),
-            image.0.color(),
+            image.as_bytes(),
+            image.width(),
+            image.height(),
+            image.color(),
         )
         .unwrap();
 
-    let data_url = writer.into_inner();
+    writer.into_inner()
+}
 
-    // TODO when src_rect.is_some()
-    // TODO maybe we could use css 'image-rendering' to control interpolation?
-    let mut node = svg::node::element::Image::new()
+/// Draw the `src_rect` area of `content`, an element drawing a whole image at
+/// its size, into `dst_rect`.
+fn draw_image_view(
+    ctx: &mut RenderContext,
+    content: impl Node,
+    src_rect: Rect,
+    dst_rect: Rect,
+    interp: InterpolationMode,
+) {
+    // a nested svg element shows only the area of its view box.
+    let view = svg::node::element::SVG::new()
         .set("x", dst_rect.x0)
         .set("y", dst_rect.y0)
-        .set("width", dst_rect.x1 - dst_rect.x0)
-        .set("height", dst_rect.y1 - dst_rect.y0)
-        .set("href", data_url);
-
+        .set("width", dst_rect.width())
+        .set("height", dst_rect.height())
+        .set(
+            "viewBox",
+            (
+                src_rect.x0,
+                src_rect.y0,
+                src_rect.width(),
+                src_rect.height(),
+            ),
+        )
+        .set("preserveAspectRatio", "none")
+        .add(content);
+    // the transform attribute isn't allowed on svg elements in SVG 1.1.
+    let mut group = svg::node::element::Group::new().add(view);
     let affine = piet::RenderContext::current_transform(ctx);
     if affine != Affine::IDENTITY {
-        node.assign("transform", xf_val(&affine));
+        group.assign("transform", xf_val(&affine));
+    }
+    set_image_attrs(ctx, &mut group, interp);
+    append_drawn(&mut ctx.doc, &mut ctx.groups, group);
+}
+
+/// Set the attributes shared by all of the elements that draw images.
+fn set_image_attrs(ctx: &RenderContext, node: &mut impl Node, interp: InterpolationMode) {
+    // viewers choose how to interpolate otherwise.
+    match interp {
+        InterpolationMode::NearestNeighbor => node.assign("image-rendering", "pixelated"),
+        InterpolationMode::HighQuality => node.assign("image-rendering", "optimizeQuality"),
+        InterpolationMode::Bilinear | InterpolationMode::Bicubic => {}
     }
     if let Some(id) = ctx.state.clip {
         node.assign("clip-path", format!("url(#{})", id.to_string()));
     }
+    if let Some(opacity) = ctx.state.opacity() {
+        node.assign("opacity", opacity);
+    }
+    if let Some(style) = blend_style(ctx.state.blend_mode) {
+        node.assign("style", style);
+    }
+    apply_element_attrs(node, &ctx.element_attrs);
+}
 
-    ctx.doc.append(node);
+/// The `style` that blends an element with `mode`, which is a CSS property
+/// rather than an attribute, if it isn't normal.
+fn blend_style(mode: BlendMode) -> Option<String> {
+    let mode = match mode {
+        BlendMode::Normal => return None,
+        BlendMode::Multiply => "multiply",
+        BlendMode::Screen => "screen",
+        BlendMode::Overlay => "overlay",
+        BlendMode::Darken => "darken",
+        BlendMode::Lighten => "lighten",
+        BlendMode::ColorDodge => "color-dodge",
+        BlendMode::ColorBurn => "color-burn",
+        BlendMode::HardLight => "hard-light",
+        BlendMode::SoftLight => "soft-light",
+        BlendMode::Difference => "difference",
+        BlendMode::Exclusion => "exclusion",
+        BlendMode::Hue => "hue",
+        BlendMode::Saturation => "saturation",
+        BlendMode::Color => "color",
+        BlendMode::Luminosity => "luminosity",
+    };
+    Some(format!("mix-blend-mode:{};", mode))
 }
 
+/// The `stroke-miterlimit` of elements that don't set one.
+const SVG_DEFAULT_MITER_LIMIT: f64 = 4.0;
+
 #[derive(Default)]
 struct Attrs<'a> {
     xf: Affine,
     clip: Option<Id>,
+    opacity: Option<f64>,
+    blend_mode: BlendMode,
+    mask: Option<Id>,
+    filter: Option<Id>,
+    /// The markers at the start and end of a path.
+    markers: [Option<Id>; 2],
     fill: Option<(Brush, Option<&'a str>)>,
     stroke: Option<(Brush, f64, &'a StrokeStyle)>,
+    element_attrs: &'a [(String, String)],
 }
 
 impl Attrs<'_> {
@@ -521,6 +1217,23 @@ impl Attrs<'_> {
         if let Some(id) = self.clip {
             node.assign("clip-path", format!("url(#{})", id.to_string()));
         }
+        if let Some(id) = self.mask {
+            node.assign("mask", format!("url(#{})", id.to_string()));
+        }
+        if let Some(id) = self.filter {
+            node.assign("filter", format!("url(#{})", id.to_string()));
+        }
+        if let Some(opacity) = self.opacity {
+            node.assign("opacity", opacity);
+        }
+        if let Some(style) = blend_style(self.blend_mode) {
+            node.assign("style", style);
+        }
+        for (name, id) in ["marker-start", "marker-end"].iter().zip(self.markers) {
+            if let Some(id) = id {
+                node.assign(*name, format!("url(#{})", id.to_string()));
+            }
+        }
         if let Some((ref brush, rule)) = self.fill {
             node.assign("fill", brush.color());
             if let Some(opacity) = brush.opacity() {
@@ -541,7 +1254,9 @@ impl Attrs<'_> {
                 node.assign("stroke-width", width);
             }
             match style.line_join {
-                LineJoin::Miter { limit } if limit == LineJoin::DEFAULT_MITER_LIMIT => (),
+                // SVG's default limit is 4, rather than piet's 10, so the
+                // limit is set unless it's the same.
+                LineJoin::Miter { limit } if limit == SVG_DEFAULT_MITER_LIMIT => (),
                 LineJoin::Miter { limit } => {
                     node.assign("stroke-miterlimit", limit);
                 }
@@ -567,7 +1282,17 @@ impl Attrs<'_> {
             if style.dash_offset != 0.0 {
                 node.assign("stroke-dashoffset", style.dash_offset);
             }
+            if !style.scale {
+                node.assign("vector-effect", "non-scaling-stroke");
+            }
         }
+        apply_element_attrs(node, self.element_attrs);
+    }
+}
+
+fn apply_element_attrs(node: &mut impl Node, attrs: &[(String, String)]) {
+    for (name, value) in attrs {
+        node.assign(name.as_str(), value.as_str());
     }
 }
 
@@ -580,6 +1305,48 @@ fn xf_val(xf: &Affine) -> svg::node::Value {
     .into()
 }
 
+/// Add an element that was drawn to the innermost open group, or to the
+/// document if there is none.
+fn append_drawn(
+    doc: &mut svg::Document,
+    groups: &mut [svg::node::element::Group],
+    node: impl Node,
+) {
+    match groups.last_mut() {
+        Some(group) => group.append(node),
+        None => doc.append(node),
+    }
+}
+
+/// Like [`add_shape`], for a shape that is drawn; see [`append_drawn`].
+fn add_drawn_shape(
+    doc: &mut svg::Document,
+    groups: &mut [svg::node::element::Group],
+    shape: impl Shape,
+    attrs: &Attrs,
+) {
+    match groups.last_mut() {
+        Some(group) => add_shape(group, shape, attrs),
+        None => add_shape(doc, shape, attrs),
+    }
+}
+
+/// A filter primitive element, such as `feGaussianBlur`, with attributes.
+fn filter_primitive(name: &str, attrs: &[(&str, svg::node::Value)]) -> svg::node::element::Element {
+    let mut primitive = svg::node::element::Element::new(name);
+    for (attr, value) in attrs {
+        primitive.assign(*attr, value.clone());
+    }
+    primitive
+}
+
+/// Escape the characters that are special in the text content of an element.
+fn escape_text(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
 fn add_shape(node: &mut impl Node, shape: impl Shape, attrs: &Attrs) {
     if let Some(circle) = shape.as_circle() {
         let mut x = svg::node::element::Circle::new()
@@ -616,10 +1383,30 @@ fn add_shape(node: &mut impl Node, shape: impl Shape, attrs: &Attrs) {
     }
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone)]
 struct State {
     xf: Affine,
     clip: Option<Id>,
+    global_alpha: f64,
+    blend_mode: BlendMode,
+}
+
+impl Default for State {
+    fn default() -> State {
+        State {
+            xf: Affine::IDENTITY,
+            clip: None,
+            global_alpha: 1.0,
+            blend_mode: BlendMode::Normal,
+        }
+    }
+}
+
+impl State {
+    /// The opacity of the elements that are drawn, if they aren't opaque.
+    fn opacity(&self) -> Option<f64> {
+        (self.global_alpha < 1.0).then_some(self.global_alpha)
+    }
 }
 
 /// An SVG brush
@@ -660,6 +1447,36 @@ impl IntoBrush<RenderContext> for Brush {
     }
 }
 
+/// A rectangle element that covers `view_box`.
+fn view_box_rect(view_box: Rect) -> svg::node::element::Rectangle {
+    svg::node::element::Rectangle::new()
+        .set("x", view_box.x0)
+        .set("y", view_box.y0)
+        .set("width", view_box.width())
+        .set("height", view_box.height())
+}
+
+impl fmt::Display for PreserveAspectRatio {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (x, y, mode) = match self {
+            PreserveAspectRatio::Stretch => return f.write_str("none"),
+            PreserveAspectRatio::Meet(x, y) => (x, y, "meet"),
+            PreserveAspectRatio::Slice(x, y) => (x, y, "slice"),
+        };
+        write!(f, "x{}Y{} {}", x.name(), y.name(), mode)
+    }
+}
+
+impl Align {
+    fn name(self) -> &'static str {
+        match self {
+            Align::Min => "Min",
+            Align::Mid => "Mid",
+            Align::Max => "Max",
+        }
+    }
+}
+
 // RGB in hex representation
 fn fmt_color(color: Color) -> String {
     format!("#{:06x}", color.as_rgba_u32() >> 8)
@@ -671,16 +1488,57 @@ fn fmt_opacity(color: Color) -> String {
 }
 
 #[derive(Clone)]
-pub struct SvgImage(image::DynamicImage);
+pub struct SvgImage {
+    image: Arc<DynamicImage>,
+    /// The area of the image that is drawn, if this is a sub-image.
+    view: Option<Rect>,
+}
+
+impl SvgImage {
+    fn new(image: DynamicImage) -> SvgImage {
+        SvgImage {
+            image: Arc::new(image),
+            view: None,
+        }
+    }
+
+    /// The area of the image that is drawn.
+    fn view(&self) -> Rect {
+        let (width, height) = self.image.dimensions();
+        self.view
+            .unwrap_or_else(|| Rect::new(0., 0., width as _, height as _))
+    }
+
+    /// The pixels that are drawn, which are copied out of the image if this
+    /// is a sub-image, since documents hold the pixels of each image.
+    fn pixels(&self) -> Cow<'_, DynamicImage> {
+        match self.view {
+            Some(view) => Cow::Owned(self.image.crop_imm(
+                view.x0 as u32,
+                view.y0 as u32,
+                view.width() as u32,
+                view.height() as u32,
+            )),
+            None => Cow::Borrowed(&self.image),
+        }
+    }
+}
 
 impl Image for SvgImage {
     fn size(&self) -> Size {
-        let (width, height) = self.0.dimensions();
-        Size {
-            width: width as _,
-            height: height as _,
+        self.view().size()
+    }
+
+    fn sub_image(&self, rect: impl Into<Rect>) -> Self {
+        SvgImage {
+            image: self.image.clone(),
+            view: Some(piet::util::sub_image_view(self.view(), rect.into())),
         }
     }
+
+    fn to_rgba_bytes(&self) -> Result<Vec<u8>> {
+        Ok(self.pixels().to_rgba8().into_raw())
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -710,3 +1568,144 @@ impl From<Id> for svg::node::Value {
         x.to_string().into()
     }
 }
+
+/// Add the text of `layout` to a text element, placing the text following each tab at its tab
+/// stop, and the text following each inline box after it.
+fn add_text_content(node: &mut svg::node::element::Text, layout: &TextLayout, x: f64) {
+    let text = layout.text();
+    let first_end = layout
+        .run_positions
+        .first()
+        .map(|(offset, _)| *offset)
+        .unwrap_or_else(|| text.len());
+    node.append(svg::node::Text::new(&text[..first_end]));
+    for (i, (start, run_x)) in layout.run_positions.iter().enumerate() {
+        let end = layout
+            .run_positions
+            .get(i + 1)
+            .map(|(offset, _)| *offset)
+            .unwrap_or_else(|| text.len());
+        let mut span = svg::node::element::Element::new("tspan");
+        span.assign("x", x + run_x);
+        span.append(svg::node::Text::new(&text[*start..end]));
+        node.append(span);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use piet::kurbo::{BezPath, Line};
+    use piet::{RenderContext as _, Text as _, TextAttribute, TextLayoutBuilder as _};
+
+    /// Draw with `f`, and return the SVG document.
+    fn draw(f: impl FnOnce(&mut RenderContext)) -> String {
+        let mut rc = RenderContext::new(Size::new(100., 100.));
+        f(&mut rc);
+        rc.finish().unwrap();
+        let mut out = Vec::new();
+        rc.write(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn non_scaling_strokes_have_a_vector_effect() {
+        let line = Line::new((10., 10.), (90., 10.));
+        let style = StrokeStyle::new().scale(false);
+        let svg = draw(|rc| {
+            rc.transform(Affine::scale(2.));
+            rc.stroke_styled(line, &Color::RED, 2., &style);
+        });
+        assert!(svg.contains(r#"vector-effect="non-scaling-stroke""#));
+        assert!(svg.contains(r#"stroke-width="2""#));
+
+        let svg = draw(|rc| rc.stroke(line, &Color::RED, 2.));
+        assert!(!svg.contains("vector-effect"));
+    }
+
+    #[test]
+    fn miter_limits_differing_from_svgs_are_set() {
+        let mut path = BezPath::new();
+        path.move_to((10., 10.));
+        path.line_to((90., 20.));
+        path.line_to((10., 30.));
+        let svg = draw(|rc| rc.stroke(&path, &Color::RED, 2.));
+        assert!(svg.contains(r#"stroke-miterlimit="10""#));
+
+        let style = StrokeStyle::new().line_join(LineJoin::Miter { limit: 4. });
+        let svg = draw(|rc| rc.stroke_styled(&path, &Color::RED, 2., &style));
+        assert!(!svg.contains("stroke-miterlimit"));
+    }
+
+    #[test]
+    fn gradients_follow_the_transform_they_are_drawn_under() {
+        let svg = draw(|rc| {
+            let gradient = piet::FixedLinearGradient {
+                start: Point::new(0., 0.),
+                end: Point::new(10., 0.),
+                stops: vec![
+                    piet::GradientStop {
+                        pos: 0.,
+                        color: Color::RED,
+                    },
+                    piet::GradientStop {
+                        pos: 1.,
+                        color: Color::BLUE,
+                    },
+                ],
+            };
+            let brush = rc.gradient(gradient).unwrap();
+            rc.transform(Affine::new([2., 0., 0., 3., 20., 30.]));
+            rc.fill(Rect::new(0., 0., 10., 10.), &brush);
+        });
+        // the gradient stays in user space, and the transform is on the
+        // element that is filled with it, which moves both together.
+        assert!(svg.contains(r#"x1="0" x2="10" y1="0" y2="0""#));
+        assert!(!svg.contains("gradientTransform"));
+        assert!(
+            svg.contains(r#"<rect fill="url(#a)" height="10" transform="matrix(2 0 0 3 20 30)""#)
+        );
+    }
+
+    #[test]
+    fn text_is_one_line_that_justifying_does_not_stretch() {
+        let svg = |alignment| {
+            draw(|rc| {
+                let layout = rc
+                    .text()
+                    .new_text_layout("piet draws text")
+                    .max_width(20.)
+                    .alignment(alignment)
+                    .build()
+                    .unwrap();
+                assert_eq!(layout.line_count(), 1);
+                rc.draw_text(&layout, (10., 10.));
+            })
+        };
+        let justified = svg(TextAlignment::Justified);
+        assert_eq!(justified, svg(TextAlignment::Start));
+        assert!(!justified.contains("text-anchor"));
+        assert!(svg(TextAlignment::End).contains("text-anchor:end"));
+    }
+
+    #[test]
+    fn text_backgrounds_are_drawn_behind_the_text() {
+        let svg = draw(|rc| {
+            let layout = rc
+                .text()
+                .new_text_layout("piet")
+                .default_attribute(TextAttribute::BackgroundColor(Color::RED))
+                .build()
+                .unwrap();
+            rc.draw_text(&layout, (10., 20.));
+        });
+        let background = svg.find(r##"fill="#ff0000""##).unwrap();
+        assert!(background < svg.find("<text").unwrap());
+
+        let svg = draw(|rc| {
+            let layout = rc.text().new_text_layout("piet").build().unwrap();
+            rc.draw_text(&layout, (10., 20.));
+        });
+        assert!(!svg.contains("<rect"));
+    }
+}
@@ -2,29 +2,26 @@
 
 use std::{
     collections::HashSet,
-    fs, io,
     ops::RangeBounds,
     sync::{Arc, Mutex},
 };
 
-use font_kit::{
-    handle::Handle,
-    source::{Source, SystemSource},
-    sources::{mem::MemSource, multi::MultiSource},
-};
-use piet::kurbo::{Point, Rect, Size};
+use piet::kurbo::{Affine, BezPath, Point, Rect, Size};
 use piet::{
-    Color, Error, FontFamily, FontStyle, FontWeight, HitTestPoint, HitTestPosition, LineMetric,
-    TextAlignment, TextAttribute, TextStorage,
+    util, BaselineAlignment, Color, Error, FontFamily, FontFeature, FontStyle, FontVariation,
+    FontWeight, Glyph, GlyphRun, HitTestPoint, HitTestPosition, LineHeight, LineMetric,
+    ScriptPosition, TextAlignment, TextAttribute, TextStorage,
 };
-use rustybuzz::{Face, UnicodeBuffer};
+use rustybuzz::{Face, Feature, Tag, UnicodeBuffer, Variation};
+
+use crate::fonts::FontSource;
 
 type Result<T> = std::result::Result<T, Error>;
 
 /// SVG text (partially implemented)
 #[derive(Clone)]
 pub struct Text {
-    source: Arc<Mutex<MultiSource>>,
+    source: Arc<Mutex<FontSource>>,
     /// Fonts we have seen this frame, and so need to embed in the SVG.
     ///
     /// We only include named font families - system defaults like SANS_SERIF are assumed to be
@@ -36,22 +33,13 @@ impl Text {
     #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
         Text {
-            source: Arc::new(Mutex::new(MultiSource::from_sources(vec![
-                Box::new(SystemSource::new()),
-                Box::new(MemSource::empty()),
-            ]))),
+            source: Arc::new(Mutex::new(FontSource::new())),
             seen_fonts: Arc::new(Mutex::new(HashSet::new())),
         }
     }
 
     pub(crate) fn font_data(&self, face: &FontFace) -> Result<Arc<Vec<u8>>> {
-        let handle = self
-            .source
-            .lock()
-            .unwrap()
-            .select_best_match(&[face.to_fk_family()], &face.to_props())
-            .map_err(|_| Error::FontLoadingFailed)?;
-        load_font_data(handle).map_err(|_| Error::FontLoadingFailed)
+        self.source.lock().unwrap().font_data(face)
     }
 }
 
@@ -60,15 +48,7 @@ impl piet::Text for Text {
     type TextLayoutBuilder = TextLayoutBuilder;
 
     fn font_family(&mut self, family_name: &str) -> Option<FontFamily> {
-        use font_kit::{family_name::FamilyName, properties::Properties};
-
-        if self
-            .source
-            .lock()
-            .unwrap()
-            .select_best_match(&[FamilyName::Title(family_name.into())], &Properties::new())
-            .is_ok()
-        {
+        if self.source.lock().unwrap().has_family(family_name) {
             Some(FontFamily::new_unchecked(family_name))
         } else {
             None
@@ -76,17 +56,8 @@ impl piet::Text for Text {
     }
 
     fn load_font(&mut self, data: &[u8]) -> Result<FontFamily> {
-        let mut multi_source = self.source.lock().unwrap();
-        let source = multi_source
-            .find_source_mut::<MemSource>()
-            .expect("mem source");
-        let font = source
-            .add_font(Handle::Memory {
-                bytes: Arc::new(data.into()),
-                font_index: 0,
-            })
-            .map_err(|_| Error::FontLoadingFailed)?;
-        Ok(FontFamily::new_unchecked(font.family_name()))
+        let family = self.source.lock().unwrap().load(data)?;
+        Ok(FontFamily::new_unchecked(family))
     }
 
     fn new_text_layout(&mut self, text: impl TextStorage) -> TextLayoutBuilder {
@@ -100,9 +71,21 @@ pub struct TextLayoutBuilder {
     font_face: FontFace,
     font_size: f64,
     text_color: Color,
+    background_color: Color,
     underline: bool,
     strikethrough: bool,
+    letter_spacing: f64,
+    word_spacing: f64,
+    variations: Arc<[FontVariation]>,
+    features: Arc<[FontFeature]>,
+    baseline_shift: f64,
+    script: ScriptPosition,
+    line_height: LineHeight,
     max_width: f64,
+    tab_stops: Arc<[f64]>,
+    tab_width: Option<f64>,
+    /// The text position, size and alignment of each inline box.
+    inline_boxes: Vec<(usize, Size, BaselineAlignment)>,
     ctx: Text,
 }
 
@@ -114,9 +97,20 @@ impl TextLayoutBuilder {
             font_size: 12.,
             font_face: FontFace::default(),
             text_color: Color::BLACK,
+            background_color: Color::TRANSPARENT,
             underline: false,
             strikethrough: false,
+            letter_spacing: 0.,
+            word_spacing: 0.,
+            variations: Vec::new().into(),
+            features: Vec::new().into(),
+            baseline_shift: 0.,
+            script: ScriptPosition::Normal,
+            line_height: LineHeight::Normal,
             max_width: f64::INFINITY,
+            tab_stops: Vec::new().into(),
+            tab_width: None,
+            inline_boxes: Vec::new(),
             ctx,
         }
     }
@@ -136,15 +130,43 @@ impl piet::TextLayoutBuilder for TextLayoutBuilder {
         self
     }
 
+    fn line_height(mut self, line_height: LineHeight) -> Self {
+        self.line_height = line_height;
+        self
+    }
+
+    fn tab_stops(mut self, stops: &[f64]) -> Self {
+        self.tab_stops = stops.into();
+        self
+    }
+
+    fn tab_width(mut self, width: f64) -> Self {
+        self.tab_width = Some(width);
+        self
+    }
+
+    fn inline_box(mut self, index: usize, size: Size, alignment: BaselineAlignment) -> Self {
+        let index = index.min(self.text.len());
+        self.inline_boxes.push((index, size, alignment));
+        self
+    }
+
     fn default_attribute(mut self, attribute: impl Into<TextAttribute>) -> Self {
         match attribute.into() {
             TextAttribute::FontFamily(font) => self.font_face.family = font,
             TextAttribute::FontSize(size) => self.font_size = size,
             TextAttribute::Weight(weight) => self.font_face.weight = weight,
             TextAttribute::TextColor(color) => self.text_color = color,
+            TextAttribute::BackgroundColor(color) => self.background_color = color,
             TextAttribute::Style(style) => self.font_face.style = style,
             TextAttribute::Underline(underline) => self.underline = underline,
             TextAttribute::Strikethrough(strikethrough) => self.strikethrough = strikethrough,
+            TextAttribute::LetterSpacing(spacing) => self.letter_spacing = spacing,
+            TextAttribute::WordSpacing(spacing) => self.word_spacing = spacing,
+            TextAttribute::FontVariations(variations) => self.variations = variations,
+            TextAttribute::FontFeatures(features) => self.features = features,
+            TextAttribute::BaselineShift(shift) => self.baseline_shift = shift,
+            TextAttribute::ScriptPosition(script) => self.script = script,
         }
 
         self
@@ -177,8 +199,26 @@ pub struct TextLayout {
     pub(crate) font_size: f64,
     pub(crate) font_face: FontFace,
     pub(crate) text_color: Color,
+    pub(crate) background_color: Color,
     pub(crate) underline: bool,
     pub(crate) strikethrough: bool,
+    pub(crate) letter_spacing: f64,
+    pub(crate) word_spacing: f64,
+    pub(crate) variations: Arc<[FontVariation]>,
+    pub(crate) features: Arc<[FontFeature]>,
+    /// Extra space above the text, when the line is taller than the font.
+    pub(crate) half_leading: f64,
+    /// The height of the font, without any leading.
+    pub(crate) natural_height: f64,
+    /// How far the glyphs are raised, in pixels.
+    pub(crate) baseline_shift: f64,
+    /// The text offset and x position of the text following each tab or
+    /// inline box, which is positioned explicitly.
+    pub(crate) run_positions: Vec<(usize, f64)>,
+    inline_boxes: Vec<Rect>,
+    baseline: f64,
+    glyph_run: GlyphRun,
+    outline: BezPath,
     size: Size,
 }
 
@@ -189,45 +229,235 @@ impl TextLayout {
     /// will depend on available fonts, conformance of renderer, DPI, etc), but it is the best we
     /// can do.
     fn from_builder(builder: TextLayoutBuilder) -> Result<Self> {
-        let face_bytes = builder
-            .font_face
-            .load(&*builder.ctx.source.lock().unwrap())?;
+        let face_bytes = builder.ctx.font_data(&builder.font_face)?;
         let mut face = Face::from_slice(&face_bytes, 0).ok_or(Error::FontLoadingFailed)?;
         // number of pixels in a point
         // I think we're OK to assume 96 DPI, because the actual SVG renderer will scale for HIDPI
         // displays.
         const DPI: f64 = 96.;
         const POINTS_PER_INCH: f64 = 72.;
-        let px_per_em = DPI / POINTS_PER_INCH * builder.font_size;
+        let font_size = builder.font_size * builder.script.font_scale();
+        let px_per_em = DPI / POINTS_PER_INCH * font_size;
         let px_per_unit = px_per_em / face.units_per_em() as f64;
         face.set_pixels_per_em(Some((px_per_em as u16, px_per_em as u16)));
+        let variations = builder
+            .variations
+            .iter()
+            .map(|var| Variation {
+                tag: Tag::from_bytes(&var.tag),
+                value: var.value as f32,
+            })
+            .collect::<Vec<_>>();
+        face.set_variations(&variations);
 
         let mut uni = UnicodeBuffer::new();
 
         // shape the full text
         uni.push_str(builder.text.as_str());
-        let layout = rustybuzz::shape(&face, &[], uni);
-        let width = layout
+        let features = builder
+            .features
+            .iter()
+            .map(|feature| Feature::new(Tag::from_bytes(&feature.tag), feature.value, ..))
+            .collect::<Vec<_>>();
+        let layout = rustybuzz::shape(&face, &features, uni);
+        let advance = layout
             .glyph_positions()
             .iter()
             .map(|pos| pos.x_advance as f64)
             .sum::<f64>()
             * px_per_unit;
-        let height = face.height() as f64 * px_per_unit;
+        // Spacing is added once per cluster (letter spacing) and once per space (word spacing),
+        // which is how the SVG renderer will apply the equivalent CSS properties.
+        let mut clusters = layout
+            .glyph_infos()
+            .iter()
+            .map(|info| info.cluster)
+            .collect::<Vec<_>>();
+        clusters.dedup();
+        let spaces = builder.text.chars().filter(|c| *c == ' ').count();
+        let spacing =
+            clusters.len() as f64 * builder.letter_spacing + spaces as f64 * builder.word_spacing;
+        let natural_height = face.height() as f64 * px_per_unit;
+        let height = builder.line_height.resolve(px_per_em, natural_height);
+        let half_leading = (height - natural_height) / 2.;
+        let ascent = face.ascender() as f64 * px_per_unit;
+        let descent = -face.descender() as f64 * px_per_unit;
+        let text_ascent = ascent + half_leading;
+
+        // the line is made taller to fit its inline boxes.
+        let box_ascents = builder
+            .inline_boxes
+            .iter()
+            .map(|(_, size, alignment)| alignment.box_ascent(size.height, ascent, descent))
+            .collect::<Vec<_>>();
+        let (above, below) = builder.inline_boxes.iter().zip(&box_ascents).fold(
+            (text_ascent, height - text_ascent),
+            |(above, below), ((_, size, _), &box_ascent)| {
+                (above.max(box_ascent), below.max(size.height - box_ascent))
+            },
+        );
+        let baseline = above;
+        let height = above + below;
+        let half_leading = half_leading + above - text_ascent;
+        let baseline_shift =
+            (builder.baseline_shift + builder.script.baseline_shift(builder.font_size)) * DPI
+                / POINTS_PER_INCH;
+
+        // position glyphs, applying spacing between clusters in the same way as above.
+        let px_per_point = DPI / POINTS_PER_INCH;
+        // like CSS, the default `tab-size` is eight spaces.
+        let tab_width = builder.tab_width.unwrap_or_else(|| {
+            let space_advance = face
+                .glyph_index(' ')
+                .and_then(|id| face.glyph_hor_advance(id))
+                .unwrap_or(0) as f64
+                * px_per_unit;
+            8. * (space_advance + (builder.letter_spacing + builder.word_spacing) * px_per_point)
+        });
+        let mut tab_advance = 0.;
+        let mut run_positions = Vec::new();
+        let mut glyphs = Vec::with_capacity(layout.len());
+        let mut pen = 0.;
+        let mut prev_cluster: Option<usize> = None;
+        // the boxes, in the order they are in the text.
+        let mut box_order = (0..builder.inline_boxes.len()).collect::<Vec<_>>();
+        box_order.sort_by_key(|&n| builder.inline_boxes[n].0);
+        let mut boxes = box_order.into_iter().peekable();
+        let mut box_rects = vec![Rect::ZERO; builder.inline_boxes.len()];
+        let mut place_box = |n: usize, pen: &mut f64| {
+            let size = builder.inline_boxes[n].1;
+            box_rects[n] = Rect::from_origin_size((*pen, baseline - box_ascents[n]), size);
+            *pen += size.width;
+        };
+        for (info, pos) in layout.glyph_infos().iter().zip(layout.glyph_positions()) {
+            let cluster = info.cluster as usize;
+            if let Some(prev) = prev_cluster.filter(|prev| *prev != cluster) {
+                pen += builder.letter_spacing * px_per_point;
+                if builder.text[prev..].starts_with(' ') {
+                    pen += builder.word_spacing * px_per_point;
+                }
+                if builder.text[prev..].starts_with('\t') {
+                    run_positions.push((cluster, pen));
+                }
+            }
+            if prev_cluster != Some(cluster) {
+                let mut placed = false;
+                while let Some(n) = boxes.next_if(|&n| builder.inline_boxes[n].0 <= cluster) {
+                    place_box(n, &mut pen);
+                    placed = true;
+                }
+                // the text after the boxes starts after them.
+                match run_positions.last_mut() {
+                    Some(last) if placed && last.0 == cluster => last.1 = pen,
+                    _ if placed => run_positions.push((cluster, pen)),
+                    _ => (),
+                }
+            }
+            prev_cluster = Some(cluster);
+            let mut glyph_advance = pos.x_advance as f64 * px_per_unit;
+            if builder.text[cluster..].starts_with('\t') {
+                // tabs advance to the next tab stop, rather than by the advance of their glyph.
+                let stop = util::next_tab_stop(pen, &builder.tab_stops, tab_width);
+                tab_advance += stop - pen - glyph_advance;
+                glyph_advance = stop - pen;
+            }
+            glyphs.push(Glyph {
+                id: info.glyph_id,
+                position: Point::new(
+                    pen + pos.x_offset as f64 * px_per_unit,
+                    baseline - baseline_shift - pos.y_offset as f64 * px_per_unit,
+                ),
+                advance: glyph_advance,
+                cluster,
+            });
+            pen += glyph_advance;
+        }
+        // boxes at the end of the text.
+        for n in boxes {
+            place_box(n, &mut pen);
+        }
+        // glyphs are in visual order, so in right-to-left runs the tabs are found backwards;
+        // the text between them is sliced in logical order.
+        run_positions.sort_by_key(|(offset, _)| *offset);
+        let box_advance = builder
+            .inline_boxes
+            .iter()
+            .map(|(_, size, _)| size.width)
+            .sum::<f64>();
+        let width = advance + tab_advance + box_advance + spacing * DPI / POINTS_PER_INCH;
         let size = Size { width, height };
+        let mut outline = BezPath::new();
+        for glyph in &glyphs {
+            let mut builder = OutlineBuilder {
+                path: &mut outline,
+                origin: glyph.position,
+                scale: px_per_unit,
+            };
+            face.outline_glyph(ttf_parser::GlyphId(glyph.id as u16), &mut builder);
+        }
+
+        let glyph_run = GlyphRun {
+            font: builder.font_face.family.clone(),
+            font_size,
+            weight: builder.font_face.weight,
+            style: builder.font_face.style,
+            line: 0,
+            glyphs,
+        };
 
         Ok(TextLayout {
             text: builder.text,
             max_width: builder.max_width,
             alignment: builder.alignment,
             font_face: builder.font_face,
-            font_size: builder.font_size,
+            font_size,
             text_color: builder.text_color,
+            background_color: builder.background_color,
             underline: builder.underline,
             strikethrough: builder.strikethrough,
+            letter_spacing: builder.letter_spacing,
+            word_spacing: builder.word_spacing,
+            variations: builder.variations,
+            features: builder.features,
+            half_leading,
+            natural_height,
+            baseline_shift,
+            run_positions,
+            inline_boxes: box_rects,
+            baseline,
+            glyph_run,
+            outline,
             size,
         })
     }
+
+    /// The CSS `font-variation-settings` declaration for this layout, if any.
+    pub(crate) fn font_variation_settings(&self) -> String {
+        let settings = self
+            .variations
+            .iter()
+            .filter_map(|var| Some(format!("\"{}\" {}", var.tag_str()?, var.value)))
+            .collect::<Vec<_>>();
+        if settings.is_empty() {
+            String::new()
+        } else {
+            format!("font-variation-settings:{};", settings.join(","))
+        }
+    }
+
+    /// The CSS `font-feature-settings` declaration for this layout, if any.
+    pub(crate) fn font_feature_settings(&self) -> String {
+        let settings = self
+            .features
+            .iter()
+            .filter_map(|feature| Some(format!("\"{}\" {}", feature.tag_str()?, feature.value)))
+            .collect::<Vec<_>>();
+        if settings.is_empty() {
+            String::new()
+        } else {
+            format!("font-feature-settings:{};", settings.join(","))
+        }
+    }
 }
 
 impl piet::TextLayout for TextLayout {
@@ -259,8 +489,8 @@ impl piet::TextLayout for TextLayout {
                 start_offset: 0,
                 end_offset: self.text.len(),
                 trailing_whitespace: self.text.len() - self.text.trim_end().len(),
-                baseline: 0.,
-                height: 0.,
+                baseline: self.baseline,
+                height: self.size.height,
                 y_offset: 0.,
             })
         } else {
@@ -272,8 +502,41 @@ impl piet::TextLayout for TextLayout {
         1
     }
 
-    fn hit_test_point(&self, _point: Point) -> HitTestPoint {
-        HitTestPoint::default()
+    fn glyph_runs(&self) -> Vec<GlyphRun> {
+        vec![self.glyph_run.clone()]
+    }
+
+    fn outline(&self) -> BezPath {
+        self.outline.clone()
+    }
+
+    fn inline_boxes(&self) -> Vec<Rect> {
+        self.inline_boxes.clone()
+    }
+
+    fn relayout_width(&mut self, width: f64) -> bool {
+        // text is a single line, so only how it is aligned depends on the
+        // width; the glyphs and the outline stay as they were shaped.
+        self.max_width = width;
+        true
+    }
+
+    fn rects_for_range(&self, range: impl RangeBounds<usize>) -> Vec<Rect> {
+        let range = util::resolve_range(range, self.text.len());
+        let spans = self
+            .glyph_run
+            .glyphs
+            .iter()
+            .filter(|glyph| range.contains(&glyph.cluster))
+            .map(|glyph| (glyph.position.x, glyph.position.x + glyph.advance));
+        util::merge_spans(spans)
+            .into_iter()
+            .map(|(x0, x1)| Rect::new(x0, 0., x1, self.size.height))
+            .collect()
+    }
+
+    fn hit_test_point(&self, point: Point) -> HitTestPoint {
+        util::hit_test_point_on_line(self, Affine::IDENTITY, point, 0, 0, false)
     }
 
     fn hit_test_text_position(&self, _text_position: usize) -> HitTestPosition {
@@ -285,6 +548,49 @@ impl piet::TextLayout for TextLayout {
     }
 }
 
+/// Collects glyph outlines, converting from font units to layout coordinates.
+struct OutlineBuilder<'a> {
+    path: &'a mut BezPath,
+    origin: Point,
+    scale: f64,
+}
+
+impl OutlineBuilder<'_> {
+    fn point(&self, x: f32, y: f32) -> Point {
+        // font units are y-up
+        Point::new(
+            self.origin.x + x as f64 * self.scale,
+            self.origin.y - y as f64 * self.scale,
+        )
+    }
+}
+
+impl ttf_parser::OutlineBuilder for OutlineBuilder<'_> {
+    fn move_to(&mut self, x: f32, y: f32) {
+        let p = self.point(x, y);
+        self.path.move_to(p);
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        let p = self.point(x, y);
+        self.path.line_to(p);
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let (p1, p) = (self.point(x1, y1), self.point(x, y));
+        self.path.quad_to(p1, p);
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let (p1, p2, p) = (self.point(x1, y1), self.point(x2, y2), self.point(x, y));
+        self.path.curve_to(p1, p2, p);
+    }
+
+    fn close(&mut self) {
+        self.path.close_path();
+    }
+}
+
 /// All the info required to indentify a font face. Basically, everythinge except the size.
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Default)]
 pub(crate) struct FontFace {
@@ -293,64 +599,83 @@ pub(crate) struct FontFace {
     pub style: FontStyle,
 }
 
-impl FontFace {
-    /// Load raw font data for `self`.
-    fn load(&self, source: &impl Source) -> Result<Arc<Vec<u8>>> {
-        load_font_data(self.find_handle(source)?).map_err(|_| Error::FontLoadingFailed)
-    }
-
-    fn find_handle(&self, source: &impl Source) -> Result<Handle> {
-        source
-            .select_best_match(&[self.to_fk_family()], &self.to_props())
-            .map_err(|_| Error::FontLoadingFailed)
+#[cfg(test)]
+mod test {
+    use super::*;
+    use piet::kurbo::Shape as _;
+    use piet::{Text as _, TextLayout as _, TextLayoutBuilder as _};
+
+    fn layout(text: &str, attribute: impl Into<TextAttribute>) -> TextLayout {
+        Text::new()
+            .new_text_layout(text.to_string())
+            .font(FontFamily::SANS_SERIF, 12.0)
+            .default_attribute(attribute)
+            .build()
+            .unwrap()
     }
 
-    fn to_fk_family(&self) -> font_kit::family_name::FamilyName {
-        use font_kit::family_name::FamilyName;
-        if self.family == FontFamily::SANS_SERIF || self.family == FontFamily::SYSTEM_UI {
-            FamilyName::SansSerif
-        } else if self.family == FontFamily::SERIF {
-            FamilyName::Serif
-        } else if self.family == FontFamily::MONOSPACE {
-            FamilyName::Monospace
-        } else {
-            FamilyName::Title(self.family.name().to_owned())
+    #[test]
+    fn glyph_runs_cover_the_text() {
+        let layout = layout("hello", TextAttribute::Weight(FontWeight::BOLD));
+        let runs = layout.glyph_runs();
+        assert_eq!(runs.len(), 1);
+        let run = &runs[0];
+        assert_eq!(run.font_size, 12.0);
+        assert_eq!(run.weight, FontWeight::BOLD);
+        assert_eq!(run.line, 0);
+        let clusters = run.glyphs.iter().map(|g| g.cluster).collect::<Vec<_>>();
+        assert_eq!(clusters, [0, 1, 2, 3, 4]);
+
+        // the glyphs are on the baseline, one after the other.
+        let baseline = layout.line_metric(0).unwrap().baseline;
+        let mut x = 0.0;
+        for glyph in &run.glyphs {
+            assert!((glyph.position.x - x).abs() < 1e-6);
+            assert_eq!(glyph.position.y, baseline);
+            assert!(glyph.advance > 0.0);
+            x += glyph.advance;
         }
+        assert!((x - layout.size().width).abs() < 1e-6);
     }
 
-    fn to_props(&self) -> font_kit::properties::Properties {
-        use font_kit::properties::{Properties, Style};
+    #[test]
+    fn outline_covers_the_ink() {
+        let layout = layout("hello", TextAttribute::Weight(FontWeight::REGULAR));
+        let outline = layout.outline();
+        assert!(outline.elements().len() > 5);
+
+        // the ink is inside the layout, mostly above the baseline.
+        let bounds = outline.bounding_box();
+        let baseline = layout.line_metric(0).unwrap().baseline;
+        let size = layout.size();
+        assert!(bounds.x0 >= 0.0 && bounds.x1 <= size.width);
+        assert!(bounds.y0 >= 0.0 && bounds.y1 <= size.height);
+        assert!(bounds.y0 < baseline && (bounds.y1 - baseline).abs() < 1.0);
+    }
 
-        let mut props = Properties::new();
-        props.weight.0 = self.weight.to_raw() as f32;
-        props.style = match self.style {
-            FontStyle::Regular => Style::Normal,
-            FontStyle::Italic => Style::Italic,
-        };
-        props
+    #[test]
+    fn variations_are_declared() {
+        let plain = layout("hello", TextAttribute::Weight(FontWeight::REGULAR));
+        assert_eq!(plain.font_variation_settings(), "");
+
+        let varied = layout("hello", FontVariation::weight(700.0));
+        assert_eq!(
+            varied.font_variation_settings(),
+            r#"font-variation-settings:"wght" 700;"#
+        );
+        // the fallback fonts have no axes, so the shaping is unchanged.
+        assert_eq!(varied.size(), plain.size());
     }
-}
 
-pub(crate) fn load_font_data(handle: Handle) -> io::Result<Arc<Vec<u8>>> {
-    // Load font data
-    Ok(match handle {
-        Handle::Path { path, font_index } => {
-            if font_index > 0 {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "font collections not supported",
-                ));
-            }
-            Arc::new(fs::read(path)?)
-        }
-        Handle::Memory { bytes, font_index } => {
-            if font_index > 0 {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "font collections not supported",
-                ));
-            }
-            bytes
-        }
-    })
+    #[test]
+    fn features_change_the_shaping() {
+        let kerned = layout("AVAVAV", TextAttribute::Weight(FontWeight::REGULAR));
+        let unkerned = layout("AVAVAV", FontFeature::off(*b"kern"));
+        assert_eq!(kerned.font_feature_settings(), "");
+        assert_eq!(
+            unkerned.font_feature_settings(),
+            r#"font-feature-settings:"kern" 0;"#
+        );
+        assert!(unkerned.size().width > kerned.size().width + 1.0);
+    }
 }
@@ -0,0 +1,116 @@
+//! Packing rasterized images, such as glyphs, into one texture.
+//!
+//! The atlas only keeps track of where things are; the texture itself, and
+//! copying into it, is up to the backend.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use etagere::{size2, AtlasAllocator};
+use piet::kurbo::Rect;
+
+/// Where images are in a square texture, found by keys of type `K`, with
+/// values of type `V` that say how to draw them.
+pub struct Atlas<K, V> {
+    size: u32,
+    allocator: AtlasAllocator,
+    entries: HashMap<K, V>,
+    /// Set when something didn't fit, so that the atlas is emptied before
+    /// the next frame.
+    full: bool,
+}
+
+/// An area of the atlas that was found for an image.
+#[derive(Clone, Copy, Debug)]
+pub struct Allocation {
+    /// The top left of the area, in pixels.
+    pub x: u32,
+    pub y: u32,
+    /// The area, in texture coordinates from 0 to 1.
+    pub uv: Rect,
+}
+
+impl<K: Eq + Hash, V> Atlas<K, V> {
+    /// An empty atlas, for a texture that is `size` pixels wide and high.
+    pub fn new(size: u32) -> Atlas<K, V> {
+        Atlas {
+            size,
+            allocator: AtlasAllocator::new(size2(size as i32, size as i32)),
+            entries: HashMap::new(),
+            full: false,
+        }
+    }
+
+    /// The width and height of the texture.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Prepare for a new frame. Images can't be evicted while a frame might
+    /// be using them, so if the atlas filled up, it is emptied here.
+    pub fn begin_frame(&mut self) {
+        if self.full {
+            self.allocator.clear();
+            self.entries.clear();
+            self.full = false;
+        }
+    }
+
+    /// Whether something didn't fit this frame, so that nothing more will.
+    pub fn is_full(&self) -> bool {
+        self.full
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.entries.get(key)
+    }
+
+    /// Find room for a `width` by `height` image.
+    ///
+    /// Returns `None` if there is no room for it, after which nothing more
+    /// is added until the next frame.
+    pub fn allocate(&mut self, width: u32, height: u32) -> Option<Allocation> {
+        if self.full {
+            return None;
+        }
+        let allocation = match self.allocator.allocate(size2(width as i32, height as i32)) {
+            Some(allocation) => allocation,
+            None => {
+                self.full = true;
+                return None;
+            }
+        };
+        let origin = allocation.rectangle.min;
+        let (x, y) = (origin.x as u32, origin.y as u32);
+        let uv = |v: u32| v as f64 / self.size as f64;
+        Some(Allocation {
+            x,
+            y,
+            uv: Rect::new(uv(x), uv(y), uv(x + width), uv(y + height)),
+        })
+    }
+
+    /// Add an image, which usually has just been copied to an allocation.
+    pub fn insert(&mut self, key: K, value: V) {
+        self.entries.insert(key, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_atlas_is_emptied_next_frame() {
+        let mut atlas = Atlas::new(64);
+        let allocation = atlas.allocate(32, 16).unwrap();
+        assert_eq!(allocation.uv.size(), piet::kurbo::Size::new(0.5, 0.25));
+        atlas.insert(1, ());
+        assert!(atlas.allocate(128, 1).is_none());
+        // nothing more is added until the next frame.
+        assert!(atlas.allocate(1, 1).is_none());
+        atlas.begin_frame();
+        assert!(atlas.get(&1).is_none());
+        assert!(atlas.allocate(1, 1).is_some());
+    }
+}
@@ -0,0 +1,379 @@
+//! Tessellation of piet shapes into triangles.
+//!
+//! GPU backends, and applications that hand shapes to game engines, need
+//! shapes as triangles. This crate tessellates fills and strokes with
+//! [lyon], following the same rules as piet's backends: fills are non-zero
+//! or even-odd, like [`RenderContext::fill`] and
+//! [`RenderContext::fill_even_odd`], and strokes follow a [`StrokeStyle`],
+//! including its dashes.
+//!
+//! Triangles are added to a [`VertexSink`], such as a [`Mesh`]:
+//!
+//! ```
+//! use piet::kurbo::Circle;
+//! use piet_tess::Mesh;
+//!
+//! let mut mesh = Mesh::default();
+//! piet_tess::tessellate(Circle::new((50.0, 50.0), 10.0), 0.1, &mut mesh).unwrap();
+//! assert_eq!(mesh.indices.len() % 3, 0);
+//! ```
+//!
+//! The tolerance is the furthest, in the units of the shape, that the edges
+//! of the triangles may stray from its curves. For drawing, a tenth of a
+//! pixel is plenty, so shapes that are scaled when they are drawn should be
+//! tessellated with a tolerance that is scaled to match.
+//!
+//! GPU backends also share the rest of what they draw with: the [`scene`] of
+//! a frame's triangles and draw commands, the [`state`] of a render context
+//! that records into it, the color [`ramps`] of gradients, and the [`atlas`]
+//! that rasterized text is packed into. What is left to each backend is
+//! uploading these, and drawing them with its GPU API.
+//!
+//! [lyon]: https://docs.rs/lyon_tessellation
+//! [`RenderContext::fill`]: piet::RenderContext::fill
+//! [`RenderContext::fill_even_odd`]: piet::RenderContext::fill_even_odd
+
+#![deny(clippy::trivially_copy_pass_by_ref)]
+
+pub mod atlas;
+pub mod ramps;
+pub mod scene;
+pub mod state;
+
+use lyon_tessellation::math::point;
+use lyon_tessellation::path::Path;
+use lyon_tessellation::{
+    FillGeometryBuilder, FillOptions, FillRule, FillTessellator, FillVertex, GeometryBuilder,
+    GeometryBuilderError, StrokeGeometryBuilder, StrokeOptions, StrokeTessellator, StrokeVertex,
+    VertexId,
+};
+
+use piet::kurbo::{BezPath, PathEl, Point, Shape};
+use piet::util::dash;
+use piet::{Error, LineCap, LineJoin, StrokeStyle};
+
+/// Something that triangles are added to, such as a vertex and index buffer.
+pub trait VertexSink {
+    /// Add a vertex at `position`, returning its index.
+    fn vertex(&mut self, position: Point) -> u32;
+
+    /// Add a triangle, of three vertices that were added by [`vertex`].
+    ///
+    /// [`vertex`]: VertexSink::vertex
+    fn triangle(&mut self, a: u32, b: u32, c: u32);
+}
+
+/// Triangles, as a list of vertices, and a list of indices into it with
+/// three for each triangle.
+#[derive(Clone, Debug, Default)]
+pub struct Mesh {
+    pub vertices: Vec<Point>,
+    pub indices: Vec<u32>,
+}
+
+/// Tessellates paths, keeping its allocations between paths.
+pub struct Tessellator {
+    fill: FillTessellator,
+    stroke: StrokeTessellator,
+}
+
+impl Mesh {
+    /// Remove all triangles, keeping the allocations.
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+        self.indices.clear();
+    }
+}
+
+impl VertexSink for Mesh {
+    fn vertex(&mut self, position: Point) -> u32 {
+        self.vertices.push(position);
+        self.vertices.len() as u32 - 1
+    }
+
+    fn triangle(&mut self, a: u32, b: u32, c: u32) {
+        self.indices.extend([a, b, c]);
+    }
+}
+
+impl Tessellator {
+    pub fn new() -> Tessellator {
+        Tessellator {
+            fill: FillTessellator::new(),
+            stroke: StrokeTessellator::new(),
+        }
+    }
+
+    /// Tessellate the inside of a path, with the non-zero rule, or with the
+    /// even-odd rule if `even_odd` is `true`.
+    ///
+    /// If the path can't be tessellated, such as when it has coordinates
+    /// that are not finite, an error is returned, and some of its triangles
+    /// may already have been added.
+    pub fn fill(
+        &mut self,
+        path: impl IntoIterator<Item = PathEl>,
+        even_odd: bool,
+        tolerance: f64,
+        sink: &mut impl VertexSink,
+    ) -> Result<(), Error> {
+        let path = convert_path(path);
+        let fill_rule = if even_odd {
+            FillRule::EvenOdd
+        } else {
+            FillRule::NonZero
+        };
+        let options = FillOptions::tolerance(tolerance as f32).with_fill_rule(fill_rule);
+        self.fill
+            .tessellate_path(&path, &options, &mut Builder(sink))
+            .map_err(|e| Error::BackendError(Box::new(e)))
+    }
+
+    /// Tessellate a stroke of a path, `width` wide, in `style`.
+    ///
+    /// The stroke is in the same space as the path; a stroke whose style
+    /// isn't [scaled] should be tessellated from a path that has already been
+    /// transformed.
+    ///
+    /// If the path can't be tessellated, an error is returned, and some of
+    /// its triangles may already have been added.
+    ///
+    /// [scaled]: StrokeStyle::scale
+    pub fn stroke(
+        &mut self,
+        path: impl IntoIterator<Item = PathEl>,
+        width: f64,
+        style: &StrokeStyle,
+        tolerance: f64,
+        sink: &mut impl VertexSink,
+    ) -> Result<(), Error> {
+        let path = if style.dash_pattern.is_empty() {
+            convert_path(path)
+        } else {
+            let path: BezPath = path.into_iter().collect();
+            convert_path(dash(path, &style.dash_pattern, style.dash_offset))
+        };
+        let options = convert_stroke(width, style).with_tolerance(tolerance as f32);
+        self.stroke
+            .tessellate_path(&path, &options, &mut Builder(sink))
+            .map_err(|e| Error::BackendError(Box::new(e)))
+    }
+}
+
+impl Default for Tessellator {
+    fn default() -> Self {
+        Tessellator::new()
+    }
+}
+
+/// Tessellate the inside of `shape`, with the non-zero rule, like
+/// [`RenderContext::fill`].
+///
+/// To tessellate many shapes, a [`Tessellator`] can be kept, to reuse its
+/// allocations.
+///
+/// [`RenderContext::fill`]: piet::RenderContext::fill
+pub fn tessellate(
+    shape: impl Shape,
+    tolerance: f64,
+    sink: &mut impl VertexSink,
+) -> Result<(), Error> {
+    Tessellator::new().fill(shape.path_elements(tolerance), false, tolerance, sink)
+}
+
+/// Tessellate the inside of `shape`, with the even-odd rule, like
+/// [`RenderContext::fill_even_odd`].
+///
+/// [`RenderContext::fill_even_odd`]: piet::RenderContext::fill_even_odd
+pub fn tessellate_even_odd(
+    shape: impl Shape,
+    tolerance: f64,
+    sink: &mut impl VertexSink,
+) -> Result<(), Error> {
+    Tessellator::new().fill(shape.path_elements(tolerance), true, tolerance, sink)
+}
+
+/// Tessellate a stroke of `shape`, `width` wide, in `style`, like
+/// [`RenderContext::stroke_styled`].
+///
+/// [`RenderContext::stroke_styled`]: piet::RenderContext::stroke_styled
+pub fn tessellate_stroke(
+    shape: impl Shape,
+    width: f64,
+    style: &StrokeStyle,
+    tolerance: f64,
+    sink: &mut impl VertexSink,
+) -> Result<(), Error> {
+    Tessellator::new().stroke(
+        shape.path_elements(tolerance),
+        width,
+        style,
+        tolerance,
+        sink,
+    )
+}
+
+/// Adds what lyon tessellates to a [`VertexSink`].
+struct Builder<'a, S>(&'a mut S);
+
+impl<S: VertexSink> GeometryBuilder for Builder<'_, S> {
+    fn add_triangle(&mut self, a: VertexId, b: VertexId, c: VertexId) {
+        self.0.triangle(a.0, b.0, c.0);
+    }
+}
+
+impl<S: VertexSink> FillGeometryBuilder for Builder<'_, S> {
+    fn add_fill_vertex(&mut self, vertex: FillVertex) -> Result<VertexId, GeometryBuilderError> {
+        Ok(VertexId(self.0.vertex(convert_point(vertex.position()))))
+    }
+}
+
+impl<S: VertexSink> StrokeGeometryBuilder for Builder<'_, S> {
+    fn add_stroke_vertex(
+        &mut self,
+        vertex: StrokeVertex,
+    ) -> Result<VertexId, GeometryBuilderError> {
+        Ok(VertexId(self.0.vertex(convert_point(vertex.position()))))
+    }
+}
+
+fn convert_stroke(width: f64, style: &StrokeStyle) -> StrokeOptions {
+    use lyon_tessellation::{LineCap as Cap, LineJoin as Join};
+
+    let (line_join, miter_limit) = match style.line_join {
+        LineJoin::Miter { limit } => (Join::MiterClip, limit),
+        LineJoin::Round => (Join::Round, StrokeOptions::DEFAULT_MITER_LIMIT as f64),
+        LineJoin::Bevel => (Join::Bevel, StrokeOptions::DEFAULT_MITER_LIMIT as f64),
+    };
+    let line_cap = match style.line_cap {
+        LineCap::Butt => Cap::Butt,
+        LineCap::Round => Cap::Round,
+        LineCap::Square => Cap::Square,
+    };
+    StrokeOptions::default()
+        .with_line_width(width as f32)
+        .with_line_join(line_join)
+        // lyon's minimum
+        .with_miter_limit((miter_limit as f32).max(1.0))
+        .with_line_cap(line_cap)
+}
+
+fn convert_path(elements: impl IntoIterator<Item = PathEl>) -> Path {
+    let mut builder = Path::builder();
+    let mut open = false;
+    let mut start = Point::ZERO;
+    let mut last = Point::ZERO;
+    for el in elements {
+        // lyon needs every segment to be in an explicitly started subpath.
+        if !open && !matches!(el, PathEl::MoveTo(_) | PathEl::ClosePath) {
+            builder.begin(lyon_point(last));
+            open = true;
+        }
+        match el {
+            PathEl::MoveTo(p) => {
+                if open {
+                    builder.end(false);
+                }
+                builder.begin(lyon_point(p));
+                open = true;
+                start = p;
+                last = p;
+            }
+            PathEl::LineTo(p) => {
+                builder.line_to(lyon_point(p));
+                last = p;
+            }
+            PathEl::QuadTo(p1, p2) => {
+                builder.quadratic_bezier_to(lyon_point(p1), lyon_point(p2));
+                last = p2;
+            }
+            PathEl::CurveTo(p1, p2, p3) => {
+                builder.cubic_bezier_to(lyon_point(p1), lyon_point(p2), lyon_point(p3));
+                last = p3;
+            }
+            PathEl::ClosePath => {
+                if open {
+                    builder.end(true);
+                    open = false;
+                }
+                last = start;
+            }
+        }
+    }
+    if open {
+        builder.end(false);
+    }
+    builder.build()
+}
+
+fn lyon_point(p: Point) -> lyon_tessellation::math::Point {
+    point(p.x as f32, p.y as f32)
+}
+
+fn convert_point(p: lyon_tessellation::math::Point) -> Point {
+    Point::new(p.x as f64, p.y as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use piet::kurbo::{Line, Rect};
+
+    fn area(mesh: &Mesh) -> f64 {
+        mesh.indices
+            .chunks_exact(3)
+            .map(|t| {
+                let [a, b, c] = [t[0], t[1], t[2]].map(|i| mesh.vertices[i as usize]);
+                ((b - a).cross(c - a) / 2.0).abs()
+            })
+            .sum()
+    }
+
+    #[test]
+    fn fill_covers_shape() {
+        let mut mesh = Mesh::default();
+        tessellate(Rect::new(0.0, 0.0, 10.0, 20.0), 0.1, &mut mesh).unwrap();
+        assert!((area(&mesh) - 200.0).abs() < 1e-3);
+
+        // the inner square has a winding of two, so only even-odd leaves it out.
+        let mut squares = Rect::new(0.0, 0.0, 10.0, 10.0).to_path(0.1);
+        squares.extend(Rect::new(2.0, 2.0, 8.0, 8.0).path_elements(0.1));
+        mesh.clear();
+        tessellate(&squares, 0.1, &mut mesh).unwrap();
+        assert!((area(&mesh) - 100.0).abs() < 1e-3);
+        mesh.clear();
+        tessellate_even_odd(&squares, 0.1, &mut mesh).unwrap();
+        assert!((area(&mesh) - 64.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn stroke_follows_style() {
+        let line = Line::new((0.0, 0.0), (10.0, 0.0));
+        let mut mesh = Mesh::default();
+        tessellate_stroke(line, 2.0, &StrokeStyle::new(), 0.1, &mut mesh).unwrap();
+        assert!((area(&mesh) - 20.0).abs() < 1e-3);
+
+        let style = StrokeStyle::new().dash_pattern(&[2.0, 3.0]);
+        mesh.clear();
+        tessellate_stroke(line, 2.0, &style, 0.1, &mut mesh).unwrap();
+        assert!((area(&mesh) - 8.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn dashes() {
+        let path = BezPath::from_vec(vec![
+            PathEl::MoveTo(Point::new(0., 0.)),
+            PathEl::LineTo(Point::new(10., 0.)),
+        ]);
+        let dashed = dash(path, &[2., 3.], 1.);
+        let starts = dashed
+            .elements()
+            .iter()
+            .filter_map(|el| match el {
+                PathEl::MoveTo(p) => Some(p.x),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(starts, vec![0., 4., 9.]);
+    }
+}
@@ -0,0 +1,105 @@
+//! The color ramps of gradients.
+//!
+//! Each gradient's colors are sampled into a row of an image, which the
+//! backend keeps in a texture, and which gradient paints look their colors
+//! up in.
+
+use std::collections::HashMap;
+
+use piet::{Error, FixedGradient, GradientOptions, GradientStop};
+
+use crate::state::Brush;
+
+/// The number of colors in each gradient's ramp.
+pub const RAMP_WIDTH: u32 = 256;
+
+/// The ramps of the gradients that have been made, one per row.
+pub struct Ramps {
+    rows: HashMap<Vec<(u32, u32)>, u32>,
+    data: Vec<u8>,
+    changed: bool,
+}
+
+impl Ramps {
+    pub fn new() -> Ramps {
+        Ramps {
+            rows: HashMap::new(),
+            data: Vec::new(),
+            changed: true,
+        }
+    }
+
+    /// The brush for a gradient, adding its ramp if it is new.
+    pub fn gradient(
+        &mut self,
+        gradient: impl Into<FixedGradient>,
+        options: GradientOptions,
+    ) -> Result<Brush, Error> {
+        match piet::util::interpolated_gradient(gradient.into(), options.interpolation) {
+            FixedGradient::Linear(linear) => Ok(Brush::Linear {
+                start: linear.start,
+                end: linear.end,
+                ramp: self.row(&linear.stops)?,
+                dither: options.dither,
+            }),
+            FixedGradient::Radial(radial) => Ok(Brush::Radial {
+                center: radial.center,
+                origin_offset: radial.origin_offset,
+                radius: radial.radius,
+                ramp: self.row(&radial.stops)?,
+                dither: options.dither,
+            }),
+        }
+    }
+
+    /// The row of the ramp for `stops`, adding it if it is new.
+    pub fn row(&mut self, stops: &[GradientStop]) -> Result<u32, Error> {
+        if stops.is_empty() {
+            return Err(Error::InvalidInput);
+        }
+        let key = stops
+            .iter()
+            .map(|stop| (stop.pos.to_bits(), stop.color.as_rgba_u32()))
+            .collect::<Vec<_>>();
+        if let Some(row) = self.rows.get(&key) {
+            return Ok(*row);
+        }
+        let row = self.rows.len() as u32;
+        for i in 0..RAMP_WIDTH {
+            let t = i as f32 / (RAMP_WIDTH - 1) as f32;
+            let (r, g, b, a) = piet::util::gradient_color(stops, t).as_rgba();
+            let premul = |c: f64| (c * a * 255.0).round() as u8;
+            self.data
+                .extend([premul(r), premul(g), premul(b), (a * 255.0).round() as u8]);
+        }
+        self.rows.insert(key, row);
+        self.changed = true;
+        Ok(row)
+    }
+
+    /// The number of rows.
+    pub fn len(&self) -> u32 {
+        self.rows.len() as u32
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// The premultiplied RGBA pixels of the rows, `RAMP_WIDTH` per row.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Whether rows were added since this was last called, so that the
+    /// texture needs to be updated. It is `true` the first time.
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::replace(&mut self.changed, false)
+    }
+}
+
+impl Default for Ramps {
+    fn default() -> Self {
+        Ramps::new()
+    }
+}
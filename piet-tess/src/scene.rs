@@ -0,0 +1,338 @@
+//! Recording drawing operations as triangle meshes.
+//!
+//! Everything drawn in a frame is tessellated into one shared vertex and
+//! index buffer, in pixel coordinates. Each vertex carries its paint, so that
+//! consecutive draws only need a new draw call when they change the bound
+//! image or the clip.
+
+use std::ops::Range;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use bytemuck::{Pod, Zeroable};
+
+use piet::kurbo::{Affine, PathEl, Point, Rect};
+use piet::StrokeStyle;
+
+use crate::{Tessellator, VertexSink};
+
+// Keep these in sync with the shaders of the backends.
+pub const PAINT_SOLID: u32 = 0;
+pub const PAINT_LINEAR: u32 = 1;
+pub const PAINT_RADIAL: u32 = 2;
+pub const PAINT_IMAGE: u32 = 3;
+pub const IMAGE_MASK: u32 = 0x10;
+pub const ATLAS_MASK: u32 = 0x20;
+pub const DITHER_MASK: u32 = 0x40;
+
+/// A vertex of the scene's mesh.
+///
+/// Its fields are, in order: its position in pixels, the position of the
+/// point in the space of its paint, and in the space of its mask, as two
+/// `f32`s each, the paint's color as four `f32`s, and the paint's kind and
+/// flags as a `u32`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Pod, Zeroable)]
+pub struct Vertex {
+    pos: [f32; 2],
+    paint_pos: [f32; 2],
+    mask_pos: [f32; 2],
+    color: [f32; 4],
+    kind: u32,
+}
+
+/// How a mesh is colored.
+#[derive(Clone, Copy, Debug)]
+pub struct Paint {
+    pub kind: u32,
+    /// The premultiplied color for solid paints; for gradients, the focus
+    /// and the row of the gradient's color ramp, followed by the alpha,
+    /// which is also the last component for images.
+    pub color: [f32; 4],
+    /// Maps the points being tessellated to the space that `kind` expects.
+    pub transform: Affine,
+}
+
+/// A texture that is bound for a draw, for image paints and masks.
+pub trait Binding: Clone {
+    /// Whether `self` and `other` bind the same texture in the same way, so
+    /// that draws with them can be merged.
+    fn same(&self, other: &Self) -> bool;
+}
+
+#[derive(Clone, Debug)]
+pub enum Command<I> {
+    /// Draw triangles where the stencil equals `stencil`, the current clip
+    /// depth, with `image` bound for image paints and masks.
+    Draw {
+        indices: Range<u32>,
+        image: Option<I>,
+        stencil: u32,
+    },
+    /// Replace the pixels under these triangles, ignoring the clip.
+    Clear { indices: Range<u32> },
+    /// Add a clip, going from `depth - 1` to `depth`.
+    PushClip { indices: Range<u32>, depth: u32 },
+    /// Remove a clip, going from `depth + 1` to `depth`.
+    PopClip { indices: Range<u32>, depth: u32 },
+}
+
+/// A frame's triangles, and the commands that draw them.
+pub struct Scene<I> {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    pub commands: Vec<Command<I>>,
+    /// The premultiplied color the target is cleared to before drawing, if
+    /// everything before a full clear was dropped.
+    pub clear_color: Option<[f32; 4]>,
+    // kept to reuse its allocations.
+    tessellator: Tessellator,
+}
+
+impl Paint {
+    pub fn solid(color: [f32; 4]) -> Paint {
+        Paint {
+            kind: PAINT_SOLID,
+            color,
+            transform: Affine::IDENTITY,
+        }
+    }
+
+    /// The paint, with its opacity multiplied by `alpha`.
+    pub fn with_alpha(mut self, alpha: f32) -> Paint {
+        if self.kind == PAINT_SOLID {
+            self.color = self.color.map(|c| c * alpha);
+        } else {
+            self.color[3] *= alpha;
+        }
+        self
+    }
+
+    fn vertex(&self, pos: Point, local: Point, mask_pos: Point) -> Vertex {
+        let paint_pos = self.transform * local;
+        Vertex {
+            pos: [pos.x as f32, pos.y as f32],
+            paint_pos: [paint_pos.x as f32, paint_pos.y as f32],
+            mask_pos: [mask_pos.x as f32, mask_pos.y as f32],
+            color: self.color,
+            kind: self.kind,
+        }
+    }
+}
+
+impl<T> Binding for Arc<T> {
+    fn same(&self, other: &Self) -> bool {
+        Arc::ptr_eq(self, other)
+    }
+}
+
+impl<T> Binding for Rc<T> {
+    fn same(&self, other: &Self) -> bool {
+        Rc::ptr_eq(self, other)
+    }
+}
+
+impl<I> Default for Scene<I> {
+    fn default() -> Scene<I> {
+        Scene {
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            commands: Vec::new(),
+            clear_color: None,
+            tessellator: Tessellator::new(),
+        }
+    }
+}
+
+impl<I: Binding> Scene<I> {
+    /// Remove everything, keeping the allocations.
+    pub fn reset(&mut self) {
+        self.vertices.clear();
+        self.indices.clear();
+        self.commands.clear();
+        self.clear_color = None;
+    }
+
+    fn next_index(&self) -> u32 {
+        self.indices.len() as u32
+    }
+
+    /// Tessellate the inside of a path, which is in a space that
+    /// `to_device` maps to pixels, with a mask if there is one.
+    ///
+    /// The mask's transform maps the path's space to its texture coordinates.
+    pub fn fill(
+        &mut self,
+        path: impl IntoIterator<Item = PathEl>,
+        even_odd: bool,
+        to_device: Affine,
+        paint: &Paint,
+        mask: Option<(u32, Affine)>,
+    ) -> Range<u32> {
+        let start = self.next_index();
+        let (flags, to_mask) = mask.unwrap_or((0, Affine::scale(0.0)));
+        let mut sink = MeshSink {
+            vertices: &mut self.vertices,
+            indices: &mut self.indices,
+            vertex: |local| {
+                let mut vertex = paint.vertex(to_device * local, local, to_mask * local);
+                vertex.kind |= flags;
+                vertex
+            },
+        };
+        // An error only leaves out the rest of the path.
+        let _ = self
+            .tessellator
+            .fill(path, even_odd, tolerance(to_device), &mut sink);
+        start..self.next_index()
+    }
+
+    /// Tessellate a stroke of a path, which is in a space that `to_device`
+    /// maps to pixels.
+    pub fn stroke(
+        &mut self,
+        path: impl IntoIterator<Item = PathEl>,
+        width: f64,
+        style: &StrokeStyle,
+        to_device: Affine,
+        paint: &Paint,
+    ) -> Range<u32> {
+        let start = self.next_index();
+        let mut sink = MeshSink {
+            vertices: &mut self.vertices,
+            indices: &mut self.indices,
+            vertex: |local| paint.vertex(to_device * local, local, Point::ZERO),
+        };
+        let _ = self
+            .tessellator
+            .stroke(path, width, style, tolerance(to_device), &mut sink);
+        start..self.next_index()
+    }
+
+    /// Add a rectangle, with `mask` spread over it if there is one.
+    ///
+    /// This is much cheaper than filling the rectangle as a path.
+    pub fn rect(
+        &mut self,
+        rect: Rect,
+        to_device: Affine,
+        paint: &Paint,
+        mask: Option<(u32, Rect)>,
+    ) -> Range<u32> {
+        let start = self.next_index();
+        let base = self.vertices.len() as u32;
+        let (flags, mask_rect) = mask.unwrap_or((0, Rect::ZERO));
+        let corners = [
+            (
+                Point::new(rect.x0, rect.y0),
+                Point::new(mask_rect.x0, mask_rect.y0),
+            ),
+            (
+                Point::new(rect.x1, rect.y0),
+                Point::new(mask_rect.x1, mask_rect.y0),
+            ),
+            (
+                Point::new(rect.x1, rect.y1),
+                Point::new(mask_rect.x1, mask_rect.y1),
+            ),
+            (
+                Point::new(rect.x0, rect.y1),
+                Point::new(mask_rect.x0, mask_rect.y1),
+            ),
+        ];
+        for (local, mask_pos) in corners {
+            let mut vertex = paint.vertex(to_device * local, local, mask_pos);
+            vertex.kind |= flags;
+            self.vertices.push(vertex);
+        }
+        self.indices
+            .extend([base, base + 1, base + 2, base, base + 2, base + 3]);
+        start..self.next_index()
+    }
+
+    /// Draw some triangles that were just added, merging them into the last
+    /// draw call if it has the same state.
+    pub fn draw(&mut self, indices: Range<u32>, image: Option<&I>, stencil: u32) {
+        if indices.is_empty() {
+            return;
+        }
+        if let Some(Command::Draw {
+            indices: last,
+            image: last_image,
+            stencil: last_stencil,
+        }) = self.commands.last_mut()
+        {
+            let same_image = match (image, &last_image) {
+                (Some(a), Some(b)) => a.same(b),
+                (None, None) => true,
+                _ => false,
+            };
+            if same_image && *last_stencil == stencil && last.end == indices.start {
+                last.end = indices.end;
+                return;
+            }
+        }
+        self.commands.push(Command::Draw {
+            indices,
+            image: image.cloned(),
+            stencil,
+        });
+    }
+}
+
+/// Adds tessellated vertices to the mesh, made by `vertex` from their
+/// positions in the space of the path.
+struct MeshSink<'a, F> {
+    vertices: &'a mut Vec<Vertex>,
+    indices: &'a mut Vec<u32>,
+    vertex: F,
+}
+
+impl<F: FnMut(Point) -> Vertex> VertexSink for MeshSink<'_, F> {
+    fn vertex(&mut self, position: Point) -> u32 {
+        self.vertices.push((self.vertex)(position));
+        self.vertices.len() as u32 - 1
+    }
+
+    fn triangle(&mut self, a: u32, b: u32, c: u32) {
+        self.indices.extend([a, b, c]);
+    }
+}
+
+/// The tolerance for flattening curves, in the space that `to_device` maps
+/// to pixels.
+fn tolerance(to_device: Affine) -> f64 {
+    const DEVICE_TOLERANCE: f64 = 0.1;
+    let scale = to_device.determinant().abs().sqrt();
+    if scale > 1e-9 {
+        DEVICE_TOLERANCE / scale
+    } else {
+        DEVICE_TOLERANCE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draws_with_the_same_state_are_merged() {
+        let mut scene = Scene::<Rc<u32>>::default();
+        let (a, b) = (Rc::new(0), Rc::new(0));
+        let paint = Paint::solid([1.0; 4]);
+        let rect = Rect::new(0.0, 0.0, 1.0, 1.0);
+        for (image, stencil) in [(None, 0), (None, 0), (Some(&a), 0), (Some(&a), 0)] {
+            let indices = scene.rect(rect, Affine::IDENTITY, &paint, None);
+            scene.draw(indices, image, stencil);
+        }
+        assert_eq!(scene.commands.len(), 2);
+        for (image, stencil) in [(Some(&b), 0), (Some(&b), 1)] {
+            let indices = scene.rect(rect, Affine::IDENTITY, &paint, None);
+            scene.draw(indices, image, stencil);
+        }
+        assert_eq!(scene.commands.len(), 4);
+        assert!(
+            matches!(scene.commands[1], Command::Draw { ref indices, .. } if *indices == (12..24))
+        );
+    }
+}
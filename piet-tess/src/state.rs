@@ -0,0 +1,381 @@
+//! The state of a render context that draws into a [`Scene`].
+//!
+//! This is everything about a GPU backend's [`RenderContext`] that doesn't
+//! depend on the GPU API: the transform, clip and alpha, and how shapes and
+//! brushes become triangles and paints. The backend only has to upload the
+//! scene and its textures, and draw it.
+//!
+//! [`RenderContext`]: piet::RenderContext
+
+use std::ops::Range;
+
+use piet::kurbo::{Affine, BezPath, Point, Rect, Shape, Vec2};
+use piet::{Color, Error, HitTester, StrokeStyle};
+
+use crate::scene::{
+    Binding, Command, Paint, Scene, DITHER_MASK, IMAGE_MASK, PAINT_IMAGE, PAINT_LINEAR,
+    PAINT_RADIAL,
+};
+
+#[derive(Clone, Debug)]
+pub enum Brush {
+    /// A premultiplied color.
+    Solid([f32; 4]),
+    Linear {
+        start: Point,
+        end: Point,
+        ramp: u32,
+        dither: bool,
+    },
+    Radial {
+        center: Point,
+        origin_offset: Vec2,
+        radius: f64,
+        ramp: u32,
+        dither: bool,
+    },
+}
+
+/// The scene being drawn, and the state it is drawn with, for images of
+/// type `I`.
+pub struct RenderState<I> {
+    pub scene: Scene<I>,
+    // the transform from piet's coordinates to pixels, which is not part of
+    // the transform reported by `current_transform`.
+    base_transform: Affine,
+    state: State,
+    saved: Vec<State>,
+    /// The triangles of each clip that is in the stencil buffer, so that it
+    /// can be removed again.
+    clips: Vec<Range<u32>>,
+    err: Result<(), Error>,
+    hit_tester: HitTester,
+}
+
+#[derive(Clone, Copy)]
+struct State {
+    transform: Affine,
+    /// The number of clips that are active, which is also the value of the
+    /// stencil buffer inside all of them.
+    clip_depth: u32,
+    global_alpha: f32,
+}
+
+impl Default for State {
+    fn default() -> State {
+        State {
+            transform: Affine::IDENTITY,
+            clip_depth: 0,
+            global_alpha: 1.0,
+        }
+    }
+}
+
+impl<I: Binding> RenderState<I> {
+    /// Start drawing into `scene`, which is emptied first, scaling
+    /// everything by `scale`, the number of pixels per display point.
+    pub fn new(mut scene: Scene<I>, scale: f64) -> RenderState<I> {
+        scene.reset();
+        RenderState {
+            scene,
+            base_transform: Affine::scale(scale),
+            state: State::default(),
+            saved: Vec::new(),
+            clips: Vec::new(),
+            err: Ok(()),
+            hit_tester: HitTester::new(),
+        }
+    }
+
+    /// The error since this was last called, like [`RenderContext::status`].
+    ///
+    /// [`RenderContext::status`]: piet::RenderContext::status
+    pub fn status(&mut self) -> Result<(), Error> {
+        std::mem::replace(&mut self.err, Ok(()))
+    }
+
+    /// Report `err` from the next call of [`status`](RenderState::status).
+    pub fn set_error(&mut self, err: Error) {
+        self.err = Err(err);
+    }
+
+    pub fn hit_tester(&mut self) -> &mut HitTester {
+        &mut self.hit_tester
+    }
+
+    /// The transform from user space to pixels.
+    pub fn device_transform(&self) -> Affine {
+        self.base_transform * self.state.transform
+    }
+
+    /// The transform from piet's coordinates to pixels.
+    pub fn base_transform(&self) -> Affine {
+        self.base_transform
+    }
+
+    pub fn transform(&mut self, transform: Affine) {
+        self.state.transform *= transform;
+    }
+
+    pub fn current_transform(&self) -> Affine {
+        self.state.transform
+    }
+
+    pub fn set_global_alpha(&mut self, alpha: f64) {
+        // NaN is treated as transparent.
+        let alpha = if alpha >= 0.0 { alpha.min(1.0) } else { 0.0 };
+        self.state.global_alpha = alpha as f32;
+    }
+
+    pub fn global_alpha(&self) -> f32 {
+        self.state.global_alpha
+    }
+
+    pub fn save(&mut self) {
+        self.saved.push(self.state);
+    }
+
+    pub fn restore(&mut self) -> Result<(), Error> {
+        match self.saved.pop() {
+            Some(state) => {
+                self.state = state;
+                self.pop_clips(state.clip_depth);
+                Ok(())
+            }
+            None => Err(Error::StackUnbalance),
+        }
+    }
+
+    /// Draw triangles that were just added inside the current clip.
+    pub fn draw(&mut self, indices: Range<u32>, image: Option<&I>) {
+        self.scene.draw(indices, image, self.state.clip_depth);
+    }
+
+    /// The paint for `brush`, for points that `to_user` maps to user space,
+    /// faded by the global alpha.
+    pub fn paint(&self, brush: &Brush, to_user: Affine) -> Paint {
+        brush.to_paint(to_user).with_alpha(self.state.global_alpha)
+    }
+
+    /// Clear `region`, or everything, like [`RenderContext::clear`].
+    ///
+    /// [`RenderContext::clear`]: piet::RenderContext::clear
+    pub fn clear(&mut self, region: Option<Rect>, color: Color) {
+        let premul = premul_color(color);
+        match region {
+            // nothing that was drawn before is visible after this, so we
+            // start again, clearing the whole target when the frame starts.
+            None => {
+                self.restart();
+                self.scene.clear_color = Some(premul);
+            }
+            // like the other backends, this ignores the current transform and clip.
+            Some(region) => {
+                let indices =
+                    self.scene
+                        .rect(region, self.base_transform, &Paint::solid(premul), None);
+                if !indices.is_empty() {
+                    self.scene.commands.push(Command::Clear { indices });
+                }
+            }
+        }
+    }
+
+    pub fn fill(&mut self, shape: impl Shape, brush: &Brush, even_odd: bool) {
+        let paint = self.paint(brush, Affine::IDENTITY);
+        let to_device = self.device_transform();
+        let indices = match shape.as_rect() {
+            Some(rect) => self.scene.rect(rect, to_device, &paint, None),
+            None => self
+                .scene
+                .fill(shape.path_elements(0.1), even_odd, to_device, &paint, None),
+        };
+        self.draw(indices, None);
+    }
+
+    /// Fill `shape` through the `uv` area of `mask`, which is spread over
+    /// the shape's bounds.
+    pub fn fill_with_mask(&mut self, shape: impl Shape, brush: &Brush, mask: &I, uv: Rect) {
+        let bounds = shape.bounding_box();
+        if bounds.is_empty() {
+            return;
+        }
+        let paint = self.paint(brush, Affine::IDENTITY);
+        let to_device = self.device_transform();
+        let indices = match shape.as_rect() {
+            Some(_) => self
+                .scene
+                .rect(bounds, to_device, &paint, Some((IMAGE_MASK, uv))),
+            None => {
+                // map the bounds to the mask's texture coordinates.
+                let to_mask = Affine::translate(uv.origin().to_vec2())
+                    * Affine::scale_non_uniform(
+                        uv.width() / bounds.width(),
+                        uv.height() / bounds.height(),
+                    )
+                    * Affine::translate(-bounds.origin().to_vec2());
+                self.scene.fill(
+                    shape.path_elements(0.1),
+                    false,
+                    to_device,
+                    &paint,
+                    Some((IMAGE_MASK, to_mask)),
+                )
+            }
+        };
+        self.draw(indices, Some(mask));
+    }
+
+    pub fn stroke(&mut self, shape: impl Shape, brush: &Brush, width: f64, style: &StrokeStyle) {
+        // The stroke parameters are interpreted without our transform if the
+        // stroke isn't scaled, so then we move the path there before
+        // stroking.
+        let (path, to_user, to_device): (BezPath, _, _) = if style.scale {
+            (
+                shape.path_elements(0.1).collect(),
+                Affine::IDENTITY,
+                self.device_transform(),
+            )
+        } else {
+            let transform = self.state.transform;
+            (
+                shape.path_elements(0.1).map(|el| transform * el).collect(),
+                transform.inverse(),
+                self.base_transform,
+            )
+        };
+        let paint = self.paint(brush, to_user);
+        let indices = self.scene.stroke(path, width, style, to_device, &paint);
+        self.draw(indices, None);
+    }
+
+    pub fn clip(&mut self, shape: impl Shape) {
+        // The stencil buffer has 8 bits.
+        if self.state.clip_depth == u8::MAX as u32 {
+            self.err = Err(Error::NotSupported);
+            return;
+        }
+        let to_device = self.device_transform();
+        let paint = Paint::solid([0.0; 4]);
+        let indices = match shape.as_rect() {
+            Some(rect) => self.scene.rect(rect, to_device, &paint, None),
+            None => self
+                .scene
+                .fill(shape.path_elements(0.1), false, to_device, &paint, None),
+        };
+        self.state.clip_depth += 1;
+        self.clips.push(indices.clone());
+        self.scene.commands.push(Command::PushClip {
+            indices,
+            depth: self.state.clip_depth,
+        });
+    }
+
+    /// Draw the `uv` area of a texture, which is premultiplied, into `rect`,
+    /// which `to_device` maps to pixels, faded by the global alpha.
+    pub fn draw_texture(&mut self, rect: Rect, to_device: Affine, image: &I, uv: Rect) {
+        if rect.is_empty() {
+            return;
+        }
+        let transform = Affine::translate(uv.origin().to_vec2())
+            * Affine::scale_non_uniform(uv.width() / rect.width(), uv.height() / rect.height())
+            * Affine::translate(-rect.origin().to_vec2());
+        let paint = Paint {
+            kind: PAINT_IMAGE,
+            color: [0.0, 0.0, 0.0, self.state.global_alpha],
+            transform,
+        };
+        let indices = self.scene.rect(rect, to_device, &paint, None);
+        self.draw(indices, Some(image));
+    }
+
+    /// Forget everything that has been drawn, keeping the active clips, as
+    /// when a frame has been finished.
+    pub fn restart(&mut self) {
+        self.scene.commands.clear();
+        self.scene.clear_color = None;
+        for (i, indices) in self.clips.iter().enumerate() {
+            self.scene.commands.push(Command::PushClip {
+                indices: indices.clone(),
+                depth: i as u32 + 1,
+            });
+        }
+    }
+
+    /// Remove clips until there are only `depth` left.
+    fn pop_clips(&mut self, depth: u32) {
+        while self.clips.len() as u32 > depth {
+            if let Some(indices) = self.clips.pop() {
+                self.scene.commands.push(Command::PopClip {
+                    indices,
+                    depth: self.clips.len() as u32,
+                });
+            }
+        }
+    }
+}
+
+impl Brush {
+    /// The paint for this brush, for points that `to_user` maps to user
+    /// space.
+    fn to_paint(&self, to_user: Affine) -> Paint {
+        match *self {
+            Brush::Solid(color) => Paint::solid(color),
+            Brush::Linear {
+                start,
+                end,
+                ramp,
+                dither,
+            } => {
+                // map the gradient's line onto the x axis, from 0 to 1.
+                let d = end - start;
+                let len2 = d.hypot2().max(1e-12);
+                let to_ramp = Affine::new([
+                    d.x / len2,
+                    0.0,
+                    d.y / len2,
+                    0.0,
+                    -start.to_vec2().dot(d) / len2,
+                    0.0,
+                ]);
+                Paint {
+                    kind: PAINT_LINEAR | dither_flag(dither),
+                    color: [0.0, 0.0, ramp as f32, 1.0],
+                    transform: to_ramp * to_user,
+                }
+            }
+            Brush::Radial {
+                center,
+                origin_offset,
+                radius,
+                ramp,
+                dither,
+            } => {
+                // map the gradient's circle to the unit circle.
+                let radius = radius.max(1e-6);
+                let to_unit = Affine::scale(1.0 / radius) * Affine::translate(-center.to_vec2());
+                let focus = origin_offset / radius;
+                Paint {
+                    kind: PAINT_RADIAL | dither_flag(dither),
+                    color: [focus.x as f32, focus.y as f32, ramp as f32, 1.0],
+                    transform: to_unit * to_user,
+                }
+            }
+        }
+    }
+}
+
+/// The flag of the paints of gradients that are dithered.
+fn dither_flag(dither: bool) -> u32 {
+    if dither {
+        DITHER_MASK
+    } else {
+        0
+    }
+}
+
+/// `color`, premultiplied, as the components of a [`Paint`].
+pub fn premul_color(color: Color) -> [f32; 4] {
+    let (r, g, b, a) = color.as_rgba();
+    [(r * a) as f32, (g * a) as f32, (b * a) as f32, a as f32]
+}
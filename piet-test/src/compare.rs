@@ -0,0 +1,227 @@
+//! Comparing snapshots, allowing for the small differences in antialiasing
+//! between backends.
+
+use crate::Snapshot;
+
+/// The side of the square windows that SSIM is computed over.
+const WINDOW: usize = 8;
+
+/// How different two snapshots may be and still match.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tolerance {
+    /// The largest difference in any channel, of premultiplied pixels, for
+    /// which two pixels are still the same.
+    pub channel: u8,
+    /// The largest fraction of the pixels that may differ.
+    pub differing_pixels: f64,
+    /// The smallest [SSIM] that the snapshots may have.
+    ///
+    /// [SSIM]: https://en.wikipedia.org/wiki/Structural_similarity
+    pub min_ssim: f64,
+}
+
+/// The result of comparing two snapshots of the same size.
+#[derive(Clone, Debug)]
+pub struct Comparison {
+    /// Whether the snapshots match, within the tolerance.
+    pub passed: bool,
+    /// The mean structural similarity of the snapshots, from 1.0 for
+    /// identical ones down to -1.0.
+    pub ssim: f64,
+    /// The number of pixels that differ by more than the tolerance.
+    pub differing_pixels: usize,
+    /// The largest difference in any channel.
+    pub max_difference: u8,
+    /// An image of the differences, where pixels are brighter the more they
+    /// differ, and black where they are the same.
+    pub diff: Snapshot,
+}
+
+impl Tolerance {
+    /// Snapshots only match if they are identical.
+    pub const EXACT: Tolerance = Tolerance {
+        channel: 0,
+        differing_pixels: 0.0,
+        min_ssim: 1.0,
+    };
+}
+
+impl Default for Tolerance {
+    /// Allows the slight differences in the antialiasing of edges that are
+    /// expected between backends.
+    fn default() -> Self {
+        Tolerance {
+            channel: 16,
+            differing_pixels: 0.01,
+            min_ssim: 0.95,
+        }
+    }
+}
+
+impl Comparison {
+    /// The fraction of the pixels that differ by more than the tolerance.
+    pub fn differing_fraction(&self) -> f64 {
+        let count = self.diff.width() * self.diff.height();
+        if count == 0 {
+            0.0
+        } else {
+            self.differing_pixels as f64 / count as f64
+        }
+    }
+}
+
+/// Compare `actual` to `expected`, or return `None` if their sizes differ.
+pub fn compare(
+    expected: &Snapshot,
+    actual: &Snapshot,
+    tolerance: &Tolerance,
+) -> Option<Comparison> {
+    if (expected.width(), expected.height()) != (actual.width(), actual.height()) {
+        return None;
+    }
+    let one = premultiplied(expected);
+    let two = premultiplied(actual);
+
+    let mut differing_pixels = 0;
+    let mut max_difference = 0;
+    let mut diff = Vec::with_capacity(one.len());
+    for (p1, p2) in one.chunks_exact(4).zip(two.chunks_exact(4)) {
+        let largest = p1
+            .iter()
+            .zip(p2)
+            .map(|(a, b)| (*a as i16 - *b as i16).unsigned_abs() as u8)
+            .max()
+            .unwrap_or(0);
+        max_difference = max_difference.max(largest);
+        if largest > tolerance.channel {
+            differing_pixels += 1;
+        }
+        // any difference is made visible.
+        let shade = if largest > 0 { largest.max(24) } else { 0 };
+        diff.extend_from_slice(&[shade, shade, shade, 255]);
+    }
+
+    let ssim = ssim(&one, &two, expected.width(), expected.height());
+    let diff = Snapshot::from_rgba(expected.width(), expected.height(), diff);
+    let mut comparison = Comparison {
+        passed: false,
+        ssim,
+        differing_pixels,
+        max_difference,
+        diff,
+    };
+    comparison.passed = comparison.differing_fraction() <= tolerance.differing_pixels
+        && comparison.ssim >= tolerance.min_ssim;
+    Some(comparison)
+}
+
+/// The pixels of `snapshot`, premultiplied, so that the colors of
+/// transparent pixels don't matter.
+fn premultiplied(snapshot: &Snapshot) -> Vec<u8> {
+    let mut pixels = snapshot.pixels().to_vec();
+    piet::util::premultiply_rgba(&mut pixels);
+    pixels
+}
+
+/// The mean SSIM of the channels of two images of premultiplied RGBA pixels,
+/// over non-overlapping windows.
+fn ssim(one: &[u8], two: &[u8], width: usize, height: usize) -> f64 {
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+    let mut total = 0.0;
+    let mut windows = 0;
+    for y0 in (0..height).step_by(WINDOW) {
+        for x0 in (0..width).step_by(WINDOW) {
+            let y1 = (y0 + WINDOW).min(height);
+            let x1 = (x0 + WINDOW).min(width);
+            let n = ((y1 - y0) * (x1 - x0)) as f64;
+            for channel in 0..4 {
+                let values = || {
+                    (y0..y1).flat_map(move |y| {
+                        (x0..x1).map(move |x| {
+                            let i = (y * width + x) * 4 + channel;
+                            (one[i] as f64, two[i] as f64)
+                        })
+                    })
+                };
+                let (sum_a, sum_b) =
+                    values().fold((0.0, 0.0), |sum, (a, b)| (sum.0 + a, sum.1 + b));
+                let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+                let (mut var_a, mut var_b, mut cov) = (0.0, 0.0, 0.0);
+                for (a, b) in values() {
+                    var_a += (a - mean_a) * (a - mean_a);
+                    var_b += (b - mean_b) * (b - mean_b);
+                    cov += (a - mean_a) * (b - mean_b);
+                }
+                let (var_a, var_b, cov) = (var_a / n, var_b / n, cov / n);
+                total += ((2.0 * mean_a * mean_b + C1) * (2.0 * cov + C2))
+                    / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+                windows += 1;
+            }
+        }
+    }
+    if windows == 0 {
+        1.0
+    } else {
+        total / windows as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkerboard(size: usize, dark: u8) -> Snapshot {
+        let pixels = (0..size * size)
+            .flat_map(|i| {
+                let v = if (i % size + i / size).is_multiple_of(2) {
+                    dark
+                } else {
+                    255
+                };
+                [v, v, v, 255]
+            })
+            .collect();
+        Snapshot::from_rgba(size, size, pixels)
+    }
+
+    #[test]
+    fn identical_snapshots_match_exactly() {
+        let one = checkerboard(10, 0);
+        let comparison = compare(&one, &one.clone(), &Tolerance::EXACT).unwrap();
+        assert!(comparison.passed);
+        assert_eq!(comparison.ssim, 1.0);
+        assert_eq!(comparison.max_difference, 0);
+        assert!(comparison
+            .diff
+            .pixels()
+            .chunks(4)
+            .all(|p| p == [0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn small_differences_are_tolerated() {
+        let one = checkerboard(16, 0);
+        let two = checkerboard(16, 8);
+        let comparison = compare(&one, &two, &Tolerance::default()).unwrap();
+        assert!(comparison.passed, "{:?}", comparison.ssim);
+        assert_eq!(comparison.differing_pixels, 0);
+        assert!(!compare(&one, &two, &Tolerance::EXACT).unwrap().passed);
+
+        let three = checkerboard(16, 128);
+        let comparison = compare(&one, &three, &Tolerance::default()).unwrap();
+        assert!(!comparison.passed);
+        assert_eq!(comparison.differing_pixels, 128);
+        assert!(comparison.ssim < 0.95);
+
+        assert!(compare(&one, &checkerboard(8, 0), &Tolerance::default()).is_none());
+    }
+
+    #[test]
+    fn transparent_colors_are_ignored() {
+        let one = Snapshot::from_rgba(1, 1, vec![255, 0, 0, 0]);
+        let two = Snapshot::from_rgba(1, 1, vec![0, 0, 255, 0]);
+        assert!(compare(&one, &two, &Tolerance::EXACT).unwrap().passed);
+    }
+}
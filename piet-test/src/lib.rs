@@ -0,0 +1,218 @@
+//! Snapshot tests of piet backends.
+//!
+//! This crate draws piet's [sample pictures] with any backend, and compares
+//! the results to reference images, such as the snapshots of another backend
+//! or of an earlier version. It is how piet's own backends are checked, and
+//! backends outside of piet can use it to run the same suite.
+//!
+//! A backend implements [`Backend`], drawing a sample into a bitmap and
+//! returning its pixels. A [`Suite`] then draws each sample, compares it to
+//! the reference image with the same number and scale, and returns a
+//! [`Report`]:
+//!
+//! ```no_run
+//! # use piet_test::{Backend, BoxErr, Snapshot, Suite, Tolerance};
+//! # struct MyBackend;
+//! # impl Backend for MyBackend {
+//! #     fn name(&self) -> &str { "my-backend" }
+//! #     fn render(&mut self, sample: usize, scale: f64) -> Result<Snapshot, BoxErr> { todo!() }
+//! # }
+//! let report = Suite::new("piet/snapshots/cairo")
+//!     .tolerance(Tolerance::default())
+//!     .out_dir("target/snapshots")
+//!     .run(&mut MyBackend)?;
+//! println!("{}", report);
+//! assert!(report.passed());
+//! # Ok::<(), BoxErr>(())
+//! ```
+//!
+//! Backends antialias edges slightly differently, so snapshots are compared
+//! with a [`Tolerance`], by their [structural similarity] and the number of
+//! pixels that differ.
+//!
+//! [sample pictures]: piet::samples
+//! [structural similarity]: https://en.wikipedia.org/wiki/Structural_similarity
+
+#![deny(clippy::trivially_copy_pass_by_ref)]
+
+mod compare;
+mod report;
+mod snapshot;
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use piet::samples;
+
+pub use crate::compare::{compare, Comparison, Tolerance};
+pub use crate::report::{Outcome, Report, SampleResult};
+pub use crate::snapshot::Snapshot;
+
+/// The error type of backends and suites.
+pub type BoxErr = Box<dyn std::error::Error>;
+
+/// A backend that can draw the samples.
+pub trait Backend {
+    /// The name of the backend, such as `"cairo-test"`, which is used for the
+    /// names of the files it writes.
+    fn name(&self) -> &str;
+
+    /// Draw the sample numbered `sample`, as returned by
+    /// [`piet::samples::get`], into a new bitmap at `scale`, and return its
+    /// pixels.
+    ///
+    /// The bitmap is the [size] of the sample times `scale`, and the render
+    /// context should be scaled by `scale`.
+    ///
+    /// [size]: piet::samples::SamplePicture::size
+    fn render(&mut self, sample: usize, scale: f64) -> Result<Snapshot, BoxErr>;
+}
+
+/// The samples to draw, and the reference images to compare them to.
+#[derive(Clone, Debug)]
+pub struct Suite {
+    reference_dir: PathBuf,
+    out_dir: Option<PathBuf>,
+    samples: Vec<usize>,
+    scale: f64,
+    tolerance: Tolerance,
+}
+
+impl Suite {
+    /// Create a new suite of all of the samples, at the default scale,
+    /// compared to the images in `reference_dir`.
+    ///
+    /// A reference image is found by its number and scale, which end its
+    /// name, as in `cairo-test-05-2.00.png`; this is how images are named by
+    /// the `test-picture` examples of piet's backends.
+    pub fn new(reference_dir: impl Into<PathBuf>) -> Suite {
+        Suite {
+            reference_dir: reference_dir.into(),
+            out_dir: None,
+            samples: (0..samples::SAMPLE_COUNT).collect(),
+            scale: samples::DEFAULT_SCALE,
+            tolerance: Tolerance::default(),
+        }
+    }
+
+    /// Builder-style method to set the samples to draw.
+    pub fn samples(mut self, samples: impl IntoIterator<Item = usize>) -> Self {
+        self.samples = samples.into_iter().collect();
+        self
+    }
+
+    /// Builder-style method to set the scale the samples are drawn at.
+    pub fn scale(mut self, scale: f64) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    /// Builder-style method to set how different the drawings may be from
+    /// the reference images.
+    pub fn tolerance(mut self, tolerance: Tolerance) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Builder-style method to set a directory that the drawings, images of
+    /// their differences from the reference images, and the report in
+    /// `report.md`, are written to.
+    pub fn out_dir(mut self, out_dir: impl Into<PathBuf>) -> Self {
+        self.out_dir = Some(out_dir.into());
+        self
+    }
+
+    /// Draw each sample with `backend`, and compare it to its reference
+    /// image.
+    ///
+    /// Failures of the backend are recorded in the report; this only returns
+    /// an error if the reference images or the output can't be read or
+    /// written.
+    pub fn run(&self, backend: &mut impl Backend) -> Result<Report, BoxErr> {
+        let references = reference_files(&self.reference_dir, self.scale)?;
+        if let Some(out_dir) = &self.out_dir {
+            std::fs::create_dir_all(out_dir)?;
+        }
+
+        let mut results = Vec::new();
+        for &sample in &self.samples {
+            let outcome = match backend.render(sample, self.scale) {
+                Ok(snapshot) => {
+                    let outcome = match references.get(&sample) {
+                        Some(path) => self.compare(&Snapshot::load(path)?, &snapshot),
+                        None => Outcome::MissingReference,
+                    };
+                    self.write(backend.name(), sample, &snapshot, &outcome)?;
+                    outcome
+                }
+                Err(err) => Outcome::Error(err.to_string()),
+            };
+            results.push(SampleResult { sample, outcome });
+        }
+
+        let report = Report {
+            backend: backend.name().to_owned(),
+            scale: self.scale,
+            results,
+        };
+        if let Some(out_dir) = &self.out_dir {
+            std::fs::write(out_dir.join("report.md"), report.to_string())?;
+        }
+        Ok(report)
+    }
+
+    fn compare(&self, expected: &Snapshot, actual: &Snapshot) -> Outcome {
+        match compare(expected, actual, &self.tolerance) {
+            Some(comparison) => Outcome::Compared(comparison),
+            None => Outcome::WrongSize {
+                expected: (expected.width(), expected.height()),
+                actual: (actual.width(), actual.height()),
+            },
+        }
+    }
+
+    /// Write a drawing, and the image of its differences if it failed, to
+    /// the output directory.
+    fn write(
+        &self,
+        name: &str,
+        sample: usize,
+        snapshot: &Snapshot,
+        outcome: &Outcome,
+    ) -> Result<(), BoxErr> {
+        let out_dir = match &self.out_dir {
+            Some(out_dir) => out_dir,
+            None => return Ok(()),
+        };
+        let stem = format!("{}-{:0>2}-{:.2}", name, sample, self.scale);
+        snapshot.save(out_dir.join(format!("{}.png", stem)))?;
+        if let Outcome::Compared(comparison) = outcome {
+            if !comparison.passed {
+                comparison
+                    .diff
+                    .save(out_dir.join(format!("{}-diff.png", stem)))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The reference images in `dir` at `scale`, by their sample numbers.
+fn reference_files(dir: &Path, scale: f64) -> Result<BTreeMap<usize, PathBuf>, BoxErr> {
+    let suffix = format!("-{:.2}", scale);
+    let mut files = BTreeMap::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension() != Some("png".as_ref()) {
+            continue;
+        }
+        let number = path
+            .file_stem()
+            .and_then(|stem| stem.to_str()?.strip_suffix(&suffix))
+            .and_then(|stem| stem.rsplit('-').next()?.parse().ok());
+        if let Some(number) = number {
+            files.insert(number, path);
+        }
+    }
+    Ok(files)
+}
@@ -0,0 +1,103 @@
+//! The results of running the samples against a backend.
+
+use std::fmt;
+
+use crate::Comparison;
+
+/// The results of a [`Suite`](crate::Suite) run.
+#[derive(Clone, Debug)]
+pub struct Report {
+    /// The name of the backend that was tested.
+    pub backend: String,
+    /// The scale the samples were drawn at.
+    pub scale: f64,
+    /// The result of each sample, in order.
+    pub results: Vec<SampleResult>,
+}
+
+/// The result of drawing one sample.
+#[derive(Clone, Debug)]
+pub struct SampleResult {
+    /// The number of the sample, as passed to [`piet::samples::get`].
+    pub sample: usize,
+    /// How the drawing compared to the reference image.
+    pub outcome: Outcome,
+}
+
+/// How a sample compared to its reference image.
+#[derive(Clone, Debug)]
+pub enum Outcome {
+    /// The sample was drawn, and compared to the reference image.
+    Compared(Comparison),
+    /// The sample was drawn at a different size than the reference image.
+    WrongSize {
+        /// The size of the reference image, in pixels.
+        expected: (usize, usize),
+        /// The size of the drawing, in pixels.
+        actual: (usize, usize),
+    },
+    /// There is no reference image for the sample.
+    MissingReference,
+    /// The backend failed to draw the sample.
+    Error(String),
+}
+
+impl Report {
+    /// Returns `true` if every sample matched its reference image.
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(SampleResult::passed)
+    }
+
+    /// The samples that didn't match their reference images.
+    pub fn failures(&self) -> impl Iterator<Item = &SampleResult> {
+        self.results.iter().filter(|result| !result.passed())
+    }
+}
+
+impl SampleResult {
+    /// Returns `true` if the sample matched its reference image.
+    pub fn passed(&self) -> bool {
+        matches!(&self.outcome, Outcome::Compared(comparison) if comparison.passed)
+    }
+}
+
+/// A Markdown table of the results.
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "# {} at scale {:.2}", self.backend, self.scale)?;
+        writeln!(f)?;
+        let passed = self.results.iter().filter(|r| r.passed()).count();
+        writeln!(f, "{} of {} samples passed.", passed, self.results.len())?;
+        writeln!(f)?;
+        writeln!(
+            f,
+            "| Sample | Result | SSIM | Differing pixels | Max difference |"
+        )?;
+        writeln!(
+            f,
+            "|-------:|--------|-----:|-----------------:|---------------:|"
+        )?;
+        for result in &self.results {
+            write!(f, "| {:02} ", result.sample)?;
+            match &result.outcome {
+                Outcome::Compared(c) => writeln!(
+                    f,
+                    "| {} | {:.4} | {} ({:.2}%) | {} |",
+                    if c.passed { "ok" } else { "FAILED" },
+                    c.ssim,
+                    c.differing_pixels,
+                    c.differing_fraction() * 100.0,
+                    c.max_difference,
+                )?,
+                Outcome::WrongSize { expected, actual } => writeln!(
+                    f,
+                    "| FAILED: {}x{}, expected {}x{} | | | |",
+                    actual.0, actual.1, expected.0, expected.1
+                )?,
+                Outcome::MissingReference => writeln!(f, "| no reference image | | | |")?,
+                Outcome::Error(err) => writeln!(f, "| FAILED: {} | | | |", err)?,
+            }
+        }
+        Ok(())
+    }
+}
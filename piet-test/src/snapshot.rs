@@ -0,0 +1,103 @@
+//! Images rendered by a backend, or loaded from reference files.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use piet::ImageBuf;
+
+use crate::BoxErr;
+
+/// An image with 8 bits per channel RGBA pixels, with separate alpha.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Snapshot {
+    /// Create a snapshot from RGBA pixels with separate alpha.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there aren't `width * height * 4` bytes of pixels.
+    pub fn from_rgba(width: usize, height: usize, pixels: Vec<u8>) -> Snapshot {
+        assert_eq!(pixels.len(), width * height * 4);
+        Snapshot {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Create a snapshot from the pixels of an [`ImageBuf`], in any format.
+    pub fn from_image_buf(buf: &ImageBuf) -> Snapshot {
+        let pixels = buf
+            .pixel_colors()
+            .flatten()
+            .flat_map(|color| {
+                let (r, g, b, a) = color.as_rgba8();
+                [r, g, b, a]
+            })
+            .collect();
+        Snapshot::from_rgba(buf.width(), buf.height(), pixels)
+    }
+
+    /// Load a snapshot from a PNG file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Snapshot, BoxErr> {
+        let mut decoder = png::Decoder::new(File::open(path)?);
+        // expand palettes and strip 16 bit channels, so that there are only
+        // the color types below.
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info()?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf)?;
+        buf.truncate(info.buffer_size());
+        let pixels = match info.color_type {
+            png::ColorType::Rgba => buf,
+            png::ColorType::Rgb => buf
+                .chunks_exact(3)
+                .flat_map(|p| [p[0], p[1], p[2], 255])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => buf
+                .chunks_exact(2)
+                .flat_map(|p| [p[0], p[0], p[0], p[1]])
+                .collect(),
+            png::ColorType::Grayscale => buf.iter().flat_map(|&p| [p, p, p, 255]).collect(),
+            png::ColorType::Indexed => return Err("indexed PNGs should have been expanded".into()),
+        };
+        Ok(Snapshot::from_rgba(
+            info.width as usize,
+            info.height as usize,
+            pixels,
+        ))
+    }
+
+    /// Save the snapshot as a PNG file.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), BoxErr> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.pixels)?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    /// The width of the image, in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// The height of the image, in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The RGBA pixels, with separate alpha.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+}
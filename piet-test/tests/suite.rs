@@ -0,0 +1,95 @@
+use piet::samples;
+use piet::{ImageBuf, RenderContext};
+use piet_test::{Backend, BoxErr, Outcome, Snapshot, Suite, Tolerance};
+use piet_tiny_skia::tiny_skia::Pixmap;
+use piet_tiny_skia::TinySkiaRenderContext;
+
+struct TinySkia;
+
+impl Backend for TinySkia {
+    fn name(&self) -> &str {
+        "tiny-skia-test"
+    }
+
+    fn render(&mut self, sample: usize, scale: f64) -> Result<Snapshot, BoxErr> {
+        let sample = samples::get(sample)?;
+        let size = sample.size() * scale;
+        let mut pixmap =
+            Pixmap::new(size.width as u32, size.height as u32).ok_or("empty sample")?;
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), scale);
+        sample.draw(&mut rc)?;
+        rc.finish()?;
+        std::mem::drop(rc);
+        let buf = ImageBuf::from_raw(
+            pixmap.data(),
+            piet::ImageFormat::RgbaPremul,
+            pixmap.width() as usize,
+            pixmap.height() as usize,
+        );
+        Ok(Snapshot::from_image_buf(&buf))
+    }
+}
+
+/// A backend that draws one sample with another's drawing.
+struct Swapped(TinySkia);
+
+impl Backend for Swapped {
+    fn name(&self) -> &str {
+        "swapped"
+    }
+
+    fn render(&mut self, sample: usize, scale: f64) -> Result<Snapshot, BoxErr> {
+        match sample {
+            0 => Err("can't draw".into()),
+            1 => self.0.render(2, scale),
+            _ => self.0.render(sample, scale),
+        }
+    }
+}
+
+#[test]
+fn backends_are_compared_to_references() {
+    let dir = std::env::temp_dir().join(format!("piet-test-{}", std::process::id()));
+    let references = dir.join("references");
+    let out = dir.join("out");
+
+    // a missing reference directory is an error.
+    assert!(Suite::new(&out).run(&mut TinySkia).is_err());
+
+    // the first run has nothing to compare to, but makes the references.
+    std::fs::create_dir_all(&out).unwrap();
+    let report = Suite::new(&out)
+        .samples(0..4)
+        .scale(1.0)
+        .out_dir(&references)
+        .run(&mut TinySkia)
+        .unwrap();
+    assert!(report
+        .results
+        .iter()
+        .all(|r| matches!(r.outcome, Outcome::MissingReference)));
+
+    let report = Suite::new(&references)
+        .samples(0..4)
+        .scale(1.0)
+        .tolerance(Tolerance::EXACT)
+        .out_dir(&out)
+        .run(&mut TinySkia)
+        .unwrap();
+    assert!(report.passed(), "{}", report);
+
+    let report = Suite::new(&references)
+        .samples(0..4)
+        .scale(1.0)
+        .out_dir(&out)
+        .run(&mut Swapped(TinySkia))
+        .unwrap();
+    assert!(!report.passed());
+    let failures: Vec<_> = report.failures().map(|r| r.sample).collect();
+    assert_eq!(failures, [0, 1]);
+    assert!(matches!(report.results[0].outcome, Outcome::Error(_)));
+    assert!(out.join("swapped-01-1.00-diff.png").exists());
+    assert!(out.join("report.md").exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,33 @@
+//! Basic example of rendering on tiny-skia.
+
+use std::path::Path;
+
+use piet::{samples, RenderContext};
+use piet_tiny_skia::tiny_skia::Pixmap;
+use piet_tiny_skia::TinySkiaRenderContext;
+
+const FILE_PREFIX: &str = "tiny-skia-test";
+
+fn main() {
+    samples::samples_main(run_sample, FILE_PREFIX, None);
+}
+
+fn run_sample(
+    number: usize,
+    scale: f64,
+    save_path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let sample = samples::get(number)?;
+    let size = sample.size() * scale;
+
+    let mut pixmap =
+        Pixmap::new(size.width as u32, size.height as u32).ok_or("sample has an empty size")?;
+    let mut piet_context = TinySkiaRenderContext::new(pixmap.as_mut(), scale);
+
+    sample.draw(&mut piet_context)?;
+
+    piet_context.finish()?;
+    std::mem::drop(piet_context);
+
+    pixmap.save_png(save_path).map_err(Into::into)
+}
@@ -0,0 +1,272 @@
+//! Cutting paths down to a rectangle.
+//!
+//! tiny-skia draws in `f32`, and its rasterizer loses precision on paths
+//! that reach far outside the pixmap, until it panics on ones around 10⁸
+//! pixels across. Such paths are cut down to a rectangle around the pixmap
+//! first, in `f64`.
+
+use std::mem;
+
+use piet::kurbo::{
+    self, BezPath, CubicBez, ParamCurve, ParamCurveExtrema, PathEl, PathSeg, Point, QuadBez, Rect,
+};
+
+/// The tolerance that curves inside the rectangle are flattened with, in
+/// pixels.
+const TOLERANCE: f64 = 0.1;
+
+/// The number of times a curve is split while looking for its parts inside
+/// the rectangle, which is enough to go from `f64::MAX` down to a pixel.
+const MAX_DEPTH: u32 = 1100;
+
+/// The parts of `path` that are inside `clip`, as lines.
+///
+/// If `fill` is true, each subpath is cut as the area that it encloses,
+/// following the edges of `clip` where it goes outside, so that it covers the
+/// same points inside `clip` as before. Otherwise the subpaths are lines that
+/// are stroked, and they are cut into the pieces that are inside `clip`.
+pub(crate) fn clip_path(path: &BezPath, clip: Rect, fill: bool) -> BezPath {
+    let mut clipped = BezPath::new();
+    for (points, closed) in polylines(path, clip) {
+        if fill {
+            add_polygon(&mut clipped, &clip_polygon(points, clip));
+        } else {
+            clip_polyline(&mut clipped, points, closed, clip);
+        }
+    }
+    clipped
+}
+
+/// The subpaths of `path` as lines, and whether they are closed.
+fn polylines(path: &BezPath, clip: Rect) -> Vec<(Vec<Point>, bool)> {
+    let mut polylines = Vec::new();
+    let mut points = Vec::new();
+    let mut start = Point::ZERO;
+    for el in path.elements() {
+        let last = points.last().copied().unwrap_or(start);
+        match *el {
+            PathEl::MoveTo(p) => {
+                if points.len() > 1 {
+                    polylines.push((mem::take(&mut points), false));
+                }
+                points.clear();
+                points.push(p);
+                start = p;
+            }
+            PathEl::LineTo(p) => points.push(p),
+            PathEl::QuadTo(p1, p2) => {
+                add_curve(
+                    PathSeg::Quad(QuadBez::new(last, p1, p2)),
+                    clip,
+                    0,
+                    &mut points,
+                );
+            }
+            PathEl::CurveTo(p1, p2, p3) => add_curve(
+                PathSeg::Cubic(CubicBez::new(last, p1, p2, p3)),
+                clip,
+                0,
+                &mut points,
+            ),
+            PathEl::ClosePath => {
+                if points.len() > 1 {
+                    polylines.push((mem::take(&mut points), true));
+                }
+                // the next subpath starts where this one did, unless it
+                // begins with a `MoveTo`.
+                points.clear();
+                points.push(start);
+            }
+        }
+    }
+    if points.len() > 1 {
+        polylines.push((points, false));
+    }
+    polylines
+}
+
+/// Add the points of the curve `seg` after its start to `points`.
+///
+/// The parts of the curve that are inside `clip` are flattened, and the
+/// others are replaced by lines between their ends. The area between such a
+/// line and its part of the curve is inside the bounding box of that part,
+/// so this doesn't change what is inside `clip`.
+fn add_curve(seg: PathSeg, clip: Rect, depth: u32, points: &mut Vec<Point>) {
+    let bounds = seg.bounding_box();
+    if depth == MAX_DEPTH || !overlaps(bounds, clip) {
+        points.push(seg.end());
+    } else if bounds.width() <= clip.width() && bounds.height() <= clip.height() {
+        let path = [PathEl::MoveTo(seg.start()), seg.as_path_el()];
+        kurbo::flatten(path, TOLERANCE, |el| {
+            if let PathEl::LineTo(p) = el {
+                points.push(p);
+            }
+        });
+    } else {
+        let (first, second) = seg.subdivide();
+        add_curve(first, clip, depth + 1, points);
+        add_curve(second, clip, depth + 1, points);
+    }
+}
+
+/// The parts of the polygon `points` that are inside `clip`, by the
+/// Sutherland-Hodgman algorithm.
+fn clip_polygon(mut points: Vec<Point>, clip: Rect) -> Vec<Point> {
+    for edge in Edge::ALL {
+        let mut clipped = Vec::with_capacity(points.len());
+        let mut prev = match points.last() {
+            Some(&prev) => prev,
+            None => break,
+        };
+        for &p in &points {
+            match (edge.inside(prev, clip), edge.inside(p, clip)) {
+                (true, true) => clipped.push(p),
+                (true, false) => clipped.push(edge.intersect(prev, p, clip)),
+                (false, true) => {
+                    clipped.push(edge.intersect(prev, p, clip));
+                    clipped.push(p);
+                }
+                (false, false) => (),
+            }
+            prev = p;
+        }
+        points = clipped;
+    }
+    points
+}
+
+/// Add the pieces of the polyline `points` that are inside `clip`.
+fn clip_polyline(clipped: &mut BezPath, mut points: Vec<Point>, closed: bool, clip: Rect) {
+    let outside = points.iter().position(|&p| !Edge::contains(clip, p));
+    let outside = match outside {
+        Some(outside) => outside,
+        None => {
+            // kept as it is, so that a closed polyline is still joined where
+            // it starts.
+            add_polyline(clipped, &points, closed);
+            return;
+        }
+    };
+    if closed {
+        // start outside, so that the pieces are only cut where they leave
+        // `clip`, and are joined everywhere inside it.
+        points.rotate_left(outside);
+        points.push(points[0]);
+    }
+    // whether the last piece ends at the start of the next line.
+    let mut joined = false;
+    for line in points.windows(2) {
+        match clip_line(line[0], line[1], clip) {
+            Some((p0, p1)) => {
+                if !joined || p0 != line[0] {
+                    clipped.move_to(p0);
+                }
+                clipped.line_to(p1);
+                joined = p1 == line[1];
+            }
+            None => joined = false,
+        }
+    }
+}
+
+/// The part of the line from `p0` to `p1` that is inside `clip`.
+fn clip_line(mut p0: Point, mut p1: Point, clip: Rect) -> Option<(Point, Point)> {
+    for edge in Edge::ALL {
+        match (edge.inside(p0, clip), edge.inside(p1, clip)) {
+            (true, true) => (),
+            (true, false) => p1 = edge.intersect(p0, p1, clip),
+            (false, true) => p0 = edge.intersect(p0, p1, clip),
+            (false, false) => return None,
+        }
+    }
+    Some((p0, p1))
+}
+
+fn add_polygon(path: &mut BezPath, points: &[Point]) {
+    add_polyline(path, points, true);
+}
+
+fn add_polyline(path: &mut BezPath, points: &[Point], closed: bool) {
+    if let Some((&first, rest)) = points.split_first() {
+        path.move_to(first);
+        for &p in rest {
+            path.line_to(p);
+        }
+        if closed {
+            path.close_path();
+        }
+    }
+}
+
+fn overlaps(a: Rect, b: Rect) -> bool {
+    a.x0 <= b.x1 && a.x1 >= b.x0 && a.y0 <= b.y1 && a.y1 >= b.y0
+}
+
+/// An edge of the rectangle that is clipped to.
+#[derive(Clone, Copy)]
+enum Edge {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+impl Edge {
+    const ALL: [Edge; 4] = [Edge::Left, Edge::Right, Edge::Top, Edge::Bottom];
+
+    fn contains(clip: Rect, p: Point) -> bool {
+        Edge::ALL.iter().all(|edge| edge.inside(p, clip))
+    }
+
+    /// Whether `p` is on the inside of this edge of `clip`.
+    fn inside(self, p: Point, clip: Rect) -> bool {
+        match self {
+            Edge::Left => p.x >= clip.x0,
+            Edge::Right => p.x <= clip.x1,
+            Edge::Top => p.y >= clip.y0,
+            Edge::Bottom => p.y <= clip.y1,
+        }
+    }
+
+    /// Where the line from `p0` to `p1`, which crosses this edge of `clip`,
+    /// meets it.
+    fn intersect(self, p0: Point, p1: Point, clip: Rect) -> Point {
+        let (value, from, to) = match self {
+            Edge::Left => (clip.x0, p0.x, p1.x),
+            Edge::Right => (clip.x1, p0.x, p1.x),
+            Edge::Top => (clip.y0, p0.y, p1.y),
+            Edge::Bottom => (clip.y1, p0.y, p1.y),
+        };
+        let mut p = p0.lerp(p1, (value - from) / (to - from));
+        // exactly on the edge, whatever the rounding.
+        match self {
+            Edge::Left | Edge::Right => p.x = value,
+            Edge::Top | Edge::Bottom => p.y = value,
+        }
+        p
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use piet::kurbo::{Circle, Shape};
+
+    #[test]
+    fn areas_are_clipped_and_lines_are_cut() {
+        let clip = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let huge = Rect::new(-1e30, 5.0, 1e30, 1e30).to_path(0.1);
+        let area = clip_path(&huge, clip, true);
+        assert_eq!(area.bounding_box(), Rect::new(0.0, 5.0, 10.0, 10.0));
+        assert_eq!(area.area().abs(), 50.0);
+
+        let outline = clip_path(&huge, clip, false);
+        assert_eq!(outline.bounding_box(), Rect::new(0.0, 5.0, 10.0, 5.0));
+
+        // only the part of the circle near `clip` is flattened.
+        let circle = Circle::new((5.0, -1e12), 1e12 + 5.0).to_path(0.1);
+        let area = clip_path(&circle, clip, true);
+        assert!(area.elements().len() < 100);
+        assert!((area.area().abs() - 50.0).abs() < 0.5);
+    }
+}
@@ -0,0 +1,1827 @@
+//! A pure-Rust CPU backend for the Piet 2D graphics abstraction, built on
+//! [tiny-skia].
+//!
+//! This backend doesn't need any system libraries, which makes it useful for
+//! headless rendering and for testing. It doesn't draw text yet: its text
+//! factory is piet's `NullText`, whose layouts are empty.
+//!
+//! [tiny-skia]: https://crates.io/crates/tiny-skia
+
+#![deny(clippy::trivially_copy_pass_by_ref)]
+
+mod clip;
+
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use tiny_skia::{
+    BlendMode, FillRule, FilterQuality, IntRect, IntSize, LinearGradient, Mask, MaskType, Paint,
+    Path, PathBuilder, PathSegment, PathStroker, Pattern, Pixmap, PixmapMut, PixmapPaint,
+    RadialGradient, Shader, SpreadMode, Stroke, Transform,
+};
+
+use piet::kurbo::{Affine, BezPath, PathEl, Point, Rect, Shape, Size, Vec2};
+use piet::{
+    Capabilities, Color, Error, FixedGradient, GradientOptions, GradientStop, HitTester, Image,
+    ImageFilter, ImageFormat, InterpolationMode, IntoBrush, LineCap, LineJoin, NullText,
+    NullTextLayout, RenderContext, StrokeStyle, TextShapingQuality, DEFAULT_TOLERANCE,
+};
+
+pub use tiny_skia;
+
+/// How far, in pixels, what is drawn can reach outside the pixmap before the
+/// parts of it that can't be seen are cut away. tiny-skia draws in `f32`,
+/// and loses precision on coordinates that are much further out.
+const MAX_OVERHANG: f64 = 1e5;
+
+pub struct TinySkiaRenderContext<'a> {
+    pixmap: PixmapMut<'a>,
+    text: NullText,
+    // the transform from piet's coordinates to pixels, which is not part of
+    // the transform reported by `current_transform`.
+    base_transform: Affine,
+    state: State,
+    saved: Vec<State>,
+    /// The opacity masks that are being drawn through, innermost last.
+    opacity_masks: Vec<OpacityMask>,
+    hit_tester: HitTester,
+}
+
+/// An opacity mask that is being drawn through. What is drawn through it is
+/// drawn into the pixmap, which is cleared when the mask is begun, and then
+/// composited over the pixels that were there before.
+struct OpacityMask {
+    /// The pixels of the pixmap when the mask was begun.
+    below: Vec<u8>,
+    /// The alpha that what is drawn through the mask is multiplied by.
+    coverage: Mask,
+    /// The number of saved states when the mask was begun, including the
+    /// one that it saved.
+    depth: usize,
+}
+
+#[derive(Clone)]
+struct State {
+    transform: Affine,
+    // shared between saved states, and only copied when a clip is added.
+    clip: Option<Arc<Mask>>,
+    global_alpha: f32,
+    blend_mode: piet::BlendMode,
+    /// The tolerance that shapes are converted to paths with.
+    tolerance: f64,
+}
+
+#[derive(Clone)]
+pub struct Brush {
+    shader: Shader<'static>,
+    /// The gradient, if it is dithered, which is drawn into a pixmap each
+    /// time the brush is used.
+    dithered: Option<Arc<FixedGradient>>,
+}
+
+#[derive(Clone)]
+pub struct TinySkiaImage {
+    pixmap: Option<Arc<Pixmap>>,
+    /// The area of the pixmap that is the image, if it is a sub-image.
+    view: Option<Rect>,
+}
+
+impl Default for State {
+    fn default() -> State {
+        State {
+            transform: Affine::IDENTITY,
+            clip: None,
+            global_alpha: 1.0,
+            blend_mode: piet::BlendMode::Normal,
+            tolerance: DEFAULT_TOLERANCE,
+        }
+    }
+}
+
+impl<'a> TinySkiaRenderContext<'a> {
+    /// Create a new tiny-skia back-end, drawing into `pixmap`.
+    ///
+    /// Everything drawn is scaled by `scale`, which is the number of pixels
+    /// per display point.
+    pub fn new(pixmap: PixmapMut<'a>, scale: f64) -> TinySkiaRenderContext<'a> {
+        TinySkiaRenderContext {
+            pixmap,
+            text: NullText,
+            base_transform: Affine::scale(scale),
+            state: State::default(),
+            saved: Vec::new(),
+            opacity_masks: Vec::new(),
+            hit_tester: HitTester::new(),
+        }
+    }
+
+    /// The transform from user space to pixels.
+    fn device_transform(&self) -> Transform {
+        affine_to_transform(self.base_transform * self.state.transform)
+    }
+
+    /// The bounds, in pixels, of `rect` in user space.
+    fn device_bounds(&self, rect: Rect) -> Rect {
+        (self.base_transform * self.state.transform).transform_rect_bbox(rect)
+    }
+
+    /// The part of `path` that can be seen when it is drawn with `transform`
+    /// to pixels, or `None` if none of it can.
+    ///
+    /// `reach` is how far, in pixels, what is drawn reaches out from the
+    /// path, such as the half-width of a stroke. Paths that reach far outside
+    /// the pixmap are cut down to the area around it that can be seen: as
+    /// areas if `fill` is true, and otherwise as lines to stroke.
+    fn visible_path(&self, path: Path, transform: Affine, reach: f64, fill: bool) -> Option<Path> {
+        // leaving room for antialiasing.
+        let reach = reach + 1.0;
+        if !reach.is_finite() {
+            return Some(path);
+        }
+        let target = Rect::new(
+            0.0,
+            0.0,
+            self.pixmap.width() as f64,
+            self.pixmap.height() as f64,
+        )
+        .inflate(reach, reach);
+        let bounds = transform.transform_rect_bbox(path_bounds(&path));
+        let overlaps = bounds.x0 <= target.x1
+            && bounds.x1 >= target.x0
+            && bounds.y0 <= target.y1
+            && bounds.y1 >= target.y0;
+        if !overlaps {
+            return None;
+        }
+        let limit = target.inflate(MAX_OVERHANG, MAX_OVERHANG);
+        if bounds.union(limit) == limit {
+            return Some(path);
+        }
+        // a path that is flattened to nothing can't be seen.
+        let det = transform.determinant();
+        if det == 0.0 || !det.is_finite() {
+            return None;
+        }
+        let device: BezPath = path_elements(&path).map(|el| transform * el).collect();
+        let visible = clip::clip_path(&device, target, fill);
+        let to_path = transform.inverse();
+        elements_to_path(visible.elements().iter().map(|&el| to_path * el))
+    }
+
+    /// A paint of `brush`, with the global alpha and blend mode, for drawing
+    /// in `device_bounds`.
+    ///
+    /// Dithered gradients are drawn into `layer` over those bounds, and the
+    /// paint draws from it.
+    fn paint<'p>(
+        &self,
+        brush: &Brush,
+        device_bounds: Rect,
+        layer: &'p mut Option<Pixmap>,
+    ) -> Paint<'p> {
+        let mut paint = brush.to_paint(self.state.global_alpha);
+        paint.blend_mode = convert_blend_mode(self.state.blend_mode);
+        if let Some(gradient) = &brush.dithered {
+            if let Some((pixmap, transform)) = self.dithered_gradient(gradient, device_bounds) {
+                let pixmap = layer.insert(pixmap);
+                paint.shader = Pattern::new(
+                    pixmap.as_ref(),
+                    SpreadMode::Pad,
+                    FilterQuality::Nearest,
+                    self.state.global_alpha,
+                    transform,
+                );
+            }
+        }
+        paint
+    }
+
+    /// Draw `gradient`, dithered, into a pixmap over the pixels of
+    /// `device_bounds` that are in the target, returning the pixmap and the
+    /// transform of the pattern that puts it there.
+    fn dithered_gradient(
+        &self,
+        gradient: &FixedGradient,
+        device_bounds: Rect,
+    ) -> Option<(Pixmap, Transform)> {
+        let target = Rect::new(
+            0.0,
+            0.0,
+            self.pixmap.width() as f64,
+            self.pixmap.height() as f64,
+        );
+        let bounds = device_bounds.expand().intersect(target);
+        let device = self.base_transform * self.state.transform;
+        if bounds.is_empty() || device.determinant() == 0.0 {
+            return None;
+        }
+        let size = IntSize::from_wh(bounds.width() as u32, bounds.height() as u32)?;
+        let to_user = device.inverse();
+        let data = piet::util::dithered_gradient_pixels(
+            gradient,
+            to_user,
+            bounds.x0 as i32,
+            bounds.y0 as i32,
+            size.width() as usize,
+            size.height() as usize,
+        );
+        let pixmap = Pixmap::from_vec(data, size)?;
+        // the pattern is in user space.
+        let transform = to_user * Affine::translate(bounds.origin().to_vec2());
+        Some((pixmap, affine_to_transform(transform)))
+    }
+
+    /// The alpha of `image` stretched over `bounds`, in user space, for each
+    /// pixel.
+    fn image_coverage(&self, image: &Pixmap, bounds: tiny_skia::Rect) -> Option<Mask> {
+        let mut layer = Pixmap::new(self.pixmap.width(), self.pixmap.height())?;
+        let image_transform = Transform::from_row(
+            bounds.width() / image.width() as f32,
+            0.0,
+            0.0,
+            bounds.height() / image.height() as f32,
+            bounds.x(),
+            bounds.y(),
+        );
+        let paint = Paint {
+            shader: Pattern::new(
+                Pixmap::as_ref(image),
+                SpreadMode::Pad,
+                FilterQuality::Bilinear,
+                1.0,
+                image_transform,
+            ),
+            ..Paint::default()
+        };
+        let device = self.base_transform * self.state.transform;
+        if let Some(path) = self.visible_path(PathBuilder::from_rect(bounds), device, 0.0, true) {
+            layer.fill_path(
+                &path,
+                &paint,
+                FillRule::Winding,
+                affine_to_transform(device),
+                None,
+            );
+        }
+        Some(Mask::from_pixmap(layer.as_ref(), MaskType::Alpha))
+    }
+
+    fn fill_impl(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>, fill_rule: FillRule) {
+        let brush = brush.make_brush(self, || shape.bounding_box());
+        let device = self.base_transform * self.state.transform;
+        let path = shape_to_path(shape, self.state.tolerance)
+            .and_then(|path| self.visible_path(path, device, 0.0, true));
+        if let Some(path) = path {
+            let mut layer = None;
+            let paint = self.paint(&brush, self.device_bounds(path_bounds(&path)), &mut layer);
+            self.pixmap.fill_path(
+                &path,
+                &paint,
+                fill_rule,
+                affine_to_transform(device),
+                self.state.clip.as_deref(),
+            );
+        }
+    }
+
+    fn stroke_impl(
+        &mut self,
+        shape: impl Shape,
+        brush: &impl IntoBrush<Self>,
+        width: f64,
+        style: &StrokeStyle,
+    ) {
+        // tiny-skia asserts that widths aren't negative, or NaN.
+        if width.is_nan() || width < 0.0 {
+            return;
+        }
+        let brush = brush.make_brush(self, || shape.bounding_box());
+        let path = match shape_to_path(shape, self.state.tolerance) {
+            Some(path) => path,
+            None => return,
+        };
+        // The stroke parameters are interpreted without our transform if the
+        // stroke isn't scaled, so then we move the path and the brush there
+        // before stroking.
+        let (path, transform) = if style.scale {
+            (path, self.base_transform * self.state.transform)
+        } else {
+            match path.transform(affine_to_transform(self.state.transform)) {
+                Some(path) => (path, self.base_transform),
+                None => return,
+            }
+        };
+        let mut stroke = convert_stroke(width, style);
+        // dashed here, the way tiny-skia would, so that the dashes are cut
+        // along with the path.
+        let path = match stroke.dash.take() {
+            Some(_) if too_many_dashes(&path, style) => return,
+            Some(dash) => {
+                let res_scale =
+                    PathStroker::compute_resolution_scale(&affine_to_transform(transform));
+                match path.dash(&dash, res_scale) {
+                    Some(path) => path,
+                    None => return,
+                }
+            }
+            None => path,
+        };
+        let reach = stroke_reach(width, style, transform);
+        // a stroke that is too wide to cut its path down to the pixmap first
+        // is turned into its outline, which is cut down and filled instead.
+        let (path, outline) = if reach > MAX_OVERHANG {
+            let res_scale = PathStroker::compute_resolution_scale(&affine_to_transform(transform));
+            // tiny-skia's stroker works in `f32`, and keeps splitting curves
+            // that it can't follow as closely as asked, so it is only asked to
+            // follow them to a millionth of the stroke's size.
+            let bounds = path.bounds();
+            let size = bounds.width().max(bounds.height()).max(stroke.width);
+            let res_scale = res_scale.min(1e6 / size);
+            let outline = path.stroke(&stroke, res_scale);
+            (
+                outline.and_then(|path| self.visible_path(path, transform, 0.0, true)),
+                true,
+            )
+        } else {
+            (self.visible_path(path, transform, reach, false), false)
+        };
+        let path = match path {
+            Some(path) => path,
+            None => return,
+        };
+        // miters that reach further are padded.
+        let device_bounds = transform.transform_rect_bbox(path_bounds(&path).inflate(width, width));
+        let mut layer = None;
+        let mut paint = self.paint(&brush, device_bounds, &mut layer);
+        if !style.scale {
+            paint
+                .shader
+                .transform(affine_to_transform(self.state.transform));
+        }
+        let transform = affine_to_transform(transform);
+        let clip = self.state.clip.as_deref();
+        if outline {
+            self.pixmap
+                .fill_path(&path, &paint, FillRule::Winding, transform, clip);
+        } else {
+            self.pixmap
+                .stroke_path(&path, &paint, &stroke, transform, clip);
+        }
+    }
+
+    fn draw_image_inner(
+        &mut self,
+        image: &TinySkiaImage,
+        src_rect: Option<Rect>,
+        dst_rect: Rect,
+        interp: InterpolationMode,
+    ) {
+        let pixmap = match &image.pixmap {
+            Some(pixmap) => pixmap,
+            None => return,
+        };
+        let view = image.view();
+        let src_rect = src_rect.map_or(view, |rect| rect + view.origin().to_vec2());
+        if src_rect.is_empty() || dst_rect.is_empty() {
+            return;
+        }
+        let scale_x = dst_rect.width() / src_rect.width();
+        let scale_y = dst_rect.height() / src_rect.height();
+        let image_transform = Affine::new([
+            scale_x,
+            0.0,
+            0.0,
+            scale_y,
+            dst_rect.x0 - scale_x * src_rect.x0,
+            dst_rect.y0 - scale_y * src_rect.y0,
+        ]);
+        let quality = match interp {
+            InterpolationMode::NearestNeighbor => FilterQuality::Nearest,
+            InterpolationMode::Bilinear => FilterQuality::Bilinear,
+            InterpolationMode::Bicubic | InterpolationMode::HighQuality => FilterQuality::Bicubic,
+        };
+        let paint = Paint {
+            shader: Pattern::new(
+                Pixmap::as_ref(pixmap),
+                SpreadMode::Pad,
+                quality,
+                self.state.global_alpha,
+                affine_to_transform(image_transform),
+            ),
+            blend_mode: convert_blend_mode(self.state.blend_mode),
+            ..Paint::default()
+        };
+        if let Some(rect) = convert_rect(dst_rect) {
+            let transform = self.device_transform();
+            self.pixmap
+                .fill_rect(rect, &paint, transform, self.state.clip.as_deref());
+        }
+    }
+}
+
+impl<'a> RenderContext for TinySkiaRenderContext<'a> {
+    type Brush = Brush;
+
+    type Text = NullText;
+    type TextLayout = NullTextLayout;
+
+    type Image = TinySkiaImage;
+
+    fn status(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn clear(&mut self, region: impl Into<Option<Rect>>, color: Color) {
+        let color = convert_color(color);
+        match region.into() {
+            None => self.pixmap.fill(color),
+            // like the other backends, this ignores the current transform and clip.
+            Some(region) => {
+                if let Some(rect) = convert_rect(region) {
+                    let paint = Paint {
+                        shader: Shader::SolidColor(color),
+                        blend_mode: BlendMode::Source,
+                        anti_alias: false,
+                        ..Paint::default()
+                    };
+                    let transform = affine_to_transform(self.base_transform);
+                    self.pixmap.fill_rect(rect, &paint, transform, None);
+                }
+            }
+        }
+    }
+
+    fn solid_brush(&mut self, color: Color) -> Brush {
+        Brush {
+            shader: Shader::SolidColor(convert_color(color)),
+            dithered: None,
+        }
+    }
+
+    fn gradient(&mut self, gradient: impl Into<FixedGradient>) -> Result<Brush, Error> {
+        self.gradient_with_options(gradient, GradientOptions::default())
+    }
+
+    fn gradient_with_options(
+        &mut self,
+        gradient: impl Into<FixedGradient>,
+        options: GradientOptions,
+    ) -> Result<Brush, Error> {
+        let gradient = piet::util::interpolated_gradient(gradient.into(), options.interpolation);
+        let dithered = options.dither.then(|| Arc::new(gradient.clone()));
+        let shader = match gradient {
+            FixedGradient::Linear(linear) => LinearGradient::new(
+                convert_point(linear.start),
+                convert_point(linear.end),
+                convert_stops(&linear.stops),
+                SpreadMode::Pad,
+                Transform::identity(),
+            ),
+            FixedGradient::Radial(radial) => RadialGradient::new(
+                convert_point(radial.center + radial.origin_offset),
+                convert_point(radial.center),
+                radial.radius as f32,
+                convert_stops(&radial.stops),
+                SpreadMode::Pad,
+                Transform::identity(),
+            ),
+        };
+        let shader = shader.ok_or(Error::InvalidInput)?;
+        Ok(Brush { shader, dithered })
+    }
+
+    fn fill(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>) {
+        self.fill_impl(shape, brush, FillRule::Winding);
+    }
+
+    fn fill_even_odd(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>) {
+        self.fill_impl(shape, brush, FillRule::EvenOdd);
+    }
+
+    fn fill_with_mask(
+        &mut self,
+        shape: impl Shape,
+        brush: &impl IntoBrush<Self>,
+        mask: &Self::Image,
+    ) {
+        let brush = brush.make_brush(self, || shape.bounding_box());
+        // masks are padded, so sub-images are copied out of their pixmaps,
+        // rather than being padded with the pixels around them.
+        let (image, bounds) = match (mask.pixels(), convert_rect(shape.bounding_box())) {
+            (Some(image), Some(bounds)) => (image, bounds),
+            _ => return,
+        };
+        let device = self.base_transform * self.state.transform;
+        let path = match shape_to_path(shape, self.state.tolerance)
+            .and_then(|path| self.visible_path(path, device, 0.0, true))
+        {
+            Some(path) => path,
+            None => return,
+        };
+        let mut layer_mask = match self.image_coverage(&image, bounds) {
+            Some(layer_mask) => layer_mask,
+            None => return,
+        };
+        if let Some(clip) = &self.state.clip {
+            for (alpha, clip) in layer_mask.data_mut().iter_mut().zip(clip.data()) {
+                *alpha = piet::util::premul(*alpha, *clip);
+            }
+        }
+        let mut layer = None;
+        let paint = self.paint(&brush, self.device_bounds(path_bounds(&path)), &mut layer);
+        self.pixmap.fill_path(
+            &path,
+            &paint,
+            FillRule::Winding,
+            affine_to_transform(device),
+            Some(&layer_mask),
+        );
+    }
+
+    fn clip(&mut self, shape: impl Shape) {
+        let device = self.base_transform * self.state.transform;
+        let path = match shape_to_path(shape, self.state.tolerance)
+            .and_then(|path| self.visible_path(path, device, 0.0, true))
+        {
+            Some(path) => path,
+            // nothing is inside an empty path, or one that is out of sight.
+            None => PathBuilder::from_rect(tiny_skia::Rect::from_xywh(0., 0., 0., 0.).unwrap()),
+        };
+        let transform = affine_to_transform(device);
+        match &mut self.state.clip {
+            Some(mask) => {
+                Arc::make_mut(mask).intersect_path(&path, FillRule::Winding, true, transform)
+            }
+            None => {
+                let mut mask = match Mask::new(self.pixmap.width(), self.pixmap.height()) {
+                    Some(mask) => mask,
+                    None => return,
+                };
+                mask.fill_path(&path, FillRule::Winding, true, transform);
+                self.state.clip = Some(Arc::new(mask));
+            }
+        }
+    }
+
+    fn stroke(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>, width: f64) {
+        self.stroke_impl(shape, brush, width, &StrokeStyle::default());
+    }
+
+    fn stroke_styled(
+        &mut self,
+        shape: impl Shape,
+        brush: &impl IntoBrush<Self>,
+        width: f64,
+        style: &StrokeStyle,
+    ) {
+        if piet::util::stroke_dash_caps(self, &shape, brush, width, style) {
+            return;
+        }
+        self.stroke_impl(shape, brush, width, style);
+    }
+
+    fn text(&mut self) -> &mut Self::Text {
+        &mut self.text
+    }
+
+    fn draw_text(&mut self, _layout: &Self::TextLayout, _pos: impl Into<Point>) {}
+
+    fn save(&mut self) -> Result<(), Error> {
+        self.saved.push(self.state.clone());
+        Ok(())
+    }
+
+    fn restore(&mut self) -> Result<(), Error> {
+        // the state saved by an opacity mask is restored by ending it.
+        if self.opacity_masks.last().map(|mask| mask.depth) == Some(self.saved.len()) {
+            return Err(Error::StackUnbalance);
+        }
+        match self.saved.pop() {
+            Some(state) => {
+                self.state = state;
+                Ok(())
+            }
+            None => Err(Error::StackUnbalance),
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn transform(&mut self, transform: Affine) {
+        self.state.transform *= transform;
+    }
+
+    fn current_transform(&self) -> Affine {
+        self.state.transform
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        // there is no text yet.
+        let mut capabilities = Capabilities::default();
+        capabilities.supports_blend_modes = true;
+        capabilities.supports_blur = true;
+        capabilities.supports_global_alpha = true;
+        capabilities.text_shaping_quality = TextShapingQuality::None;
+        capabilities
+    }
+
+    fn set_global_alpha(&mut self, alpha: f64) {
+        // NaN is treated as transparent.
+        let alpha = if alpha >= 0.0 { alpha.min(1.0) } else { 0.0 };
+        self.state.global_alpha = alpha as f32;
+    }
+
+    fn global_alpha(&self) -> f64 {
+        self.state.global_alpha as f64
+    }
+
+    fn set_blend_mode(&mut self, mode: piet::BlendMode) {
+        self.state.blend_mode = mode;
+    }
+
+    fn blend_mode(&self) -> piet::BlendMode {
+        self.state.blend_mode
+    }
+
+    fn set_tolerance(&mut self, tolerance: f64) {
+        if tolerance > 0.0 && tolerance.is_finite() {
+            self.state.tolerance = tolerance;
+        }
+    }
+
+    fn tolerance(&self) -> f64 {
+        self.state.tolerance
+    }
+
+    fn begin_opacity_mask(
+        &mut self,
+        mask: &Self::Image,
+        rect: impl Into<Rect>,
+    ) -> Result<(), Error> {
+        let coverage = match (mask.pixels(), convert_rect(rect.into())) {
+            (Some(image), Some(bounds)) => self.image_coverage(&image, bounds),
+            // an empty mask hides everything.
+            _ => Mask::new(self.pixmap.width(), self.pixmap.height()),
+        }
+        .ok_or(Error::InvalidInput)?;
+        self.save()?;
+        let below = self.pixmap.data_mut();
+        let mask = OpacityMask {
+            below: below.to_vec(),
+            coverage,
+            depth: self.saved.len(),
+        };
+        below.fill(0);
+        self.opacity_masks.push(mask);
+        Ok(())
+    }
+
+    fn end_opacity_mask(&mut self) -> Result<(), Error> {
+        match self.opacity_masks.last() {
+            Some(mask) if mask.depth == self.saved.len() => (),
+            _ => return Err(Error::StackUnbalance),
+        }
+        let mask = self.opacity_masks.pop().unwrap();
+        let pixels = self.pixmap.data_mut().chunks_exact_mut(4);
+        let below = mask.below.chunks_exact(4);
+        for ((px, below), &coverage) in pixels.zip(below).zip(mask.coverage.data()) {
+            let keep = 255 - piet::util::premul(px[3], coverage);
+            for (c, &below) in px.iter_mut().zip(below) {
+                *c = piet::util::premul(*c, coverage)
+                    .saturating_add(piet::util::premul(below, keep));
+            }
+        }
+        self.restore()
+    }
+
+    fn hit_tester(&mut self) -> Option<&mut HitTester> {
+        Some(&mut self.hit_tester)
+    }
+
+    fn make_image(
+        &mut self,
+        width: usize,
+        height: usize,
+        buf: &[u8],
+        format: ImageFormat,
+    ) -> Result<Self::Image, Error> {
+        let size = match IntSize::from_wh(width as u32, height as u32) {
+            Some(size) => size,
+            // tiny-skia has no empty pixmaps.
+            None => return Ok(TinySkiaImage::new(None)),
+        };
+        let data = premultiplied_rgba(width * height, buf, format)?;
+        let pixmap = Pixmap::from_vec(data, size).ok_or(Error::InvalidInput)?;
+        Ok(TinySkiaImage::new(Some(pixmap)))
+    }
+
+    #[inline]
+    fn draw_image(
+        &mut self,
+        image: &Self::Image,
+        dst_rect: impl Into<Rect>,
+        interp: InterpolationMode,
+    ) {
+        self.draw_image_inner(image, None, dst_rect.into(), interp);
+    }
+
+    #[inline]
+    fn draw_image_area(
+        &mut self,
+        image: &Self::Image,
+        src_rect: impl Into<Rect>,
+        dst_rect: impl Into<Rect>,
+        interp: InterpolationMode,
+    ) {
+        self.draw_image_inner(image, Some(src_rect.into()), dst_rect.into(), interp);
+    }
+
+    fn draw_image_filtered(
+        &mut self,
+        image: &Self::Image,
+        dst_rect: impl Into<Rect>,
+        filters: &[ImageFilter],
+    ) {
+        let dst_rect = dst_rect.into();
+        let pixmap = match image.pixels() {
+            Some(pixmap) if !dst_rect.is_empty() => pixmap,
+            _ => return,
+        };
+        let size = image.size();
+        let scale = Vec2::new(
+            size.width / dst_rect.width(),
+            size.height / dst_rect.height(),
+        );
+        let filtered = piet::util::filter_pixels(
+            pixmap.width() as usize,
+            pixmap.height() as usize,
+            pixmap.data(),
+            filters,
+            scale,
+        );
+        let filtered_rect = filtered.dst_rect(size, dst_rect);
+        let pixmap_size = IntSize::from_wh(filtered.width as u32, filtered.height as u32);
+        let pixmap = match pixmap_size.and_then(|size| Pixmap::from_vec(filtered.data, size)) {
+            Some(pixmap) => pixmap,
+            None => return,
+        };
+        let image = TinySkiaImage::new(Some(pixmap));
+        self.draw_image_inner(&image, None, filtered_rect, InterpolationMode::Bilinear);
+    }
+
+    fn capture_image_area(&mut self, src_rect: impl Into<Rect>) -> Result<Self::Image, Error> {
+        let device_rect = (self.base_transform * self.state.transform)
+            .transform_rect_bbox(src_rect.into())
+            .round();
+        let rect = IntRect::from_ltrb(
+            device_rect.x0 as i32,
+            device_rect.y0 as i32,
+            device_rect.x1 as i32,
+            device_rect.y1 as i32,
+        )
+        .ok_or(Error::InvalidInput)?;
+        let pixmap = self
+            .pixmap
+            .as_ref()
+            .clone_rect(rect)
+            .ok_or(Error::InvalidInput)?;
+        Ok(TinySkiaImage::new(Some(pixmap)))
+    }
+
+    fn blurred_rect(&mut self, rect: Rect, blur_radius: f64, brush: &impl IntoBrush<Self>) {
+        let brush = brush.make_brush(self, || rect);
+        let size = piet::util::size_for_blurred_rect(rect, blur_radius);
+        let (width, height) = (size.width as usize, size.height as usize);
+        let mut data = vec![0u8; width * height];
+        let rect_exp = piet::util::compute_blurred_rect(rect, blur_radius, width, &mut data);
+
+        let mask = IntSize::from_wh(width as u32, height as u32)
+            .and_then(|size| Mask::from_vec(data, size));
+        let (mask, mut image) = match (mask, Pixmap::new(width as u32, height as u32)) {
+            (Some(mask), Some(image)) => (mask, image),
+            _ => return,
+        };
+        // paint the brush in user space, with the image's origin at the
+        // expanded rect's origin, and then cut it down to the blurred shape.
+        if let Some(fill_rect) = convert_rect(rect_exp) {
+            let origin = Transform::from_translate(-rect_exp.x0 as f32, -rect_exp.y0 as f32);
+            image.fill_rect(fill_rect, &brush.to_paint(1.0), origin, None);
+        }
+        image.apply_mask(&mask);
+
+        let transform = affine_to_transform(
+            self.base_transform
+                * self.state.transform
+                * Affine::translate(rect_exp.origin().to_vec2()),
+        );
+        let paint = PixmapPaint {
+            opacity: self.state.global_alpha,
+            blend_mode: convert_blend_mode(self.state.blend_mode),
+            ..PixmapPaint::default()
+        };
+        self.pixmap.draw_pixmap(
+            0,
+            0,
+            image.as_ref(),
+            &paint,
+            transform,
+            self.state.clip.as_deref(),
+        );
+    }
+}
+
+impl Brush {
+    /// A paint of the brush, with its opacity multiplied by `alpha`.
+    fn to_paint(&self, alpha: f32) -> Paint<'static> {
+        let mut shader = self.shader.clone();
+        if alpha < 1.0 {
+            shader.apply_opacity(alpha);
+        }
+        Paint {
+            shader,
+            ..Paint::default()
+        }
+    }
+}
+
+impl<'a> IntoBrush<TinySkiaRenderContext<'a>> for Brush {
+    fn make_brush<'b>(
+        &'b self,
+        _piet: &mut TinySkiaRenderContext,
+        _bbox: impl FnOnce() -> Rect,
+    ) -> Cow<'b, Brush> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl TinySkiaImage {
+    fn new(pixmap: Option<Pixmap>) -> TinySkiaImage {
+        TinySkiaImage {
+            pixmap: pixmap.map(Arc::new),
+            view: None,
+        }
+    }
+
+    /// The area of the pixmap that is the image.
+    fn view(&self) -> Rect {
+        match (&self.pixmap, self.view) {
+            (_, Some(view)) => view,
+            (Some(pixmap), None) => {
+                Rect::new(0., 0., pixmap.width().into(), pixmap.height().into())
+            }
+            (None, None) => Rect::ZERO,
+        }
+    }
+
+    /// The pixels of the image, which are copied out of the pixmap if the
+    /// image is a sub-image.
+    fn pixels(&self) -> Option<Cow<'_, Pixmap>> {
+        let pixmap = self.pixmap.as_ref()?;
+        match self.view {
+            None => Some(Cow::Borrowed(pixmap)),
+            Some(view) => IntRect::from_xywh(
+                view.x0 as i32,
+                view.y0 as i32,
+                view.width() as u32,
+                view.height() as u32,
+            )
+            .and_then(|rect| pixmap.clone_rect(rect))
+            .map(Cow::Owned),
+        }
+    }
+}
+
+impl Image for TinySkiaImage {
+    fn size(&self) -> Size {
+        self.view().size()
+    }
+
+    fn sub_image(&self, rect: impl Into<Rect>) -> Self {
+        TinySkiaImage {
+            pixmap: self.pixmap.clone(),
+            view: Some(piet::util::sub_image_view(self.view(), rect.into())),
+        }
+    }
+
+    /// The pixels are copied first if the image is shared with its clones,
+    /// so the clones keep the old pixels.
+    fn update_region(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        buf: &[u8],
+        format: ImageFormat,
+    ) -> Result<(), Error> {
+        let view = self.view();
+        if (x + width) as f64 > view.width() || (y + height) as f64 > view.height() {
+            return Err(Error::InvalidInput);
+        }
+        let (x, y) = (x + view.x0 as usize, y + view.y0 as usize);
+        let pixmap = match &mut self.pixmap {
+            Some(pixmap) if width > 0 && height > 0 => Arc::make_mut(pixmap),
+            _ => return Ok(()),
+        };
+        let data = premultiplied_rgba(width * height, buf, format)?;
+        let stride = pixmap.width() as usize * 4;
+        let dst = pixmap.data_mut();
+        for (row, src) in data.chunks_exact(width * 4).enumerate() {
+            let start = (y + row) * stride + x * 4;
+            dst[start..start + width * 4].copy_from_slice(src);
+        }
+        Ok(())
+    }
+
+    fn to_rgba_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut data = match self.pixels() {
+            Some(pixmap) => pixmap.data().to_vec(),
+            None => Vec::new(),
+        };
+        piet::util::unpremultiply_rgba(&mut data);
+        Ok(data)
+    }
+}
+
+/// Convert `len` pixels in `format` to premultiplied RGBA, which is what
+/// tiny-skia draws from.
+fn premultiplied_rgba(len: usize, buf: &[u8], format: ImageFormat) -> Result<Vec<u8>, Error> {
+    let buf = buf
+        .get(..len * format.bytes_per_pixel())
+        .ok_or(Error::InvalidInput)?;
+    Ok(match format {
+        ImageFormat::RgbaPremul => buf.to_vec(),
+        ImageFormat::RgbaSeparate => {
+            let mut data = buf.to_vec();
+            piet::util::premultiply_rgba(&mut data);
+            data
+        }
+        ImageFormat::Rgb => {
+            let mut data = vec![0; len * 4];
+            piet::util::rgb_to_rgba(buf, &mut data);
+            data
+        }
+        ImageFormat::Grayscale => {
+            let mut data = vec![0; len * 4];
+            piet::util::gray_to_rgba(buf, &mut data);
+            data
+        }
+        ImageFormat::BgraSeparate | ImageFormat::BgraPremul | ImageFormat::Rgba16 => {
+            let (buf, format) = piet::util::to_rgba8(buf, format);
+            return premultiplied_rgba(len, &buf, format);
+        }
+        _ => return Err(Error::NotSupported),
+    })
+}
+
+/// The bounds of `path`, in the space it is in.
+fn path_bounds(path: &Path) -> Rect {
+    let bounds = path.bounds();
+    Rect::new(
+        bounds.left() as f64,
+        bounds.top() as f64,
+        bounds.right() as f64,
+        bounds.bottom() as f64,
+    )
+}
+
+fn shape_to_path(shape: impl Shape, tolerance: f64) -> Option<Path> {
+    // tiny-skia can't draw anything that doesn't fit in an f32, and curves that big would be
+    // flattened into an endless number of segments, so they are skipped before that.
+    if shape.as_path_slice().is_none() {
+        let bounds = shape.bounding_box();
+        let max = f32::MAX as f64;
+        let fits = bounds.x0 >= -max && bounds.y0 >= -max && bounds.x1 <= max && bounds.y1 <= max;
+        if !fits {
+            return None;
+        }
+    }
+    elements_to_path(shape.path_elements(tolerance))
+}
+
+fn elements_to_path(elements: impl IntoIterator<Item = PathEl>) -> Option<Path> {
+    let mut builder = PathBuilder::new();
+    for el in elements {
+        match el {
+            PathEl::MoveTo(p) => builder.move_to(p.x as f32, p.y as f32),
+            PathEl::LineTo(p) => builder.line_to(p.x as f32, p.y as f32),
+            PathEl::QuadTo(p1, p2) => {
+                builder.quad_to(p1.x as f32, p1.y as f32, p2.x as f32, p2.y as f32)
+            }
+            PathEl::CurveTo(p1, p2, p3) => builder.cubic_to(
+                p1.x as f32,
+                p1.y as f32,
+                p2.x as f32,
+                p2.y as f32,
+                p3.x as f32,
+                p3.y as f32,
+            ),
+            PathEl::ClosePath => builder.close(),
+        }
+    }
+    // tiny-skia's bounds skip NaN, so it doesn't reject paths with them itself.
+    builder
+        .finish()
+        .filter(|path| path.points().iter().all(|p| p.is_finite()))
+}
+
+fn path_elements(path: &Path) -> impl Iterator<Item = PathEl> + '_ {
+    let point = |p: tiny_skia::Point| Point::new(p.x as f64, p.y as f64);
+    path.segments().map(move |seg| match seg {
+        PathSegment::MoveTo(p) => PathEl::MoveTo(point(p)),
+        PathSegment::LineTo(p) => PathEl::LineTo(point(p)),
+        PathSegment::QuadTo(p1, p2) => PathEl::QuadTo(point(p1), point(p2)),
+        PathSegment::CubicTo(p1, p2, p3) => PathEl::CurveTo(point(p1), point(p2), point(p3)),
+        PathSegment::Close => PathEl::ClosePath,
+    })
+}
+
+/// Whether tiny-skia would give up on dashing `path` with the dashes of
+/// `style`, because there would be more than a million of them.
+///
+/// tiny-skia only finds out after it has measured the path, which takes very
+/// long for huge ones, so this checks the distance between the points that
+/// the path goes through, which is no longer than the path, first.
+fn too_many_dashes(path: &Path, style: &StrokeStyle) -> bool {
+    const MAX_DASHES: f64 = 1e6;
+    let mut length = 0.0;
+    let (mut start, mut last) = (Point::ZERO, Point::ZERO);
+    for el in path_elements(path) {
+        let p = match el {
+            PathEl::MoveTo(p) => {
+                start = p;
+                last = p;
+                continue;
+            }
+            PathEl::LineTo(p) | PathEl::QuadTo(_, p) | PathEl::CurveTo(_, _, p) => p,
+            PathEl::ClosePath => start,
+        };
+        length += last.distance(p);
+        last = p;
+    }
+    // tiny-skia counts a dash for each pair of lengths in the pattern.
+    let pattern_len: f64 = style.dash_pattern.iter().sum();
+    length * style.dash_pattern.len() as f64 / (2.0 * pattern_len) > MAX_DASHES
+}
+
+/// How far, in pixels, a stroke of `width` reaches out from its path when
+/// it is drawn with `transform`.
+fn stroke_reach(width: f64, style: &StrokeStyle, transform: Affine) -> f64 {
+    // square caps reach out along the diagonal, and miters up to their limit.
+    let factor = match style.line_join {
+        LineJoin::Miter { limit } => limit.max(std::f64::consts::SQRT_2),
+        _ => std::f64::consts::SQRT_2,
+    };
+    // no vector is scaled by more than this.
+    let [a, b, c, d, _, _] = transform.as_coeffs();
+    let scale = (a * a + b * b + c * c + d * d).sqrt();
+    width * 0.5 * factor * scale
+}
+
+fn convert_stroke(width: f64, style: &StrokeStyle) -> Stroke {
+    let (line_join, miter_limit) = match style.line_join {
+        LineJoin::Miter { limit } => (tiny_skia::LineJoin::Miter, limit as f32),
+        LineJoin::Round => (tiny_skia::LineJoin::Round, 4.0),
+        LineJoin::Bevel => (tiny_skia::LineJoin::Bevel, 4.0),
+    };
+    let line_cap = match style.line_cap {
+        LineCap::Butt => tiny_skia::LineCap::Butt,
+        LineCap::Round => tiny_skia::LineCap::Round,
+        LineCap::Square => tiny_skia::LineCap::Square,
+    };
+    let mut dashes: Vec<f32> = style.dash_pattern.iter().map(|&d| d as f32).collect();
+    // tiny-skia needs an even number of lengths.
+    if dashes.len() % 2 == 1 {
+        dashes.extend_from_within(..);
+    }
+    let dash = if dashes.is_empty() {
+        None
+    } else {
+        tiny_skia::StrokeDash::new(dashes, style.dash_offset as f32)
+    };
+    Stroke {
+        width: width as f32,
+        miter_limit,
+        line_cap,
+        line_join,
+        dash,
+    }
+}
+
+fn convert_stops(stops: &[GradientStop]) -> Vec<tiny_skia::GradientStop> {
+    stops
+        .iter()
+        .map(|stop| tiny_skia::GradientStop::new(stop.pos, convert_color(stop.color)))
+        .collect()
+}
+
+fn convert_color(color: Color) -> tiny_skia::Color {
+    let (r, g, b, a) = color.as_rgba8();
+    tiny_skia::Color::from_rgba8(r, g, b, a)
+}
+
+fn convert_blend_mode(mode: piet::BlendMode) -> BlendMode {
+    match mode {
+        piet::BlendMode::Normal => BlendMode::SourceOver,
+        piet::BlendMode::Multiply => BlendMode::Multiply,
+        piet::BlendMode::Screen => BlendMode::Screen,
+        piet::BlendMode::Overlay => BlendMode::Overlay,
+        piet::BlendMode::Darken => BlendMode::Darken,
+        piet::BlendMode::Lighten => BlendMode::Lighten,
+        piet::BlendMode::ColorDodge => BlendMode::ColorDodge,
+        piet::BlendMode::ColorBurn => BlendMode::ColorBurn,
+        piet::BlendMode::HardLight => BlendMode::HardLight,
+        piet::BlendMode::SoftLight => BlendMode::SoftLight,
+        piet::BlendMode::Difference => BlendMode::Difference,
+        piet::BlendMode::Exclusion => BlendMode::Exclusion,
+        piet::BlendMode::Hue => BlendMode::Hue,
+        piet::BlendMode::Saturation => BlendMode::Saturation,
+        piet::BlendMode::Color => BlendMode::Color,
+        piet::BlendMode::Luminosity => BlendMode::Luminosity,
+    }
+}
+
+fn convert_point(point: Point) -> tiny_skia::Point {
+    tiny_skia::Point::from_xy(point.x as f32, point.y as f32)
+}
+
+fn convert_rect(rect: Rect) -> Option<tiny_skia::Rect> {
+    let rect = rect.abs();
+    tiny_skia::Rect::from_ltrb(
+        rect.x0 as f32,
+        rect.y0 as f32,
+        rect.x1 as f32,
+        rect.y1 as f32,
+    )
+}
+
+fn affine_to_transform(affine: Affine) -> Transform {
+    let [a, b, c, d, e, f] = affine.as_coeffs();
+    Transform::from_row(a as f32, b as f32, c as f32, d as f32, e as f32, f as f32)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use piet::kurbo::Circle;
+    use piet::GradientStops;
+
+    fn pixel(pixmap: &Pixmap, x: u32, y: u32) -> [u8; 4] {
+        let px = pixmap.pixel(x, y).unwrap();
+        [px.red(), px.green(), px.blue(), px.alpha()]
+    }
+
+    #[test]
+    fn fill_respects_clip_and_scale() {
+        let mut pixmap = Pixmap::new(20, 20).unwrap();
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 2.0);
+        rc.with_save(|rc| {
+            rc.clip(Rect::new(0., 0., 5., 10.));
+            rc.fill(Rect::new(0., 0., 10., 10.), &Color::RED);
+            Ok(())
+        })
+        .unwrap();
+        rc.finish().unwrap();
+        std::mem::drop(rc);
+
+        assert_eq!(pixel(&pixmap, 5, 5), [255, 0, 0, 255]);
+        assert_eq!(pixel(&pixmap, 15, 5), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn clip_and_degenerate_samples() {
+        let draw = |number| {
+            let sample = piet::samples::get(number).unwrap();
+            let size = sample.size();
+            let mut pixmap = Pixmap::new(size.width as u32, size.height as u32).unwrap();
+            let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+            sample.draw(&mut rc).unwrap();
+            rc.finish().unwrap();
+            std::mem::drop(rc);
+            pixmap
+        };
+        let (black, red, green, blue) = (
+            [0, 0, 0, 255],
+            [255, 0, 0, 255],
+            [0, 255, 0, 255],
+            [0, 0, 255, 255],
+        );
+
+        let nested = draw(17);
+        assert_eq!(pixel(&nested, 15, 100), red);
+        assert_eq!(pixel(&nested, 50, 60), red);
+        assert_eq!(pixel(&nested, 50, 100), black, "even-odd hole");
+        assert_eq!(pixel(&nested, 5, 100), black, "outer clip");
+        assert_eq!(pixel(&nested, 12, 58), black, "inner clip");
+        assert_eq!(pixel(&nested, 150, 50), green, "nonzero clip");
+        assert_eq!(pixel(&nested, 150, 150), black, "hole in clip");
+        assert_eq!(pixel(&nested, 115, 150), blue);
+
+        let restored = draw(18);
+        assert_eq!(pixel(&restored, 50, 20), red);
+        assert_eq!(pixel(&restored, 150, 20), black);
+        assert_eq!(pixel(&restored, 150, 40), green);
+        assert_eq!(pixel(&restored, 70, 70), [128, 128, 128, 255]);
+        assert_eq!(pixel(&restored, 90, 70), black);
+        assert_eq!(pixel(&restored, 70, 90), [255, 255, 0, 255]);
+        assert_eq!(pixel(&restored, 120, 90), black);
+        assert_eq!(pixel(&restored, 100, 160), red);
+        assert_eq!(pixel(&restored, 120, 160), black);
+        assert_eq!(pixel(&restored, 20, 190), blue);
+
+        let degenerate = draw(19);
+        assert_eq!(pixel(&degenerate, 50, 50), red);
+        assert_eq!(pixel(&degenerate, 180, 180), green);
+        assert_eq!(pixel(&degenerate, 150, 150), black);
+        assert_eq!(pixel(&degenerate, 190, 190), black);
+        assert_eq!(pixel(&degenerate, 120, 50), black);
+        assert_eq!(pixel(&degenerate, 50, 180), blue);
+    }
+
+    #[test]
+    fn global_alpha_fades_everything() {
+        let sample = piet::samples::get(20).unwrap();
+        let mut pixmap = Pixmap::new(200, 200).unwrap();
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+        sample.draw(&mut rc).unwrap();
+        assert_eq!(rc.global_alpha(), 1.0);
+        rc.finish().unwrap();
+        std::mem::drop(rc);
+
+        let near = |x, y, expected: [u8; 4]| {
+            let actual = pixel(&pixmap, x, y);
+            let close = actual
+                .iter()
+                .zip(expected)
+                .all(|(a, e)| (*a as i32 - e as i32).abs() <= 2);
+            assert!(close, "{:?} at {}, {}", actual, x, y);
+        };
+        near(30, 20, [128, 0, 0, 255]);
+        near(30, 40, [255, 128, 128, 255]);
+        near(65, 20, [64, 128, 0, 255]);
+        near(100, 20, [0, 128, 0, 255]);
+        // the stroke, and the white and black pixels of the image.
+        near(126, 110, [128, 128, 128, 255]);
+        near(160, 100, [128, 128, 128, 255]);
+        near(180, 100, [0, 0, 0, 255]);
+        near(100, 157, [0, 0, 255, 255]);
+        near(100, 182, [0, 0, 64, 255]);
+    }
+
+    #[test]
+    fn non_scaling_strokes_keep_their_width() {
+        // the context's scale is kept, as it is for high-DPI displays.
+        let mut pixmap = Pixmap::new(100, 40).unwrap();
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 2.0);
+        rc.transform(Affine::scale(4.0));
+        let line = piet::kurbo::Line::new((5., 0.), (5., 5.));
+        rc.stroke_styled(line, &Color::RED, 2., &StrokeStyle::new().scale(false));
+        rc.stroke(line + Vec2::new(5., 0.), &Color::BLUE, 2.);
+        rc.finish().unwrap();
+        std::mem::drop(rc);
+
+        // 2 units wide, at 2 pixels per unit.
+        let red = [255, 0, 0, 255];
+        assert_eq!(pixel(&pixmap, 37, 20), [0, 0, 0, 0]);
+        assert_eq!(pixel(&pixmap, 38, 20), red);
+        assert_eq!(pixel(&pixmap, 41, 20), red);
+        assert_eq!(pixel(&pixmap, 42, 20), [0, 0, 0, 0]);
+        // 2 units wide, scaled by the transform too.
+        let blue = [0, 0, 255, 255];
+        assert_eq!(pixel(&pixmap, 71, 20), [0, 0, 0, 0]);
+        assert_eq!(pixel(&pixmap, 72, 20), blue);
+        assert_eq!(pixel(&pixmap, 87, 20), blue);
+        assert_eq!(pixel(&pixmap, 88, 20), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn huge_shapes_are_drawn_where_they_can_be_seen() {
+        let draw = |x0| {
+            let mut pixmap = Pixmap::new(64, 64).unwrap();
+            let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+            rc.transform(Affine::rotate(1.0));
+            let rect = Rect::new(x0, 0.0, 32.0, 32.0);
+            rc.fill(rect, &Color::RED);
+            rc.stroke(rect, &Color::BLUE, 2.0);
+            rc.finish().unwrap();
+            std::mem::drop(rc);
+            pixmap
+        };
+        // tiny-skia's rasterizer panics on the huge one, unless the parts
+        // that are out of sight are cut away first.
+        let huge = draw(-1e8);
+        assert_eq!(pixel(&huge, 11, 25), [255, 0, 0, 255]);
+        assert_eq!(pixel(&huge, 40, 10), [0, 0, 0, 0]);
+        // the same as what is drawn of a shape that is cut off out of sight.
+        assert_eq!(huge.data(), draw(-100.0).data());
+
+        // strokes too wide to be cut down before they are stroked.
+        for (center, width) in [(1e16, 1e16), (1e11, 1e11)] {
+            let mut pixmap = Pixmap::new(8, 8).unwrap();
+            let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+            rc.stroke(Circle::new((center, 0.0), center), &Color::RED, width);
+            rc.finish().unwrap();
+            std::mem::drop(rc);
+            assert!(pixmap.pixels().iter().all(|p| p.red() == 255));
+        }
+    }
+
+    #[test]
+    fn unusable_strokes_draw_nothing() {
+        let mut pixmap = Pixmap::new(20, 20).unwrap();
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+        let rect = Rect::new(5.0, 5.0, 15.0, 15.0);
+        rc.stroke(rect, &Color::RED, f64::NAN);
+        rc.stroke(rect, &Color::RED, -2.0);
+        // kurbo would flatten these into endless curves.
+        rc.stroke(Circle::new((10.0, 10.0), f64::INFINITY), &Color::RED, 2.0);
+        rc.fill(Circle::new((10.0, 10.0), 1e300), &Color::RED);
+        // tiny-skia's hairlines panic on NaN.
+        let mut nan = piet::kurbo::BezPath::new();
+        nan.move_to((0.0, f64::NAN));
+        nan.quad_to((0.0, 0.0), (0.0, 0.0));
+        rc.stroke(nan, &Color::RED, 0.0);
+        // tiny-skia gives up on dashing this, after measuring it for ages.
+        let dashes = StrokeStyle::new().dash_pattern(&[1.0, 1.0]);
+        rc.stroke_styled(Circle::new((10.0, 1e20), 1e20), &Color::RED, 2.0, &dashes);
+        rc.finish().unwrap();
+        std::mem::drop(rc);
+        assert!(pixmap.pixels().iter().all(|p| p.alpha() == 0));
+    }
+
+    #[test]
+    fn blend_modes_are_saved_and_skip_clear() {
+        let mut pixmap = Pixmap::new(3, 1).unwrap();
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+        rc.fill(Rect::new(0., 0., 3., 1.), &Color::rgb8(255, 255, 0));
+        rc.save().unwrap();
+        rc.set_blend_mode(piet::BlendMode::Multiply);
+        rc.fill(Rect::new(0., 0., 1., 1.), &Color::rgb8(0, 255, 255));
+        rc.clear(Rect::new(2., 0., 3., 1.), Color::rgb8(0, 0, 255));
+        assert_eq!(rc.blend_mode(), piet::BlendMode::Multiply);
+        rc.restore().unwrap();
+        assert_eq!(rc.blend_mode(), piet::BlendMode::Normal);
+        rc.fill(Rect::new(1., 0., 2., 1.), &Color::rgb8(0, 255, 255));
+        rc.finish().unwrap();
+        std::mem::drop(rc);
+        assert_eq!(pixel(&pixmap, 0, 0), [0, 255, 0, 255]);
+        assert_eq!(pixel(&pixmap, 1, 0), [0, 255, 255, 255]);
+        assert_eq!(pixel(&pixmap, 2, 0), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn conic_gradients_go_around_their_center() {
+        let mut pixmap = Pixmap::new(20, 20).unwrap();
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+        let stops = [Color::rgb8(255, 0, 0), Color::rgb8(0, 0, 255)];
+        let shape = Rect::new(0., 0., 20., 10.);
+        piet::fallback::fill_conic_gradient(&mut rc, shape, (10., 10.), 0., &stops[..]).unwrap();
+        rc.finish().unwrap();
+        std::mem::drop(rc);
+
+        let near = |x, y, expected: [u8; 4]| {
+            let actual = pixel(&pixmap, x, y);
+            let close = actual
+                .iter()
+                .zip(expected)
+                .all(|(a, e)| (*a as i32 - e as i32).abs() <= 8);
+            assert!(close, "{:?} at {}, {}", actual, x, y);
+        };
+        // angles go clockwise from the right, so the top is three quarters
+        // of the way around.
+        near(0, 9, [128, 0, 128, 255]);
+        near(10, 0, [64, 0, 191, 255]);
+        near(19, 9, [8, 0, 247, 255]);
+        // only the shape is filled.
+        near(10, 15, [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn dithered_gradients_mix_neighboring_colors() {
+        let mut pixmap = Pixmap::new(32, 8).unwrap();
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 2.0);
+        let gradient = piet::FixedLinearGradient {
+            start: Point::new(0., 0.),
+            end: Point::new(16., 0.),
+            stops: (Color::rgb8(10, 10, 10), Color::rgb8(12, 12, 12)).to_vec(),
+        };
+        let options = GradientOptions {
+            dither: true,
+            ..GradientOptions::default()
+        };
+        let brush = rc.gradient_with_options(gradient.clone(), options).unwrap();
+        rc.fill(Rect::new(0., 0., 16., 2.), &brush);
+        let brush = rc.gradient(gradient).unwrap();
+        rc.fill(Rect::new(0., 2., 16., 4.), &brush);
+        rc.finish().unwrap();
+        std::mem::drop(rc);
+
+        let column = |x, rows: std::ops::Range<u32>| -> Vec<u8> {
+            rows.map(|y| pixel(&pixmap, x, y)[0]).collect()
+        };
+        // a quarter of the way along, the color is 10.5.
+        let dithered = column(8, 0..4);
+        assert!(
+            dithered.contains(&10) && dithered.contains(&11),
+            "{:?}",
+            dithered
+        );
+        let plain = column(8, 4..8);
+        assert!(plain.iter().all(|&v| v == plain[0]), "{:?}", plain);
+        assert!(column(31, 0..4).iter().all(|&v| v == 12));
+    }
+
+    #[test]
+    fn gradients_can_mix_in_linear_light() {
+        let mut pixmap = Pixmap::new(64, 2).unwrap();
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+        let gradient = piet::FixedLinearGradient {
+            start: Point::new(0., 0.),
+            end: Point::new(64., 0.),
+            stops: (Color::rgb8(255, 0, 0), Color::rgb8(0, 255, 0)).to_vec(),
+        };
+        let brush = rc.gradient(gradient.clone()).unwrap();
+        rc.fill(Rect::new(0., 0., 64., 1.), &brush);
+        let options = GradientOptions {
+            interpolation: piet::GradientInterpolation::LinearRgb,
+            ..GradientOptions::default()
+        };
+        let brush = rc.gradient_with_options(gradient, options).unwrap();
+        rc.fill(Rect::new(0., 1., 64., 2.), &brush);
+        rc.finish().unwrap();
+        std::mem::drop(rc);
+
+        // in sRGB, the middle is a dull (128, 128, 0).
+        let srgb = pixel(&pixmap, 32, 0);
+        assert!(srgb[0] < 140 && srgb[1] < 140, "{:?}", srgb);
+        let linear = pixel(&pixmap, 32, 1);
+        assert!(linear[0] > 170 && linear[1] > 170, "{:?}", linear);
+    }
+
+    #[test]
+    fn brushes_can_be_faded_and_borrowed() {
+        let mut pixmap = Pixmap::new(2, 1).unwrap();
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+        const RED: Color = Color::rgb8(255, 0, 0);
+        rc.fill(Rect::new(0., 0., 1., 1.), &(RED, 0.5));
+        let gradient = FixedGradient::Linear(piet::FixedLinearGradient {
+            start: Point::ZERO,
+            end: Point::new(2., 0.),
+            stops: vec![GradientStop {
+                pos: 0.0,
+                color: Color::BLUE,
+            }],
+        });
+        let gradients = [&gradient];
+        for gradient in gradients.iter() {
+            rc.fill(Rect::new(1., 0., 2., 1.), gradient);
+        }
+        std::mem::drop(rc);
+
+        assert_eq!(pixel(&pixmap, 0, 0), [128, 0, 0, 128]);
+        assert_eq!(pixel(&pixmap, 1, 0), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn saved_states_return_values() {
+        let mut pixmap = Pixmap::new(20, 20).unwrap();
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+        let transform = rc
+            .with_transform(Affine::scale(2.0), |rc| Ok(rc.current_transform()))
+            .unwrap();
+        assert_eq!(transform, Affine::scale(2.0));
+        assert_eq!(rc.current_transform(), Affine::IDENTITY);
+
+        let result = rc.with_save_and_clip(Rect::new(0., 0., 5., 5.), |rc| {
+            rc.fill(Rect::new(0., 0., 10., 10.), &Color::RED);
+            Err::<(), _>(Error::InvalidInput)
+        });
+        assert!(matches!(result, Err(Error::InvalidInput)));
+        rc.fill(Rect::new(10., 10., 20., 20.), &Color::BLUE);
+        std::mem::drop(rc);
+
+        assert_eq!(pixel(&pixmap, 7, 7), [0, 0, 0, 0]);
+        assert_eq!(pixel(&pixmap, 15, 15), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn image_with_quad_has_no_seams() {
+        let mut pixmap = Pixmap::new(40, 40).unwrap();
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+        let image = rc
+            .make_image(2, 2, &[0, 0, 255, 255].repeat(4), ImageFormat::RgbaSeparate)
+            .unwrap();
+        let quad = [(10., 2.), (30., 2.), (38., 38.), (2., 38.)].map(Point::from);
+        rc.draw_image_with_quad(&image, quad, InterpolationMode::NearestNeighbor);
+        std::mem::drop(rc);
+
+        for y in 4..36 {
+            assert_eq!(pixel(&pixmap, 20, y), [0, 0, 255, 255]);
+        }
+        assert_eq!(pixel(&pixmap, 4, 4), [0, 0, 0, 0]);
+        assert_eq!(pixel(&pixmap, 36, 4), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn filtered_images_draw_outside_their_rect() {
+        let mut pixmap = Pixmap::new(30, 10).unwrap();
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+        let image = rc
+            .make_image(2, 2, &[0, 0, 255, 255].repeat(4), ImageFormat::RgbaSeparate)
+            .unwrap();
+        let shadow = ImageFilter::drop_shadow((10., 0.), 0., Color::RED);
+        rc.draw_image_filtered(&image, Rect::new(0., 0., 10., 10.), &[shadow]);
+        rc.draw_image_filtered(&image, Rect::new(20., 0., 30., 10.), &[]);
+        std::mem::drop(rc);
+
+        assert_eq!(pixel(&pixmap, 5, 5), [0, 0, 255, 255]);
+        assert_eq!(pixel(&pixmap, 15, 5), [255, 0, 0, 255]);
+        assert_eq!(pixel(&pixmap, 25, 5), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn tolerance_is_saved_and_validated() {
+        let mut pixmap = Pixmap::new(20, 20).unwrap();
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+        assert_eq!(rc.tolerance(), piet::DEFAULT_TOLERANCE);
+        rc.with_save(|rc| {
+            rc.set_tolerance(0.5);
+            rc.set_tolerance(0.0);
+            rc.set_tolerance(f64::NAN);
+            assert_eq!(rc.tolerance(), 0.5);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(rc.tolerance(), piet::DEFAULT_TOLERANCE);
+    }
+
+    #[test]
+    fn clear_ignores_clip() {
+        let mut pixmap = Pixmap::new(10, 10).unwrap();
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+        rc.clip(Rect::new(0., 0., 1., 1.));
+        rc.clear(Rect::new(5., 5., 10., 10.), Color::BLUE);
+        std::mem::drop(rc);
+
+        assert_eq!(pixel(&pixmap, 7, 7), [0, 0, 255, 255]);
+        assert_eq!(pixel(&pixmap, 2, 2), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn bgra_and_16_bit_images() {
+        let mut pixmap = Pixmap::new(2, 1).unwrap();
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+        let bgra = rc
+            .make_image(1, 1, &[255, 0, 0, 255], ImageFormat::BgraSeparate)
+            .unwrap();
+        let red = [0xffffu16, 0, 0, 0xffff]
+            .iter()
+            .flat_map(|x| x.to_ne_bytes())
+            .collect::<Vec<_>>();
+        let rgba16 = rc.make_image(1, 1, &red, ImageFormat::Rgba16).unwrap();
+        let interp = InterpolationMode::NearestNeighbor;
+        rc.draw_image(&bgra, Rect::new(0., 0., 1., 1.), interp);
+        rc.draw_image(&rgba16, Rect::new(1., 0., 2., 1.), interp);
+        std::mem::drop(rc);
+
+        assert_eq!(pixel(&pixmap, 0, 0), [0, 0, 255, 255]);
+        assert_eq!(pixel(&pixmap, 1, 0), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn image_regions_are_updated() {
+        let mut pixmap = Pixmap::new(2, 1).unwrap();
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+        let mut image = rc
+            .make_image(2, 1, &[255, 0, 0, 255, 0, 0], ImageFormat::Rgb)
+            .unwrap();
+        let old = image.clone();
+        image
+            .update_region(1, 0, 1, 1, &[0, 255, 0], ImageFormat::Rgb)
+            .unwrap();
+        assert!(image
+            .update_region(1, 0, 2, 1, &[0; 6], ImageFormat::Rgb)
+            .is_err());
+        let interp = InterpolationMode::NearestNeighbor;
+        rc.draw_image(&old, Rect::new(0., 0., 2., 1.), interp);
+        rc.draw_image(&image, Rect::new(1., 0., 3., 1.), interp);
+        std::mem::drop(rc);
+
+        assert_eq!(pixel(&pixmap, 0, 0), [255, 0, 0, 255]);
+        assert_eq!(pixel(&pixmap, 1, 0), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn images_are_read_back() {
+        let mut pixmap = Pixmap::new(1, 1).unwrap();
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+        let rgba = [255, 0, 0, 255, 0, 0, 255, 102];
+        let image = rc
+            .make_image(2, 1, &rgba, ImageFormat::RgbaSeparate)
+            .unwrap();
+        assert_eq!(image.to_rgba_bytes().unwrap(), rgba);
+        let gray = rc.make_image(1, 1, &[51], ImageFormat::Grayscale).unwrap();
+        assert_eq!(gray.to_rgba_bytes().unwrap(), [51, 51, 51, 255]);
+    }
+
+    #[test]
+    fn sub_images_are_drawn_as_their_area() {
+        let mut pixmap = Pixmap::new(2, 1).unwrap();
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+        let sheet = rc
+            .make_image(3, 1, &[255, 0, 0, 0, 0, 255, 0, 255, 0], ImageFormat::Rgb)
+            .unwrap();
+        let sprite = sheet.sub_image(Rect::new(1., 0., 3., 1.));
+        assert_eq!(sprite.size(), Size::new(2., 1.));
+        let green = sprite.sub_image(Rect::new(1., 0., 2., 1.));
+        assert_eq!(green.to_rgba_bytes().unwrap(), [0, 255, 0, 255]);
+        let interp = InterpolationMode::NearestNeighbor;
+        rc.draw_image(&sprite, Rect::new(0., 0., 2., 1.), interp);
+        std::mem::drop(rc);
+
+        assert_eq!(pixel(&pixmap, 0, 0), [0, 0, 255, 255]);
+        assert_eq!(pixel(&pixmap, 1, 0), [0, 255, 0, 255]);
+    }
+
+    #[test]
+    fn mip_images_are_drawn_from_their_levels() {
+        let mut pixmap = Pixmap::new(2, 2).unwrap();
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+        // black and white stripes, a pixel wide.
+        let stripes: Vec<u8> = (0..8 * 8)
+            .map(|i| if i % 2 == 0 { 0 } else { 255 })
+            .collect();
+        let image = rc
+            .make_mip_image(8, 8, &stripes, ImageFormat::Grayscale)
+            .unwrap();
+        assert_eq!(image.levels().len(), 4);
+        let interp = InterpolationMode::NearestNeighbor;
+        rc.draw_mip_image(&image, Rect::new(0., 0., 2., 2.), interp);
+        std::mem::drop(rc);
+
+        // the stripes are averaged, rather than every other one being drawn.
+        assert_eq!(pixel(&pixmap, 0, 0), [128, 128, 128, 255]);
+        assert_eq!(pixel(&pixmap, 1, 1), [128, 128, 128, 255]);
+    }
+
+    #[test]
+    fn masked_fills_use_mask_alpha() {
+        let mut pixmap = Pixmap::new(8, 2).unwrap();
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 2.0);
+        // the color of the mask is ignored.
+        let mask = rc
+            .make_image(
+                2,
+                1,
+                &[0, 0, 255, 255, 0, 255, 0, 0],
+                ImageFormat::RgbaSeparate,
+            )
+            .unwrap();
+        rc.fill_with_mask(Rect::new(0., 0., 4., 1.), &Color::RED, &mask);
+        std::mem::drop(rc);
+
+        assert_eq!(pixel(&pixmap, 0, 1), [255, 0, 0, 255]);
+        assert_eq!(pixel(&pixmap, 7, 1), [0, 0, 0, 0]);
+        let middle = pixel(&pixmap, 4, 0)[3];
+        assert!(middle > 0 && middle < 255, "{}", middle);
+    }
+
+    #[test]
+    fn opacity_masks_fade_what_is_drawn_as_one_layer() {
+        let mut pixmap = Pixmap::new(8, 2).unwrap();
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+        let mask = rc
+            .make_image(
+                4,
+                1,
+                &[0, 0, 0, 255, 0, 0, 0, 255, 0, 0, 0, 128, 0, 0, 0, 0],
+                ImageFormat::RgbaSeparate,
+            )
+            .unwrap();
+        rc.fill(Rect::new(0., 0., 8., 2.), &Color::BLUE);
+        rc.with_opacity_mask(&mask, Rect::new(0., 0., 4., 2.), |rc| {
+            // overlapping fills are masked together, not one by one.
+            rc.fill(Rect::new(0., 0., 8., 2.), &Color::RED);
+            rc.fill(Rect::new(0., 0., 8., 2.), &Color::RED);
+            rc.save()?;
+            rc.restore()
+        })
+        .unwrap();
+        assert!(rc
+            .begin_opacity_mask(&mask, Rect::new(0., 0., 1., 1.))
+            .is_ok());
+        assert!(matches!(rc.restore(), Err(Error::StackUnbalance)));
+        assert!(rc.end_opacity_mask().is_ok());
+        assert!(matches!(rc.end_opacity_mask(), Err(Error::StackUnbalance)));
+        std::mem::drop(rc);
+
+        assert_eq!(pixel(&pixmap, 1, 1), [255, 0, 0, 255]);
+        assert_eq!(pixel(&pixmap, 2, 0), [128, 0, 127, 255]);
+        // nothing is drawn where the mask is transparent, or outside of it.
+        assert_eq!(pixel(&pixmap, 3, 0), [0, 0, 255, 255]);
+        assert_eq!(pixel(&pixmap, 6, 1), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn dashes_can_have_their_own_caps() {
+        let mut pixmap = Pixmap::new(40, 10).unwrap();
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+        let style = StrokeStyle::new()
+            .dash_pattern(&[10.0, 10.0])
+            .dash_cap(LineCap::Round);
+        let line = piet::kurbo::Line::new((5., 5.), (35., 5.));
+        rc.stroke_styled(line, &Color::RED, 6.0, &style);
+        std::mem::drop(rc);
+
+        // the ends of the line are butt capped, the ends of dashes round.
+        assert_eq!(pixel(&pixmap, 3, 5), [0, 0, 0, 0]);
+        assert_eq!(pixel(&pixmap, 16, 5), [255, 0, 0, 255]);
+        assert_eq!(pixel(&pixmap, 23, 5), [255, 0, 0, 255]);
+        assert_eq!(pixel(&pixmap, 36, 5), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn markers_are_filled_at_the_ends() {
+        let mut pixmap = Pixmap::new(40, 20).unwrap();
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+        let line = piet::kurbo::Line::new((2., 10.), (30., 10.));
+        let markers = piet::Markers::new().end(piet::Marker::ARROW);
+        rc.stroke_with_markers(line, &Color::RED, 2.0, &StrokeStyle::new(), &markers);
+        std::mem::drop(rc);
+
+        assert_eq!(pixel(&pixmap, 10, 10), [255, 0, 0, 255]);
+        assert_eq!(pixel(&pixmap, 10, 8), [0, 0, 0, 0]);
+        // the arrowhead is three times as wide as the line, and ends at the
+        // end of the line.
+        assert_eq!(pixel(&pixmap, 25, 8), [255, 0, 0, 255]);
+        assert_eq!(pixel(&pixmap, 31, 10), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn gradients_follow_the_path() {
+        let mut pixmap = Pixmap::new(20, 40).unwrap();
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+        // a path that doubles back, so that a gradient fixed in space would
+        // be the same color at both ends.
+        let mut path = piet::kurbo::BezPath::new();
+        path.move_to((5., 2.));
+        path.line_to((5., 38.));
+        path.line_to((15., 38.));
+        path.line_to((15., 2.));
+        let stops = (Color::RED, Color::BLUE);
+        rc.stroke_gradient_along_path(&path, stops, 4.0, &StrokeStyle::new());
+        std::mem::drop(rc);
+
+        let [r, _, b, a] = pixel(&pixmap, 5, 3);
+        assert!(r > 240 && b < 15 && a == 255);
+        let [r, _, b, a] = pixel(&pixmap, 15, 3);
+        assert!(r < 15 && b > 240 && a == 255);
+        // the pieces don't leave seams between them.
+        assert!((4..36).all(|y| pixel(&pixmap, 5, y)[3] == 255));
+    }
+
+    #[test]
+    fn encoded_images_are_decoded() {
+        let mut source = Pixmap::new(2, 2).unwrap();
+        source.fill(tiny_skia::Color::from_rgba8(0, 255, 0, 255));
+        let png = source.encode_png().unwrap();
+
+        let mut pixmap = Pixmap::new(4, 4).unwrap();
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+        let image = rc.make_image_from_encoded(&png).unwrap();
+        assert_eq!(image.size(), Size::new(2., 2.));
+        rc.draw_image(
+            &image,
+            Rect::new(0., 0., 4., 4.),
+            InterpolationMode::Bilinear,
+        );
+        assert!(rc.make_image_from_encoded(b"not an image").is_err());
+        std::mem::drop(rc);
+
+        assert_eq!(pixel(&pixmap, 2, 2), [0, 255, 0, 255]);
+    }
+
+    #[test]
+    fn unbalanced_restore() {
+        let mut pixmap = Pixmap::new(1, 1).unwrap();
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+        rc.save().unwrap();
+        assert!(rc.restore().is_ok());
+        assert!(matches!(rc.restore(), Err(Error::StackUnbalance)));
+    }
+
+    #[test]
+    fn empty_image() {
+        let mut pixmap = Pixmap::new(1, 1).unwrap();
+        let mut rc = TinySkiaRenderContext::new(pixmap.as_mut(), 1.0);
+        let image = rc.make_image(0, 0, &[], ImageFormat::Rgb).unwrap();
+        assert_eq!(image.size(), Size::ZERO);
+        rc.draw_image(
+            &image,
+            Rect::new(0., 0., 1., 1.),
+            InterpolationMode::Bilinear,
+        );
+    }
+}
@@ -0,0 +1,80 @@
+//! Detecting when the context of a canvas is lost, and restored.
+//!
+//! Browsers can take the memory of canvases back when they are short of it,
+//! which loses the contents and the state of their contexts. A lost context
+//! draws nothing, and when it is restored, it is blank, with the default
+//! state.
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use js_sys::{Function, Reflect};
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement};
+
+/// Listens for the `contextlost` and `contextrestored` events of a canvas,
+/// until it is dropped.
+pub(crate) struct ContextLoss {
+    canvas: HtmlCanvasElement,
+    lost: Rc<Cell<bool>>,
+    on_lost: Closure<dyn FnMut()>,
+    on_restored: Closure<dyn FnMut()>,
+}
+
+impl ContextLoss {
+    /// Start listening for the loss of `ctx`, if it has a canvas.
+    pub(crate) fn new(ctx: &CanvasRenderingContext2d) -> Option<ContextLoss> {
+        let canvas = ctx.canvas()?;
+        // the context may have been lost before it was handed to us.
+        let lost = Rc::new(Cell::new(is_context_lost(ctx)));
+        let set_lost = |value| {
+            let lost = lost.clone();
+            Closure::wrap(Box::new(move || lost.set(value)) as Box<dyn FnMut()>)
+        };
+        // unlike with WebGL, preventing the default of `contextlost` stops
+        // the context from being restored, so it isn't prevented.
+        let on_lost = set_lost(true);
+        let on_restored = set_lost(false);
+        let _ = canvas
+            .add_event_listener_with_callback("contextlost", on_lost.as_ref().unchecked_ref());
+        let _ = canvas.add_event_listener_with_callback(
+            "contextrestored",
+            on_restored.as_ref().unchecked_ref(),
+        );
+        Some(ContextLoss {
+            canvas,
+            lost,
+            on_lost,
+            on_restored,
+        })
+    }
+
+    /// Returns `true` if the context has been lost, and not restored.
+    pub(crate) fn is_lost(&self) -> bool {
+        self.lost.get()
+    }
+}
+
+impl Drop for ContextLoss {
+    fn drop(&mut self) {
+        let _ = self.canvas.remove_event_listener_with_callback(
+            "contextlost",
+            self.on_lost.as_ref().unchecked_ref(),
+        );
+        let _ = self.canvas.remove_event_listener_with_callback(
+            "contextrestored",
+            self.on_restored.as_ref().unchecked_ref(),
+        );
+    }
+}
+
+/// Ask `ctx` whether it is lost, which is only possible in browsers that
+/// support `isContextLost`; the others are assumed not to lose contexts.
+fn is_context_lost(ctx: &CanvasRenderingContext2d) -> bool {
+    Reflect::get(ctx, &JsValue::from_str("isContextLost"))
+        .ok()
+        .and_then(|f| f.dyn_into::<Function>().ok())
+        .and_then(|f| f.call0(ctx).ok())
+        .is_some_and(|lost| lost.is_truthy())
+}
@@ -0,0 +1,109 @@
+//! Reading the headers of encoded images, which the browser decodes.
+
+use std::convert::TryInto;
+
+/// The type and size of an encoded image.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Header {
+    pub(crate) mime: &'static str,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+}
+
+/// Read the header of a PNG, JPEG or GIF image.
+///
+/// The browser decodes images asynchronously, but their size is needed as
+/// soon as they are made, so it is read here.
+pub(crate) fn read_header(data: &[u8]) -> Option<Header> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") && data.get(12..16)? == b"IHDR" {
+        Some(Header {
+            mime: "image/png",
+            width: u32::from_be_bytes(data.get(16..20)?.try_into().ok()?),
+            height: u32::from_be_bytes(data.get(20..24)?.try_into().ok()?),
+        })
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some(Header {
+            mime: "image/gif",
+            width: u16::from_le_bytes(data.get(6..8)?.try_into().ok()?).into(),
+            height: u16::from_le_bytes(data.get(8..10)?.try_into().ok()?).into(),
+        })
+    } else if data.starts_with(b"\xff\xd8") {
+        read_jpeg_header(data)
+    } else {
+        None
+    }
+}
+
+/// Find the size in the start of frame segment of a JPEG image.
+fn read_jpeg_header(data: &[u8]) -> Option<Header> {
+    let mut pos = 2;
+    loop {
+        if *data.get(pos)? != 0xff {
+            return None;
+        }
+        let marker = *data.get(pos + 1)?;
+        match marker {
+            // markers may be padded with any number of fill bytes.
+            0xff => pos += 1,
+            // markers without a segment.
+            0x01 | 0xd0..=0xd7 => pos += 2,
+            // start of frame, except for DHT, JPG and DAC, which share the
+            // range.
+            0xc0..=0xcf if !matches!(marker, 0xc4 | 0xc8 | 0xcc) => {
+                let frame = data.get(pos + 5..pos + 9)?;
+                return Some(Header {
+                    mime: "image/jpeg",
+                    width: u16::from_be_bytes([frame[2], frame[3]]).into(),
+                    height: u16::from_be_bytes([frame[0], frame[1]]).into(),
+                });
+            }
+            _ => {
+                let length = u16::from_be_bytes(data.get(pos + 2..pos + 4)?.try_into().ok()?);
+                pos += 2 + length as usize;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn png_header() {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend_from_slice(&[0, 0, 1, 0, 0, 0, 0, 20]);
+        let header = read_header(&png).unwrap();
+        assert_eq!(
+            (header.mime, header.width, header.height),
+            ("image/png", 256, 20)
+        );
+        assert_eq!(read_header(&png[..20]), None);
+    }
+
+    #[test]
+    fn gif_header() {
+        let header = read_header(b"GIF89a\x0a\x00\x00\x01").unwrap();
+        assert_eq!(
+            (header.mime, header.width, header.height),
+            ("image/gif", 10, 256)
+        );
+    }
+
+    #[test]
+    fn jpeg_header() {
+        let jpeg = [
+            0xff, 0xd8, // start of image
+            0xff, 0xe0, 0x00, 0x04, 0x00, 0x00, // an application segment
+            0xff, 0xff, // fill
+            0xff, 0xc2, 0x00, 0x11, 0x08, 0x00, 0x30, 0x00, 0x40, // progressive frame
+        ];
+        let header = read_header(&jpeg).unwrap();
+        assert_eq!(
+            (header.mime, header.width, header.height),
+            ("image/jpeg", 64, 48)
+        );
+        assert_eq!(read_header(&jpeg[..12]), None);
+        assert_eq!(read_header(b"not an image"), None);
+    }
+}
@@ -0,0 +1,87 @@
+//! A log of the drawing calls that failed, for debugging.
+
+use std::collections::VecDeque;
+
+/// A call to a [`WebRenderContext`] that failed, as logged when errors are
+/// logged with [`log_errors`].
+///
+/// [`WebRenderContext`]: crate::WebRenderContext
+/// [`log_errors`]: crate::WebRenderContext::log_errors
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FailedCall {
+    /// The name of the method that failed, such as `"draw_image"`.
+    pub method: &'static str,
+    /// The arguments that identify the call, formatted for debugging.
+    pub args: String,
+    /// The message of the error.
+    pub message: String,
+}
+
+/// The most recent failed calls, up to a capacity.
+#[derive(Default)]
+pub(crate) struct ErrorLog {
+    calls: VecDeque<FailedCall>,
+    capacity: usize,
+}
+
+impl ErrorLog {
+    /// Set how many calls are kept, dropping the oldest ones if there are
+    /// more than that. Nothing is logged if it is 0.
+    pub(crate) fn set_capacity(&mut self, capacity: usize) {
+        let excess = self.calls.len().saturating_sub(capacity);
+        self.calls.drain(..excess);
+        self.capacity = capacity;
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Add a call, dropping the oldest one if the log is full.
+    pub(crate) fn push(&mut self, call: FailedCall) {
+        if !self.is_enabled() {
+            return;
+        }
+        if self.calls.len() == self.capacity {
+            self.calls.pop_front();
+        }
+        self.calls.push_back(call);
+    }
+
+    /// Remove all of the calls, oldest first.
+    pub(crate) fn take(&mut self) -> Vec<FailedCall> {
+        self.calls.drain(..).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(args: &str) -> FailedCall {
+        FailedCall {
+            method: "fill",
+            args: args.into(),
+            message: String::new(),
+        }
+    }
+
+    #[test]
+    fn oldest_calls_are_dropped() {
+        let mut log = ErrorLog::default();
+        log.push(call("ignored"));
+        assert!(log.take().is_empty());
+
+        log.set_capacity(2);
+        for args in ["a", "b", "c"] {
+            log.push(call(args));
+        }
+        assert_eq!(log.take(), vec![call("b"), call("c")]);
+        assert!(log.take().is_empty());
+
+        log.push(call("d"));
+        log.push(call("e"));
+        log.set_capacity(1);
+        assert_eq!(log.take(), vec![call("e")]);
+    }
+}
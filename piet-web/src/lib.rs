@@ -1,12 +1,16 @@
-// allows e.g. raw_data[dst_off + x * 4 + 2] = buf[src_off + x * 4 + 0];
-#![allow(clippy::identity_op)]
 #![deny(clippy::trivially_copy_pass_by_ref)]
 
 //! The Web Canvas backend for the Piet 2D graphics abstraction.
 
+mod context_loss;
+mod encoded;
+mod error_log;
+mod mirror;
+mod render_loop;
 mod text;
 
 use std::borrow::Cow;
+use std::f64::consts::TAU;
 use std::fmt;
 use std::marker::PhantomData;
 use std::ops::Deref;
@@ -14,18 +18,25 @@ use std::ops::Deref;
 use js_sys::{Float64Array, Reflect};
 use wasm_bindgen::{Clamped, JsCast, JsValue};
 use web_sys::{
-    CanvasGradient, CanvasRenderingContext2d, CanvasWindingRule, DomMatrix, HtmlCanvasElement,
-    ImageData, Window,
+    CanvasGradient, CanvasRenderingContext2d, CanvasWindingRule, DomMatrix, Element,
+    HtmlCanvasElement, HtmlImageElement, ImageData, Path2d, Window,
 };
 
-use piet::kurbo::{Affine, PathEl, Point, Rect, Shape, Size};
+use piet::kurbo::{Affine, BezPath, PathEl, Point, Rect, Shape, Size, Vec2};
 
-use piet::util::unpremul;
 use piet::{
-    Color, Error, FixedGradient, GradientStop, Image, ImageFormat, InterpolationMode, IntoBrush,
-    LineCap, LineJoin, RenderContext, StrokeDash, StrokeStyle,
+    BlendMode, Capabilities, Color, Error, FixedGradient, GradientStop, HitTester, Image,
+    ImageFilter, ImageFormat, InterpolationMode, IntoBrush, LineCap, LineJoin, RenderContext,
+    StrokeDash, StrokeStyle, Symbol, TextLayout, TextShapingQuality, DEFAULT_TOLERANCE,
 };
+use unicode_segmentation::UnicodeSegmentation;
 
+use context_loss::ContextLoss;
+use error_log::ErrorLog;
+use mirror::TextMirror;
+
+pub use error_log::FailedCall;
+pub use render_loop::{FrameInfo, WebRenderLoop};
 pub use text::{WebFont, WebTextLayout, WebTextLayoutBuilder};
 
 pub struct WebRenderContext<'a> {
@@ -34,21 +45,223 @@ pub struct WebRenderContext<'a> {
     window: Window,
     text: WebText,
     err: Result<(), Error>,
+    /// The calls that failed, if they are logged; see
+    /// [`WebRenderContext::log_errors`].
+    error_log: ErrorLog,
+    /// Whether errors panic; see [`WebRenderContext::set_strict`].
+    strict: bool,
+    /// The transform of the canvas before piet transformed it, such as the
+    /// scale for the device pixel ratio. Non-scaling strokes are sized in its
+    /// units.
+    base_transform: Affine,
     canvas_states: Vec<CanvasState>,
+    /// The opacity masks that are being drawn through, innermost last.
+    opacity_masks: Vec<OpacityMask>,
+    /// Set if drawn text is copied into the DOM; see
+    /// [`WebRenderContext::mirror_text`].
+    text_mirror: Option<TextMirror>,
+    hit_tester: HitTester,
+    /// Listens for the context being lost, if it has a canvas.
+    context_loss: Option<ContextLoss>,
     _phantom: PhantomData<&'a ()>,
 }
 
 impl WebRenderContext<'_> {
     pub fn new(ctx: CanvasRenderingContext2d, window: Window) -> WebRenderContext<'static> {
+        let context_loss = ContextLoss::new(&ctx);
+        let base_transform = match ctx.get_transform() {
+            Ok(matrix) => matrix_to_affine(matrix),
+            Err(_) => Affine::IDENTITY,
+        };
         WebRenderContext {
             ctx: ctx.clone(),
             window,
             text: WebText::new(ctx),
             err: Ok(()),
-            canvas_states: vec![CanvasState::default()],
+            error_log: ErrorLog::default(),
+            strict: false,
+            base_transform,
+            // The context may have been transformed before it was handed to us.
+            canvas_states: vec![CanvasState {
+                transform: None,
+                ..CanvasState::default()
+            }],
+            opacity_masks: Vec::new(),
+            text_mirror: None,
+            hit_tester: HitTester::new(),
+            context_loss,
             _phantom: PhantomData,
         }
     }
+
+    /// Notify the context that the canvas transform was changed outside of piet.
+    ///
+    /// The transform is normally tracked without querying the canvas; if you
+    /// modify the transform of the underlying `CanvasRenderingContext2d`
+    /// directly, call this so that [`current_transform`] remains accurate.
+    ///
+    /// [`current_transform`]: RenderContext::current_transform
+    pub fn invalidate_transform(&mut self) {
+        self.canvas_states.last_mut().unwrap().transform = None;
+    }
+
+    /// Returns `true` if the canvas context has been lost, and not restored.
+    ///
+    /// Browsers can take the memory of canvases back, such as when there are
+    /// many of them, or when a page is in the background on a mobile device.
+    /// Nothing is drawn while the context is lost, and [`status`] and
+    /// [`finish`] return [`Error::BackendLost`]. Once it is restored, call
+    /// [`reinitialize`] before drawing again.
+    ///
+    /// Only contexts of canvases that are elements are known to be lost.
+    ///
+    /// [`status`]: RenderContext::status
+    /// [`finish`]: RenderContext::finish
+    /// [`reinitialize`]: WebRenderContext::reinitialize
+    pub fn is_context_lost(&self) -> bool {
+        self.context_loss.as_ref().is_some_and(ContextLoss::is_lost)
+    }
+
+    /// Prepare to draw again after the canvas context was lost and
+    /// restored.
+    ///
+    /// A restored context is blank, and has the default state, so the
+    /// transform and the saved states of this context are reset to match.
+    /// Images are kept in canvases too, so the images that were made before
+    /// the context was lost should be made again.
+    ///
+    /// Returns [`Error::BackendLost`] if the context hasn't been restored
+    /// yet.
+    pub fn reinitialize(&mut self) -> Result<(), Error> {
+        if self.is_context_lost() {
+            return Err(Error::BackendLost);
+        }
+        self.canvas_states = vec![CanvasState::default()];
+        self.base_transform = Affine::IDENTITY;
+        self.err = Ok(());
+        Ok(())
+    }
+
+    /// Copy the text that is drawn into elements in `root`, so that screen
+    /// readers can read it.
+    ///
+    /// Each layout drawn with [`draw_text`] is copied into a `div` in `root`,
+    /// in the order they are drawn, which is placed over where the layout
+    /// was drawn on the canvas and has transparent text. If `root` is the
+    /// canvas itself, the copies are its fallback content, which isn't
+    /// displayed; otherwise, `root` should be a positioned element over the
+    /// canvas, which also lets the text be selected. The role of each copy
+    /// can be set with [`WebTextLayout::set_aria_role`].
+    ///
+    /// The copies of the text that wasn't drawn again are removed when
+    /// [`finish`] is called. Copies made by an earlier context in the same
+    /// root are reused, so a new context can be made for each frame.
+    ///
+    /// [`draw_text`]: RenderContext::draw_text
+    /// [`finish`]: RenderContext::finish
+    pub fn mirror_text(&mut self, root: Element) -> Result<(), Error> {
+        let document = self.window.document().ok_or(Error::NotSupported)?;
+        self.text_mirror = Some(TextMirror::new(document, root));
+        Ok(())
+    }
+
+    /// Keep a log of the last `capacity` calls that failed, to be returned by
+    /// [`status_detailed`].
+    ///
+    /// Drawing methods don't return errors, so a failure is only reported by
+    /// the next call to [`status`] or [`finish`], as the last error, which
+    /// doesn't say which call it came from. The log also records the method
+    /// and the arguments of each call that failed. Errors aren't logged by
+    /// default, and a capacity of 0 stops logging them.
+    ///
+    /// [`status_detailed`]: WebRenderContext::status_detailed
+    /// [`status`]: RenderContext::status
+    /// [`finish`]: RenderContext::finish
+    pub fn log_errors(&mut self, capacity: usize) {
+        self.error_log.set_capacity(capacity);
+    }
+
+    /// Returns the calls that failed since this was last called, oldest
+    /// first, if errors are logged; see [`log_errors`].
+    ///
+    /// This also clears the error that [`status`] returns, though that
+    /// doesn't clear the log.
+    ///
+    /// [`log_errors`]: WebRenderContext::log_errors
+    /// [`status`]: RenderContext::status
+    pub fn status_detailed(&mut self) -> Vec<FailedCall> {
+        self.err = Ok(());
+        self.error_log.take()
+    }
+
+    /// Panic when a call fails, rather than returning the error from
+    /// [`status`] later.
+    ///
+    /// The context never panics by default, so a failure in the browser
+    /// doesn't take down the app. Strict mode is for development, where a
+    /// panic at the call that failed is easier to debug. Errors that are
+    /// returned directly, such as from [`make_image`], are still returned.
+    ///
+    /// [`status`]: RenderContext::status
+    /// [`make_image`]: RenderContext::make_image
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Prepare to draw a frame after the last one, with `transform` as the
+    /// base transform, and with no hit regions.
+    ///
+    /// The saves of the last frame are restored, unless the state of the
+    /// canvas was `reset`, such as by resizing it.
+    fn begin_frame(&mut self, transform: Affine, reset: bool) {
+        if reset {
+            self.canvas_states = vec![CanvasState::default()];
+        } else {
+            while self.canvas_states.len() > 1 {
+                self.canvas_states.pop();
+                self.ctx.restore();
+            }
+        }
+        let a = transform.as_coeffs();
+        let _ = self.ctx.set_transform(a[0], a[1], a[2], a[3], a[4], a[5]);
+        self.canvas_states[0].transform = Some(transform);
+        self.base_transform = transform;
+        self.hit_tester.clear();
+    }
+
+    /// Record the error of a call that failed, for [`status`] and the log.
+    ///
+    /// `args` is only called if errors are logged.
+    ///
+    /// [`status`]: RenderContext::status
+    fn record_error(&mut self, method: &'static str, args: impl FnOnce() -> String, error: Error) {
+        if self.strict {
+            panic!("{} failed: {}", method, error);
+        }
+        if self.error_log.is_enabled() {
+            self.error_log.push(FailedCall {
+                method,
+                args: args(),
+                message: error.to_string(),
+            });
+        }
+        self.err = Err(error);
+    }
+}
+
+/// An opacity mask that is being drawn through. What is drawn through it is
+/// drawn into an offscreen canvas, which is masked and then drawn into the
+/// canvas that was being drawn into when the mask was begun.
+struct OpacityMask {
+    /// The context that was being drawn into when the mask was begun.
+    outer: CanvasRenderingContext2d,
+    mask: WebImage,
+    rect: Rect,
+    /// The transform that the rect is in.
+    transform: Affine,
+    /// The number of canvas states when the mask was begun, including the
+    /// one that it saved.
+    depth: usize,
 }
 
 #[derive(Clone)]
@@ -58,6 +271,16 @@ struct CanvasState {
     line_dash_offset: f64,
     line_join: LineJoin,
     line_width: f64,
+    /// The current transform, tracked on the Rust side so that we don't have
+    /// to ask the canvas for it.
+    ///
+    /// This is `None` if the transform is not known, for instance because the
+    /// canvas was modified outside of piet; in that case we query the canvas.
+    transform: Option<Affine>,
+    global_alpha: f64,
+    blend_mode: BlendMode,
+    /// The tolerance that shapes are converted to paths with.
+    tolerance: f64,
 }
 
 impl Default for CanvasState {
@@ -75,6 +298,13 @@ impl Default for CanvasState {
             line_join: LineJoin::Miter { limit: 10. },
             // https://developer.mozilla.org/en-US/docs/Web/API/CanvasRenderingContext2D/lineWidth#value
             line_width: 1.,
+            // https://developer.mozilla.org/en-US/docs/Web/API/CanvasRenderingContext2D/getTransform
+            transform: Some(Affine::IDENTITY),
+            // https://developer.mozilla.org/en-US/docs/Web/API/CanvasRenderingContext2D/globalAlpha#value
+            global_alpha: 1.,
+            // https://developer.mozilla.org/en-US/docs/Web/API/CanvasRenderingContext2D/globalCompositeOperation#value
+            blend_mode: BlendMode::Normal,
+            tolerance: DEFAULT_TOLERANCE,
         }
     }
 }
@@ -98,13 +328,40 @@ pub enum Brush {
 
 #[derive(Clone)]
 pub struct WebImage {
-    /// We use a canvas element for now, but could be ImageData or ImageBitmap,
-    /// so consider an enum.
-    inner: HtmlCanvasElement,
+    inner: ImageSource,
+    /// The position of the image in its source, which is not the origin if
+    /// it is a sub-image.
+    x: u32,
+    y: u32,
     width: u32,
     height: u32,
 }
 
+/// What a [`WebImage`] is drawn from.
+#[derive(Clone)]
+enum ImageSource {
+    /// Pixels, which are kept in a canvas.
+    Canvas(HtmlCanvasElement),
+    /// An encoded image, which the browser decodes.
+    Element(HtmlImageElement),
+}
+
+impl WebImage {
+    /// Returns `true` if the image can be drawn.
+    ///
+    /// Images made with [`make_image_from_encoded`] are decoded by the
+    /// browser in the background, and draw nothing until they are decoded,
+    /// or if they can't be.
+    ///
+    /// [`make_image_from_encoded`]: RenderContext::make_image_from_encoded
+    pub fn is_ready(&self) -> bool {
+        match &self.inner {
+            ImageSource::Canvas(_) => true,
+            ImageSource::Element(element) => element.complete() && element.natural_width() > 0,
+        }
+    }
+}
+
 #[derive(Debug)]
 struct WrappedJs(JsValue);
 
@@ -148,17 +405,7 @@ fn convert_line_join(line_join: LineJoin) -> &'static str {
 }
 
 fn convert_dash_pattern(pattern: &[f64]) -> Float64Array {
-    let len = pattern.len() as u32;
-    let array = Float64Array::new_with_length(len);
-    for (i, elem) in pattern.iter().enumerate() {
-        Reflect::set(
-            array.as_ref(),
-            &JsValue::from(i as u32),
-            &JsValue::from(*elem),
-        )
-        .unwrap();
-    }
-    array
+    Float64Array::from(pattern)
 }
 
 impl RenderContext for WebRenderContext<'_> {
@@ -171,7 +418,11 @@ impl RenderContext for WebRenderContext<'_> {
     type Image = WebImage;
 
     fn status(&mut self) -> Result<(), Error> {
-        std::mem::replace(&mut self.err, Ok(()))
+        let err = std::mem::replace(&mut self.err, Ok(()));
+        if self.is_context_lost() {
+            return Err(Error::BackendLost);
+        }
+        err
     }
 
     fn clear(&mut self, region: impl Into<Option<Rect>>, color: Color) {
@@ -185,7 +436,19 @@ impl RenderContext for WebRenderContext<'_> {
             .into()
             .unwrap_or_else(|| Rect::new(0.0, 0.0, width as f64, height as f64));
         let brush = self.solid_brush(color);
+        // the global alpha and blend mode don't apply to clearing.
+        self.ctx.set_global_alpha(1.0);
+        let blend_mode = self.blend_mode();
+        if blend_mode != BlendMode::Normal {
+            let _ = self.ctx.set_global_composite_operation("source-over");
+        }
         self.fill(rect, &brush);
+        self.ctx.set_global_alpha(self.global_alpha());
+        if blend_mode != BlendMode::Normal {
+            let _ = self
+                .ctx
+                .set_global_composite_operation(blend_mode_operation(blend_mode));
+        }
     }
 
     fn solid_brush(&mut self, color: Color) -> Brush {
@@ -231,6 +494,24 @@ impl RenderContext for WebRenderContext<'_> {
             .fill_with_canvas_winding_rule(CanvasWindingRule::Evenodd);
     }
 
+    fn fill_with_mask(
+        &mut self,
+        shape: impl Shape,
+        brush: &impl IntoBrush<Self>,
+        mask: &Self::Image,
+    ) {
+        let brush = brush.make_brush(self, || shape.bounding_box());
+        if !mask.is_ready() || shape.bounding_box().is_empty() {
+            return;
+        }
+        let bounds = shape.bounding_box();
+        if let Err(e) = self.fill_with_mask_impl(shape, &brush, mask) {
+            let size = mask.size();
+            let args = || format!("bounds: {:?}, mask size: {:?}", bounds, size);
+            self.record_error("fill_with_mask", args, e);
+        }
+    }
+
     fn clip(&mut self, shape: impl Shape) {
         self.set_path(shape);
         self.ctx
@@ -240,7 +521,7 @@ impl RenderContext for WebRenderContext<'_> {
     fn stroke(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>, width: f64) {
         let brush = brush.make_brush(self, || shape.bounding_box());
         self.set_path(shape);
-        self.set_stroke(width, None);
+        self.set_stroke("stroke", width, None);
         self.set_brush(brush.deref(), false);
         self.ctx.stroke();
     }
@@ -252,32 +533,216 @@ impl RenderContext for WebRenderContext<'_> {
         width: f64,
         style: &StrokeStyle,
     ) {
+        if piet::util::stroke_dash_caps(self, &shape, brush, width, style) {
+            return;
+        }
         let brush = brush.make_brush(self, || shape.bounding_box());
-        self.set_path(shape);
-        self.set_stroke(width, Some(style));
+        self.set_path(&shape);
+        self.set_stroke("stroke_styled", width, Some(style));
+        self.set_brush(brush.deref(), false);
+        self.stroke_path("stroke_styled", shape, &brush, width, style);
+    }
+
+    fn fill_and_stroke(
+        &mut self,
+        shape: impl Shape,
+        fill_brush: &impl IntoBrush<Self>,
+        stroke_brush: &impl IntoBrush<Self>,
+        width: f64,
+        style: &StrokeStyle,
+    ) {
+        // dashes with their own caps are drawn by filling them.
+        if style.effective_dash_cap() != style.line_cap && !style.dash_pattern.is_empty() {
+            self.fill(&shape, fill_brush);
+            self.stroke_styled(&shape, stroke_brush, width, style);
+            return;
+        }
+        let fill_brush = fill_brush.make_brush(self, || shape.bounding_box());
+        let stroke_brush = stroke_brush.make_brush(self, || shape.bounding_box());
+        self.set_path(&shape);
+        self.set_brush(&fill_brush, true);
+        self.ctx
+            .fill_with_canvas_winding_rule(CanvasWindingRule::Nonzero);
+        self.set_stroke("fill_and_stroke", width, Some(style));
+        self.set_brush(&stroke_brush, false);
+        self.stroke_path("fill_and_stroke", shape, &stroke_brush, width, style);
+    }
+
+    fn fill_many(
+        &mut self,
+        shapes: impl IntoIterator<Item = impl Shape>,
+        brush: &impl IntoBrush<Self>,
+    ) {
+        let bounds = match self.set_paths(shapes) {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        let brush = brush.make_brush(self, || bounds);
+        self.set_brush(&brush, true);
+        self.ctx
+            .fill_with_canvas_winding_rule(CanvasWindingRule::Nonzero);
+    }
+
+    fn stroke_many(
+        &mut self,
+        shapes: impl IntoIterator<Item = impl Shape>,
+        brush: &impl IntoBrush<Self>,
+        width: f64,
+        style: &StrokeStyle,
+    ) {
+        // dashes with their own caps are drawn by filling them, and strokes
+        // that don't scale are drawn from a path of their own; both are
+        // done by `stroke_styled`.
+        let dash_caps =
+            style.effective_dash_cap() != style.line_cap && !style.dash_pattern.is_empty();
+        if dash_caps || !style.scale {
+            let tolerance = self.tolerance();
+            let mut path = BezPath::new();
+            for shape in shapes {
+                path.extend(shape.path_elements(tolerance));
+            }
+            self.stroke_styled(path, brush, width, style);
+            return;
+        }
+        let bounds = match self.set_paths(shapes) {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        let brush = brush.make_brush(self, || bounds);
+        self.set_stroke("stroke_many", width, Some(style));
         self.set_brush(brush.deref(), false);
         self.ctx.stroke();
     }
 
+    fn draw_symbol_instances(
+        &mut self,
+        symbol: Symbol<Self::Image>,
+        brush: &impl IntoBrush<Self>,
+        instances: &[(Affine, Option<Color>)],
+    ) {
+        let shape = match symbol {
+            Symbol::Shape(shape) if !instances.is_empty() => shape,
+            _ => return piet::util::draw_symbol_instances(self, symbol, brush, instances),
+        };
+        // the path is built once, and only the transform and the color are
+        // changed between instances.
+        let path = match path_2d(shape, self.tolerance()) {
+            Ok(path) => path,
+            Err(e) => {
+                let args = || format!("instances: {}", instances.len());
+                return self.record_error("draw_symbol_instances", args, e);
+            }
+        };
+        let brush = brush.make_brush(self, || shape.bounding_box());
+        let base = self.current_transform();
+        self.ctx.save();
+        let mut fill = None;
+        for &(transform, color) in instances {
+            if fill != Some(color) {
+                match color {
+                    Some(color) => self.set_brush(&Brush::Solid(color.as_rgba_u32()), true),
+                    None => self.set_brush(&brush, true),
+                }
+                fill = Some(color);
+            }
+            let a = (base * transform).as_coeffs();
+            let _ = self.ctx.set_transform(a[0], a[1], a[2], a[3], a[4], a[5]);
+            self.ctx
+                .fill_with_path_2d_and_winding(&path, CanvasWindingRule::Nonzero);
+        }
+        self.ctx.restore();
+    }
+
     fn text(&mut self) -> &mut Self::Text {
         &mut self.text
     }
 
     fn draw_text(&mut self, layout: &Self::TextLayout, pos: impl Into<Point>) {
+        let pos = pos.into();
+        if self.text_mirror.is_some() {
+            self.mirror_text_layout(layout, pos);
+        }
+        let background = layout.background_color();
+        if background != Color::TRANSPARENT {
+            for rect in layout.rects_for_range(..) {
+                self.fill(rect + pos.to_vec2(), &background);
+            }
+        }
+
         // TODO: bounding box for text
         self.ctx.save();
-        self.ctx.set_font(&layout.font.get_font_string());
+        let spacing = layout.configure_ctx(&self.ctx);
         let color = layout.color();
         let brush = color.make_brush(self, || layout.size().to_rect());
         self.set_brush(&brush, true);
-        let pos = pos.into();
-        for lm in &layout.line_metrics {
+        let mut pos = pos;
+        let vertical = layout.writing_mode().is_vertical();
+        if vertical {
+            // lines are laid out horizontally, and rotated into place.
+            let affine = Affine::translate(pos.to_vec2()) * layout.writing_transform();
+            let a = affine.as_coeffs();
+            let _ = self.ctx.transform(a[0], a[1], a[2], a[3], a[4], a[5]);
+            pos = Point::ZERO;
+        }
+        for (line_number, lm) in layout.line_metrics.iter().enumerate() {
             let line_text = &layout.text[lm.range()];
-            let line_y = lm.y_offset + lm.baseline + pos.y;
-            let draw_line = self.ctx.fill_text(line_text, pos.x, line_y).wrap();
+            let line_y = lm.y_offset + lm.baseline + pos.y - layout.baseline_shift();
+            let spacing = layout.line_spacing(spacing, line_number);
+            let runs = text::bidi::visual_runs(&self.ctx, spacing, line_text);
+            let draw_line = if spacing.is_zero() && !vertical && !line_text.contains('\t') {
+                match runs {
+                    // draw each run of a single direction at its visual position.
+                    Some(runs) => runs.iter().try_for_each(|run| {
+                        let run_text = &line_text[run.range.clone()];
+                        self.ctx.fill_text(run_text, pos.x + run.x, line_y).wrap()
+                    }),
+                    None => self.ctx.fill_text(line_text, pos.x, line_y).wrap(),
+                }
+            } else {
+                // the canvas can't space or orient the text for us, so we place
+                // each grapheme individually.
+                let x_pos = |idx| layout.line_position(spacing, line_number, idx);
+                line_text
+                    .grapheme_indices(true)
+                    .try_for_each(|(idx, grapheme)| {
+                        let (start, end) = (x_pos(idx), x_pos(idx + grapheme.len()));
+                        // in right-to-left runs the grapheme's start is its right edge
+                        let x = start.min(end);
+                        let upright = grapheme
+                            .chars()
+                            .next()
+                            .map(text::is_upright_in_vertical)
+                            .unwrap_or(false);
+                        if vertical && upright {
+                            // rotate the grapheme back around the center of its cell
+                            let center_x = pos.x + (start + end) / 2.0;
+                            let center_y = pos.y + lm.y_offset + lm.height / 2.0;
+                            self.ctx.save();
+                            let _ = self.ctx.translate(center_x, center_y);
+                            let _ = self.ctx.rotate(-std::f64::consts::FRAC_PI_2);
+                            self.ctx.set_text_align("center");
+                            self.ctx.set_text_baseline("middle");
+                            let result = self.ctx.fill_text(grapheme, 0.0, 0.0).wrap();
+                            self.ctx.restore();
+                            result
+                        } else {
+                            self.ctx.fill_text(grapheme, pos.x + x, line_y).wrap()
+                        }
+                    })
+            };
+
+            let draw_line = draw_line.and_then(|_| {
+                if layout.is_hyphenated(line_number) {
+                    let x = layout.line_position(spacing, line_number, line_text.len());
+                    self.ctx.fill_text(text::HYPHEN, pos.x + x, line_y).wrap()
+                } else {
+                    Ok(())
+                }
+            });
 
             if let Err(e) = draw_line {
-                self.err = Err(e);
+                let args = || format!("line {}: {:?}", line_number, line_text);
+                self.record_error("draw_text", args, e);
             }
         }
         self.ctx.restore();
@@ -291,6 +756,10 @@ impl RenderContext for WebRenderContext<'_> {
     }
 
     fn restore(&mut self) -> Result<(), Error> {
+        // the state saved by an opacity mask is restored by ending it.
+        if self.opacity_masks.last().map(|mask| mask.depth) == Some(self.canvas_states.len()) {
+            return Err(Error::StackUnbalance);
+        }
         // restore state only if there is a state to restore
         if self.canvas_states.len() > 1 {
             self.canvas_states.pop();
@@ -300,16 +769,129 @@ impl RenderContext for WebRenderContext<'_> {
     }
 
     fn finish(&mut self) -> Result<(), Error> {
+        if let Some(mirror) = &mut self.text_mirror {
+            mirror.finish_frame();
+        }
         self.status()
     }
 
     fn transform(&mut self, transform: Affine) {
         let a = transform.as_coeffs();
         let _ = self.ctx.transform(a[0], a[1], a[2], a[3], a[4], a[5]);
+        let new_transform = match self.canvas_states.last().unwrap().transform {
+            Some(current) => current * transform,
+            // resync with the canvas, so we only pay for the query once.
+            None => self.canvas_transform(),
+        };
+        self.canvas_states.last_mut().unwrap().transform = Some(new_transform);
     }
 
     fn current_transform(&self) -> Affine {
-        matrix_to_affine(self.ctx.get_transform().unwrap())
+        match self.canvas_states.last().unwrap().transform {
+            Some(transform) => transform,
+            None => self.canvas_transform(),
+        }
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        let mut capabilities = Capabilities::default();
+        capabilities.supports_blend_modes = true;
+        capabilities.supports_blur = true;
+        capabilities.supports_global_alpha = true;
+        capabilities.text_shaping_quality = TextShapingQuality::Full;
+        capabilities
+    }
+
+    fn set_global_alpha(&mut self, alpha: f64) {
+        // the canvas ignores values outside of 0..=1, rather than clamping them.
+        let alpha = if alpha.is_nan() {
+            0.0
+        } else {
+            alpha.clamp(0.0, 1.0)
+        };
+        self.ctx.set_global_alpha(alpha);
+        self.canvas_states.last_mut().unwrap().global_alpha = alpha;
+    }
+
+    fn global_alpha(&self) -> f64 {
+        self.canvas_states.last().unwrap().global_alpha
+    }
+
+    fn set_blend_mode(&mut self, mode: BlendMode) {
+        let operation = blend_mode_operation(mode);
+        match self.ctx.set_global_composite_operation(operation).wrap() {
+            Ok(()) => self.canvas_states.last_mut().unwrap().blend_mode = mode,
+            Err(e) => self.record_error("set_blend_mode", || format!("{:?}", mode), e),
+        }
+    }
+
+    fn blend_mode(&self) -> BlendMode {
+        self.canvas_states.last().unwrap().blend_mode
+    }
+
+    fn set_tolerance(&mut self, tolerance: f64) {
+        if tolerance > 0.0 && tolerance.is_finite() {
+            self.canvas_states.last_mut().unwrap().tolerance = tolerance;
+        }
+    }
+
+    fn tolerance(&self) -> f64 {
+        self.canvas_states.last().unwrap().tolerance
+    }
+
+    fn begin_opacity_mask(
+        &mut self,
+        mask: &Self::Image,
+        rect: impl Into<Rect>,
+    ) -> Result<(), Error> {
+        let canvas = self.ctx.canvas().ok_or(Error::NotSupported)?;
+        let layer = self.create_canvas()?;
+        layer.set_width(canvas.width());
+        layer.set_height(canvas.height());
+        let layer = canvas_context(&layer)?;
+        let transform = self.current_transform();
+        let a = transform.as_coeffs();
+        layer
+            .set_transform(a[0], a[1], a[2], a[3], a[4], a[5])
+            .wrap()?;
+        layer.set_global_alpha(self.global_alpha());
+        // the layer's own state is restored before it is masked, in case
+        // something was clipped without being saved.
+        layer.save();
+
+        self.save()?;
+        // the layer is drawn with the blend mode, so what is drawn into it
+        // starts with the canvas's defaults.
+        let state = self.canvas_states.last_mut().unwrap();
+        *state = CanvasState {
+            transform: Some(transform),
+            global_alpha: state.global_alpha,
+            tolerance: state.tolerance,
+            ..CanvasState::default()
+        };
+        let outer = std::mem::replace(&mut self.ctx, layer);
+        self.opacity_masks.push(OpacityMask {
+            outer,
+            mask: mask.clone(),
+            rect: rect.into(),
+            transform,
+            depth: self.canvas_states.len(),
+        });
+        Ok(())
+    }
+
+    fn end_opacity_mask(&mut self) -> Result<(), Error> {
+        match self.opacity_masks.last() {
+            Some(mask) if mask.depth == self.canvas_states.len() => (),
+            _ => return Err(Error::StackUnbalance),
+        }
+        let mask = self.opacity_masks.pop().unwrap();
+        let layer = std::mem::replace(&mut self.ctx, mask.outer.clone());
+        self.draw_opacity_mask(&layer, &mask).and(self.restore())
+    }
+
+    fn hit_tester(&mut self) -> Option<&mut HitTester> {
+        Some(&mut self.hit_tester)
     }
 
     fn make_image(
@@ -319,63 +901,46 @@ impl RenderContext for WebRenderContext<'_> {
         buf: &[u8],
         format: ImageFormat,
     ) -> Result<Self::Image, Error> {
-        let document = self.window.document().unwrap();
-        let element = document.create_element("canvas").unwrap();
-        let canvas = element.dyn_into::<HtmlCanvasElement>().unwrap();
+        let image_data = image_data(width, height, buf, format)?;
+        let canvas = self.create_canvas()?;
         canvas.set_width(width as u32);
         canvas.set_height(height as u32);
-        let mut new_buf: Vec<u8>;
-        let buf = match format {
-            ImageFormat::RgbaSeparate => buf,
-            ImageFormat::RgbaPremul => {
-                new_buf = vec![0; width * height * 4];
-                for i in 0..width * height {
-                    let a = buf[i * 4 + 3];
-                    new_buf[i * 4 + 0] = unpremul(buf[i * 4 + 0], a);
-                    new_buf[i * 4 + 1] = unpremul(buf[i * 4 + 1], a);
-                    new_buf[i * 4 + 2] = unpremul(buf[i * 4 + 2], a);
-                    new_buf[i * 4 + 3] = a;
-                }
-                new_buf.as_slice()
-            }
-            ImageFormat::Rgb => {
-                new_buf = vec![0; width * height * 4];
-                for i in 0..width * height {
-                    new_buf[i * 4 + 0] = buf[i * 3 + 0];
-                    new_buf[i * 4 + 1] = buf[i * 3 + 1];
-                    new_buf[i * 4 + 2] = buf[i * 3 + 2];
-                    new_buf[i * 4 + 3] = 255;
-                }
-                new_buf.as_slice()
-            }
-            ImageFormat::Grayscale => {
-                new_buf = vec![0; width * height * 4];
-                for i in 0..width * height {
-                    new_buf[i * 4 + 0] = buf[i];
-                    new_buf[i * 4 + 1] = buf[i];
-                    new_buf[i * 4 + 2] = buf[i];
-                    new_buf[i * 4 + 3] = 255;
-                }
-                new_buf.as_slice()
-            }
-            _ => &[],
-        };
-
-        let image_data = ImageData::new_with_u8_clamped_array(Clamped(buf), width as u32).wrap()?;
-        let context = canvas
-            .get_context("2d")
-            .unwrap()
-            .unwrap()
-            .dyn_into::<web_sys::CanvasRenderingContext2d>()
-            .unwrap();
-        context.put_image_data(&image_data, 0.0, 0.0).wrap()?;
+        canvas_context(&canvas)?
+            .put_image_data(&image_data, 0.0, 0.0)
+            .wrap()?;
         Ok(WebImage {
-            inner: canvas,
+            inner: ImageSource::Canvas(canvas),
+            x: 0,
+            y: 0,
             width: width as u32,
             height: height as u32,
         })
     }
 
+    fn make_image_from_encoded(&mut self, data: &[u8]) -> Result<Self::Image, Error> {
+        // the browser decodes the image in the background, so its size is
+        // read from its header.
+        let header = encoded::read_header(data).ok_or(Error::InvalidInput)?;
+        let document = self.window.document().ok_or(Error::NotSupported)?;
+        let element = document
+            .create_element("img")
+            .wrap()?
+            .dyn_into::<HtmlImageElement>()
+            .map_err(JsValue::from)
+            .wrap()?;
+        // `btoa` takes a string of bytes, one per character.
+        let bytes = data.iter().copied().map(char::from).collect::<String>();
+        let base64 = self.window.btoa(&bytes).wrap()?;
+        element.set_src(&format!("data:{};base64,{}", header.mime, base64));
+        Ok(WebImage {
+            inner: ImageSource::Element(element),
+            x: 0,
+            y: 0,
+            width: header.width,
+            height: header.height,
+        })
+    }
+
     #[inline]
     fn draw_image(
         &mut self,
@@ -401,6 +966,33 @@ impl RenderContext for WebRenderContext<'_> {
         Err(Error::Unimplemented)
     }
 
+    fn draw_image_filtered(
+        &mut self,
+        image: &Self::Image,
+        dst_rect: impl Into<Rect>,
+        filters: &[ImageFilter],
+    ) {
+        let dst_rect = dst_rect.into();
+        if !image.is_ready() || dst_rect.is_empty() {
+            return;
+        }
+        let result = match css_filter(filters, self.current_transform()) {
+            Some(filter) => self.with_save(|rc| {
+                // the filter is part of the state that `with_save` restores.
+                rc.ctx.set_filter(&filter);
+                draw_image(rc, image, None, dst_rect, InterpolationMode::Bilinear);
+                Ok(())
+            }),
+            // color matrices have no CSS filter function, so those are
+            // applied to the pixels of the image.
+            None => self.draw_image_filtered_pixels(image, dst_rect, filters),
+        };
+        if let Err(e) = result {
+            let args = || format!("{:?}, {:?}", dst_rect, filters);
+            self.record_error("draw_image_filtered", args, e);
+        }
+    }
+
     fn blurred_rect(&mut self, rect: Rect, blur_radius: f64, brush: &impl IntoBrush<Self>) {
         let brush = brush.make_brush(self, || rect);
         self.ctx.set_shadow_blur(blur_radius);
@@ -416,23 +1008,119 @@ impl RenderContext for WebRenderContext<'_> {
     }
 }
 
+/// Convert pixels in `format` to the image data of a canvas.
+fn image_data(
+    width: usize,
+    height: usize,
+    buf: &[u8],
+    format: ImageFormat,
+) -> Result<ImageData, Error> {
+    if format == ImageFormat::Rgba16 {
+        let buf = piet::util::rgba16_to_rgba8(buf);
+        return image_data(width, height, &buf, ImageFormat::RgbaSeparate);
+    }
+    let mut new_buf: Vec<u8>;
+    let buf = match format {
+        ImageFormat::RgbaSeparate => buf,
+        ImageFormat::RgbaPremul => {
+            new_buf = buf.to_vec();
+            piet::util::unpremultiply_rgba(&mut new_buf);
+            new_buf.as_slice()
+        }
+        ImageFormat::BgraSeparate => {
+            new_buf = buf.to_vec();
+            piet::util::swap_red_blue(&mut new_buf);
+            new_buf.as_slice()
+        }
+        ImageFormat::BgraPremul => {
+            new_buf = buf.to_vec();
+            piet::util::swap_red_blue(&mut new_buf);
+            piet::util::unpremultiply_rgba(&mut new_buf);
+            new_buf.as_slice()
+        }
+        ImageFormat::Rgb => {
+            new_buf = vec![0; width * height * 4];
+            piet::util::rgb_to_rgba(buf, &mut new_buf);
+            new_buf.as_slice()
+        }
+        ImageFormat::Grayscale => {
+            new_buf = vec![0; width * height * 4];
+            piet::util::gray_to_rgba(buf, &mut new_buf);
+            new_buf.as_slice()
+        }
+        _ => &[],
+    };
+
+    ImageData::new_with_u8_clamped_array(Clamped(buf), width as u32).wrap()
+}
+
+/// The 2D context of a canvas.
+fn canvas_context(canvas: &HtmlCanvasElement) -> Result<CanvasRenderingContext2d, Error> {
+    canvas
+        .get_context("2d")
+        .wrap()?
+        // the canvas already has a context of another type.
+        .ok_or(Error::NotSupported)?
+        .dyn_into::<CanvasRenderingContext2d>()
+        .map_err(JsValue::from)
+        .wrap()
+}
+
 fn draw_image(
     ctx: &mut WebRenderContext,
     image: &<WebRenderContext as RenderContext>::Image,
     src_rect: Option<Rect>,
     dst_rect: Rect,
-    _interp: InterpolationMode,
+    interp: InterpolationMode,
 ) {
+    if !image.is_ready() {
+        return;
+    }
     let result = ctx.with_save(|rc| {
-        // TODO: Implement InterpolationMode::NearestNeighbor in software
-        //       See for inspiration http://phrogz.net/tmp/canvas_image_zoom.html
+        // both of these are part of the state that `with_save` restores.
+        let quality = match interp {
+            InterpolationMode::NearestNeighbor => None,
+            InterpolationMode::Bilinear => Some("low"),
+            InterpolationMode::Bicubic => Some("medium"),
+            InterpolationMode::HighQuality => Some("high"),
+        };
+        rc.ctx.set_image_smoothing_enabled(quality.is_some());
+        if let Some(quality) = quality {
+            // web-sys has no binding for this, and browsers without it ignore it.
+            Reflect::set(
+                &rc.ctx,
+                &JsValue::from_str("imageSmoothingQuality"),
+                &JsValue::from_str(quality),
+            )
+            .wrap()?;
+        }
         let src_rect = match src_rect {
             Some(src_rect) => src_rect,
             None => Rect::new(0.0, 0.0, image.width as f64, image.height as f64),
         };
-        rc.ctx
+        draw_image_source(&rc.ctx, image, src_rect, dst_rect).wrap()
+    });
+    if let Err(e) = result {
+        let (method, args) = match src_rect {
+            Some(src_rect) => ("draw_image_area", format!("{:?}, {:?}", src_rect, dst_rect)),
+            None => ("draw_image", format!("{:?}", dst_rect)),
+        };
+        ctx.record_error(method, || args, e);
+    }
+}
+
+/// Draw the `src_rect` area of an image into `dst_rect` of a canvas.
+fn draw_image_source(
+    ctx: &CanvasRenderingContext2d,
+    image: &WebImage,
+    src_rect: Rect,
+    dst_rect: Rect,
+) -> Result<(), JsValue> {
+    let src_rect = src_rect + Vec2::new(image.x.into(), image.y.into());
+    match &image.inner {
+        ImageSource::Canvas(canvas) => ctx
             .draw_image_with_html_canvas_element_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
-                &image.inner,
+                canvas,
                 src_rect.x0,
                 src_rect.y0,
                 src_rect.width(),
@@ -441,11 +1129,19 @@ fn draw_image(
                 dst_rect.y0,
                 dst_rect.width(),
                 dst_rect.height(),
-            )
-            .wrap()
-    });
-    if let Err(e) = result {
-        ctx.err = Err(e);
+            ),
+        ImageSource::Element(element) => ctx
+            .draw_image_with_html_image_element_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
+                element,
+                src_rect.x0,
+                src_rect.y0,
+                src_rect.width(),
+                src_rect.height(),
+                dst_rect.x0,
+                dst_rect.y0,
+                dst_rect.width(),
+                dst_rect.height(),
+            ),
     }
 }
 
@@ -463,6 +1159,119 @@ impl Image for WebImage {
     fn size(&self) -> Size {
         Size::new(self.width.into(), self.height.into())
     }
+
+    fn sub_image(&self, rect: impl Into<Rect>) -> Self {
+        let view = Rect::from_origin_size((self.x as f64, self.y as f64), self.size());
+        let view = piet::util::sub_image_view(view, rect.into());
+        WebImage {
+            inner: self.inner.clone(),
+            x: view.x0 as u32,
+            y: view.y0 as u32,
+            width: view.width() as u32,
+            height: view.height() as u32,
+        }
+    }
+
+    fn update_region(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        buf: &[u8],
+        format: ImageFormat,
+    ) -> Result<(), Error> {
+        let canvas = match &self.inner {
+            ImageSource::Canvas(canvas) => canvas,
+            // decoded images can't be written to.
+            ImageSource::Element(_) => return Err(Error::NotSupported),
+        };
+        if x + width > self.width as usize
+            || y + height > self.height as usize
+            || buf.len() < width * height * format.bytes_per_pixel()
+        {
+            return Err(Error::InvalidInput);
+        }
+        if width == 0 || height == 0 {
+            return Ok(());
+        }
+        let image_data = image_data(width, height, buf, format)?;
+        let (x, y) = (x + self.x as usize, y + self.y as usize);
+        canvas_context(canvas)?
+            .put_image_data(&image_data, x as f64, y as f64)
+            .wrap()
+    }
+
+    /// Decoded images are drawn into an offscreen canvas to be read, so they
+    /// must be [ready](WebImage::is_ready). Reading images from other origins
+    /// that don't allow it fails, as with any canvas.
+    fn to_rgba_bytes(&self) -> Result<Vec<u8>, Error> {
+        let size = self.size();
+        if size.is_empty() {
+            return Ok(Vec::new());
+        }
+        let (ctx, origin) = match &self.inner {
+            ImageSource::Canvas(canvas) => (canvas_context(canvas)?, (self.x, self.y)),
+            ImageSource::Element(element) => {
+                if !self.is_ready() {
+                    return Err(Error::NotSupported);
+                }
+                let canvas = element
+                    .owner_document()
+                    .ok_or(Error::NotSupported)?
+                    .create_element("canvas")
+                    .wrap()?
+                    .dyn_into::<HtmlCanvasElement>()
+                    .map_err(JsValue::from)
+                    .wrap()?;
+                canvas.set_width(self.width);
+                canvas.set_height(self.height);
+                let ctx = canvas_context(&canvas)?;
+                draw_image_source(&ctx, self, size.to_rect(), size.to_rect()).wrap()?;
+                (ctx, (0, 0))
+            }
+        };
+        // image data has separate alpha.
+        let (x, y) = (origin.0 as f64, origin.1 as f64);
+        let data = ctx.get_image_data(x, y, size.width, size.height).wrap()?;
+        Ok(data.data().0)
+    }
+}
+
+/// The CSS filter of `filters`, with lengths in the pixels of the canvas,
+/// or `None` if one of them has no CSS filter function.
+///
+/// Canvas filters aren't transformed, so their lengths are transformed here.
+fn css_filter(filters: &[ImageFilter], transform: Affine) -> Option<String> {
+    let scale = transform.determinant().abs().sqrt();
+    let linear = |v: Vec2| (transform * v.to_point()) - (transform * Point::ORIGIN);
+    let mut css = Vec::with_capacity(filters.len());
+    for filter in filters {
+        css.push(match *filter {
+            ImageFilter::Blur { radius } => format!("blur({}px)", radius.abs() * scale),
+            // the blur of a CSS shadow is twice its standard deviation.
+            ImageFilter::DropShadow {
+                offset,
+                radius,
+                color,
+            } => {
+                let offset = linear(offset);
+                format!(
+                    "drop-shadow({}px {}px {}px {})",
+                    offset.x,
+                    offset.y,
+                    2.0 * radius.abs() * scale,
+                    format_color(color.as_rgba_u32())
+                )
+            }
+            _ => return None,
+        });
+    }
+    Some(if css.is_empty() {
+        "none".into()
+    } else {
+        css.join(" ")
+    })
 }
 
 fn format_color(rgba: u32) -> String {
@@ -495,12 +1304,7 @@ impl WebRenderContext<'_> {
     /// Web canvas is super stateful, and we're trying to have more retained stuff.
     /// This is part of the impedance matching.
     fn set_brush(&mut self, brush: &Brush, is_fill: bool) {
-        let value = self.brush_value(brush);
-        if is_fill {
-            self.ctx.set_fill_style(&value);
-        } else {
-            self.ctx.set_stroke_style(&value);
-        }
+        set_brush(&self.ctx, &self.brush_value(brush), is_fill);
     }
 
     fn brush_value(&self, brush: &Brush) -> JsValue {
@@ -510,8 +1314,8 @@ impl WebRenderContext<'_> {
         }
     }
 
-    /// Set the stroke parameters.
-    fn set_stroke(&mut self, width: f64, style: Option<&StrokeStyle>) {
+    /// Set the stroke parameters, for a call to `method`.
+    fn set_stroke(&mut self, method: &'static str, width: f64, style: Option<&StrokeStyle>) {
         let default_style = StrokeStyle::default();
         let style = style.unwrap_or(&default_style);
         let mut canvas_state = self.canvas_states.last_mut().unwrap();
@@ -534,34 +1338,408 @@ impl WebRenderContext<'_> {
             canvas_state.line_cap = style.line_cap;
         }
 
+        let mut dash_result = Ok(());
         if style.dash_pattern != canvas_state.line_dash {
             let dash_segs = convert_dash_pattern(&style.dash_pattern);
-            self.ctx.set_line_dash(dash_segs.as_ref()).unwrap();
-            canvas_state.line_dash = style.dash_pattern.clone();
+            dash_result = self.ctx.set_line_dash(dash_segs.as_ref()).wrap();
+            if dash_result.is_ok() {
+                canvas_state.line_dash = style.dash_pattern.clone();
+            }
         }
 
         if style.dash_offset != canvas_state.line_dash_offset {
             self.ctx.set_line_dash_offset(style.dash_offset);
             canvas_state.line_dash_offset = style.dash_offset;
         }
+
+        if let Err(e) = dash_result {
+            let args = || format!("dash pattern: {:?}", style.dash_pattern);
+            self.record_error(method, args, e);
+        }
+    }
+
+    /// The transform of the canvas, which is queried when it isn't known.
+    fn canvas_transform(&self) -> Affine {
+        match self.ctx.get_transform() {
+            Ok(matrix) => matrix_to_affine(matrix),
+            Err(e) if self.strict => panic!("current_transform failed: {:?}", e),
+            // the transform of a new canvas is the identity.
+            Err(_) => Affine::IDENTITY,
+        }
+    }
+
+    /// Create a canvas element that isn't in the document.
+    fn create_canvas(&self) -> Result<HtmlCanvasElement, Error> {
+        let document = self.window.document().ok_or(Error::NotSupported)?;
+        document
+            .create_element("canvas")
+            .wrap()?
+            .dyn_into::<HtmlCanvasElement>()
+            .map_err(JsValue::from)
+            .wrap()
+    }
+
+    /// Multiply a layer that was drawn through an opacity mask by the mask,
+    /// and draw it.
+    fn draw_opacity_mask(
+        &self,
+        layer: &CanvasRenderingContext2d,
+        mask: &OpacityMask,
+    ) -> Result<(), Error> {
+        let size = mask.mask.size();
+        // an empty mask, or one that hasn't loaded yet, hides everything.
+        if !mask.mask.is_ready() || size.is_empty() || mask.rect.is_empty() {
+            return Ok(());
+        }
+        let canvas = layer.canvas().ok_or(Error::NotSupported)?;
+        let a = mask.transform.as_coeffs();
+        layer.restore();
+        layer.set_global_alpha(1.0);
+        // keep the layer only where the mask was drawn.
+        layer
+            .set_global_composite_operation("destination-in")
+            .wrap()?;
+        draw_image_source(layer, &mask.mask, size.to_rect(), mask.rect).wrap()?;
+
+        self.ctx.save();
+        let result = self
+            .ctx
+            .set_transform(a[0], a[1], a[2], a[3], a[4], a[5])
+            .and_then(|()| {
+                self.ctx.begin_path();
+                self.ctx.rect(
+                    mask.rect.x0,
+                    mask.rect.y0,
+                    mask.rect.width(),
+                    mask.rect.height(),
+                );
+                self.ctx.clip();
+                self.ctx.set_global_alpha(1.0);
+                self.ctx.set_transform(1.0, 0.0, 0.0, 1.0, 0.0, 0.0)
+            })
+            .and_then(|()| {
+                self.ctx
+                    .draw_image_with_html_canvas_element(&canvas, 0.0, 0.0)
+            });
+        self.ctx.restore();
+        result.wrap()
+    }
+
+    /// Apply filters to the pixels of an image, and draw the result.
+    fn draw_image_filtered_pixels(
+        &mut self,
+        image: &WebImage,
+        dst_rect: Rect,
+        filters: &[ImageFilter],
+    ) -> Result<(), Error> {
+        let (width, height) = (image.width as usize, image.height as usize);
+        if width == 0 || height == 0 {
+            return Ok(());
+        }
+        let canvas = self.create_canvas()?;
+        canvas.set_width(image.width);
+        canvas.set_height(image.height);
+        let ctx = canvas_context(&canvas)?;
+        let size = Size::new(width as f64, height as f64);
+        draw_image_source(&ctx, image, size.to_rect(), size.to_rect()).wrap()?;
+        let mut rgba = ctx
+            .get_image_data(0.0, 0.0, size.width, size.height)
+            .wrap()?
+            .data()
+            .0;
+        piet::util::premultiply_rgba(&mut rgba);
+
+        let scale = Vec2::new(
+            size.width / dst_rect.width(),
+            size.height / dst_rect.height(),
+        );
+        let filtered = piet::util::filter_pixels(width, height, &rgba, filters, scale);
+        let filtered_rect = filtered.dst_rect(size, dst_rect);
+        let filtered = self.make_image(
+            filtered.width,
+            filtered.height,
+            &filtered.data,
+            ImageFormat::RgbaPremul,
+        )?;
+        draw_image(
+            self,
+            &filtered,
+            None,
+            filtered_rect,
+            InterpolationMode::Bilinear,
+        );
+        Ok(())
+    }
+
+    /// Copy the text of a layout that is drawn into the DOM.
+    fn mirror_text_layout(&mut self, layout: &WebTextLayout, pos: Point) {
+        let transform = self.current_transform() * Affine::translate(pos.to_vec2());
+        let canvas = self.ctx.canvas();
+        if let Some(mirror) = &mut self.text_mirror {
+            if let Err(e) = mirror.add(layout, transform, canvas).wrap() {
+                let args = || format!("pos: {:?}, text: {:?}", pos, layout.text());
+                self.record_error("draw_text", args, e);
+            }
+        }
+    }
+
+    /// Stroke `shape`, which is the current path, with `brush`, for a call
+    /// to `method`. The stroke parameters have been set to `width` and
+    /// `style`, and the stroke style to `brush`.
+    ///
+    /// The width and dashes of strokes that don't scale are in the units of
+    /// the base transform, so they are stroked under it, with the path mapped
+    /// through piet's transform on top of it.
+    fn stroke_path(
+        &mut self,
+        method: &'static str,
+        shape: impl Shape,
+        brush: &Brush,
+        width: f64,
+        style: &StrokeStyle,
+    ) {
+        if style.scale {
+            self.ctx.stroke();
+            return;
+        }
+        let transform = self.base_transform.inverse() * self.current_transform();
+        let path = shape
+            .path_elements(self.tolerance())
+            .map(|el| transform * el)
+            .collect::<BezPath>();
+        let result = match brush {
+            Brush::Solid(_) => {
+                let a = self.base_transform.as_coeffs();
+                self.ctx.save();
+                let result = self
+                    .ctx
+                    .set_transform(a[0], a[1], a[2], a[3], a[4], a[5])
+                    .map(|()| {
+                        set_path(&self.ctx, &path, self.tolerance());
+                        self.ctx.stroke();
+                    });
+                self.ctx.restore();
+                result.wrap()
+            }
+            Brush::Gradient(gradient) => self.stroke_gradient_path(&path, gradient, width, style),
+        };
+        if let Err(e) = result {
+            let args = || format!("width: {:?}, style: {:?}", width, style);
+            self.record_error(method, args, e);
+        }
+    }
+
+    /// Stroke `path`, which is in the units of the base transform, with a
+    /// gradient in the space of piet's transform.
+    ///
+    /// Gradients are placed by the canvas transform when they are drawn, so
+    /// the stroke is drawn in a layer under the base transform, and then
+    /// colored with the gradient under piet's transform.
+    fn stroke_gradient_path(
+        &self,
+        path: &BezPath,
+        gradient: &CanvasGradient,
+        width: f64,
+        style: &StrokeStyle,
+    ) -> Result<(), Error> {
+        let canvas = match self.ctx.canvas() {
+            Some(canvas) => canvas,
+            None => return Ok(()),
+        };
+        let layer = self.create_canvas()?;
+        layer.set_width(canvas.width());
+        layer.set_height(canvas.height());
+        let layer_ctx = canvas_context(&layer)?;
+        let a = self.base_transform.as_coeffs();
+        layer_ctx
+            .set_transform(a[0], a[1], a[2], a[3], a[4], a[5])
+            .wrap()?;
+        set_stroke_style(&layer_ctx, width, style).wrap()?;
+        set_path(&layer_ctx, path, self.tolerance());
+        layer_ctx.stroke();
+
+        // keep the gradient only where the stroke was drawn.
+        let transform = self.current_transform();
+        let a = transform.as_coeffs();
+        layer_ctx
+            .set_global_composite_operation("source-in")
+            .wrap()?;
+        layer_ctx
+            .set_transform(a[0], a[1], a[2], a[3], a[4], a[5])
+            .wrap()?;
+        let size = Size::new(canvas.width() as f64, canvas.height() as f64);
+        let bounds = transform.inverse().transform_rect_bbox(size.to_rect());
+        set_brush(&layer_ctx, &JsValue::from(gradient), true);
+        layer_ctx.fill_rect(bounds.x0, bounds.y0, bounds.width(), bounds.height());
+
+        self.ctx.save();
+        let result = self
+            .ctx
+            .set_transform(1.0, 0.0, 0.0, 1.0, 0.0, 0.0)
+            .and_then(|()| {
+                self.ctx
+                    .draw_image_with_html_canvas_element(&layer, 0.0, 0.0)
+            });
+        self.ctx.restore();
+        result.wrap()
     }
 
     fn set_path(&mut self, shape: impl Shape) {
-        // This shouldn't be necessary, we always leave the context in no-path
-        // state. But just in case, and it should be harmless.
+        set_path(&self.ctx, shape, self.tolerance());
+    }
+
+    /// Set the path to the subpaths of all of `shapes`, and return the union
+    /// of their bounds, or `None` if there are no shapes.
+    fn set_paths(&mut self, shapes: impl IntoIterator<Item = impl Shape>) -> Option<Rect> {
         self.ctx.begin_path();
-        for el in shape.path_elements(1e-3) {
-            match el {
-                PathEl::MoveTo(p) => self.ctx.move_to(p.x, p.y),
-                PathEl::LineTo(p) => self.ctx.line_to(p.x, p.y),
-                PathEl::QuadTo(p1, p2) => self.ctx.quadratic_curve_to(p1.x, p1.y, p2.x, p2.y),
-                PathEl::CurveTo(p1, p2, p3) => {
-                    self.ctx.bezier_curve_to(p1.x, p1.y, p2.x, p2.y, p3.x, p3.y)
-                }
-                PathEl::ClosePath => self.ctx.close_path(),
-            }
+        let tolerance = self.tolerance();
+        shapes
+            .into_iter()
+            .fold(None, |bounds: Option<Rect>, shape| {
+                let shape_bounds = shape.bounding_box();
+                append_path(&self.ctx, shape, tolerance);
+                Some(bounds.map_or(shape_bounds, |bounds| bounds.union(shape_bounds)))
+            })
+    }
+
+    /// Fill a shape through a mask, in an offscreen canvas that is then
+    /// drawn over this one.
+    fn fill_with_mask_impl(
+        &mut self,
+        shape: impl Shape,
+        brush: &Brush,
+        mask: &WebImage,
+    ) -> Result<(), Error> {
+        let canvas = match self.ctx.canvas() {
+            Some(canvas) => canvas,
+            None => return Ok(()),
+        };
+        let layer = self.create_canvas()?;
+        layer.set_width(canvas.width());
+        layer.set_height(canvas.height());
+        let layer_ctx = canvas_context(&layer)?;
+        let m = self.ctx.get_transform().wrap()?;
+        layer_ctx
+            .set_transform(m.a(), m.b(), m.c(), m.d(), m.e(), m.f())
+            .wrap()?;
+
+        let bounds = shape.bounding_box();
+        let size = mask.size();
+        let src_rect = Rect::new(0.0, 0.0, size.width, size.height);
+        draw_image_source(&layer_ctx, mask, src_rect, bounds).wrap()?;
+        // keep the brush only where the mask was drawn.
+        layer_ctx
+            .set_global_composite_operation("source-in")
+            .wrap()?;
+        set_brush(&layer_ctx, &self.brush_value(brush), true);
+        set_path(&layer_ctx, shape, self.tolerance());
+        layer_ctx.fill_with_canvas_winding_rule(CanvasWindingRule::Nonzero);
+
+        self.ctx.save();
+        let result = self
+            .ctx
+            .set_transform(1.0, 0.0, 0.0, 1.0, 0.0, 0.0)
+            .and_then(|()| {
+                self.ctx
+                    .draw_image_with_html_canvas_element(&layer, 0.0, 0.0)
+            });
+        self.ctx.restore();
+        result.wrap()
+    }
+}
+
+/// The `globalCompositeOperation` of a blend mode.
+fn blend_mode_operation(mode: BlendMode) -> &'static str {
+    match mode {
+        BlendMode::Normal => "source-over",
+        BlendMode::Multiply => "multiply",
+        BlendMode::Screen => "screen",
+        BlendMode::Overlay => "overlay",
+        BlendMode::Darken => "darken",
+        BlendMode::Lighten => "lighten",
+        BlendMode::ColorDodge => "color-dodge",
+        BlendMode::ColorBurn => "color-burn",
+        BlendMode::HardLight => "hard-light",
+        BlendMode::SoftLight => "soft-light",
+        BlendMode::Difference => "difference",
+        BlendMode::Exclusion => "exclusion",
+        BlendMode::Hue => "hue",
+        BlendMode::Saturation => "saturation",
+        BlendMode::Color => "color",
+        BlendMode::Luminosity => "luminosity",
+    }
+}
+
+fn set_brush(ctx: &CanvasRenderingContext2d, value: &JsValue, is_fill: bool) {
+    if is_fill {
+        ctx.set_fill_style(value);
+    } else {
+        ctx.set_stroke_style(value);
+    }
+}
+
+/// Set the stroke parameters of `ctx` to `width` and `style`.
+fn set_stroke_style(
+    ctx: &CanvasRenderingContext2d,
+    width: f64,
+    style: &StrokeStyle,
+) -> Result<(), JsValue> {
+    ctx.set_line_width(width);
+    ctx.set_line_join(convert_line_join(style.line_join));
+    if let Some(limit) = style.miter_limit() {
+        ctx.set_miter_limit(limit);
+    }
+    ctx.set_line_cap(convert_line_cap(style.line_cap));
+    ctx.set_line_dash_offset(style.dash_offset);
+    ctx.set_line_dash(convert_dash_pattern(&style.dash_pattern).as_ref())
+}
+
+fn set_path(ctx: &CanvasRenderingContext2d, shape: impl Shape, tolerance: f64) {
+    // This shouldn't be necessary, we always leave the context in no-path
+    // state. But just in case, and it should be harmless.
+    ctx.begin_path();
+    append_path(ctx, shape, tolerance);
+}
+
+/// Add the subpaths of `shape` to the current path.
+fn append_path(ctx: &CanvasRenderingContext2d, shape: impl Shape, tolerance: f64) {
+    // rectangles and circles take fewer calls into JavaScript than their
+    // path elements.
+    if let Some(rect) = shape.as_rect() {
+        ctx.rect(rect.x0, rect.y0, rect.width(), rect.height());
+        return;
+    }
+    if let Some(circle) = shape.as_circle().filter(|circle| circle.radius >= 0.0) {
+        let center = circle.center;
+        ctx.move_to(center.x + circle.radius, center.y);
+        let _ = ctx.arc(center.x, center.y, circle.radius, 0.0, TAU);
+        ctx.close_path();
+        return;
+    }
+    for el in shape.path_elements(tolerance) {
+        match el {
+            PathEl::MoveTo(p) => ctx.move_to(p.x, p.y),
+            PathEl::LineTo(p) => ctx.line_to(p.x, p.y),
+            PathEl::QuadTo(p1, p2) => ctx.quadratic_curve_to(p1.x, p1.y, p2.x, p2.y),
+            PathEl::CurveTo(p1, p2, p3) => ctx.bezier_curve_to(p1.x, p1.y, p2.x, p2.y, p3.x, p3.y),
+            PathEl::ClosePath => ctx.close_path(),
+        }
+    }
+}
+
+/// A `Path2D` of `shape`, which can be drawn many times.
+fn path_2d(shape: impl Shape, tolerance: f64) -> Result<Path2d, Error> {
+    let path = Path2d::new().wrap()?;
+    for el in shape.path_elements(tolerance) {
+        match el {
+            PathEl::MoveTo(p) => path.move_to(p.x, p.y),
+            PathEl::LineTo(p) => path.line_to(p.x, p.y),
+            PathEl::QuadTo(p1, p2) => path.quadratic_curve_to(p1.x, p1.y, p2.x, p2.y),
+            PathEl::CurveTo(p1, p2, p3) => path.bezier_curve_to(p1.x, p1.y, p2.x, p2.y, p3.x, p3.y),
+            PathEl::ClosePath => path.close_path(),
         }
     }
+    Ok(path)
 }
 
 fn byte_to_frac(byte: u32) -> f64 {
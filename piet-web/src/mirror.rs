@@ -0,0 +1,108 @@
+//! Mirroring drawn text into the DOM, so that screen readers can read it.
+
+use wasm_bindgen::JsValue;
+use web_sys::{Document, Element, HtmlCanvasElement};
+
+use piet::kurbo::{Affine, Size};
+use piet::TextLayout;
+
+use crate::WebTextLayout;
+
+/// The attribute that marks the elements made by a [`TextMirror`].
+const MARKER: &str = "data-piet-text";
+
+/// Copies of the text drawn in a frame, kept in elements in the DOM.
+pub(crate) struct TextMirror {
+    document: Document,
+    root: Element,
+    /// The elements holding the copies, in the order they were drawn.
+    nodes: Vec<Element>,
+    /// How many of `nodes` have been used in this frame.
+    used: usize,
+    /// The size of a canvas pixel in CSS pixels, once it is known for this
+    /// frame.
+    css_scale: Option<f64>,
+}
+
+impl TextMirror {
+    pub(crate) fn new(document: Document, root: Element) -> TextMirror {
+        // reuse the copies made by an earlier context.
+        let children = root.children();
+        let nodes = (0..children.length())
+            .filter_map(|i| children.item(i))
+            .filter(|child| child.has_attribute(MARKER))
+            .collect();
+        TextMirror {
+            document,
+            root,
+            nodes,
+            used: 0,
+            css_scale: None,
+        }
+    }
+
+    /// Copy the text of `layout`, drawn with `transform`, into the next
+    /// element.
+    pub(crate) fn add(
+        &mut self,
+        layout: &WebTextLayout,
+        transform: Affine,
+        canvas: Option<HtmlCanvasElement>,
+    ) -> Result<(), JsValue> {
+        let scale = *self.css_scale.get_or_insert_with(|| match canvas {
+            Some(canvas) if canvas.width() > 0 => {
+                canvas.client_width() as f64 / canvas.width() as f64
+            }
+            _ => 1.0,
+        });
+        let node = match self.nodes.get(self.used) {
+            Some(node) => node.clone(),
+            None => {
+                let node = self.document.create_element("div")?;
+                node.set_attribute(MARKER, "")?;
+                self.root.append_child(&node)?;
+                self.nodes.push(node.clone());
+                node
+            }
+        };
+        self.used += 1;
+
+        let text = layout.text();
+        if node.text_content().as_deref() != Some(text) {
+            node.set_text_content(Some(text));
+        }
+        match layout.aria_role() {
+            Some(role) => node.set_attribute("role", role)?,
+            None => node.remove_attribute("role")?,
+        }
+        node.set_attribute(
+            "style",
+            &style(layout.size(), Affine::scale(scale) * transform),
+        )
+    }
+
+    /// Remove the elements that weren't used in this frame, and start the
+    /// next one.
+    pub(crate) fn finish_frame(&mut self) {
+        for node in self.nodes.drain(self.used..) {
+            node.remove();
+        }
+        self.used = 0;
+        self.css_scale = None;
+    }
+}
+
+/// The style of an element that covers a layout of `size`, drawn with
+/// `transform` in CSS pixels.
+///
+/// The text is transparent, so only the text drawn on the canvas is seen.
+fn style(size: Size, transform: Affine) -> String {
+    let [a, b, c, d, e, f] = transform.as_coeffs();
+    format!(
+        "position:absolute;left:0;top:0;margin:0;padding:0;\
+        width:{}px;height:{}px;\
+        transform-origin:0 0;transform:matrix({},{},{},{},{},{});\
+        color:transparent;white-space:pre;overflow:hidden;",
+        size.width, size.height, a, b, c, d, e, f
+    )
+}
@@ -0,0 +1,249 @@
+//! A loop that draws a canvas on every animation frame.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::JsCast;
+use web_sys::{HtmlCanvasElement, Window};
+
+use piet::kurbo::{Affine, Size};
+use piet::{Error, RenderContext};
+
+use crate::{canvas_context, WebRenderContext};
+
+/// The timing and the size of a frame drawn by a [`WebRenderLoop`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameInfo {
+    /// The number of frames that were drawn before this one.
+    pub frame: u64,
+    /// The time of the frame in milliseconds, as passed to
+    /// `requestAnimationFrame` callbacks, which is comparable with
+    /// `performance.now()`.
+    pub time: f64,
+    /// The time since the last frame was drawn, in milliseconds, or `0` for
+    /// the first frame.
+    pub elapsed: f64,
+    /// The size of the canvas in CSS pixels, which are the units that are
+    /// drawn in.
+    pub size: Size,
+    /// The number of device pixels in each CSS pixel.
+    pub scale: f64,
+    /// Whether the canvas context was lost and restored since the last
+    /// frame, in which case the images that were made before need to be
+    /// made again; see [`WebRenderContext::is_context_lost`].
+    pub restored: bool,
+}
+
+type DrawFn = Box<dyn FnMut(&mut WebRenderContext<'static>, &FrameInfo) -> Result<(), Error>>;
+
+/// Draws a canvas with a [`WebRenderContext`] on every animation frame,
+/// while it is running.
+///
+/// Before each frame, the size of the canvas is matched to its size on the
+/// page and the device pixel ratio, so that it is sharp on high resolution
+/// displays, and the context is transformed so that it draws in CSS pixels.
+/// The state of the context is restored to the state at the start of the
+/// frame, so saves that a frame doesn't restore don't carry over into the
+/// next one, and the hit regions of the last frame are cleared. While the
+/// canvas context is lost, frames aren't drawn, and [`status`] returns
+/// [`Error::BackendLost`].
+///
+/// The loop stops when it is dropped.
+///
+/// [`status`]: WebRenderLoop::status
+///
+/// ```no_run
+/// use piet::{kurbo::Rect, Color, RenderContext};
+/// use piet_web::WebRenderLoop;
+/// # use wasm_bindgen::JsCast;
+/// # let canvas = web_sys::window().unwrap().document().unwrap()
+/// #     .get_element_by_id("canvas").unwrap()
+/// #     .dyn_into::<web_sys::HtmlCanvasElement>().unwrap();
+///
+/// let render_loop = WebRenderLoop::new(canvas, |rc, frame| {
+///     rc.clear(None, Color::WHITE);
+///     let x = (frame.time / 10.0) % frame.size.width;
+///     rc.fill(Rect::new(x, 0.0, x + 20.0, 20.0), &Color::BLUE);
+///     Ok(())
+/// })
+/// .unwrap();
+/// render_loop.start();
+/// # std::mem::forget(render_loop);
+/// ```
+pub struct WebRenderLoop {
+    state: Rc<RefCell<LoopState>>,
+}
+
+struct LoopState {
+    canvas: HtmlCanvasElement,
+    window: Window,
+    rc: WebRenderContext<'static>,
+    draw: DrawFn,
+    /// The callback passed to `requestAnimationFrame`, which only holds a
+    /// weak reference to the state, so that they are dropped with the loop.
+    callback: Closure<dyn FnMut(f64)>,
+    /// The id of the request for the next frame, if one is scheduled.
+    request: Option<i32>,
+    running: bool,
+    frame: u64,
+    last_time: Option<f64>,
+    /// Set when a frame isn't drawn because the context is lost.
+    lost: bool,
+    err: Result<(), Error>,
+}
+
+impl WebRenderLoop {
+    /// A new loop that calls `draw` to draw `canvas` on each frame, once it
+    /// is [started](WebRenderLoop::start).
+    ///
+    /// An error returned by `draw`, or by [`finish`] after it, doesn't stop
+    /// the loop; the last one is returned by [`status`].
+    ///
+    /// [`finish`]: RenderContext::finish
+    /// [`status`]: WebRenderLoop::status
+    pub fn new(
+        canvas: HtmlCanvasElement,
+        draw: impl FnMut(&mut WebRenderContext<'static>, &FrameInfo) -> Result<(), Error> + 'static,
+    ) -> Result<WebRenderLoop, Error> {
+        let window = web_sys::window().ok_or(Error::NotSupported)?;
+        let rc = WebRenderContext::new(canvas_context(&canvas)?, window.clone());
+        let state = Rc::new_cyclic(|weak| {
+            let weak = weak.clone();
+            let callback = Closure::wrap(Box::new(move |time| {
+                if let Some(state) = weak.upgrade() {
+                    LoopState::on_frame(&state, time);
+                }
+            }) as Box<dyn FnMut(f64)>);
+            RefCell::new(LoopState {
+                canvas,
+                window,
+                rc,
+                draw: Box::new(draw),
+                callback,
+                request: None,
+                running: false,
+                frame: 0,
+                last_time: None,
+                lost: false,
+                err: Ok(()),
+            })
+        });
+        Ok(WebRenderLoop { state })
+    }
+
+    /// Start drawing a frame on every animation frame.
+    pub fn start(&self) {
+        let mut state = self.state.borrow_mut();
+        state.running = true;
+        state.schedule();
+    }
+
+    /// Stop drawing frames, after the one that is being drawn, if any.
+    ///
+    /// The frame after the loop is started again has an [`elapsed`] time
+    /// of `0`.
+    ///
+    /// [`elapsed`]: FrameInfo::elapsed
+    pub fn stop(&self) {
+        let mut state = self.state.borrow_mut();
+        state.running = false;
+        state.last_time = None;
+        state.cancel();
+    }
+
+    /// Returns `true` if the loop has been started and not stopped.
+    pub fn is_running(&self) -> bool {
+        self.state.borrow().running
+    }
+
+    /// Call `f` with the render context between frames, such as to
+    /// configure it with [`log_errors`], or to query the hit regions of the
+    /// last frame.
+    ///
+    /// # Panics
+    ///
+    /// Panics if it is called while a frame is being drawn, such as from the
+    /// draw function, as are the other methods of the loop.
+    ///
+    /// [`log_errors`]: WebRenderContext::log_errors
+    pub fn with_context<T>(&self, f: impl FnOnce(&mut WebRenderContext<'static>) -> T) -> T {
+        f(&mut self.state.borrow_mut().rc)
+    }
+
+    /// Returns the last error from drawing a frame since this was last
+    /// called, if there was one.
+    pub fn status(&self) -> Result<(), Error> {
+        std::mem::replace(&mut self.state.borrow_mut().err, Ok(()))
+    }
+}
+
+impl Drop for WebRenderLoop {
+    fn drop(&mut self) {
+        self.state.borrow_mut().cancel();
+    }
+}
+
+impl LoopState {
+    fn on_frame(state: &RefCell<LoopState>, time: f64) {
+        let mut state = state.borrow_mut();
+        state.request = None;
+        if state.running {
+            state.draw_frame(time);
+            state.schedule();
+        }
+    }
+
+    fn draw_frame(&mut self, time: f64) {
+        if self.rc.is_context_lost() {
+            self.lost = true;
+            self.err = Err(Error::BackendLost);
+            return;
+        }
+        let restored = std::mem::take(&mut self.lost);
+        let scale = self.window.device_pixel_ratio();
+        let size = Size::new(
+            self.canvas.offset_width() as f64,
+            self.canvas.offset_height() as f64,
+        );
+        let width = (size.width * scale).round() as u32;
+        let height = (size.height * scale).round() as u32;
+        // resizing the canvas clears it, and resets the state of its context.
+        let resized = self.canvas.width() != width || self.canvas.height() != height;
+        if resized {
+            self.canvas.set_width(width);
+            self.canvas.set_height(height);
+        }
+        self.rc
+            .begin_frame(Affine::scale(scale), resized || restored);
+
+        let frame = FrameInfo {
+            frame: self.frame,
+            time,
+            elapsed: self.last_time.map_or(0.0, |last| time - last),
+            size,
+            scale,
+            restored,
+        };
+        let drawn = (self.draw)(&mut self.rc, &frame);
+        let finished = self.rc.finish();
+        if let Err(e) = drawn.and(finished) {
+            self.err = Err(e);
+        }
+        self.frame += 1;
+        self.last_time = Some(time);
+    }
+
+    fn schedule(&mut self) {
+        if self.request.is_none() {
+            let callback = self.callback.as_ref().unchecked_ref();
+            self.request = self.window.request_animation_frame(callback).ok();
+        }
+    }
+
+    fn cancel(&mut self) {
+        if let Some(request) = self.request.take() {
+            let _ = self.window.cancel_animation_frame(request);
+        }
+    }
+}
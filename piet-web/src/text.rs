@@ -1,24 +1,32 @@
 //! Text functionality for Piet web backend
 
+pub(crate) mod bidi;
 mod grapheme;
 mod lines;
 
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::fmt;
 use std::ops::RangeBounds;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
 
-use web_sys::CanvasRenderingContext2d;
+use js_sys::Reflect;
+use wasm_bindgen::JsValue;
+use web_sys::{CanvasRenderingContext2d, TextMetrics};
 
-use piet::kurbo::{Point, Rect, Size};
+use piet::kurbo::{Affine, Point, Rect, Size};
 
 use piet::{
-    util, Color, Error, FontFamily, HitTestPoint, HitTestPosition, LineMetric, Text, TextAttribute,
-    TextLayout, TextLayoutBuilder, TextStorage,
+    util, Color, Error, FontFamily, FontFeature, FontVariation, Hinting, HitTestPoint,
+    HitTestPosition, LineHeight, LineMetric, Overflow, Text, TextAlignment, TextAttribute,
+    TextLayout, TextLayoutBuilder, TextStorage, WritingMode,
 };
 use unicode_segmentation::UnicodeSegmentation;
 
-use self::grapheme::{get_grapheme_boundaries, point_x_in_grapheme};
+use self::grapheme::{get_grapheme_boundaries, point_x_in_grapheme, LineAdvances};
+use self::lines::Hyphenator;
+pub(crate) use self::lines::HYPHEN;
 use crate::WebText;
 
 #[derive(Clone)]
@@ -26,6 +34,7 @@ pub struct WebFont {
     family: FontFamily,
     weight: u32,
     style: FontStyle,
+    small_caps: bool,
     size: f64,
 }
 
@@ -34,12 +43,44 @@ pub struct WebTextLayout {
     ctx: CanvasRenderingContext2d,
     pub(crate) font: WebFont,
     pub(crate) text: Rc<dyn TextStorage>,
+    /// The text as provided by the user; `text` may be truncated from this,
+    /// depending on the `overflow` mode.
+    source_text: Rc<dyn TextStorage>,
 
     // Calculated on build
+    /// The width that the text was last laid out to fit.
+    max_width: f64,
     pub(crate) line_metrics: Vec<LineMetric>,
+    /// Whether each line ends at a hyphenation point, and needs a hyphen drawn.
+    hyphenated: Vec<bool>,
+    /// The advances of the graphemes of each line, for hit testing, unless
+    /// the line has right-to-left text.
+    advances: Vec<Option<LineAdvances>>,
+    /// The extra advance added to each space in each line, for justified text.
+    justification: Vec<f64>,
     size: Size,
+    /// The bounds of the glyphs, if the browser measures them.
+    ink_bounds: Option<Rect>,
     trailing_ws_width: f64,
     color: Color,
+    background_color: Color,
+    /// How far the glyphs are raised above each line's baseline.
+    baseline_shift: f64,
+    letter_spacing: f64,
+    word_spacing: f64,
+    line_height: LineHeight,
+    paragraph_spacing: f64,
+    max_height: f64,
+    overflow: Overflow,
+    writing_mode: WritingMode,
+    alignment: TextAlignment,
+    tab_stops: Rc<[f64]>,
+    tab_width: Option<f64>,
+    hyphenator: Option<Hyphenator>,
+    /// The role of the text when it is copied into the DOM.
+    aria_role: Option<Rc<str>>,
+    /// Changed each time the text is laid out; see [`WebTextLayout::id`].
+    id: u64,
 }
 
 pub struct WebTextLayoutBuilder {
@@ -47,6 +88,15 @@ pub struct WebTextLayoutBuilder {
     text: Rc<dyn TextStorage>,
     width: f64,
     defaults: util::LayoutDefaults,
+    line_height: LineHeight,
+    paragraph_spacing: f64,
+    max_height: f64,
+    overflow: Overflow,
+    writing_mode: WritingMode,
+    alignment: TextAlignment,
+    tab_stops: Rc<[f64]>,
+    tab_width: Option<f64>,
+    hyphenator: Option<Hyphenator>,
 }
 
 /// <https://developer.mozilla.org/en-US/docs/Web/CSS/font-style>
@@ -54,7 +104,6 @@ pub struct WebTextLayoutBuilder {
 enum FontStyle {
     Normal,
     Italic,
-    #[allow(dead_code)] // Not used by piet, but here for completeness
     Oblique(Option<f64>),
 }
 
@@ -70,6 +119,10 @@ impl Text for WebText {
         Err(Error::Unimplemented)
     }
 
+    fn set_hinting(&mut self, _hinting: Hinting) {
+        // the canvas API gives us no control over how text is rasterized.
+    }
+
     fn new_text_layout(&mut self, text: impl TextStorage) -> Self::TextLayoutBuilder {
         WebTextLayoutBuilder {
             // TODO: it's very likely possible to do this without cloning ctx, but
@@ -78,6 +131,15 @@ impl Text for WebText {
             text: Rc::new(text),
             width: f64::INFINITY,
             defaults: Default::default(),
+            line_height: LineHeight::Normal,
+            paragraph_spacing: 0.0,
+            max_height: f64::INFINITY,
+            overflow: Overflow::Visible,
+            writing_mode: WritingMode::HorizontalTb,
+            alignment: TextAlignment::Start,
+            tab_stops: Vec::new().into(),
+            tab_width: None,
+            hyphenator: None,
         }
     }
 }
@@ -93,6 +155,7 @@ impl WebFont {
         WebFont {
             family,
             style: FontStyle::Normal,
+            small_caps: false,
             size: piet::util::DEFAULT_FONT_SIZE,
             weight: 400,
         }
@@ -114,6 +177,34 @@ impl WebFont {
         self
     }
 
+    /// Approximate variable font axes, which canvas cannot set directly.
+    ///
+    /// The weight axis maps onto the (numeric) font weight, and the slant
+    /// axis onto an oblique style.
+    fn with_variations(mut self, variations: &[FontVariation]) -> Self {
+        if let Some(wght) = FontVariation::find(variations, FontVariation::WEIGHT) {
+            self = self.with_weight(piet::FontWeight::new(wght.round().max(0.0) as u16));
+        }
+        if let Some(slnt) = FontVariation::find(variations, FontVariation::SLANT) {
+            if slnt != 0.0 {
+                // slnt is measured counter-clockwise, CSS oblique angles clockwise
+                self.style = FontStyle::Oblique(Some(-slnt));
+            }
+        }
+        self
+    }
+
+    /// Apply OpenType features.
+    ///
+    /// Canvas has no way to set arbitrary features; we only support small caps,
+    /// via the `font-variant` part of the font string.
+    fn with_features(mut self, features: &[FontFeature]) -> Self {
+        if let Some(smcp) = features.iter().rev().find(|f| f.tag == *b"smcp") {
+            self.small_caps = smcp.value != 0;
+        }
+        self
+    }
+
     fn with_size(mut self, size: f64) -> Self {
         self.size = size;
         self
@@ -126,9 +217,11 @@ impl WebFont {
             FontStyle::Oblique(None) => Cow::from("italic"),
             FontStyle::Oblique(Some(angle)) => Cow::from(format!("oblique {}deg", angle)),
         };
+        let variant_str = if self.small_caps { " small-caps" } else { "" };
         format!(
-            "{} {} {}px \"{}\"",
+            "{}{} {} {}px \"{}\"",
             style_str,
+            variant_str,
             self.weight,
             self.size,
             self.family.name()
@@ -144,8 +237,51 @@ impl TextLayoutBuilder for WebTextLayoutBuilder {
         self
     }
 
-    fn alignment(self, _alignment: piet::TextAlignment) -> Self {
-        web_sys::console::log_1(&"TextLayout alignment unsupported on web".into());
+    fn alignment(mut self, alignment: TextAlignment) -> Self {
+        if alignment != TextAlignment::Justified {
+            web_sys::console::log_1(&"TextLayout alignment unsupported on web".into());
+        }
+        self.alignment = alignment;
+        self
+    }
+
+    fn max_height(mut self, height: f64) -> Self {
+        self.max_height = height;
+        self
+    }
+
+    fn overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    fn writing_mode(mut self, mode: WritingMode) -> Self {
+        self.writing_mode = mode;
+        self
+    }
+
+    fn line_height(mut self, line_height: LineHeight) -> Self {
+        self.line_height = line_height;
+        self
+    }
+
+    fn paragraph_spacing(mut self, spacing: f64) -> Self {
+        self.paragraph_spacing = spacing;
+        self
+    }
+
+    fn tab_stops(mut self, stops: &[f64]) -> Self {
+        self.tab_stops = stops.into();
+        self
+    }
+
+    fn tab_width(mut self, width: f64) -> Self {
+        self.tab_width = Some(width);
+        self
+    }
+
+    fn hyphenator(mut self, hyphenator: impl Fn(&str) -> Vec<usize> + 'static) -> Self {
+        self.hyphenator = Some(Rc::new(hyphenator));
         self
     }
 
@@ -164,19 +300,44 @@ impl TextLayoutBuilder for WebTextLayoutBuilder {
     }
 
     fn build(self) -> Result<Self::Out, Error> {
+        let font_size = self.defaults.scaled_font_size();
+        let baseline_shift = self.defaults.total_baseline_shift();
         let font = WebFont::new(self.defaults.font)
-            .with_size(self.defaults.font_size)
+            .with_size(font_size)
             .with_weight(self.defaults.weight)
-            .with_style(self.defaults.style);
+            .with_style(self.defaults.style)
+            .with_variations(&self.defaults.variations)
+            .with_features(&self.defaults.features);
 
         let mut layout = WebTextLayout {
             ctx: self.ctx,
             font,
-            text: self.text,
+            text: self.text.clone(),
+            source_text: self.text,
+            max_width: self.width,
             line_metrics: Vec::new(),
+            hyphenated: Vec::new(),
+            advances: Vec::new(),
+            justification: Vec::new(),
             size: Size::ZERO,
+            ink_bounds: None,
             trailing_ws_width: 0.0,
             color: self.defaults.fg_color,
+            background_color: self.defaults.bg_color,
+            baseline_shift,
+            letter_spacing: self.defaults.letter_spacing,
+            word_spacing: self.defaults.word_spacing,
+            line_height: self.line_height,
+            paragraph_spacing: self.paragraph_spacing,
+            max_height: self.max_height,
+            overflow: self.overflow,
+            writing_mode: self.writing_mode,
+            alignment: self.alignment,
+            tab_stops: self.tab_stops,
+            tab_width: self.tab_width,
+            hyphenator: self.hyphenator,
+            aria_role: None,
+            id: 0,
         };
 
         layout.update_width(self.width);
@@ -192,16 +353,30 @@ impl fmt::Debug for WebTextLayoutBuilder {
 
 impl TextLayout for WebTextLayout {
     fn size(&self) -> Size {
-        self.size
+        if self.writing_mode.is_vertical() {
+            Size::new(self.size.height, self.size.width)
+        } else {
+            self.size
+        }
     }
 
     fn trailing_whitespace_width(&self) -> f64 {
         self.trailing_ws_width
     }
 
+    fn relayout_width(&mut self, width: f64) -> bool {
+        if width != self.max_width {
+            self.update_width(width);
+        }
+        true
+    }
+
     fn image_bounds(&self) -> Rect {
-        //FIXME: figure out actual image bounds on web?
-        self.size.to_rect()
+        match self.ink_bounds {
+            Some(bounds) => self.writing_transform().transform_rect_bbox(bounds),
+            // browsers that don't measure the glyphs get the layout's bounds.
+            None => TextLayout::size(self).to_rect(),
+        }
     }
 
     fn text(&self) -> &str {
@@ -223,13 +398,22 @@ impl TextLayout for WebTextLayout {
     }
 
     fn hit_test_point(&self, point: Point) -> HitTestPoint {
-        self.ctx.set_font(&self.font.get_font_string());
+        let spacing = self.configure_ctx(&self.ctx);
+        // do the hit test in the horizontal coordinate space
+        let point = self.writing_transform().inverse() * point;
         // internal logic is using grapheme clusters, but return the text position associated
         // with the border of the grapheme cluster.
 
         // null case
         if self.text.is_empty() {
-            return HitTestPoint::default();
+            return util::hit_test_point_on_line(
+                self,
+                self.writing_transform(),
+                point,
+                0,
+                0,
+                false,
+            );
         }
 
         // this assumes that all heights/baselines are the same.
@@ -247,15 +431,16 @@ impl TextLayout for WebTextLayout {
         let mut lm = self
             .line_metrics
             .iter()
-            .skip_while(|l| l.y_offset + l.height < point.y);
-        let lm = lm
+            .enumerate()
+            .skip_while(|(_, l)| l.y_offset + l.height < point.y);
+        let (line_number, lm) = lm
             .next()
             .or_else(|| {
                 // This means it went over the last line, so return the last line.
                 is_y_inside = false;
-                self.line_metrics.last()
+                self.line_metrics.iter().enumerate().next_back()
             })
-            .cloned()
+            .map(|(i, lm)| (i, lm.clone()))
             .unwrap_or_else(|| {
                 is_y_inside = false;
                 Default::default()
@@ -264,19 +449,83 @@ impl TextLayout for WebTextLayout {
         // Then for the line, do hit test point
         // Trailing whitespace is remove for the line
         let line = &self.text[lm.start_offset..lm.end_offset];
+        let spacing = self.line_spacing(spacing, line_number);
 
-        let mut htp = hit_test_line_point(&self.ctx, line, point);
+        let mut htp = match self.line_advances(line_number) {
+            Some(advances) => advances.hit_test(point.x),
+            None => hit_test_line_point(&self.ctx, spacing, line, point),
+        };
         htp.idx += lm.start_offset;
 
         if !is_y_inside {
             htp.is_inside = false;
         }
 
-        htp
+        util::hit_test_point_on_line(
+            self,
+            self.writing_transform(),
+            point,
+            line_number,
+            htp.idx,
+            htp.is_inside,
+        )
+    }
+
+    fn rects_for_range(&self, range: impl RangeBounds<usize>) -> Vec<Rect> {
+        let spacing = self.configure_ctx(&self.ctx);
+        let range = util::resolve_range(range, self.text.len());
+        let transform = self.writing_transform();
+        let mut rects = Vec::new();
+        for (line_number, lm) in self.line_metrics.iter().enumerate() {
+            let start = range.start.max(lm.start_offset);
+            let end = range.end.min(lm.end_offset);
+            if start >= end {
+                continue;
+            }
+            let line = &self.text[lm.range()];
+            let (start, end) = (start - lm.start_offset, end - lm.start_offset);
+            let spacing = self.line_spacing(spacing, line_number);
+            let spans = match bidi::visual_runs(&self.ctx, spacing, line) {
+                // the range may cover part of several runs, which are not
+                // necessarily contiguous.
+                Some(runs) => runs
+                    .iter()
+                    .filter_map(|run| {
+                        let (run_start, run_end) =
+                            (start.max(run.range.start), end.min(run.range.end));
+                        if run_start >= run_end {
+                            return None;
+                        }
+                        let run_text = &line[run.range.start..];
+                        let x0 = text_width(
+                            &run_text[..run_start - run.range.start],
+                            &self.ctx,
+                            spacing,
+                        );
+                        let x1 =
+                            text_width(&run_text[..run_end - run.range.start], &self.ctx, spacing);
+                        if run.is_rtl {
+                            Some((run.x + run.width - x1, run.x + run.width - x0))
+                        } else {
+                            Some((run.x + x0, run.x + x1))
+                        }
+                    })
+                    .collect(),
+                None => vec![(
+                    self.line_position(spacing, line_number, start),
+                    self.line_position(spacing, line_number, end),
+                )],
+            };
+            for (x0, x1) in util::merge_spans(spans) {
+                let rect = Rect::new(x0, lm.y_offset, x1, lm.y_offset + lm.height);
+                rects.push(transform.transform_rect_bbox(rect));
+            }
+        }
+        rects
     }
 
     fn hit_test_text_position(&self, idx: usize) -> HitTestPosition {
-        self.ctx.set_font(&self.font.get_font_string());
+        let spacing = self.configure_ctx(&self.ctx);
         let idx = idx.min(self.text.len());
         assert!(self.text.is_char_boundary(idx));
         // first need to find line it's on, and get line start offset
@@ -286,11 +535,12 @@ impl TextLayout for WebTextLayout {
         let y_pos = lm.y_offset + lm.baseline;
         // Then for the line, do text position
         // Trailing whitespace is removed for the line
-        let line = &self.text[lm.range()];
         let line_position = idx - lm.start_offset;
+        let spacing = self.line_spacing(spacing, line_num);
 
-        let x_pos = hit_test_line_position(&self.ctx, line, line_position);
-        HitTestPosition::new(Point::new(x_pos, y_pos), line_num)
+        let x_pos = self.line_position(spacing, line_num, line_position);
+        let point = self.writing_transform() * Point::new(x_pos, y_pos);
+        HitTestPosition::new(point, line_num)
     }
 }
 
@@ -301,6 +551,30 @@ impl fmt::Debug for WebTextLayout {
 }
 
 impl WebTextLayout {
+    /// Set the [ARIA role] of this text when it is copied into the DOM; see
+    /// [`WebRenderContext::mirror_text`].
+    ///
+    /// [ARIA role]: https://www.w3.org/TR/wai-aria/#role_definitions
+    /// [`WebRenderContext::mirror_text`]: crate::WebRenderContext::mirror_text
+    pub fn set_aria_role(&mut self, role: Option<&str>) {
+        self.aria_role = role.map(Into::into);
+    }
+
+    /// The role set with [`set_aria_role`](WebTextLayout::set_aria_role).
+    pub fn aria_role(&self) -> Option<&str> {
+        self.aria_role.as_deref()
+    }
+
+    /// A number that is the same for clones of this layout, and changes each
+    /// time the text is laid out again, such as by
+    /// [`relayout_width`](TextLayout::relayout_width).
+    ///
+    /// Renderers that cache the text they draw, such as piet-webgl, use this
+    /// to know when the cached text is stale.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
     pub(crate) fn size(&self) -> Size {
         self.size
     }
@@ -309,20 +583,134 @@ impl WebTextLayout {
         self.color
     }
 
+    pub(crate) fn background_color(&self) -> Color {
+        self.background_color
+    }
+
+    pub(crate) fn baseline_shift(&self) -> f64 {
+        self.baseline_shift
+    }
+
+    pub(crate) fn writing_mode(&self) -> WritingMode {
+        self.writing_mode
+    }
+
+    /// Returns the spacing to use for a line, including any extra word
+    /// spacing added to justify it.
+    pub(crate) fn line_spacing<'a>(&self, spacing: Spacing<'a>, line_number: usize) -> Spacing<'a> {
+        let extra = self.justification.get(line_number).copied().unwrap_or(0.0);
+        spacing.with_extra_word_spacing(extra)
+    }
+
+    /// The advances of the graphemes of a line, if it has no right-to-left
+    /// text.
+    fn line_advances(&self, line_number: usize) -> Option<&LineAdvances> {
+        self.advances.get(line_number)?.as_ref()
+    }
+
+    /// The x offset of the text position `idx` in a line, where `spacing`
+    /// is the spacing of the line.
+    ///
+    /// This doesn't measure the text again, unless the line has
+    /// right-to-left text.
+    pub(crate) fn line_position(&self, spacing: Spacing, line_number: usize, idx: usize) -> f64 {
+        if let Some(advances) = self.line_advances(line_number) {
+            return advances.x_for_offset(idx);
+        }
+        let line = self
+            .line_metrics
+            .get(line_number)
+            .map_or("", |lm| &self.text[lm.range()]);
+        hit_test_line_position(&self.ctx, spacing, line, idx)
+    }
+
+    /// Returns `true` if the line ends at a hyphenation point, and should
+    /// be drawn followed by a hyphen.
+    pub(crate) fn is_hyphenated(&self, line_number: usize) -> bool {
+        self.hyphenated.get(line_number).copied().unwrap_or(false)
+    }
+
+    /// The transform from the horizontal space lines are laid out in, to
+    /// the space of the layout.
+    pub(crate) fn writing_transform(&self) -> Affine {
+        self.writing_mode.transform(self.size)
+    }
+
+    /// Configure `ctx` with this layout's font and spacing.
+    ///
+    /// Returns the spacing that the canvas could not apply itself, and which
+    /// must be accounted for manually when measuring and drawing.
+    pub(crate) fn configure_ctx(&self, ctx: &CanvasRenderingContext2d) -> Spacing<'_> {
+        self.configure_ctx_with_tab_stops(ctx, &self.tab_stops)
+    }
+
+    /// As [`configure_ctx`], but with the tab stops borrowed separately from
+    /// the layout, so that the layout can be modified while they are in use.
+    ///
+    /// [`configure_ctx`]: WebTextLayout::configure_ctx
+    fn configure_ctx_with_tab_stops<'a>(
+        &self,
+        ctx: &CanvasRenderingContext2d,
+        tab_stops: &'a [f64],
+    ) -> Spacing<'a> {
+        ctx.set_font(&self.font.get_font_string());
+        let mut spacing = Spacing {
+            tab_stops,
+            ..Default::default()
+        };
+        if !set_spacing_property(ctx, "letterSpacing", self.letter_spacing) {
+            spacing.letter = self.letter_spacing;
+        }
+        if !set_spacing_property(ctx, "wordSpacing", self.word_spacing) {
+            spacing.word = self.word_spacing;
+        }
+        // like CSS, the default `tab-size` is eight spaces.
+        spacing.tab_width = self
+            .tab_width
+            .unwrap_or_else(|| DEFAULT_TAB_SIZE * text_width(" ", ctx, spacing));
+        spacing
+    }
+
     fn update_width(&mut self, new_width: impl Into<Option<f64>>) {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        self.id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
         // various functions like `text_width` are stateful, and require
         // the context to be configured correcttly.
-        self.ctx.set_font(&self.font.get_font_string());
+        let tab_stops = self.tab_stops.clone();
+        let spacing = self.configure_ctx_with_tab_stops(&self.ctx, &tab_stops);
         let new_width = new_width.into().unwrap_or(std::f64::INFINITY);
-        let mut line_metrics =
-            lines::calculate_line_metrics(&self.text, &self.ctx, new_width, self.font.size);
+        let previous_text = std::mem::replace(&mut self.text, self.source_text.clone());
+
+        // Vertical measures constant across all lines for now (web text).
+        // Browsers that don't measure the font's extents get heuristics.
+        let (ascent, descent) =
+            font_extents(&self.ctx).unwrap_or((self.font.size * 0.96, self.font.size * 0.24));
+        let natural_height = ascent + descent;
+        let height = self.line_height.resolve(self.font.size, natural_height);
+        let baseline = ascent + (height - natural_height) / 2.0;
+        let lines::Lines {
+            metrics: mut line_metrics,
+            mut hyphenated,
+            ..
+        } = lines::calculate_line_metrics(
+            &self.text,
+            &self.ctx,
+            spacing,
+            new_width,
+            height,
+            baseline,
+            self.paragraph_spacing,
+            self.hyphenator.as_ref(),
+        );
 
         if self.text.is_empty() {
             line_metrics.push(LineMetric {
                 baseline: self.font.size * 0.2,
-                height: self.font.size * 1.2,
+                height,
                 ..Default::default()
-            })
+            });
+            hyphenated.push(false);
         } else if util::trailing_nlf(&self.text).is_some() {
             assert!(!line_metrics.is_empty());
             let newline_eof = line_metrics
@@ -332,24 +720,36 @@ impl WebTextLayout {
                     end_offset: self.text.len(),
                     height: lm.height,
                     baseline: lm.baseline,
-                    y_offset: lm.y_offset + lm.height,
+                    y_offset: lm.y_offset + lm.height + self.paragraph_spacing,
                     trailing_whitespace: 0,
                 })
                 .unwrap();
             line_metrics.push(newline_eof);
+            hyphenated.push(false);
         }
 
+        self.apply_overflow(&mut line_metrics, &mut hyphenated, new_width, spacing);
+
+        let hyphen_width = text_width(HYPHEN, &self.ctx, spacing);
+        let justification = self.justify(&line_metrics, &hyphenated, new_width, spacing);
         let (width, ws_width) = line_metrics
             .iter()
-            .map(|lm| {
-                let full_width = text_width(&self.text[lm.range()], &self.ctx);
+            .zip(&hyphenated)
+            .zip(&justification)
+            .map(|((lm, &hyphenated), &extra)| {
+                let spacing = spacing.with_extra_word_spacing(extra);
+                let full_width = text_width(&self.text[lm.range()], &self.ctx, spacing);
                 let non_ws_width = if lm.trailing_whitespace > 0 {
                     let non_ws_range = lm.start_offset..lm.end_offset - lm.trailing_whitespace;
-                    text_width(&self.text[non_ws_range], &self.ctx)
+                    text_width(&self.text[non_ws_range], &self.ctx, spacing)
                 } else {
                     full_width
                 };
-                (non_ws_width, full_width)
+                if hyphenated {
+                    (non_ws_width + hyphen_width, full_width + hyphen_width)
+                } else {
+                    (non_ws_width, full_width)
+                }
             })
             .fold((0.0, 0.0), |a: (f64, f64), b| (a.0.max(b.0), a.1.max(b.1)));
 
@@ -357,30 +757,216 @@ impl WebTextLayout {
             .last()
             .map(|l| l.y_offset + l.height)
             .unwrap_or_default();
+        // lines with the same text and spacing as before keep the advances
+        // that were measured for them, so that only the lines that were
+        // broken differently are measured again.
+        let mut measured: HashMap<(&str, u64), Option<LineAdvances>> = HashMap::new();
+        for ((lm, extra), advances) in self
+            .line_metrics
+            .iter()
+            .zip(&self.justification)
+            .zip(self.advances.drain(..))
+        {
+            measured.insert((&previous_text[lm.range()], extra.to_bits()), advances);
+        }
+        let advances = line_metrics
+            .iter()
+            .zip(&justification)
+            .map(|(lm, &extra)| {
+                let line = &self.text[lm.range()];
+                measured
+                    .remove(&(line, extra.to_bits()))
+                    .unwrap_or_else(|| {
+                        let spacing = spacing.with_extra_word_spacing(extra);
+                        LineAdvances::measure(&self.ctx, spacing, line)
+                    })
+            })
+            .collect();
+
+        self.max_width = new_width;
         self.line_metrics = line_metrics;
+        self.hyphenated = hyphenated;
+        self.justification = justification;
+        self.advances = advances;
         self.trailing_ws_width = ws_width;
         self.size = Size::new(width, height);
+        self.ink_bounds = self.measure_ink_bounds(spacing, hyphen_width);
+    }
+
+    /// The union of the bounds of the glyphs of each line, as the browser
+    /// measures them, or `None` if it doesn't.
+    fn measure_ink_bounds(&self, spacing: Spacing, hyphen_width: f64) -> Option<Rect> {
+        let mut bounds: Option<Rect> = None;
+        for (line_number, lm) in self.line_metrics.iter().enumerate() {
+            let line = &self.text[lm.range()];
+            if line.trim().is_empty() {
+                continue;
+            }
+            let metrics = self.ctx.measure_text(line).ok()?;
+            let left = metric(&metrics, "actualBoundingBoxLeft")?;
+            let right = metric(&metrics, "actualBoundingBoxRight")?;
+            let ascent = metric(&metrics, "actualBoundingBoxAscent")?;
+            let descent = metric(&metrics, "actualBoundingBoxDescent")?;
+            // the canvas doesn't measure the spacing that is applied by hand,
+            // or the hyphen.
+            let spacing = self.line_spacing(spacing, line_number);
+            let mut extra = text_width(line, &self.ctx, spacing) - metrics.width();
+            if self.is_hyphenated(line_number) {
+                extra += hyphen_width;
+            }
+            let baseline = lm.y_offset + lm.baseline - self.baseline_shift;
+            let line_bounds = Rect::new(
+                -left,
+                baseline - ascent,
+                right + extra.max(0.0),
+                baseline + descent,
+            );
+            bounds = Some(bounds.map_or(line_bounds, |bounds| bounds.union(line_bounds)));
+        }
+        Some(bounds.unwrap_or_else(|| Rect::from_origin_size(Point::ZERO, Size::ZERO)))
+    }
+
+    /// Compute the extra advance to add to each space in each line, so that
+    /// justified lines fill `width`.
+    ///
+    /// The last line of each paragraph is left unjustified, as are lines
+    /// without any spaces.
+    fn justify(
+        &self,
+        line_metrics: &[LineMetric],
+        hyphenated: &[bool],
+        width: f64,
+        spacing: Spacing,
+    ) -> Vec<f64> {
+        if self.alignment != TextAlignment::Justified || !width.is_finite() {
+            return vec![0.0; line_metrics.len()];
+        }
+        line_metrics
+            .iter()
+            .zip(hyphenated)
+            .enumerate()
+            .map(|(i, (lm, &hyphenated))| {
+                let is_last_line = i + 1 == line_metrics.len();
+                if is_last_line || util::trailing_nlf(&self.text[lm.range()]).is_some() {
+                    return 0.0;
+                }
+                let line = &self.text[lm.start_offset..lm.end_offset - lm.trailing_whitespace];
+                let spaces = line.chars().filter(|c| *c == ' ' || *c == '\u{a0}').count();
+                if spaces == 0 {
+                    return 0.0;
+                }
+                let mut natural_width = text_width(line, &self.ctx, spacing);
+                if hyphenated {
+                    natural_width += text_width(HYPHEN, &self.ctx, spacing);
+                }
+                ((width - natural_width) / spaces as f64).max(0.0)
+            })
+            .collect()
+    }
+
+    /// Remove lines that don't fit in `max_height`, truncating the text to match.
+    fn apply_overflow(
+        &mut self,
+        line_metrics: &mut Vec<LineMetric>,
+        hyphenated: &mut Vec<bool>,
+        width: f64,
+        spacing: Spacing,
+    ) {
+        if self.overflow == Overflow::Visible {
+            return;
+        }
+        // we always keep at least one line
+        let n_lines = line_metrics
+            .iter()
+            .skip(1)
+            .take_while(|lm| lm.y_offset + lm.height <= self.max_height)
+            .count()
+            + 1;
+        if n_lines >= line_metrics.len() {
+            return;
+        }
+        line_metrics.truncate(n_lines);
+        hyphenated.truncate(n_lines);
+        let last = line_metrics.last_mut().unwrap();
+
+        if self.overflow == Overflow::Clip {
+            self.text = Rc::new(self.source_text[..last.end_offset].to_string());
+            return;
+        }
+
+        // drop graphemes until the line and the ellipsis fit in the width.
+        let line = self.source_text[last.range()].trim_end();
+        let mut end = line.len();
+        let mut truncated = format!("{}{}", line, ELLIPSIS);
+        while end > 0 && text_width(&truncated, &self.ctx, spacing) > width {
+            end = line[..end]
+                .grapheme_indices(true)
+                .next_back()
+                .map(|(idx, _)| idx)
+                .unwrap_or(0);
+            truncated = format!("{}{}", &line[..end], ELLIPSIS);
+        }
+
+        let text = format!("{}{}", &self.source_text[..last.start_offset], truncated);
+        last.end_offset = text.len();
+        last.trailing_whitespace = 0;
+        // the ellipsis replaces any hyphen
+        hyphenated[n_lines - 1] = false;
+        self.text = Rc::new(text);
     }
 }
 
+const ELLIPSIS: &str = "\u{2026}";
+
+/// The default distance between tab stops, in spaces.
+const DEFAULT_TAB_SIZE: f64 = 8.0;
+
+/// Returns `true` if this character should be drawn upright in vertical text.
+///
+/// This is a rough approximation of the Unicode `Vertical_Orientation` property,
+/// covering the common CJK blocks.
+pub(crate) fn is_upright_in_vertical(c: char) -> bool {
+    matches!(c,
+        '\u{1100}'..='\u{11FF}'
+        | '\u{2E80}'..='\u{2FDF}'
+        | '\u{3000}'..='\u{9FFF}'
+        | '\u{A960}'..='\u{A97F}'
+        | '\u{AC00}'..='\u{D7AF}'
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{FE30}'..='\u{FE4F}'
+        | '\u{FF00}'..='\u{FF60}'
+        | '\u{FFE0}'..='\u{FFE6}'
+        | '\u{20000}'..='\u{3FFFF}'
+    )
+}
+
 // NOTE this is the same as the old, non-line-aware version of hit_test_point
 // Future: instead of passing ctx, should there be some other line-level text layout?
-fn hit_test_line_point(ctx: &CanvasRenderingContext2d, text: &str, point: Point) -> HitTestPoint {
+fn hit_test_line_point(
+    ctx: &CanvasRenderingContext2d,
+    spacing: Spacing,
+    text: &str,
+    point: Point,
+) -> HitTestPoint {
     // null case
     if text.is_empty() {
         return HitTestPoint::default();
     }
 
+    if let Some(runs) = bidi::visual_runs(ctx, spacing, text) {
+        return bidi::x_to_offset(ctx, spacing, text, &runs, point.x);
+    }
+
     // get bounds
     // TODO handle if string is not null yet count is 0?
     let end = UnicodeSegmentation::graphemes(text, true).count() - 1;
-    let end_bounds = match get_grapheme_boundaries(ctx, text, end) {
+    let end_bounds = match get_grapheme_boundaries(ctx, spacing, text, end) {
         Some(bounds) => bounds,
         None => return HitTestPoint::default(),
     };
 
     let start = 0;
-    let start_bounds = match get_grapheme_boundaries(ctx, text, start) {
+    let start_bounds = match get_grapheme_boundaries(ctx, spacing, text, start) {
         Some(bounds) => bounds,
         None => return HitTestPoint::default(),
     };
@@ -410,7 +996,7 @@ fn hit_test_line_point(ctx: &CanvasRenderingContext2d, text: &str, point: Point)
         // pick halfway point
         let middle = left + ((right - left) / 2);
 
-        let grapheme_bounds = match get_grapheme_boundaries(ctx, text, middle) {
+        let grapheme_bounds = match get_grapheme_boundaries(ctx, spacing, text, middle) {
             Some(bounds) => bounds,
             None => return HitTestPoint::default(),
         };
@@ -434,17 +1020,26 @@ fn hit_test_line_point(ctx: &CanvasRenderingContext2d, text: &str, point: Point)
 // NOTE this is the same as the old, non-line-aware version of hit_test_text_position.
 // Future: instead of passing ctx, should there be some other line-level text layout?
 /// Returns the x offset of the given text position in this text.
-fn hit_test_line_position(ctx: &CanvasRenderingContext2d, text: &str, idx: usize) -> f64 {
+pub(crate) fn hit_test_line_position(
+    ctx: &CanvasRenderingContext2d,
+    spacing: Spacing,
+    text: &str,
+    idx: usize,
+) -> f64 {
     // Using substrings with unicode grapheme awareness
 
     let text_len = text.len();
 
+    if let Some(runs) = bidi::visual_runs(ctx, spacing, text) {
+        return bidi::offset_to_x(ctx, spacing, text, &runs, idx.min(text_len));
+    }
+
     if idx == 0 {
         return 0.0;
     }
 
     if idx as usize >= text_len {
-        return text_width(text, ctx);
+        return text_width(text, ctx, spacing);
     }
 
     // Already checked that text_position > 0 and text_position < count.
@@ -458,13 +1053,94 @@ fn hit_test_line_position(ctx: &CanvasRenderingContext2d, text: &str, idx: usize
         .last()
         .map(|(idx, _)| idx)
         .unwrap_or(text_len);
-    text_width(&text[..text_end], ctx)
+    text_width(&text[..text_end], ctx, spacing)
+}
+
+pub(crate) fn text_width(text: &str, ctx: &CanvasRenderingContext2d, spacing: Spacing) -> f64 {
+    if spacing.tab_width > 0.0 && text.contains('\t') {
+        // the canvas draws tabs as spaces, so we measure between them, and
+        // advance to the next tab stop ourselves.
+        let mut x = 0.0;
+        for (i, segment) in text.split('\t').enumerate() {
+            if i > 0 {
+                x = util::next_tab_stop(x, spacing.tab_stops, spacing.tab_width);
+            }
+            x += measure_text(segment, ctx) + spacing.extra_width(segment);
+        }
+        return x;
+    }
+    measure_text(text, ctx) + spacing.extra_width(text)
+}
+
+fn measure_text(text: &str, ctx: &CanvasRenderingContext2d) -> f64 {
+    // measuring only fails if the font can't be used, and then nothing is
+    // drawn either.
+    ctx.measure_text(text).map(|m| m.width()).unwrap_or(0.0)
 }
 
-pub(crate) fn text_width(text: &str, ctx: &CanvasRenderingContext2d) -> f64 {
-    ctx.measure_text(text)
-        .map(|m| m.width())
-        .expect("Text measurement failed")
+/// The ascent and descent of the font that `ctx` is configured with, if the
+/// browser measures them.
+fn font_extents(ctx: &CanvasRenderingContext2d) -> Option<(f64, f64)> {
+    // the extents of the font don't depend on the text that is measured.
+    let metrics = ctx.measure_text("M").ok()?;
+    let ascent = metric(&metrics, "fontBoundingBoxAscent")?;
+    let descent = metric(&metrics, "fontBoundingBoxDescent")?;
+    Some((ascent, descent))
+}
+
+/// A property of `metrics` that not all browsers measure, such as
+/// `actualBoundingBoxAscent`, or `None` if it isn't measured.
+fn metric(metrics: &TextMetrics, name: &str) -> Option<f64> {
+    Reflect::get(metrics, &JsValue::from_str(name))
+        .ok()?
+        .as_f64()
+        .filter(|x| x.is_finite())
+}
+
+/// Letter and word spacing that is applied manually, for canvases
+/// that don't support the `letterSpacing` and `wordSpacing` properties,
+/// along with the layout's tab stops.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct Spacing<'a> {
+    pub letter: f64,
+    pub word: f64,
+    pub tab_stops: &'a [f64],
+    pub tab_width: f64,
+}
+
+impl<'a> Spacing<'a> {
+    pub(crate) fn is_zero(self) -> bool {
+        self.letter == 0.0 && self.word == 0.0
+    }
+
+    /// Returns this spacing with `extra` added to the word spacing.
+    pub(crate) fn with_extra_word_spacing(self, extra: f64) -> Spacing<'a> {
+        Spacing {
+            word: self.word + extra,
+            ..self
+        }
+    }
+
+    /// The additional advance of `text` due to this spacing.
+    fn extra_width(self, text: &str) -> f64 {
+        if self.is_zero() {
+            return 0.0;
+        }
+        let graphemes = text.graphemes(true).count() as f64;
+        let spaces = text.chars().filter(|c| *c == ' ' || *c == '\u{a0}').count() as f64;
+        graphemes * self.letter + spaces * self.word
+    }
+}
+
+/// Set a CSS length property like `letterSpacing` on the context, if the
+/// browser supports it. Returns `false` if the property is unavailable.
+fn set_spacing_property(ctx: &CanvasRenderingContext2d, name: &str, value: f64) -> bool {
+    let name = JsValue::from_str(name);
+    if !Reflect::has(ctx, &name).unwrap_or(false) {
+        return false;
+    }
+    let value = JsValue::from_str(&format!("{}px", value));
+    Reflect::set(ctx, &name, &value).unwrap_or(false)
 }
 
 // NOTE these tests are currently only working on chrome.
@@ -473,7 +1149,7 @@ pub(crate) fn text_width(text: &str, ctx: &CanvasRenderingContext2d) -> f64 {
 #[cfg(test)]
 pub(crate) mod test {
     use piet::kurbo::Point;
-    use piet::{Text, TextLayout, TextLayoutBuilder};
+    use piet::{Text, TextAlignment, TextLayout, TextLayoutBuilder, WritingMode};
     use wasm_bindgen_test::*;
     use web_sys::{console, window, HtmlCanvasElement};
 
@@ -1135,4 +1811,249 @@ pub(crate) mod test {
         assert_eq!(pt.idx, 5);
         assert!(!pt.is_inside);
     }
+
+    #[wasm_bindgen_test]
+    pub fn test_hit_test_rtl() {
+        let (_window, context) = setup_ctx();
+        let mut text = WebText::new(context);
+        let font = text.font_family("sans-serif").unwrap();
+
+        // "shalom" in hebrew, followed by a neutral character
+        let input = "שלום!";
+        let layout = text
+            .new_text_layout(input)
+            .font(font, 14.0)
+            .build()
+            .unwrap();
+        let width = layout.size().width;
+
+        // the start of the text is on the right
+        let pos = layout.hit_test_text_position(0);
+        assert_close_to(pos.point.x, width, 1.0);
+        let pos = layout.hit_test_text_position(input.len());
+        assert_close_to(pos.point.x, 0.0, 1.0);
+
+        let pt = layout.hit_test_point(Point::new(width - 0.5, 0.0));
+        assert_eq!(pt.idx, 0);
+        let pt = layout.hit_test_point(Point::new(0.5, 0.0));
+        assert_eq!(pt.idx, input.len());
+    }
+
+    #[wasm_bindgen_test]
+    pub fn test_image_bounds() {
+        let (_window, context) = setup_ctx();
+        let mut text_layout = WebText::new(context);
+        let font = text_layout.font_family("sans-serif").unwrap();
+
+        let layout = text_layout
+            .new_text_layout("piet text!")
+            .font(font.clone(), 14.0)
+            .build()
+            .unwrap();
+        let size = layout.size();
+        let bounds = layout.image_bounds();
+        // the glyphs are inside of the lines, but don't fill them.
+        assert!(bounds.width() > 0.0 && bounds.width() < size.width + 2.0);
+        assert!(bounds.y0 >= -1.0 && bounds.y1 <= size.height + 1.0);
+        assert!(bounds.height() < size.height);
+
+        // the descender of "p" goes below the baseline, which "t" doesn't.
+        let p = text_layout
+            .new_text_layout("p")
+            .font(font.clone(), 14.0)
+            .build()
+            .unwrap();
+        let t = text_layout
+            .new_text_layout("t")
+            .font(font, 14.0)
+            .build()
+            .unwrap();
+        let baseline = p.line_metric(0).unwrap().baseline;
+        assert!(p.image_bounds().y1 > baseline);
+        assert_close_to(t.image_bounds().y1, baseline, 1.0);
+    }
+
+    #[wasm_bindgen_test]
+    pub fn test_cached_advances_match_measuring() {
+        let (_window, context) = setup_ctx();
+        let mut text_layout = WebText::new(context);
+        let font = text_layout.font_family("sans-serif").unwrap();
+
+        let input = "piet text, é and 🤦\u{1f3fc}\u{200d}\u{2642}\u{fe0f}!";
+        let layout = text_layout
+            .new_text_layout(input)
+            .font(font, 14.0)
+            .default_attribute(piet::TextAttribute::LetterSpacing(1.5))
+            .build()
+            .unwrap();
+        let spacing = layout.configure_ctx(&layout.ctx);
+        for idx in (0..=input.len()).filter(|&idx| input.is_char_boundary(idx)) {
+            let measured = super::hit_test_line_position(&layout.ctx, spacing, input, idx);
+            assert_eq!(layout.line_position(spacing, 0, idx), measured);
+
+            let htp = layout.hit_test_point(Point::new(measured + 0.1, 0.0));
+            let expected = super::hit_test_line_point(
+                &layout.ctx,
+                spacing,
+                input,
+                Point::new(measured + 0.1, 0.0),
+            );
+            assert_eq!(htp, expected);
+        }
+    }
+
+    #[wasm_bindgen_test]
+    pub fn test_relayout_width_matches_building() {
+        let (_window, context) = setup_ctx();
+        let mut text_layout = WebText::new(context);
+        let font = text_layout.font_family("sans-serif").unwrap();
+        let input = "piet text is laid out again when it is resized";
+        let build = |text_layout: &mut WebText, width: f64| {
+            text_layout
+                .new_text_layout(input)
+                .font(font.clone(), 14.0)
+                .max_width(width)
+                .build()
+                .unwrap()
+        };
+
+        let mut layout = build(&mut text_layout, 60.0);
+        for width in [120.0, 60.0, f64::INFINITY] {
+            assert!(layout.relayout_width(width));
+            let built = build(&mut text_layout, width);
+            assert_eq!(layout.line_metrics, built.line_metrics);
+            assert_eq!(layout.advances, built.advances);
+            assert_eq!(layout.size(), built.size());
+        }
+    }
+
+    #[wasm_bindgen_test]
+    pub fn test_vertical_layout_is_transposed() {
+        let (_window, context) = setup_ctx();
+        let mut text_layout = WebText::new(context);
+        let font = text_layout.font_family("sans-serif").unwrap();
+        let input = "piet text\nin columns";
+        let build = |text_layout: &mut WebText, mode: WritingMode| {
+            text_layout
+                .new_text_layout(input)
+                .font(font.clone(), 14.0)
+                .writing_mode(mode)
+                .build()
+                .unwrap()
+        };
+        let horizontal = build(&mut text_layout, WritingMode::HorizontalTb);
+        let vertical = build(&mut text_layout, WritingMode::VerticalRl);
+
+        // the columns are the lines of the horizontal layout, turned.
+        let size = horizontal.size();
+        assert_eq!(vertical.size(), Size::new(size.height, size.width));
+        assert_eq!(vertical.line_count(), 2);
+        for line in 0..2 {
+            assert_eq!(vertical.line_metric(line), horizontal.line_metric(line));
+        }
+
+        // the first column is on the right, and goes down.
+        let metric = vertical.line_metric(0).unwrap();
+        let start = vertical.hit_test_text_position(0).point;
+        let end = vertical.hit_test_text_position(4).point;
+        assert_close_to(start.x, size.height - metric.baseline, 1.0);
+        assert_close_to(start.y, 0.0, 1.0);
+        assert_close_to(end.x, start.x, 0.1);
+        assert!(end.y > start.y);
+        let hit = vertical.hit_test_point(Point::new(start.x, end.y + 1.0));
+        assert_eq!(hit.idx, 4);
+        assert_eq!(hit.line, 0);
+    }
+
+    #[wasm_bindgen_test]
+    pub fn test_hyphenated_lines() {
+        let (_window, context) = setup_ctx();
+        let mut text_layout = WebText::new(context);
+        let font = text_layout.font_family("sans-serif").unwrap();
+        let hyphenated_width = text_layout
+            .new_text_layout("piet hyphen-")
+            .font(font.clone(), 14.0)
+            .build()
+            .unwrap()
+            .size()
+            .width;
+        let build = |text_layout: &mut WebText, text: &str, hyphenate: bool| {
+            let builder = text_layout
+                .new_text_layout(text.to_string())
+                .font(font.clone(), 14.0)
+                .max_width(hyphenated_width + 1.0);
+            let builder = if hyphenate {
+                builder.hyphenator(|word| {
+                    if word == "hyphenation" {
+                        vec![6]
+                    } else {
+                        vec![]
+                    }
+                })
+            } else {
+                builder
+            };
+            builder.build().unwrap()
+        };
+
+        // without hyphenation points, the long word goes on the next line.
+        let layout = build(&mut text_layout, "piet hyphenation", false);
+        assert_eq!(layout.line_count(), 2);
+        assert_eq!(layout.line_text(0), Some("piet "));
+
+        // with them, the line is broken inside the word, and the hyphen is
+        // measured as part of the line.
+        let layout = build(&mut text_layout, "piet hyphenation", true);
+        assert_eq!(layout.line_count(), 2);
+        assert_eq!(layout.line_text(0), Some("piet hyphen"));
+        assert_eq!(layout.line_text(1), Some("ation"));
+        assert!(layout.is_hyphenated(0));
+        assert!(!layout.is_hyphenated(1));
+        assert_close_to(layout.size().width, hyphenated_width, 1.0);
+
+        // soft hyphens in the text are hyphenation points too.
+        let layout = build(&mut text_layout, "piet hyphen\u{AD}ation", false);
+        assert_eq!(layout.line_text(0), Some("piet hyphen\u{AD}"));
+        assert!(layout.is_hyphenated(0));
+    }
+
+    #[wasm_bindgen_test]
+    pub fn test_justified_lines_fill_the_width() {
+        let (_window, context) = setup_ctx();
+        let mut text_layout = WebText::new(context);
+        let font = text_layout.font_family("sans-serif").unwrap();
+        let input = "piet draws text in lines of words\nend";
+        let build = |text_layout: &mut WebText, alignment: TextAlignment| {
+            text_layout
+                .new_text_layout(input)
+                .font(font.clone(), 14.0)
+                .max_width(100.0)
+                .alignment(alignment)
+                .build()
+                .unwrap()
+        };
+        let start = build(&mut text_layout, TextAlignment::Start);
+        let justified = build(&mut text_layout, TextAlignment::Justified);
+
+        // justifying does not change where the lines break.
+        let line_count = justified.line_count();
+        assert!(line_count > 2);
+        assert_eq!(line_count, start.line_count());
+        for line in 0..line_count {
+            assert_eq!(justified.line_text(line), start.line_text(line));
+        }
+
+        let line_end = |layout: &WebTextLayout, line: usize| {
+            let lm = layout.line_metric(line).unwrap();
+            let end = lm.end_offset - lm.trailing_whitespace;
+            layout.hit_test_text_position(end).point.x
+        };
+        // every line but the last of each paragraph ends at the max width.
+        for line in 0..line_count - 2 {
+            assert_close_to(line_end(&justified, line), 100.0, 1.0);
+        }
+        for line in line_count - 2..line_count {
+            assert_close_to(line_end(&justified, line), line_end(&start, line), 0.1);
+        }
+    }
 }
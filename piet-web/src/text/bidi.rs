@@ -0,0 +1,118 @@
+//! Bidirectional text support.
+//!
+//! Lines containing right-to-left text are split into runs of a single
+//! direction, which are then measured and drawn in visual order.
+
+use std::ops::Range;
+
+use piet::HitTestPoint;
+use unic_bidi::BidiInfo;
+use unicode_segmentation::UnicodeSegmentation;
+use web_sys::CanvasRenderingContext2d;
+
+use super::{text_width, Spacing};
+
+/// A run of text with a single direction, positioned within its line.
+#[derive(Debug, Clone)]
+pub(crate) struct VisualRun {
+    /// The range of the run in the line's text.
+    pub range: Range<usize>,
+    pub is_rtl: bool,
+    /// The x offset of the left edge of the run.
+    pub x: f64,
+    pub width: f64,
+}
+
+/// Returns `true` if the line contains right-to-left text.
+pub(crate) fn has_rtl(line: &str) -> bool {
+    BidiInfo::new(line, None).has_rtl()
+}
+
+/// Returns the runs of this line in visual order, or `None` if the line
+/// contains no right-to-left text.
+pub(crate) fn visual_runs(
+    ctx: &CanvasRenderingContext2d,
+    spacing: Spacing,
+    line: &str,
+) -> Option<Vec<VisualRun>> {
+    let info = BidiInfo::new(line, None);
+    if !info.has_rtl() {
+        return None;
+    }
+
+    let mut runs = Vec::new();
+    let mut x = 0.0;
+    for para in &info.paragraphs {
+        let (levels, level_runs) = info.visual_runs(para, para.range.clone());
+        for range in level_runs {
+            let width = text_width(&line[range.clone()], ctx, spacing);
+            runs.push(VisualRun {
+                is_rtl: levels[range.start].is_rtl(),
+                range,
+                x,
+                width,
+            });
+            x += width;
+        }
+    }
+    Some(runs)
+}
+
+/// Returns the x offset of the caret at `idx` in this line.
+pub(crate) fn offset_to_x(
+    ctx: &CanvasRenderingContext2d,
+    spacing: Spacing,
+    line: &str,
+    runs: &[VisualRun],
+    idx: usize,
+) -> f64 {
+    let run = runs
+        .iter()
+        .find(|run| run.range.contains(&idx))
+        .or_else(|| runs.iter().find(|run| run.range.end == idx));
+    let run = match run {
+        Some(run) => run,
+        None => return 0.0,
+    };
+    let advance = text_width(&line[run.range.start..idx], ctx, spacing);
+    if run.is_rtl {
+        run.x + run.width - advance
+    } else {
+        run.x + advance
+    }
+}
+
+/// Returns the text position closest to the x offset `x` in this line.
+pub(crate) fn x_to_offset(
+    ctx: &CanvasRenderingContext2d,
+    spacing: Spacing,
+    line: &str,
+    runs: &[VisualRun],
+    x: f64,
+) -> HitTestPoint {
+    let (first, last) = match (runs.first(), runs.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return HitTestPoint::default(),
+    };
+    let is_inside = x >= first.x && x <= last.x + last.width;
+    let run = runs
+        .iter()
+        .find(|run| x <= run.x + run.width)
+        .unwrap_or(last);
+
+    // find the closest grapheme boundary in the run
+    let run_text = &line[run.range.clone()];
+    let boundaries = run_text
+        .grapheme_indices(true)
+        .map(|(idx, _)| idx)
+        .chain(Some(run_text.len()));
+    let mut best = (f64::INFINITY, run.range.start);
+    for idx in boundaries {
+        let idx = run.range.start + idx;
+        let distance = (offset_to_x(ctx, spacing, line, runs, idx) - x).abs();
+        if distance < best.0 {
+            best = (distance, idx);
+        }
+    }
+    HitTestPoint::new(best.1, is_inside)
+}
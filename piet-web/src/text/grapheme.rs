@@ -2,7 +2,7 @@ use piet::HitTestPoint;
 use unicode_segmentation::UnicodeSegmentation;
 use web_sys::CanvasRenderingContext2d;
 
-use super::hit_test_line_position;
+use super::{bidi, hit_test_line_position, Spacing};
 
 // currently copied and pasted from cairo backend.
 //
@@ -13,6 +13,7 @@ use super::hit_test_line_position;
 /// both horizontal and vertial components
 pub(crate) fn get_grapheme_boundaries(
     ctx: &CanvasRenderingContext2d,
+    spacing: Spacing,
     text: &str,
     grapheme_position: usize,
 ) -> Option<GraphemeBoundaries> {
@@ -20,8 +21,8 @@ pub(crate) fn get_grapheme_boundaries(
     let (text_position, _) = graphemes.nth(grapheme_position)?;
     let (next_text_position, _) = graphemes.next().unwrap_or((text.len(), ""));
 
-    let curr_edge = hit_test_line_position(ctx, text, text_position);
-    let next_edge = hit_test_line_position(ctx, text, next_text_position);
+    let curr_edge = hit_test_line_position(ctx, spacing, text, text_position);
+    let next_edge = hit_test_line_position(ctx, spacing, text, next_text_position);
 
     let res = GraphemeBoundaries {
         curr_idx: text_position,
@@ -66,3 +67,70 @@ pub(crate) struct GraphemeBoundaries {
     // not technically trailing; it's the lead boundary for the next grapheme cluster
     pub trailing: f64,
 }
+
+/// The x offset of each grapheme boundary in a line of left-to-right text,
+/// which are measured once when the layout is built, so that hit testing
+/// is a binary search, rather than measuring the text again.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct LineAdvances {
+    /// The offsets of the boundaries, including the start and the end of
+    /// the line.
+    offsets: Vec<usize>,
+    /// The x offset of each boundary, which is the advance of the text
+    /// before it.
+    xs: Vec<f64>,
+}
+
+impl LineAdvances {
+    /// Measure the boundaries of `line`, or return `None` if it contains
+    /// right-to-left text, whose boundaries aren't in order.
+    pub(crate) fn measure(
+        ctx: &CanvasRenderingContext2d,
+        spacing: Spacing,
+        line: &str,
+    ) -> Option<LineAdvances> {
+        if bidi::has_rtl(line) {
+            return None;
+        }
+        // each prefix is measured whole, so that kerning and shaping across
+        // graphemes is included, as it is when the line is drawn.
+        let offsets: Vec<usize> = line
+            .grapheme_indices(true)
+            .map(|(idx, _)| idx)
+            .chain(Some(line.len()))
+            .collect();
+        let xs = offsets
+            .iter()
+            .map(|&idx| hit_test_line_position(ctx, spacing, line, idx))
+            .collect();
+        Some(LineAdvances { offsets, xs })
+    }
+
+    /// The x offset of the text position `idx`, which is the offset of the
+    /// start of the grapheme it is in.
+    pub(crate) fn x_for_offset(&self, idx: usize) -> f64 {
+        let i = self.offsets.partition_point(|&offset| offset <= idx);
+        self.xs[i.saturating_sub(1)]
+    }
+
+    /// The text position closest to `x`, as in
+    /// [`hit_test_line_point`](super::hit_test_line_point).
+    pub(crate) fn hit_test(&self, x: f64) -> HitTestPoint {
+        let end = self.xs.len() - 1;
+        if end == 0 || x <= self.xs[0] {
+            return HitTestPoint::default();
+        }
+        if x > self.xs[end] {
+            return HitTestPoint::new(self.offsets[end], false);
+        }
+        // the grapheme from `i - 1` to `i` contains `x`.
+        let i = self.xs.partition_point(|&boundary| boundary < x).max(1);
+        let boundaries = GraphemeBoundaries {
+            curr_idx: self.offsets[i - 1],
+            next_idx: self.offsets[i],
+            leading: self.xs[i - 1],
+            trailing: self.xs[i],
+        };
+        point_x_in_grapheme(x, &boundaries).unwrap_or_default()
+    }
+}
@@ -5,20 +5,50 @@
 // code in `piet` core doesn't really make sense as it's implementation specific.
 //
 
+use std::rc::Rc;
+
 use web_sys::CanvasRenderingContext2d;
 use xi_unicode::LineBreakIterator;
 
-use super::{text_width, LineMetric};
+use super::{text_width, LineMetric, Spacing};
+
+/// A function returning the byte offsets at which a word may be hyphenated.
+pub(crate) type Hyphenator = Rc<dyn Fn(&str) -> Vec<usize>>;
+
+/// The hyphen drawn at the end of a line broken at a hyphenation point.
+pub(crate) const HYPHEN: &str = "-";
+
+const SOFT_HYPHEN: char = '\u{AD}';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BreakKind {
+    Soft,
+    /// A soft break that requires a hyphen to be drawn if it is taken.
+    Hyphen,
+    Hard,
+}
 
-// NOTE font_size is used only for heuristic purposes, prefer actual web-api for height and
-// baseline when available.
-#[allow(clippy::branches_sharing_code)] // clearer as written
+/// The lines computed so far, along with whether each ends in a hyphen.
+#[derive(Default)]
+pub(crate) struct Lines {
+    pub(crate) metrics: Vec<LineMetric>,
+    pub(crate) hyphenated: Vec<bool>,
+    y_offset: f64,
+}
+
+// NOTE `height` and `baseline` are constant across all lines; `paragraph_spacing` is added after
+// each hard break.
+#[allow(clippy::branches_sharing_code, clippy::too_many_arguments)] // clearer as written
 pub(crate) fn calculate_line_metrics(
     text: &str,
     ctx: &CanvasRenderingContext2d,
+    spacing: Spacing,
     width: f64,
-    font_size: f64,
-) -> Vec<LineMetric> {
+    height: f64,
+    baseline: f64,
+    paragraph_spacing: f64,
+    hyphenator: Option<&Hyphenator>,
+) -> Lines {
     // first pass, completely naive and inefficient. Check at every break to see if line longer
     // than width.
     //
@@ -30,8 +60,8 @@ pub(crate) fn calculate_line_metrics(
     //
     // So, every time there's a a hard break, must break.
     //
-    // soft-hyphen, don't need to deal with this round. Looks like automatic hyphenation, but
-    // with unicode codepoint. Don't special case, let if break for now.
+    // soft-hyphen, and any points returned by the hyphenator, are soft breaks that draw a
+    // hyphen when taken; the hyphen is included when measuring the line.
     //
     // For soft breaks, then I need to check line widths etc.
     //
@@ -45,21 +75,24 @@ pub(crate) fn calculate_line_metrics(
     // see https://glyphsapp.com/tutorials/vertical-metrics
     // https://stackoverflow.com/questions/27631736/meaning-of-top-ascent-baseline-descent-bottom-and-leading-in-androids-font
     // https://www.cairographics.org/manual/cairo-cairo-scaled-font-t.html#cairo-font-extents-t
-    let mut line_metrics = Vec::new();
+    let mut lines = Lines::default();
     let mut line_start = 0;
     let mut prev_break = 0;
-    let mut y_offset = 0.0;
+    let mut prev_kind = BreakKind::Soft;
 
-    // Vertical measures constant across all lines for now (web text)
-    // We use heuristics because we don't have access to web apis through web-sys yet.
-    let height = font_size * 1.2;
-    let baseline = height * 0.8;
+    let measure = |start: usize, end: usize, kind: BreakKind| {
+        let width = text_width(&text[start..end], ctx, spacing);
+        if kind == BreakKind::Hyphen {
+            width + text_width(HYPHEN, ctx, spacing)
+        } else {
+            width
+        }
+    };
 
-    for (line_break, is_hard_break) in LineBreakIterator::new(text) {
-        if !is_hard_break {
+    for (line_break, kind) in break_opportunities(text, hyphenator) {
+        if kind != BreakKind::Hard {
             // this section is for soft breaks
-            let curr_str = &text[line_start..line_break];
-            let curr_width = text_width(curr_str, ctx);
+            let curr_width = measure(line_start, line_break, kind);
 
             if curr_width > width {
                 // since curr_width is longer than desired line width, it's time to break ending
@@ -73,6 +106,7 @@ pub(crate) fn calculate_line_metrics(
                 // in a flag after next add_line_metric.
                 if prev_break == line_start {
                     prev_break = line_break;
+                    prev_kind = kind;
                 }
 
                 // first do the line to prev break
@@ -82,8 +116,8 @@ pub(crate) fn calculate_line_metrics(
                     prev_break,
                     baseline,
                     height,
-                    &mut y_offset,
-                    &mut line_metrics,
+                    prev_kind == BreakKind::Hyphen,
+                    &mut lines,
                 );
 
                 // Now handle the graphemes between prev_break and current break. The
@@ -92,8 +126,7 @@ pub(crate) fn calculate_line_metrics(
                 //
                 // If it's shorter than desired width, just continue.
 
-                let curr_str = &text[prev_break..line_break];
-                let curr_width = text_width(curr_str, ctx);
+                let curr_width = measure(prev_break, line_break, kind);
 
                 if curr_width > width {
                     add_line_metric(
@@ -102,8 +135,8 @@ pub(crate) fn calculate_line_metrics(
                         line_break,
                         baseline,
                         height,
-                        &mut y_offset,
-                        &mut line_metrics,
+                        kind == BreakKind::Hyphen,
+                        &mut lines,
                     );
 
                     line_start = line_break;
@@ -117,13 +150,13 @@ pub(crate) fn calculate_line_metrics(
                 // Since curr_width < width, don't break and just continue
                 prev_break = line_break;
             }
+            prev_kind = kind;
         } else {
             // this section is for hard breaks
 
             // even when there's a hard break, need to check first to see if width is too wide. If
             // it is, need to break at the previous soft break first.
-            let curr_str = &text[line_start..line_break];
-            let curr_width = text_width(curr_str, ctx);
+            let curr_width = measure(line_start, line_break, kind);
 
             if curr_width > width {
                 // if line is too wide but can't break down anymore, just skip to the next
@@ -139,8 +172,8 @@ pub(crate) fn calculate_line_metrics(
                         prev_break,
                         baseline,
                         height,
-                        &mut y_offset,
-                        &mut line_metrics,
+                        prev_kind == BreakKind::Hyphen,
+                        &mut lines,
                     );
 
                     line_start = prev_break;
@@ -149,16 +182,12 @@ pub(crate) fn calculate_line_metrics(
 
             // now do the hard break
             add_line_metric(
-                text,
-                line_start,
-                line_break,
-                baseline,
-                height,
-                &mut y_offset,
-                &mut line_metrics,
+                text, line_start, line_break, baseline, height, false, &mut lines,
             );
+            lines.y_offset += paragraph_spacing;
             line_start = line_break;
             prev_break = line_break;
+            prev_kind = kind;
         }
     }
 
@@ -170,12 +199,46 @@ pub(crate) fn calculate_line_metrics(
             text.len(),
             baseline,
             height,
-            &mut y_offset,
-            &mut line_metrics,
+            false,
+            &mut lines,
         );
     }
 
-    line_metrics
+    lines
+}
+
+/// Returns the offsets of all line break opportunities in `text`, in order.
+///
+/// In addition to the breaks found by the unicode line breaking algorithm,
+/// this includes the hyphenation points returned by `hyphenator` for each word.
+fn break_opportunities(text: &str, hyphenator: Option<&Hyphenator>) -> Vec<(usize, BreakKind)> {
+    let mut breaks = Vec::new();
+    let mut word_start = 0;
+    for (line_break, is_hard_break) in LineBreakIterator::new(text) {
+        if let Some(hyphenate) = hyphenator {
+            let word = text[word_start..line_break].trim_end();
+            let mut points = hyphenate(word);
+            points.sort_unstable();
+            points.dedup();
+            breaks.extend(
+                points
+                    .into_iter()
+                    .filter(|&idx| idx > 0 && idx < word.len() && word.is_char_boundary(idx))
+                    .map(|idx| (word_start + idx, BreakKind::Hyphen)),
+            );
+        }
+
+        let kind = if is_hard_break {
+            BreakKind::Hard
+        } else if text[..line_break].ends_with(SOFT_HYPHEN) {
+            BreakKind::Hyphen
+        } else {
+            BreakKind::Soft
+        };
+        breaks.push((line_break, kind));
+        word_start = line_break;
+    }
+    breaks
 }
 
 fn add_line_metric(
@@ -184,8 +247,8 @@ fn add_line_metric(
     end_offset: usize,
     baseline: f64,
     height: f64,
-    y_offset: &mut f64,
-    line_metrics: &mut Vec<LineMetric>,
+    hyphenated: bool,
+    lines: &mut Lines,
 ) {
     let line = &text[start_offset..end_offset];
     let trailing_whitespace = count_trailing_whitespace(line);
@@ -196,10 +259,11 @@ fn add_line_metric(
         trailing_whitespace,
         baseline,
         height,
-        y_offset: *y_offset,
+        y_offset: lines.y_offset,
     };
-    line_metrics.push(line_metric);
-    *y_offset += height;
+    lines.metrics.push(line_metric);
+    lines.hyphenated.push(hyphenated);
+    lines.y_offset += height;
 }
 
 // TODO: is non-breaking space trailing whitespace? Check with dwrite and
@@ -0,0 +1,56 @@
+//! Times the piet-bench workloads in a browser, where criterion can't run.
+//!
+//! Run with `wasm-pack test --headless --release --chrome piet-web -- --test bench`;
+//! the times are printed to the console.
+
+#![cfg(target_arch = "wasm32")]
+
+use piet_bench::{measure, Fixture, Workload};
+use piet_web::WebRenderContext;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_test::*;
+use web_sys::{console, window, CanvasRenderingContext2d, HtmlCanvasElement};
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+/// The number of times each workload is run.
+const ITERATIONS: usize = 50;
+
+#[wasm_bindgen_test]
+fn bench_workloads() {
+    let window = window().unwrap();
+    let performance = window.performance().unwrap();
+    let canvas = window
+        .document()
+        .unwrap()
+        .create_element("canvas")
+        .unwrap()
+        .dyn_into::<HtmlCanvasElement>()
+        .unwrap();
+    canvas.set_width(400);
+    canvas.set_height(400);
+    let context = canvas
+        .get_context("2d")
+        .unwrap()
+        .unwrap()
+        .dyn_into::<CanvasRenderingContext2d>()
+        .unwrap();
+    let mut rc = WebRenderContext::new(context, window);
+
+    let fixture = Fixture::new();
+    for workload in Workload::ALL {
+        let measurement = measure(&mut rc, workload, &fixture, ITERATIONS, || {
+            performance.now()
+        })
+        .unwrap();
+        console::log_1(
+            &format!(
+                "{}: {:.3} ms ({} iterations)",
+                workload.name(),
+                measurement.mean_ms(),
+                measurement.iterations
+            )
+            .into(),
+        );
+    }
+}
@@ -0,0 +1,85 @@
+//! A texture of rasterized text, which text is drawn from.
+//!
+//! The browser lays out and rasterizes text much better than we could, so
+//! whole layouts are drawn onto a 2D canvas with piet-web, and copied into
+//! the atlas.
+
+use std::rc::Rc;
+
+use piet::kurbo::Rect;
+use piet::Error;
+use piet_tess::atlas::Atlas;
+use web_sys::{HtmlCanvasElement, WebGl2RenderingContext as Gl};
+
+use crate::renderer::{self, Texture};
+
+/// The width and height of the atlas texture.
+pub(crate) const ATLAS_SIZE: u32 = 1024;
+
+/// Everything that changes how a layout is rasterized.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct TextKey {
+    /// The layout's [`id`](piet_web::WebTextLayout::id).
+    pub(crate) layout: u64,
+    /// The number of pixels per unit of the layout, in 1/64ths.
+    pub(crate) scale: u32,
+    /// The horizontal offset of the layout from the pixel grid, in quarters
+    /// of a pixel.
+    pub(crate) subpixel: u8,
+}
+
+pub(crate) struct TextAtlas {
+    pub(crate) texture: Rc<Texture>,
+    /// Where each text is in the atlas, from 0 to 1.
+    entries: Atlas<TextKey, Rect>,
+}
+
+impl TextAtlas {
+    pub(crate) fn new(gl: &Gl) -> Result<TextAtlas, Error> {
+        let texture = renderer::create_texture(gl, ATLAS_SIZE, ATLAS_SIZE, &[])?;
+        gl.bind_texture(Gl::TEXTURE_2D, None);
+        Ok(TextAtlas {
+            texture: Rc::new(texture),
+            entries: Atlas::new(ATLAS_SIZE),
+        })
+    }
+
+    pub(crate) fn begin_frame(&mut self) {
+        self.entries.begin_frame();
+    }
+
+    pub(crate) fn get(&self, key: TextKey) -> Option<Rect> {
+        self.entries.get(&key).copied()
+    }
+
+    /// Copy the top left `width` by `height` pixels of `canvas` into the
+    /// atlas, returning where they are.
+    ///
+    /// Returns `None` if there is no room for them.
+    pub(crate) fn insert(
+        &mut self,
+        gl: &Gl,
+        key: TextKey,
+        canvas: &HtmlCanvasElement,
+        width: u32,
+        height: u32,
+    ) -> Result<Option<Rect>, Error> {
+        let allocation = match self.entries.allocate(width, height) {
+            Some(allocation) => allocation,
+            None => return Ok(None),
+        };
+        gl.bind_texture(Gl::TEXTURE_2D, Some(&self.texture.texture));
+        let result = renderer::copy_canvas(
+            gl,
+            canvas,
+            allocation.x as i32,
+            allocation.y as i32,
+            width as i32,
+            height as i32,
+        );
+        gl.bind_texture(Gl::TEXTURE_2D, None);
+        result?;
+        self.entries.insert(key, allocation.uv);
+        Ok(Some(allocation.uv))
+    }
+}
@@ -0,0 +1,589 @@
+//! A WebGL2 backend for the Piet 2D graphics abstraction.
+//!
+//! This is a fallback for browsers, for when drawing many shapes with the 2D
+//! canvas of [piet-web] is too slow. Paths are tessellated into triangles on
+//! the CPU with [`piet_tess`], and everything drawn in a frame is batched into
+//! as few draw calls as possible, drawn with multisampling. Clips are kept in
+//! a stencil buffer.
+//!
+//! Text is laid out by piet-web, with the browser's fonts and metrics, so
+//! text measures the same as it does there. Each layout is rasterized by the
+//! browser once, into an atlas, and drawn from there.
+//!
+//! Long-lived GPU state is kept in a [`Renderer`], which is passed to a new
+//! [`WebGlRenderContext`] for each frame. Nothing is drawn until
+//! [`finish`](piet::RenderContext::finish) is called.
+//!
+//! [piet-web]: piet_web
+
+#![deny(clippy::trivially_copy_pass_by_ref)]
+
+mod atlas;
+mod renderer;
+
+use std::borrow::Cow;
+use std::rc::Rc;
+
+use piet::kurbo::{Affine, Point, Rect, Shape, Size};
+use piet::{
+    Capabilities, Color, Error, FixedGradient, GradientOptions, HitTester, Image, ImageFormat,
+    InterpolationMode, IntoBrush, MipImage, RenderContext, StrokeStyle, TextLayout,
+    TextShapingQuality,
+};
+use piet_tess::state::{premul_color, RenderState};
+use web_sys::WebGl2RenderingContext as Gl;
+
+use atlas::{TextKey, ATLAS_SIZE};
+use renderer::{ImageBinding, Texture};
+
+pub use piet_tess::state::Brush;
+pub use piet_web::{WebText, WebTextLayout, WebTextLayoutBuilder};
+pub use renderer::{get_context, Renderer};
+
+pub struct WebGlRenderContext<'a> {
+    renderer: &'a mut Renderer,
+    state: RenderState<ImageBinding>,
+    text: WebText,
+}
+
+#[derive(Clone)]
+pub struct WebGlImage(Option<Rc<ImageInner>>);
+
+struct ImageInner {
+    texture: Rc<Texture>,
+    /// The size of the texture.
+    size: Size,
+    /// The area of the texture that is the image.
+    view: Rect,
+}
+
+impl ImageInner {
+    /// The texture coordinates of `rect`, in the pixels of the texture.
+    fn uv(&self, rect: Rect) -> Rect {
+        Rect::new(
+            rect.x0 / self.size.width,
+            rect.y0 / self.size.height,
+            rect.x1 / self.size.width,
+            rect.y1 / self.size.height,
+        )
+    }
+}
+
+impl<'a> WebGlRenderContext<'a> {
+    /// Create a new WebGL back-end, which draws into the canvas of the
+    /// renderer's context when it is finished.
+    ///
+    /// Everything drawn is scaled by `scale`, which is the number of pixels
+    /// per display point, usually the window's `devicePixelRatio`.
+    pub fn new(renderer: &'a mut Renderer, scale: f64) -> WebGlRenderContext<'a> {
+        renderer.atlas.begin_frame();
+        let scene = std::mem::take(&mut renderer.scene);
+        WebGlRenderContext {
+            text: renderer.text.clone(),
+            renderer,
+            state: RenderState::new(scene, scale),
+        }
+    }
+
+    fn draw_image_inner(
+        &mut self,
+        image: &WebGlImage,
+        src_rect: Option<Rect>,
+        dst_rect: Rect,
+        interp: InterpolationMode,
+    ) {
+        let inner = match &image.0 {
+            Some(inner) => inner.clone(),
+            None => return,
+        };
+        let src_rect = src_rect.unwrap_or_else(|| inner.view.size().to_rect());
+        if src_rect.is_empty() || dst_rect.is_empty() {
+            return;
+        }
+        // map the destination to the source, and then to texture coordinates.
+        let src_uv = inner.uv(src_rect + inner.view.origin().to_vec2());
+        let binding = ImageBinding {
+            texture: inner.texture.clone(),
+            nearest: interp == InterpolationMode::NearestNeighbor,
+        };
+        let to_device = self.state.device_transform();
+        self.state
+            .draw_texture(dst_rect, to_device, &binding, src_uv);
+    }
+
+    /// Find rasterized text in the atlas, or rasterize it with the browser.
+    ///
+    /// The text is drawn onto the scratch canvas under `transform`, which
+    /// maps `rect`, in pixels, to the top left of the canvas. Returns the
+    /// texture and where the text is in it.
+    fn rasterize_text(
+        &mut self,
+        layout: &WebTextLayout,
+        key: TextKey,
+        rect: Rect,
+        transform: Affine,
+    ) -> Result<(ImageBinding, Rect), Error> {
+        let renderer = &mut *self.renderer;
+        if let Some(uv) = renderer.atlas.get(key) {
+            return Ok((ImageBinding::linear(renderer.atlas.texture.clone()), uv));
+        }
+        let (width, height) = (rect.width() as u32, rect.height() as u32);
+        if width > renderer.max_texture_size() || height > renderer.max_texture_size() {
+            return Err(Error::NotSupported);
+        }
+        let scratch = &mut renderer.scratch;
+        scratch.prepare(width, height);
+        scratch.rc.with_save(|rc| {
+            rc.transform(transform);
+            rc.draw_text(layout, Point::ZERO);
+            Ok(())
+        })?;
+        scratch.rc.status()?;
+        if width <= ATLAS_SIZE && height <= ATLAS_SIZE {
+            let uv = renderer
+                .atlas
+                .insert(&renderer.gl, key, &scratch.canvas, width, height)?;
+            if let Some(uv) = uv {
+                return Ok((ImageBinding::linear(renderer.atlas.texture.clone()), uv));
+            }
+        }
+        // too big for the atlas, or it is full.
+        let gl = &renderer.gl;
+        let texture = renderer::create_texture(gl, width, height, &[])?;
+        let (w, h) = (width as i32, height as i32);
+        let result = renderer::copy_canvas(gl, &scratch.canvas, 0, 0, w, h);
+        gl.bind_texture(Gl::TEXTURE_2D, None);
+        result?;
+        Ok((
+            ImageBinding::linear(Rc::new(texture)),
+            Rect::new(0.0, 0.0, 1.0, 1.0),
+        ))
+    }
+}
+
+impl<'a> RenderContext for WebGlRenderContext<'a> {
+    type Brush = Brush;
+
+    type Text = WebText;
+    type TextLayout = WebTextLayout;
+
+    type Image = WebGlImage;
+
+    fn status(&mut self) -> Result<(), Error> {
+        self.state.status()
+    }
+
+    fn clear(&mut self, region: impl Into<Option<Rect>>, color: Color) {
+        self.state.clear(region.into(), color);
+    }
+
+    fn solid_brush(&mut self, color: Color) -> Brush {
+        Brush::Solid(premul_color(color))
+    }
+
+    fn gradient(&mut self, gradient: impl Into<FixedGradient>) -> Result<Brush, Error> {
+        self.gradient_with_options(gradient, GradientOptions::default())
+    }
+
+    fn gradient_with_options(
+        &mut self,
+        gradient: impl Into<FixedGradient>,
+        options: GradientOptions,
+    ) -> Result<Brush, Error> {
+        self.renderer.ramps.gradient(gradient, options)
+    }
+
+    fn fill(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>) {
+        let brush = brush.make_brush(self, || shape.bounding_box());
+        self.state.fill(shape, &brush, false);
+    }
+
+    fn fill_even_odd(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>) {
+        let brush = brush.make_brush(self, || shape.bounding_box());
+        self.state.fill(shape, &brush, true);
+    }
+
+    fn fill_with_mask(
+        &mut self,
+        shape: impl Shape,
+        brush: &impl IntoBrush<Self>,
+        mask: &Self::Image,
+    ) {
+        let brush = brush.make_brush(self, || shape.bounding_box());
+        if let Some(inner) = &mask.0 {
+            let uv = inner.uv(inner.view);
+            let binding = ImageBinding::linear(inner.texture.clone());
+            self.state.fill_with_mask(shape, &brush, &binding, uv);
+        }
+    }
+
+    fn clip(&mut self, shape: impl Shape) {
+        self.state.clip(shape);
+    }
+
+    fn stroke(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>, width: f64) {
+        let brush = brush.make_brush(self, || shape.bounding_box());
+        self.state
+            .stroke(shape, &brush, width, &StrokeStyle::default());
+    }
+
+    fn stroke_styled(
+        &mut self,
+        shape: impl Shape,
+        brush: &impl IntoBrush<Self>,
+        width: f64,
+        style: &StrokeStyle,
+    ) {
+        if piet::util::stroke_dash_caps(self, &shape, brush, width, style) {
+            return;
+        }
+        let brush = brush.make_brush(self, || shape.bounding_box());
+        self.state.stroke(shape, &brush, width, style);
+    }
+
+    fn text(&mut self) -> &mut Self::Text {
+        &mut self.text
+    }
+
+    fn draw_text(&mut self, layout: &Self::TextLayout, pos: impl Into<Point>) {
+        let to_device = self.state.device_transform() * Affine::translate(pos.into().to_vec2());
+        // the background reaches the edges of the layout, and the ink may
+        // go past them.
+        let bounds = layout.image_bounds().union(layout.size().to_rect());
+        let [a, b, c, d, e, f] = to_device.as_coeffs();
+        // Text that isn't rotated or skewed is placed to a quarter of a pixel
+        // horizontally, and to whole pixels vertically, like glyphs are in
+        // the other backends. Other text is rasterized at its average scale,
+        // and transformed when it is drawn.
+        let (scale, subpixel, to_device) = if b == 0.0 && c == 0.0 && a == d && a > 0.0 {
+            let quarters = (e * 4.0).round();
+            let x = (quarters / 4.0).floor();
+            let subpixel = (quarters - x * 4.0) as u8;
+            (a, subpixel, Affine::translate((x, f.round())))
+        } else {
+            let scale = to_device.determinant().abs().sqrt();
+            (scale, 0, to_device * Affine::scale(1.0 / scale))
+        };
+        if !(scale > 1e-9 && scale.is_finite()) || bounds.is_empty() {
+            return;
+        }
+        let to_pixels = Affine::translate((subpixel as f64 / 4.0, 0.0)) * Affine::scale(scale);
+        // leave a pixel of space around the text, so that filtering doesn't
+        // pick up its neighbours.
+        let rect = to_pixels
+            .transform_rect_bbox(bounds)
+            .expand()
+            .inflate(1.0, 1.0);
+        let key = TextKey {
+            layout: layout.id(),
+            scale: (scale * 64.0).round() as u32,
+            subpixel,
+        };
+        let to_canvas = Affine::translate(-rect.origin().to_vec2()) * to_pixels;
+        match self.rasterize_text(layout, key, rect, to_canvas) {
+            Ok((binding, uv)) => self.state.draw_texture(rect, to_device, &binding, uv),
+            Err(e) => self.state.set_error(e),
+        }
+    }
+
+    fn save(&mut self) -> Result<(), Error> {
+        self.state.save();
+        Ok(())
+    }
+
+    fn restore(&mut self) -> Result<(), Error> {
+        self.state.restore()
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        let result = self.renderer.render(&self.state.scene);
+        self.state.restart();
+        result
+    }
+
+    fn transform(&mut self, transform: Affine) {
+        self.state.transform(transform);
+    }
+
+    fn current_transform(&self) -> Affine {
+        self.state.current_transform()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        // image filters aren't drawn yet, so only blurred rects are blurred.
+        let mut capabilities = Capabilities::default();
+        capabilities.supports_global_alpha = true;
+        capabilities.max_image_size = self.renderer.max_texture_size() as usize;
+        capabilities.text_shaping_quality = TextShapingQuality::Full;
+        capabilities
+    }
+
+    fn set_global_alpha(&mut self, alpha: f64) {
+        self.state.set_global_alpha(alpha);
+    }
+
+    fn global_alpha(&self) -> f64 {
+        self.state.global_alpha() as f64
+    }
+
+    fn hit_tester(&mut self) -> Option<&mut HitTester> {
+        Some(self.state.hit_tester())
+    }
+    fn make_image(
+        &mut self,
+        width: usize,
+        height: usize,
+        buf: &[u8],
+        format: ImageFormat,
+    ) -> Result<Self::Image, Error> {
+        if width == 0 || height == 0 {
+            return Ok(WebGlImage(None));
+        }
+        let max = self.renderer.max_texture_size() as usize;
+        if width > max || height > max {
+            return Err(Error::InvalidInput);
+        }
+        let buf = buf
+            .get(..width * height * format.bytes_per_pixel())
+            .ok_or(Error::InvalidInput)?;
+        let data: Cow<[u8]> = match format {
+            ImageFormat::RgbaPremul => buf.into(),
+            ImageFormat::RgbaSeparate => {
+                let mut data = buf.to_vec();
+                piet::util::premultiply_rgba(&mut data);
+                data.into()
+            }
+            ImageFormat::Rgb => {
+                let mut data = vec![0; width * height * 4];
+                piet::util::rgb_to_rgba(buf, &mut data);
+                data.into()
+            }
+            ImageFormat::Grayscale => {
+                let mut data = vec![0; width * height * 4];
+                piet::util::gray_to_rgba(buf, &mut data);
+                data.into()
+            }
+            ImageFormat::BgraSeparate | ImageFormat::BgraPremul | ImageFormat::Rgba16 => {
+                let (buf, format) = piet::util::to_rgba8(buf, format);
+                return self.make_image(width, height, &buf, format);
+            }
+            _ => return Err(Error::NotSupported),
+        };
+        let gl = &self.renderer.gl;
+        let texture = renderer::create_texture(gl, width as u32, height as u32, &data);
+        gl.bind_texture(Gl::TEXTURE_2D, None);
+        Ok(texture_image(texture?, width, height))
+    }
+
+    /// The levels of the mipmap are generated by the GPU, and kept in the
+    /// image's texture, which picks the level for each pixel as it is drawn.
+    fn make_mip_image(
+        &mut self,
+        width: usize,
+        height: usize,
+        buf: &[u8],
+        format: ImageFormat,
+    ) -> Result<MipImage<Self::Image>, Error> {
+        if width == 0 || height == 0 {
+            return Ok(MipImage::from_image(WebGlImage(None)));
+        }
+        let max = self.renderer.max_texture_size() as usize;
+        if width > max || height > max {
+            return Err(Error::InvalidInput);
+        }
+        let data = piet::util::premultiplied_rgba(width * height, buf, format)?;
+        let gl = &self.renderer.gl;
+        let texture = renderer::create_mip_texture(gl, width as u32, height as u32, &data);
+        gl.bind_texture(Gl::TEXTURE_2D, None);
+        Ok(MipImage::from_image(texture_image(texture?, width, height)))
+    }
+
+    #[inline]
+    fn draw_image(
+        &mut self,
+        image: &Self::Image,
+        dst_rect: impl Into<Rect>,
+        interp: InterpolationMode,
+    ) {
+        self.draw_image_inner(image, None, dst_rect.into(), interp);
+    }
+
+    #[inline]
+    fn draw_image_area(
+        &mut self,
+        image: &Self::Image,
+        src_rect: impl Into<Rect>,
+        dst_rect: impl Into<Rect>,
+        interp: InterpolationMode,
+    ) {
+        self.draw_image_inner(image, Some(src_rect.into()), dst_rect.into(), interp);
+    }
+
+    fn capture_image_area(&mut self, _src_rect: impl Into<Rect>) -> Result<Self::Image, Error> {
+        // Nothing is drawn until the frame is finished.
+        Err(Error::NotSupported)
+    }
+
+    fn blurred_rect(&mut self, rect: Rect, blur_radius: f64, brush: &impl IntoBrush<Self>) {
+        let brush = brush.make_brush(self, || rect);
+        let size = piet::util::size_for_blurred_rect(rect, blur_radius);
+        let (width, height) = (size.width as usize, size.height as usize);
+        if width == 0 || height == 0 {
+            return;
+        }
+        let mut data = vec![0u8; width * height];
+        let rect_exp = piet::util::compute_blurred_rect(rect, blur_radius, width, &mut data);
+        let data = data.iter().flat_map(|&a| [a; 4]).collect::<Vec<_>>();
+        let gl = &self.renderer.gl;
+        let texture = renderer::create_texture(gl, width as u32, height as u32, &data);
+        gl.bind_texture(Gl::TEXTURE_2D, None);
+        let mask = match texture {
+            Ok(texture) => ImageBinding::linear(Rc::new(texture)),
+            Err(e) => {
+                self.state.set_error(e);
+                return;
+            }
+        };
+        let uv = Rect::new(0.0, 0.0, 1.0, 1.0);
+        self.state.fill_with_mask(rect_exp, &brush, &mask, uv);
+    }
+}
+
+impl Drop for WebGlRenderContext<'_> {
+    fn drop(&mut self) {
+        // give the scene back, so that its allocations are reused.
+        self.renderer.scene = std::mem::take(&mut self.state.scene);
+    }
+}
+
+impl<'a> IntoBrush<WebGlRenderContext<'a>> for Brush {
+    fn make_brush<'b>(
+        &'b self,
+        _piet: &mut WebGlRenderContext,
+        _bbox: impl FnOnce() -> Rect,
+    ) -> Cow<'b, Brush> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl Image for WebGlImage {
+    fn size(&self) -> Size {
+        self.0
+            .as_ref()
+            .map(|inner| inner.view.size())
+            .unwrap_or(Size::ZERO)
+    }
+
+    fn sub_image(&self, rect: impl Into<Rect>) -> Self {
+        let inner = match &self.0 {
+            Some(inner) => inner,
+            None => return WebGlImage(None),
+        };
+        let view = piet::util::sub_image_view(inner.view, rect.into());
+        if view.is_empty() {
+            return WebGlImage(None);
+        }
+        // the sub-image samples the same texture.
+        WebGlImage(Some(Rc::new(ImageInner {
+            texture: inner.texture.clone(),
+            size: inner.size,
+            view,
+        })))
+    }
+}
+
+/// The image of all of a `width` by `height` texture.
+fn texture_image(texture: Texture, width: usize, height: usize) -> WebGlImage {
+    let size = Size::new(width as f64, height as f64);
+    WebGlImage(Some(Rc::new(ImageInner {
+        texture: Rc::new(texture),
+        size,
+        view: size.to_rect(),
+    })))
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod test {
+    use super::*;
+
+    use piet::{Text, TextLayoutBuilder};
+    use wasm_bindgen::JsCast;
+    use wasm_bindgen_test::*;
+    use web_sys::HtmlCanvasElement;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    /// A renderer for a new canvas, or `None` if the browser has no WebGL2.
+    fn renderer(size: u32) -> Option<Renderer> {
+        let canvas = web_sys::window()?
+            .document()?
+            .create_element("canvas")
+            .ok()?
+            .dyn_into::<HtmlCanvasElement>()
+            .ok()?;
+        canvas.set_width(size);
+        canvas.set_height(size);
+        Renderer::new(get_context(&canvas).ok()?).ok()
+    }
+
+    /// A pixel of the canvas, with `y` going down, as in piet.
+    fn pixel(renderer: &Renderer, x: i32, y: i32) -> [u8; 4] {
+        let gl = &renderer.gl;
+        let mut data = [0; 4];
+        let y = gl.drawing_buffer_height() - 1 - y;
+        gl.read_pixels_with_opt_u8_array(x, y, 1, 1, Gl::RGBA, Gl::UNSIGNED_BYTE, Some(&mut data))
+            .unwrap();
+        data
+    }
+
+    #[wasm_bindgen_test]
+    fn fill_respects_clip_and_scale() {
+        let mut renderer = match renderer(20) {
+            Some(renderer) => renderer,
+            None => return,
+        };
+        let mut rc = WebGlRenderContext::new(&mut renderer, 2.0);
+        rc.clear(None, Color::TRANSPARENT);
+        rc.with_save(|rc| {
+            rc.clip(Rect::new(0., 0., 5., 10.));
+            rc.fill(Rect::new(0., 0., 10., 10.), &Color::RED);
+            Ok(())
+        })
+        .unwrap();
+        rc.fill(Rect::new(0., 8., 10., 10.), &Color::BLUE);
+        rc.finish().unwrap();
+        std::mem::drop(rc);
+
+        assert_eq!(pixel(&renderer, 5, 5), [255, 0, 0, 255]);
+        assert_eq!(pixel(&renderer, 15, 5), [0, 0, 0, 0]);
+        // the clip is gone after the restore.
+        assert_eq!(pixel(&renderer, 15, 18), [0, 0, 255, 255]);
+    }
+
+    #[wasm_bindgen_test]
+    fn text_is_drawn() {
+        let mut renderer = match renderer(40) {
+            Some(renderer) => renderer,
+            None => return,
+        };
+        let layout = renderer
+            .text()
+            .new_text_layout("\u{2588}")
+            .font(piet::FontFamily::SANS_SERIF, 30.0)
+            .text_color(Color::BLACK)
+            .build()
+            .unwrap();
+        let mut rc = WebGlRenderContext::new(&mut renderer, 1.0);
+        rc.clear(None, Color::WHITE);
+        rc.draw_text(&layout, (0.0, 0.0));
+        rc.finish().unwrap();
+        std::mem::drop(rc);
+
+        let bounds = layout.image_bounds();
+        let center = bounds.center();
+        assert_eq!(
+            pixel(&renderer, center.x as i32, center.y as i32),
+            [0, 0, 0, 255]
+        );
+    }
+}
@@ -0,0 +1,686 @@
+//! The GPU side: the shader program, shared textures, and drawing a frame.
+
+use std::fmt;
+use std::rc::Rc;
+
+use piet::Error;
+use piet_tess::ramps::{Ramps, RAMP_WIDTH};
+use piet_tess::scene::{Binding, Command, Vertex};
+use piet_web::{WebRenderContext, WebText};
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{
+    CanvasRenderingContext2d, HtmlCanvasElement, WebGl2RenderingContext as Gl, WebGlBuffer,
+    WebGlContextAttributes, WebGlFramebuffer, WebGlProgram, WebGlRenderbuffer, WebGlSampler,
+    WebGlShader, WebGlTexture, WebGlUniformLocation, WebGlVertexArrayObject,
+};
+
+use crate::atlas::TextAtlas;
+
+/// The number of samples per pixel, for antialiasing, if the context
+/// supports that many.
+const SAMPLE_COUNT: i32 = 4;
+/// The texture units that gradient ramps and images are bound to.
+const RAMP_UNIT: u32 = 0;
+const IMAGE_UNIT: u32 = 1;
+
+/// The state that outlives a frame: the shader program, gradient ramps, the
+/// text atlas, and buffers.
+///
+/// Create one for each WebGL2 context, and pass it to
+/// [`WebGlRenderContext::new`](crate::WebGlRenderContext::new) for each
+/// frame.
+pub struct Renderer {
+    pub(crate) gl: Gl,
+    program: WebGlProgram,
+    size_location: Option<WebGlUniformLocation>,
+    ramp_rows_location: Option<WebGlUniformLocation>,
+    vertex_array: WebGlVertexArrayObject,
+    vertices: WebGlBuffer,
+    indices: WebGlBuffer,
+    linear_sampler: WebGlSampler,
+    nearest_sampler: WebGlSampler,
+    /// Bound for draws that don't use an image.
+    no_image: Texture,
+    pub(crate) ramps: Ramps,
+    ramp_texture: RampTexture,
+    pub(crate) atlas: TextAtlas,
+    targets: Option<FrameTargets>,
+    pub(crate) text: WebText,
+    pub(crate) scratch: Scratch,
+    /// Kept between frames, to reuse its allocations.
+    pub(crate) scene: Scene,
+}
+
+/// A frame's triangles, with the textures of its images.
+pub(crate) type Scene = piet_tess::scene::Scene<ImageBinding>;
+
+/// A texture, which is deleted when it is dropped.
+pub(crate) struct Texture {
+    gl: Gl,
+    pub(crate) texture: WebGlTexture,
+}
+
+/// A texture that is bound for image paints and masks, and how it is
+/// sampled.
+#[derive(Clone)]
+pub(crate) struct ImageBinding {
+    pub(crate) texture: Rc<Texture>,
+    pub(crate) nearest: bool,
+}
+
+/// A 2D canvas that text is drawn onto with piet-web, before it is copied
+/// into a texture.
+pub(crate) struct Scratch {
+    pub(crate) canvas: HtmlCanvasElement,
+    pub(crate) ctx: CanvasRenderingContext2d,
+    pub(crate) rc: WebRenderContext<'static>,
+}
+
+/// The multisampled color and stencil buffers that a frame is drawn into,
+/// before it is copied to the canvas.
+struct FrameTargets {
+    gl: Gl,
+    width: i32,
+    height: i32,
+    framebuffer: WebGlFramebuffer,
+    color: WebGlRenderbuffer,
+    stencil: WebGlRenderbuffer,
+}
+
+/// The texture that the color ramps of gradients are in, one per row.
+struct RampTexture {
+    texture: Option<Texture>,
+    /// The number of rows in the texture.
+    capacity: u32,
+}
+
+/// An error reported by WebGL, such as a shader that didn't compile.
+#[derive(Clone, Debug)]
+struct WebGlError(String);
+
+impl Renderer {
+    /// Create a new renderer for `gl`.
+    ///
+    /// The context must not be antialiased, since frames are drawn into
+    /// multisampled buffers of the renderer's and then copied to it; use
+    /// [`get_context`](crate::get_context) to get a suitable one.
+    pub fn new(gl: Gl) -> Result<Renderer, Error> {
+        let program = create_program(&gl)?;
+        let size_location = gl.get_uniform_location(&program, "size");
+        let ramp_rows_location = gl.get_uniform_location(&program, "ramp_rows");
+        gl.use_program(Some(&program));
+        gl.uniform1i(
+            gl.get_uniform_location(&program, "ramps").as_ref(),
+            RAMP_UNIT as i32,
+        );
+        gl.uniform1i(
+            gl.get_uniform_location(&program, "image").as_ref(),
+            IMAGE_UNIT as i32,
+        );
+        gl.use_program(None);
+
+        let vertex_array = gl.create_vertex_array().ok_or_else(lost)?;
+        let vertices = gl.create_buffer().ok_or_else(lost)?;
+        let indices = gl.create_buffer().ok_or_else(lost)?;
+        gl.bind_vertex_array(Some(&vertex_array));
+        gl.bind_buffer(Gl::ARRAY_BUFFER, Some(&vertices));
+        gl.bind_buffer(Gl::ELEMENT_ARRAY_BUFFER, Some(&indices));
+        let stride = std::mem::size_of::<Vertex>() as i32;
+        // pos, paint_pos, mask_pos and color, followed by kind.
+        let mut offset = 0;
+        for (location, size) in [(0, 2), (1, 2), (2, 2), (3, 4)] {
+            gl.enable_vertex_attrib_array(location);
+            gl.vertex_attrib_pointer_with_i32(location, size, Gl::FLOAT, false, stride, offset);
+            offset += size * 4;
+        }
+        gl.enable_vertex_attrib_array(4);
+        gl.vertex_attrib_i_pointer_with_i32(4, 1, Gl::UNSIGNED_INT, stride, offset);
+        gl.bind_vertex_array(None);
+
+        let sampler = |min_filter, filter| -> Result<WebGlSampler, Error> {
+            let sampler = gl.create_sampler().ok_or_else(lost)?;
+            gl.sampler_parameteri(&sampler, Gl::TEXTURE_MIN_FILTER, min_filter as i32);
+            gl.sampler_parameteri(&sampler, Gl::TEXTURE_MAG_FILTER, filter as i32);
+            gl.sampler_parameteri(&sampler, Gl::TEXTURE_WRAP_S, Gl::CLAMP_TO_EDGE as i32);
+            gl.sampler_parameteri(&sampler, Gl::TEXTURE_WRAP_T, Gl::CLAMP_TO_EDGE as i32);
+            Ok(sampler)
+        };
+        // only the images made by `make_mip_image` have levels, and the
+        // textures of the others have one, which is the one that is used.
+        let linear_sampler = sampler(Gl::LINEAR_MIPMAP_LINEAR, Gl::LINEAR)?;
+        let nearest_sampler = sampler(Gl::NEAREST_MIPMAP_NEAREST, Gl::NEAREST)?;
+        let no_image = create_texture(&gl, 1, 1, &[255; 4])?;
+        let atlas = TextAtlas::new(&gl)?;
+        let scratch = Scratch::new()?;
+
+        Ok(Renderer {
+            text: WebText::new(scratch.ctx.clone()),
+            gl,
+            program,
+            size_location,
+            ramp_rows_location,
+            vertex_array,
+            vertices,
+            indices,
+            linear_sampler,
+            nearest_sampler,
+            no_image,
+            ramps: Ramps::new(),
+            ramp_texture: RampTexture::new(),
+            atlas,
+            targets: None,
+            scratch,
+            scene: Scene::default(),
+        })
+    }
+
+    /// The WebGL context that is drawn into.
+    pub fn context(&self) -> &Gl {
+        &self.gl
+    }
+
+    /// The text factory, for making text layouts outside of a frame.
+    pub fn text(&mut self) -> &mut WebText {
+        &mut self.text
+    }
+
+    /// The largest width and height of a texture.
+    pub(crate) fn max_texture_size(&self) -> u32 {
+        self.gl
+            .get_parameter(Gl::MAX_TEXTURE_SIZE)
+            .ok()
+            .and_then(|size| size.as_f64())
+            .map(|size| size as u32)
+            // the least that WebGL2 allows.
+            .unwrap_or(2048)
+    }
+
+    /// Draw `scene` into the canvas of the context.
+    pub(crate) fn render(&mut self, scene: &Scene) -> Result<(), Error> {
+        if self.gl.is_context_lost() {
+            return Err(Error::BackendLost);
+        }
+        let (width, height) = (
+            self.gl.drawing_buffer_width(),
+            self.gl.drawing_buffer_height(),
+        );
+        if width <= 0 || height <= 0 {
+            return Ok(());
+        }
+        let max_texture_size = self.max_texture_size();
+        self.ramp_texture
+            .upload(&mut self.ramps, &self.gl, max_texture_size)?;
+        self.prepare_targets(width, height)?;
+        let (gl, targets) = match &self.targets {
+            Some(targets) => (&self.gl, targets),
+            None => return Err(Error::InvalidInput),
+        };
+
+        gl.bind_framebuffer(Gl::FRAMEBUFFER, Some(&targets.framebuffer));
+        gl.viewport(0, 0, width, height);
+        gl.disable(Gl::SCISSOR_TEST);
+        gl.disable(Gl::DEPTH_TEST);
+        gl.disable(Gl::CULL_FACE);
+        gl.color_mask(true, true, true, true);
+        gl.stencil_mask(0xff);
+        gl.clear_stencil(0);
+        let mut clear = Gl::STENCIL_BUFFER_BIT;
+        // otherwise, what was drawn before is kept.
+        if let Some([r, g, b, a]) = scene.clear_color {
+            gl.clear_color(r, g, b, a);
+            clear |= Gl::COLOR_BUFFER_BIT;
+        }
+        gl.clear(clear);
+
+        if !scene.commands.is_empty() {
+            gl.bind_vertex_array(Some(&self.vertex_array));
+            gl.bind_buffer(Gl::ARRAY_BUFFER, Some(&self.vertices));
+            gl.bind_buffer(Gl::ELEMENT_ARRAY_BUFFER, Some(&self.indices));
+            gl.buffer_data_with_u8_array(
+                Gl::ARRAY_BUFFER,
+                bytemuck::cast_slice(&scene.vertices),
+                Gl::STREAM_DRAW,
+            );
+            gl.buffer_data_with_u8_array(
+                Gl::ELEMENT_ARRAY_BUFFER,
+                bytemuck::cast_slice(&scene.indices),
+                Gl::STREAM_DRAW,
+            );
+            gl.use_program(Some(&self.program));
+            gl.uniform2f(self.size_location.as_ref(), width as f32, height as f32);
+            gl.uniform1f(
+                self.ramp_rows_location.as_ref(),
+                self.ramp_texture.capacity as f32,
+            );
+            gl.active_texture(Gl::TEXTURE0 + RAMP_UNIT);
+            gl.bind_texture(
+                Gl::TEXTURE_2D,
+                self.ramp_texture
+                    .texture
+                    .as_ref()
+                    .map(|ramps| &ramps.texture),
+            );
+            gl.enable(Gl::STENCIL_TEST);
+            gl.blend_func(Gl::ONE, Gl::ONE_MINUS_SRC_ALPHA);
+            for command in &scene.commands {
+                let (indices, image) = match command {
+                    Command::Draw {
+                        indices,
+                        image,
+                        stencil,
+                    } => {
+                        gl.enable(Gl::BLEND);
+                        gl.color_mask(true, true, true, true);
+                        gl.stencil_func(Gl::EQUAL, *stencil as i32, 0xff);
+                        gl.stencil_op(Gl::KEEP, Gl::KEEP, Gl::KEEP);
+                        (indices, image.as_ref())
+                    }
+                    Command::Clear { indices } => {
+                        gl.disable(Gl::BLEND);
+                        gl.color_mask(true, true, true, true);
+                        gl.stencil_func(Gl::ALWAYS, 0, 0xff);
+                        gl.stencil_op(Gl::KEEP, Gl::KEEP, Gl::KEEP);
+                        (indices, None)
+                    }
+                    // A clip is added inside the current clip, where the
+                    // stencil equals the reference, and removed everywhere
+                    // inside it, where the stencil is more than the reference.
+                    Command::PushClip { indices, depth } => {
+                        gl.color_mask(false, false, false, false);
+                        gl.stencil_func(Gl::EQUAL, *depth as i32 - 1, 0xff);
+                        gl.stencil_op(Gl::KEEP, Gl::KEEP, Gl::INCR);
+                        (indices, None)
+                    }
+                    Command::PopClip { indices, depth } => {
+                        gl.color_mask(false, false, false, false);
+                        gl.stencil_func(Gl::LESS, *depth as i32, 0xff);
+                        gl.stencil_op(Gl::KEEP, Gl::KEEP, Gl::DECR);
+                        (indices, None)
+                    }
+                };
+                let (texture, sampler) = match image {
+                    Some(image) if image.nearest => (&image.texture.texture, &self.nearest_sampler),
+                    Some(image) => (&image.texture.texture, &self.linear_sampler),
+                    None => (&self.no_image.texture, &self.linear_sampler),
+                };
+                gl.active_texture(Gl::TEXTURE0 + IMAGE_UNIT);
+                gl.bind_texture(Gl::TEXTURE_2D, Some(texture));
+                gl.bind_sampler(IMAGE_UNIT, Some(sampler));
+                gl.draw_elements_with_i32(
+                    Gl::TRIANGLES,
+                    indices.len() as i32,
+                    Gl::UNSIGNED_INT,
+                    indices.start as i32 * 4,
+                );
+            }
+            gl.disable(Gl::STENCIL_TEST);
+            gl.disable(Gl::BLEND);
+            gl.color_mask(true, true, true, true);
+            gl.bind_sampler(IMAGE_UNIT, None);
+            gl.use_program(None);
+            gl.bind_vertex_array(None);
+        }
+
+        // resolve the samples into the canvas.
+        gl.bind_framebuffer(Gl::READ_FRAMEBUFFER, Some(&targets.framebuffer));
+        gl.bind_framebuffer(Gl::DRAW_FRAMEBUFFER, None);
+        gl.blit_framebuffer(
+            0,
+            0,
+            width,
+            height,
+            0,
+            0,
+            width,
+            height,
+            Gl::COLOR_BUFFER_BIT,
+            Gl::NEAREST,
+        );
+        gl.bind_framebuffer(Gl::FRAMEBUFFER, None);
+        Ok(())
+    }
+
+    fn prepare_targets(&mut self, width: i32, height: i32) -> Result<(), Error> {
+        if let Some(targets) = &self.targets {
+            if targets.width == width && targets.height == height {
+                return Ok(());
+            }
+        }
+        // free the old buffers first, since they may be large.
+        self.targets = None;
+        let gl = &self.gl;
+        let max_samples = gl
+            .get_parameter(Gl::MAX_SAMPLES)
+            .ok()
+            .and_then(|samples| samples.as_f64())
+            .unwrap_or(0.0) as i32;
+        let samples = SAMPLE_COUNT.min(max_samples);
+        let renderbuffer = |format| -> Result<WebGlRenderbuffer, Error> {
+            let renderbuffer = gl.create_renderbuffer().ok_or_else(lost)?;
+            gl.bind_renderbuffer(Gl::RENDERBUFFER, Some(&renderbuffer));
+            gl.renderbuffer_storage_multisample(Gl::RENDERBUFFER, samples, format, width, height);
+            Ok(renderbuffer)
+        };
+        let color = renderbuffer(Gl::RGBA8)?;
+        let stencil = renderbuffer(Gl::DEPTH24_STENCIL8)?;
+        gl.bind_renderbuffer(Gl::RENDERBUFFER, None);
+        let framebuffer = gl.create_framebuffer().ok_or_else(lost)?;
+        let targets = FrameTargets {
+            gl: gl.clone(),
+            width,
+            height,
+            framebuffer,
+            color,
+            stencil,
+        };
+        gl.bind_framebuffer(Gl::FRAMEBUFFER, Some(&targets.framebuffer));
+        gl.framebuffer_renderbuffer(
+            Gl::FRAMEBUFFER,
+            Gl::COLOR_ATTACHMENT0,
+            Gl::RENDERBUFFER,
+            Some(&targets.color),
+        );
+        gl.framebuffer_renderbuffer(
+            Gl::FRAMEBUFFER,
+            Gl::DEPTH_STENCIL_ATTACHMENT,
+            Gl::RENDERBUFFER,
+            Some(&targets.stencil),
+        );
+        let status = gl.check_framebuffer_status(Gl::FRAMEBUFFER);
+        gl.bind_framebuffer(Gl::FRAMEBUFFER, None);
+        if status != Gl::FRAMEBUFFER_COMPLETE {
+            return Err(backend_error(format!(
+                "incomplete framebuffer: {:#x}",
+                status
+            )));
+        }
+        self.targets = Some(targets);
+        Ok(())
+    }
+}
+
+impl Scratch {
+    fn new() -> Result<Scratch, Error> {
+        let window = web_sys::window().ok_or(Error::NotSupported)?;
+        let canvas = window
+            .document()
+            .ok_or(Error::NotSupported)?
+            .create_element("canvas")
+            .map_err(js_error)?
+            .dyn_into::<HtmlCanvasElement>()
+            .map_err(js_error)?;
+        let ctx = canvas
+            .get_context("2d")
+            .map_err(js_error)?
+            .ok_or(Error::NotSupported)?
+            .dyn_into::<CanvasRenderingContext2d>()
+            .map_err(js_error)?;
+        Ok(Scratch {
+            rc: WebRenderContext::new(ctx.clone(), window),
+            canvas,
+            ctx,
+        })
+    }
+
+    /// Make the canvas at least `width` by `height` pixels, and clear that
+    /// much of it.
+    pub(crate) fn prepare(&mut self, width: u32, height: u32) {
+        // resizing clears the whole canvas, and resets its state.
+        if self.canvas.width() < width || self.canvas.height() < height {
+            self.canvas.set_width(self.canvas.width().max(width));
+            self.canvas.set_height(self.canvas.height().max(height));
+        }
+        self.ctx.clear_rect(0.0, 0.0, width as f64, height as f64);
+    }
+}
+
+impl ImageBinding {
+    /// A binding of `texture` that is sampled linearly.
+    pub(crate) fn linear(texture: Rc<Texture>) -> ImageBinding {
+        ImageBinding {
+            texture,
+            nearest: false,
+        }
+    }
+}
+
+impl Binding for ImageBinding {
+    fn same(&self, other: &ImageBinding) -> bool {
+        self.texture.same(&other.texture) && self.nearest == other.nearest
+    }
+}
+
+impl RampTexture {
+    fn new() -> RampTexture {
+        RampTexture {
+            texture: None,
+            capacity: 0,
+        }
+    }
+
+    /// Copy new ramps to the texture, replacing it if it is too small.
+    fn upload(&mut self, ramps: &mut Ramps, gl: &Gl, max_texture_size: u32) -> Result<(), Error> {
+        if !ramps.take_changed() {
+            return Ok(());
+        }
+        let rows = ramps.len().max(1);
+        if self.texture.is_none() || rows > self.capacity {
+            self.capacity = rows.next_power_of_two().clamp(16, max_texture_size);
+            let texture = create_texture(gl, RAMP_WIDTH, self.capacity, &[])?;
+            // ramps are sampled without a sampler object.
+            for (param, value) in [
+                (Gl::TEXTURE_MIN_FILTER, Gl::LINEAR),
+                (Gl::TEXTURE_MAG_FILTER, Gl::LINEAR),
+                (Gl::TEXTURE_WRAP_S, Gl::CLAMP_TO_EDGE),
+                (Gl::TEXTURE_WRAP_T, Gl::CLAMP_TO_EDGE),
+            ] {
+                gl.tex_parameteri(Gl::TEXTURE_2D, param, value as i32);
+            }
+            gl.bind_texture(Gl::TEXTURE_2D, None);
+            self.texture = Some(texture);
+        }
+        let rows = ramps.len().min(self.capacity);
+        if let (Some(texture), true) = (&self.texture, rows > 0) {
+            gl.bind_texture(Gl::TEXTURE_2D, Some(&texture.texture));
+            let result = gl.tex_sub_image_2d_with_i32_and_i32_and_u32_and_type_and_opt_u8_array(
+                Gl::TEXTURE_2D,
+                0,
+                0,
+                0,
+                RAMP_WIDTH as i32,
+                rows as i32,
+                Gl::RGBA,
+                Gl::UNSIGNED_BYTE,
+                Some(&ramps.data()[..(rows * RAMP_WIDTH * 4) as usize]),
+            );
+            gl.bind_texture(Gl::TEXTURE_2D, None);
+            result.map_err(js_error)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Texture {
+    fn drop(&mut self) {
+        self.gl.delete_texture(Some(&self.texture));
+    }
+}
+
+impl Drop for FrameTargets {
+    fn drop(&mut self) {
+        self.gl.delete_framebuffer(Some(&self.framebuffer));
+        self.gl.delete_renderbuffer(Some(&self.color));
+        self.gl.delete_renderbuffer(Some(&self.stencil));
+    }
+}
+
+impl fmt::Display for WebGlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "WebGL error: {}", self.0)
+    }
+}
+
+impl std::error::Error for WebGlError {}
+
+/// Get the WebGL2 context of `canvas`, with the attributes that a
+/// [`Renderer`] expects.
+///
+/// The context has no antialiasing, depth or stencil buffers of its own,
+/// since frames are drawn into multisampled buffers of the renderer's, and
+/// its alpha is premultiplied.
+pub fn get_context(canvas: &HtmlCanvasElement) -> Result<Gl, Error> {
+    let attributes = WebGlContextAttributes::new();
+    attributes.set_alpha(true);
+    attributes.set_antialias(false);
+    attributes.set_depth(false);
+    attributes.set_stencil(false);
+    attributes.set_premultiplied_alpha(true);
+    canvas
+        .get_context_with_context_options("webgl2", &attributes)
+        .map_err(js_error)?
+        // browsers without WebGL2
+        .ok_or(Error::NotSupported)?
+        .dyn_into::<Gl>()
+        .map_err(js_error)
+}
+
+/// Make an RGBA texture with premultiplied `data`, or with undefined
+/// contents if `data` is empty, and leave it bound.
+pub(crate) fn create_texture(
+    gl: &Gl,
+    width: u32,
+    height: u32,
+    data: &[u8],
+) -> Result<Texture, Error> {
+    create_texture_with_levels(gl, width, height, data, 1)
+}
+
+/// Make an RGBA texture with premultiplied `data`, and the levels of its
+/// mipmap, which are generated by the GPU, and leave it bound.
+pub(crate) fn create_mip_texture(
+    gl: &Gl,
+    width: u32,
+    height: u32,
+    data: &[u8],
+) -> Result<Texture, Error> {
+    // each level is half the size of the one before, down to a pixel.
+    let levels = 32 - width.max(height).leading_zeros() as i32;
+    let texture = create_texture_with_levels(gl, width, height, data, levels)?;
+    gl.generate_mipmap(Gl::TEXTURE_2D);
+    Ok(texture)
+}
+
+fn create_texture_with_levels(
+    gl: &Gl,
+    width: u32,
+    height: u32,
+    data: &[u8],
+    levels: i32,
+) -> Result<Texture, Error> {
+    let texture = Texture {
+        gl: gl.clone(),
+        texture: gl.create_texture().ok_or_else(lost)?,
+    };
+    gl.bind_texture(Gl::TEXTURE_2D, Some(&texture.texture));
+    gl.tex_storage_2d(
+        Gl::TEXTURE_2D,
+        levels,
+        Gl::RGBA8,
+        width as i32,
+        height as i32,
+    );
+    if !data.is_empty() {
+        gl.pixel_storei(Gl::UNPACK_ALIGNMENT, 1);
+        gl.tex_sub_image_2d_with_i32_and_i32_and_u32_and_type_and_opt_u8_array(
+            Gl::TEXTURE_2D,
+            0,
+            0,
+            0,
+            width as i32,
+            height as i32,
+            Gl::RGBA,
+            Gl::UNSIGNED_BYTE,
+            Some(data),
+        )
+        .map_err(js_error)?;
+    }
+    Ok(texture)
+}
+
+/// Copy the top left `width` by `height` pixels of `canvas` into the bound
+/// texture at `x`, `y`, premultiplying them.
+pub(crate) fn copy_canvas(
+    gl: &Gl,
+    canvas: &HtmlCanvasElement,
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+) -> Result<(), Error> {
+    gl.pixel_storei(Gl::UNPACK_PREMULTIPLY_ALPHA_WEBGL, 1);
+    let result = gl.tex_sub_image_2d_with_i32_and_i32_and_u32_and_type_and_html_canvas_element(
+        Gl::TEXTURE_2D,
+        0,
+        x,
+        y,
+        width,
+        height,
+        Gl::RGBA,
+        Gl::UNSIGNED_BYTE,
+        canvas,
+    );
+    gl.pixel_storei(Gl::UNPACK_PREMULTIPLY_ALPHA_WEBGL, 0);
+    result.map_err(js_error)
+}
+
+fn create_program(gl: &Gl) -> Result<WebGlProgram, Error> {
+    let vertex = compile_shader(gl, Gl::VERTEX_SHADER, include_str!("shader.vert"))?;
+    let fragment = compile_shader(gl, Gl::FRAGMENT_SHADER, include_str!("shader.frag"))?;
+    let program = gl.create_program().ok_or_else(lost)?;
+    gl.attach_shader(&program, &vertex);
+    gl.attach_shader(&program, &fragment);
+    gl.link_program(&program);
+    // the program keeps what it needs.
+    gl.delete_shader(Some(&vertex));
+    gl.delete_shader(Some(&fragment));
+    let linked = gl
+        .get_program_parameter(&program, Gl::LINK_STATUS)
+        .as_bool()
+        .unwrap_or(false);
+    if !linked {
+        let log = gl.get_program_info_log(&program).unwrap_or_default();
+        gl.delete_program(Some(&program));
+        return Err(backend_error(log));
+    }
+    Ok(program)
+}
+
+fn compile_shader(gl: &Gl, kind: u32, source: &str) -> Result<WebGlShader, Error> {
+    let shader = gl.create_shader(kind).ok_or_else(lost)?;
+    gl.shader_source(&shader, source);
+    gl.compile_shader(&shader);
+    let compiled = gl
+        .get_shader_parameter(&shader, Gl::COMPILE_STATUS)
+        .as_bool()
+        .unwrap_or(false);
+    if !compiled {
+        let log = gl.get_shader_info_log(&shader).unwrap_or_default();
+        gl.delete_shader(Some(&shader));
+        return Err(backend_error(log));
+    }
+    Ok(shader)
+}
+
+/// WebGL creates nothing while its context is lost.
+fn lost() -> Error {
+    Error::BackendLost
+}
+
+fn backend_error(message: String) -> Error {
+    let e: Box<dyn std::error::Error> = Box::new(WebGlError(message));
+    e.into()
+}
+
+pub(crate) fn js_error(value: impl Into<JsValue>) -> Error {
+    backend_error(format!("{:?}", value.into()))
+}
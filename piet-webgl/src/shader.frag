@@ -0,0 +1,80 @@
+#version 300 es
+
+precision highp float;
+
+// Keep these, and the paint kinds below, in sync with `piet_tess::scene`.
+const uint PAINT_MASK = 0xfu;
+const uint IMAGE_MASK = 0x10u;
+const uint DITHER_MASK = 0x40u;
+
+uniform sampler2D ramps;
+uniform float ramp_rows;
+uniform sampler2D image;
+
+in vec2 v_paint_pos;
+in vec2 v_mask_pos;
+in vec4 v_color;
+flat in uint v_kind;
+
+out vec4 frag_color;
+
+// The position along a radial gradient of `p`, in a space where the gradient
+// is the unit circle, and its focus is at `focus`.
+float radial_t(vec2 p, vec2 focus) {
+    vec2 d = p - focus;
+    float dd = dot(d, d);
+    if (dd == 0.0) {
+        return 0.0;
+    }
+    float fd = dot(focus, d);
+    float ff = dot(focus, focus);
+    float s = (-fd + sqrt(max(fd * fd - dd * (ff - 1.0), 0.0))) / dd;
+    return 1.0 / s;
+}
+
+vec4 ramp(float t, float row) {
+    vec2 uv = vec2(clamp(t, 0.0, 1.0), (row + 0.5) / ramp_rows);
+    return textureLod(ramps, uv, 0.0);
+}
+
+// Offset `color` by a 4×4 ordered dither at the pixel `pos`, of up to half a
+// step of 8 bits, so that gradients don't band.
+vec4 dither(vec4 color, vec2 pos) {
+    uvec2 p = uvec2(pos) & 3u;
+    uint v = p.x ^ p.y;
+    uint bayer = ((v & 1u) << 3) | ((p.y & 1u) << 2) | (v & 2u) | ((p.y & 2u) >> 1);
+    float offset = ((float(bayer) + 0.5) / 16.0 - 0.5) / 255.0;
+    return vec4(clamp(color.rgb + offset, vec3(0.0), vec3(color.a)), color.a);
+}
+
+void main() {
+    // derivatives are taken outside of branches, for images with mipmaps.
+    vec2 paint_dx = dFdx(v_paint_pos);
+    vec2 paint_dy = dFdy(v_paint_pos);
+    vec4 color;
+    switch (v_kind & PAINT_MASK) {
+        // linear gradient
+        case 1u:
+            color = ramp(v_paint_pos.x, v_color.z) * v_color.w;
+            break;
+        // radial gradient
+        case 2u:
+            color = ramp(radial_t(v_paint_pos, v_color.xy), v_color.z) * v_color.w;
+            break;
+        // image
+        case 3u:
+            color = textureGrad(image, v_paint_pos, paint_dx, paint_dy) * v_color.w;
+            break;
+        // solid color
+        default:
+            color = v_color;
+            break;
+    }
+    if ((v_kind & IMAGE_MASK) != 0u) {
+        color *= textureLod(image, v_mask_pos, 0.0).a;
+    }
+    if ((v_kind & DITHER_MASK) != 0u) {
+        color = dither(color, gl_FragCoord.xy);
+    }
+    frag_color = color;
+}
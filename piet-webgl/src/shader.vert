@@ -0,0 +1,27 @@
+#version 300 es
+
+// Everything piet draws is a triangle mesh, in pixel coordinates. Each vertex
+// says how to paint itself, so that meshes with different brushes can share a
+// draw call.
+
+uniform vec2 size;
+
+layout(location = 0) in vec2 pos;
+layout(location = 1) in vec2 paint_pos;
+layout(location = 2) in vec2 mask_pos;
+layout(location = 3) in vec4 color;
+layout(location = 4) in uint kind;
+
+out vec2 v_paint_pos;
+out vec2 v_mask_pos;
+out vec4 v_color;
+flat out uint v_kind;
+
+void main() {
+    vec2 ndc = pos / size * 2.0 - 1.0;
+    gl_Position = vec4(ndc.x, -ndc.y, 0.0, 1.0);
+    v_paint_pos = paint_pos;
+    v_mask_pos = mask_pos;
+    v_color = color;
+    v_kind = kind;
+}
@@ -0,0 +1,94 @@
+//! Basic example of rendering with wgpu, without a window.
+
+use std::path::Path;
+
+use piet::{samples, RenderContext};
+use piet_wgpu::wgpu;
+use piet_wgpu::{Renderer, WgpuRenderContext};
+
+const FILE_PREFIX: &str = "wgpu-test";
+
+fn main() {
+    samples::samples_main(run_sample, FILE_PREFIX, None);
+}
+
+fn run_sample(
+    number: usize,
+    scale: f64,
+    save_path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let sample = samples::get(number)?;
+    let size = sample.size() * scale;
+    let (width, height) = (size.width as u32, size.height as u32);
+
+    let instance = wgpu::Instance::default();
+    let adapter = pollster::block_on(instance.request_adapter(&Default::default()))
+        .ok_or("no suitable GPU adapter")?;
+    let (device, queue) = pollster::block_on(adapter.request_device(&Default::default(), None))?;
+    let mut renderer = Renderer::new(&device, &queue);
+
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: None,
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba8Unorm,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+    let mut piet_context = WgpuRenderContext::new(&mut renderer, &device, &queue, &texture, scale);
+
+    sample.draw(&mut piet_context)?;
+
+    piet_context.finish()?;
+    std::mem::drop(piet_context);
+
+    let data = read_texture(&device, &queue, &texture);
+    image::save_buffer(save_path, &data, width, height, image::ColorType::Rgba8).map_err(Into::into)
+}
+
+/// Copy the pixels of an RGBA texture back from the GPU.
+fn read_texture(device: &wgpu::Device, queue: &wgpu::Queue, texture: &wgpu::Texture) -> Vec<u8> {
+    let (width, height) = (texture.width(), texture.height());
+    // rows in the buffer must be aligned.
+    let row_len = width * 4;
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let stride = row_len.div_ceil(align) * align;
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size: (stride * height) as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&Default::default());
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(stride),
+                rows_per_image: None,
+            },
+        },
+        texture.size(),
+    );
+    queue.submit(Some(encoder.finish()));
+
+    let slice = buffer.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |_| ());
+    device.poll(wgpu::Maintain::Wait);
+    let mapped = slice.get_mapped_range();
+    let mut data = Vec::with_capacity((row_len * height) as usize);
+    for row in mapped.chunks_exact(stride as usize) {
+        data.extend_from_slice(&row[..row_len as usize]);
+    }
+    // The texture is premultiplied, and PNGs aren't.
+    piet::util::unpremultiply_rgba(&mut data);
+    data
+}
@@ -0,0 +1,144 @@
+//! A texture of rasterized glyphs, which text is drawn from.
+
+use piet::kurbo::Rect;
+use piet_tess::atlas::Atlas;
+
+/// The width and height of the atlas texture.
+const ATLAS_SIZE: u32 = 1024;
+
+/// Everything that changes how a glyph is rasterized.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct GlyphKey {
+    pub(crate) font: u64,
+    pub(crate) glyph: u16,
+    /// The size of the font, in 1/64ths of a pixel.
+    pub(crate) size: u32,
+    /// The horizontal offset of the glyph from the pixel grid, in quarters of
+    /// a pixel.
+    pub(crate) subpixel: u8,
+}
+
+/// A glyph's coverage, one byte per pixel.
+pub(crate) struct GlyphBitmap {
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    /// The position of the top left of the bitmap, relative to the glyph's
+    /// origin on the pixel grid.
+    pub(crate) left: i32,
+    pub(crate) top: i32,
+    pub(crate) data: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct AtlasGlyph {
+    /// Where to draw the glyph, in pixels relative to its origin.
+    pub(crate) rect: Rect,
+    /// Where the glyph is in the atlas, from 0 to 1.
+    pub(crate) uv: Rect,
+}
+
+pub(crate) struct GlyphAtlas {
+    texture: wgpu::Texture,
+    /// `None` for glyphs without any pixels, like spaces.
+    glyphs: Atlas<GlyphKey, Option<AtlasGlyph>>,
+}
+
+impl GlyphAtlas {
+    pub(crate) fn new(device: &wgpu::Device) -> GlyphAtlas {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("piet glyphs"),
+            size: wgpu::Extent3d {
+                width: ATLAS_SIZE,
+                height: ATLAS_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        GlyphAtlas {
+            texture,
+            glyphs: Atlas::new(ATLAS_SIZE),
+        }
+    }
+
+    pub(crate) fn view(&self) -> wgpu::TextureView {
+        self.texture
+            .create_view(&wgpu::TextureViewDescriptor::default())
+    }
+
+    pub(crate) fn begin_frame(&mut self) {
+        self.glyphs.begin_frame();
+    }
+
+    /// Find a glyph, rasterizing and adding it if needed.
+    ///
+    /// Returns `None` if the glyph isn't there and the atlas is full.
+    pub(crate) fn get(
+        &mut self,
+        queue: &wgpu::Queue,
+        key: GlyphKey,
+        rasterize: impl FnOnce() -> Option<GlyphBitmap>,
+    ) -> Option<Option<AtlasGlyph>> {
+        if let Some(glyph) = self.glyphs.get(&key) {
+            return Some(*glyph);
+        }
+        if self.glyphs.is_full() {
+            return None;
+        }
+        let bitmap = match rasterize() {
+            Some(bitmap) => bitmap,
+            None => {
+                self.glyphs.insert(key, None);
+                return Some(None);
+            }
+        };
+        // Leave a pixel of space around each glyph, so that filtering doesn't
+        // pick up its neighbours.
+        let (width, height) = (bitmap.width + 2, bitmap.height + 2);
+        let allocation = self.glyphs.allocate(width, height)?;
+        let mut padded = vec![0; (width * height) as usize];
+        for (y, row) in bitmap.data.chunks_exact(bitmap.width as usize).enumerate() {
+            let start = (y + 1) * width as usize + 1;
+            padded[start..start + row.len()].copy_from_slice(row);
+        }
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: allocation.x,
+                    y: allocation.y,
+                    z: 0,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            &padded,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(width),
+                rows_per_image: None,
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        let rect = Rect::new(
+            bitmap.left as f64 - 1.0,
+            bitmap.top as f64 - 1.0,
+            (bitmap.left + width as i32) as f64 - 1.0,
+            (bitmap.top + height as i32) as f64 - 1.0,
+        );
+        let glyph = AtlasGlyph {
+            rect,
+            uv: allocation.uv,
+        };
+        self.glyphs.insert(key, Some(glyph));
+        Some(Some(glyph))
+    }
+}
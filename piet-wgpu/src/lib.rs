@@ -0,0 +1,723 @@
+//! A GPU backend for the Piet 2D graphics abstraction, built on [wgpu].
+//!
+//! Paths are tessellated into triangles on the CPU with [`piet_tess`], and
+//! everything drawn in a frame is batched into as few draw calls as
+//! possible, which makes drawing many small shapes fast. Text is drawn from
+//! an atlas of rasterized glyphs, and clips are kept in a stencil buffer.
+//!
+//! Long-lived GPU state is kept in a [`Renderer`], which is passed to a new
+//! [`WgpuRenderContext`] for each frame. Nothing is drawn until
+//! [`finish`](piet::RenderContext::finish) is called.
+//!
+//! [wgpu]: https://wgpu.rs
+
+#![deny(clippy::trivially_copy_pass_by_ref)]
+
+mod atlas;
+mod renderer;
+mod text;
+
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use piet::kurbo::{Affine, BezPath, Point, Rect, Shape, Size, Vec2};
+use piet::{
+    Capabilities, Color, Error, FixedGradient, GradientOptions, HitTester, Image, ImageFormat,
+    InterpolationMode, IntoBrush, MipImage, RenderContext, StrokeStyle, TextShapingQuality,
+};
+use piet_tess::scene::{Paint, ATLAS_MASK};
+use piet_tess::state::{premul_color, RenderState};
+
+use atlas::GlyphKey;
+
+pub use piet_tess::state::Brush;
+pub use renderer::Renderer;
+pub use text::{WgpuText, WgpuTextLayout, WgpuTextLayoutBuilder};
+pub use wgpu;
+
+/// Glyphs bigger than this, in pixels, are drawn as paths instead of from
+/// the atlas.
+const MAX_ATLAS_FONT_SIZE: f64 = 128.0;
+
+pub struct WgpuRenderContext<'a> {
+    renderer: &'a mut Renderer,
+    device: &'a wgpu::Device,
+    queue: &'a wgpu::Queue,
+    target: &'a wgpu::Texture,
+    state: RenderState<Arc<wgpu::BindGroup>>,
+    text: WgpuText,
+}
+
+#[derive(Clone)]
+pub struct WgpuImage(Option<Arc<ImageInner>>);
+
+struct ImageInner {
+    linear: Arc<wgpu::BindGroup>,
+    nearest: Arc<wgpu::BindGroup>,
+    /// The size of the texture.
+    size: Size,
+    /// The area of the texture that is the image.
+    view: Rect,
+}
+
+impl ImageInner {
+    /// The texture coordinates of `rect`, in the pixels of the texture.
+    fn uv(&self, rect: Rect) -> Rect {
+        Rect::new(
+            rect.x0 / self.size.width,
+            rect.y0 / self.size.height,
+            rect.x1 / self.size.width,
+            rect.y1 / self.size.height,
+        )
+    }
+}
+
+impl<'a> WgpuRenderContext<'a> {
+    /// Create a new wgpu back-end, which draws into `target` when it is
+    /// finished.
+    ///
+    /// `target` must have been created with `RENDER_ATTACHMENT` usage, with
+    /// a sample count of 1. Everything drawn is scaled by `scale`, which is
+    /// the number of pixels per display point.
+    pub fn new(
+        renderer: &'a mut Renderer,
+        device: &'a wgpu::Device,
+        queue: &'a wgpu::Queue,
+        target: &'a wgpu::Texture,
+        scale: f64,
+    ) -> WgpuRenderContext<'a> {
+        renderer.atlas.begin_frame();
+        let scene = std::mem::take(&mut renderer.scene);
+        WgpuRenderContext {
+            text: renderer.text.clone(),
+            renderer,
+            device,
+            queue,
+            target,
+            state: RenderState::new(scene, scale),
+        }
+    }
+
+    fn draw_image_inner(
+        &mut self,
+        image: &WgpuImage,
+        src_rect: Option<Rect>,
+        dst_rect: Rect,
+        interp: InterpolationMode,
+    ) {
+        let inner = match &image.0 {
+            Some(inner) => inner.clone(),
+            None => return,
+        };
+        let src_rect = src_rect.unwrap_or_else(|| inner.view.size().to_rect());
+        if src_rect.is_empty() || dst_rect.is_empty() {
+            return;
+        }
+        let src_uv = inner.uv(src_rect + inner.view.origin().to_vec2());
+        let bind_group = match interp {
+            InterpolationMode::NearestNeighbor => &inner.nearest,
+            InterpolationMode::Bilinear
+            | InterpolationMode::Bicubic
+            | InterpolationMode::HighQuality => &inner.linear,
+        };
+        let to_device = self.state.device_transform();
+        self.state
+            .draw_texture(dst_rect, to_device, bind_group, src_uv);
+    }
+
+    /// Draw text from the glyph atlas, falling back to filling the outlines
+    /// of glyphs that aren't in it.
+    fn draw_glyphs(&mut self, layout: &WgpuTextLayout, to_device: Affine, paint: &Paint) {
+        let [a, b, c, d, _, _] = to_device.as_coeffs();
+        let size = layout.font_size * a;
+        let use_atlas = b == 0.0 && c == 0.0 && a == d && a > 0.0 && size <= MAX_ATLAS_FONT_SIZE;
+        let mut outlines = BezPath::new();
+        for glyph in layout.lines.iter().flat_map(|line| &line.glyphs) {
+            if use_atlas {
+                // glyphs are positioned to a quarter of a pixel horizontally,
+                // and to whole pixels vertically.
+                let origin = to_device * glyph.position;
+                let quarters = (origin.x * 4.0).round();
+                let x = (quarters / 4.0).floor();
+                let subpixel = (quarters - x * 4.0) as u8;
+                let key = GlyphKey {
+                    font: layout.font.id,
+                    glyph: glyph.id as u16,
+                    size: (size * 64.0).round() as u32,
+                    subpixel,
+                };
+                let font = &layout.font;
+                let rasterize =
+                    || text::rasterize_glyph(font, key.glyph, size, subpixel as f64 / 4.0);
+                match self.renderer.atlas.get(self.queue, key, rasterize) {
+                    Some(Some(atlas_glyph)) => {
+                        let rect = atlas_glyph.rect + Vec2::new(x, origin.y.round());
+                        let mask = Some((ATLAS_MASK, atlas_glyph.uv));
+                        let indices = self.state.scene.rect(rect, Affine::IDENTITY, paint, mask);
+                        self.state.draw(indices, None);
+                        continue;
+                    }
+                    Some(None) => continue,
+                    // the atlas is full.
+                    None => (),
+                }
+            }
+            layout.glyph_outline(glyph, &mut outlines);
+        }
+        if !outlines.is_empty() {
+            let indices = self
+                .state
+                .scene
+                .fill(outlines, false, to_device, paint, None);
+            self.state.draw(indices, None);
+        }
+    }
+}
+
+impl<'a> RenderContext for WgpuRenderContext<'a> {
+    type Brush = Brush;
+
+    type Text = WgpuText;
+    type TextLayout = WgpuTextLayout;
+
+    type Image = WgpuImage;
+
+    fn status(&mut self) -> Result<(), Error> {
+        self.state.status()
+    }
+
+    fn clear(&mut self, region: impl Into<Option<Rect>>, color: Color) {
+        self.state.clear(region.into(), color);
+    }
+
+    fn solid_brush(&mut self, color: Color) -> Brush {
+        Brush::Solid(premul_color(color))
+    }
+
+    fn gradient(&mut self, gradient: impl Into<FixedGradient>) -> Result<Brush, Error> {
+        self.gradient_with_options(gradient, GradientOptions::default())
+    }
+
+    fn gradient_with_options(
+        &mut self,
+        gradient: impl Into<FixedGradient>,
+        options: GradientOptions,
+    ) -> Result<Brush, Error> {
+        self.renderer.ramps.gradient(gradient, options)
+    }
+
+    fn fill(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>) {
+        let brush = brush.make_brush(self, || shape.bounding_box());
+        self.state.fill(shape, &brush, false);
+    }
+
+    fn fill_even_odd(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>) {
+        let brush = brush.make_brush(self, || shape.bounding_box());
+        self.state.fill(shape, &brush, true);
+    }
+
+    fn fill_with_mask(
+        &mut self,
+        shape: impl Shape,
+        brush: &impl IntoBrush<Self>,
+        mask: &Self::Image,
+    ) {
+        let brush = brush.make_brush(self, || shape.bounding_box());
+        if let Some(inner) = &mask.0 {
+            let uv = inner.uv(inner.view);
+            self.state.fill_with_mask(shape, &brush, &inner.linear, uv);
+        }
+    }
+
+    fn clip(&mut self, shape: impl Shape) {
+        self.state.clip(shape);
+    }
+
+    fn stroke(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>, width: f64) {
+        let brush = brush.make_brush(self, || shape.bounding_box());
+        self.state
+            .stroke(shape, &brush, width, &StrokeStyle::default());
+    }
+
+    fn stroke_styled(
+        &mut self,
+        shape: impl Shape,
+        brush: &impl IntoBrush<Self>,
+        width: f64,
+        style: &StrokeStyle,
+    ) {
+        if piet::util::stroke_dash_caps(self, &shape, brush, width, style) {
+            return;
+        }
+        let brush = brush.make_brush(self, || shape.bounding_box());
+        self.state.stroke(shape, &brush, width, style);
+    }
+
+    fn text(&mut self) -> &mut Self::Text {
+        &mut self.text
+    }
+
+    fn draw_text(&mut self, layout: &Self::TextLayout, pos: impl Into<Point>) {
+        let to_device = self.state.device_transform() * Affine::translate(pos.into().to_vec2());
+        let alpha = self.state.global_alpha();
+        let paint = Paint::solid(premul_color(layout.text_color)).with_alpha(alpha);
+        if layout.background_color.as_rgba_u32() & 0xff != 0 {
+            let background = Paint::solid(premul_color(layout.background_color)).with_alpha(alpha);
+            for line in &layout.lines {
+                let rect = Rect::new(
+                    line.x,
+                    line.metric.y_offset,
+                    line.x + line.width,
+                    line.metric.y_offset + line.metric.height,
+                );
+                let indices = self.state.scene.rect(rect, to_device, &background, None);
+                self.state.draw(indices, None);
+            }
+        }
+        self.draw_glyphs(layout, to_device, &paint);
+        let decorations = [
+            (layout.underline, layout.underline_metrics),
+            (layout.strikethrough, layout.strikethrough_metrics),
+        ];
+        for (_, (offset, thickness)) in decorations.iter().filter(|(on, _)| *on) {
+            for line in &layout.lines {
+                let y = line.metric.y_offset + line.metric.baseline + offset;
+                let rect = Rect::new(line.x, y, line.x + line.width, y + thickness);
+                let indices = self.state.scene.rect(rect, to_device, &paint, None);
+                self.state.draw(indices, None);
+            }
+        }
+    }
+
+    fn save(&mut self) -> Result<(), Error> {
+        self.state.save();
+        Ok(())
+    }
+
+    fn restore(&mut self) -> Result<(), Error> {
+        self.state.restore()
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        let result = self
+            .renderer
+            .render(self.device, self.queue, self.target, &self.state.scene);
+        self.state.restart();
+        result
+    }
+
+    fn transform(&mut self, transform: Affine) {
+        self.state.transform(transform);
+    }
+
+    fn current_transform(&self) -> Affine {
+        self.state.current_transform()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        // image filters aren't drawn yet, so only blurred rects are blurred.
+        let mut capabilities = Capabilities::default();
+        capabilities.supports_global_alpha = true;
+        capabilities.max_image_size = self.device.limits().max_texture_dimension_2d as usize;
+        capabilities.text_shaping_quality = TextShapingQuality::Full;
+        capabilities
+    }
+
+    fn set_global_alpha(&mut self, alpha: f64) {
+        self.state.set_global_alpha(alpha);
+    }
+
+    fn global_alpha(&self) -> f64 {
+        self.state.global_alpha() as f64
+    }
+
+    fn hit_tester(&mut self) -> Option<&mut HitTester> {
+        Some(self.state.hit_tester())
+    }
+    fn make_image(
+        &mut self,
+        width: usize,
+        height: usize,
+        buf: &[u8],
+        format: ImageFormat,
+    ) -> Result<Self::Image, Error> {
+        if width == 0 || height == 0 {
+            return Ok(WgpuImage(None));
+        }
+        let max = self.device.limits().max_texture_dimension_2d as usize;
+        if width > max || height > max {
+            return Err(Error::InvalidInput);
+        }
+        let buf = buf
+            .get(..width * height * format.bytes_per_pixel())
+            .ok_or(Error::InvalidInput)?;
+        let data: Cow<[u8]> = match format {
+            ImageFormat::RgbaPremul => buf.into(),
+            ImageFormat::RgbaSeparate => {
+                let mut data = buf.to_vec();
+                piet::util::premultiply_rgba(&mut data);
+                data.into()
+            }
+            ImageFormat::Rgb => {
+                let mut data = vec![0; width * height * 4];
+                piet::util::rgb_to_rgba(buf, &mut data);
+                data.into()
+            }
+            ImageFormat::Grayscale => {
+                let mut data = vec![0; width * height * 4];
+                piet::util::gray_to_rgba(buf, &mut data);
+                data.into()
+            }
+            ImageFormat::BgraSeparate | ImageFormat::BgraPremul | ImageFormat::Rgba16 => {
+                let (buf, format) = piet::util::to_rgba8(buf, format);
+                return self.make_image(width, height, &buf, format);
+            }
+            _ => return Err(Error::NotSupported),
+        };
+        let texture =
+            renderer::create_texture(self.device, self.queue, width as u32, height as u32, &data);
+        Ok(self.texture_image(&texture))
+    }
+
+    /// The levels of the mipmap are kept in the image's texture, and the GPU
+    /// picks the level for each pixel as it is drawn.
+    fn make_mip_image(
+        &mut self,
+        width: usize,
+        height: usize,
+        buf: &[u8],
+        format: ImageFormat,
+    ) -> Result<MipImage<Self::Image>, Error> {
+        if width == 0 || height == 0 {
+            return Ok(MipImage::from_image(WgpuImage(None)));
+        }
+        let max = self.device.limits().max_texture_dimension_2d as usize;
+        if width > max || height > max {
+            return Err(Error::InvalidInput);
+        }
+        let data = piet::util::premultiplied_rgba(width * height, buf, format)?;
+        let levels = piet::util::mip_levels(width, height, &data);
+        let texture = renderer::create_mip_texture(
+            self.device,
+            self.queue,
+            width as u32,
+            height as u32,
+            &data,
+            &levels,
+        );
+        Ok(MipImage::from_image(self.texture_image(&texture)))
+    }
+
+    #[inline]
+    fn draw_image(
+        &mut self,
+        image: &Self::Image,
+        dst_rect: impl Into<Rect>,
+        interp: InterpolationMode,
+    ) {
+        self.draw_image_inner(image, None, dst_rect.into(), interp);
+    }
+
+    #[inline]
+    fn draw_image_area(
+        &mut self,
+        image: &Self::Image,
+        src_rect: impl Into<Rect>,
+        dst_rect: impl Into<Rect>,
+        interp: InterpolationMode,
+    ) {
+        self.draw_image_inner(image, Some(src_rect.into()), dst_rect.into(), interp);
+    }
+
+    fn capture_image_area(&mut self, _src_rect: impl Into<Rect>) -> Result<Self::Image, Error> {
+        // Nothing is drawn until the frame is finished.
+        Err(Error::NotSupported)
+    }
+
+    fn blurred_rect(&mut self, rect: Rect, blur_radius: f64, brush: &impl IntoBrush<Self>) {
+        let brush = brush.make_brush(self, || rect);
+        let size = piet::util::size_for_blurred_rect(rect, blur_radius);
+        let (width, height) = (size.width as usize, size.height as usize);
+        if width == 0 || height == 0 {
+            return;
+        }
+        let mut data = vec![0u8; width * height];
+        let rect_exp = piet::util::compute_blurred_rect(rect, blur_radius, width, &mut data);
+        let data = data.iter().flat_map(|&a| [a; 4]).collect::<Vec<_>>();
+        let texture =
+            renderer::create_texture(self.device, self.queue, width as u32, height as u32, &data);
+        let mask = Arc::new(self.renderer.image_bind_group(
+            self.device,
+            &texture,
+            InterpolationMode::Bilinear,
+        ));
+        let uv = Rect::new(0.0, 0.0, 1.0, 1.0);
+        self.state.fill_with_mask(rect_exp, &brush, &mask, uv);
+    }
+}
+
+impl WgpuRenderContext<'_> {
+    /// The image of all of `texture`.
+    fn texture_image(&self, texture: &wgpu::Texture) -> WgpuImage {
+        let bind_group =
+            |interp| Arc::new(self.renderer.image_bind_group(self.device, texture, interp));
+        let size = Size::new(texture.width() as f64, texture.height() as f64);
+        WgpuImage(Some(Arc::new(ImageInner {
+            linear: bind_group(InterpolationMode::Bilinear),
+            nearest: bind_group(InterpolationMode::NearestNeighbor),
+            size,
+            view: size.to_rect(),
+        })))
+    }
+}
+
+impl Drop for WgpuRenderContext<'_> {
+    fn drop(&mut self) {
+        // give the scene back, so that its allocations are reused.
+        self.renderer.scene = std::mem::take(&mut self.state.scene);
+    }
+}
+
+impl<'a> IntoBrush<WgpuRenderContext<'a>> for Brush {
+    fn make_brush<'b>(
+        &'b self,
+        _piet: &mut WgpuRenderContext,
+        _bbox: impl FnOnce() -> Rect,
+    ) -> Cow<'b, Brush> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl Image for WgpuImage {
+    fn size(&self) -> Size {
+        self.0
+            .as_ref()
+            .map(|inner| inner.view.size())
+            .unwrap_or(Size::ZERO)
+    }
+
+    fn sub_image(&self, rect: impl Into<Rect>) -> Self {
+        let inner = match &self.0 {
+            Some(inner) => inner,
+            None => return WgpuImage(None),
+        };
+        let view = piet::util::sub_image_view(inner.view, rect.into());
+        if view.is_empty() {
+            return WgpuImage(None);
+        }
+        // the sub-image samples the same texture.
+        WgpuImage(Some(Arc::new(ImageInner {
+            linear: inner.linear.clone(),
+            nearest: inner.nearest.clone(),
+            size: inner.size,
+            view,
+        })))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Gpu {
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+    }
+
+    /// A device on any adapter, or `None` if there isn't one.
+    fn gpu() -> Option<Gpu> {
+        let instance = wgpu::Instance::default();
+        let adapter = pollster::block_on(instance.request_adapter(&Default::default()))?;
+        let (device, queue) =
+            pollster::block_on(adapter.request_device(&Default::default(), None)).ok()?;
+        Some(Gpu { device, queue })
+    }
+
+    fn target(gpu: &Gpu, size: u32) -> wgpu::Texture {
+        gpu.device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        })
+    }
+
+    fn pixel(gpu: &Gpu, texture: &wgpu::Texture, x: u32, y: u32) -> [u8; 4] {
+        let buffer = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: 4,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = gpu.device.create_command_encoder(&Default::default());
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                origin: wgpu::Origin3d { x, y, z: 0 },
+                ..texture.as_image_copy()
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout::default(),
+            },
+            wgpu::Extent3d::default(),
+        );
+        gpu.queue.submit(Some(encoder.finish()));
+        let slice = buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |_| ());
+        gpu.device.poll(wgpu::Maintain::Wait);
+        let data = slice.get_mapped_range();
+        [data[0], data[1], data[2], data[3]]
+    }
+
+    #[test]
+    fn fill_respects_clip_and_scale() {
+        let gpu = match gpu() {
+            Some(gpu) => gpu,
+            None => return,
+        };
+        let texture = target(&gpu, 20);
+        let mut renderer = Renderer::new(&gpu.device, &gpu.queue);
+        let mut rc = WgpuRenderContext::new(&mut renderer, &gpu.device, &gpu.queue, &texture, 2.0);
+        rc.clear(None, Color::TRANSPARENT);
+        rc.with_save(|rc| {
+            rc.clip(Rect::new(0., 0., 5., 10.));
+            rc.fill(Rect::new(0., 0., 10., 10.), &Color::RED);
+            Ok(())
+        })
+        .unwrap();
+        rc.fill(Rect::new(0., 8., 10., 10.), &Color::BLUE);
+        rc.finish().unwrap();
+        std::mem::drop(rc);
+
+        assert_eq!(pixel(&gpu, &texture, 5, 5), [255, 0, 0, 255]);
+        assert_eq!(pixel(&gpu, &texture, 15, 5), [0, 0, 0, 0]);
+        // the clip is gone after the restore.
+        assert_eq!(pixel(&gpu, &texture, 15, 18), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn clear_ignores_clip() {
+        let gpu = match gpu() {
+            Some(gpu) => gpu,
+            None => return,
+        };
+        let texture = target(&gpu, 10);
+        let mut renderer = Renderer::new(&gpu.device, &gpu.queue);
+        let mut rc = WgpuRenderContext::new(&mut renderer, &gpu.device, &gpu.queue, &texture, 1.0);
+        rc.clip(Rect::new(0., 0., 1., 1.));
+        rc.clear(None, Color::WHITE);
+        rc.clear(Rect::new(5., 5., 10., 10.), Color::BLUE);
+        rc.fill(Rect::new(0., 0., 10., 10.), &Color::RED);
+        rc.finish().unwrap();
+        std::mem::drop(rc);
+
+        assert_eq!(pixel(&gpu, &texture, 7, 7), [0, 0, 255, 255]);
+        assert_eq!(pixel(&gpu, &texture, 2, 2), [255, 255, 255, 255]);
+        assert_eq!(pixel(&gpu, &texture, 0, 0), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn global_alpha_fades_fills() {
+        let gpu = match gpu() {
+            Some(gpu) => gpu,
+            None => return,
+        };
+        let texture = target(&gpu, 10);
+        let mut renderer = Renderer::new(&gpu.device, &gpu.queue);
+        let mut rc = WgpuRenderContext::new(&mut renderer, &gpu.device, &gpu.queue, &texture, 1.0);
+        rc.clear(None, Color::TRANSPARENT);
+        rc.with_save(|rc| {
+            rc.set_global_alpha(0.5);
+            rc.fill(Rect::new(0., 0., 5., 10.), &Color::RED);
+            Ok(())
+        })
+        .unwrap();
+        rc.fill(Rect::new(5., 0., 10., 10.), &Color::RED);
+        rc.finish().unwrap();
+        std::mem::drop(rc);
+
+        let [r, _, _, a] = pixel(&gpu, &texture, 2, 2);
+        assert!((127..=128).contains(&r) && (127..=128).contains(&a));
+        assert_eq!(pixel(&gpu, &texture, 7, 7), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn sub_images_draw_their_area() {
+        let gpu = match gpu() {
+            Some(gpu) => gpu,
+            None => return,
+        };
+        let texture = target(&gpu, 10);
+        let mut renderer = Renderer::new(&gpu.device, &gpu.queue);
+        let mut rc = WgpuRenderContext::new(&mut renderer, &gpu.device, &gpu.queue, &texture, 1.0);
+        let sheet = rc
+            .make_image(2, 1, &[255, 0, 0, 0, 0, 255], ImageFormat::Rgb)
+            .unwrap();
+        let blue = sheet.sub_image(Rect::new(1., 0., 2., 1.));
+        assert_eq!(blue.size(), Size::new(1., 1.));
+        rc.clear(None, Color::TRANSPARENT);
+        rc.draw_image(
+            &blue,
+            Rect::new(0., 0., 10., 10.),
+            InterpolationMode::NearestNeighbor,
+        );
+        rc.finish().unwrap();
+        std::mem::drop(rc);
+
+        assert_eq!(pixel(&gpu, &texture, 0, 0), [0, 0, 255, 255]);
+        assert_eq!(pixel(&gpu, &texture, 9, 9), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn mip_images_average_when_scaled_down() {
+        let gpu = match gpu() {
+            Some(gpu) => gpu,
+            None => return,
+        };
+        let texture = target(&gpu, 10);
+        let mut renderer = Renderer::new(&gpu.device, &gpu.queue);
+        let mut rc = WgpuRenderContext::new(&mut renderer, &gpu.device, &gpu.queue, &texture, 1.0);
+        // black and white stripes, a pixel wide.
+        let stripes: Vec<u8> = (0..8 * 8)
+            .map(|i| if i % 2 == 0 { 0 } else { 255 })
+            .collect();
+        let image = rc
+            .make_mip_image(8, 8, &stripes, ImageFormat::Grayscale)
+            .unwrap();
+        assert_eq!(image.size(), Size::new(8., 8.));
+        rc.clear(None, Color::TRANSPARENT);
+        rc.draw_mip_image(
+            &image,
+            Rect::new(0., 0., 2., 2.),
+            InterpolationMode::Bilinear,
+        );
+        rc.finish().unwrap();
+        std::mem::drop(rc);
+
+        let [r, g, b, a] = pixel(&gpu, &texture, 0, 0);
+        assert!((120..=136).contains(&r), "{}", r);
+        assert_eq!((r, g, a), (b, b, 255));
+    }
+
+    #[test]
+    fn unbalanced_restore() {
+        let gpu = match gpu() {
+            Some(gpu) => gpu,
+            None => return,
+        };
+        let texture = target(&gpu, 1);
+        let mut renderer = Renderer::new(&gpu.device, &gpu.queue);
+        let mut rc = WgpuRenderContext::new(&mut renderer, &gpu.device, &gpu.queue, &texture, 1.0);
+        rc.save().unwrap();
+        assert!(rc.restore().is_ok());
+        assert!(matches!(rc.restore(), Err(Error::StackUnbalance)));
+    }
+}
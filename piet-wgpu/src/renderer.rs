@@ -0,0 +1,670 @@
+//! The GPU side: pipelines, shared textures, and submitting a frame.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use bytemuck::{Pod, Zeroable};
+use piet::{Error, InterpolationMode};
+use piet_tess::ramps::{Ramps, RAMP_WIDTH};
+use piet_tess::scene::{Command, Vertex};
+
+use crate::atlas::GlyphAtlas;
+use crate::text::WgpuText;
+
+/// The number of samples per pixel, for antialiasing.
+const SAMPLE_COUNT: u32 = 4;
+/// The format of the stencil buffer, which holds the clip.
+const STENCIL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Stencil8;
+/// The layout of [`Vertex`].
+const VERTEX_ATTRIBUTES: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![
+    0 => Float32x2,
+    1 => Float32x2,
+    2 => Float32x2,
+    3 => Float32x4,
+    4 => Uint32,
+];
+
+/// A frame's triangles, with the bind groups of its images.
+pub(crate) type Scene = piet_tess::scene::Scene<Arc<wgpu::BindGroup>>;
+
+/// The state that outlives a frame: pipelines, gradient ramps, the glyph
+/// atlas, fonts, and buffers.
+///
+/// Create one for each `wgpu::Device`, and pass it to
+/// [`WgpuRenderContext::new`](crate::WgpuRenderContext::new) for each frame.
+pub struct Renderer {
+    shader: wgpu::ShaderModule,
+    globals_layout: wgpu::BindGroupLayout,
+    pub(crate) image_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipelines: HashMap<wgpu::TextureFormat, Pipelines>,
+    uniforms: wgpu::Buffer,
+    pub(crate) linear_sampler: wgpu::Sampler,
+    pub(crate) nearest_sampler: wgpu::Sampler,
+    /// Bound for draws that don't use an image.
+    no_image: wgpu::BindGroup,
+    /// Rebuilt when the ramp texture grows.
+    globals: Option<wgpu::BindGroup>,
+    pub(crate) ramps: Ramps,
+    ramp_texture: RampTexture,
+    pub(crate) atlas: GlyphAtlas,
+    buffers: Option<Buffers>,
+    targets: Option<FrameTargets>,
+    pub(crate) text: WgpuText,
+    /// Kept between frames, to reuse its allocations.
+    pub(crate) scene: Scene,
+}
+
+struct Pipelines {
+    draw: wgpu::RenderPipeline,
+    clear: wgpu::RenderPipeline,
+    push_clip: wgpu::RenderPipeline,
+    pop_clip: wgpu::RenderPipeline,
+}
+
+struct Buffers {
+    vertices: wgpu::Buffer,
+    indices: wgpu::Buffer,
+}
+
+/// The multisampled color and stencil textures that a frame is drawn into,
+/// before it is resolved to the target.
+struct FrameTargets {
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+    color: wgpu::TextureView,
+    stencil: wgpu::TextureView,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct Uniforms {
+    size: [f32; 2],
+    srgb: u32,
+    ramp_rows: f32,
+}
+
+/// The texture that the color ramps of gradients are in, one per row.
+struct RampTexture {
+    texture: Option<wgpu::Texture>,
+    /// The number of rows in the texture.
+    capacity: u32,
+}
+
+impl Renderer {
+    /// Create a new renderer for `device`.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Renderer {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("piet shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
+        });
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let sampler_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        let globals_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("piet globals"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                texture_entry(1),
+                texture_entry(2),
+                sampler_entry(3),
+            ],
+        });
+        let image_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("piet image"),
+            entries: &[texture_entry(0), sampler_entry(1)],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("piet"),
+            bind_group_layouts: &[&globals_layout, &image_layout],
+            push_constant_ranges: &[],
+        });
+        let uniforms = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("piet uniforms"),
+            size: std::mem::size_of::<Uniforms>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let sampler = |filter| {
+            device.create_sampler(&wgpu::SamplerDescriptor {
+                label: Some("piet"),
+                mag_filter: filter,
+                min_filter: filter,
+                // only the images made by `make_mip_image` have levels.
+                mipmap_filter: filter,
+                ..Default::default()
+            })
+        };
+        let linear_sampler = sampler(wgpu::FilterMode::Linear);
+        let nearest_sampler = sampler(wgpu::FilterMode::Nearest);
+
+        let white = create_texture(device, queue, 1, 1, &[255; 4]);
+        let no_image = create_image_bind_group(device, &image_layout, &white, &linear_sampler);
+
+        Renderer {
+            shader,
+            globals_layout,
+            image_layout,
+            pipeline_layout,
+            pipelines: HashMap::new(),
+            uniforms,
+            linear_sampler,
+            nearest_sampler,
+            no_image,
+            globals: None,
+            ramps: Ramps::new(),
+            ramp_texture: RampTexture::new(),
+            atlas: GlyphAtlas::new(device),
+            buffers: None,
+            targets: None,
+            text: WgpuText::new(),
+            scene: Scene::default(),
+        }
+    }
+
+    /// The text factory, for making text layouts outside of a frame.
+    pub fn text(&mut self) -> &mut WgpuText {
+        &mut self.text
+    }
+
+    /// Make a bind group for an image, with the sampler for `interp`.
+    pub(crate) fn image_bind_group(
+        &self,
+        device: &wgpu::Device,
+        texture: &wgpu::Texture,
+        interp: InterpolationMode,
+    ) -> wgpu::BindGroup {
+        let sampler = match interp {
+            InterpolationMode::NearestNeighbor => &self.nearest_sampler,
+            InterpolationMode::Bilinear
+            | InterpolationMode::Bicubic
+            | InterpolationMode::HighQuality => &self.linear_sampler,
+        };
+        create_image_bind_group(device, &self.image_layout, texture, sampler)
+    }
+
+    /// Draw `scene` into `target`, and submit it.
+    pub(crate) fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        target: &wgpu::Texture,
+        scene: &Scene,
+    ) -> Result<(), Error> {
+        let format = target.format();
+        let (width, height) = (target.width(), target.height());
+        if target.sample_count() != 1
+            || !target
+                .usage()
+                .contains(wgpu::TextureUsages::RENDER_ATTACHMENT)
+        {
+            return Err(Error::InvalidInput);
+        }
+
+        if !self.pipelines.contains_key(&format) {
+            let pipelines = self.create_pipelines(device, format);
+            self.pipelines.insert(format, pipelines);
+        }
+        if self.ramp_texture.upload(&mut self.ramps, device, queue) || self.globals.is_none() {
+            self.globals = Some(self.create_globals(device));
+        }
+        let uniforms = Uniforms {
+            size: [width as f32, height as f32],
+            srgb: format.is_srgb() as u32,
+            ramp_rows: self.ramp_texture.capacity as f32,
+        };
+        queue.write_buffer(&self.uniforms, 0, bytemuck::bytes_of(&uniforms));
+        self.prepare_targets(device, format, width, height);
+        self.upload_mesh(device, queue, scene);
+
+        let (pipelines, globals, frame, buffers) = match (
+            self.pipelines.get(&format),
+            &self.globals,
+            &self.targets,
+            &self.buffers,
+        ) {
+            (Some(pipelines), Some(globals), Some(frame), buffers) => {
+                (pipelines, globals, frame, buffers)
+            }
+            _ => return Err(Error::InvalidInput),
+        };
+
+        let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+        let clear_color = scene.clear_color.map(|color| {
+            let [r, g, b, a] = color.map(f64::from);
+            wgpu::Color { r, g, b, a }
+        });
+        let load = match clear_color {
+            Some(color) if format.is_srgb() => wgpu::LoadOp::Clear(linear_color(color)),
+            Some(color) => wgpu::LoadOp::Clear(color),
+            None => wgpu::LoadOp::Load,
+        };
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("piet"),
+        });
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("piet"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &frame.color,
+                    resolve_target: Some(&target_view),
+                    ops: wgpu::Operations {
+                        load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &frame.stencil,
+                    depth_ops: None,
+                    stencil_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(0),
+                        store: wgpu::StoreOp::Discard,
+                    }),
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            if let Some(buffers) = buffers.as_ref().filter(|_| !scene.commands.is_empty()) {
+                pass.set_vertex_buffer(0, buffers.vertices.slice(..));
+                pass.set_index_buffer(buffers.indices.slice(..), wgpu::IndexFormat::Uint32);
+                pass.set_bind_group(0, globals, &[]);
+                for command in &scene.commands {
+                    let (pipeline, indices, image, stencil) = match command {
+                        Command::Draw {
+                            indices,
+                            image,
+                            stencil,
+                        } => (&pipelines.draw, indices, image.as_deref(), *stencil),
+                        Command::Clear { indices } => (&pipelines.clear, indices, None, 0),
+                        Command::PushClip { indices, depth } => {
+                            (&pipelines.push_clip, indices, None, depth - 1)
+                        }
+                        Command::PopClip { indices, depth } => {
+                            (&pipelines.pop_clip, indices, None, *depth)
+                        }
+                    };
+                    pass.set_pipeline(pipeline);
+                    pass.set_stencil_reference(stencil);
+                    pass.set_bind_group(1, image.unwrap_or(&self.no_image), &[]);
+                    pass.draw_indexed(indices.clone(), 0, 0..1);
+                }
+            }
+        }
+        queue.submit(Some(encoder.finish()));
+        Ok(())
+    }
+
+    fn create_pipelines(&self, device: &wgpu::Device, format: wgpu::TextureFormat) -> Pipelines {
+        let stencil = |compare, pass_op| wgpu::StencilFaceState {
+            compare,
+            fail_op: wgpu::StencilOperation::Keep,
+            depth_fail_op: wgpu::StencilOperation::Keep,
+            pass_op,
+        };
+        let pipeline = |label, blend, write_mask, stencil| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&self.pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &self.shader,
+                    entry_point: "vs_main",
+                    buffers: &[wgpu::VertexBufferLayout {
+                        array_stride: std::mem::size_of::<Vertex>() as u64,
+                        step_mode: wgpu::VertexStepMode::Vertex,
+                        attributes: &VERTEX_ATTRIBUTES,
+                    }],
+                },
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: STENCIL_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    stencil: wgpu::StencilState {
+                        front: stencil,
+                        back: stencil,
+                        read_mask: 0xff,
+                        write_mask: 0xff,
+                    },
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: SAMPLE_COUNT,
+                    ..Default::default()
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &self.shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend,
+                        write_mask,
+                    })],
+                }),
+                multiview: None,
+            })
+        };
+        use wgpu::{BlendState, ColorWrites, CompareFunction, StencilOperation};
+        Pipelines {
+            draw: pipeline(
+                "piet draw",
+                Some(BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                ColorWrites::ALL,
+                stencil(CompareFunction::Equal, StencilOperation::Keep),
+            ),
+            clear: pipeline(
+                "piet clear",
+                Some(BlendState::REPLACE),
+                ColorWrites::ALL,
+                stencil(CompareFunction::Always, StencilOperation::Keep),
+            ),
+            // A clip is added inside the current clip, where the stencil
+            // equals the reference, and removed everywhere inside it, where
+            // the stencil is more than the reference.
+            push_clip: pipeline(
+                "piet push clip",
+                None,
+                ColorWrites::empty(),
+                stencil(CompareFunction::Equal, StencilOperation::IncrementClamp),
+            ),
+            pop_clip: pipeline(
+                "piet pop clip",
+                None,
+                ColorWrites::empty(),
+                stencil(CompareFunction::Less, StencilOperation::DecrementClamp),
+            ),
+        }
+    }
+
+    fn create_globals(&self, device: &wgpu::Device) -> wgpu::BindGroup {
+        let ramps = self.ramp_texture.view();
+        let atlas = self.atlas.view();
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("piet globals"),
+            layout: &self.globals_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.uniforms.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&ramps),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&atlas),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&self.linear_sampler),
+                },
+            ],
+        })
+    }
+
+    fn prepare_targets(
+        &mut self,
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) {
+        if let Some(targets) = &self.targets {
+            if targets.format == format && targets.width == width && targets.height == height {
+                return;
+            }
+        }
+        let texture = |label, format| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: wgpu::Extent3d {
+                        width,
+                        height,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: SAMPLE_COUNT,
+                    dimension: wgpu::TextureDimension::D2,
+                    format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        };
+        self.targets = Some(FrameTargets {
+            format,
+            width,
+            height,
+            color: texture("piet color", format),
+            stencil: texture("piet stencil", STENCIL_FORMAT),
+        });
+    }
+
+    fn upload_mesh(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, scene: &Scene) {
+        let vertices: &[u8] = bytemuck::cast_slice(&scene.vertices);
+        let indices: &[u8] = bytemuck::cast_slice(&scene.indices);
+        let big_enough = |buffer: &wgpu::Buffer, data: &[u8]| buffer.size() >= data.len() as u64;
+        let fits = match &self.buffers {
+            Some(buffers) => {
+                big_enough(&buffers.vertices, vertices) && big_enough(&buffers.indices, indices)
+            }
+            None => false,
+        };
+        if !fits {
+            // grow geometrically, so that a growing scene doesn't
+            // reallocate every frame.
+            let buffer = |label, usage, len: usize| {
+                device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some(label),
+                    size: (len.max(1024) as u64).next_power_of_two(),
+                    usage: usage | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                })
+            };
+            self.buffers = Some(Buffers {
+                vertices: buffer("piet vertices", wgpu::BufferUsages::VERTEX, vertices.len()),
+                indices: buffer("piet indices", wgpu::BufferUsages::INDEX, indices.len()),
+            });
+        }
+        if let Some(buffers) = &self.buffers {
+            if !vertices.is_empty() {
+                queue.write_buffer(&buffers.vertices, 0, vertices);
+            }
+            if !indices.is_empty() {
+                queue.write_buffer(&buffers.indices, 0, indices);
+            }
+        }
+    }
+}
+
+impl RampTexture {
+    fn new() -> RampTexture {
+        RampTexture {
+            texture: None,
+            capacity: 0,
+        }
+    }
+
+    /// Copy new ramps to the texture, returning `true` if the texture was
+    /// replaced.
+    fn upload(&mut self, ramps: &mut Ramps, device: &wgpu::Device, queue: &wgpu::Queue) -> bool {
+        if !ramps.take_changed() {
+            return false;
+        }
+        let rows = ramps.len().max(1);
+        let mut replaced = false;
+        if self.texture.is_none() || rows > self.capacity {
+            let max = device.limits().max_texture_dimension_2d;
+            self.capacity = rows.next_power_of_two().clamp(16, max);
+            self.texture = Some(device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("piet gradients"),
+                size: wgpu::Extent3d {
+                    width: RAMP_WIDTH,
+                    height: self.capacity,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba8Unorm,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            }));
+            replaced = true;
+        }
+        let rows = ramps.len().min(self.capacity);
+        if let (Some(texture), true) = (&self.texture, rows > 0) {
+            queue.write_texture(
+                texture.as_image_copy(),
+                &ramps.data()[..(rows * RAMP_WIDTH * 4) as usize],
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(RAMP_WIDTH * 4),
+                    rows_per_image: None,
+                },
+                wgpu::Extent3d {
+                    width: RAMP_WIDTH,
+                    height: rows,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+        replaced
+    }
+
+    fn view(&self) -> wgpu::TextureView {
+        self.texture
+            .as_ref()
+            .expect("ramps are uploaded before use")
+            .create_view(&wgpu::TextureViewDescriptor::default())
+    }
+}
+
+fn linear_color(color: wgpu::Color) -> wgpu::Color {
+    let linear = |c: f64| {
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    // the clear color is premultiplied, but the transfer function isn't.
+    let unpremul = |c: f64| if color.a > 0.0 { c / color.a } else { 0.0 };
+    wgpu::Color {
+        r: linear(unpremul(color.r)) * color.a,
+        g: linear(unpremul(color.g)) * color.a,
+        b: linear(unpremul(color.b)) * color.a,
+        a: color.a,
+    }
+}
+
+/// Make an RGBA texture with premultiplied `data`.
+pub(crate) fn create_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    width: u32,
+    height: u32,
+    data: &[u8],
+) -> wgpu::Texture {
+    create_mip_texture(device, queue, width, height, data, &[])
+}
+
+/// Make a texture with premultiplied `data`, and the smaller `levels` of its
+/// mipmap, as made by [`piet::util::mip_levels`].
+pub(crate) fn create_mip_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    width: u32,
+    height: u32,
+    data: &[u8],
+    levels: &[(usize, usize, Vec<u8>)],
+) -> wgpu::Texture {
+    let size = wgpu::Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+    };
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("piet image"),
+        size,
+        mip_level_count: levels.len() as u32 + 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba8Unorm,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+    let level_data = levels
+        .iter()
+        .map(|(width, height, data)| (*width as u32, *height as u32, &data[..]));
+    for (mip_level, (width, height, data)) in std::iter::once((width, height, data))
+        .chain(level_data)
+        .enumerate()
+    {
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                mip_level: mip_level as u32,
+                ..texture.as_image_copy()
+            },
+            data,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(width * 4),
+                rows_per_image: None,
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+    texture
+}
+
+fn create_image_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    texture: &wgpu::Texture,
+    sampler: &wgpu::Sampler,
+) -> wgpu::BindGroup {
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("piet image"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+        ],
+    })
+}
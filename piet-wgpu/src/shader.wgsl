@@ -0,0 +1,125 @@
+// Everything piet draws is a triangle mesh, in pixel coordinates. Each vertex
+// says how to paint itself, so that meshes with different brushes can share a
+// draw call.
+
+struct Uniforms {
+    size: vec2<f32>,
+    // nonzero if the target is sRGB, and so expects linear colors.
+    srgb: u32,
+    ramp_rows: f32,
+}
+
+// Keep these, and the paint kinds below, in sync with `piet_tess::scene`.
+const PAINT_MASK: u32 = 0xfu;
+const IMAGE_MASK: u32 = 0x10u;
+const ATLAS_MASK: u32 = 0x20u;
+const DITHER_MASK: u32 = 0x40u;
+
+@group(0) @binding(0) var<uniform> uniforms: Uniforms;
+@group(0) @binding(1) var ramps: texture_2d<f32>;
+@group(0) @binding(2) var atlas: texture_2d<f32>;
+@group(0) @binding(3) var ramp_sampler: sampler;
+@group(1) @binding(0) var image: texture_2d<f32>;
+@group(1) @binding(1) var image_sampler: sampler;
+
+struct VertexInput {
+    @location(0) pos: vec2<f32>,
+    @location(1) paint_pos: vec2<f32>,
+    @location(2) mask_pos: vec2<f32>,
+    @location(3) color: vec4<f32>,
+    @location(4) kind: u32,
+}
+
+struct VertexOutput {
+    @builtin(position) pos: vec4<f32>,
+    @location(0) paint_pos: vec2<f32>,
+    @location(1) mask_pos: vec2<f32>,
+    @location(2) color: vec4<f32>,
+    @location(3) @interpolate(flat) kind: u32,
+}
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    let ndc = in.pos / uniforms.size * 2.0 - 1.0;
+    out.pos = vec4<f32>(ndc.x, -ndc.y, 0.0, 1.0);
+    out.paint_pos = in.paint_pos;
+    out.mask_pos = in.mask_pos;
+    out.color = in.color;
+    out.kind = in.kind;
+    return out;
+}
+
+// The position along a radial gradient of `p`, in a space where the gradient
+// is the unit circle, and its focus is at `focus`.
+fn radial_t(p: vec2<f32>, focus: vec2<f32>) -> f32 {
+    let d = p - focus;
+    let dd = dot(d, d);
+    if dd == 0.0 {
+        return 0.0;
+    }
+    let fd = dot(focus, d);
+    let ff = dot(focus, focus);
+    let s = (-fd + sqrt(max(fd * fd - dd * (ff - 1.0), 0.0))) / dd;
+    return 1.0 / s;
+}
+
+fn ramp(t: f32, row: f32) -> vec4<f32> {
+    let uv = vec2<f32>(clamp(t, 0.0, 1.0), (row + 0.5) / uniforms.ramp_rows);
+    return textureSampleLevel(ramps, ramp_sampler, uv, 0.0);
+}
+
+fn srgb_to_linear(c: vec3<f32>) -> vec3<f32> {
+    let low = c / 12.92;
+    let high = pow((c + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(high, low, c <= vec3<f32>(0.04045));
+}
+
+// Offset `color` by a 4×4 ordered dither at the pixel `pos`, of up to half a
+// step of 8 bits, so that gradients don't band.
+fn dither(color: vec4<f32>, pos: vec2<f32>) -> vec4<f32> {
+    let p = vec2<u32>(pos) & vec2<u32>(3u);
+    let v = p.x ^ p.y;
+    let bayer = ((v & 1u) << 3u) | ((p.y & 1u) << 2u) | (v & 2u) | ((p.y & 2u) >> 1u);
+    let offset = ((f32(bayer) + 0.5) / 16.0 - 0.5) / 255.0;
+    return vec4<f32>(clamp(color.rgb + offset, vec3<f32>(0.0), vec3<f32>(color.a)), color.a);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // derivatives are taken outside of branches, for images with mipmaps.
+    let paint_dx = dpdx(in.paint_pos);
+    let paint_dy = dpdy(in.paint_pos);
+    var color: vec4<f32>;
+    switch in.kind & PAINT_MASK {
+        // linear gradient
+        case 1u: {
+            color = ramp(in.paint_pos.x, in.color.z) * in.color.w;
+        }
+        // radial gradient
+        case 2u: {
+            color = ramp(radial_t(in.paint_pos, in.color.xy), in.color.z) * in.color.w;
+        }
+        // image
+        case 3u: {
+            color = textureSampleGrad(image, image_sampler, in.paint_pos, paint_dx, paint_dy) * in.color.w;
+        }
+        // solid color
+        default: {
+            color = in.color;
+        }
+    }
+    if (in.kind & IMAGE_MASK) != 0u {
+        color *= textureSampleLevel(image, image_sampler, in.mask_pos, 0.0).a;
+    }
+    if (in.kind & ATLAS_MASK) != 0u {
+        color *= textureSampleLevel(atlas, ramp_sampler, in.mask_pos, 0.0).r;
+    }
+    if (in.kind & DITHER_MASK) != 0u {
+        color = dither(color, in.pos.xy);
+    }
+    if uniforms.srgb != 0u && color.a > 0.0 {
+        color = vec4<f32>(srgb_to_linear(color.rgb / color.a) * color.a, color.a);
+    }
+    return color;
+}
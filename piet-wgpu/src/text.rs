@@ -0,0 +1,925 @@
+//! Text functionality for the piet wgpu backend.
+//!
+//! Text is shaped with rustybuzz and broken into lines here; drawing uses
+//! glyphs rasterized into the renderer's atlas.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::ops::{Range, RangeBounds};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use font_kit::handle::Handle;
+use font_kit::source::SystemSource;
+use font_kit::sources::{mem::MemSource, multi::MultiSource};
+use piet::kurbo::{Affine, BezPath, Point, Rect, Size, Vec2};
+use piet::{
+    util, BaselineAlignment, Error, FontFamily, FontStyle, FontWeight, Glyph, GlyphRun,
+    HitTestPoint, HitTestPosition, LineHeight, LineMetric, TextAlignment, TextAttribute,
+    TextStorage,
+};
+use rustybuzz::{Direction, Face, Feature, Tag, UnicodeBuffer, Variation};
+
+use crate::atlas::GlyphBitmap;
+
+/// The text factory for the wgpu backend.
+///
+/// Fonts are shared between clones, and with the [`Renderer`](crate::Renderer)
+/// it came from.
+#[derive(Clone)]
+pub struct WgpuText {
+    fonts: Rc<RefCell<Fonts>>,
+}
+
+struct Fonts {
+    source: MultiSource,
+    loaded: HashMap<FontFace, Arc<FontData>>,
+}
+
+/// The data of a loaded font, with an id that is unique to it.
+pub(crate) struct FontData {
+    pub(crate) id: u64,
+    data: Arc<Vec<u8>>,
+    index: u32,
+}
+
+/// All the info required to identify a font face, except the size.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Default)]
+struct FontFace {
+    family: FontFamily,
+    weight: FontWeight,
+    style: FontStyle,
+}
+
+pub struct WgpuTextLayoutBuilder {
+    text: Arc<dyn TextStorage>,
+    defaults: util::LayoutDefaults,
+    alignment: TextAlignment,
+    line_height: LineHeight,
+    max_width: f64,
+    tab_stops: Arc<[f64]>,
+    tab_width: Option<f64>,
+    /// The text position, size and alignment of each inline box.
+    inline_boxes: Vec<(usize, Size, BaselineAlignment)>,
+    fonts: WgpuText,
+}
+
+/// A text layout for the wgpu backend.
+///
+/// Attributes apply to the whole layout: range attributes are only used if
+/// they cover all of the text. Text is laid out left to right.
+#[derive(Clone)]
+pub struct WgpuTextLayout {
+    text: Arc<dyn TextStorage>,
+    pub(crate) font: Arc<FontData>,
+    face: FontFace,
+    pub(crate) font_size: f64,
+    pub(crate) text_color: piet::Color,
+    pub(crate) background_color: piet::Color,
+    pub(crate) underline: bool,
+    pub(crate) strikethrough: bool,
+    pub(crate) lines: Vec<Line>,
+    size: Size,
+    trailing_whitespace_width: f64,
+    /// The position and thickness of underlines and strikethroughs, relative
+    /// to the baseline.
+    pub(crate) underline_metrics: (f64, f64),
+    pub(crate) strikethrough_metrics: (f64, f64),
+}
+
+#[derive(Clone)]
+pub(crate) struct Line {
+    pub(crate) metric: LineMetric,
+    /// Positioned in layout coordinates, on this line's baseline.
+    pub(crate) glyphs: Vec<Glyph>,
+    /// The left edge of the line, after alignment.
+    pub(crate) x: f64,
+    /// The width of the line, without trailing whitespace.
+    pub(crate) width: f64,
+    full_width: f64,
+    /// The inline boxes on this line, with the order they were added in,
+    /// positioned in layout coordinates.
+    boxes: Vec<(usize, Rect)>,
+}
+
+/// A glyph, or the gap for an inline box, before it is placed on a line.
+struct Shaped {
+    id: u32,
+    cluster: usize,
+    pen: f64,
+    advance: f64,
+    offset: Point,
+    is_whitespace: bool,
+    /// The inline box this is the gap for, rather than a glyph.
+    inline_box: Option<usize>,
+}
+
+impl Shaped {
+    /// The gap for the inline box `n`, which is `inline_box`, at `pen`.
+    fn gap(n: usize, inline_box: &(usize, Size, BaselineAlignment), pen: f64) -> Shaped {
+        Shaped {
+            id: 0,
+            cluster: inline_box.0,
+            pen,
+            advance: inline_box.1.width,
+            offset: Point::ZERO,
+            is_whitespace: false,
+            inline_box: Some(n),
+        }
+    }
+}
+
+impl WgpuText {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> WgpuText {
+        WgpuText {
+            fonts: Rc::new(RefCell::new(Fonts {
+                source: MultiSource::from_sources(vec![
+                    Box::new(SystemSource::new()),
+                    Box::new(MemSource::empty()),
+                ]),
+                loaded: HashMap::new(),
+            })),
+        }
+    }
+
+    fn font_data(&self, face: &FontFace) -> Result<Arc<FontData>, Error> {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+        let mut fonts = self.fonts.borrow_mut();
+        if let Some(data) = fonts.loaded.get(face) {
+            return Ok(data.clone());
+        }
+        let handle = fonts
+            .source
+            .select_best_match(&[face.to_fk_family()], &face.to_props())
+            .map_err(|_| Error::FontLoadingFailed)?;
+        let (data, index) = match handle {
+            Handle::Path { path, font_index } => (
+                Arc::new(fs::read(path).map_err(|_| Error::FontLoadingFailed)?),
+                font_index,
+            ),
+            Handle::Memory { bytes, font_index } => (bytes, font_index),
+        };
+        let data = Arc::new(FontData {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            data,
+            index,
+        });
+        fonts.loaded.insert(face.clone(), data.clone());
+        Ok(data)
+    }
+}
+
+impl piet::Text for WgpuText {
+    type TextLayout = WgpuTextLayout;
+    type TextLayoutBuilder = WgpuTextLayoutBuilder;
+
+    fn font_family(&mut self, family_name: &str) -> Option<FontFamily> {
+        use font_kit::{family_name::FamilyName, properties::Properties};
+
+        self.fonts
+            .borrow_mut()
+            .source
+            .select_best_match(&[FamilyName::Title(family_name.into())], &Properties::new())
+            .ok()
+            .map(|_| FontFamily::new_unchecked(family_name))
+    }
+
+    fn load_font(&mut self, data: &[u8]) -> Result<FontFamily, Error> {
+        let mut fonts = self.fonts.borrow_mut();
+        let source = fonts
+            .source
+            .find_source_mut::<MemSource>()
+            .expect("mem source");
+        let font = source
+            .add_font(Handle::Memory {
+                bytes: Arc::new(data.into()),
+                font_index: 0,
+            })
+            .map_err(|_| Error::FontLoadingFailed)?;
+        Ok(FontFamily::new_unchecked(font.family_name()))
+    }
+
+    fn new_text_layout(&mut self, text: impl TextStorage) -> WgpuTextLayoutBuilder {
+        WgpuTextLayoutBuilder {
+            text: Arc::new(text),
+            defaults: util::LayoutDefaults::default(),
+            alignment: TextAlignment::default(),
+            line_height: LineHeight::default(),
+            max_width: f64::INFINITY,
+            tab_stops: Vec::new().into(),
+            tab_width: None,
+            inline_boxes: Vec::new(),
+            fonts: self.clone(),
+        }
+    }
+}
+
+impl piet::TextLayoutBuilder for WgpuTextLayoutBuilder {
+    type Out = WgpuTextLayout;
+
+    fn max_width(mut self, width: f64) -> Self {
+        self.max_width = width;
+        self
+    }
+
+    fn alignment(mut self, alignment: TextAlignment) -> Self {
+        self.alignment = alignment;
+        self
+    }
+
+    fn line_height(mut self, line_height: LineHeight) -> Self {
+        self.line_height = line_height;
+        self
+    }
+
+    fn tab_stops(mut self, stops: &[f64]) -> Self {
+        self.tab_stops = stops.into();
+        self
+    }
+
+    fn tab_width(mut self, width: f64) -> Self {
+        self.tab_width = Some(width);
+        self
+    }
+
+    fn inline_box(mut self, index: usize, size: Size, alignment: BaselineAlignment) -> Self {
+        let index = index.min(self.text.len());
+        self.inline_boxes.push((index, size, alignment));
+        self
+    }
+
+    fn default_attribute(mut self, attribute: impl Into<TextAttribute>) -> Self {
+        self.defaults.set(attribute);
+        self
+    }
+
+    fn range_attribute(
+        mut self,
+        range: impl RangeBounds<usize>,
+        attribute: impl Into<TextAttribute>,
+    ) -> Self {
+        let range = util::resolve_range(range, self.text.len());
+        if range == (0..self.text.len()) {
+            self.defaults.set(attribute);
+        }
+        self
+    }
+
+    fn build(self) -> Result<WgpuTextLayout, Error> {
+        WgpuTextLayout::from_builder(self)
+    }
+}
+
+impl WgpuTextLayout {
+    fn from_builder(builder: WgpuTextLayoutBuilder) -> Result<WgpuTextLayout, Error> {
+        let defaults = &builder.defaults;
+        let face = FontFace {
+            family: defaults.font.clone(),
+            weight: defaults.weight,
+            style: defaults.style,
+        };
+        let font = builder.fonts.font_data(&face)?;
+        let mut shaper =
+            Face::from_slice(&font.data, font.index).ok_or(Error::FontLoadingFailed)?;
+        let variations = defaults
+            .variations
+            .iter()
+            .map(|var| Variation {
+                tag: Tag::from_bytes(&var.tag),
+                value: var.value as f32,
+            })
+            .collect::<Vec<_>>();
+        shaper.set_variations(&variations);
+        let features = defaults
+            .features
+            .iter()
+            .map(|feature| Feature::new(Tag::from_bytes(&feature.tag), feature.value, ..))
+            .collect::<Vec<_>>();
+
+        let font_size = defaults.scaled_font_size();
+        let scale = font_size / shaper.units_per_em() as f64;
+        let ascent = shaper.ascender() as f64 * scale;
+        let descent = -shaper.descender() as f64 * scale;
+        let natural_height = shaper.height() as f64 * scale;
+        let line_height = builder.line_height.resolve(font_size, natural_height);
+        let baseline = ascent + (line_height - natural_height) / 2.0;
+        let baseline_shift = defaults.total_baseline_shift();
+        let tab_width = builder.tab_width.unwrap_or_else(|| {
+            let space = shaper
+                .glyph_index(' ')
+                .and_then(|id| shaper.glyph_hor_advance(id))
+                .unwrap_or(0);
+            8.0 * (space as f64 * scale + defaults.letter_spacing + defaults.word_spacing)
+        });
+
+        // how far each inline box rises above the baseline.
+        let box_ascents = builder
+            .inline_boxes
+            .iter()
+            .map(|(_, size, alignment)| alignment.box_ascent(size.height, ascent, descent))
+            .collect::<Vec<_>>();
+        // the boxes, in the order they are in the text.
+        let mut box_order = (0..builder.inline_boxes.len()).collect::<Vec<_>>();
+        box_order.sort_by_key(|&n| builder.inline_boxes[n].0);
+        let mut boxes = box_order.into_iter().peekable();
+
+        let text = builder.text.as_str();
+        let mut lines = Vec::new();
+        let mut y_offset = 0.0;
+        for paragraph in paragraphs(text) {
+            let mut buffer = UnicodeBuffer::new();
+            buffer.push_str(&text[paragraph.start..paragraph.content_end]);
+            // clusters are in visual order, so this keeps them increasing.
+            buffer.set_direction(Direction::LeftToRight);
+            let output = rustybuzz::shape(&shaper, &features, buffer);
+            let mut shaped = Vec::with_capacity(output.len());
+            let mut pen = 0.0;
+            let infos = output.glyph_infos().iter();
+            for (info, pos) in infos.zip(output.glyph_positions()) {
+                let cluster = paragraph.start + info.cluster as usize;
+                while let Some(n) = boxes.next_if(|&n| builder.inline_boxes[n].0 <= cluster) {
+                    shaped.push(Shaped::gap(n, &builder.inline_boxes[n], pen));
+                    pen += builder.inline_boxes[n].1.width;
+                }
+                let c = text[cluster..].chars().next().unwrap_or(' ');
+                let mut advance = pos.x_advance as f64 * scale + defaults.letter_spacing;
+                if c == ' ' {
+                    advance += defaults.word_spacing;
+                }
+                if c == '\t' {
+                    advance = util::next_tab_stop(pen, &builder.tab_stops, tab_width) - pen;
+                }
+                shaped.push(Shaped {
+                    id: info.glyph_id,
+                    cluster,
+                    pen,
+                    advance,
+                    offset: Point::new(
+                        pos.x_offset as f64 * scale,
+                        -pos.y_offset as f64 * scale - baseline_shift,
+                    ),
+                    is_whitespace: c.is_whitespace(),
+                    inline_box: None,
+                });
+                pen += advance;
+            }
+            // boxes at the end of the paragraph, before its line break.
+            while let Some(n) = boxes.next_if(|&n| {
+                let index = builder.inline_boxes[n].0;
+                index <= paragraph.content_end || index < paragraph.end
+            }) {
+                shaped.push(Shaped::gap(n, &builder.inline_boxes[n], pen));
+                pen += builder.inline_boxes[n].1.width;
+            }
+
+            for glyphs in wrap(&shaped, builder.max_width) {
+                let start_offset = glyphs
+                    .start
+                    .checked_sub(1)
+                    .map(|_| shaped[glyphs.start].cluster)
+                    .unwrap_or(paragraph.start);
+                let end_offset = shaped
+                    .get(glyphs.end)
+                    .map(|glyph| glyph.cluster)
+                    .unwrap_or(paragraph.end);
+                let line_text = &text[start_offset..end_offset];
+                let origin = shaped.get(glyphs.start).map(|g| g.pen).unwrap_or(0.0);
+                let line_end = |glyph: &Shaped| glyph.pen + glyph.advance - origin;
+                let full_width = shaped[glyphs.clone()].last().map(line_end).unwrap_or(0.0);
+                let width = shaped[glyphs.clone()]
+                    .iter()
+                    .rev()
+                    .find(|glyph| !glyph.is_whitespace)
+                    .map(line_end)
+                    .unwrap_or(0.0);
+                // lines are made taller to fit their boxes.
+                let line_boxes = shaped[glyphs.clone()]
+                    .iter()
+                    .filter_map(|glyph| Some((glyph.inline_box?, glyph.pen - origin)))
+                    .collect::<Vec<_>>();
+                let (line_baseline, line_descent) = line_boxes.iter().fold(
+                    (baseline, line_height - baseline),
+                    |(above, below), &(n, _)| {
+                        let height = builder.inline_boxes[n].1.height;
+                        let box_ascent = box_ascents[n];
+                        (above.max(box_ascent), below.max(height - box_ascent))
+                    },
+                );
+                let boxes = line_boxes
+                    .into_iter()
+                    .map(|(n, x)| {
+                        let top = y_offset + line_baseline - box_ascents[n];
+                        (
+                            n,
+                            Rect::from_origin_size((x, top), builder.inline_boxes[n].1),
+                        )
+                    })
+                    .collect();
+                let glyphs = shaped[glyphs]
+                    .iter()
+                    .filter(|glyph| glyph.inline_box.is_none())
+                    .map(|glyph| Glyph {
+                        id: glyph.id,
+                        position: Point::new(
+                            glyph.pen - origin + glyph.offset.x,
+                            y_offset + line_baseline + glyph.offset.y,
+                        ),
+                        advance: glyph.advance,
+                        cluster: glyph.cluster,
+                    })
+                    .collect();
+                let height = line_baseline + line_descent;
+                lines.push(Line {
+                    metric: LineMetric {
+                        start_offset,
+                        end_offset,
+                        trailing_whitespace: line_text.len() - line_text.trim_end().len(),
+                        baseline: line_baseline,
+                        height,
+                        y_offset,
+                    },
+                    glyphs,
+                    x: 0.0,
+                    width,
+                    full_width,
+                    boxes,
+                });
+                y_offset += height;
+            }
+        }
+
+        let width = lines.iter().map(|line| line.width).fold(0.0, f64::max);
+        let trailing_whitespace_width =
+            lines.iter().map(|line| line.full_width).fold(0.0, f64::max);
+        let align_width = if builder.max_width.is_finite() {
+            builder.max_width
+        } else {
+            width
+        };
+        for line in &mut lines {
+            let free = align_width - line.width;
+            line.x = match builder.alignment {
+                TextAlignment::Start | TextAlignment::Justified => 0.0,
+                TextAlignment::End => free,
+                TextAlignment::Center => free / 2.0,
+            };
+            for glyph in &mut line.glyphs {
+                glyph.position.x += line.x;
+            }
+            for (_, rect) in &mut line.boxes {
+                *rect = *rect + Vec2::new(line.x, 0.0);
+            }
+        }
+
+        let underline_metrics = shaper
+            .underline_metrics()
+            .map(|m| (-m.position as f64 * scale, m.thickness as f64 * scale))
+            .unwrap_or((font_size / 10.0, font_size / 16.0));
+        let strikethrough_metrics = shaper
+            .strikeout_metrics()
+            .map(|m| (-m.position as f64 * scale, m.thickness as f64 * scale))
+            .unwrap_or((-font_size / 3.0, font_size / 16.0));
+
+        Ok(WgpuTextLayout {
+            size: Size::new(width, y_offset),
+            font,
+            face,
+            font_size,
+            text_color: defaults.fg_color,
+            background_color: defaults.bg_color,
+            underline: defaults.underline,
+            strikethrough: defaults.strikethrough,
+            lines,
+            trailing_whitespace_width,
+            underline_metrics,
+            strikethrough_metrics,
+            text: builder.text,
+        })
+    }
+
+    /// The outline of a glyph, in layout coordinates.
+    pub(crate) fn glyph_outline(&self, glyph: &Glyph, path: &mut BezPath) {
+        if let Ok(face) = ttf_parser::Face::from_slice(&self.font.data, self.font.index) {
+            let scale = self.font_size / face.units_per_em().unwrap_or(1000) as f64;
+            let mut builder = OutlineBuilder {
+                path,
+                origin: glyph.position,
+                scale,
+            };
+            face.outline_glyph(ttf_parser::GlyphId(glyph.id as u16), &mut builder);
+        }
+    }
+
+    fn line_for_y(&self, y: f64) -> usize {
+        self.lines
+            .iter()
+            .position(|line| y < line.metric.y_offset + line.metric.height)
+            .unwrap_or(self.lines.len() - 1)
+    }
+}
+
+impl piet::TextLayout for WgpuTextLayout {
+    fn size(&self) -> Size {
+        self.size
+    }
+
+    fn trailing_whitespace_width(&self) -> f64 {
+        self.trailing_whitespace_width
+    }
+
+    fn image_bounds(&self) -> Rect {
+        self.size.to_rect()
+    }
+
+    fn text(&self) -> &str {
+        self.text.as_str()
+    }
+
+    fn line_text(&self, line_number: usize) -> Option<&str> {
+        self.lines
+            .get(line_number)
+            .map(|line| &self.text[line.metric.range()])
+    }
+
+    fn line_metric(&self, line_number: usize) -> Option<LineMetric> {
+        self.lines.get(line_number).map(|line| line.metric.clone())
+    }
+
+    fn line_count(&self) -> usize {
+        self.lines.len()
+    }
+
+    fn hit_test_point(&self, point: Point) -> HitTestPoint {
+        let line_number = self.line_for_y(point.y);
+        let line = &self.lines[line_number];
+        let inside_y = point.y >= 0.0 && point.y <= self.size.height;
+        let inside_x = point.x >= line.x && point.x <= line.x + line.width;
+        let idx = line
+            .glyphs
+            .iter()
+            .find(|glyph| point.x < glyph.position.x + glyph.advance / 2.0)
+            .map(|glyph| glyph.cluster)
+            .unwrap_or_else(|| {
+                let text = &self.text[line.metric.range()];
+                line.metric.end_offset - util::trailing_nlf(text).unwrap_or(0)
+            });
+        util::hit_test_point_on_line(
+            self,
+            Affine::IDENTITY,
+            point,
+            line_number,
+            idx,
+            inside_x && inside_y,
+        )
+    }
+
+    fn hit_test_text_position(&self, idx: usize) -> HitTestPosition {
+        let idx = idx.min(self.text.len());
+        let metrics = self
+            .lines
+            .iter()
+            .map(|line| line.metric.clone())
+            .collect::<Vec<_>>();
+        let line_number = util::line_number_for_position(&metrics, idx);
+        let line = &self.lines[line_number];
+        let x = line
+            .glyphs
+            .iter()
+            .find(|glyph| glyph.cluster >= idx)
+            .map(|glyph| glyph.position.x)
+            .unwrap_or(line.x + line.full_width);
+        let y = line.metric.y_offset + line.metric.baseline;
+        HitTestPosition::new(Point::new(x, y), line_number)
+    }
+
+    fn glyph_runs(&self) -> Vec<GlyphRun> {
+        self.lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| GlyphRun {
+                font: self.face.family.clone(),
+                font_size: self.font_size,
+                weight: self.face.weight,
+                style: self.face.style,
+                line: i,
+                glyphs: line.glyphs.clone(),
+            })
+            .collect()
+    }
+
+    fn outline(&self) -> BezPath {
+        let mut path = BezPath::new();
+        for glyph in self.lines.iter().flat_map(|line| &line.glyphs) {
+            self.glyph_outline(glyph, &mut path);
+        }
+        path
+    }
+
+    fn inline_boxes(&self) -> Vec<Rect> {
+        let mut boxes = self
+            .lines
+            .iter()
+            .flat_map(|line| &line.boxes)
+            .collect::<Vec<_>>();
+        boxes.sort_by_key(|(n, _)| *n);
+        boxes.into_iter().map(|(_, rect)| *rect).collect()
+    }
+}
+
+struct Paragraph {
+    start: usize,
+    /// The end, not including the line break.
+    content_end: usize,
+    end: usize,
+}
+
+/// Split text at hard line breaks. Text that ends in a line break has an
+/// empty paragraph after it.
+fn paragraphs(text: &str) -> Vec<Paragraph> {
+    let mut paragraphs = Vec::new();
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        if c == '\n' {
+            let content_end = if text[..i].ends_with('\r') { i - 1 } else { i };
+            paragraphs.push(Paragraph {
+                start,
+                content_end,
+                end: i + 1,
+            });
+            start = i + 1;
+        }
+    }
+    paragraphs.push(Paragraph {
+        start,
+        content_end: text.len(),
+        end: text.len(),
+    });
+    paragraphs
+}
+
+/// Break a paragraph into lines no wider than `max_width`, after
+/// whitespace and on either side of inline boxes, returning the range of
+/// glyphs in each line.
+fn wrap(glyphs: &[Shaped], max_width: f64) -> Vec<Range<usize>> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    let mut last_break = None;
+    for (i, glyph) in glyphs.iter().enumerate() {
+        let can_break = |prev: &Shaped| {
+            prev.is_whitespace || prev.inline_box.is_some() || glyph.inline_box.is_some()
+        };
+        if i > start && !glyph.is_whitespace && can_break(&glyphs[i - 1]) {
+            last_break = Some(i);
+        }
+        let too_wide = glyph.pen + glyph.advance - glyphs[start].pen > max_width;
+        if let (true, false, Some(brk)) = (too_wide, glyph.is_whitespace, last_break) {
+            lines.push(start..brk);
+            start = brk;
+            last_break = None;
+        }
+    }
+    lines.push(start..glyphs.len());
+    lines
+}
+
+/// Rasterize a glyph of `font`, `size` pixels high, offset from the pixel
+/// grid horizontally by `subpixel`.
+pub(crate) fn rasterize_glyph(
+    font: &FontData,
+    glyph: u16,
+    size: f64,
+    subpixel: f64,
+) -> Option<GlyphBitmap> {
+    let face = ttf_parser::Face::from_slice(&font.data, font.index).ok()?;
+    let scale = size / face.units_per_em()? as f64;
+    let bounds = face.glyph_bounding_box(ttf_parser::GlyphId(glyph))?;
+    // pixel bounds, with y down.
+    let left = (bounds.x_min as f64 * scale + subpixel).floor() as i32;
+    let right = (bounds.x_max as f64 * scale + subpixel).ceil() as i32;
+    let top = (-bounds.y_max as f64 * scale).floor() as i32;
+    let bottom = (-bounds.y_min as f64 * scale).ceil() as i32;
+    let (width, height) = ((right - left).max(0) as u32, (bottom - top).max(0) as u32);
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    let mut path = BezPath::new();
+    let mut builder = OutlineBuilder {
+        path: &mut path,
+        origin: Point::new(subpixel - left as f64, -top as f64),
+        scale,
+    };
+    face.outline_glyph(ttf_parser::GlyphId(glyph), &mut builder)?;
+    let mut rasterizer = ab_glyph_rasterizer::Rasterizer::new(width as usize, height as usize);
+    let point = |p: Point| ab_glyph_rasterizer::point(p.x as f32, p.y as f32);
+    let mut last = Point::ZERO;
+    let mut start = Point::ZERO;
+    for el in path.elements() {
+        use piet::kurbo::PathEl;
+        match *el {
+            PathEl::MoveTo(p) => {
+                start = p;
+                last = p;
+            }
+            PathEl::LineTo(p) => {
+                rasterizer.draw_line(point(last), point(p));
+                last = p;
+            }
+            PathEl::QuadTo(p1, p2) => {
+                rasterizer.draw_quad(point(last), point(p1), point(p2));
+                last = p2;
+            }
+            PathEl::CurveTo(p1, p2, p3) => {
+                rasterizer.draw_cubic(point(last), point(p1), point(p2), point(p3));
+                last = p3;
+            }
+            PathEl::ClosePath => {
+                rasterizer.draw_line(point(last), point(start));
+                last = start;
+            }
+        }
+    }
+    let mut data = vec![0; (width * height) as usize];
+    rasterizer.for_each_pixel(|i, coverage| {
+        data[i] = (coverage.min(1.0) * 255.0).round() as u8;
+    });
+    Some(GlyphBitmap {
+        width,
+        height,
+        left,
+        top,
+        data,
+    })
+}
+
+/// Collects glyph outlines, converting from font units to layout coordinates.
+struct OutlineBuilder<'a> {
+    path: &'a mut BezPath,
+    origin: Point,
+    scale: f64,
+}
+
+impl OutlineBuilder<'_> {
+    fn point(&self, x: f32, y: f32) -> Point {
+        // font units are y-up
+        Point::new(
+            self.origin.x + x as f64 * self.scale,
+            self.origin.y - y as f64 * self.scale,
+        )
+    }
+}
+
+impl ttf_parser::OutlineBuilder for OutlineBuilder<'_> {
+    fn move_to(&mut self, x: f32, y: f32) {
+        let p = self.point(x, y);
+        self.path.move_to(p);
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        let p = self.point(x, y);
+        self.path.line_to(p);
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let (p1, p) = (self.point(x1, y1), self.point(x, y));
+        self.path.quad_to(p1, p);
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let (p1, p2, p) = (self.point(x1, y1), self.point(x2, y2), self.point(x, y));
+        self.path.curve_to(p1, p2, p);
+    }
+
+    fn close(&mut self) {
+        self.path.close_path();
+    }
+}
+
+impl FontFace {
+    fn to_fk_family(&self) -> font_kit::family_name::FamilyName {
+        use font_kit::family_name::FamilyName;
+        if self.family == FontFamily::SANS_SERIF || self.family == FontFamily::SYSTEM_UI {
+            FamilyName::SansSerif
+        } else if self.family == FontFamily::SERIF {
+            FamilyName::Serif
+        } else if self.family == FontFamily::MONOSPACE {
+            FamilyName::Monospace
+        } else {
+            FamilyName::Title(self.family.name().to_owned())
+        }
+    }
+
+    fn to_props(&self) -> font_kit::properties::Properties {
+        use font_kit::properties::{Properties, Style};
+
+        let mut props = Properties::new();
+        props.weight.0 = self.weight.to_raw() as f32;
+        props.style = match self.style {
+            FontStyle::Regular => Style::Normal,
+            FontStyle::Italic => Style::Italic,
+        };
+        props
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn shaped(widths: &[(f64, bool)]) -> Vec<Shaped> {
+        let mut pen = 0.0;
+        widths
+            .iter()
+            .enumerate()
+            .map(|(i, &(advance, is_whitespace))| {
+                let glyph = Shaped {
+                    id: 0,
+                    cluster: i,
+                    pen,
+                    advance,
+                    offset: Point::ZERO,
+                    is_whitespace,
+                    inline_box: None,
+                };
+                pen += advance;
+                glyph
+            })
+            .collect()
+    }
+
+    #[test]
+    fn wrap_after_whitespace() {
+        // "aa bb cc", one unit per glyph.
+        let glyphs = shaped(&[
+            (1., false),
+            (1., false),
+            (1., true),
+            (1., false),
+            (1., false),
+            (1., true),
+            (1., false),
+            (1., false),
+        ]);
+        assert_eq!(wrap(&glyphs, 5.5), vec![0..6, 6..8]);
+        assert_eq!(wrap(&glyphs, 3.0), vec![0..3, 3..6, 6..8]);
+        // words that don't fit aren't broken.
+        assert_eq!(wrap(&glyphs, 1.0), vec![0..3, 3..6, 6..8]);
+        assert_eq!(wrap(&glyphs, f64::INFINITY), vec![0..8]);
+    }
+
+    #[test]
+    fn wrap_around_inline_boxes() {
+        // "aa", a box two units wide, then "bb", without spaces.
+        let mut glyphs = shaped(&[
+            (1., false),
+            (1., false),
+            (2., false),
+            (1., false),
+            (1., false),
+        ]);
+        glyphs[2].inline_box = Some(0);
+        assert_eq!(wrap(&glyphs, 4.5), vec![0..3, 3..5]);
+        assert_eq!(wrap(&glyphs, 3.5), vec![0..2, 2..3, 3..5]);
+    }
+
+    #[test]
+    fn inline_boxes_are_laid_out() {
+        use piet::{Text, TextLayout, TextLayoutBuilder};
+
+        let layout = WgpuText::new()
+            .new_text_layout("ab cd")
+            .inline_box(3, Size::new(10., 40.), BaselineAlignment::Bottom)
+            .inline_box(0, Size::new(5., 5.), BaselineAlignment::Baseline(4.))
+            .build()
+            .unwrap();
+        let boxes = layout.inline_boxes();
+        let metric = layout.line_metric(0).unwrap();
+        // the tall box sits on the baseline, and makes the line taller.
+        assert_eq!(boxes.len(), 2);
+        assert_eq!(metric.baseline, 40.);
+        assert_eq!(boxes[0].y1, metric.baseline);
+        assert_eq!(boxes[1], Rect::new(0., 36., 5., 41.));
+        // the text is laid out around the boxes.
+        let glyphs = &layout.lines[0].glyphs;
+        assert_eq!(glyphs[0].position.x, 5.);
+        assert_eq!(glyphs[3].position.x, boxes[0].x1);
+        assert_eq!(layout.hit_test_text_position(3).point.x, boxes[0].x1);
+    }
+
+    #[test]
+    fn paragraph_breaks() {
+        let ranges = |text| {
+            paragraphs(text)
+                .iter()
+                .map(|p| (p.start, p.content_end, p.end))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ranges(""), vec![(0, 0, 0)]);
+        assert_eq!(ranges("a\r\nb"), vec![(0, 1, 3), (3, 4, 4)]);
+        assert_eq!(ranges("a\n"), vec![(0, 1, 2), (2, 2, 2)]);
+    }
+}
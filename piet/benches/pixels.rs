@@ -0,0 +1,67 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use piet::util;
+
+/// The number of pixels in a 2160p frame.
+const PIXELS: usize = 3840 * 2160;
+
+fn fill_random(data: &mut [u8]) {
+    // A simple LCG with parameters from Wikipedia. See glibc/ ANSI C, CodeWarrior, ... in
+    // https://en.wikipedia.org/w/index.php?title=Linear_congruential_generator&oldid=1028647893#Parameters_in_common_use
+    let mut state: u32 = 123456789;
+    let m: u32 = 1 << 31;
+    let a: u32 = 1103515245;
+    let c: u32 = 12345;
+
+    let mut next_number = || {
+        state = (a * state + c) % m;
+        // Take a higher byte since it is more random than the low bytes
+        (state >> 16) as u8
+    };
+
+    data.iter_mut().for_each(|b| *b = next_number());
+}
+
+pub fn bench_pixels(c: &mut Criterion) {
+    let mut rgba = vec![0; PIXELS * 4];
+    fill_random(&mut rgba);
+    let mut scratch = rgba.clone();
+
+    // the per-pixel loop that backends used before, for comparison.
+    c.bench_function("premultiply_rgba_scalar_2160p", |b| {
+        b.iter(|| {
+            scratch.copy_from_slice(&rgba);
+            for px in black_box(&mut scratch).chunks_exact_mut(4) {
+                let a = px[3];
+                px[0] = util::premul(px[0], a);
+                px[1] = util::premul(px[1], a);
+                px[2] = util::premul(px[2], a);
+            }
+        })
+    });
+    c.bench_function("premultiply_rgba_2160p", |b| {
+        b.iter(|| {
+            scratch.copy_from_slice(&rgba);
+            util::premultiply_rgba(black_box(&mut scratch));
+        })
+    });
+    c.bench_function("unpremultiply_rgba_2160p", |b| {
+        b.iter(|| {
+            scratch.copy_from_slice(&rgba);
+            util::unpremultiply_rgba(black_box(&mut scratch));
+        })
+    });
+    c.bench_function("swap_red_blue_2160p", |b| {
+        b.iter(|| util::swap_red_blue(black_box(&mut scratch)))
+    });
+    c.bench_function("rgb_to_rgba_2160p", |b| {
+        let rgb = &rgba[..PIXELS * 3];
+        b.iter(|| util::rgb_to_rgba(black_box(rgb), &mut scratch))
+    });
+    c.bench_function("gray_to_rgba_2160p", |b| {
+        let gray = &rgba[..PIXELS];
+        b.iter(|| util::gray_to_rgba(black_box(gray), &mut scratch))
+    });
+}
+
+criterion_group!(benches, bench_pixels);
+criterion_main!(benches);
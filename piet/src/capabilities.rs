@@ -0,0 +1,69 @@
+//! What a backend can do, for choosing features at runtime.
+
+/// The features that a backend supports natively, which are returned by
+/// [`RenderContext::capabilities`].
+///
+/// Apps can use this to choose what to draw on each platform, such as
+/// leaving out blurs where they would be slow, without needing to know
+/// which backend they were built with. Features that aren't supported still
+/// work, but may be ignored or drawn more simply.
+///
+/// More fields may be added, so this can't be made outside of piet except
+/// with [`Capabilities::default`], whose fields can then be changed.
+///
+/// [`RenderContext::capabilities`]: crate::RenderContext::capabilities
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Capabilities {
+    /// Whether the backend can composite with blend modes, such as multiply
+    /// and screen, instead of only drawing over what is there.
+    pub supports_blend_modes: bool,
+    /// Whether [`blurred_rect`] and the blurs and shadows of
+    /// [`draw_image_filtered`] are drawn, rather than being approximated or
+    /// left out.
+    ///
+    /// [`blurred_rect`]: crate::RenderContext::blurred_rect
+    /// [`draw_image_filtered`]: crate::RenderContext::draw_image_filtered
+    pub supports_blur: bool,
+    /// Whether the [global alpha] fades what is drawn, rather than being
+    /// ignored, which also makes [`fill_with_alpha`] fade its fill.
+    ///
+    /// [global alpha]: crate::RenderContext::set_global_alpha
+    /// [`fill_with_alpha`]: crate::RenderContext::fill_with_alpha
+    pub supports_global_alpha: bool,
+    /// The largest width and height of an image, in pixels.
+    ///
+    /// Making a bigger image fails. This is `usize::MAX` for backends
+    /// without a limit of their own, although memory still limits them.
+    pub max_image_size: usize,
+    /// How well text is shaped.
+    pub text_shaping_quality: TextShapingQuality,
+}
+
+/// How well a backend shapes text, from worst to best.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum TextShapingQuality {
+    /// Text isn't drawn.
+    None,
+    /// Text is drawn, but complex scripts, such as Arabic and Devanagari,
+    /// may not be shaped correctly, and ligatures and kerning may be left
+    /// out.
+    Basic,
+    /// Text is shaped by a full shaper, such as the platform's, which
+    /// handles complex scripts and OpenType features.
+    Full,
+}
+
+impl Default for Capabilities {
+    /// Capabilities that promise nothing beyond what every backend does.
+    fn default() -> Capabilities {
+        Capabilities {
+            supports_blend_modes: false,
+            supports_blur: false,
+            supports_global_alpha: false,
+            max_image_size: usize::MAX,
+            text_shaping_quality: TextShapingQuality::Basic,
+        }
+    }
+}
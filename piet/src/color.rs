@@ -318,6 +318,34 @@ impl Debug for Color {
     }
 }
 
+/// A color is serialized as a hex string, like `"#ff0000ff"`, in formats
+/// that are human readable, and as its [RGBA value](Color::as_rgba_u32)
+/// otherwise.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Color {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(&format_args!("{:?}", self))
+        } else {
+            serializer.serialize_u32(self.as_rgba_u32())
+        }
+    }
+}
+
+/// In formats that are human readable, a color can be deserialized from any
+/// of the strings that [`Color::from_hex_str`] accepts.
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Color {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let hex = String::deserialize(deserializer)?;
+            Color::from_hex_str(&hex).map_err(serde::de::Error::custom)
+        } else {
+            u32::deserialize(deserializer).map(Color::from_rgba32_u32)
+        }
+    }
+}
+
 impl std::fmt::Display for ColorParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
@@ -355,4 +383,18 @@ mod tests {
         assert!(Color::from_hex_str("x0f").is_err());
         assert!(Color::from_hex_str("#0afa1").is_err());
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn color_serde() {
+        let color = Color::rgba8(0xba, 0xdd, 0x00, 0x80);
+        let json = serde_json::to_string(&color).unwrap();
+        assert_eq!(json, r##""#badd0080""##);
+        assert_eq!(serde_json::from_str::<Color>(&json).unwrap(), color);
+        assert_eq!(
+            serde_json::from_str::<Color>(r##""#0f6""##).unwrap(),
+            Color::rgb8(0, 0xff, 0x66)
+        );
+        assert!(serde_json::from_str::<Color>(r##""#0f""##).is_err());
+    }
 }
@@ -0,0 +1,886 @@
+//! A render context that leaves out what can't be seen.
+
+use std::borrow::Cow;
+
+use kurbo::{Affine, BezPath, Insets, PathEl, Point, Rect, Shape};
+
+use crate::{
+    util, BlendMode, Capabilities, Color, Error, FixedGradient, GradientOptions, GradientStops,
+    HitTester, ImageFilter, ImageFormat, InterpolationMode, IntoBrush, LineJoin, Markers, MipImage,
+    PreparedShape, RenderContext, SharedImage, StrokeStyle, Symbol, TextLayout,
+};
+
+/// Segments shorter than this, in device space, are collapsed into the
+/// segment that follows them.
+const DEGENERATE_LENGTH: f64 = 0.05;
+
+/// How far antialiasing can reach outside of a shape, in device space.
+const AA_MARGIN: f64 = 1.0;
+
+/// A render context that simplifies what is drawn before passing it on to
+/// another render context.
+///
+/// Scenes such as maps often draw much more than is visible. This context
+/// keeps track of the transform and the bounds of the clip, and leaves out
+/// anything that is drawn entirely outside of them, or outside of the
+/// viewport that it is made with. It also collapses segments of paths that
+/// are too short to be seen, and merges consecutive fills of the same color
+/// that don't overlap into one fill.
+///
+/// It works with any backend, including the recording context of
+/// `piet-recorder`, so that a recorded scene only keeps what is visible:
+///
+/// ```
+/// # use piet::{kurbo::Rect, Color, CullingRenderContext, NullRenderContext, RenderContext};
+/// let mut rc = CullingRenderContext::new(NullRenderContext::new(), Rect::new(0.0, 0.0, 100.0, 100.0));
+/// rc.fill(Rect::new(10.0, 10.0, 20.0, 20.0), &Color::BLACK);
+/// rc.fill(Rect::new(200.0, 10.0, 220.0, 20.0), &Color::BLACK);
+/// rc.finish().unwrap();
+/// assert_eq!(rc.culled(), 1);
+/// ```
+///
+/// Fills are held back so that they can be merged, so [`finish`] must be
+/// called, or the context turned back into the inner context with
+/// [`into_inner`], for everything to be drawn.
+///
+/// [`finish`]: RenderContext::finish
+/// [`into_inner`]: CullingRenderContext::into_inner
+pub struct CullingRenderContext<RC: RenderContext> {
+    inner: RC,
+    state: CullingState,
+    saved: Vec<CullingState>,
+    pending: Option<PendingFill<RC::Brush>>,
+    culled: usize,
+}
+
+/// The brush of a [`CullingRenderContext`], which is the brush of the
+/// context it wraps.
+#[derive(Clone)]
+pub struct CullingBrush<B> {
+    /// The color, if the brush is solid, so that fills can be merged.
+    color: Option<Color>,
+    inner: B,
+}
+
+#[derive(Clone, Copy)]
+struct CullingState {
+    transform: Affine,
+    /// The bounds of the clip, and of the viewport, in device space.
+    clip: Rect,
+}
+
+/// Consecutive fills of one color, which are drawn as one shape.
+struct PendingFill<B> {
+    color: Color,
+    brush: B,
+    shape: PendingShape,
+    /// The bounds of the fills, in user space.
+    bounds: Rect,
+}
+
+/// The fills are kept as a rectangle while there is only one, since backends
+/// can often draw those faster than paths.
+enum PendingShape {
+    Rect(Rect),
+    Path(BezPath),
+}
+
+impl<RC: RenderContext> CullingRenderContext<RC> {
+    /// Wrap `inner`, leaving out anything that is drawn outside of
+    /// `viewport`.
+    ///
+    /// The viewport is in the space that [`current_transform`] of `inner`
+    /// maps to, which for most backends is the pixels of the target.
+    ///
+    /// [`current_transform`]: RenderContext::current_transform
+    pub fn new(inner: RC, viewport: Rect) -> CullingRenderContext<RC> {
+        let transform = inner.current_transform();
+        CullingRenderContext {
+            inner,
+            state: CullingState {
+                transform,
+                clip: viewport,
+            },
+            saved: Vec::new(),
+            pending: None,
+            culled: 0,
+        }
+    }
+
+    /// Draw anything that is held back, and return the inner context.
+    pub fn into_inner(mut self) -> RC {
+        self.flush();
+        self.inner
+    }
+
+    /// The number of primitives that have been left out, because they were
+    /// outside of the clip.
+    pub fn culled(&self) -> usize {
+        self.culled
+    }
+
+    /// Draw the fills that are held back.
+    fn flush(&mut self) {
+        if let Some(pending) = self.pending.take() {
+            match pending.shape {
+                PendingShape::Rect(rect) => self.inner.fill(rect, &pending.brush),
+                PendingShape::Path(path) => self.inner.fill(path, &pending.brush),
+            }
+        }
+    }
+
+    /// Returns `true` if something within `bounds`, in user space, can be
+    /// seen. `device_margin` is added to the bounds in device space.
+    fn visible(&mut self, bounds: Rect, device_margin: f64) -> bool {
+        let device = self
+            .state
+            .transform
+            .transform_rect_bbox(bounds)
+            .inflate(device_margin, device_margin);
+        let clip = self.state.clip;
+        // bounds that are NaN are never culled.
+        let outside = device.x1 < clip.x0
+            || device.x0 > clip.x1
+            || device.y1 < clip.y0
+            || device.y0 > clip.y1;
+        if outside {
+            self.culled += 1;
+        }
+        !outside
+    }
+
+    /// `shape` with its degenerate segments collapsed, if it has any.
+    fn simplify(&self, shape: &impl Shape, keep_first: bool) -> Option<BezPath> {
+        let scale = self.state.transform.determinant().abs().sqrt();
+        if !scale.is_normal() {
+            return None;
+        }
+        collapse_degenerate(
+            shape.as_path_slice()?,
+            DEGENERATE_LENGTH / scale,
+            keep_first,
+        )
+    }
+
+    /// Returns `true` if the stroke of a shape with `bounds` may be visible.
+    fn stroke_visible(&mut self, bounds: Rect, width: f64, style: &StrokeStyle) -> bool {
+        let extent = stroke_extent(width, style);
+        if style.scale {
+            self.visible(bounds.inflate(extent, extent), AA_MARGIN)
+        } else {
+            self.visible(bounds, extent + AA_MARGIN)
+        }
+    }
+
+    fn stroke_impl(
+        &mut self,
+        shape: impl Shape,
+        brush: &impl IntoBrush<Self>,
+        width: f64,
+        style: Option<&StrokeStyle>,
+    ) {
+        let bounds = shape.bounding_box();
+        let default_style = StrokeStyle::new();
+        if !self.stroke_visible(bounds, width, style.unwrap_or(&default_style)) {
+            return;
+        }
+        self.flush();
+        let brush = brush.make_brush(self, || bounds);
+        // the first segment of each subpath is kept, so that zero-length
+        // strokes still draw their caps.
+        let simplified = self.simplify(&shape, true);
+        match (simplified, style) {
+            (Some(path), Some(style)) => self.inner.stroke_styled(path, &brush.inner, width, style),
+            (Some(path), None) => self.inner.stroke(path, &brush.inner, width),
+            (None, Some(style)) => self.inner.stroke_styled(shape, &brush.inner, width, style),
+            (None, None) => self.inner.stroke(shape, &brush.inner, width),
+        }
+    }
+}
+
+impl<RC: RenderContext> RenderContext for CullingRenderContext<RC> {
+    type Brush = CullingBrush<RC::Brush>;
+    type Text = RC::Text;
+    type TextLayout = RC::TextLayout;
+    type Image = RC::Image;
+
+    fn status(&mut self) -> Result<(), Error> {
+        self.inner.status()
+    }
+
+    fn solid_brush(&mut self, color: Color) -> Self::Brush {
+        CullingBrush {
+            color: Some(color),
+            inner: self.inner.solid_brush(color),
+        }
+    }
+
+    fn gradient(&mut self, gradient: impl Into<FixedGradient>) -> Result<Self::Brush, Error> {
+        Ok(CullingBrush {
+            color: None,
+            inner: self.inner.gradient(gradient)?,
+        })
+    }
+
+    fn gradient_with_options(
+        &mut self,
+        gradient: impl Into<FixedGradient>,
+        options: GradientOptions,
+    ) -> Result<Self::Brush, Error> {
+        Ok(CullingBrush {
+            color: None,
+            inner: self.inner.gradient_with_options(gradient, options)?,
+        })
+    }
+
+    fn clear(&mut self, region: impl Into<Option<Rect>>, color: Color) {
+        self.flush();
+        self.inner.clear(region, color);
+    }
+
+    fn stroke(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>, width: f64) {
+        self.stroke_impl(shape, brush, width, None);
+    }
+
+    fn stroke_styled(
+        &mut self,
+        shape: impl Shape,
+        brush: &impl IntoBrush<Self>,
+        width: f64,
+        style: &StrokeStyle,
+    ) {
+        self.stroke_impl(shape, brush, width, Some(style));
+    }
+
+    fn stroke_gradient_along_path(
+        &mut self,
+        shape: impl Shape,
+        stops: impl GradientStops,
+        width: f64,
+        style: &StrokeStyle,
+    ) {
+        self.flush();
+        self.inner
+            .stroke_gradient_along_path(shape, stops, width, style);
+    }
+
+    fn stroke_with_markers(
+        &mut self,
+        shape: impl Shape,
+        brush: &impl IntoBrush<Self>,
+        width: f64,
+        style: &StrokeStyle,
+        markers: &Markers,
+    ) {
+        // markers can reach far outside of the shape, so these are never culled.
+        self.flush();
+        let brush = brush.make_brush(self, || shape.bounding_box());
+        self.inner
+            .stroke_with_markers(shape, &brush.inner, width, style, markers);
+    }
+
+    fn fill(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>) {
+        let bounds = shape.bounding_box();
+        if !self.visible(bounds, AA_MARGIN) {
+            return;
+        }
+        let brush = brush.make_brush(self, || bounds);
+        let simplified = self.simplify(&shape, false);
+
+        // fills that don't overlap can be merged without changing what is
+        // drawn, since no pixel is covered by both.
+        if let (Some(pending), Some(color)) = (&mut self.pending, brush.color) {
+            let overlaps = pending.bounds.intersect(bounds).area() > 0.0;
+            if pending.color == color && !overlaps {
+                if let PendingShape::Rect(rect) = pending.shape {
+                    pending.shape = PendingShape::Path(rect.to_path(0.1));
+                }
+                let path = match &mut pending.shape {
+                    PendingShape::Path(path) => path,
+                    PendingShape::Rect(_) => unreachable!(),
+                };
+                match &simplified {
+                    Some(simplified) => path.extend(simplified.iter()),
+                    None => path.extend(shape.path_elements(0.1)),
+                }
+                pending.bounds = pending.bounds.union(bounds);
+                return;
+            }
+        }
+        self.flush();
+        match brush.color {
+            Some(color) => {
+                let shape = match (simplified, shape.as_rect()) {
+                    (Some(path), _) => PendingShape::Path(path),
+                    (None, Some(rect)) => PendingShape::Rect(rect),
+                    (None, None) => PendingShape::Path(shape.into_path(self.inner.tolerance())),
+                };
+                self.pending = Some(PendingFill {
+                    color,
+                    brush: brush.into_owned().inner,
+                    shape,
+                    bounds,
+                });
+            }
+            None => match simplified {
+                Some(path) => self.inner.fill(path, &brush.inner),
+                None => self.inner.fill(shape, &brush.inner),
+            },
+        }
+    }
+
+    fn fill_even_odd(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>) {
+        let bounds = shape.bounding_box();
+        if !self.visible(bounds, AA_MARGIN) {
+            return;
+        }
+        self.flush();
+        let brush = brush.make_brush(self, || bounds);
+        match self.simplify(&shape, false) {
+            Some(path) => self.inner.fill_even_odd(path, &brush.inner),
+            None => self.inner.fill_even_odd(shape, &brush.inner),
+        }
+    }
+
+    fn fill_and_stroke(
+        &mut self,
+        shape: impl Shape,
+        fill_brush: &impl IntoBrush<Self>,
+        stroke_brush: &impl IntoBrush<Self>,
+        width: f64,
+        style: &StrokeStyle,
+    ) {
+        let bounds = shape.bounding_box();
+        if !self.stroke_visible(bounds, width, style) {
+            return;
+        }
+        self.flush();
+        let fill_brush = fill_brush.make_brush(self, || bounds);
+        let stroke_brush = stroke_brush.make_brush(self, || bounds);
+        self.inner
+            .fill_and_stroke(shape, &fill_brush.inner, &stroke_brush.inner, width, style);
+    }
+
+    fn fill_many(
+        &mut self,
+        shapes: impl IntoIterator<Item = impl Shape>,
+        brush: &impl IntoBrush<Self>,
+    ) {
+        let shapes: Vec<_> = shapes
+            .into_iter()
+            .filter(|shape| self.visible(shape.bounding_box(), AA_MARGIN))
+            .collect();
+        if shapes.is_empty() {
+            return;
+        }
+        self.flush();
+        let brush = brush.make_brush(self, || util::union_bounds(&shapes));
+        self.inner.fill_many(shapes, &brush.inner);
+    }
+
+    fn stroke_many(
+        &mut self,
+        shapes: impl IntoIterator<Item = impl Shape>,
+        brush: &impl IntoBrush<Self>,
+        width: f64,
+        style: &StrokeStyle,
+    ) {
+        let shapes: Vec<_> = shapes
+            .into_iter()
+            .filter(|shape| self.stroke_visible(shape.bounding_box(), width, style))
+            .collect();
+        if shapes.is_empty() {
+            return;
+        }
+        self.flush();
+        let brush = brush.make_brush(self, || util::union_bounds(&shapes));
+        self.inner.stroke_many(shapes, &brush.inner, width, style);
+    }
+
+    fn fill_with_mask(
+        &mut self,
+        shape: impl Shape,
+        brush: &impl IntoBrush<Self>,
+        mask: &Self::Image,
+    ) {
+        let bounds = shape.bounding_box();
+        if !self.visible(bounds, AA_MARGIN) {
+            return;
+        }
+        self.flush();
+        let brush = brush.make_brush(self, || bounds);
+        self.inner.fill_with_mask(shape, &brush.inner, mask);
+    }
+
+    fn clip(&mut self, shape: impl Shape) {
+        self.flush();
+        let device = self
+            .state
+            .transform
+            .transform_rect_bbox(shape.bounding_box());
+        self.state.clip = self.state.clip.intersect(device);
+        self.inner.clip(shape);
+    }
+
+    fn prepare_shape(&mut self, shape: impl Shape) -> PreparedShape {
+        self.inner.prepare_shape(shape)
+    }
+
+    fn draw_symbol_instances(
+        &mut self,
+        symbol: Symbol<Self::Image>,
+        brush: &impl IntoBrush<Self>,
+        instances: &[(Affine, Option<Color>)],
+    ) {
+        let bounds = symbol.bounds();
+        let instances: Vec<_> = instances
+            .iter()
+            .copied()
+            .filter(|(transform, _)| self.visible(transform.transform_rect_bbox(bounds), AA_MARGIN))
+            .collect();
+        if instances.is_empty() {
+            return;
+        }
+        self.flush();
+        let brush = brush.make_brush(self, || bounds);
+        self.inner
+            .draw_symbol_instances(symbol, &brush.inner, &instances);
+    }
+
+    fn text(&mut self) -> &mut Self::Text {
+        self.inner.text()
+    }
+
+    fn draw_text(&mut self, layout: &Self::TextLayout, pos: impl Into<Point>) {
+        let pos = pos.into();
+        if !self.visible(layout.image_bounds() + pos.to_vec2(), AA_MARGIN) {
+            return;
+        }
+        self.flush();
+        self.inner.draw_text(layout, pos);
+    }
+
+    fn save(&mut self) -> Result<(), Error> {
+        self.flush();
+        self.inner.save()?;
+        self.saved.push(self.state);
+        Ok(())
+    }
+
+    fn restore(&mut self) -> Result<(), Error> {
+        self.flush();
+        self.inner.restore()?;
+        if let Some(state) = self.saved.pop() {
+            self.state = state;
+        }
+        Ok(())
+    }
+
+    fn set_global_alpha(&mut self, alpha: f64) {
+        self.flush();
+        self.inner.set_global_alpha(alpha);
+    }
+
+    fn global_alpha(&self) -> f64 {
+        self.inner.global_alpha()
+    }
+
+    fn set_blend_mode(&mut self, mode: BlendMode) {
+        self.flush();
+        self.inner.set_blend_mode(mode);
+    }
+
+    fn blend_mode(&self) -> BlendMode {
+        self.inner.blend_mode()
+    }
+
+    fn set_tolerance(&mut self, tolerance: f64) {
+        self.inner.set_tolerance(tolerance);
+    }
+
+    fn tolerance(&self) -> f64 {
+        self.inner.tolerance()
+    }
+
+    fn annotate(&mut self, key: &str, value: &str) {
+        self.flush();
+        self.inner.annotate(key, value);
+    }
+
+    fn begin_group(&mut self, role: &str) -> Result<(), Error> {
+        self.flush();
+        self.inner.begin_group(role)
+    }
+
+    fn end_group(&mut self) -> Result<(), Error> {
+        self.flush();
+        self.inner.end_group()
+    }
+
+    fn begin_opacity_mask(
+        &mut self,
+        mask: &Self::Image,
+        rect: impl Into<Rect>,
+    ) -> Result<(), Error> {
+        self.flush();
+        let rect = rect.into();
+        self.inner.begin_opacity_mask(mask, rect)?;
+        self.saved.push(self.state);
+        // nothing is drawn outside of the mask.
+        let device = self.state.transform.transform_rect_bbox(rect);
+        self.state.clip = self.state.clip.intersect(device);
+        Ok(())
+    }
+
+    fn end_opacity_mask(&mut self) -> Result<(), Error> {
+        self.flush();
+        self.inner.end_opacity_mask()?;
+        if let Some(state) = self.saved.pop() {
+            self.state = state;
+        }
+        Ok(())
+    }
+
+    fn hit_region(&mut self, id: u64, shape: impl Shape) {
+        self.inner.hit_region(id, shape);
+    }
+
+    fn hit_region_stroke(&mut self, id: u64, shape: impl Shape, width: f64, style: &StrokeStyle) {
+        self.inner.hit_region_stroke(id, shape, width, style);
+    }
+
+    fn hit_tester(&mut self) -> Option<&mut HitTester> {
+        self.inner.hit_tester()
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        self.flush();
+        self.inner.finish()
+    }
+
+    fn transform(&mut self, transform: Affine) {
+        self.flush();
+        self.state.transform *= transform;
+        self.inner.transform(transform);
+    }
+
+    fn make_image(
+        &mut self,
+        width: usize,
+        height: usize,
+        buf: &[u8],
+        format: ImageFormat,
+    ) -> Result<Self::Image, Error> {
+        self.inner.make_image(width, height, buf, format)
+    }
+
+    fn make_shared_image(&mut self, image: &SharedImage) -> Result<Self::Image, Error> {
+        self.inner.make_shared_image(image)
+    }
+
+    fn make_image_from_encoded(&mut self, data: &[u8]) -> Result<Self::Image, Error> {
+        self.inner.make_image_from_encoded(data)
+    }
+
+    fn make_mip_image(
+        &mut self,
+        width: usize,
+        height: usize,
+        buf: &[u8],
+        format: ImageFormat,
+    ) -> Result<MipImage<Self::Image>, Error> {
+        self.inner.make_mip_image(width, height, buf, format)
+    }
+
+    fn draw_image(
+        &mut self,
+        image: &Self::Image,
+        dst_rect: impl Into<Rect>,
+        interp: InterpolationMode,
+    ) {
+        let dst_rect = dst_rect.into();
+        if !self.visible(dst_rect, AA_MARGIN) {
+            return;
+        }
+        self.flush();
+        self.inner.draw_image(image, dst_rect, interp);
+    }
+
+    fn draw_image_area(
+        &mut self,
+        image: &Self::Image,
+        src_rect: impl Into<Rect>,
+        dst_rect: impl Into<Rect>,
+        interp: InterpolationMode,
+    ) {
+        let dst_rect = dst_rect.into();
+        if !self.visible(dst_rect, AA_MARGIN) {
+            return;
+        }
+        self.flush();
+        self.inner
+            .draw_image_area(image, src_rect, dst_rect, interp);
+    }
+
+    fn draw_mip_image(
+        &mut self,
+        image: &MipImage<Self::Image>,
+        dst_rect: impl Into<Rect>,
+        interp: InterpolationMode,
+    ) {
+        let dst_rect = dst_rect.into();
+        if !self.visible(dst_rect, AA_MARGIN) {
+            return;
+        }
+        self.flush();
+        self.inner.draw_mip_image(image, dst_rect, interp);
+    }
+
+    fn draw_image_nine(
+        &mut self,
+        image: &Self::Image,
+        insets: impl Into<Insets>,
+        dst_rect: impl Into<Rect>,
+        interp: InterpolationMode,
+    ) {
+        let dst_rect = dst_rect.into();
+        if !self.visible(dst_rect, AA_MARGIN) {
+            return;
+        }
+        self.flush();
+        self.inner.draw_image_nine(image, insets, dst_rect, interp);
+    }
+
+    fn draw_image_with_quad(
+        &mut self,
+        image: &Self::Image,
+        dst_quad: [Point; 4],
+        interp: InterpolationMode,
+    ) {
+        let bounds = dst_quad[1..]
+            .iter()
+            .fold(Rect::from_points(dst_quad[0], dst_quad[0]), |bounds, &p| {
+                bounds.union_pt(p)
+            });
+        if !self.visible(bounds, AA_MARGIN) {
+            return;
+        }
+        self.flush();
+        self.inner.draw_image_with_quad(image, dst_quad, interp);
+    }
+
+    fn draw_image_filtered(
+        &mut self,
+        image: &Self::Image,
+        dst_rect: impl Into<Rect>,
+        filters: &[ImageFilter],
+    ) {
+        let dst_rect = dst_rect.into();
+        if !self.visible(dst_rect + ImageFilter::total_outset(filters), AA_MARGIN) {
+            return;
+        }
+        self.flush();
+        self.inner.draw_image_filtered(image, dst_rect, filters);
+    }
+
+    fn capture_image_area(&mut self, src_rect: impl Into<Rect>) -> Result<Self::Image, Error> {
+        self.flush();
+        self.inner.capture_image_area(src_rect)
+    }
+
+    fn blurred_rect(&mut self, rect: Rect, blur_radius: f64, brush: &impl IntoBrush<Self>) {
+        // the blur fades out within about three times its radius.
+        let reach = 3.0 * blur_radius.abs();
+        if !self.visible(rect.inflate(reach, reach), AA_MARGIN) {
+            return;
+        }
+        self.flush();
+        let brush = brush.make_brush(self, || rect);
+        self.inner.blurred_rect(rect, blur_radius, &brush.inner);
+    }
+
+    fn current_transform(&self) -> Affine {
+        self.state.transform
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+}
+
+impl<RC: RenderContext> IntoBrush<CullingRenderContext<RC>> for CullingBrush<RC::Brush> {
+    fn make_brush<'a>(
+        &'a self,
+        _piet: &mut CullingRenderContext<RC>,
+        _bbox: impl FnOnce() -> Rect,
+    ) -> Cow<'a, CullingBrush<RC::Brush>> {
+        Cow::Borrowed(self)
+    }
+}
+
+/// How far a stroke can reach outside of its shape.
+fn stroke_extent(width: f64, style: &StrokeStyle) -> f64 {
+    // square caps reach furthest at their corners.
+    let factor = match style.line_join {
+        LineJoin::Miter { limit } => limit.max(std::f64::consts::SQRT_2),
+        _ => std::f64::consts::SQRT_2,
+    };
+    width.abs() / 2.0 * factor
+}
+
+/// Remove the segments of a path that are shorter than `min_length`, or
+/// `None` if there are none.
+///
+/// If `keep_first` is `true`, the first segment of each subpath is never
+/// removed.
+fn collapse_degenerate(elements: &[PathEl], min_length: f64, keep_first: bool) -> Option<BezPath> {
+    let min_length2 = min_length * min_length;
+    let short =
+        |last: Point, points: &[Point]| points.iter().all(|p| (*p - last).hypot2() < min_length2);
+    let mut path: Option<BezPath> = None;
+    let mut last = Point::ZERO;
+    let mut start = Point::ZERO;
+    let mut first = true;
+    for (i, el) in elements.iter().enumerate() {
+        let drop = match *el {
+            PathEl::MoveTo(p) => {
+                last = p;
+                start = p;
+                first = true;
+                false
+            }
+            PathEl::ClosePath => {
+                last = start;
+                false
+            }
+            PathEl::LineTo(p) => !(keep_first && first) && short(last, &[p]),
+            PathEl::QuadTo(p1, p2) => !(keep_first && first) && short(last, &[p1, p2]),
+            PathEl::CurveTo(p1, p2, p3) => !(keep_first && first) && short(last, &[p1, p2, p3]),
+        };
+        if drop {
+            // copy what has been kept so far, the first time something is dropped.
+            path.get_or_insert_with(|| elements[..i].iter().copied().collect());
+            continue;
+        }
+        if let PathEl::LineTo(p) | PathEl::QuadTo(_, p) | PathEl::CurveTo(_, _, p) = *el {
+            last = p;
+            first = false;
+        }
+        if let Some(path) = &mut path {
+            path.push(*el);
+        }
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NullRenderContext;
+
+    fn culling() -> CullingRenderContext<NullRenderContext> {
+        CullingRenderContext::new(NullRenderContext::new(), Rect::new(0.0, 0.0, 100.0, 100.0))
+    }
+
+    #[test]
+    fn shapes_outside_the_clip_are_culled() {
+        let mut rc = culling();
+        rc.fill(Rect::new(10.0, 10.0, 20.0, 20.0), &Color::BLACK);
+        rc.fill(Rect::new(110.0, 10.0, 120.0, 20.0), &Color::BLACK);
+        assert_eq!(rc.culled(), 1);
+
+        // a wide stroke can reach into the viewport.
+        rc.stroke(Rect::new(105.0, 10.0, 120.0, 20.0), &Color::BLACK, 20.0);
+        assert_eq!(rc.culled(), 1);
+
+        rc.with_save(|rc| {
+            rc.clip(Rect::new(0.0, 0.0, 50.0, 50.0));
+            rc.fill(Rect::new(60.0, 10.0, 70.0, 20.0), &Color::BLACK);
+            assert_eq!(rc.culled(), 2);
+            rc.transform(Affine::translate((-50.0, 0.0)));
+            rc.fill(Rect::new(60.0, 10.0, 70.0, 20.0), &Color::BLACK);
+            assert_eq!(rc.culled(), 2);
+            Ok(())
+        })
+        .unwrap();
+        rc.fill(Rect::new(60.0, 10.0, 70.0, 20.0), &Color::BLACK);
+        assert_eq!(rc.culled(), 2);
+
+        // the stroke of a filled and stroked shape counts too.
+        let style = StrokeStyle::new();
+        let shape = Rect::new(105.0, 10.0, 120.0, 20.0);
+        rc.fill_and_stroke(shape, &Color::BLACK, &Color::WHITE, 20.0, &style);
+        assert_eq!(rc.culled(), 2);
+        let shape = Rect::new(115.0, 10.0, 130.0, 20.0);
+        rc.fill_and_stroke(shape, &Color::BLACK, &Color::WHITE, 2.0, &style);
+        assert_eq!(rc.culled(), 3);
+
+        // each of many shapes is culled on its own.
+        let shapes = [
+            Rect::new(10.0, 10.0, 20.0, 20.0),
+            Rect::new(110.0, 10.0, 120.0, 20.0),
+            Rect::new(10.0, 110.0, 20.0, 120.0),
+        ];
+        rc.fill_many(shapes, &Color::BLACK);
+        assert_eq!(rc.culled(), 5);
+        rc.stroke_many(shapes, &Color::BLACK, 20.0, &style);
+        assert_eq!(rc.culled(), 5);
+        rc.fill_many(&shapes[1..], &Color::BLACK);
+        assert_eq!(rc.culled(), 7);
+
+        // and so is each instance of a symbol.
+        let symbol = rc.prepare_shape(Rect::new(0.0, 0.0, 10.0, 10.0));
+        let instances = [
+            (Affine::translate((10.0, 10.0)), None),
+            (Affine::translate((110.0, 10.0)), Some(Color::WHITE)),
+            (Affine::scale(20.0), None),
+        ];
+        rc.draw_symbol_instances(Symbol::Shape(&symbol), &Color::BLACK, &instances);
+        assert_eq!(rc.culled(), 8);
+
+        // nothing is drawn outside of an opacity mask.
+        let mask = rc.make_image(1, 1, &[255], ImageFormat::Grayscale).unwrap();
+        rc.with_opacity_mask(&mask, Rect::new(0.0, 0.0, 50.0, 50.0), |rc| {
+            rc.fill(Rect::new(60.0, 10.0, 70.0, 20.0), &Color::BLACK);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(rc.culled(), 9);
+        rc.fill(Rect::new(60.0, 10.0, 70.0, 20.0), &Color::BLACK);
+        assert_eq!(rc.culled(), 9);
+        rc.finish().unwrap();
+    }
+
+    #[test]
+    fn short_segments_are_collapsed() {
+        let mut path = BezPath::new();
+        path.move_to((0.0, 0.0));
+        path.line_to((0.01, 0.0));
+        path.line_to((10.0, 0.0));
+        path.line_to((10.0, 0.01));
+        path.line_to((10.0, 10.0));
+        path.close_path();
+        let collapsed = collapse_degenerate(path.elements(), 0.05, false).unwrap();
+        assert_eq!(
+            collapsed.elements(),
+            [
+                PathEl::MoveTo((0.0, 0.0).into()),
+                PathEl::LineTo((10.0, 0.0).into()),
+                PathEl::LineTo((10.0, 10.0).into()),
+                PathEl::ClosePath,
+            ]
+        );
+
+        // a dot is kept for strokes, which draw its caps.
+        let mut dot = BezPath::new();
+        dot.move_to((5.0, 5.0));
+        dot.line_to((5.0, 5.0));
+        assert!(collapse_degenerate(dot.elements(), 0.05, true).is_none());
+        assert!(collapse_degenerate(
+            Rect::new(0.0, 0.0, 1.0, 1.0).to_path(0.1).elements(),
+            0.05,
+            false
+        )
+        .is_none());
+    }
+}
@@ -18,6 +18,10 @@ pub enum Error {
     StackUnbalance,
     /// The backend failed unexpectedly.
     BackendError(Box<dyn std::error::Error>),
+    /// The backend lost the device or context it draws with, such as when
+    /// the system reclaims it, so nothing can be drawn until it is restored,
+    /// and the resources made with it need to be made again.
+    BackendLost,
     /// A font could not be found.
     MissingFont,
     /// Font data could not be loaded.
@@ -39,6 +43,7 @@ impl fmt::Display for Error {
                 f,
                 "This functionality is not yet implemented for this backend"
             ),
+            Error::BackendLost => write!(f, "The device or context was lost"),
             Error::MissingFeature(feature) => write!(f, "Missing feature '{}'", feature),
             Error::BackendError(e) => {
                 write!(f, "Backend error: ")?;
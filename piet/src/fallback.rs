@@ -0,0 +1,152 @@
+//! Drawing with features that not every backend has, falling back to doing
+//! it in software where they are missing.
+//!
+//! Each function here checks the [`capabilities`] of the context, and uses
+//! the backend's own support if it has it. Otherwise the feature is drawn
+//! in software, as an image, or where that can't be done, drawn as closely
+//! as it can be without it. App code can call these on every backend,
+//! rather than checking the capabilities itself.
+//!
+//! [`capabilities`]: crate::RenderContext::capabilities
+
+use std::f64::consts::TAU;
+
+use kurbo::{Point, Rect, Shape, Vec2};
+
+use crate::{
+    util, BlendMode, Error, GradientStops, ImageBuf, ImageFilter, ImageFormat, InterpolationMode,
+    RenderContext,
+};
+
+/// The largest width and height of the images that are drawn in software,
+/// in pixels.
+const MAX_SOFTWARE_SIZE: usize = 4096;
+
+/// Draw with `f` with the blend `mode`, with the context state saved and
+/// then restored.
+///
+/// Blending needs the pixels that are already drawn, which piet can't read
+/// back from every backend, so backends without
+/// [`supports_blend_modes`](crate::Capabilities::supports_blend_modes)
+/// draw normally instead.
+pub fn with_blend_mode<R: RenderContext + ?Sized, T>(
+    rc: &mut R,
+    mode: BlendMode,
+    f: impl FnOnce(&mut R) -> Result<T, Error>,
+) -> Result<T, Error> {
+    let supported = rc.capabilities().supports_blend_modes;
+    rc.with_save(|rc| {
+        if supported {
+            rc.set_blend_mode(mode);
+        }
+        f(rc)
+    })
+}
+
+/// Fill a shape with a conic gradient, also known as a sweep gradient,
+/// whose color goes around `center`.
+///
+/// The color at a point is that of `stops` at the fraction of a turn from
+/// `start_angle`, in radians, to the angle of the point around `center`.
+/// Angles are measured from the positive x axis towards the positive y axis,
+/// as in [`kurbo`].
+///
+/// No backend draws conic gradients itself, so the gradient is drawn into
+/// an image at the resolution of the current transform, which is then drawn
+/// clipped to the shape. Backends that scale to pixels outside of the
+/// transform, for high-DPI displays, draw it a little softer than other
+/// brushes.
+pub fn fill_conic_gradient<R: RenderContext + ?Sized>(
+    rc: &mut R,
+    shape: impl Shape,
+    center: impl Into<Point>,
+    start_angle: f64,
+    stops: impl GradientStops,
+) -> Result<(), Error> {
+    let center = center.into();
+    let stops = stops.to_vec();
+    let bounds = shape.bounding_box();
+    let max_size = rc.capabilities().max_image_size.min(MAX_SOFTWARE_SIZE);
+    let scale = rc.current_transform().determinant().abs().sqrt();
+    // the image has at least a pixel per unit, and is never bigger than the
+    // largest size, even if that makes its pixels bigger.
+    let scale = scale
+        .max(1.0)
+        .min(max_size as f64 / bounds.width().max(bounds.height()));
+    if bounds.is_empty() || !scale.is_finite() || scale <= 0.0 {
+        return Ok(());
+    }
+    let width = ((bounds.width() * scale).ceil() as usize).clamp(1, max_size);
+    let height = ((bounds.height() * scale).ceil() as usize).clamp(1, max_size);
+
+    let mut data = vec![0u8; width * height * 4];
+    for (y, row) in data.chunks_exact_mut(width * 4).enumerate() {
+        for (x, px) in row.chunks_exact_mut(4).enumerate() {
+            let point = bounds.origin() + Vec2::new(x as f64 + 0.5, y as f64 + 0.5) / scale;
+            let angle = (point - center).atan2() - start_angle;
+            let t = angle.rem_euclid(TAU) / TAU;
+            let (r, g, b, a) = util::gradient_color(&stops, t as f32).as_rgba8();
+            px.copy_from_slice(&[
+                util::premul(r, a),
+                util::premul(g, a),
+                util::premul(b, a),
+                a,
+            ]);
+        }
+    }
+    let image = rc.make_image(width, height, &data, ImageFormat::RgbaPremul)?;
+    let dst_rect = Rect::from_origin_size(
+        bounds.origin(),
+        (width as f64 / scale, height as f64 / scale),
+    );
+    rc.with_save(|rc| {
+        rc.clip(shape);
+        rc.draw_image(&image, dst_rect, InterpolationMode::Bilinear);
+        Ok(())
+    })
+}
+
+/// Draw an image into `dst_rect`, through [`ImageFilter`]s, like
+/// [`RenderContext::draw_image_filtered`].
+///
+/// Backends with
+/// [`supports_blur`](crate::Capabilities::supports_blur) filter the image
+/// themselves. Otherwise the filters are applied in software, with
+/// [`util::filter_pixels`], at the resolution of the image, rather than
+/// being left out.
+pub fn draw_image_filtered<R: RenderContext + ?Sized>(
+    rc: &mut R,
+    image: &ImageBuf,
+    dst_rect: impl Into<Rect>,
+    filters: &[ImageFilter],
+) -> Result<(), Error> {
+    let dst_rect = dst_rect.into();
+    let (width, height) = (image.width(), image.height());
+    if rc.capabilities().supports_blur || filters.is_empty() {
+        let image = rc.make_image(width, height, image.raw_pixels(), image.format())?;
+        rc.draw_image_filtered(&image, dst_rect, filters);
+        return Ok(());
+    }
+    if width == 0 || height == 0 || dst_rect.is_empty() {
+        return Ok(());
+    }
+    let data = util::premultiplied_rgba(width * height, image.raw_pixels(), image.format())?;
+    let size = image.size();
+    let scale = Vec2::new(
+        size.width / dst_rect.width(),
+        size.height / dst_rect.height(),
+    );
+    let filtered = util::filter_pixels(width, height, &data, filters, scale);
+    let image = rc.make_image(
+        filtered.width,
+        filtered.height,
+        &filtered.data,
+        ImageFormat::RgbaPremul,
+    )?;
+    rc.draw_image(
+        &image,
+        filtered.dst_rect(size, dst_rect),
+        InterpolationMode::Bilinear,
+    );
+    Ok(())
+}
@@ -0,0 +1,121 @@
+//! Effects that images are drawn through.
+
+use kurbo::{Insets, Vec2};
+
+use crate::Color;
+
+/// How far blurs reach, in standard deviations, beyond which they are too
+/// faint to see.
+const BLUR_REACH: f64 = 3.0;
+
+/// An effect that is applied to an image when it is drawn with
+/// [`RenderContext::draw_image_filtered`].
+///
+/// Lengths are in the user space that the image is drawn in, like those of
+/// [`RenderContext::blurred_rect`].
+///
+/// [`RenderContext::draw_image_filtered`]: crate::RenderContext::draw_image_filtered
+/// [`RenderContext::blurred_rect`]: crate::RenderContext::blurred_rect
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum ImageFilter {
+    /// A Gaussian blur, whose radius is its standard deviation.
+    Blur {
+        /// The standard deviation of the blur.
+        radius: f64,
+    },
+    /// A blurred shadow of the image, in a color, that is drawn under it.
+    DropShadow {
+        /// How far the shadow is moved from the image.
+        offset: Vec2,
+        /// The standard deviation of the blur of the shadow.
+        radius: f64,
+        /// The color of the shadow, which is multiplied by the alpha of
+        /// the image.
+        color: Color,
+    },
+    /// A matrix that each color is multiplied by, like SVG's
+    /// `feColorMatrix`.
+    ///
+    /// The matrix has four rows, of the red, green, blue and alpha of the
+    /// result, and five columns: one for each of the red, green, blue and
+    /// alpha of the image, which is not premultiplied, and one that is
+    /// added. Channels are from `0.0` to `1.0`.
+    ColorMatrix([f64; 20]),
+}
+
+impl ImageFilter {
+    /// A Gaussian blur with the standard deviation `radius`.
+    pub fn blur(radius: f64) -> ImageFilter {
+        ImageFilter::Blur { radius }
+    }
+
+    /// A shadow of the image that is moved by `offset`, blurred with the
+    /// standard deviation `radius`, and drawn under it in `color`.
+    pub fn drop_shadow(offset: impl Into<Vec2>, radius: f64, color: Color) -> ImageFilter {
+        ImageFilter::DropShadow {
+            offset: offset.into(),
+            radius,
+            color,
+        }
+    }
+
+    /// A color matrix that changes the saturation of the image, from `0.0`,
+    /// which is gray, through `1.0`, which leaves it as it is.
+    ///
+    /// This is the matrix of SVG's `saturate`.
+    pub fn saturate(amount: f64) -> ImageFilter {
+        let s = amount;
+        #[rustfmt::skip]
+        let matrix = [
+            0.213 + 0.787 * s, 0.715 - 0.715 * s, 0.072 - 0.072 * s, 0.0, 0.0,
+            0.213 - 0.213 * s, 0.715 + 0.285 * s, 0.072 - 0.072 * s, 0.0, 0.0,
+            0.213 - 0.213 * s, 0.715 - 0.715 * s, 0.072 + 0.928 * s, 0.0, 0.0,
+            0.0, 0.0, 0.0, 1.0, 0.0,
+        ];
+        ImageFilter::ColorMatrix(matrix)
+    }
+
+    /// A color matrix that multiplies the alpha of the image by `opacity`.
+    pub fn opacity(opacity: f64) -> ImageFilter {
+        let mut matrix = [0.0; 20];
+        for (i, value) in [1.0, 1.0, 1.0, opacity].iter().enumerate() {
+            matrix[i * 6] = *value;
+        }
+        ImageFilter::ColorMatrix(matrix)
+    }
+
+    /// How far beyond the image this filter draws, on each side.
+    pub fn outset(&self) -> Insets {
+        match *self {
+            ImageFilter::Blur { radius } => Insets::uniform(BLUR_REACH * radius.abs()),
+            ImageFilter::DropShadow { offset, radius, .. } => {
+                let reach = BLUR_REACH * radius.abs();
+                Insets::new(
+                    (reach - offset.x).max(0.0),
+                    (reach - offset.y).max(0.0),
+                    (reach + offset.x).max(0.0),
+                    (reach + offset.y).max(0.0),
+                )
+            }
+            ImageFilter::ColorMatrix(_) => Insets::ZERO,
+        }
+    }
+
+    /// How far beyond the image `filters`, applied in order, draw, on each
+    /// side.
+    pub fn total_outset(filters: &[ImageFilter]) -> Insets {
+        filters
+            .iter()
+            .map(ImageFilter::outset)
+            .fold(Insets::ZERO, |total, outset| {
+                Insets::new(
+                    total.x0 + outset.x0,
+                    total.y0 + outset.y0,
+                    total.x1 + outset.x1,
+                    total.y1 + outset.y1,
+                )
+            })
+    }
+}
@@ -55,6 +55,124 @@ pub enum FontStyle {
     Italic,
 }
 
+/// A value for one axis of a variable font, such as its weight or width.
+///
+/// Axes are identified by their four-byte OpenType tag; the registered axes
+/// have constructors on this type, such as [`FontVariation::weight`].
+///
+/// Backends that cannot set axis values directly will approximate the
+/// registered axes with the closest available font properties.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FontVariation {
+    /// The OpenType tag of the axis, such as `*b"wght"`.
+    pub tag: [u8; 4],
+    /// The value of the axis, in the axis's own units.
+    pub value: f64,
+}
+
+impl FontVariation {
+    /// The tag of the weight axis.
+    pub const WEIGHT: [u8; 4] = *b"wght";
+    /// The tag of the width axis.
+    pub const WIDTH: [u8; 4] = *b"wdth";
+    /// The tag of the slant axis.
+    pub const SLANT: [u8; 4] = *b"slnt";
+    /// The tag of the italic axis.
+    pub const ITALIC: [u8; 4] = *b"ital";
+    /// The tag of the optical size axis.
+    pub const OPTICAL_SIZE: [u8; 4] = *b"opsz";
+
+    /// Create a new variation for an arbitrary axis.
+    pub const fn new(tag: [u8; 4], value: f64) -> FontVariation {
+        FontVariation { tag, value }
+    }
+
+    /// Set the weight axis; the value is on the same scale as [`FontWeight`].
+    pub const fn weight(value: f64) -> FontVariation {
+        FontVariation::new(FontVariation::WEIGHT, value)
+    }
+
+    /// Set the width axis, as a percentage of the normal width.
+    pub const fn width(value: f64) -> FontVariation {
+        FontVariation::new(FontVariation::WIDTH, value)
+    }
+
+    /// Set the slant axis, in degrees; negative values slant to the right.
+    pub const fn slant(value: f64) -> FontVariation {
+        FontVariation::new(FontVariation::SLANT, value)
+    }
+
+    /// The tag of this axis as a big-endian integer, which is how it appears
+    /// in font tables.
+    pub fn tag_u32(&self) -> u32 {
+        u32::from_be_bytes(self.tag)
+    }
+
+    /// The tag of this axis as a string, if it is valid ASCII.
+    pub fn tag_str(&self) -> Option<&str> {
+        std::str::from_utf8(&self.tag).ok().filter(|s| s.is_ascii())
+    }
+
+    /// Find the value set for `tag` in a list of variations.
+    ///
+    /// If an axis is set more than once, the last value wins.
+    pub fn find(variations: &[FontVariation], tag: [u8; 4]) -> Option<f64> {
+        variations
+            .iter()
+            .rev()
+            .find(|var| var.tag == tag)
+            .map(|var| var.value)
+    }
+}
+
+/// An OpenType feature setting, such as tabular numbers or small caps.
+///
+/// Features are identified by their four-byte OpenType tag. For most features
+/// the value is `1` to turn the feature on and `0` to turn it off; features
+/// with alternates, such as `salt`, use the value to select one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FontFeature {
+    /// The OpenType tag of the feature, such as `*b"tnum"`.
+    pub tag: [u8; 4],
+    /// The value of the feature.
+    pub value: u32,
+}
+
+impl FontFeature {
+    /// Tabular (fixed-width) numbers.
+    pub const TABULAR_NUMBERS: FontFeature = FontFeature::on(*b"tnum");
+    /// Small capitals.
+    pub const SMALL_CAPS: FontFeature = FontFeature::on(*b"smcp");
+    /// Turns off standard ligatures.
+    pub const NO_LIGATURES: FontFeature = FontFeature::off(*b"liga");
+
+    /// Create a new feature setting.
+    pub const fn new(tag: [u8; 4], value: u32) -> FontFeature {
+        FontFeature { tag, value }
+    }
+
+    /// Turn on the feature with the given tag.
+    pub const fn on(tag: [u8; 4]) -> FontFeature {
+        FontFeature::new(tag, 1)
+    }
+
+    /// Turn off the feature with the given tag.
+    pub const fn off(tag: [u8; 4]) -> FontFeature {
+        FontFeature::new(tag, 0)
+    }
+
+    /// The tag of this feature as a big-endian integer, which is how it appears
+    /// in font tables.
+    pub fn tag_u32(&self) -> u32 {
+        u32::from_be_bytes(self.tag)
+    }
+
+    /// The tag of this feature as a string, if it is valid ASCII.
+    pub fn tag_str(&self) -> Option<&str> {
+        std::str::from_utf8(&self.tag).ok().filter(|s| s.is_ascii())
+    }
+}
+
 impl FontFamily {
     /// A san-serif font, such as Arial or Helvetica.
     pub const SANS_SERIF: FontFamily = FontFamily(FontFamilyInner::SansSerif);
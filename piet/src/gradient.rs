@@ -40,7 +40,8 @@ use crate::Color;
 /// of the item being drawn; for these, use [`LinearGradient`] instead.
 ///
 /// [`LinearGradient`]: struct.LinearGradient.html
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FixedLinearGradient {
     /// The start point (corresponding to pos 0.0).
     pub start: Point,
@@ -59,7 +60,8 @@ pub struct FixedLinearGradient {
 /// of the item being drawn; for these, use [`RadialGradient`] instead.
 ///
 /// [`RadialGradient`]: struct.RadialGradient.html
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FixedRadialGradient {
     /// The center.
     pub center: Point,
@@ -82,7 +84,8 @@ pub struct FixedRadialGradient {
 ///
 /// [`FixedLinearGradient`]: struct.FixedLinearGradient.html
 /// [`FixedRadialGradient`]: struct.FixedRadialGradient.html
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FixedGradient {
     /// A linear gradient.
     Linear(FixedLinearGradient),
@@ -90,8 +93,60 @@ pub enum FixedGradient {
     Radial(FixedRadialGradient),
 }
 
+/// Options for how a gradient is drawn, beyond its geometry and stops.
+///
+/// These are given to [`RenderContext::gradient_with_options`] for fixed
+/// gradients, and to the `with_options` methods of [`LinearGradient`] and
+/// [`RadialGradient`]. Backends that can't dither ignore `dither`.
+///
+/// ```
+/// use piet::{GradientInterpolation, GradientOptions};
+///
+/// let options = GradientOptions {
+///     dither: true,
+///     interpolation: GradientInterpolation::Oklab,
+/// };
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GradientOptions {
+    /// Whether the colors are dithered when they are rounded to the pixels
+    /// of the target.
+    ///
+    /// Gradients that change slowly over a large area, such as dark
+    /// backgrounds, show bands where 8 bits per channel round many pixels
+    /// to the same color. Dithering mixes the colors on either side of each
+    /// band in a fine pattern, which hides them. This is honored by the
+    /// backends that draw into pixels, and ignored by those that write
+    /// documents or hand gradients to the platform.
+    pub dither: bool,
+    /// The space in which the colors between stops are mixed.
+    pub interpolation: GradientInterpolation,
+}
+
+/// The color space in which a gradient mixes the colors of its stops.
+///
+/// Mixing in sRGB, as backends do by default, makes gradients between
+/// saturated colors, such as red and green, darker and grayer in the
+/// middle than either end. The other spaces keep them bright. Backends
+/// that can't mix in a space themselves approximate it with extra stops,
+/// with [`util::interpolated_gradient`](crate::util::interpolated_gradient).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum GradientInterpolation {
+    /// Mix the sRGB values of the colors.
+    #[default]
+    Srgb,
+    /// Mix the colors in linear light, as light is mixed physically.
+    LinearRgb,
+    /// Mix the colors in the OKLab space, so that the lightness changes
+    /// evenly as it is perceived.
+    Oklab,
+}
+
 /// Specification of a gradient stop.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct GradientStop {
     /// The coordinate of the stop.
     pub pos: f32,
@@ -124,6 +179,7 @@ pub struct LinearGradient {
     start: UnitPoint,
     end: UnitPoint,
     stops: Vec<GradientStop>,
+    options: GradientOptions,
 }
 
 /// A description of a radial gradient in the unit rect, which can be resolved
@@ -160,6 +216,7 @@ pub struct RadialGradient {
     radius: f64,
     stops: Vec<GradientStop>,
     scale_mode: ScaleMode,
+    options: GradientOptions,
 }
 
 /// Mappings from the unit square into a non-square rectangle.
@@ -315,9 +372,17 @@ impl LinearGradient {
             start,
             end,
             stops: stops.to_vec(),
+            options: GradientOptions::default(),
         }
     }
 
+    /// A builder-style method for changing the [`GradientOptions`] that the
+    /// gradient is drawn with.
+    pub fn with_options(mut self, options: GradientOptions) -> Self {
+        self.options = options;
+        self
+    }
+
     // maybe these should be public API? that was my original intention but I'm not
     // sure there's a clear use, so keeping them private for now.
     /// Generate a [`FixedLinearGradient`] by mapping points in the unit square
@@ -350,6 +415,7 @@ impl RadialGradient {
             radius,
             stops: stops.to_vec(),
             scale_mode: ScaleMode::Fill,
+            options: GradientOptions::default(),
         }
     }
 
@@ -383,6 +449,13 @@ impl RadialGradient {
         self
     }
 
+    /// A builder-style method for changing the [`GradientOptions`] that the
+    /// gradient is drawn with.
+    pub fn with_options(mut self, options: GradientOptions) -> Self {
+        self.options = options;
+        self
+    }
+
     /// Generate a [`FixedRadialGradient`] by mapping points in the unit square
     /// onto points in `rect`.
     ///
@@ -434,7 +507,10 @@ impl<P: RenderContext> IntoBrush<P> for LinearGradient {
         let rect = bbox();
         let gradient = self.resolve(rect);
         // Perhaps the make_brush method should be fallible instead of panicking.
-        Cow::Owned(piet.gradient(gradient).expect("error creating gradient"))
+        Cow::Owned(
+            piet.gradient_with_options(gradient, self.options)
+                .expect("error creating gradient"),
+        )
     }
 }
 
@@ -443,7 +519,10 @@ impl<P: RenderContext> IntoBrush<P> for RadialGradient {
         let rect = bbox();
         let gradient = self.resolve(rect);
         // Perhaps the make_brush method should be fallible instead of panicking.
-        Cow::Owned(piet.gradient(gradient).expect("error creating gradient"))
+        Cow::Owned(
+            piet.gradient_with_options(gradient, self.options)
+                .expect("error creating gradient"),
+        )
     }
 }
 
@@ -0,0 +1,201 @@
+//! Hit regions, for answering pointer queries about what was drawn.
+
+use std::sync::Arc;
+
+use kurbo::{Affine, BezPath, Point, Rect, Shape};
+
+use crate::util::{self, StrokeGeometry};
+use crate::{LineCap, LineJoin, StrokeStyle};
+
+/// The tolerance used to flatten shapes into paths.
+///
+/// This is in the units of the shape, so it is small enough for the
+/// scales that shapes are usually drawn at.
+const TOLERANCE: f64 = 1e-3;
+
+/// Shapes recorded with ids, for finding which of them is under a point.
+///
+/// Regions are usually added with [`RenderContext::hit_region`] while
+/// drawing, and queried with [`test`](#method.test) afterwards, such as when
+/// handling pointer events. They can also be added directly, with
+/// [`add`](#method.add).
+///
+/// A region covers either the inside of its shape, by the non-zero winding
+/// rule, or the area of its stroke, as they are drawn; see
+/// [`util::fill_contains`] and [`util::stroke_contains`]. Clipping isn't
+/// taken into account.
+///
+/// [`RenderContext::hit_region`]: crate::RenderContext::hit_region
+#[derive(Clone, Debug, Default)]
+pub struct HitTester {
+    regions: Vec<Region>,
+}
+
+#[derive(Clone, Debug)]
+struct Region {
+    id: u64,
+    path: BezPath,
+    transform: Affine,
+    /// The bounds of the region, after it is transformed.
+    bounds: Rect,
+    /// The stroke, if the region is the stroke of the shape.
+    stroke: Option<Stroke>,
+}
+
+/// A [`StrokeStyle`], which unlike that can be sent between threads.
+#[derive(Clone, Debug)]
+struct Stroke {
+    width: f64,
+    line_join: LineJoin,
+    line_cap: LineCap,
+    dash_cap: LineCap,
+    dash_pattern: Arc<[f64]>,
+    dash_offset: f64,
+    scale: bool,
+}
+
+impl HitTester {
+    /// Create a new, empty hit tester.
+    pub fn new() -> HitTester {
+        HitTester::default()
+    }
+
+    /// Remove all regions, such as before drawing a new frame.
+    pub fn clear(&mut self) {
+        self.regions.clear();
+    }
+
+    /// Returns `true` if no regions have been added.
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    /// Add a region covering `shape`, filled with `transform`.
+    ///
+    /// Regions added later are on top of those added earlier. Several
+    /// regions may have the same id.
+    pub fn add(&mut self, id: u64, shape: impl Shape, transform: Affine) {
+        // a shape drawn with a degenerate transform covers no area.
+        if transform.determinant() == 0.0 {
+            return;
+        }
+        let path = shape.into_path(TOLERANCE);
+        let bounds = transform.transform_rect_bbox(path.bounding_box());
+        self.regions.push(Region {
+            id,
+            path,
+            transform,
+            bounds,
+            stroke: None,
+        });
+    }
+
+    /// Add a region covering the stroke of `shape`, drawn with `transform`.
+    pub fn add_stroke(
+        &mut self,
+        id: u64,
+        shape: impl Shape,
+        transform: Affine,
+        width: f64,
+        style: &StrokeStyle,
+    ) {
+        let path = shape.into_path(TOLERANCE);
+        let bounds = StrokeGeometry::new(width, style).bounds(path.bounding_box(), transform);
+        self.regions.push(Region {
+            id,
+            path,
+            transform,
+            bounds,
+            stroke: Some(Stroke {
+                width,
+                line_join: style.line_join,
+                line_cap: style.line_cap,
+                dash_cap: style.effective_dash_cap(),
+                dash_pattern: style.dash_pattern.iter().copied().collect(),
+                dash_offset: style.dash_offset,
+                scale: style.scale,
+            }),
+        });
+    }
+
+    /// The id of the topmost region containing `point`.
+    ///
+    /// The point is in the space that the regions' transforms map to, which
+    /// for regions added by a render context is its space before any
+    /// transform was applied.
+    pub fn test(&self, point: Point) -> Option<u64> {
+        self.regions
+            .iter()
+            .rev()
+            .find(|region| region.contains(point))
+            .map(|region| region.id)
+    }
+}
+
+impl Region {
+    fn contains(&self, point: Point) -> bool {
+        // `Rect::contains` excludes the far edges, which the path includes.
+        let bounds = self.bounds;
+        if point.x < bounds.x0 || point.x > bounds.x1 || point.y < bounds.y0 || point.y > bounds.y1
+        {
+            return false;
+        }
+        match &self.stroke {
+            None => util::fill_contains(&self.path, self.transform, point),
+            Some(stroke) => StrokeGeometry {
+                width: stroke.width,
+                line_join: stroke.line_join,
+                line_cap: stroke.line_cap,
+                dash_cap: stroke.dash_cap,
+                dash_pattern: &stroke.dash_pattern,
+                dash_offset: stroke.dash_offset,
+                scale: stroke.scale,
+            }
+            .contains(&self.path, self.transform, point),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kurbo::Circle;
+
+    #[test]
+    fn topmost_region_is_hit() {
+        let mut tester = HitTester::new();
+        tester.add(1, Rect::new(0.0, 0.0, 100.0, 100.0), Affine::IDENTITY);
+        tester.add(2, Circle::new((50.0, 50.0), 10.0), Affine::IDENTITY);
+        assert_eq!(tester.test(Point::new(50.0, 50.0)), Some(2));
+        assert_eq!(tester.test(Point::new(10.0, 10.0)), Some(1));
+        assert_eq!(tester.test(Point::new(150.0, 10.0)), None);
+    }
+
+    #[test]
+    fn regions_are_transformed() {
+        let mut tester = HitTester::new();
+        let transform = Affine::translate((100.0, 0.0)) * Affine::scale(2.0);
+        tester.add(1, Rect::new(0.0, 0.0, 10.0, 10.0), transform);
+        tester.add(2, Rect::new(0.0, 0.0, 10.0, 10.0), Affine::scale(0.0));
+        assert_eq!(tester.test(Point::new(5.0, 5.0)), None);
+        assert_eq!(tester.test(Point::new(115.0, 15.0)), Some(1));
+        assert_eq!(tester.test(Point::new(125.0, 15.0)), None);
+        assert_eq!(tester.test(Point::new(0.0, 0.0)), None);
+
+        tester.clear();
+        assert!(tester.is_empty());
+    }
+
+    #[test]
+    fn strokes_are_hit() {
+        let mut tester = HitTester::new();
+        let rect = Rect::new(0.0, 0.0, 100.0, 100.0);
+        tester.add(1, rect, Affine::IDENTITY);
+        let style = StrokeStyle::new().dash_pattern(&[50.0, 50.0]);
+        tester.add_stroke(2, rect, Affine::IDENTITY, 10.0, &style);
+        assert_eq!(tester.test(Point::new(25.0, 2.0)), Some(2));
+        assert_eq!(tester.test(Point::new(75.0, 2.0)), Some(1));
+        assert_eq!(tester.test(Point::new(75.0, -2.0)), None);
+        assert_eq!(tester.test(Point::new(50.0, 50.0)), Some(1));
+    }
+}
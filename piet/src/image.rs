@@ -20,7 +20,7 @@ use std::error::Error;
 use std::path::Path;
 use std::sync::Arc;
 
-use crate::kurbo::Size;
+use crate::kurbo::{Rect, Size};
 use crate::util::unpremul;
 use crate::{Color, ImageFormat, RenderContext};
 
@@ -30,6 +30,64 @@ use crate::{Color, ImageFormat, RenderContext};
 pub trait Image: Clone {
     /// The size of the image
     fn size(&self) -> Size;
+
+    /// An image of the `rect` area of this one, which shares its pixels.
+    ///
+    /// `rect` is in the image's pixels. It is rounded to whole pixels, and
+    /// clipped to the image. The sub-image is drawn, used as a mask and read
+    /// back as if it were an image of just that area, so that sprites can
+    /// be cut from a sheet once and passed around, rather than passing the
+    /// sheet and the area of each sprite to every call that draws one.
+    ///
+    /// The pixels around the area may still be blended into its edges when
+    /// it is scaled with [`InterpolationMode::Bilinear`] or smoother, as with
+    /// [`RenderContext::draw_image_area`]; sheets usually leave a gap
+    /// between their sprites for this. The pixels are shared as they are
+    /// between clones, so whether [`update_region`] on one is seen by the
+    /// other depends on the backend.
+    ///
+    /// [`update_region`]: Image::update_region
+    /// [`InterpolationMode::Bilinear`]: crate::InterpolationMode::Bilinear
+    fn sub_image(&self, rect: impl Into<Rect>) -> Self;
+
+    /// Replace the pixels of an area of the image, such as with the next
+    /// frame of a video.
+    ///
+    /// The area is `width` by `height` pixels, with its top left corner at
+    /// `(x, y)`, and must be inside the image. `buf` holds its pixels in
+    /// `format`, as for [`RenderContext::make_image`]. This is usually much
+    /// cheaper than making a new image.
+    ///
+    /// Images are shared between their clones, so whether the clones see the
+    /// new pixels depends on the backend. Backends that can't update images
+    /// return [`Error::NotSupported`], which is the default.
+    ///
+    /// [`Error::NotSupported`]: crate::Error::NotSupported
+    fn update_region(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        buf: &[u8],
+        format: ImageFormat,
+    ) -> Result<(), crate::Error> {
+        let _ = (x, y, width, height, buf, format);
+        Err(crate::Error::NotSupported)
+    }
+
+    /// Read the pixels of the image back, as [`ImageFormat::RgbaSeparate`],
+    /// with rows going down and no padding between them.
+    ///
+    /// This lets apps save or export an image without code for each
+    /// backend. Reading pixels back can be slow, such as from the GPU, so
+    /// this isn't meant to be done every frame. Backends that can't read
+    /// images back return [`Error::Unimplemented`], which is the default.
+    ///
+    /// [`Error::Unimplemented`]: crate::Error::Unimplemented
+    fn to_rgba_bytes(&self) -> Result<Vec<u8>, crate::Error> {
+        Err(crate::Error::Unimplemented)
+    }
 }
 
 /// An in-memory pixel buffer.
@@ -129,6 +187,16 @@ impl ImageBuf {
                             let a = p[3];
                             Color::rgba8(unpremul(p[0], a), unpremul(p[1], a), unpremul(p[2], a), a)
                         }
+                        ImageFormat::BgraSeparate => Color::rgba8(p[2], p[1], p[0], p[3]),
+                        ImageFormat::BgraPremul => {
+                            let a = p[3];
+                            Color::rgba8(unpremul(p[2], a), unpremul(p[1], a), unpremul(p[0], a), a)
+                        }
+                        ImageFormat::Rgba16 => {
+                            let c =
+                                |i: usize| u16::from_ne_bytes([p[i], p[i + 1]]) as f64 / 65535.0;
+                            Color::rgba(c(0), c(2), c(4), c(6))
+                        }
                     })
             })
     }
@@ -147,6 +215,51 @@ impl ImageBuf {
     }
 }
 
+/// An image that can be shared between threads, and drawn with any backend.
+///
+/// Backends' [`Image`] types are often tied to the thread or the device they
+/// were made on, so they can't be made on a worker thread. A `SharedImage`
+/// only holds the pixels, which are shared between its clones, so it can be
+/// decoded on one thread and turned into an [`Image`] with
+/// [`RenderContext::make_shared_image`] on the thread that draws it.
+#[derive(Clone, Debug, Default)]
+pub struct SharedImage(ImageBuf);
+
+impl SharedImage {
+    /// Create a new `SharedImage` holding the pixels of `buf`.
+    pub fn new(buf: ImageBuf) -> SharedImage {
+        SharedImage(buf)
+    }
+
+    /// The pixels of the image.
+    pub fn image_buf(&self) -> &ImageBuf {
+        &self.0
+    }
+
+    /// The size of this image, in pixels.
+    pub fn size(&self) -> Size {
+        self.0.size()
+    }
+
+    /// Make an image that can be drawn into `ctx`.
+    ///
+    /// This is the same as [`RenderContext::make_shared_image`].
+    pub fn to_image<Ctx: RenderContext>(&self, ctx: &mut Ctx) -> Result<Ctx::Image, crate::Error> {
+        ctx.make_shared_image(self)
+    }
+
+    /// Returns `true` if the two images share their pixels.
+    pub fn ptr_eq(&self, other: &SharedImage) -> bool {
+        self.0.ptr_eq(&other.0)
+    }
+}
+
+impl From<ImageBuf> for SharedImage {
+    fn from(buf: ImageBuf) -> SharedImage {
+        SharedImage(buf)
+    }
+}
+
 impl Default for ImageBuf {
     fn default() -> Self {
         ImageBuf::empty()
@@ -26,30 +26,51 @@
 
 pub use kurbo;
 
+pub mod fallback;
 /// utilities shared by various backends
 pub mod util;
 
+mod capabilities;
 mod color;
 mod conv;
+mod culling;
 mod error;
+mod filter;
 mod font;
 mod gradient;
+mod hit;
 mod image;
+mod marker;
+mod mip_image;
 mod null_renderer;
+mod prepared_shape;
 mod render_context;
 mod shapes;
+mod squircle;
+mod symbol;
 mod text;
+mod transform_stack;
 
 #[cfg(feature = "samples")]
 pub mod samples;
 
+pub use crate::capabilities::*;
 pub use crate::color::*;
 pub use crate::conv::*;
+pub use crate::culling::*;
 pub use crate::error::*;
+pub use crate::filter::*;
 pub use crate::font::*;
 pub use crate::gradient::*;
+pub use crate::hit::*;
 pub use crate::image::*;
+pub use crate::marker::*;
+pub use crate::mip_image::*;
 pub use crate::null_renderer::*;
+pub use crate::prepared_shape::*;
 pub use crate::render_context::*;
 pub use crate::shapes::*;
+pub use crate::squircle::*;
+pub use crate::symbol::*;
 pub use crate::text::*;
+pub use crate::transform_stack::*;
@@ -0,0 +1,234 @@
+//! Markers, such as arrowheads, at the ends of strokes.
+
+use kurbo::{
+    Affine, BezPath, Circle, ParamCurve, ParamCurveArclen, PathEl, PathSeg, Point, Shape, Vec2,
+};
+
+/// The accuracy with which paths are measured when they are shortened.
+const ACCURACY: f64 = 1e-3;
+
+/// A shape drawn at an end of a stroke, such as an arrowhead.
+///
+/// Markers are described in a space where the end of the path is at the
+/// origin, the path leaves it along the positive x axis, and one unit is the
+/// width of the stroke; an arrowhead points along the positive x axis, and
+/// grows with the stroke.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Marker {
+    /// A triangular arrowhead, whose tip is at the end of the path.
+    ///
+    /// The path is shortened by `length`, so that the stroke ends at the
+    /// base of the arrowhead instead of poking through its tip.
+    Arrow {
+        /// The distance from the base of the arrowhead to its tip.
+        length: f64,
+        /// The width of the base of the arrowhead.
+        width: f64,
+    },
+    /// A circle centered on the end of the path.
+    Circle {
+        /// The radius of the circle.
+        radius: f64,
+    },
+    /// Any shape.
+    Custom {
+        /// The outline of the marker, which is filled.
+        path: BezPath,
+        /// How much the path is shortened, so that it ends at
+        /// `(-setback, 0)`.
+        setback: f64,
+    },
+}
+
+/// The markers at the start and end of a stroke, for
+/// [`RenderContext::stroke_with_markers`].
+///
+/// The start marker is drawn at the start of the path if its first subpath
+/// is open, and the end marker at the end of the path if its last subpath is
+/// open.
+///
+/// [`RenderContext::stroke_with_markers`]: crate::RenderContext::stroke_with_markers
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Markers {
+    /// The marker at the start of the path.
+    pub start: Option<Marker>,
+    /// The marker at the end of the path.
+    pub end: Option<Marker>,
+}
+
+/// Where [`Markers`] are drawn on a path, as found by [`Markers::layout`].
+#[derive(Clone, Debug)]
+pub struct MarkerLayout<'a> {
+    /// The path, shortened at the ends that have markers.
+    pub path: BezPath,
+    /// The start marker, if it is drawn, and the transform from its space to
+    /// the space of the path.
+    pub start: Option<(&'a Marker, Affine)>,
+    /// The end marker, if it is drawn, and the transform from its space to
+    /// the space of the path.
+    pub end: Option<(&'a Marker, Affine)>,
+}
+
+impl Marker {
+    /// An arrowhead three times as long and as wide as the stroke.
+    pub const ARROW: Marker = Marker::Arrow {
+        length: 3.0,
+        width: 3.0,
+    };
+
+    /// The outline of the marker, with curves flattened to within
+    /// `tolerance`.
+    pub fn to_path(&self, tolerance: f64) -> BezPath {
+        match self {
+            Marker::Arrow { length, width } => {
+                let mut path = BezPath::new();
+                path.move_to(Point::ZERO);
+                path.line_to((-length, width / 2.0));
+                path.line_to((-length, -width / 2.0));
+                path.close_path();
+                path
+            }
+            Marker::Circle { radius } => Circle::new(Point::ZERO, *radius).into_path(tolerance),
+            Marker::Custom { path, .. } => path.clone(),
+        }
+    }
+
+    /// How much the path is shortened by the marker.
+    pub fn setback(&self) -> f64 {
+        match self {
+            Marker::Arrow { length, .. } => *length,
+            Marker::Circle { .. } => 0.0,
+            Marker::Custom { setback, .. } => *setback,
+        }
+    }
+}
+
+impl Markers {
+    /// Create a new `Markers`, without any markers.
+    pub fn new() -> Markers {
+        Markers::default()
+    }
+
+    /// Builder-style method to set the marker at the start of the path.
+    pub fn start(mut self, marker: Marker) -> Self {
+        self.start = Some(marker);
+        self
+    }
+
+    /// Builder-style method to set the marker at the end of the path.
+    pub fn end(mut self, marker: Marker) -> Self {
+        self.end = Some(marker);
+        self
+    }
+
+    /// Find where the markers are drawn on `path`, stroked with `width`,
+    /// and shorten it for them.
+    ///
+    /// The markers are placed at the shortened ends, moved back along the
+    /// direction the path leaves in by their setback.
+    pub fn layout(&self, path: &BezPath, width: f64) -> MarkerLayout<'_> {
+        let mut path = path.clone();
+        let elements = path.elements();
+        let is_move = |el: &PathEl| matches!(el, PathEl::MoveTo(_));
+        // the first and last segments of the path, if they are at open ends.
+        let first = elements.iter().position(|el| !is_move(el)).filter(|&i| {
+            !elements[i..]
+                .iter()
+                .take_while(|el| !is_move(el))
+                .any(|el| *el == PathEl::ClosePath)
+        });
+        let last = elements
+            .len()
+            .checked_sub(1)
+            .filter(|&i| !matches!(elements[i], PathEl::MoveTo(_) | PathEl::ClosePath));
+
+        let start = self.start.as_ref().zip(first).and_then(|(marker, i)| {
+            let seg = path.get_seg(i)?;
+            let setback = marker.setback() * width;
+            let seg = seg.subsegment(param_at(seg, setback)..1.0);
+            path.elements_mut()[i - 1] = PathEl::MoveTo(seg.start());
+            path.elements_mut()[i] = seg.as_path_el();
+            let direction = -leaving(seg);
+            Some((marker, place(seg.start(), direction, setback, width)))
+        });
+        let end = self.end.as_ref().zip(last).and_then(|(marker, i)| {
+            let seg = path.get_seg(i)?;
+            let setback = marker.setback() * width;
+            let length = seg.arclen(ACCURACY);
+            let seg = seg.subsegment(0.0..param_at(seg, length - setback));
+            path.elements_mut()[i] = seg.as_path_el();
+            let direction = -leaving(seg.reverse());
+            Some((marker, place(seg.end(), direction, setback, width)))
+        });
+        MarkerLayout { path, start, end }
+    }
+}
+
+/// The parameter of the point `distance` along `seg`, clamped to its ends.
+fn param_at(seg: PathSeg, distance: f64) -> f64 {
+    if distance <= 0.0 {
+        0.0
+    } else if distance >= seg.arclen(ACCURACY) {
+        1.0
+    } else {
+        seg.inv_arclen(distance, ACCURACY)
+    }
+}
+
+/// The direction in which `seg` leaves its start.
+fn leaving(seg: PathSeg) -> Vec2 {
+    let points = match seg {
+        PathSeg::Line(l) => vec![l.p0, l.p1],
+        PathSeg::Quad(q) => vec![q.p0, q.p1, q.p2],
+        PathSeg::Cubic(c) => vec![c.p0, c.p1, c.p2, c.p3],
+    };
+    // a control point may coincide with the start, in which case the next
+    // one gives the direction.
+    points[1..]
+        .iter()
+        .map(|p| *p - points[0])
+        .find(|v| v.hypot2() > 0.0)
+        .map_or(Vec2::new(1.0, 0.0), Vec2::normalize)
+}
+
+/// The transform from a marker's space, for a marker at `setback` from
+/// `end` in `direction`.
+fn place(end: Point, direction: Vec2, setback: f64, width: f64) -> Affine {
+    let origin = end + direction * setback;
+    Affine::translate(origin.to_vec2()) * Affine::rotate(direction.atan2()) * Affine::scale(width)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kurbo::Line;
+
+    #[test]
+    fn arrows_shorten_the_path() {
+        let line = Line::new((0.0, 0.0), (100.0, 0.0)).into_path(ACCURACY);
+        let markers = Markers::new()
+            .start(Marker::Circle { radius: 2.0 })
+            .end(Marker::ARROW);
+        let layout = markers.layout(&line, 2.0);
+        assert_eq!(
+            layout.path.elements(),
+            &[
+                PathEl::MoveTo(Point::new(0.0, 0.0)),
+                PathEl::LineTo(Point::new(94.0, 0.0))
+            ]
+        );
+
+        // the start marker's x axis points back along the line.
+        let (_, start) = layout.start.unwrap();
+        assert!((start * Point::new(1.0, 0.0) - Point::new(-2.0, 0.0)).hypot() < 1e-9);
+        let (_, end) = layout.end.unwrap();
+        assert!((end * Point::ZERO - Point::new(100.0, 0.0)).hypot() < 1e-9);
+        assert!((end * Point::new(-3.0, 1.5) - Point::new(94.0, 3.0)).hypot() < 1e-9);
+
+        // closed paths have no ends.
+        let rect = kurbo::Rect::new(0.0, 0.0, 10.0, 10.0).into_path(ACCURACY);
+        let layout = markers.layout(&rect, 2.0);
+        assert!(layout.start.is_none() && layout.end.is_none());
+        assert_eq!(layout.path, rect);
+    }
+}
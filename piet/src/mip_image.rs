@@ -0,0 +1,108 @@
+//! Images with smaller copies of themselves, for drawing them scaled down.
+
+use kurbo::Size;
+
+use crate::Image;
+
+/// An image along with smaller copies of it, its levels, made with
+/// [`RenderContext::make_mip_image`] and drawn with
+/// [`RenderContext::draw_mip_image`].
+///
+/// An image that is drawn much smaller than it is skips over most of its
+/// pixels, so it shimmers as it moves, as the tiles of a map do when it is
+/// zoomed out. A mip image is drawn from the level that is closest to the
+/// size it is drawn at, whose pixels are averages of the image's. Each level
+/// is half the size of the one before, so together they take a third more
+/// memory than the image.
+///
+/// Backends whose images can hold their own levels, such as the GPU
+/// backends, keep a single image, which picks its level for each pixel as
+/// it is drawn; others keep an image for each level. The images are shared
+/// between clones, as they are when images are cloned.
+///
+/// [`RenderContext::make_mip_image`]: crate::RenderContext::make_mip_image
+/// [`RenderContext::draw_mip_image`]: crate::RenderContext::draw_mip_image
+#[derive(Clone)]
+pub struct MipImage<I> {
+    /// The image, followed by each of its levels, from the largest.
+    levels: Vec<I>,
+}
+
+impl<I: Image> MipImage<I> {
+    /// A mip image of `image`, with `levels` of it, from the largest.
+    pub fn new(image: I, levels: Vec<I>) -> MipImage<I> {
+        let mut all = Vec::with_capacity(levels.len() + 1);
+        all.push(image);
+        all.extend(levels);
+        MipImage { levels: all }
+    }
+
+    /// A mip image of an image that holds its own levels.
+    pub fn from_image(image: I) -> MipImage<I> {
+        MipImage::new(image, Vec::new())
+    }
+
+    /// The image at its full size.
+    pub fn image(&self) -> &I {
+        &self.levels[0]
+    }
+
+    /// The image, followed by each of its levels, from the largest.
+    pub fn levels(&self) -> &[I] {
+        &self.levels
+    }
+
+    /// The size of the image.
+    pub fn size(&self) -> Size {
+        self.image().size()
+    }
+
+    /// The level to draw at `size`, in pixels: the smallest one that is at
+    /// least that wide and that high, or the image itself if none of them
+    /// are.
+    pub fn level_for_size(&self, size: Size) -> &I {
+        self.levels
+            .iter()
+            .rev()
+            .find(|level| {
+                let level = level.size();
+                level.width >= size.width && level.height >= size.height
+            })
+            .unwrap_or_else(|| self.image())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kurbo::Rect;
+
+    /// An image of some size, without pixels.
+    #[derive(Clone)]
+    struct Blank(Size);
+
+    impl Image for Blank {
+        fn size(&self) -> Size {
+            self.0
+        }
+
+        fn sub_image(&self, rect: impl Into<Rect>) -> Self {
+            Blank(rect.into().size())
+        }
+    }
+
+    #[test]
+    fn levels_are_picked_by_size() {
+        let image = MipImage::new(
+            Blank(Size::new(100., 50.)),
+            vec![Blank(Size::new(50., 25.)), Blank(Size::new(25., 13.))],
+        );
+        assert_eq!(image.size(), Size::new(100., 50.));
+        assert_eq!(image.levels().len(), 3);
+        let level = |size| image.level_for_size(size).size();
+        assert_eq!(level(Size::new(20., 10.)), Size::new(25., 13.));
+        assert_eq!(level(Size::new(30., 10.)), Size::new(50., 25.));
+        assert_eq!(level(Size::new(50., 25.)), Size::new(50., 25.));
+        assert_eq!(level(Size::new(400., 200.)), Size::new(100., 50.));
+    }
+}
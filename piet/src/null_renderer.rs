@@ -6,9 +6,9 @@ use std::ops::RangeBounds;
 use kurbo::{Affine, Point, Rect, Shape, Size};
 
 use crate::{
-    Color, Error, FixedGradient, FontFamily, HitTestPoint, HitTestPosition, Image, ImageFormat,
-    InterpolationMode, IntoBrush, LineMetric, RenderContext, StrokeStyle, Text, TextAttribute,
-    TextLayout, TextLayoutBuilder, TextStorage,
+    Capabilities, Color, Error, FixedGradient, FontFamily, HitTestPoint, HitTestPosition, Image,
+    ImageFormat, InterpolationMode, IntoBrush, LineMetric, RenderContext, StrokeStyle, Text,
+    TextAttribute, TextLayout, TextLayoutBuilder, TextShapingQuality, TextStorage,
 };
 
 /// A render context that doesn't render.
@@ -78,6 +78,14 @@ impl RenderContext for NullRenderContext {
 
     fn fill_even_odd(&mut self, _shape: impl Shape, _brush: &impl IntoBrush<Self>) {}
 
+    fn fill_with_mask(
+        &mut self,
+        _shape: impl Shape,
+        _brush: &impl IntoBrush<Self>,
+        _mask: &Self::Image,
+    ) {
+    }
+
     fn clip(&mut self, _shape: impl Shape) {}
 
     fn text(&mut self) -> &mut Self::Text {
@@ -132,6 +140,14 @@ impl RenderContext for NullRenderContext {
     fn current_transform(&self) -> Affine {
         Affine::default()
     }
+
+    fn capabilities(&self) -> Capabilities {
+        // nothing is drawn.
+        Capabilities {
+            text_shaping_quality: TextShapingQuality::None,
+            ..Capabilities::default()
+        }
+    }
 }
 
 impl Text for NullText {
@@ -231,4 +247,8 @@ impl Image for NullImage {
     fn size(&self) -> Size {
         Size::ZERO
     }
+
+    fn sub_image(&self, _rect: impl Into<Rect>) -> Self {
+        NullImage
+    }
 }
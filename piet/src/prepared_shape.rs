@@ -0,0 +1,116 @@
+//! Shapes that are prepared once and drawn many times.
+
+use std::sync::Arc;
+
+use kurbo::{BezPath, PathEl, Point, Rect, Shape};
+
+/// The accuracy with which shapes that aren't paths, such as circles, are
+/// converted to paths.
+const TOLERANCE: f64 = 1e-3;
+
+/// A shape that has been prepared to be drawn many times, made with
+/// [`RenderContext::prepare_shape`].
+///
+/// A `PreparedShape` is a [`Shape`], so it can be passed to any drawing
+/// method, of any backend. It holds the shape as a path, along with its
+/// bounds, so that neither is computed again each time the shape is drawn;
+/// backends that can keep shapes in a form that is quicker to draw, such as
+/// a geometry of the platform, may keep that too, and use it when the
+/// prepared shape is drawn.
+///
+/// The path is shared between clones, so cloning is cheap.
+///
+/// [`RenderContext::prepare_shape`]: crate::RenderContext::prepare_shape
+#[derive(Clone, Debug)]
+pub struct PreparedShape {
+    path: Arc<BezPath>,
+    bounds: Rect,
+}
+
+impl PreparedShape {
+    /// Prepare `shape`, converting it to a path.
+    pub fn new(shape: impl Shape) -> PreparedShape {
+        let bounds = shape.bounding_box();
+        let path = shape.into_path(TOLERANCE);
+        PreparedShape {
+            path: Arc::new(path),
+            bounds,
+        }
+    }
+
+    /// The path of the shape.
+    pub fn path(&self) -> &BezPath {
+        &self.path
+    }
+
+    /// The path of the shape, as it is shared between clones.
+    ///
+    /// The path can't be changed while it is shared, so a backend that
+    /// keeps a clone of this can identify the shape by the address of its
+    /// elements, which is what [`Shape::as_path_slice`] returns when the
+    /// shape is drawn.
+    pub fn shared_path(&self) -> &Arc<BezPath> {
+        &self.path
+    }
+
+    /// Returns `true` if the two shapes share their path.
+    pub fn ptr_eq(&self, other: &PreparedShape) -> bool {
+        Arc::ptr_eq(&self.path, &other.path)
+    }
+}
+
+impl Shape for PreparedShape {
+    type PathElementsIter<'iter> = std::iter::Copied<std::slice::Iter<'iter, PathEl>>;
+
+    fn path_elements(&self, _tolerance: f64) -> Self::PathElementsIter<'_> {
+        self.path.elements().iter().copied()
+    }
+
+    fn to_path(&self, _tolerance: f64) -> BezPath {
+        (*self.path).clone()
+    }
+
+    fn area(&self) -> f64 {
+        self.path.area()
+    }
+
+    fn perimeter(&self, accuracy: f64) -> f64 {
+        self.path.perimeter(accuracy)
+    }
+
+    fn winding(&self, pt: Point) -> i32 {
+        self.path.winding(pt)
+    }
+
+    fn bounding_box(&self) -> Rect {
+        self.bounds
+    }
+
+    fn as_path_slice(&self) -> Option<&[PathEl]> {
+        Some(self.path.elements())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kurbo::Circle;
+
+    #[test]
+    fn prepared_shape_matches_shape() {
+        let circle = Circle::new((50.0, 50.0), 10.0);
+        let prepared = PreparedShape::new(circle);
+        assert_eq!(prepared.bounding_box(), circle.bounding_box());
+        assert!((prepared.area() - circle.area()).abs() < 0.1);
+        assert_eq!(prepared.winding(Point::new(50.0, 50.0)), 1);
+        assert_eq!(prepared.winding(Point::new(65.0, 50.0)), 0);
+
+        let clone = prepared.clone();
+        assert!(clone.ptr_eq(&prepared));
+        assert_eq!(
+            clone.as_path_slice().map(<[_]>::as_ptr),
+            prepared.as_path_slice().map(<[_]>::as_ptr)
+        );
+        assert!(!PreparedShape::new(circle).ptr_eq(&prepared));
+    }
+}
@@ -2,24 +2,84 @@
 
 use std::borrow::Cow;
 
-use kurbo::{Affine, Point, Rect, Shape};
+use kurbo::{Affine, BezPath, Insets, Point, Rect, Shape};
 
 use crate::{
-    Color, Error, FixedGradient, FixedLinearGradient, FixedRadialGradient, Image, LinearGradient,
-    RadialGradient, StrokeStyle, Text, TextLayout,
+    util, Capabilities, Color, Error, FixedGradient, FixedLinearGradient, FixedRadialGradient,
+    GradientOptions, GradientStops, HitTester, Image, ImageFilter, LinearGradient, Markers,
+    MipImage, PreparedShape, RadialGradient, SharedImage, StrokeStyle, Symbol, Text, TextLayout,
 };
 
+/// The tolerance that shapes are converted to paths with, unless another is
+/// set with [`RenderContext::set_tolerance`].
+pub const DEFAULT_TOLERANCE: f64 = 1e-3;
+
 /// A requested interpolation mode for drawing images.
+///
+/// The modes are in order of increasing quality and cost. Backends that don't
+/// support a mode use the best one they have below it.
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum InterpolationMode {
     /// Don't interpolate, use nearest neighbor.
     NearestNeighbor,
     /// Use bilinear interpolation.
     Bilinear,
+    /// Use bicubic interpolation, which is smoother when scaling up.
+    Bicubic,
+    /// Use the best filtering the backend has, which also avoids aliasing
+    /// when scaling down a lot, such as for thumbnails.
+    HighQuality,
+}
+
+/// How what is drawn is composited with what is already there.
+///
+/// These are the separable and non-separable blend modes of the
+/// [Compositing and Blending] spec, as used by CSS's `mix-blend-mode` and
+/// the `globalCompositeOperation` of an HTML canvas, and are set with
+/// [`RenderContext::set_blend_mode`].
+///
+/// [Compositing and Blending]: https://www.w3.org/TR/compositing-1/#blending
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BlendMode {
+    /// Draw over what is there.
+    #[default]
+    Normal,
+    /// Multiply the colors, which darkens.
+    Multiply,
+    /// Multiply the inverses of the colors, which lightens.
+    Screen,
+    /// Multiply or screen, depending on the color that is there.
+    Overlay,
+    /// Keep the darker of the colors.
+    Darken,
+    /// Keep the lighter of the colors.
+    Lighten,
+    /// Brighten what is there to reflect the color drawn.
+    ColorDodge,
+    /// Darken what is there to reflect the color drawn.
+    ColorBurn,
+    /// Multiply or screen, depending on the color drawn.
+    HardLight,
+    /// Darken or lighten, depending on the color drawn.
+    SoftLight,
+    /// Subtract the darker of the colors from the lighter.
+    Difference,
+    /// Like `Difference`, with lower contrast.
+    Exclusion,
+    /// The hue drawn, with the saturation and luminosity that are there.
+    Hue,
+    /// The saturation drawn, with the hue and luminosity that are there.
+    Saturation,
+    /// The hue and saturation drawn, with the luminosity that is there.
+    Color,
+    /// The luminosity drawn, with the hue and saturation that are there.
+    Luminosity,
 }
 
 /// The pixel format for bitmap images.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[non_exhaustive]
 pub enum ImageFormat {
     /// 1 byte per pixel.
@@ -41,6 +101,25 @@ pub enum ImageFormat {
     /// For example, a full-intensity red pixel with 50% transparency consists of four bytes
     /// `[0x80, 0, 0, 0x80]` independent of the system's endianness.
     RgbaPremul,
+    /// 4 bytes per pixel, in BGRA order, with separate alpha.
+    ///
+    /// For example, a full-intensity red pixel with 50% transparency consists of four bytes
+    /// `[0, 0, 0xff, 0x80]` independent of the system's endianness.
+    BgraSeparate,
+    /// 4 bytes per pixel, in BGRA order, with premultiplied alpha.
+    ///
+    /// This is the order of many video decoders and capture APIs. For example, a
+    /// full-intensity red pixel with 50% transparency consists of four bytes
+    /// `[0, 0, 0x80, 0x80]` independent of the system's endianness.
+    BgraPremul,
+    /// 8 bytes per pixel, in RGBA order, with separate alpha and 16 bits per channel.
+    ///
+    /// Each channel is a `u16` in the system's endianness, so a buffer of `u16`s can be
+    /// passed as it is. For example, a full-intensity red pixel with 50% transparency
+    /// consists of the `u16`s `[0xffff, 0, 0, 0x8000]`.
+    ///
+    /// Backends draw with 8 bits per channel, so the extra precision is discarded.
+    Rgba16,
 }
 
 impl ImageFormat {
@@ -49,7 +128,11 @@ impl ImageFormat {
         match self {
             ImageFormat::Grayscale => 1,
             ImageFormat::Rgb => 3,
-            ImageFormat::RgbaPremul | ImageFormat::RgbaSeparate => 4,
+            ImageFormat::RgbaPremul
+            | ImageFormat::RgbaSeparate
+            | ImageFormat::BgraPremul
+            | ImageFormat::BgraSeparate => 4,
+            ImageFormat::Rgba16 => 8,
         }
     }
 }
@@ -100,8 +183,29 @@ where
     fn solid_brush(&mut self, color: Color) -> Self::Brush;
 
     /// Create a new gradient brush.
+    ///
+    /// The points of the gradient are in the user space of whatever it is
+    /// drawn with, under the transform at the time of drawing, rather than
+    /// under the transform when the brush was created.
     fn gradient(&mut self, gradient: impl Into<FixedGradient>) -> Result<Self::Brush, Error>;
 
+    /// Create a new gradient brush, which is drawn with `options`.
+    ///
+    /// This is like [`gradient`](#method.gradient), which is the same as
+    /// this with the default options. By default, dithering is ignored, and
+    /// other interpolations are approximated with
+    /// [`util::interpolated_gradient`].
+    fn gradient_with_options(
+        &mut self,
+        gradient: impl Into<FixedGradient>,
+        options: GradientOptions,
+    ) -> Result<Self::Brush, Error> {
+        self.gradient(util::interpolated_gradient(
+            gradient.into(),
+            options.interpolation,
+        ))
+    }
+
     /// Replace a region of the canvas with the provided [`Color`].
     ///
     /// The region can be omitted, in which case it will apply to the entire
@@ -133,6 +237,39 @@ where
         style: &StrokeStyle,
     );
 
+    /// Stroke a [`Shape`] with a gradient that follows the path, rather than
+    /// one that is fixed in space.
+    ///
+    /// The positions of the `stops` are fractions of the length of the path,
+    /// from its start to its end; this is useful for showing progress along
+    /// a route, for instance. By default the path is split into short pieces
+    /// of solid color; see [`util::stroke_gradient_along_path`].
+    fn stroke_gradient_along_path(
+        &mut self,
+        shape: impl Shape,
+        stops: impl GradientStops,
+        width: f64,
+        style: &StrokeStyle,
+    ) {
+        util::stroke_gradient_along_path(self, shape, &stops.to_vec(), width, style);
+    }
+
+    /// Stroke a [`Shape`] with [`Markers`], such as arrowheads, at its ends.
+    ///
+    /// The markers are filled with the brush. By default they are drawn with
+    /// [`fill`](#method.fill), after the stroke; see
+    /// [`util::stroke_with_markers`].
+    fn stroke_with_markers(
+        &mut self,
+        shape: impl Shape,
+        brush: &impl IntoBrush<Self>,
+        width: f64,
+        style: &StrokeStyle,
+        markers: &Markers,
+    ) {
+        util::stroke_with_markers(self, shape, brush, width, style, markers);
+    }
+
     /// Fill a [`Shape`], using the [non-zero fill rule].
     ///
     /// [non-zero fill rule]: https://en.wikipedia.org/wiki/Nonzero-rule
@@ -143,12 +280,146 @@ where
     /// [even-odd fill rule]: https://en.wikipedia.org/wiki/Even–odd_rule
     fn fill_even_odd(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>);
 
+    /// Fill a [`Shape`], and then stroke it, such as for a marker with an
+    /// outline.
+    ///
+    /// This draws the same as [`fill`] followed by [`stroke_styled`], which
+    /// is the default, but backends can draw both from the same path, rather
+    /// than building it twice.
+    ///
+    /// [`fill`]: #method.fill
+    /// [`stroke_styled`]: #method.stroke_styled
+    fn fill_and_stroke(
+        &mut self,
+        shape: impl Shape,
+        fill_brush: &impl IntoBrush<Self>,
+        stroke_brush: &impl IntoBrush<Self>,
+        width: f64,
+        style: &StrokeStyle,
+    ) {
+        self.fill(&shape, fill_brush);
+        self.stroke_styled(&shape, stroke_brush, width, style);
+    }
+
+    /// Fill many [`Shape`]s with the same brush, such as the points of a
+    /// scatter plot.
+    ///
+    /// The shapes are filled as one path, using the non-zero fill rule, so
+    /// that backends can draw them with one call rather than one each. Where
+    /// the shapes overlap, they are only drawn once, which looks the same as
+    /// filling them one at a time unless the brush is translucent, and shapes
+    /// that wind in opposite directions leave a hole where they overlap.
+    ///
+    /// By default, the shapes are appended to a path that is drawn with
+    /// [`fill`](#method.fill).
+    fn fill_many(
+        &mut self,
+        shapes: impl IntoIterator<Item = impl Shape>,
+        brush: &impl IntoBrush<Self>,
+    ) {
+        let tolerance = self.tolerance();
+        let mut path = BezPath::new();
+        for shape in shapes {
+            path.extend(shape.path_elements(tolerance));
+        }
+        if !path.elements().is_empty() {
+            self.fill(path, brush);
+        }
+    }
+
+    /// Stroke many [`Shape`]s with the same brush and style.
+    ///
+    /// Like [`fill_many`], the shapes are stroked as one path, so where their
+    /// strokes overlap they are only drawn once. Dashes start over at the
+    /// start of each shape, as they do for each subpath of a path.
+    ///
+    /// By default, the shapes are appended to a path that is drawn with
+    /// [`stroke_styled`].
+    ///
+    /// [`fill_many`]: #method.fill_many
+    /// [`stroke_styled`]: #method.stroke_styled
+    fn stroke_many(
+        &mut self,
+        shapes: impl IntoIterator<Item = impl Shape>,
+        brush: &impl IntoBrush<Self>,
+        width: f64,
+        style: &StrokeStyle,
+    ) {
+        let tolerance = self.tolerance();
+        let mut path = BezPath::new();
+        for shape in shapes {
+            path.extend(shape.path_elements(tolerance));
+        }
+        if !path.elements().is_empty() {
+            self.stroke_styled(path, brush, width, style);
+        }
+    }
+
+    /// Fill a [`Shape`] through an alpha mask.
+    ///
+    /// `mask` is stretched over the bounding box of the shape, and the fill is
+    /// multiplied by the alpha of its pixels, so that it is only drawn where
+    /// the mask is opaque. Only the alpha of the mask is used, not its color.
+    ///
+    /// This can be used to draw an icon in a color, by filling the icon's
+    /// bounds with that color, or for fills with soft edges.
+    ///
+    /// By default, the mask is ignored, and the whole shape is filled as by
+    /// [`fill`](#method.fill); backends that can composite with an alpha
+    /// mask override this.
+    fn fill_with_mask(
+        &mut self,
+        shape: impl Shape,
+        brush: &impl IntoBrush<Self>,
+        mask: &Self::Image,
+    ) {
+        let _ = mask;
+        self.fill(shape, brush);
+    }
+
     /// Clip to a [`Shape`].
     ///
     /// All subsequent drawing operations up to the next [`restore`](#method.restore)
     /// are clipped by the shape.
     fn clip(&mut self, shape: impl Shape);
 
+    /// Prepare a [`Shape`] to be drawn many times, such as a complex path
+    /// that is drawn every frame.
+    ///
+    /// The [`PreparedShape`] can be drawn by passing it, or a reference to
+    /// it, to any drawing method. By default, this converts the shape to a
+    /// path and computes its bounds, once. Backends that can keep shapes in
+    /// a form that is quicker to draw, such as a geometry of the platform,
+    /// may also do that here, and use it whenever the prepared shape is
+    /// drawn.
+    fn prepare_shape(&mut self, shape: impl Shape) -> PreparedShape {
+        PreparedShape::new(shape)
+    }
+
+    /// Draw a [`Symbol`] at each of many instances, such as the markers of a
+    /// scatter plot.
+    ///
+    /// Each instance is a transform, which is appended to the current
+    /// transform to draw the symbol, and an optional color. A shape is filled
+    /// with the color of the instance, or with `brush` if it has none. An
+    /// image is drawn as it is if the instance has no color, and otherwise
+    /// used as a mask that is filled with the color, as with
+    /// [`fill_with_mask`](#method.fill_with_mask), such as to draw an icon in
+    /// many colors. The brush is in the space of each instance.
+    ///
+    /// By default, the instances are drawn one at a time; see
+    /// [`util::draw_symbol_instances`]. Backends may draw them faster, such
+    /// as by building the shape once and only changing the transform and the
+    /// color between instances.
+    fn draw_symbol_instances(
+        &mut self,
+        symbol: Symbol<Self::Image>,
+        brush: &impl IntoBrush<Self>,
+        instances: &[(Affine, Option<Color>)],
+    ) {
+        util::draw_symbol_instances(self, symbol, brush, instances);
+    }
+
     /// Returns a reference to a shared [`Text`] object.
     ///
     /// This provides access to the text API.
@@ -183,10 +454,274 @@ where
     ///
     /// Equivalent to [`save`](#method.save), calling `f`, then
     /// [`restore`](#method.restore). See those methods for more details.
-    fn with_save(&mut self, f: impl FnOnce(&mut Self) -> Result<(), Error>) -> Result<(), Error> {
+    ///
+    /// Returns what `f` returns, or the error from restoring if `f`
+    /// succeeded and that failed.
+    fn with_save<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T, Error>) -> Result<T, Error> {
         self.save()?;
+        let result = f(self);
         // Always try to restore the stack, even if `f` errored.
-        f(self).and(self.restore())
+        let restored = self.restore();
+        result.and_then(|value| restored.map(|()| value))
+    }
+
+    /// Do graphics operations clipped to `shape`, with the context state
+    /// saved and then restored.
+    ///
+    /// Equivalent to calling [`clip`](#method.clip) at the start of
+    /// [`with_save`](#method.with_save).
+    fn with_save_and_clip<T>(
+        &mut self,
+        shape: impl Shape,
+        f: impl FnOnce(&mut Self) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        self.with_save(|rc| {
+            rc.clip(shape);
+            f(rc)
+        })
+    }
+
+    /// Do graphics operations with `transform` applied, with the context
+    /// state saved and then restored.
+    ///
+    /// Equivalent to calling [`transform`](#method.transform) at the start of
+    /// [`with_save`](#method.with_save).
+    fn with_transform<T>(
+        &mut self,
+        transform: Affine,
+        f: impl FnOnce(&mut Self) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        self.with_save(|rc| {
+            rc.transform(transform);
+            f(rc)
+        })
+    }
+
+    /// Set the opacity of everything that is drawn afterwards, from `0.0`,
+    /// transparent, to `1.0`, opaque.
+    ///
+    /// Each primitive, whether it is a fill, a stroke, text or an image, is
+    /// drawn with its opacity multiplied by the alpha, like the `globalAlpha`
+    /// of an HTML canvas. This fades out what is drawn without making new
+    /// brushes; where faded primitives overlap, both are still seen.
+    ///
+    /// The alpha is part of the state that is saved by
+    /// [`save`](#method.save) and restored by [`restore`](#method.restore).
+    /// It starts at `1.0`, and doesn't apply to [`clear`](#method.clear).
+    /// By default, the alpha is ignored, and everything is drawn with the
+    /// opacity of its brush; backends that use it report
+    /// [`supports_global_alpha`](crate::Capabilities::supports_global_alpha).
+    fn set_global_alpha(&mut self, alpha: f64) {
+        let _ = alpha;
+    }
+
+    /// The alpha set by [`set_global_alpha`](#method.set_global_alpha).
+    ///
+    /// By default, this is always `1.0`, as the alpha is ignored.
+    fn global_alpha(&self) -> f64 {
+        1.0
+    }
+
+    /// Set how everything that is drawn afterwards is composited with what
+    /// is already there.
+    ///
+    /// The mode is part of the state that is saved by
+    /// [`save`](#method.save) and restored by [`restore`](#method.restore).
+    /// It starts at [`BlendMode::Normal`], and doesn't apply to
+    /// [`clear`](#method.clear). Backends that can't blend, which say so in
+    /// their [`capabilities`](#method.capabilities), ignore it; by default,
+    /// the mode is ignored.
+    fn set_blend_mode(&mut self, mode: BlendMode) {
+        let _ = mode;
+    }
+
+    /// The mode set by [`set_blend_mode`](#method.set_blend_mode).
+    ///
+    /// By default, this is always [`BlendMode::Normal`].
+    fn blend_mode(&self) -> BlendMode {
+        BlendMode::Normal
+    }
+
+    /// Set how closely shapes that aren't paths, such as circles, ellipses
+    /// and arcs, are followed when they are converted to paths to be drawn,
+    /// as the largest distance, in user space, between a shape and its path.
+    ///
+    /// A smaller tolerance gives smoother curves in views that are zoomed in
+    /// far, such as in CAD, and a larger one gives paths with fewer segments,
+    /// which are quicker to build and draw, such as on slow devices. Very
+    /// small tolerances give paths with very many segments. Tolerances that
+    /// aren't positive and finite are ignored.
+    ///
+    /// The tolerance is part of the state that is saved by
+    /// [`save`](#method.save) and restored by [`restore`](#method.restore).
+    /// It starts at [`DEFAULT_TOLERANCE`]. Backends that don't convert shapes
+    /// with a tolerance ignore this, and circles, which most backends draw as
+    /// arcs of their own, aren't affected by it.
+    fn set_tolerance(&mut self, tolerance: f64) {
+        let _ = tolerance;
+    }
+
+    /// The tolerance set by [`set_tolerance`](#method.set_tolerance).
+    fn tolerance(&self) -> f64 {
+        DEFAULT_TOLERANCE
+    }
+
+    /// Fill a [`Shape`] with its opacity multiplied by `alpha`, as well as by
+    /// the [global alpha](#method.set_global_alpha).
+    ///
+    /// By default, this multiplies the global alpha by `alpha` for the fill,
+    /// so backends that ignore the global alpha also ignore `alpha`, unless
+    /// they implement this themselves. Whether they use it is reported by
+    /// [`supports_global_alpha`](crate::Capabilities::supports_global_alpha);
+    /// elsewhere, a brush of a faded color can be used instead.
+    fn fill_with_alpha(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>, alpha: f64) {
+        let global_alpha = self.global_alpha();
+        self.set_global_alpha(global_alpha * alpha);
+        self.fill(shape, brush);
+        self.set_global_alpha(global_alpha);
+    }
+
+    /// Attach metadata to what is drawn, such as a title for accessibility.
+    ///
+    /// The annotation applies to the innermost group begun with
+    /// [`begin_group`](#method.begin_group), or to the whole document if no
+    /// group is open, and should be added before anything is drawn in it.
+    ///
+    /// Backends that write documents should understand the keys `"title"`, a
+    /// short name, and `"description"`, a longer text alternative; other keys
+    /// may be passed through as they are, or ignored.
+    ///
+    /// Backends that can't represent metadata ignore it, which is the default.
+    fn annotate(&mut self, key: &str, value: &str) {
+        let _ = (key, value);
+    }
+
+    /// Begin a group of drawing operations with a semantic role.
+    ///
+    /// `role` is an [ARIA role], such as `"img"`, `"figure"` or `"heading"`,
+    /// which backends map to their own structure; it may be empty. Groups
+    /// can be nested, and each must be ended with
+    /// [`end_group`](#method.end_group). Groups don't affect what is drawn,
+    /// and are independent of [`save`](#method.save) and
+    /// [`restore`](#method.restore).
+    ///
+    /// Prefer [`with_group`](#method.with_group) if possible, as that
+    /// statically enforces balance of begin/end pairs.
+    ///
+    /// Backends that can't represent structure ignore groups, which is the
+    /// default.
+    ///
+    /// [ARIA role]: https://www.w3.org/TR/wai-aria/#role_definitions
+    fn begin_group(&mut self, role: &str) -> Result<(), Error> {
+        let _ = role;
+        Ok(())
+    }
+
+    /// End the group begun by the matching [`begin_group`](#method.begin_group).
+    fn end_group(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Do graphics operations in a group with a semantic role.
+    ///
+    /// Equivalent to [`begin_group`](#method.begin_group), calling `f`, then
+    /// [`end_group`](#method.end_group).
+    fn with_group(
+        &mut self,
+        role: &str,
+        f: impl FnOnce(&mut Self) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        self.begin_group(role)?;
+        // Always try to end the group, even if `f` errored.
+        f(self).and(self.end_group())
+    }
+
+    /// Begin drawing through an opacity mask.
+    ///
+    /// Everything drawn until the matching
+    /// [`end_opacity_mask`](#method.end_opacity_mask) is drawn as one layer,
+    /// which is then multiplied by the alpha of `mask`, stretched over `rect`,
+    /// and composited. Nothing is drawn outside of `rect`. As with
+    /// [`fill_with_mask`](#method.fill_with_mask), only the alpha of the mask
+    /// is used, not its color.
+    ///
+    /// This can be used to fade the edges of a scrolled view, or to feather
+    /// the edges of a viewport.
+    ///
+    /// This saves the state of the context, as [`save`](#method.save) does,
+    /// and ending the mask restores it; any states saved in between must be
+    /// restored first. Prefer [`with_opacity_mask`](#method.with_opacity_mask)
+    /// if possible, as that statically enforces balance of begin/end pairs.
+    ///
+    /// By default, which is for backends that can't draw through masks,
+    /// drawing is only clipped to `rect`.
+    fn begin_opacity_mask(
+        &mut self,
+        mask: &Self::Image,
+        rect: impl Into<Rect>,
+    ) -> Result<(), Error> {
+        let _ = mask;
+        self.save()?;
+        self.clip(rect.into());
+        Ok(())
+    }
+
+    /// End the opacity mask begun by the matching
+    /// [`begin_opacity_mask`](#method.begin_opacity_mask), and draw what was
+    /// drawn through it.
+    fn end_opacity_mask(&mut self) -> Result<(), Error> {
+        self.restore()
+    }
+
+    /// Do graphics operations through an opacity mask.
+    ///
+    /// Equivalent to [`begin_opacity_mask`](#method.begin_opacity_mask),
+    /// calling `f`, then [`end_opacity_mask`](#method.end_opacity_mask).
+    fn with_opacity_mask(
+        &mut self,
+        mask: &Self::Image,
+        rect: impl Into<Rect>,
+        f: impl FnOnce(&mut Self) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        self.begin_opacity_mask(mask, rect)?;
+        // Always try to end the mask, even if `f` errored.
+        f(self).and(self.end_opacity_mask())
+    }
+
+    /// Mark the area of `shape` as belonging to `id`, for hit testing.
+    ///
+    /// The shape is added with the current transform to the context's
+    /// [`hit_tester`](#method.hit_tester), if it has one. Nothing is drawn.
+    fn hit_region(&mut self, id: u64, shape: impl Shape) {
+        let transform = self.current_transform();
+        if let Some(tester) = self.hit_tester() {
+            tester.add(id, shape, transform);
+        }
+    }
+
+    /// Mark the area of the stroke of `shape` as belonging to `id`, for hit
+    /// testing.
+    ///
+    /// The area is that of the stroke drawn by
+    /// [`stroke_styled`](#method.stroke_styled) with the same arguments,
+    /// including its joins, caps and dashes. Nothing is drawn.
+    fn hit_region_stroke(&mut self, id: u64, shape: impl Shape, width: f64, style: &StrokeStyle) {
+        let transform = self.current_transform();
+        if let Some(tester) = self.hit_tester() {
+            tester.add_stroke(id, shape, transform, width, style);
+        }
+    }
+
+    /// The [`HitTester`] that [`hit_region`](#method.hit_region) and
+    /// [`hit_region_stroke`](#method.hit_region_stroke) add regions to.
+    ///
+    /// Regions are kept until the tester is cleared, so it can be taken with
+    /// [`std::mem::take`] once drawing is done, to answer pointer queries.
+    ///
+    /// Backends that don't keep hit regions return `None`, which is the
+    /// default.
+    fn hit_tester(&mut self) -> Option<&mut HitTester> {
+        None
     }
 
     /// Finish any pending operations.
@@ -228,6 +763,66 @@ where
         format: ImageFormat,
     ) -> Result<Self::Image, Error>;
 
+    /// Create a new [`Image`] from a [`SharedImage`].
+    ///
+    /// By default, this passes the pixels to
+    /// [`make_image`](#method.make_image). Backends that can share the
+    /// pixels with the image, instead of copying them, may do that instead.
+    fn make_shared_image(&mut self, image: &SharedImage) -> Result<Self::Image, Error> {
+        let buf = image.image_buf();
+        self.make_image(buf.width(), buf.height(), buf.raw_pixels(), buf.format())
+    }
+
+    /// Create a new [`Image`] from encoded image data, such as the contents
+    /// of a PNG, JPEG or GIF file.
+    ///
+    /// By default, the data is decoded with the `image` crate and passed to
+    /// [`make_image`](#method.make_image). This needs the `image` feature,
+    /// and a feature for each format to be decoded, such as `image_png`,
+    /// `jpeg` or `gif`; without it, this returns
+    /// [`Error::MissingFeature`]. Backends whose platform can decode images
+    /// itself may do that instead.
+    ///
+    /// Data that can't be decoded is an [`Error::BackendError`].
+    fn make_image_from_encoded(&mut self, data: &[u8]) -> Result<Self::Image, Error> {
+        #[cfg(feature = "image")]
+        {
+            let buf = crate::ImageBuf::from_data(data).map_err(|e| Error::BackendError(e))?;
+            self.make_image(buf.width(), buf.height(), buf.raw_pixels(), buf.format())
+        }
+        #[cfg(not(feature = "image"))]
+        {
+            let _ = data;
+            Err(Error::MissingFeature("image"))
+        }
+    }
+
+    /// Create a new [`MipImage`], which keeps smaller copies of the image
+    /// for drawing it scaled down, such as the tiles of a zoomed out map.
+    ///
+    /// The arguments are those of [`make_image`](#method.make_image). By
+    /// default, the levels are made on the CPU, by
+    /// [`util::mip_levels`](crate::util::mip_levels), and each is made into
+    /// an image. Backends whose images can hold their own levels, such as
+    /// the GPU backends, make a single image with them instead.
+    fn make_mip_image(
+        &mut self,
+        width: usize,
+        height: usize,
+        buf: &[u8],
+        format: ImageFormat,
+    ) -> Result<MipImage<Self::Image>, Error> {
+        let image = self.make_image(width, height, buf, format)?;
+        let data = util::premultiplied_rgba(width * height, buf, format)?;
+        let levels = util::mip_levels(width, height, &data)
+            .into_iter()
+            .map(|(width, height, data)| {
+                self.make_image(width, height, &data, ImageFormat::RgbaPremul)
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(MipImage::new(image, levels))
+    }
+
     /// Draw an [`Image`] into the provided [`Rect`].
     ///
     /// The image is scaled to fit the provided [`Rect`]; it will be squashed
@@ -251,6 +846,92 @@ where
         interp: InterpolationMode,
     );
 
+    /// Draw an [`Image`] as a nine-patch, with fixed corners and stretched
+    /// edges and center.
+    ///
+    /// `insets` are the sizes of the corners, in the image's pixels, and the
+    /// corners are drawn at that size in `dst_rect`, unless it is too small
+    /// for them. The edges between the corners are stretched along the sides
+    /// of `dst_rect`, and the center fills the rest. See
+    /// [`util::nine_patch`](crate::util::nine_patch) for the details.
+    ///
+    /// This is useful for drawing the backgrounds of buttons and other
+    /// widgets from images. The default implementation draws each area with
+    /// [`draw_image_area`](#method.draw_image_area).
+    fn draw_image_nine(
+        &mut self,
+        image: &Self::Image,
+        insets: impl Into<Insets>,
+        dst_rect: impl Into<Rect>,
+        interp: InterpolationMode,
+    ) {
+        let areas = crate::util::nine_patch(image.size(), insets.into(), dst_rect.into());
+        for (src_rect, dst_rect) in areas {
+            self.draw_image_area(image, src_rect, dst_rect, interp);
+        }
+    }
+
+    /// Draw an [`Image`] warped onto a quad, with a perspective transform,
+    /// such as for cards that are tilted away from the viewer.
+    ///
+    /// The corners of `dst_quad` are those that the top-left, top-right,
+    /// bottom-right and bottom-left corners of the image are drawn at. The
+    /// quad must be convex; otherwise nothing is drawn.
+    ///
+    /// By default, the image is drawn as many triangles, each with an affine
+    /// transform, by
+    /// [`util::draw_image_with_quad`](crate::util::draw_image_with_quad).
+    fn draw_image_with_quad(
+        &mut self,
+        image: &Self::Image,
+        dst_quad: [Point; 4],
+        interp: InterpolationMode,
+    ) {
+        crate::util::draw_image_with_quad(self, image, dst_quad, interp);
+    }
+
+    /// Draw a [`MipImage`] into the provided [`Rect`], like
+    /// [`draw_image`](#method.draw_image), from the level that suits the
+    /// size it is drawn at.
+    ///
+    /// The level is the smallest that is at least as big as `dst_rect` is in
+    /// pixels, by the scale of the current transform. Backends that scale to
+    /// pixels outside of the transform, for high-DPI displays, draw from a
+    /// level that is a little too small, unless they override this.
+    fn draw_mip_image(
+        &mut self,
+        image: &MipImage<Self::Image>,
+        dst_rect: impl Into<Rect>,
+        interp: InterpolationMode,
+    ) {
+        let dst_rect = dst_rect.into();
+        let scale = self.current_transform().determinant().abs().sqrt();
+        let level = image.level_for_size(dst_rect.abs().size() * scale);
+        self.draw_image(level, dst_rect, interp);
+    }
+
+    /// Draw an [`Image`] into the provided [`Rect`], through [`ImageFilter`]s,
+    /// such as blurs, shadows and color matrices.
+    ///
+    /// The filters are applied in order, and their lengths are in user
+    /// space. Blurs and shadows draw outside of `dst_rect`, by as far as
+    /// [`ImageFilter::total_outset`] says. The image is drawn with
+    /// [`InterpolationMode::Bilinear`].
+    ///
+    /// Backends apply filters natively where they can, and otherwise in
+    /// software, with [`util::filter_pixels`](crate::util::filter_pixels).
+    /// By default, which is for backends that can do neither, the image is
+    /// drawn without the filters.
+    fn draw_image_filtered(
+        &mut self,
+        image: &Self::Image,
+        dst_rect: impl Into<Rect>,
+        filters: &[ImageFilter],
+    ) {
+        let _ = filters;
+        self.draw_image(image, dst_rect, InterpolationMode::Bilinear);
+    }
+
     /// Create an [`Image`] of the specified region of the context.
     ///
     /// The `src_rect` area of the current render context will be captured
@@ -267,12 +948,23 @@ where
 
     /// Returns the transformations currently applied to the context.
     fn current_transform(&self) -> Affine;
+
+    /// The features that this backend supports natively.
+    ///
+    /// This lets apps choose which features to use at runtime, such as
+    /// leaving out expensive effects on backends that would approximate
+    /// them. The default promises nothing beyond what every backend does.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
 }
 
 /// A trait for various types that can be used as brushes.
 ///
 /// These include backend-independent types such `Color` and `LinearGradient`,
 /// as well as the types used to represent these on a specific backend.
+/// A `(Color, f64)` pair is the color with its alpha multiplied by the
+/// number, and references to brushes are brushes too.
 ///
 /// This is an internal trait that you should not have to implement or think about.
 pub trait IntoBrush<P: RenderContext>
@@ -289,6 +981,20 @@ impl<P: RenderContext> IntoBrush<P> for Color {
     }
 }
 
+impl<P: RenderContext> IntoBrush<P> for (Color, f64) {
+    fn make_brush<'a>(&'a self, piet: &mut P, _bbox: impl FnOnce() -> Rect) -> Cow<'a, P::Brush> {
+        let (color, alpha) = *self;
+        let (_, _, _, a) = color.as_rgba();
+        Cow::Owned(piet.solid_brush(color.with_alpha(a * alpha)))
+    }
+}
+
+impl<P: RenderContext, T: IntoBrush<P> + ?Sized> IntoBrush<P> for &T {
+    fn make_brush<'a>(&'a self, piet: &mut P, bbox: impl FnOnce() -> Rect) -> Cow<'a, P::Brush> {
+        (**self).make_brush(piet, bbox)
+    }
+}
+
 /// A color or a gradient.
 ///
 /// This type is provided as a convenience, so that library consumers can
@@ -29,6 +29,10 @@ mod picture_13;
 mod picture_14;
 mod picture_15;
 mod picture_16;
+mod picture_17;
+mod picture_18;
+mod picture_19;
+mod picture_20;
 
 type BoxErr = Box<dyn std::error::Error>;
 
@@ -37,7 +41,7 @@ type BoxErr = Box<dyn std::error::Error>;
 pub const DEFAULT_SCALE: f64 = 2.0;
 
 /// The total number of samples in this module.
-pub const SAMPLE_COUNT: usize = 17;
+pub const SAMPLE_COUNT: usize = 21;
 
 /// file we save an os fingerprint to
 pub const GENERATED_BY: &str = "GENERATED_BY";
@@ -62,6 +66,10 @@ pub fn get<R: RenderContext>(number: usize) -> Result<SamplePicture<R>, BoxErr>
         14 => SamplePicture::new(picture_14::SIZE, picture_14::draw),
         15 => SamplePicture::new(picture_15::SIZE, picture_15::draw),
         16 => SamplePicture::new(picture_16::SIZE, picture_16::draw),
+        17 => SamplePicture::new(picture_17::SIZE, picture_17::draw),
+        18 => SamplePicture::new(picture_18::SIZE, picture_18::draw),
+        19 => SamplePicture::new(picture_19::SIZE, picture_19::draw),
+        20 => SamplePicture::new(picture_20::SIZE, picture_20::draw),
         _ => return Err(format!("No sample #{} exists", number).into()),
     })
 }
@@ -44,8 +44,7 @@ pub fn draw(rc: &mut impl RenderContext) -> Result<(), Error> {
 
     rc.stroke(Line::new((80.0, 12.0), (80.0 + w, 12.0)), &RED_ALPHA, 1.0);
 
-    rc.with_save(|rc| {
-        rc.transform(Affine::rotate(0.1));
+    rc.with_transform(Affine::rotate(0.1), |rc| {
         rc.draw_text(&layout, (80.0, 10.0));
         Ok(())
     })?;
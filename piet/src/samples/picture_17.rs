@@ -0,0 +1,69 @@
+//! Nested clips and fill rules.
+//!
+//! This tests that clips intersect, that a clip path's subpaths are combined
+//! with the nonzero rule, and that even-odd fills are clipped.
+//!
+//! 1. Left: a square ring filled with the even-odd rule, inside a circle
+//!    clip, inside a rectangle clip. Only the part of the ring inside both is
+//!    drawn, and its hole is black.
+//! 2. Right: a clip of two squares, one inside the other. Going the same way,
+//!    they clip to the outer square; with the inner one reversed, there is a
+//!    hole in the clip, which stays black.
+
+use crate::kurbo::{BezPath, Circle, Rect, Size};
+use crate::{Color, Error, RenderContext};
+
+pub const SIZE: Size = Size::new(200., 200.);
+
+const RED: Color = Color::rgb8(255, 0, 0);
+const GREEN: Color = Color::rgb8(0, 255, 0);
+const BLUE: Color = Color::rgb8(0, 0, 255);
+
+pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
+    rc.clear(None, Color::BLACK);
+
+    rc.save()?;
+    rc.clip(Rect::new(10., 10., 90., 190.));
+    rc.clip(Circle::new((50., 100.), 45.));
+    rc.fill_even_odd(ring(Rect::new(0., 55., 100., 145.), 20., false), &RED);
+    rc.restore()?;
+
+    rc.save()?;
+    rc.clip(ring(Rect::new(110., 10., 190., 90.), 20., false));
+    rc.fill(Rect::new(100., 0., 200., 100.), &GREEN);
+    rc.restore()?;
+
+    rc.save()?;
+    rc.clip(ring(Rect::new(110., 110., 190., 190.), 20., true));
+    rc.fill(Rect::new(100., 100., 200., 200.), &BLUE);
+    rc.restore()?;
+
+    Ok(())
+}
+
+/// A square with another square `width` inside it, optionally going the
+/// other way.
+fn ring(outer: Rect, width: f64, reverse_inner: bool) -> BezPath {
+    let inner = outer.inset(-width);
+    let mut path = BezPath::new();
+    path.move_to((outer.x0, outer.y0));
+    path.line_to((outer.x1, outer.y0));
+    path.line_to((outer.x1, outer.y1));
+    path.line_to((outer.x0, outer.y1));
+    path.close_path();
+    let mut corners = [
+        (inner.x0, inner.y0),
+        (inner.x1, inner.y0),
+        (inner.x1, inner.y1),
+        (inner.x0, inner.y1),
+    ];
+    if reverse_inner {
+        corners.reverse();
+    }
+    path.move_to(corners[0]);
+    for corner in &corners[1..] {
+        path.line_to(*corner);
+    }
+    path.close_path();
+    path
+}
@@ -0,0 +1,58 @@
+//! Saving and restoring, interleaved with transforms and clips.
+//!
+//! This tests that `restore` brings back both the transform and the clip
+//! that were saved, including when a clip was set after a transform.
+//!
+//! 1. Top: a red bar clipped to the left half, then, after restoring, a
+//!    green bar that isn't clipped.
+//! 2. Middle: nested saves, each translating and clipping. Restoring the
+//!    inner one brings back the outer clip and translation, so the yellow
+//!    square is clipped to the outer clip only.
+//! 3. Bottom: a rotated clip, restored before drawing the blue bar, which is
+//!    neither rotated nor clipped.
+
+use crate::kurbo::{Affine, Rect, Size};
+use crate::{Color, Error, RenderContext};
+
+pub const SIZE: Size = Size::new(200., 200.);
+
+const RED: Color = Color::rgb8(255, 0, 0);
+const GREEN: Color = Color::rgb8(0, 255, 0);
+const BLUE: Color = Color::rgb8(0, 0, 255);
+const YELLOW: Color = Color::rgb8(255, 255, 0);
+const GREY: Color = Color::grey8(128);
+
+pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
+    rc.clear(None, Color::BLACK);
+
+    rc.save()?;
+    rc.clip(Rect::new(0., 0., 100., 200.));
+    rc.fill(Rect::new(10., 10., 190., 30.), &RED);
+    rc.restore()?;
+    rc.fill(Rect::new(10., 35., 190., 45.), &GREEN);
+
+    rc.save()?;
+    rc.transform(Affine::translate((20., 60.)));
+    rc.clip(Rect::new(0., 0., 80., 60.));
+    rc.save()?;
+    rc.transform(Affine::translate((40., 0.)));
+    rc.clip(Rect::new(0., 0., 20., 20.));
+    rc.fill(Rect::new(-100., -100., 100., 100.), &GREY);
+    rc.restore()?;
+    // back in the outer state, so this is at (40, 80) and clipped to the
+    // outer clip, which ends at x = 100.
+    rc.fill(Rect::new(20., 20., 120., 50.), &YELLOW);
+    rc.restore()?;
+
+    rc.save()?;
+    rc.transform(Affine::rotate_about(
+        std::f64::consts::FRAC_PI_4,
+        (100., 160.).into(),
+    ));
+    rc.clip(Rect::new(90., 150., 110., 170.));
+    rc.fill(Rect::new(0., 0., 200., 200.), &RED);
+    rc.restore()?;
+    rc.fill(Rect::new(10., 185., 190., 195.), &BLUE);
+
+    Ok(())
+}
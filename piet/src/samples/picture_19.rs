@@ -0,0 +1,42 @@
+//! Very large and degenerate shapes.
+//!
+//! This tests that backends draw shapes far bigger than the context, and
+//! don't fail on shapes with no area.
+//!
+//! 1. A rectangle a million pixels wide, filling the top left.
+//! 2. The edge of a circle a million pixels across, as a diagonal line.
+//! 3. Empty rectangles and a path with no segments, which draw nothing, and
+//!    a line of zero length with round caps, which is a dot.
+
+use crate::kurbo::{BezPath, Circle, Line, Point, Rect, Size, Vec2};
+use crate::{Color, Error, LineCap, RenderContext, StrokeStyle};
+
+pub const SIZE: Size = Size::new(200., 200.);
+
+const RED: Color = Color::rgb8(255, 0, 0);
+const GREEN: Color = Color::rgb8(0, 255, 0);
+const BLUE: Color = Color::rgb8(0, 0, 255);
+
+pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
+    rc.clear(None, Color::BLACK);
+
+    rc.fill(Rect::new(-1e6, -1e6, 100., 100.), &RED);
+
+    // the center is far off the bottom right, so the edge crosses the
+    // context diagonally.
+    let radius = 5e5;
+    let center = Point::new(200., 200.) + Vec2::new(1., 1.) * (radius / 2f64.sqrt() - 20.);
+    rc.stroke(Circle::new(center, radius), &GREEN, 4.0);
+
+    rc.fill(Rect::new(120., 20., 120., 80.), &BLUE);
+    rc.fill(Rect::new(120., 20., 180., 20.), &BLUE);
+    let mut path = BezPath::new();
+    path.move_to((150., 50.));
+    rc.fill(&path, &BLUE);
+    rc.stroke(&path, &BLUE, 4.0);
+
+    let round = StrokeStyle::new().line_cap(LineCap::Round);
+    rc.stroke_styled(Line::new((50., 180.), (50., 180.)), &BLUE, 10.0, &round);
+
+    Ok(())
+}
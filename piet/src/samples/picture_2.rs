@@ -67,6 +67,7 @@ fn make_image_data(width: usize, height: usize, format: ImageFormat) -> Vec<u8>
                     result[ix + 2] = b;
                 }
                 ImageFormat::Grayscale => result[ix] = a,
+                _ => unreachable!("only the formats above are drawn"),
             }
         }
     }
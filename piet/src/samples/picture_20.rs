@@ -0,0 +1,53 @@
+//! Global alpha.
+//!
+//! This tests that the global alpha fades out fills, strokes, gradients and
+//! images, that it is saved and restored, and that faded primitives that
+//! overlap are both seen.
+//!
+//! 1. Top: two overlapping squares at half alpha, over a white bar; where
+//!    they overlap, the green one is drawn over the red one.
+//! 2. Middle: a gradient, a stroke, and an image, each at half alpha.
+//! 3. Bottom: after restoring, an opaque blue bar, and a blue bar drawn with
+//!    `fill_with_alpha` at a quarter alpha.
+
+use crate::kurbo::{Circle, Rect, Size};
+use crate::{
+    Color, Error, ImageFormat, InterpolationMode, LinearGradient, RenderContext, UnitPoint,
+};
+
+pub const SIZE: Size = Size::new(200., 200.);
+
+const RED: Color = Color::rgb8(255, 0, 0);
+const GREEN: Color = Color::rgb8(0, 255, 0);
+const BLUE: Color = Color::rgb8(0, 0, 255);
+const YELLOW: Color = Color::rgb8(255, 255, 0);
+
+pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
+    rc.clear(None, Color::BLACK);
+    rc.fill(Rect::new(0., 30., 200., 50.), &Color::WHITE);
+
+    rc.save()?;
+    rc.set_global_alpha(0.5);
+    rc.fill(Rect::new(20., 10., 80., 70.), &RED);
+    rc.fill(Rect::new(50., 10., 110., 70.), &GREEN);
+
+    let gradient = LinearGradient::new(UnitPoint::LEFT, UnitPoint::RIGHT, (YELLOW, BLUE));
+    rc.fill(Rect::new(10., 90., 70., 130.), &gradient);
+    rc.stroke(Circle::new((110., 110.), 16.), &Color::WHITE, 6.0);
+    let checker = [
+        255, 255, 255, 255, 0, 0, 0, 255, //
+        0, 0, 0, 255, 255, 255, 255, 255,
+    ];
+    let image = rc.make_image(2, 2, &checker, ImageFormat::RgbaSeparate)?;
+    rc.draw_image(
+        &image,
+        Rect::new(150., 90., 190., 130.),
+        InterpolationMode::NearestNeighbor,
+    );
+    rc.restore()?;
+
+    rc.fill(Rect::new(10., 150., 190., 165.), &BLUE);
+    rc.fill_with_alpha(Rect::new(10., 175., 190., 190.), &BLUE, 0.25);
+
+    Ok(())
+}
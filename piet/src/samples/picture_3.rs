@@ -19,8 +19,7 @@ pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
         for line_join in &[LineJoin::Bevel, LineJoin::default(), LineJoin::Round] {
             let width = 5.0;
             let mut style = StrokeStyle::new();
-            rc.with_save(|rc| {
-                rc.transform(Affine::translate((x, y)));
+            rc.with_transform(Affine::translate((x, y)), |rc| {
                 style.set_line_cap(*line_cap);
                 style.set_line_join(*line_join);
                 rc.stroke_styled(&path, &brush, width, &style);
@@ -33,7 +33,9 @@ use std::rc::Rc;
 /// ```
 ///
 /// [PLRMv3]: https://www.adobe.com/content/dam/acom/en/devnet/actionscript/articles/PLRM.pdf
-#[derive(Clone, PartialEq, Debug, Default)]
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct StrokeStyle {
     /// How to join segments of the path.
     ///
@@ -45,6 +47,15 @@ pub struct StrokeStyle {
     ///
     /// by default, this is [`LineCap::Butt`].
     pub line_cap: LineCap,
+    /// How to terminate the dashes of a dashed line, where they are not at
+    /// an end of the path.
+    ///
+    /// This allows, for instance, dashes with round ends on a line whose own
+    /// ends are square. Backends that can only draw one kind of cap draw
+    /// the caps separately when this differs from `line_cap`.
+    ///
+    /// By default, this is `None`, meaning that dashes use `line_cap`.
+    pub dash_cap: Option<LineCap>,
     /// The sequence of alternating dashes and gaps uses to draw the line.
     ///
     /// If the sequence is not empty, all numbers should be finite and
@@ -60,6 +71,20 @@ pub struct StrokeStyle {
     ///
     /// By default, this is `0.0`.
     pub dash_offset: f64,
+    /// Whether the stroke is affected by the current transform.
+    ///
+    /// If this is `false`, the stroke width (and the dash pattern) are
+    /// interpreted in device space, so that the stroke keeps a constant
+    /// on-screen width regardless of any [`transform`] calls. This is
+    /// sometimes called a 'hairline' or 'non-scaling' stroke. The geometry
+    /// of the path itself, and the brush, are still transformed. A scale that
+    /// the context was created with, such as for a high-DPI display, still
+    /// applies to the width.
+    ///
+    /// By default, this is `true`.
+    ///
+    /// [`transform`]: crate::RenderContext::transform
+    pub scale: bool,
 }
 
 /// A type that represents an alternating pattern of drawn and undrawn segments.
@@ -67,7 +92,7 @@ pub struct StrokeStyle {
 /// We use our own type as a way of making this work in `const` contexts.
 ///
 /// This type `Deref`s to `&[f64]`.
-#[derive(Debug, Default, Clone, PartialEq)]
+#[derive(Debug, Default, Clone)]
 pub struct StrokeDash {
     slice: &'static [f64],
     alloc: Option<Rc<[f64]>>,
@@ -75,6 +100,7 @@ pub struct StrokeDash {
 
 /// Options for angled joins in strokes.
 #[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LineJoin {
     /// The outer edges of the two paths are extended until they intersect.
     ///
@@ -109,6 +135,7 @@ impl LineJoin {
 
 /// Options for the cap of stroked lines.
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LineCap {
     /// The stroke is squared off at the endpoint of the path.
     Butt,
@@ -149,7 +176,9 @@ impl StrokeStyle {
                 limit: LineJoin::DEFAULT_MITER_LIMIT,
             },
             line_cap: LineCap::Butt,
+            dash_cap: None,
             dash_offset: 0.0,
+            scale: true,
         }
     }
 
@@ -169,6 +198,14 @@ impl StrokeStyle {
         self
     }
 
+    /// Builder-style method to set the [`dash_cap`].
+    ///
+    /// [`dash_cap`]: StrokeStyle#structfield.dash_cap
+    pub const fn dash_cap(mut self, dash_cap: LineCap) -> Self {
+        self.dash_cap = Some(dash_cap);
+        self
+    }
+
     /// Builder-style method to set the [`dash_offset`].
     ///
     /// [`dash_offset`]: StrokeStyle#structfield.dash_offset
@@ -190,6 +227,17 @@ impl StrokeStyle {
         self
     }
 
+    /// Builder-style method to set whether the stroke is affected by the
+    /// current transform.
+    ///
+    /// See [`scale`] for details.
+    ///
+    /// [`scale`]: #structfield.scale
+    pub const fn scale(mut self, scale: bool) -> Self {
+        self.scale = scale;
+        self
+    }
+
     /// Set the [`LineJoin`].
     pub fn set_line_join(&mut self, line_join: LineJoin) {
         self.line_join = line_join;
@@ -200,6 +248,11 @@ impl StrokeStyle {
         self.line_cap = line_cap;
     }
 
+    /// Set the [`LineCap`] of dashes, or `None` to use the line cap.
+    pub fn set_dash_cap(&mut self, dash_cap: Option<LineCap>) {
+        self.dash_cap = dash_cap;
+    }
+
     /// Set the dash offset.
     pub fn set_dash_offset(&mut self, offset: f64) {
         self.dash_offset = offset;
@@ -215,6 +268,16 @@ impl StrokeStyle {
         self.dash_pattern.alloc = Some(lengths.into());
     }
 
+    /// Set whether the stroke is affected by the current transform.
+    pub fn set_scale(&mut self, scale: bool) {
+        self.scale = scale;
+    }
+
+    /// The [`LineCap`] of the ends of dashes that are not ends of the path.
+    pub fn effective_dash_cap(&self) -> LineCap {
+        self.dash_cap.unwrap_or(self.line_cap)
+    }
+
     /// If the current [`LineJoin`] is [`LineJoin::Miter`] return the miter limit.
     pub fn miter_limit(&self) -> Option<f64> {
         match self.line_join {
@@ -224,6 +287,12 @@ impl StrokeStyle {
     }
 }
 
+impl Default for StrokeStyle {
+    fn default() -> Self {
+        StrokeStyle::new()
+    }
+}
+
 impl Default for LineJoin {
     fn default() -> Self {
         LineJoin::Miter {
@@ -244,3 +313,50 @@ impl std::ops::Deref for StrokeDash {
         self.alloc.as_deref().unwrap_or(self.slice)
     }
 }
+
+/// Dash patterns are equal if they have the same lengths, whether they were
+/// allocated or not.
+impl PartialEq for StrokeDash {
+    fn eq(&self, other: &StrokeDash) -> bool {
+        **self == **other
+    }
+}
+
+/// A dash pattern is serialized as a sequence of lengths.
+#[cfg(feature = "serde")]
+impl serde::Serialize for StrokeDash {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for StrokeDash {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let lengths = Vec::<f64>::deserialize(deserializer)?;
+        Ok(StrokeDash {
+            slice: &[],
+            alloc: Some(lengths.into()),
+        })
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stroke_style_serde() {
+        let style = StrokeStyle::new()
+            .dash_pattern(&[4.0, 2.0])
+            .line_join(LineJoin::Round)
+            .dash_cap(LineCap::Square);
+        let json = serde_json::to_string(&style).unwrap();
+        assert_eq!(serde_json::from_str::<StrokeStyle>(&json).unwrap(), style);
+
+        // missing fields have their defaults.
+        let style: StrokeStyle = serde_json::from_str(r#"{ "dash_pattern": [1.0] }"#).unwrap();
+        assert_eq!(&*style.dash_pattern, &[1.0]);
+        assert_eq!(style.line_join, LineJoin::default());
+    }
+}
@@ -0,0 +1,258 @@
+//! Rounded rectangles with superellipse corners.
+
+use std::f64::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+
+use kurbo::{BezPath, PathEl, Point, Rect, RoundedRect, RoundedRectRadii, Shape, Vec2};
+
+/// A rectangle whose corners are rounded with quarters of a superellipse,
+/// often called a squircle.
+///
+/// Each corner is a quarter of the curve `|x|ⁿ + |y|ⁿ = rⁿ`, where `r` is
+/// the radius of that corner and `n` is the exponent. An exponent of `2`
+/// gives circular corners, so the shape is exactly a [`RoundedRect`]; larger
+/// exponents give corners that meet the sides more gradually, and look
+/// smoother than circular ones at the same radius.
+///
+/// Like a [`RoundedRect`], each corner can have its own radius, which is
+/// clamped to half of the shorter side.
+///
+/// When the shape is exactly a rounded rectangle or a rectangle,
+/// [`Shape::as_rounded_rect`] and [`Shape::as_rect`] return it, so backends
+/// that draw those natively can draw it without converting it to a path.
+/// Otherwise the corners are approximated with cubic Béziers, within the
+/// tolerance passed to [`Shape::path_elements`].
+///
+/// ```
+/// use piet::kurbo::{Rect, Shape};
+/// use piet::Squircle;
+///
+/// let rect = Rect::new(0.0, 0.0, 100.0, 60.0);
+/// let squircle = Squircle::new(rect, 20.0);
+/// assert!(squircle.as_rounded_rect().is_none());
+///
+/// let rounded = squircle.with_exponent(2.0);
+/// assert_eq!(rounded.as_rounded_rect(), Some(rect.to_rounded_rect(20.0)));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Squircle {
+    rounded_rect: RoundedRect,
+    exponent: f64,
+}
+
+impl Squircle {
+    /// The exponent of the corners of a new `Squircle`.
+    pub const DEFAULT_EXPONENT: f64 = 4.0;
+
+    /// A new squircle from a rectangle and the radii of its corners, with
+    /// the [default exponent](Squircle::DEFAULT_EXPONENT).
+    pub fn new(rect: impl Into<Rect>, radii: impl Into<RoundedRectRadii>) -> Squircle {
+        Squircle {
+            rounded_rect: RoundedRect::from_rect(rect.into(), radii),
+            exponent: Squircle::DEFAULT_EXPONENT,
+        }
+    }
+
+    /// Builder-style method to set the exponent of the corners.
+    ///
+    /// The exponent is clamped to be at least `1`, which gives straight
+    /// corners that are cut off at 45°.
+    pub fn with_exponent(mut self, exponent: f64) -> Squircle {
+        self.exponent = exponent.max(1.0);
+        self
+    }
+
+    /// The rectangle that the squircle fills.
+    pub fn rect(&self) -> Rect {
+        self.rounded_rect.rect()
+    }
+
+    /// The radii of the corners.
+    pub fn radii(&self) -> RoundedRectRadii {
+        self.rounded_rect.radii()
+    }
+
+    /// The exponent of the corners.
+    pub fn exponent(&self) -> f64 {
+        self.exponent
+    }
+
+    /// The corners, clockwise from the top left, each as the center of
+    /// its curve, its radius and the angle at which it starts.
+    fn corners(&self) -> [(Point, f64, f64); 4] {
+        let Rect { x0, y0, x1, y1 } = self.rect();
+        let radii = self.radii();
+        let (tl, tr, br, bl) = (
+            radii.top_left,
+            radii.top_right,
+            radii.bottom_right,
+            radii.bottom_left,
+        );
+        [
+            (Point::new(x0 + tl, y0 + tl), tl, PI),
+            (Point::new(x1 - tr, y0 + tr), tr, -FRAC_PI_2),
+            (Point::new(x1 - br, y1 - br), br, 0.0),
+            (Point::new(x0 + bl, y1 - bl), bl, FRAC_PI_2),
+        ]
+    }
+
+    /// The point at `angle` on a corner of `radius` around the origin, and
+    /// its derivative with respect to the angle.
+    fn corner_point(&self, radius: f64, angle: f64) -> (Vec2, Vec2) {
+        let n = self.exponent;
+        let (sin, cos) = angle.sin_cos();
+        let (c, s) = (cos.abs(), sin.abs());
+        let sum = c.powf(n) + s.powf(n);
+        let rho = radius / sum.powf(1.0 / n);
+        let d_rho = rho
+            * (c.powf(n - 1.0) * cos.signum() * sin - s.powf(n - 1.0) * sin.signum() * cos)
+            / sum;
+        let point = Vec2::new(cos, sin) * rho;
+        let deriv = Vec2::new(d_rho * cos - rho * sin, d_rho * sin + rho * cos);
+        (point, deriv)
+    }
+
+    /// The number of cubics to approximate a corner of `radius` with.
+    fn n_segments(&self, radius: f64, tolerance: f64) -> usize {
+        // the error of each cubic shrinks with the fourth power of the angle
+        // it spans; corners with larger exponents bend more sharply.
+        let n = (radius / tolerance.max(1e-9)).powf(0.25) * (self.exponent / 4.0).max(0.5);
+        (n.ceil() as usize).clamp(1, 64)
+    }
+}
+
+impl From<RoundedRect> for Squircle {
+    /// A squircle that is exactly `rounded_rect`, with an exponent of `2`.
+    fn from(rounded_rect: RoundedRect) -> Squircle {
+        Squircle {
+            rounded_rect,
+            exponent: 2.0,
+        }
+    }
+}
+
+impl Shape for Squircle {
+    type PathElementsIter<'iter> = std::vec::IntoIter<PathEl>;
+
+    fn path_elements(&self, tolerance: f64) -> Self::PathElementsIter<'_> {
+        if let Some(rounded_rect) = self.as_rounded_rect() {
+            return rounded_rect.to_path(tolerance).into_iter();
+        }
+        let mut path = BezPath::new();
+        for (i, (center, radius, start)) in self.corners().iter().copied().enumerate() {
+            let (point, _) = self.corner_point(radius, start);
+            if i == 0 {
+                path.move_to(center + point);
+            } else {
+                path.line_to(center + point);
+            }
+            if radius <= 0.0 {
+                continue;
+            }
+            let n = self.n_segments(radius, tolerance);
+            let step = FRAC_PI_2 / n as f64;
+            for j in 0..n {
+                let a0 = start + step * j as f64;
+                let (p0, d0) = self.corner_point(radius, a0);
+                let (p1, d1) = self.corner_point(radius, a0 + step);
+                path.curve_to(
+                    center + p0 + d0 * (step / 3.0),
+                    center + p1 - d1 * (step / 3.0),
+                    center + p1,
+                );
+            }
+        }
+        path.close_path();
+        path.into_iter()
+    }
+
+    fn area(&self) -> f64 {
+        match self.as_rounded_rect() {
+            Some(rounded_rect) => rounded_rect.area(),
+            None => self.to_path(1e-3).area(),
+        }
+    }
+
+    fn perimeter(&self, accuracy: f64) -> f64 {
+        match self.as_rounded_rect() {
+            Some(rounded_rect) => rounded_rect.perimeter(accuracy),
+            None => self.to_path(accuracy).perimeter(accuracy),
+        }
+    }
+
+    fn winding(&self, pt: Point) -> i32 {
+        if !self.rect().contains(pt) {
+            return 0;
+        }
+        let n = self.exponent;
+        for &(center, radius, start) in self.corners().iter() {
+            // the quadrant of the corner that is outside of the other corners.
+            let (sin, cos) = (start + FRAC_PI_4).sin_cos();
+            let d = pt - center;
+            if d.x * cos.signum() > 0.0 && d.y * sin.signum() > 0.0 {
+                let inside = (d.x.abs() / radius).powf(n) + (d.y.abs() / radius).powf(n) <= 1.0;
+                return inside as i32;
+            }
+        }
+        1
+    }
+
+    fn bounding_box(&self) -> Rect {
+        self.rect()
+    }
+
+    fn as_rect(&self) -> Option<Rect> {
+        let radii = self.radii();
+        let is_rect = radii.top_left == 0.0
+            && radii.top_right == 0.0
+            && radii.bottom_right == 0.0
+            && radii.bottom_left == 0.0;
+        is_rect.then(|| self.rect())
+    }
+
+    fn as_rounded_rect(&self) -> Option<RoundedRect> {
+        (self.exponent == 2.0).then_some(self.rounded_rect)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn squircle_is_between_rounded_rect_and_rect() {
+        let rect = Rect::new(10.0, 20.0, 110.0, 80.0);
+        let rounded_rect = RoundedRect::from_rect(rect, (30.0, 10.0, 0.0, 20.0));
+        let squircle = Squircle::new(rect, (30.0, 10.0, 0.0, 20.0));
+        assert_eq!(squircle.radii(), rounded_rect.radii());
+        assert!(squircle.as_rounded_rect().is_none());
+        assert!(squircle.area() > rounded_rect.area() + 1.0);
+        assert!(squircle.area() < rect.area() - 1.0);
+
+        // a point that the circular corner cuts off, but the squircle doesn't.
+        let pt = Point::new(17.0, 27.0);
+        assert_eq!(rounded_rect.winding(pt), 0);
+        assert_eq!(squircle.winding(pt), 1);
+        assert_eq!(squircle.to_path(1e-3).winding(pt), 1);
+        assert_eq!(squircle.winding(Point::new(11.0, 21.0)), 0);
+        assert_eq!(squircle.winding(Point::new(109.0, 79.0)), 1);
+
+        let bounds = squircle.to_path(1e-3).bounding_box();
+        assert_eq!(
+            rect.inflate(1e-3, 1e-3).union(bounds),
+            rect.inflate(1e-3, 1e-3)
+        );
+        assert_eq!(bounds.union(rect.inflate(-1e-3, -1e-3)), bounds);
+    }
+
+    #[test]
+    fn exact_shapes_are_hinted() {
+        let rect = Rect::new(0.0, 0.0, 40.0, 20.0);
+        let rounded = Squircle::new(rect, 5.0).with_exponent(2.0);
+        assert_eq!(rounded.as_rounded_rect(), Some(rect.to_rounded_rect(5.0)));
+        let rounded: Squircle = rect.to_rounded_rect(5.0).into();
+        assert_eq!(rounded.exponent(), 2.0);
+        assert!(rounded.as_rounded_rect().is_some());
+        assert_eq!(Squircle::new(rect, 0.0).as_rect(), Some(rect));
+        assert_eq!(Squircle::new(rect, 5.0).as_rect(), None);
+    }
+}
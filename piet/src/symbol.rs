@@ -0,0 +1,61 @@
+//! Symbols that are stamped many times, such as the markers of a plot.
+
+use std::fmt;
+
+use kurbo::{Rect, Shape};
+
+use crate::{InterpolationMode, PreparedShape};
+
+/// What [`RenderContext::draw_symbol_instances`] draws at each instance.
+///
+/// `I` is the image type of the render context.
+///
+/// [`RenderContext::draw_symbol_instances`]: crate::RenderContext::draw_symbol_instances
+pub enum Symbol<'a, I> {
+    /// A shape, which is filled at each instance.
+    ///
+    /// The shape is prepared, with [`RenderContext::prepare_shape`], so that
+    /// backends only need to build their form of it once.
+    ///
+    /// [`RenderContext::prepare_shape`]: crate::RenderContext::prepare_shape
+    Shape(&'a PreparedShape),
+    /// An image, which is drawn into `rect` at each instance.
+    Image {
+        /// The image.
+        image: &'a I,
+        /// Where the image is drawn, before the transform of the instance.
+        rect: Rect,
+        /// How the image is scaled into `rect`.
+        interp: InterpolationMode,
+    },
+}
+
+impl<'a, I> Symbol<'a, I> {
+    /// The bounds of the symbol, before the transform of an instance.
+    pub fn bounds(&self) -> Rect {
+        match self {
+            Symbol::Shape(shape) => shape.bounding_box(),
+            Symbol::Image { rect, .. } => rect.abs(),
+        }
+    }
+}
+
+impl<'a, I> Clone for Symbol<'a, I> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, I> Copy for Symbol<'a, I> {}
+
+impl<'a, I> fmt::Debug for Symbol<'a, I> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Symbol::Shape(shape) => f.debug_tuple("Shape").field(shape).finish(),
+            Symbol::Image { rect, .. } => f
+                .debug_struct("Image")
+                .field("rect", rect)
+                .finish_non_exhaustive(),
+        }
+    }
+}
@@ -1,9 +1,12 @@
 //! Traits for fonts and text handling.
 
 use std::ops::{Range, RangeBounds};
+use std::sync::Arc;
 
-use crate::kurbo::{Point, Rect, Size};
-use crate::{Color, Error, FontFamily, FontStyle, FontWeight};
+use unicode_segmentation::GraphemeCursor;
+
+use crate::kurbo::{Affine, BezPath, Point, Rect, Size};
+use crate::{Color, Error, FontFamily, FontFeature, FontStyle, FontVariation, FontWeight};
 
 /// The Piet text API.
 ///
@@ -105,6 +108,44 @@ pub trait Text: Clone {
     ///
     /// [`TextLayoutBuilder`]: trait.TextLayoutBuilder.html
     fn new_text_layout(&mut self, text: impl TextStorage) -> Self::TextLayoutBuilder;
+
+    /// Set how text is fitted to the pixel grid when it is drawn.
+    ///
+    /// Depending on the backend, this may only affect layouts created after
+    /// it is called. The default is [`Hinting::Default`].
+    /// Backends that do not support this may ignore it.
+    fn set_hinting(&mut self, hinting: Hinting) {
+        let _ = hinting;
+    }
+
+    /// Set whether glyphs that fonts have in color, such as emoji, are drawn
+    /// in color, rather than as outlines in the text color.
+    ///
+    /// The default is `true`. Backends that can only draw one or the other
+    /// may ignore this.
+    fn set_color_glyphs(&mut self, color: bool) {
+        let _ = color;
+    }
+}
+
+/// How text is fitted to the pixel grid, trading crispness for fidelity.
+///
+/// This is set with [`Text::set_hinting`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Hinting {
+    /// Whatever the backend or platform does by default.
+    #[default]
+    Default,
+    /// Glyphs are snapped to whole pixels and their outlines are hinted.
+    ///
+    /// This makes small text crisper, at the cost of the font's designed
+    /// shapes and spacing.
+    Pixel,
+    /// Glyphs are placed at fractional pixel positions and are not hinted.
+    ///
+    /// Text keeps its designed shapes and spacing at any size and scale, but
+    /// small text may look blurrier.
+    Subpixel,
 }
 
 /// A type that stores text.
@@ -142,7 +183,7 @@ impl std::ops::Deref for dyn TextStorage {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 /// Attributes that can be applied to text.
 pub enum TextAttribute {
     /// The font family.
@@ -153,6 +194,10 @@ pub enum TextAttribute {
     Weight(FontWeight),
     /// The foreground color of the text.
     TextColor(crate::Color),
+    /// The color drawn behind the text, such as for highlighting.
+    ///
+    /// The default is [`Color::TRANSPARENT`], which draws no background.
+    BackgroundColor(crate::Color),
     /// The [`FontStyle`]; either regular or italic.
     ///
     /// [`FontStyle`]: enum.FontStyle.html
@@ -161,6 +206,34 @@ pub enum TextAttribute {
     Underline(bool),
     /// Strikethrough.
     Strikethrough(bool),
+    /// Extra space, in display points, added after each character.
+    ///
+    /// Negative values tighten the text.
+    LetterSpacing(f64),
+    /// Extra space, in display points, added to each word separator.
+    ///
+    /// This is applied in addition to any letter spacing.
+    WordSpacing(f64),
+    /// Values for the axes of a variable font.
+    ///
+    /// This replaces any previously set variations, so all axes that should
+    /// be set for a range must be included.
+    FontVariations(Arc<[FontVariation]>),
+    /// OpenType features to enable or disable.
+    ///
+    /// Like [`TextAttribute::FontVariations`], this replaces any previously
+    /// set features.
+    FontFeatures(Arc<[FontFeature]>),
+    /// A vertical offset of the baseline, in display points.
+    ///
+    /// Positive values raise the text.
+    BaselineShift(f64),
+    /// Draw the text as a superscript or subscript.
+    ///
+    /// This scales the font and shifts the baseline relative to the font size
+    /// in effect for the range; see [`ScriptPosition`] for the exact amounts.
+    /// It is applied in addition to any [`TextAttribute::BaselineShift`].
+    ScriptPosition(ScriptPosition),
 }
 
 /// A trait for laying out text.
@@ -177,11 +250,136 @@ pub trait TextLayoutBuilder: Sized {
     /// default behaviour.
     fn max_width(self, width: f64) -> Self;
 
+    /// Set a max height for this layout.
+    ///
+    /// Lines that do not fit within this height are handled according to
+    /// the layout's [`Overflow`] mode. The default is `f64::INFINITY`.
+    /// Backends that do not support this may ignore it.
+    fn max_height(self, height: f64) -> Self {
+        let _ = height;
+        self
+    }
+
+    /// Set the [`Overflow`] mode, which determines what happens to text that
+    /// does not fit within the layout's [`max_height`].
+    ///
+    /// The default is [`Overflow::Visible`].
+    /// Backends that do not support this may ignore it.
+    ///
+    /// [`max_height`]: TextLayoutBuilder::max_height
+    fn overflow(self, overflow: Overflow) -> Self {
+        let _ = overflow;
+        self
+    }
+
     /// Set the [`TextAlignment`] to be used for this layout.
     ///
     /// [`TextAlignment`]: enum.TextAlignment.html
     fn alignment(self, alignment: TextAlignment) -> Self;
 
+    /// Set the [`TextAlignment`] of the paragraphs that begin in `range`,
+    /// overriding the layout's [`alignment`] for them.
+    ///
+    /// A paragraph ends at each hard line break, so that, for example, a
+    /// heading can be centered above left-aligned text in one layout.
+    /// Backends that do not support this may ignore it.
+    ///
+    /// [`alignment`]: TextLayoutBuilder::alignment
+    fn paragraph_alignment(self, range: impl RangeBounds<usize>, alignment: TextAlignment) -> Self {
+        let _ = (range, alignment);
+        self
+    }
+
+    /// Set the [`WritingMode`] to be used for this layout.
+    ///
+    /// The default is [`WritingMode::HorizontalTb`].
+    /// Backends that do not support vertical text may ignore it.
+    fn writing_mode(self, mode: WritingMode) -> Self {
+        let _ = mode;
+        self
+    }
+
+    /// Set the [`LineHeight`] to be used for this layout.
+    ///
+    /// The default is [`LineHeight::Normal`], which uses the natural height
+    /// of the font. Backends that do not support this may ignore it.
+    fn line_height(self, line_height: LineHeight) -> Self {
+        let _ = line_height;
+        self
+    }
+
+    /// Set additional space, in display points, to insert after each paragraph.
+    ///
+    /// A paragraph ends at each hard line break. The default is `0.0`.
+    /// Backends that do not support this may ignore it.
+    fn paragraph_spacing(self, spacing: f64) -> Self {
+        let _ = spacing;
+        self
+    }
+
+    /// Set the positions of the tab stops used for this layout.
+    ///
+    /// Positions are in display points from the start of each line, and must
+    /// be in increasing order. A tab character advances to the next stop;
+    /// past the last stop, tabs advance to the next multiple of the
+    /// [`tab_width`].
+    ///
+    /// Backends that do not support this may ignore it.
+    ///
+    /// [`tab_width`]: TextLayoutBuilder::tab_width
+    fn tab_stops(self, stops: &[f64]) -> Self {
+        let _ = stops;
+        self
+    }
+
+    /// Set the distance between the default tab stops, in display points.
+    ///
+    /// These are used for tabs past the last of the explicit [`tab_stops`].
+    /// If this is not set, the platform's default is used; this is typically
+    /// around eight spaces.
+    ///
+    /// Backends that do not support this may ignore it.
+    ///
+    /// [`tab_stops`]: TextLayoutBuilder::tab_stops
+    fn tab_width(self, width: f64) -> Self {
+        let _ = width;
+        self
+    }
+
+    /// Set a function used to find hyphenation points when wrapping lines.
+    ///
+    /// The function is called with each word of the text, and returns the
+    /// byte offsets within that word at which it may be broken. When a line
+    /// is broken at one of these points, a hyphen is drawn at the end of the
+    /// line. Soft hyphens (`U+00AD`) in the text are treated the same way.
+    ///
+    /// This is only supported by piet-web and piet-webgl, which break lines
+    /// themselves. The other backends ignore it: piet-cairo,
+    /// piet-coregraphics, piet-direct2d and piet-wgpu break lines without
+    /// it, and piet-svg doesn't break lines at all.
+    fn hyphenator(self, hyphenator: impl Fn(&str) -> Vec<usize> + 'static) -> Self {
+        let _ = hyphenator;
+        self
+    }
+
+    /// Reserve a gap of `size`, in display points, before the text at
+    /// `index`, in which to draw something that isn't text, such as an icon
+    /// or an image of an emoji.
+    ///
+    /// The gap is laid out as a word of its own, and its line is made tall
+    /// enough to hold it; `alignment` sets where it sits on the line. Nothing
+    /// is drawn in it: the gaps are found with [`TextLayout::inline_boxes`]
+    /// once the layout is built, to draw into.
+    ///
+    /// Inline boxes are supported by the cairo, svg and wgpu backends. The
+    /// web, Core Graphics and Direct2D backends don't support them yet, and
+    /// by default the box is ignored: the text is laid out without a gap, and
+    /// [`TextLayout::inline_boxes`] returns no rects for it.
+    fn inline_box(self, index: usize, size: Size, alignment: BaselineAlignment) -> Self {
+        let _ = (index, size, alignment);
+        self
+    }
+
     /// A convenience method for setting the default font family and size.
     ///
     /// # Examples
@@ -299,10 +497,160 @@ pub enum TextAlignment {
     /// Line width is increased to fill available space.
     ///
     /// This may be achieved through increases in word or character spacing,
-    /// or through ligatures where available.
+    /// or through ligatures where available. The last line of a paragraph is
+    /// aligned to the start instead, and so is all text in backends that
+    /// don't wrap lines, such as piet-svg, where every layout is one line.
     Justified,
 }
 
+/// The direction in which lines of text are laid out in a [`TextLayout`].
+///
+/// These correspond to the CSS [`writing-mode`] property.
+///
+/// In vertical modes the layout's [`max_width`] constrains the length of each
+/// column, and [`LineMetric`]s describe columns rather than rows: a line's
+/// `y_offset` is its distance from the right edge of the layout, and its
+/// `height` is the width of the column. Points passed to and returned from
+/// hit testing are in the layout's (vertical) coordinate space.
+///
+/// [`writing-mode`]: https://developer.mozilla.org/en-US/docs/Web/CSS/writing-mode
+/// [`max_width`]: TextLayoutBuilder::max_width
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WritingMode {
+    /// Lines are horizontal, and are stacked from top to bottom.
+    #[default]
+    HorizontalTb,
+    /// Lines are vertical, and are stacked from right to left.
+    ///
+    /// Characters from CJK scripts are drawn upright; other text is rotated
+    /// 90° clockwise.
+    VerticalRl,
+}
+
+impl WritingMode {
+    /// Returns `true` if this is a vertical writing mode.
+    pub fn is_vertical(self) -> bool {
+        matches!(self, WritingMode::VerticalRl)
+    }
+
+    /// The transform from a horizontal layout's coordinate space to the
+    /// coordinate space of a layout in this mode.
+    ///
+    /// `horizontal_size` is the size of the layout before rotation.
+    pub fn transform(self, horizontal_size: Size) -> Affine {
+        match self {
+            WritingMode::HorizontalTb => Affine::IDENTITY,
+            WritingMode::VerticalRl => {
+                Affine::translate((horizontal_size.height, 0.0))
+                    * Affine::rotate(std::f64::consts::FRAC_PI_2)
+            }
+        }
+    }
+}
+
+/// How a [`TextLayout`] handles text that does not fit its max height.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// All lines are laid out, even if they exceed the max height.
+    #[default]
+    Visible,
+    /// Lines that do not fit are removed from the layout.
+    Clip,
+    /// Lines that do not fit are removed, and the last remaining line is
+    /// truncated and ends with an ellipsis ("…").
+    ///
+    /// The ellipsis is part of the layout's text, and so is visible to
+    /// methods such as [`TextLayout::line_text`] and hit testing.
+    Ellipsis,
+}
+
+/// The height of each line in a [`TextLayout`].
+///
+/// When a line is taller than its content, the extra space is split evenly
+/// above and below the text.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LineHeight {
+    /// Use the natural line height of the font.
+    #[default]
+    Normal,
+    /// A multiple of the font size.
+    Relative(f64),
+    /// An absolute height, in display points.
+    Absolute(f64),
+}
+
+impl LineHeight {
+    /// Resolve this to a height in display points, given the font size and
+    /// the natural height of the line.
+    pub fn resolve(self, font_size: f64, natural_height: f64) -> f64 {
+        match self {
+            LineHeight::Normal => natural_height,
+            LineHeight::Relative(factor) => factor * font_size,
+            LineHeight::Absolute(height) => height,
+        }
+    }
+}
+
+/// The vertical position of text relative to the surrounding text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScriptPosition {
+    /// Text sits on the normal baseline.
+    #[default]
+    Normal,
+    /// Text is smaller and raised, as in "x²".
+    Superscript,
+    /// Text is smaller and lowered, as in "CO₂".
+    Subscript,
+}
+
+impl ScriptPosition {
+    /// The factor by which the font size is scaled.
+    pub fn font_scale(self) -> f64 {
+        match self {
+            ScriptPosition::Normal => 1.0,
+            ScriptPosition::Superscript | ScriptPosition::Subscript => 0.65,
+        }
+    }
+
+    /// The baseline shift in display points, for text whose unscaled font
+    /// size is `font_size`. Positive values raise the text.
+    pub fn baseline_shift(self, font_size: f64) -> f64 {
+        match self {
+            ScriptPosition::Normal => 0.0,
+            ScriptPosition::Superscript => 0.35 * font_size,
+            ScriptPosition::Subscript => -0.15 * font_size,
+        }
+    }
+}
+
+/// How an inline box, added with [`TextLayoutBuilder::inline_box`], sits on
+/// its line.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum BaselineAlignment {
+    /// The bottom of the box is on the baseline, as images are in CSS.
+    #[default]
+    Bottom,
+    /// The box is centered on the text, halfway between the font's ascent and
+    /// descent.
+    Center,
+    /// The box has a baseline of its own, this far below its top, which is
+    /// on the line's baseline.
+    Baseline(f64),
+}
+
+impl BaselineAlignment {
+    /// The distance from the top of a box of `height` to the line's
+    /// baseline, on a line whose font rises `ascent` above the baseline and
+    /// falls `descent` below it.
+    pub fn box_ascent(self, height: f64, ascent: f64, descent: f64) -> f64 {
+        match self {
+            BaselineAlignment::Bottom => height,
+            BaselineAlignment::Center => (height + ascent - descent) / 2.0,
+            BaselineAlignment::Baseline(baseline) => baseline,
+        }
+    }
+}
+
 /// A drawable text object.
 ///
 /// ## Line Breaks
@@ -432,8 +780,14 @@ pub trait TextLayout: Clone {
     ///
     /// `range` will be clamped to the length of the text if necessary.
     ///
-    /// Note: this implementation is not currently BiDi aware; it will be updated
-    /// when BiDi support is added.
+    /// There is at least one rect for each line the range touches; in
+    /// bidirectional text, a line may have several, one for each run of
+    /// the range that is visually contiguous.
+    ///
+    /// Note: the default implementation is built on [`hit_test_text_position`]
+    /// and is not BiDi aware; backends should override it where they can.
+    ///
+    /// [`hit_test_text_position`]: TextLayout::hit_test_text_position
     fn rects_for_range(&self, range: impl RangeBounds<usize>) -> Vec<Rect> {
         let text_len = self.text().len();
         let mut range = crate::util::resolve_range(range, text_len);
@@ -478,6 +832,154 @@ pub trait TextLayout: Clone {
         }
         result
     }
+
+    /// Returns the positioned glyphs of this layout, grouped into runs that
+    /// share a single font.
+    ///
+    /// This is intended for clients that want to do their own glyph
+    /// rendering, for instance with a GPU glyph atlas, without re-shaping
+    /// the text.
+    ///
+    /// Backends that do not expose their shaping output return an empty `Vec`.
+    fn glyph_runs(&self) -> Vec<GlyphRun> {
+        Vec::new()
+    }
+
+    /// Returns the outlines of all the glyphs in this layout, as a single path.
+    ///
+    /// The path is in the layout's coordinate space, so it can be filled,
+    /// stroked, or used as a clip at the same position the layout would be
+    /// drawn at with [`RenderContext::draw_text`].
+    ///
+    /// Backends that cannot produce glyph outlines return an empty path.
+    ///
+    /// [`RenderContext::draw_text`]: crate::RenderContext::draw_text
+    fn outline(&self) -> BezPath {
+        BezPath::new()
+    }
+
+    /// Lay the text out again to fit `width`, as if the layout had been
+    /// built with that [`max_width`], and return `true`.
+    ///
+    /// This is quicker than building a new layout, such as while a panel of
+    /// text is resized, because the text isn't shaped again; only its lines
+    /// are broken again. Backends that can't do this return `false`, and
+    /// leave the layout as it is, in which case a new layout needs to be
+    /// built with the new width.
+    ///
+    /// [`max_width`]: TextLayoutBuilder::max_width
+    fn relayout_width(&mut self, width: f64) -> bool {
+        let _ = width;
+        false
+    }
+
+    /// The gaps reserved with [`TextLayoutBuilder::inline_box`], in the
+    /// order they were added, in the layout's coordinate space.
+    ///
+    /// Backends that do not support inline boxes return an empty `Vec`.
+    fn inline_boxes(&self) -> Vec<Rect> {
+        Vec::new()
+    }
+
+    /// Returns the text position of the next caret stop after `offset`.
+    ///
+    /// Caret stops are grapheme cluster boundaries, so this will never move
+    /// the caret into the middle of a cluster (or a `\r\n` pair). If `offset`
+    /// is at or past the end of the text, the length of the text is returned.
+    fn next_caret(&self, offset: usize) -> usize {
+        let text = self.text();
+        let offset = offset.min(text.len());
+        GraphemeCursor::new(offset, text.len(), true)
+            .next_boundary(text, 0)
+            .ok()
+            .flatten()
+            .unwrap_or(text.len())
+    }
+
+    /// Returns the text position of the previous caret stop before `offset`.
+    ///
+    /// This is the inverse of [`next_caret`]; if `offset` is `0`, `0` is returned.
+    ///
+    /// [`next_caret`]: TextLayout::next_caret
+    fn prev_caret(&self, offset: usize) -> usize {
+        let text = self.text();
+        let offset = offset.min(text.len());
+        GraphemeCursor::new(offset, text.len(), true)
+            .prev_boundary(text, 0)
+            .ok()
+            .flatten()
+            .unwrap_or(0)
+    }
+
+    /// Returns the text position and location of the caret when it is moved
+    /// up a line from `offset`.
+    ///
+    /// `x` is the horizontal position the caret should try to stay at; editors
+    /// usually remember this across consecutive vertical movements. If `offset`
+    /// is on the first line, the caret moves to the start of the text.
+    fn caret_up(&self, offset: usize, x: f64) -> (usize, HitTestPosition) {
+        let line = self.hit_test_text_position(offset).line;
+        let idx = match line.checked_sub(1).and_then(|line| self.line_metric(line)) {
+            Some(metric) => {
+                let y = metric.y_offset + metric.height / 2.0;
+                self.hit_test_point(Point::new(x, y)).idx
+            }
+            None => 0,
+        };
+        (idx, self.hit_test_text_position(idx))
+    }
+
+    /// Returns the text position and location of the caret when it is moved
+    /// down a line from `offset`.
+    ///
+    /// This is the counterpart of [`caret_up`]; if `offset` is on the last line,
+    /// the caret moves to the end of the text.
+    ///
+    /// [`caret_up`]: TextLayout::caret_up
+    fn caret_down(&self, offset: usize, x: f64) -> (usize, HitTestPosition) {
+        let line = self.hit_test_text_position(offset).line;
+        let idx = match self.line_metric(line + 1) {
+            Some(metric) => {
+                let y = metric.y_offset + metric.height / 2.0;
+                self.hit_test_point(Point::new(x, y)).idx
+            }
+            None => self.text().len(),
+        };
+        (idx, self.hit_test_text_position(idx))
+    }
+}
+
+/// A sequence of glyphs in a single font, returned by [`TextLayout::glyph_runs`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct GlyphRun {
+    /// The font family of these glyphs, as requested in the layout's attributes.
+    pub font: FontFamily,
+    /// The font size, in display points.
+    pub font_size: f64,
+    /// The font weight.
+    pub weight: FontWeight,
+    /// The font style.
+    pub style: FontStyle,
+    /// The number of the line containing this run.
+    pub line: usize,
+    /// The glyphs in this run, in visual order.
+    pub glyphs: Vec<Glyph>,
+}
+
+/// A single positioned glyph in a [`GlyphRun`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Glyph {
+    /// The id of the glyph in its font.
+    pub id: u32,
+    /// The position of the glyph's origin relative to the layout's origin.
+    ///
+    /// The `y` value is on the baseline of the glyph's line.
+    pub position: Point,
+    /// The horizontal advance of the glyph, in display points.
+    pub advance: f64,
+    /// The start of the cluster this glyph belongs to, as a utf-8 offset into
+    /// the layout's text.
+    pub cluster: usize,
 }
 
 /// Metadata about each line in a text layout.
@@ -555,6 +1057,28 @@ pub struct HitTestPoint {
     /// end of that line, and a click below the last line will resolve to a
     /// position in that line.
     pub is_inside: bool,
+    /// The number of the line that `idx` is on.
+    ///
+    /// This is the line that the point is on, or the nearest one if the point
+    /// is above or below the layout, and can be used to retrieve the
+    /// [`LineMetric`] for it, via [`TextLayout::line_metric`].
+    pub line: usize,
+    /// Whether the point is above the first line of the layout.
+    pub is_above: bool,
+    /// Whether the point is below the last line of the layout.
+    pub is_below: bool,
+    /// Whether the point is to the left of the text on its line.
+    ///
+    /// The text of an empty line is taken to be where its caret is.
+    pub is_left: bool,
+    /// Whether the point is to the right of the text on its line, as it is
+    /// when clicking past the end of a line.
+    ///
+    /// In vertical [`WritingMode`]s, these flags are relative to the lines as
+    /// though they were horizontal: a point is above the layout if it is to
+    /// the right of its first column, and to the right of a column's text if
+    /// it is below it.
+    pub is_right: bool,
 }
 
 /// Result of hit testing a text position in a [`TextLayout`].
@@ -587,7 +1111,11 @@ impl HitTestPoint {
     /// Only for use by backends
     #[doc(hidden)]
     pub fn new(idx: usize, is_inside: bool) -> HitTestPoint {
-        HitTestPoint { idx, is_inside }
+        HitTestPoint {
+            idx,
+            is_inside,
+            ..Default::default()
+        }
     }
 }
 
@@ -617,6 +1145,36 @@ impl From<FontStyle> for TextAttribute {
     }
 }
 
+impl From<ScriptPosition> for TextAttribute {
+    fn from(src: ScriptPosition) -> TextAttribute {
+        TextAttribute::ScriptPosition(src)
+    }
+}
+
+impl From<FontVariation> for TextAttribute {
+    fn from(src: FontVariation) -> TextAttribute {
+        TextAttribute::FontVariations(Arc::new([src]))
+    }
+}
+
+impl From<Vec<FontVariation>> for TextAttribute {
+    fn from(src: Vec<FontVariation>) -> TextAttribute {
+        TextAttribute::FontVariations(src.into())
+    }
+}
+
+impl From<FontFeature> for TextAttribute {
+    fn from(src: FontFeature) -> TextAttribute {
+        TextAttribute::FontFeatures(Arc::new([src]))
+    }
+}
+
+impl From<Vec<FontFeature>> for TextAttribute {
+    fn from(src: Vec<FontFeature>) -> TextAttribute {
+        TextAttribute::FontFeatures(src.into())
+    }
+}
+
 impl Default for TextAlignment {
     fn default() -> Self {
         TextAlignment::Start
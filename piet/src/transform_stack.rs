@@ -0,0 +1,525 @@
+//! A render context that keeps track of its own transform.
+
+use std::borrow::Cow;
+
+use kurbo::{Affine, Insets, Point, Rect, Shape, Vec2};
+
+use crate::{
+    util, BlendMode, Capabilities, Color, Error, FixedGradient, GradientOptions, GradientStops,
+    HitTester, ImageFilter, ImageFormat, InterpolationMode, IntoBrush, Markers, MipImage,
+    PreparedShape, RenderContext, SharedImage, StrokeStyle, Symbol,
+};
+
+/// A render context that keeps a stack of transforms, so that the current
+/// transform, and the mapping between user space and the screen, can be
+/// found without asking the context it wraps.
+///
+/// Some backends can only report their transform slowly; on the web,
+/// [`current_transform`] is a call into JavaScript. This context keeps a copy
+/// of the transform as it is changed, and answers [`current_transform`],
+/// [`to_screen`] and [`from_screen`] from that copy, the same way for every
+/// backend:
+///
+/// ```
+/// # use piet::{kurbo::Point, NullRenderContext, RenderContext, TransformCtx};
+/// let mut rc = TransformCtx::new(NullRenderContext::new());
+/// rc.push_translate((100.0, 50.0)).unwrap();
+/// rc.push_scale(2.0).unwrap();
+/// assert_eq!(rc.to_screen(Point::new(1.0, 1.0)), Point::new(102.0, 52.0));
+/// assert_eq!(rc.from_screen(Point::new(102.0, 52.0)), Point::new(1.0, 1.0));
+/// rc.pop().unwrap();
+/// rc.pop().unwrap();
+/// ```
+///
+/// Each push saves the state of the context, the same as [`save`], and each
+/// [`pop`] restores it, so a clip that is set after a push is removed by the
+/// matching pop.
+///
+/// [`current_transform`]: RenderContext::current_transform
+/// [`save`]: RenderContext::save
+/// [`to_screen`]: TransformCtx::to_screen
+/// [`from_screen`]: TransformCtx::from_screen
+/// [`pop`]: TransformCtx::pop
+pub struct TransformCtx<RC: RenderContext> {
+    inner: RC,
+    transform: Affine,
+    saved: Vec<Affine>,
+}
+
+/// The brush of a [`TransformCtx`], which is the brush of the context it
+/// wraps.
+#[derive(Clone)]
+pub struct TransformBrush<B> {
+    inner: B,
+}
+
+impl<RC: RenderContext> TransformCtx<RC> {
+    /// Wrap `inner`, starting from its current transform.
+    pub fn new(inner: RC) -> TransformCtx<RC> {
+        let transform = inner.current_transform();
+        TransformCtx {
+            inner,
+            transform,
+            saved: Vec::new(),
+        }
+    }
+
+    /// Return the inner context.
+    pub fn into_inner(self) -> RC {
+        self.inner
+    }
+
+    /// The number of states that have been pushed, or saved, and not yet
+    /// popped.
+    pub fn depth(&self) -> usize {
+        self.saved.len()
+    }
+
+    /// Save the state of the context, and then apply `transform`.
+    pub fn push_transform(&mut self, transform: Affine) -> Result<(), Error> {
+        self.save()?;
+        self.transform(transform);
+        Ok(())
+    }
+
+    /// Save the state of the context, and then translate by `offset`.
+    pub fn push_translate(&mut self, offset: impl Into<Vec2>) -> Result<(), Error> {
+        self.push_transform(Affine::translate(offset))
+    }
+
+    /// Save the state of the context, and then scale uniformly by `scale`.
+    pub fn push_scale(&mut self, scale: f64) -> Result<(), Error> {
+        self.push_transform(Affine::scale(scale))
+    }
+
+    /// Save the state of the context, and then rotate by `radians`.
+    pub fn push_rotate(&mut self, radians: f64) -> Result<(), Error> {
+        self.push_transform(Affine::rotate(radians))
+    }
+
+    /// Restore the state from before the last push, or save.
+    pub fn pop(&mut self) -> Result<(), Error> {
+        self.restore()
+    }
+
+    /// Map `point` from user space to the space of the screen.
+    pub fn to_screen(&self, point: Point) -> Point {
+        self.transform * point
+    }
+
+    /// Map `point` from the space of the screen to user space.
+    ///
+    /// If the current transform can't be inverted, such as after scaling by
+    /// zero, the coordinates of the result are not finite.
+    pub fn from_screen(&self, point: Point) -> Point {
+        self.transform.inverse() * point
+    }
+}
+
+impl<RC: RenderContext> RenderContext for TransformCtx<RC> {
+    type Brush = TransformBrush<RC::Brush>;
+    type Text = RC::Text;
+    type TextLayout = RC::TextLayout;
+    type Image = RC::Image;
+
+    fn status(&mut self) -> Result<(), Error> {
+        self.inner.status()
+    }
+
+    fn solid_brush(&mut self, color: Color) -> Self::Brush {
+        TransformBrush {
+            inner: self.inner.solid_brush(color),
+        }
+    }
+
+    fn gradient(&mut self, gradient: impl Into<FixedGradient>) -> Result<Self::Brush, Error> {
+        Ok(TransformBrush {
+            inner: self.inner.gradient(gradient)?,
+        })
+    }
+
+    fn gradient_with_options(
+        &mut self,
+        gradient: impl Into<FixedGradient>,
+        options: GradientOptions,
+    ) -> Result<Self::Brush, Error> {
+        Ok(TransformBrush {
+            inner: self.inner.gradient_with_options(gradient, options)?,
+        })
+    }
+
+    fn clear(&mut self, region: impl Into<Option<Rect>>, color: Color) {
+        self.inner.clear(region, color);
+    }
+
+    fn stroke(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>, width: f64) {
+        let brush = brush.make_brush(self, || shape.bounding_box());
+        self.inner.stroke(shape, &brush.inner, width);
+    }
+
+    fn stroke_styled(
+        &mut self,
+        shape: impl Shape,
+        brush: &impl IntoBrush<Self>,
+        width: f64,
+        style: &StrokeStyle,
+    ) {
+        let brush = brush.make_brush(self, || shape.bounding_box());
+        self.inner.stroke_styled(shape, &brush.inner, width, style);
+    }
+
+    fn stroke_gradient_along_path(
+        &mut self,
+        shape: impl Shape,
+        stops: impl GradientStops,
+        width: f64,
+        style: &StrokeStyle,
+    ) {
+        self.inner
+            .stroke_gradient_along_path(shape, stops, width, style);
+    }
+
+    fn stroke_with_markers(
+        &mut self,
+        shape: impl Shape,
+        brush: &impl IntoBrush<Self>,
+        width: f64,
+        style: &StrokeStyle,
+        markers: &Markers,
+    ) {
+        let brush = brush.make_brush(self, || shape.bounding_box());
+        self.inner
+            .stroke_with_markers(shape, &brush.inner, width, style, markers);
+    }
+
+    fn fill(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>) {
+        let brush = brush.make_brush(self, || shape.bounding_box());
+        self.inner.fill(shape, &brush.inner);
+    }
+
+    fn fill_even_odd(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>) {
+        let brush = brush.make_brush(self, || shape.bounding_box());
+        self.inner.fill_even_odd(shape, &brush.inner);
+    }
+
+    fn fill_and_stroke(
+        &mut self,
+        shape: impl Shape,
+        fill_brush: &impl IntoBrush<Self>,
+        stroke_brush: &impl IntoBrush<Self>,
+        width: f64,
+        style: &StrokeStyle,
+    ) {
+        let fill_brush = fill_brush.make_brush(self, || shape.bounding_box());
+        let stroke_brush = stroke_brush.make_brush(self, || shape.bounding_box());
+        self.inner
+            .fill_and_stroke(shape, &fill_brush.inner, &stroke_brush.inner, width, style);
+    }
+
+    fn fill_many(
+        &mut self,
+        shapes: impl IntoIterator<Item = impl Shape>,
+        brush: &impl IntoBrush<Self>,
+    ) {
+        let shapes: Vec<_> = shapes.into_iter().collect();
+        let brush = brush.make_brush(self, || util::union_bounds(&shapes));
+        self.inner.fill_many(shapes, &brush.inner);
+    }
+
+    fn stroke_many(
+        &mut self,
+        shapes: impl IntoIterator<Item = impl Shape>,
+        brush: &impl IntoBrush<Self>,
+        width: f64,
+        style: &StrokeStyle,
+    ) {
+        let shapes: Vec<_> = shapes.into_iter().collect();
+        let brush = brush.make_brush(self, || util::union_bounds(&shapes));
+        self.inner.stroke_many(shapes, &brush.inner, width, style);
+    }
+
+    fn fill_with_mask(
+        &mut self,
+        shape: impl Shape,
+        brush: &impl IntoBrush<Self>,
+        mask: &Self::Image,
+    ) {
+        let brush = brush.make_brush(self, || shape.bounding_box());
+        self.inner.fill_with_mask(shape, &brush.inner, mask);
+    }
+
+    fn clip(&mut self, shape: impl Shape) {
+        self.inner.clip(shape);
+    }
+
+    fn prepare_shape(&mut self, shape: impl Shape) -> PreparedShape {
+        self.inner.prepare_shape(shape)
+    }
+
+    fn draw_symbol_instances(
+        &mut self,
+        symbol: Symbol<Self::Image>,
+        brush: &impl IntoBrush<Self>,
+        instances: &[(Affine, Option<Color>)],
+    ) {
+        let brush = brush.make_brush(self, || symbol.bounds());
+        self.inner
+            .draw_symbol_instances(symbol, &brush.inner, instances);
+    }
+
+    fn text(&mut self) -> &mut Self::Text {
+        self.inner.text()
+    }
+
+    fn draw_text(&mut self, layout: &Self::TextLayout, pos: impl Into<Point>) {
+        self.inner.draw_text(layout, pos);
+    }
+
+    fn save(&mut self) -> Result<(), Error> {
+        self.inner.save()?;
+        self.saved.push(self.transform);
+        Ok(())
+    }
+
+    fn restore(&mut self) -> Result<(), Error> {
+        // the inner context isn't asked to restore a state that was never
+        // saved, since not every backend reports that as an error.
+        if self.saved.is_empty() {
+            return Err(Error::StackUnbalance);
+        }
+        self.inner.restore()?;
+        self.transform = self.saved.pop().unwrap();
+        Ok(())
+    }
+
+    fn set_global_alpha(&mut self, alpha: f64) {
+        self.inner.set_global_alpha(alpha);
+    }
+
+    fn global_alpha(&self) -> f64 {
+        self.inner.global_alpha()
+    }
+
+    fn set_blend_mode(&mut self, mode: BlendMode) {
+        self.inner.set_blend_mode(mode);
+    }
+
+    fn blend_mode(&self) -> BlendMode {
+        self.inner.blend_mode()
+    }
+
+    fn set_tolerance(&mut self, tolerance: f64) {
+        self.inner.set_tolerance(tolerance);
+    }
+
+    fn tolerance(&self) -> f64 {
+        self.inner.tolerance()
+    }
+
+    fn fill_with_alpha(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>, alpha: f64) {
+        let brush = brush.make_brush(self, || shape.bounding_box());
+        self.inner.fill_with_alpha(shape, &brush.inner, alpha);
+    }
+
+    fn annotate(&mut self, key: &str, value: &str) {
+        self.inner.annotate(key, value);
+    }
+
+    fn begin_group(&mut self, role: &str) -> Result<(), Error> {
+        self.inner.begin_group(role)
+    }
+
+    fn end_group(&mut self) -> Result<(), Error> {
+        self.inner.end_group()
+    }
+
+    fn begin_opacity_mask(
+        &mut self,
+        mask: &Self::Image,
+        rect: impl Into<Rect>,
+    ) -> Result<(), Error> {
+        self.inner.begin_opacity_mask(mask, rect)?;
+        self.saved.push(self.transform);
+        Ok(())
+    }
+
+    fn end_opacity_mask(&mut self) -> Result<(), Error> {
+        if self.saved.is_empty() {
+            return Err(Error::StackUnbalance);
+        }
+        self.inner.end_opacity_mask()?;
+        self.transform = self.saved.pop().unwrap();
+        Ok(())
+    }
+
+    fn hit_region(&mut self, id: u64, shape: impl Shape) {
+        self.inner.hit_region(id, shape);
+    }
+
+    fn hit_region_stroke(&mut self, id: u64, shape: impl Shape, width: f64, style: &StrokeStyle) {
+        self.inner.hit_region_stroke(id, shape, width, style);
+    }
+
+    fn hit_tester(&mut self) -> Option<&mut HitTester> {
+        self.inner.hit_tester()
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        self.inner.finish()
+    }
+
+    fn transform(&mut self, transform: Affine) {
+        self.transform *= transform;
+        self.inner.transform(transform);
+    }
+
+    fn make_image(
+        &mut self,
+        width: usize,
+        height: usize,
+        buf: &[u8],
+        format: ImageFormat,
+    ) -> Result<Self::Image, Error> {
+        self.inner.make_image(width, height, buf, format)
+    }
+
+    fn make_shared_image(&mut self, image: &SharedImage) -> Result<Self::Image, Error> {
+        self.inner.make_shared_image(image)
+    }
+
+    fn make_image_from_encoded(&mut self, data: &[u8]) -> Result<Self::Image, Error> {
+        self.inner.make_image_from_encoded(data)
+    }
+
+    fn make_mip_image(
+        &mut self,
+        width: usize,
+        height: usize,
+        buf: &[u8],
+        format: ImageFormat,
+    ) -> Result<MipImage<Self::Image>, Error> {
+        self.inner.make_mip_image(width, height, buf, format)
+    }
+
+    fn draw_image(
+        &mut self,
+        image: &Self::Image,
+        dst_rect: impl Into<Rect>,
+        interp: InterpolationMode,
+    ) {
+        self.inner.draw_image(image, dst_rect, interp);
+    }
+
+    fn draw_image_area(
+        &mut self,
+        image: &Self::Image,
+        src_rect: impl Into<Rect>,
+        dst_rect: impl Into<Rect>,
+        interp: InterpolationMode,
+    ) {
+        self.inner
+            .draw_image_area(image, src_rect, dst_rect, interp);
+    }
+
+    fn draw_mip_image(
+        &mut self,
+        image: &MipImage<Self::Image>,
+        dst_rect: impl Into<Rect>,
+        interp: InterpolationMode,
+    ) {
+        self.inner.draw_mip_image(image, dst_rect, interp);
+    }
+
+    fn draw_image_nine(
+        &mut self,
+        image: &Self::Image,
+        insets: impl Into<Insets>,
+        dst_rect: impl Into<Rect>,
+        interp: InterpolationMode,
+    ) {
+        self.inner.draw_image_nine(image, insets, dst_rect, interp);
+    }
+
+    fn draw_image_with_quad(
+        &mut self,
+        image: &Self::Image,
+        dst_quad: [Point; 4],
+        interp: InterpolationMode,
+    ) {
+        self.inner.draw_image_with_quad(image, dst_quad, interp);
+    }
+
+    fn draw_image_filtered(
+        &mut self,
+        image: &Self::Image,
+        dst_rect: impl Into<Rect>,
+        filters: &[ImageFilter],
+    ) {
+        self.inner.draw_image_filtered(image, dst_rect, filters);
+    }
+
+    fn capture_image_area(&mut self, src_rect: impl Into<Rect>) -> Result<Self::Image, Error> {
+        self.inner.capture_image_area(src_rect)
+    }
+
+    fn blurred_rect(&mut self, rect: Rect, blur_radius: f64, brush: &impl IntoBrush<Self>) {
+        let brush = brush.make_brush(self, || rect);
+        self.inner.blurred_rect(rect, blur_radius, &brush.inner);
+    }
+
+    fn current_transform(&self) -> Affine {
+        self.transform
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+}
+
+impl<RC: RenderContext> IntoBrush<TransformCtx<RC>> for TransformBrush<RC::Brush> {
+    fn make_brush<'a>(
+        &'a self,
+        _piet: &mut TransformCtx<RC>,
+        _bbox: impl FnOnce() -> Rect,
+    ) -> Cow<'a, TransformBrush<RC::Brush>> {
+        Cow::Borrowed(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NullRenderContext;
+
+    fn assert_near(p: Point, expected: Point) {
+        assert!((p - expected).hypot() < 1e-9, "{:?} != {:?}", p, expected);
+    }
+
+    #[test]
+    fn pushes_and_pops_transforms() {
+        let mut rc = TransformCtx::new(NullRenderContext::new());
+        rc.push_translate((10.0, 20.0)).unwrap();
+        rc.push_rotate(std::f64::consts::FRAC_PI_2).unwrap();
+        rc.push_scale(2.0).unwrap();
+        assert_eq!(rc.depth(), 3);
+
+        let screen = rc.to_screen(Point::new(1.0, 0.0));
+        assert_near(screen, Point::new(10.0, 22.0));
+        assert_near(rc.from_screen(screen), Point::new(1.0, 0.0));
+
+        // saves and transforms of the context itself are kept on the stack too.
+        rc.with_transform(Affine::translate((1.0, 0.0)), |rc| {
+            assert_eq!(rc.depth(), 4);
+            assert_near(rc.to_screen(Point::ZERO), Point::new(10.0, 22.0));
+            Ok(())
+        })
+        .unwrap();
+
+        rc.pop().unwrap();
+        assert_near(rc.to_screen(Point::new(1.0, 0.0)), Point::new(10.0, 21.0));
+        rc.pop().unwrap();
+        rc.pop().unwrap();
+        assert_eq!(rc.current_transform(), Affine::IDENTITY);
+        assert!(matches!(rc.pop(), Err(Error::StackUnbalance)));
+    }
+}
@@ -1,12 +1,31 @@
 //! Code useful for multiple backends
 
+use std::borrow::Cow;
+use std::f64::consts::SQRT_2;
 use std::ops::{Bound, Range, RangeBounds};
+use std::sync::Arc;
 
-use crate::kurbo::{Rect, Size};
-use crate::{Color, FontFamily, FontStyle, FontWeight, LineMetric, TextAttribute};
+use crate::kurbo::{
+    self, Affine, BezPath, CubicBez, Insets, Line, ParamCurve, ParamCurveArclen, PathEl, PathSeg,
+    Point, QuadBez, Rect, Shape, Size, Vec2,
+};
+use crate::{
+    Color, FontFamily, FontFeature, FontStyle, FontVariation, FontWeight, GradientStop,
+    HitTestPoint, Image, ImageFormat, InterpolationMode, IntoBrush, LineCap, LineJoin, LineMetric,
+    Markers, RenderContext, ScriptPosition, StrokeStyle, Symbol, TextAttribute, TextLayout,
+};
 
 use unic_bidi::bidi_class::{BidiClass, BidiClassCategory};
 
+mod filter;
+mod gradient;
+mod simd;
+
+pub use self::filter::{filter_pixels, FilteredPixels};
+pub use self::gradient::{
+    dithered_gradient_pixels, gradient_position, interpolated_gradient, interpolated_stops,
+};
+
 /// The default point size for text in piet.
 pub const DEFAULT_FONT_SIZE: f64 = 12.0;
 
@@ -82,6 +101,52 @@ pub fn line_number_for_position(lines: &[LineMetric], position: usize) -> usize
     }
 }
 
+/// The [`HitTestPoint`] for a hit at `idx` on `line` of `layout`, with where
+/// `point` is relative to the text.
+///
+/// `point` is in the layout's horizontal coordinate space, as its
+/// [`LineMetric`]s are, and `to_layout` is the transform from that space to
+/// the layout's own; see [`WritingMode::transform`].
+///
+/// [`WritingMode::transform`]: crate::WritingMode::transform
+pub fn hit_test_point_on_line(
+    layout: &impl TextLayout,
+    to_layout: Affine,
+    point: Point,
+    line: usize,
+    idx: usize,
+    is_inside: bool,
+) -> HitTestPoint {
+    let last_line = layout.line_count().saturating_sub(1);
+    let first = layout.line_metric(0).unwrap_or_default();
+    let last = layout.line_metric(last_line).unwrap_or_default();
+    let metric = layout.line_metric(line).unwrap_or_default();
+
+    // the line's text, without its newline.
+    let end = metric.end_offset - trailing_nlf(layout.line_text(line).unwrap_or("")).unwrap_or(0);
+    let from_layout = to_layout.inverse();
+    let (x0, x1) = layout
+        .rects_for_range(metric.start_offset..end)
+        .into_iter()
+        .map(|rect| from_layout.transform_rect_bbox(rect))
+        .map(|rect| (rect.x0, rect.x1))
+        .reduce(|(a0, a1), (b0, b1)| (a0.min(b0), a1.max(b1)))
+        .unwrap_or_else(|| {
+            let caret = from_layout * layout.hit_test_text_position(metric.start_offset).point;
+            (caret.x, caret.x)
+        });
+
+    HitTestPoint {
+        idx,
+        is_inside,
+        line,
+        is_above: point.y < first.y_offset,
+        is_below: point.y > last.y_offset + last.height,
+        is_left: point.x < x0,
+        is_right: point.x > x1,
+    }
+}
+
 /// Resolves a `RangeBounds` into a range in the range 0..len.
 pub fn resolve_range(range: impl RangeBounds<usize>, len: usize) -> Range<usize> {
     let start = match range.start_bound() {
@@ -157,9 +222,16 @@ pub struct LayoutDefaults {
     pub font_size: f64,
     pub weight: FontWeight,
     pub fg_color: Color,
+    pub bg_color: Color,
     pub style: FontStyle,
     pub underline: bool,
     pub strikethrough: bool,
+    pub letter_spacing: f64,
+    pub word_spacing: f64,
+    pub variations: Arc<[FontVariation]>,
+    pub features: Arc<[FontFeature]>,
+    pub baseline_shift: f64,
+    pub script: ScriptPosition,
 }
 
 impl LayoutDefaults {
@@ -174,9 +246,27 @@ impl LayoutDefaults {
             TextAttribute::Style(style) => self.style = style,
             TextAttribute::Underline(flag) => self.underline = flag,
             TextAttribute::TextColor(color) => self.fg_color = color,
+            TextAttribute::BackgroundColor(color) => self.bg_color = color,
             TextAttribute::Strikethrough(flag) => self.strikethrough = flag,
+            TextAttribute::LetterSpacing(spacing) => self.letter_spacing = spacing,
+            TextAttribute::WordSpacing(spacing) => self.word_spacing = spacing,
+            TextAttribute::FontVariations(variations) => self.variations = variations,
+            TextAttribute::FontFeatures(features) => self.features = features,
+            TextAttribute::BaselineShift(shift) => self.baseline_shift = shift,
+            TextAttribute::ScriptPosition(script) => self.script = script,
         }
     }
+
+    /// The font size after applying the [`ScriptPosition`].
+    pub fn scaled_font_size(&self) -> f64 {
+        self.font_size * self.script.font_scale()
+    }
+
+    /// The total baseline shift, in display points, including the shift
+    /// implied by the [`ScriptPosition`]. Positive values raise the text.
+    pub fn total_baseline_shift(&self) -> f64 {
+        self.baseline_shift + self.script.baseline_shift(self.font_size)
+    }
 }
 
 impl Default for LayoutDefaults {
@@ -186,9 +276,16 @@ impl Default for LayoutDefaults {
             font_size: DEFAULT_FONT_SIZE,
             weight: FontWeight::default(),
             fg_color: DEFAULT_TEXT_COLOR,
+            bg_color: Color::TRANSPARENT,
             style: FontStyle::default(),
             underline: false,
             strikethrough: false,
+            letter_spacing: 0.0,
+            word_spacing: 0.0,
+            variations: Vec::new().into(),
+            features: Vec::new().into(),
+            baseline_shift: 0.0,
+            script: ScriptPosition::Normal,
         }
     }
 }
@@ -204,18 +301,169 @@ pub fn unpremul(x: u8, a: u8) -> u8 {
     }
 }
 
+/// If `x` is a single (non-alpha) channel of a color with separate alpha and `a` is the alpha
+/// channel, returns the corresponding channel of the premultiplied version of the color.
+pub fn premul(x: u8, a: u8) -> u8 {
+    let y = (x as u16) * (a as u16);
+    ((y + (y >> 8) + 0x80) >> 8) as u8
+}
+
+/// Takes a buffer of RGBA pixels with separate alpha and premultiplies them in place.
+///
+/// This works the same for BGRA pixels. Like the other pixel conversions here, it uses SIMD
+/// instructions where they are available.
+pub fn premultiply_rgba(data: &mut [u8]) {
+    let done = simd::premultiply_rgba(data);
+    for px in data[done * 4..].chunks_exact_mut(4) {
+        let a = px[3];
+        for x in &mut px[..3] {
+            *x = premul(*x, a);
+        }
+    }
+}
+
 /// Takes a buffer of premultiplied RGBA pixels and unpremultiplies them in place.
+///
+/// Transparent pixels are left as they are.
 pub fn unpremultiply_rgba(data: &mut [u8]) {
-    for i in (0..data.len()).step_by(4) {
-        let a = data[i + 3];
+    let done = simd::unpremultiply_rgba(data);
+    for px in data[done * 4..].chunks_exact_mut(4) {
+        let a = px[3];
         if a != 0 {
-            for x in &mut data[i..(i + 3)] {
+            for x in &mut px[..3] {
                 *x = unpremul(*x, a);
             }
         }
     }
 }
 
+/// Swaps the red and blue channels of a buffer of 4-byte pixels in place, converting
+/// between BGRA and RGBA.
+pub fn swap_red_blue(data: &mut [u8]) {
+    let done = simd::swap_red_blue(data);
+    for px in data[done * 4..].chunks_exact_mut(4) {
+        px.swap(0, 2);
+    }
+}
+
+/// Expands RGB pixels in `src` to opaque RGBA pixels in `dst`.
+///
+/// As many pixels are converted as fit in both buffers.
+pub fn rgb_to_rgba(src: &[u8], dst: &mut [u8]) {
+    let done = simd::rgb_to_rgba(src, dst);
+    let src = src[done * 3..].chunks_exact(3);
+    for (px, rgb) in dst[done * 4..].chunks_exact_mut(4).zip(src) {
+        px.copy_from_slice(&[rgb[0], rgb[1], rgb[2], 255]);
+    }
+}
+
+/// Expands grayscale pixels in `src` to opaque RGBA pixels in `dst`.
+///
+/// As many pixels are converted as fit in both buffers.
+pub fn gray_to_rgba(src: &[u8], dst: &mut [u8]) {
+    let done = simd::gray_to_rgba(src, dst);
+    for (px, &v) in dst[done * 4..].chunks_exact_mut(4).zip(&src[done..]) {
+        px.copy_from_slice(&[v, v, v, 255]);
+    }
+}
+
+/// Converts a buffer of [`ImageFormat::Rgba16`] pixels to [`ImageFormat::RgbaSeparate`].
+pub fn rgba16_to_rgba8(data: &[u8]) -> Vec<u8> {
+    data.chunks_exact(2)
+        .map(|c| {
+            let x = u16::from_ne_bytes([c[0], c[1]]) as u32;
+            // rounds to the nearest 8-bit value.
+            ((x * 255 + 32895) >> 16) as u8
+        })
+        .collect()
+}
+
+/// Converts pixels in the BGRA and 16-bit formats to the 8-bit RGBA format with the same
+/// kind of alpha, for backends that only take RGBA.
+///
+/// Other formats are returned as they are.
+pub fn to_rgba8(buf: &[u8], format: ImageFormat) -> (Cow<'_, [u8]>, ImageFormat) {
+    match format {
+        ImageFormat::BgraSeparate | ImageFormat::BgraPremul => {
+            let mut data = buf.to_vec();
+            swap_red_blue(&mut data);
+            let format = match format {
+                ImageFormat::BgraSeparate => ImageFormat::RgbaSeparate,
+                _ => ImageFormat::RgbaPremul,
+            };
+            (data.into(), format)
+        }
+        ImageFormat::Rgba16 => (rgba16_to_rgba8(buf).into(), ImageFormat::RgbaSeparate),
+        _ => (buf.into(), format),
+    }
+}
+
+/// The first `len` pixels of `buf`, in `format`, as premultiplied RGBA.
+///
+/// Returns [`Error::InvalidInput`] if `buf` is too short.
+///
+/// [`Error::InvalidInput`]: crate::Error::InvalidInput
+pub fn premultiplied_rgba(
+    len: usize,
+    buf: &[u8],
+    format: ImageFormat,
+) -> Result<Vec<u8>, crate::Error> {
+    let (buf, format) = to_rgba8(buf, format);
+    let buf = buf
+        .get(..len * format.bytes_per_pixel())
+        .ok_or(crate::Error::InvalidInput)?;
+    let mut data = vec![0; len * 4];
+    match format {
+        ImageFormat::RgbaPremul => data.copy_from_slice(buf),
+        ImageFormat::RgbaSeparate => {
+            data.copy_from_slice(buf);
+            premultiply_rgba(&mut data);
+        }
+        ImageFormat::Rgb => rgb_to_rgba(buf, &mut data),
+        ImageFormat::Grayscale => gray_to_rgba(buf, &mut data),
+        _ => return Err(crate::Error::NotSupported),
+    }
+    Ok(data)
+}
+
+/// The levels of a mipmap of a `width` by `height` image, whose pixels are
+/// premultiplied RGBA, as the width, height and pixels of each.
+///
+/// Each level is half the size of the one before, rounding up, down to a
+/// single pixel, and the image itself isn't included. Each pixel is the
+/// average of the two by two pixels that it covers in the level before;
+/// the last row and column of a level with an odd size are used twice.
+pub fn mip_levels(width: usize, height: usize, data: &[u8]) -> Vec<(usize, usize, Vec<u8>)> {
+    let mut levels: Vec<(usize, usize, Vec<u8>)> = Vec::new();
+    loop {
+        let (src_width, src_height, src) = match levels.last() {
+            Some((width, height, data)) => (*width, *height, &data[..]),
+            None => (width, height, data),
+        };
+        // empty images have no levels, and a pixel is the last level.
+        if src_width * src_height <= 1 {
+            return levels;
+        }
+        let (dst_width, dst_height) = (src_width.div_ceil(2), src_height.div_ceil(2));
+        let mut dst = vec![0; dst_width * dst_height * 4];
+        for (y, row) in dst.chunks_exact_mut(dst_width * 4).enumerate() {
+            let rows = [2 * y, (2 * y + 1).min(src_height - 1)];
+            for (x, px) in row.chunks_exact_mut(4).enumerate() {
+                let cols = [2 * x, (2 * x + 1).min(src_width - 1)];
+                for (c, value) in px.iter_mut().enumerate() {
+                    let sum: u32 = rows
+                        .iter()
+                        .flat_map(|y| cols.iter().map(move |x| (y * src_width + x) * 4 + c))
+                        .map(|i| src[i] as u32)
+                        .sum();
+                    *value = ((sum + 2) / 4) as u8;
+                }
+            }
+        }
+        levels.push((dst_width, dst_height, dst));
+    }
+}
+
 /// A heurstic for text direction; returns `true` if, while enumerating characters
 /// in this string, a character in the 'R' (strong right-to-left) category is
 /// encountered before any character in the 'L' (strong left-to-right) category is.
@@ -231,10 +479,1313 @@ pub fn first_strong_rtl(text: &str) -> bool {
         .unwrap_or(false)
 }
 
+/// Returns the position of the first tab stop after `x`.
+///
+/// `stops` are explicit tab stop positions, in increasing order; past the last
+/// of these, there is a tab stop at every multiple of `tab_width`.
+pub fn next_tab_stop(x: f64, stops: &[f64], tab_width: f64) -> f64 {
+    if let Some(stop) = stops.iter().copied().find(|stop| *stop > x) {
+        return stop;
+    }
+    if tab_width > 0.0 {
+        ((x / tab_width).floor() + 1.0) * tab_width
+    } else {
+        x
+    }
+}
+
+/// Merge overlapping or touching horizontal spans, such as the extents of
+/// the glyphs in a text selection.
+///
+/// The returned spans are sorted from left to right.
+pub fn merge_spans(spans: impl IntoIterator<Item = (f64, f64)>) -> Vec<(f64, f64)> {
+    // glyph advances don't always sum exactly
+    const TOLERANCE: f64 = 1e-3;
+    let mut spans = spans.into_iter().collect::<Vec<_>>();
+    spans.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+    let mut merged: Vec<(f64, f64)> = Vec::with_capacity(spans.len());
+    for (x0, x1) in spans {
+        match merged.last_mut() {
+            Some(last) if x0 <= last.1 + TOLERANCE => last.1 = last.1.max(x1),
+            _ => merged.push((x0, x1)),
+        }
+    }
+    merged
+}
+
+/// The area of the pixels of an image that a sub-image of it covers.
+///
+/// `view` is the area of the pixels that the image covers, which is all of
+/// them unless it is itself a sub-image, and `rect` is the area of the
+/// sub-image, in the image's own space. The area is rounded to whole pixels
+/// and clipped to `view`. This is for backends implementing
+/// [`Image::sub_image`].
+///
+/// [`Image::sub_image`]: crate::Image::sub_image
+pub fn sub_image_view(view: Rect, rect: Rect) -> Rect {
+    (rect.round() + view.origin().to_vec2()).intersect(view)
+}
+
+/// Split an image of `size` into the nine areas of a nine-patch, and pair
+/// each with the area of `dst_rect` it is drawn to.
+///
+/// `insets` are the sizes of the corners, in the image's pixels. The corners
+/// are drawn at that size, the edges are stretched along them, and the center
+/// is stretched both ways. If `dst_rect` is too small for the corners, they are
+/// scaled down to fit. Areas that are empty are left out.
+///
+/// This is what [`RenderContext::draw_image_nine`] draws.
+///
+/// [`RenderContext::draw_image_nine`]: crate::RenderContext::draw_image_nine
+pub fn nine_patch(size: Size, insets: Insets, dst_rect: Rect) -> Vec<(Rect, Rect)> {
+    // the corners along one axis, fitted into `len`.
+    fn fit(start: f64, end: f64, len: f64) -> (f64, f64) {
+        let (start, end) = (start.max(0.0), end.max(0.0));
+        let scale = if start + end > len {
+            len / (start + end)
+        } else {
+            1.0
+        };
+        (start * scale, end * scale)
+    }
+
+    let dst_rect = dst_rect.abs();
+    let (left, right) = fit(insets.x0, insets.x1, size.width);
+    let (top, bottom) = fit(insets.y0, insets.y1, size.height);
+    let src_x = [0.0, left, size.width - right, size.width];
+    let src_y = [0.0, top, size.height - bottom, size.height];
+    let (left, right) = fit(left, right, dst_rect.width());
+    let (top, bottom) = fit(top, bottom, dst_rect.height());
+    let dst_x = [
+        dst_rect.x0,
+        dst_rect.x0 + left,
+        dst_rect.x1 - right,
+        dst_rect.x1,
+    ];
+    let dst_y = [
+        dst_rect.y0,
+        dst_rect.y0 + top,
+        dst_rect.y1 - bottom,
+        dst_rect.y1,
+    ];
+
+    let mut areas = Vec::with_capacity(9);
+    for row in 0..3 {
+        for col in 0..3 {
+            let src = Rect::new(src_x[col], src_y[row], src_x[col + 1], src_y[row + 1]);
+            let dst = Rect::new(dst_x[col], dst_y[row], dst_x[col + 1], dst_y[row + 1]);
+            if src.area() > 0.0 && dst.area() > 0.0 {
+                areas.push((src, dst));
+            }
+        }
+    }
+    areas
+}
+
+/// The winding number of `shape`, drawn with `transform`, around `point`.
+///
+/// `point` is in the space that `transform` maps to, such as the space of a
+/// render context before any transform was applied, so that this can be
+/// passed [`RenderContext::current_transform`] to test a point against a
+/// shape as it was drawn. A shape drawn with a degenerate transform has no
+/// area, so this is `0` for it.
+///
+/// [`RenderContext::current_transform`]: crate::RenderContext::current_transform
+pub fn winding(shape: impl Shape, transform: Affine, point: Point) -> i32 {
+    if transform.determinant() == 0.0 {
+        return 0;
+    }
+    shape.winding(transform.inverse() * point)
+}
+
+/// Returns `true` if `point` is inside `shape` as it is drawn by
+/// [`RenderContext::fill`] with `transform`.
+///
+/// See [`winding`] for the space that `point` is in.
+///
+/// [`RenderContext::fill`]: crate::RenderContext::fill
+pub fn fill_contains(shape: impl Shape, transform: Affine, point: Point) -> bool {
+    winding(shape, transform, point) != 0
+}
+
+/// Returns `true` if `point` is inside `shape` as it is drawn by
+/// [`RenderContext::fill_even_odd`] with `transform`.
+///
+/// See [`winding`] for the space that `point` is in.
+///
+/// [`RenderContext::fill_even_odd`]: crate::RenderContext::fill_even_odd
+pub fn fill_even_odd_contains(shape: impl Shape, transform: Affine, point: Point) -> bool {
+    winding(shape, transform, point) % 2 != 0
+}
+
+/// Returns `true` if `point` is covered by the stroke of `shape`, as it is
+/// drawn by [`RenderContext::stroke_styled`] with `transform`.
+///
+/// The joins, caps and dashes of `style` are taken into account. Curves are
+/// flattened to within a small fraction of the stroke's width, so points very
+/// close to the edge of a curved stroke may be misjudged. See [`winding`] for
+/// the space that `point` is in.
+///
+/// [`RenderContext::stroke_styled`]: crate::RenderContext::stroke_styled
+pub fn stroke_contains(
+    shape: impl Shape,
+    transform: Affine,
+    point: Point,
+    width: f64,
+    style: &StrokeStyle,
+) -> bool {
+    StrokeGeometry::new(width, style).contains(shape, transform, point)
+}
+
+/// The union of the bounding boxes of `shapes`, or an empty rectangle if
+/// there are none.
+pub fn union_bounds(shapes: &[impl Shape]) -> Rect {
+    shapes
+        .iter()
+        .map(Shape::bounding_box)
+        .reduce(|a, b| a.union(b))
+        .unwrap_or_default()
+}
+
+/// The parts of a stroke that decide which points it covers.
+pub(crate) struct StrokeGeometry<'a> {
+    pub(crate) width: f64,
+    pub(crate) line_join: LineJoin,
+    pub(crate) line_cap: LineCap,
+    pub(crate) dash_cap: LineCap,
+    pub(crate) dash_pattern: &'a [f64],
+    pub(crate) dash_offset: f64,
+    pub(crate) scale: bool,
+}
+
+impl<'a> StrokeGeometry<'a> {
+    pub(crate) fn new(width: f64, style: &'a StrokeStyle) -> StrokeGeometry<'a> {
+        StrokeGeometry {
+            width,
+            line_join: style.line_join,
+            line_cap: style.line_cap,
+            dash_cap: style.effective_dash_cap(),
+            dash_pattern: &style.dash_pattern,
+            dash_offset: style.dash_offset,
+            scale: style.scale,
+        }
+    }
+
+    /// A rectangle containing the stroke of a shape with bounds `bounds`,
+    /// drawn with `transform`.
+    pub(crate) fn bounds(&self, bounds: Rect, transform: Affine) -> Rect {
+        // miters reach furthest, followed by square caps.
+        let reach = match self.line_join {
+            LineJoin::Miter { limit } => limit.max(SQRT_2),
+            _ => SQRT_2,
+        } * self.width
+            / 2.0;
+        if self.scale {
+            transform.transform_rect_bbox(bounds.inflate(reach, reach))
+        } else {
+            transform.transform_rect_bbox(bounds).inflate(reach, reach)
+        }
+    }
+
+    pub(crate) fn contains(&self, shape: impl Shape, transform: Affine, point: Point) -> bool {
+        let half_width = self.width / 2.0;
+        if half_width <= 0.0 || !self.bounds(shape.bounding_box(), transform).contains(point) {
+            return false;
+        }
+        // work in the space that the width is measured in.
+        let (path, point) = if self.scale {
+            if transform.determinant() == 0.0 {
+                return false;
+            }
+            (shape.into_path(1e-3), transform.inverse() * point)
+        } else {
+            (transform * shape.into_path(1e-3), point)
+        };
+
+        let polylines = flatten_polylines(&path, (half_width * 0.05).max(1e-3));
+        let caps = [self.line_cap; 2];
+        let mut dashes = Vec::new();
+        polylines.into_iter().any(|(points, closed)| {
+            if !self.is_dashed() {
+                return self.polyline_contains(&points, closed, caps, point, half_width);
+            }
+            dashes.clear();
+            self.dash(&points, closed, &mut dashes);
+            dashes.iter().any(|dash| {
+                self.polyline_contains(&dash.points, false, dash.caps, point, half_width)
+            })
+        })
+    }
+
+    /// Returns `true` if the dash pattern is valid and not empty.
+    fn is_dashed(&self) -> bool {
+        self.dash_pattern.iter().all(|x| x.is_finite() && *x >= 0.0)
+            && self.dash_pattern.iter().sum::<f64>() > 0.0
+    }
+
+    /// Returns `true` if a dash, rather than a gap, is at `distance` along a
+    /// dashed line.
+    fn dash_is_on(&self, distance: f64) -> bool {
+        let pattern = self.dash_pattern;
+        let period = if pattern.len() % 2 == 1 {
+            pattern.len() * 2
+        } else {
+            pattern.len()
+        };
+        let total = pattern.iter().sum::<f64>() * (period / pattern.len()) as f64;
+        let mut offset = (self.dash_offset + distance).rem_euclid(total);
+        let mut index = 0;
+        while offset > pattern[index % pattern.len()] {
+            offset -= pattern[index % pattern.len()];
+            index += 1;
+        }
+        index % 2 == 0
+    }
+
+    /// Split a polyline into its dashes.
+    fn dash(&self, points: &[Point], closed: bool, dashes: &mut Vec<Dash>) {
+        let pattern = self.dash_pattern;
+        // an odd number of lengths is repeated, so that dashes and gaps
+        // alternate.
+        let period = if pattern.len() % 2 == 1 {
+            pattern.len() * 2
+        } else {
+            pattern.len()
+        };
+        let total = pattern.iter().sum::<f64>() * (period / pattern.len()) as f64;
+        let mut offset = self.dash_offset.rem_euclid(total);
+        let mut index = 0;
+        let mut remaining = pattern[0];
+        while offset > remaining {
+            offset -= remaining;
+            index = (index + 1) % period;
+            remaining = pattern[index % pattern.len()];
+        }
+        remaining -= offset;
+
+        let first = dashes.len();
+        let starts_on = index % 2 == 0;
+        let mut piece = Vec::new();
+        if starts_on {
+            piece.push(points[0]);
+        }
+        // only the ends of open paths have the line cap.
+        let mut start_cap = if closed { self.dash_cap } else { self.line_cap };
+        let closing = closed.then(|| (points[points.len() - 1], points[0]));
+        let segments = points.windows(2).map(|w| (w[0], w[1])).chain(closing);
+        for (a, b) in segments {
+            let length = (b - a).hypot();
+            let mut t = 0.0;
+            while length - t > remaining {
+                t += remaining;
+                push_point(&mut piece, a.lerp(b, t / length));
+                if index % 2 == 0 {
+                    dashes.push(Dash {
+                        points: std::mem::take(&mut piece),
+                        caps: [start_cap, self.dash_cap],
+                    });
+                }
+                start_cap = self.dash_cap;
+                index = (index + 1) % period;
+                remaining = pattern[index % pattern.len()];
+            }
+            remaining -= length - t;
+            if index % 2 == 0 {
+                push_point(&mut piece, b);
+            }
+        }
+        if piece.is_empty() {
+            return;
+        }
+        if closed && starts_on && dashes.len() > first {
+            // the last dash carries on into the first one.
+            let dash = &mut dashes[first];
+            for p in dash.points.drain(..) {
+                push_point(&mut piece, p);
+            }
+            dash.points = piece;
+            dash.caps[0] = self.dash_cap;
+        } else {
+            let end_cap = if closed { self.dash_cap } else { self.line_cap };
+            dashes.push(Dash {
+                points: piece,
+                caps: [start_cap, end_cap],
+            });
+        }
+    }
+
+    /// Returns `true` if `p` is in the stroke of a polyline, whose ends, if
+    /// it is open, have `caps`.
+    fn polyline_contains(
+        &self,
+        points: &[Point],
+        closed: bool,
+        caps: [LineCap; 2],
+        p: Point,
+        half_width: f64,
+    ) -> bool {
+        let n = points.len();
+        if n == 1 {
+            // a zero-length line only has caps.
+            let d = p - points[0];
+            return match caps[0] {
+                LineCap::Butt => false,
+                LineCap::Round => d.hypot() <= half_width,
+                LineCap::Square => d.x.abs() <= half_width && d.y.abs() <= half_width,
+            };
+        }
+        let segments = if closed { n } else { n - 1 };
+        let on_segment = (0..segments).any(|i| {
+            let (a, b) = (points[i], points[(i + 1) % n]);
+            let d = b - a;
+            let t = (p - a).dot(d) / d.hypot2();
+            (0.0..=1.0).contains(&t) && (p - a).cross(d).abs() <= half_width * d.hypot()
+        });
+        if on_segment {
+            return true;
+        }
+        let joins = if closed { 0..n } else { 1..n - 1 };
+        let on_join = joins.into_iter().any(|i| {
+            let prev = points[(i + n - 1) % n];
+            let next = points[(i + 1) % n];
+            self.join_contains(prev, points[i], next, p, half_width)
+        });
+        on_join
+            || (!closed
+                && (cap_contains(caps[0], points[0], points[0] - points[1], p, half_width)
+                    || cap_contains(
+                        caps[1],
+                        points[n - 1],
+                        points[n - 1] - points[n - 2],
+                        p,
+                        half_width,
+                    )))
+    }
+
+    fn join_contains(&self, prev: Point, v: Point, next: Point, p: Point, half_width: f64) -> bool {
+        let d0 = (v - prev).normalize();
+        let d1 = (next - v).normalize();
+        let cross = d0.cross(d1);
+        // the join fills the outside of the turn.
+        let outside = if cross > 0.0 { -half_width } else { half_width };
+        let n0 = Vec2::new(-d0.y, d0.x) * outside;
+        let n1 = Vec2::new(-d1.y, d1.x) * outside;
+        let bevel = [v, v + n0, v + n1];
+        match self.line_join {
+            LineJoin::Round => (p - v).hypot() <= half_width,
+            LineJoin::Bevel => convex_contains(&bevel, p),
+            LineJoin::Miter { limit } => {
+                let cos_half = ((1.0 + d0.dot(d1)) / 2.0).sqrt();
+                if cos_half > 0.0 && 1.0 / cos_half <= limit {
+                    let tip = v + (n0 + n1).normalize() * (half_width / cos_half);
+                    convex_contains(&[v, v + n0, tip, v + n1], p)
+                } else {
+                    convex_contains(&bevel, p)
+                }
+            }
+        }
+    }
+}
+
+/// A dash of a stroke, as an open polyline.
+struct Dash {
+    points: Vec<Point>,
+    /// The caps of the start and end; the line cap where the dash ends at
+    /// an end of the path, and the dash cap elsewhere.
+    caps: [LineCap; 2],
+}
+
+/// Flatten a path into polylines, each of which may be closed.
+fn flatten_polylines(path: &BezPath, tolerance: f64) -> Vec<(Vec<Point>, bool)> {
+    let mut polylines: Vec<(Vec<Point>, bool)> = Vec::new();
+    path.flatten(tolerance, |el| match el {
+        PathEl::MoveTo(p) => polylines.push((vec![p], false)),
+        PathEl::LineTo(p) => match polylines.last_mut() {
+            // drawing after a close starts again from the start.
+            Some((points, true)) => {
+                let start = points[0];
+                polylines.push((vec![start, p], false));
+            }
+            Some((points, false)) => push_point(points, p),
+            None => polylines.push((vec![p], false)),
+        },
+        PathEl::ClosePath => {
+            if let Some((points, closed)) = polylines.last_mut() {
+                if points.len() > 1 && points.first() == points.last() {
+                    points.pop();
+                }
+                *closed = true;
+            }
+        }
+        _ => (),
+    });
+    polylines
+}
+
+/// Returns `true` if `p` is in a cap at `end`, where the line leaves in
+/// `direction`.
+fn cap_contains(cap: LineCap, end: Point, direction: Vec2, p: Point, half_width: f64) -> bool {
+    let d = p - end;
+    match cap {
+        LineCap::Butt => false,
+        LineCap::Round => d.hypot() <= half_width,
+        LineCap::Square => {
+            let u = direction.normalize();
+            (0.0..=half_width).contains(&d.dot(u)) && d.cross(u).abs() <= half_width
+        }
+    }
+}
+
+/// Add the outline of a cap at `end`, where the line leaves in `direction`,
+/// to `path`.
+fn push_cap(path: &mut BezPath, cap: LineCap, end: Point, direction: Vec2, half_width: f64) {
+    let u = direction.normalize() * half_width;
+    let n = Vec2::new(-u.y, u.x);
+    match cap {
+        LineCap::Butt => return,
+        LineCap::Round => {
+            let arc = kurbo::Arc {
+                center: end,
+                radii: Vec2::new(half_width, half_width),
+                start_angle: n.atan2(),
+                sweep_angle: -std::f64::consts::PI,
+                x_rotation: 0.0,
+            };
+            path.extend(arc.path_elements(0.1));
+        }
+        LineCap::Square => {
+            path.move_to(end + n);
+            path.line_to(end + n + u);
+            path.line_to(end - n + u);
+            path.line_to(end - n);
+        }
+    }
+    path.close_path();
+}
+
+/// Draw the stroke of a shape whose dashes have a different cap from the
+/// ends of the path, as set with [`StrokeStyle::dash_cap`], for backends
+/// that can only draw one kind of cap.
+///
+/// The dashes are stroked with butt caps, and their caps are filled
+/// separately. This returns `false`, without drawing anything, if the
+/// style's dashes have the same cap as its ends, in which case the backend
+/// should draw the stroke itself. It is meant to be called at the start of
+/// [`RenderContext::stroke_styled`].
+///
+/// [`RenderContext::stroke_styled`]: crate::RenderContext::stroke_styled
+pub fn stroke_dash_caps<R: RenderContext>(
+    rc: &mut R,
+    shape: impl Shape,
+    brush: &impl IntoBrush<R>,
+    width: f64,
+    style: &StrokeStyle,
+) -> bool {
+    let geometry = StrokeGeometry::new(width, style);
+    if geometry.dash_cap == geometry.line_cap || !geometry.is_dashed() {
+        return false;
+    }
+    let transform = rc.current_transform();
+    if width <= 0.0 || transform.determinant() == 0.0 {
+        return true;
+    }
+    let brush = brush.make_brush(rc, || shape.bounding_box());
+
+    // work in the space that the width is measured in, flattening to a
+    // tenth of a pixel.
+    let (path, to_user, tolerance) = if style.scale {
+        let scale = transform.determinant().abs().sqrt();
+        (shape.into_path(1e-3), Affine::IDENTITY, 0.1 / scale)
+    } else {
+        (transform * shape.into_path(1e-3), transform.inverse(), 0.1)
+    };
+    let mut dashes = Vec::new();
+    for (points, closed) in flatten_polylines(&path, tolerance) {
+        geometry.dash(&points, closed, &mut dashes);
+    }
+
+    let half_width = width / 2.0;
+    let mut body = BezPath::new();
+    let mut caps = BezPath::new();
+    for Dash {
+        points,
+        caps: [start, end],
+    } in &dashes
+    {
+        let n = points.len();
+        if n == 1 {
+            let (p, r) = (points[0], half_width);
+            match start {
+                LineCap::Butt => (),
+                LineCap::Round => caps.extend(kurbo::Circle::new(p, r).path_elements(0.1)),
+                LineCap::Square => {
+                    caps.extend(Rect::new(p.x - r, p.y - r, p.x + r, p.y + r).path_elements(0.1))
+                }
+            }
+            continue;
+        }
+        body.move_to(points[0]);
+        for p in &points[1..] {
+            body.line_to(*p);
+        }
+        push_cap(
+            &mut caps,
+            *start,
+            points[0],
+            points[0] - points[1],
+            half_width,
+        );
+        let direction = points[n - 1] - points[n - 2];
+        push_cap(&mut caps, *end, points[n - 1], direction, half_width);
+    }
+
+    let body_style = StrokeStyle::new()
+        .line_join(style.line_join)
+        .scale(style.scale);
+    rc.stroke_styled(to_user * body, &*brush, width, &body_style);
+    rc.fill(to_user * caps, &*brush);
+    true
+}
+
+/// Split the outline of `shape` into the dashes that a stroke with a dash
+/// pattern draws.
+///
+/// `pattern` and `offset` are a [`StrokeStyle`]'s [`dash_pattern`] and
+/// [`dash_offset`]: the lengths alternate between dashes and gaps, an odd
+/// number of lengths is repeated, and each subpath starts `offset` into the
+/// pattern. Each dash is an open subpath of the result, and curves are split
+/// where the dashes start and end, rather than flattened. On a closed
+/// subpath, a dash that runs over the start is kept in one piece, and a dash
+/// of zero length is a line to its own start, so that it still has caps.
+///
+/// Stroking the result without a dash pattern draws the same dashes as
+/// stroking `shape` with one, which is useful for backends that can't dash
+/// strokes themselves, and for testing points against exactly the dashes
+/// that are visible. If the pattern is empty, or has lengths that are
+/// negative or not finite, the outline is returned undashed.
+///
+/// [`dash_pattern`]: StrokeStyle#structfield.dash_pattern
+/// [`dash_offset`]: StrokeStyle#structfield.dash_offset
+pub fn dash(shape: impl Shape, pattern: &[f64], offset: f64) -> BezPath {
+    let is_dashed =
+        pattern.iter().all(|x| x.is_finite() && *x >= 0.0) && pattern.iter().sum::<f64>() > 0.0;
+    if !is_dashed {
+        return shape.into_path(1e-3);
+    }
+    let mut dashed = BezPath::new();
+    let mut segments = Vec::new();
+    let (mut start, mut last) = (Point::ZERO, Point::ZERO);
+    for el in shape.path_elements(1e-3) {
+        let seg = match el {
+            PathEl::MoveTo(p) => {
+                dash_subpath(&segments, false, pattern, offset, &mut dashed);
+                segments.clear();
+                start = p;
+                last = p;
+                continue;
+            }
+            PathEl::LineTo(p) => PathSeg::Line(Line::new(last, p)),
+            PathEl::QuadTo(p1, p2) => PathSeg::Quad(QuadBez::new(last, p1, p2)),
+            PathEl::CurveTo(p1, p2, p3) => PathSeg::Cubic(CubicBez::new(last, p1, p2, p3)),
+            PathEl::ClosePath => {
+                if last != start {
+                    segments.push(PathSeg::Line(Line::new(last, start)));
+                }
+                dash_subpath(&segments, true, pattern, offset, &mut dashed);
+                segments.clear();
+                last = start;
+                continue;
+            }
+        };
+        last = seg.end();
+        segments.push(seg);
+    }
+    dash_subpath(&segments, false, pattern, offset, &mut dashed);
+    dashed
+}
+
+/// Add the dashes of a subpath to `dashed`; see [`dash`].
+fn dash_subpath(
+    segments: &[PathSeg],
+    closed: bool,
+    pattern: &[f64],
+    offset: f64,
+    dashed: &mut BezPath,
+) {
+    if segments.is_empty() {
+        return;
+    }
+    const ACCURACY: f64 = 1e-6;
+    // an odd number of lengths is repeated, so that dashes and gaps
+    // alternate.
+    let period = if pattern.len() % 2 == 1 {
+        pattern.len() * 2
+    } else {
+        pattern.len()
+    };
+    let total = pattern.iter().sum::<f64>() * (period / pattern.len()) as f64;
+    let mut offset = offset.rem_euclid(total);
+    let mut index = 0;
+    let mut remaining = pattern[0];
+    while offset > remaining {
+        offset -= remaining;
+        index = (index + 1) % period;
+        remaining = pattern[index % pattern.len()];
+    }
+    remaining -= offset;
+
+    let starts_on = index % 2 == 0;
+    let mut els = Vec::new();
+    // the start of the dash that is being drawn, if there is one.
+    let mut dash_start = None;
+    let mut n_dashes = 0;
+    for seg in segments {
+        let length = seg.arclen(ACCURACY);
+        let (mut pos, mut t0) = (0.0, 0.0);
+        loop {
+            let on = index % 2 == 0;
+            if on && dash_start.is_none() {
+                dash_start = Some(els.len());
+                els.push(PathEl::MoveTo(seg.eval(t0)));
+            }
+            if length - pos <= remaining {
+                remaining -= length - pos;
+                if on && t0 < 1.0 {
+                    els.push(seg.subsegment(t0..1.0).as_path_el());
+                }
+                break;
+            }
+            pos += remaining;
+            let t1 = seg.inv_arclen(pos, ACCURACY).max(t0);
+            if on {
+                if t1 > t0 {
+                    els.push(seg.subsegment(t0..t1).as_path_el());
+                } else if dash_start == Some(els.len() - 1) {
+                    els.push(PathEl::LineTo(seg.eval(t0)));
+                }
+                dash_start = None;
+                n_dashes += 1;
+            }
+            t0 = t1;
+            index = (index + 1) % period;
+            remaining = pattern[index % pattern.len()];
+        }
+    }
+    match dash_start {
+        // the pattern doesn't break the subpath.
+        Some(_) if closed && n_dashes == 0 => {
+            // the close draws the line back to the start.
+            if let (PathEl::MoveTo(start), Some(PathEl::LineTo(end))) = (els[0], els.last()) {
+                if els.len() > 2 && start == *end {
+                    els.pop();
+                }
+            }
+            dashed.extend(els);
+            dashed.close_path();
+        }
+        // the last dash carries on into the first one.
+        Some(last) if closed && starts_on && n_dashes > 0 => {
+            let first_end = els[1..]
+                .iter()
+                .position(|el| matches!(el, PathEl::MoveTo(_)))
+                .map(|i| i + 1)
+                .unwrap_or(last);
+            dashed.extend(els[last..].iter().copied());
+            dashed.extend(els[1..first_end].iter().copied());
+            dashed.extend(els[first_end..last].iter().copied());
+        }
+        _ => dashed.extend(els),
+    }
+}
+
+/// Stroke a shape with markers at its ends, filling the markers after the
+/// stroke.
+///
+/// This is the default implementation of
+/// [`RenderContext::stroke_with_markers`], for backends that draw markers
+/// natively to fall back on. The markers are sized and placed in the space
+/// that the width is measured in, so that they don't scale with the
+/// transform if [`StrokeStyle::scale`] is `false`.
+///
+/// [`RenderContext::stroke_with_markers`]: crate::RenderContext::stroke_with_markers
+/// [`StrokeStyle::scale`]: crate::StrokeStyle#structfield.scale
+pub fn stroke_with_markers<R: RenderContext + ?Sized>(
+    rc: &mut R,
+    shape: impl Shape,
+    brush: &impl IntoBrush<R>,
+    width: f64,
+    style: &StrokeStyle,
+    markers: &Markers,
+) {
+    let brush = brush.make_brush(rc, || shape.bounding_box());
+    let transform = rc.current_transform();
+    let (to_width, tolerance) = if style.scale {
+        let scale = transform.determinant().abs().sqrt();
+        (Affine::IDENTITY, 0.1 / scale)
+    } else {
+        (transform, 0.1)
+    };
+    if to_width.determinant() == 0.0 || !tolerance.is_finite() {
+        return;
+    }
+    let to_user = to_width.inverse();
+    let layout = markers.layout(&(to_width * shape.into_path(1e-3)), width);
+    rc.stroke_styled(to_user * layout.path, &*brush, width, style);
+    for (marker, placement) in layout.start.into_iter().chain(layout.end) {
+        let path = marker.to_path(tolerance / width);
+        rc.fill(to_user * placement * path, &*brush);
+    }
+}
+
+/// Draw a symbol at each of `instances`, one at a time.
+///
+/// This is the default implementation of
+/// [`RenderContext::draw_symbol_instances`], for backends that draw some
+/// symbols natively to fall back on. Each instance is drawn between a save
+/// and a restore, with its transform appended to the current transform.
+///
+/// [`RenderContext::draw_symbol_instances`]: crate::RenderContext::draw_symbol_instances
+pub fn draw_symbol_instances<R: RenderContext + ?Sized>(
+    rc: &mut R,
+    symbol: Symbol<R::Image>,
+    brush: &impl IntoBrush<R>,
+    instances: &[(Affine, Option<Color>)],
+) {
+    let brush = brush.make_brush(rc, || symbol.bounds());
+    for &(transform, color) in instances {
+        let color = color.map(|color| rc.solid_brush(color));
+        let _ = rc.with_save(|rc| {
+            rc.transform(transform);
+            match symbol {
+                Symbol::Shape(shape) => match &color {
+                    Some(color) => rc.fill(shape, color),
+                    None => rc.fill(shape, &*brush),
+                },
+                Symbol::Image {
+                    image,
+                    rect,
+                    interp,
+                } => match &color {
+                    Some(color) => rc.fill_with_mask(rect, color, image),
+                    None => rc.draw_image(image, rect, interp),
+                },
+            }
+            Ok(())
+        });
+    }
+}
+
+/// The projective transform that maps the unit square onto a quad.
+///
+/// The corners `(0, 0)`, `(1, 0)`, `(1, 1)` and `(0, 1)` are mapped to the
+/// corners of `quad`, in that order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuadMap {
+    coeffs: [f64; 8],
+}
+
+impl QuadMap {
+    /// The map onto `quad`, or `None` if it isn't a convex quad that has its
+    /// corners in order.
+    pub fn new(quad: [Point; 4]) -> Option<QuadMap> {
+        let [p0, p1, p2, p3] = quad;
+        let sx = p0.x - p1.x + p2.x - p3.x;
+        let sy = p0.y - p1.y + p2.y - p3.y;
+        let (g, h) = if sx == 0.0 && sy == 0.0 {
+            (0.0, 0.0)
+        } else {
+            let (d1, d2) = (p1 - p2, p3 - p2);
+            let det = d1.cross(d2);
+            if det == 0.0 {
+                return None;
+            }
+            ((sx * d2.y - d2.x * sy) / det, (d1.x * sy - sx * d1.y) / det)
+        };
+        let map = QuadMap {
+            coeffs: [
+                p1.x - p0.x + g * p1.x,
+                p3.x - p0.x + h * p3.x,
+                p0.x,
+                p1.y - p0.y + g * p1.y,
+                p3.y - p0.y + h * p3.y,
+                p0.y,
+                g,
+                h,
+            ],
+        };
+        // the corners turn the same way, and the map doesn't go through
+        // infinity between them.
+        let turns = [(p0, p1, p2), (p1, p2, p3), (p2, p3, p0), (p3, p0, p1)]
+            .map(|(a, b, c)| (b - a).cross(c - b));
+        let convex = turns.iter().all(|&turn| turn > 0.0) || turns.iter().all(|&turn| turn < 0.0);
+        let finite = map.coeffs.iter().all(|c| c.is_finite());
+        let ahead = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]
+            .iter()
+            .all(|&(u, v)| map.w(u, v) > 0.0);
+        (convex && finite && ahead).then_some(map)
+    }
+
+    fn w(&self, u: f64, v: f64) -> f64 {
+        self.coeffs[6] * u + self.coeffs[7] * v + 1.0
+    }
+
+    /// Returns `true` if the quad is a parallelogram, which an [`Affine`]
+    /// maps the unit square onto.
+    pub fn is_affine(&self) -> bool {
+        self.coeffs[6] == 0.0 && self.coeffs[7] == 0.0
+    }
+
+    /// The point of the quad that `(u, v)` in the unit square maps to.
+    pub fn map(&self, u: f64, v: f64) -> Point {
+        let [a, b, c, d, e, f, ..] = self.coeffs;
+        let w = self.w(u, v);
+        Point::new((a * u + b * v + c) / w, (d * u + e * v + f) / w)
+    }
+}
+
+/// Draw `image` warped onto `quad`, by splitting it into triangles that are
+/// each drawn with an affine transform.
+///
+/// This is the default implementation of
+/// [`RenderContext::draw_image_with_quad`]. Quads that are parallelograms
+/// are drawn with one transform; for others, the image is split into a grid
+/// of cells that is fine enough that the triangles stray from the true warp
+/// by less than a quarter of a unit of user space.
+///
+/// Each triangle is clipped to itself, grown by a pixel so that no seams
+/// show between them, and the whole image is clipped to the quad. The
+/// triangles overlap where they are grown, which shows as faint lines where
+/// the image is translucent.
+///
+/// [`RenderContext::draw_image_with_quad`]: crate::RenderContext::draw_image_with_quad
+pub fn draw_image_with_quad<R: RenderContext + ?Sized>(
+    rc: &mut R,
+    image: &R::Image,
+    quad: [Point; 4],
+    interp: InterpolationMode,
+) {
+    let size = image.size();
+    let map = match QuadMap::new(quad) {
+        Some(map) if !size.is_empty() => map,
+        _ => return,
+    };
+    let bounds = size.to_rect();
+    if map.is_affine() {
+        let [p0, p1, _, p3] = quad;
+        let transform = Affine::new([
+            (p1.x - p0.x) / size.width,
+            (p1.y - p0.y) / size.width,
+            (p3.x - p0.x) / size.height,
+            (p3.y - p0.y) / size.height,
+            p0.x,
+            p0.y,
+        ]);
+        let _ = rc.with_save(|rc| {
+            rc.transform(transform);
+            rc.draw_image(image, bounds, interp);
+            Ok(())
+        });
+        return;
+    }
+
+    // the triangles of a cell of 1/n of the quad are off by about 1/n² of
+    // how far its center is from that of the parallelogram of its corners.
+    let [p0, p1, p2, p3] = quad;
+    let error = map
+        .map(0.5, 0.5)
+        .distance(p0.midpoint(p2).midpoint(p1.midpoint(p3)));
+    let n = ((error / 0.25).sqrt().ceil() as usize).clamp(1, 64);
+    let scale = rc.current_transform().determinant().abs().sqrt();
+    let grow = if scale > 0.0 { 1.0 / scale } else { 0.0 };
+
+    let _ = rc.with_save(|rc| {
+        rc.clip(polygon(&quad));
+        for row in 0..n {
+            for col in 0..n {
+                let (u0, u1) = (col as f64 / n as f64, (col + 1) as f64 / n as f64);
+                let (v0, v1) = (row as f64 / n as f64, (row + 1) as f64 / n as f64);
+                let cell = Rect::new(
+                    u0 * size.width,
+                    v0 * size.height,
+                    u1 * size.width,
+                    v1 * size.height,
+                );
+                let corners = [(u0, v0), (u1, v0), (u1, v1), (u0, v1)];
+                let dst = corners.map(|(u, v)| map.map(u, v));
+                let src = corners.map(|(u, v)| Point::new(u * size.width, v * size.height));
+                for [a, b, c] in [[0, 1, 2], [0, 2, 3]] {
+                    let transform =
+                        triangle_transform([src[a], src[b], src[c]], [dst[a], dst[b], dst[c]]);
+                    let triangle = grow_triangle([dst[a], dst[b], dst[c]], grow);
+                    // enough of the image around the cell to fill the
+                    // triangle where it has grown.
+                    let [xx, xy, yx, yy, ..] = transform.inverse().as_coeffs();
+                    let margin = grow * (Vec2::new(xx, xy).hypot() + Vec2::new(yx, yy).hypot());
+                    let area = cell.inflate(margin, margin).intersect(bounds);
+                    let _ = rc.with_save(|rc| {
+                        rc.clip(polygon(&triangle));
+                        rc.transform(transform);
+                        rc.draw_image_area(image, area, area, interp);
+                        Ok(())
+                    });
+                }
+            }
+        }
+        Ok(())
+    });
+}
+
+/// The closed path through `points`.
+fn polygon(points: &[Point]) -> BezPath {
+    let mut path = BezPath::new();
+    for (i, &point) in points.iter().enumerate() {
+        if i == 0 {
+            path.move_to(point);
+        } else {
+            path.line_to(point);
+        }
+    }
+    path.close_path();
+    path
+}
+
+/// The affine transform that maps the triangle `from` onto `to`.
+fn triangle_transform(from: [Point; 3], to: [Point; 3]) -> Affine {
+    let basis = |[p0, p1, p2]: [Point; 3]| {
+        Affine::new([
+            p1.x - p0.x,
+            p1.y - p0.y,
+            p2.x - p0.x,
+            p2.y - p0.y,
+            p0.x,
+            p0.y,
+        ])
+    };
+    basis(to) * basis(from).inverse()
+}
+
+/// Move the sides of a triangle out by `by`.
+fn grow_triangle(triangle: [Point; 3], by: f64) -> [Point; 3] {
+    let [a, b, c] = triangle;
+    let turn = (b - a).cross(c - b).signum();
+    // the outward normals of the sides from each corner to the next.
+    let normal = |from: Point, to: Point| {
+        let side = to - from;
+        let length = side.hypot();
+        if length > 0.0 {
+            Vec2::new(side.y, -side.x) * (turn / length)
+        } else {
+            Vec2::ZERO
+        }
+    };
+    let normals = [normal(a, b), normal(b, c), normal(c, a)];
+    let mut grown = triangle;
+    for (i, corner) in grown.iter_mut().enumerate() {
+        // where the sides that meet at the corner meet once moved, unless
+        // the corner is so sharp that it would go far.
+        let (n0, n1) = (normals[(i + 2) % 3], normals[i]);
+        let miter = (n0 + n1) / (1.0 + n0.dot(n1)).max(0.25);
+        *corner += miter * by;
+    }
+    grown
+}
+
+/// The color of a gradient at `t`, whose stops are in order.
+///
+/// Before the first stop and after the last, the color is that of the stop.
+pub fn gradient_color(stops: &[GradientStop], t: f32) -> Color {
+    match gradient_rgba(stops, t) {
+        Some([r, g, b, a]) => Color::rgba(r, g, b, a),
+        None => Color::TRANSPARENT,
+    }
+}
+
+/// The color of a gradient at `t`, as separate RGBA from 0 to 1, without
+/// being rounded to 8 bits. This is `None` if there are no stops.
+pub(crate) fn gradient_rgba(stops: &[GradientStop], t: f32) -> Option<[f64; 4]> {
+    let rgba = |color: Color| {
+        let (r, g, b, a) = color.as_rgba();
+        [r, g, b, a]
+    };
+    Some(match stops {
+        [] => return None,
+        [first, ..] if t <= first.pos => rgba(first.color),
+        [.., last] if t >= last.pos => rgba(last.color),
+        _ => {
+            let i = stops
+                .iter()
+                .position(|stop| stop.pos > t)
+                .unwrap_or(0)
+                .max(1);
+            let (a, b) = (&stops[i - 1], &stops[i]);
+            let span = b.pos - a.pos;
+            let frac = if span > 0.0 { (t - a.pos) / span } else { 0.0 };
+            let (c0, c1) = (rgba(a.color), rgba(b.color));
+            [0, 1, 2, 3].map(|i| c0[i] + (c1[i] - c0[i]) * frac as f64)
+        }
+    })
+}
+
+/// Stroke a shape with a gradient that follows its length, such that the
+/// color at a distance along the path is that of `stops` at the fraction of
+/// the total length of the path.
+///
+/// This is the default implementation of
+/// [`RenderContext::stroke_gradient_along_path`]. The path is split into
+/// pieces about a pixel long, or a thousandth of the path if that is
+/// longer, each stroked with a solid color. If the stops are opaque, each
+/// piece carries on a pixel under the next, to hide the seams between them;
+/// translucent gradients may show faint seams.
+///
+/// [`RenderContext::stroke_gradient_along_path`]: crate::RenderContext::stroke_gradient_along_path
+pub fn stroke_gradient_along_path<R: RenderContext + ?Sized>(
+    rc: &mut R,
+    shape: impl Shape,
+    stops: &[GradientStop],
+    width: f64,
+    style: &StrokeStyle,
+) {
+    let transform = rc.current_transform();
+    // work in the space that the width is measured in, where a pixel is
+    // `pixel` long.
+    let (to_width, pixel) = if style.scale {
+        (Affine::IDENTITY, 1.0 / transform.determinant().abs().sqrt())
+    } else {
+        (transform, 1.0)
+    };
+    if stops.is_empty() || width <= 0.0 || !pixel.is_finite() || to_width.determinant() == 0.0 {
+        return;
+    }
+    let to_user = to_width.inverse();
+    let path = to_width * shape.into_path(1e-3);
+    let polylines = flatten_polylines(&path, pixel * 0.1);
+    let lengths: Vec<f64> = polylines
+        .iter()
+        .map(|(points, closed)| polyline_length(points, *closed))
+        .collect();
+    let total = lengths.iter().sum::<f64>();
+    let color_at = |distance: f64| {
+        let t = if total > 0.0 { distance / total } else { 0.0 };
+        gradient_color(stops, t as f32)
+    };
+    let piece_length = pixel.max(total / 1000.0);
+    // overlapping translucent pieces would show, unlike the seams.
+    let opaque = stops.iter().all(|stop| stop.color.as_rgba().3 >= 1.0);
+    let overlap = if opaque { pixel } else { 0.0 };
+
+    let geometry = StrokeGeometry::new(width, style);
+    let dashed = geometry.is_dashed();
+    let piece_style = StrokeStyle {
+        line_cap: LineCap::Butt,
+        dash_cap: Some(style.effective_dash_cap()),
+        ..style.clone()
+    };
+    let half_width = width / 2.0;
+    let mut travelled = 0.0;
+    for ((points, closed), length) in polylines.iter().zip(lengths) {
+        for (start, end, piece, piece_closed) in
+            split_polyline(points, *closed, piece_length, overlap)
+        {
+            let mut piece_path = BezPath::new();
+            piece_path.move_to(piece[0]);
+            for p in &piece[1..] {
+                piece_path.line_to(*p);
+            }
+            if piece_closed {
+                piece_path.close_path();
+            }
+            let mut piece_style = piece_style.clone();
+            // the dashes carry on from piece to piece.
+            piece_style.dash_offset += start;
+            let brush = rc.solid_brush(color_at(travelled + (start + end) / 2.0));
+            rc.stroke_styled(to_user * piece_path, &brush, width, &piece_style);
+        }
+
+        // the pieces have butt caps, so the caps at the ends are added.
+        if *closed || style.line_cap == LineCap::Butt {
+            travelled += length;
+            continue;
+        }
+        let n = points.len();
+        let mut caps = Vec::new();
+        if n == 1 {
+            let (p, r) = (points[0], half_width);
+            let mut cap = BezPath::new();
+            match style.line_cap {
+                LineCap::Round => cap.extend(kurbo::Circle::new(p, r).path_elements(0.1)),
+                _ => cap.extend(Rect::new(p.x - r, p.y - r, p.x + r, p.y + r).path_elements(0.1)),
+            }
+            caps.push((cap, travelled));
+        } else {
+            let ends = [
+                (points[0], points[0] - points[1], 0.0),
+                (points[n - 1], points[n - 1] - points[n - 2], length),
+            ];
+            for (end, direction, distance) in ends {
+                // a dashed line has no cap where it is in a gap.
+                if dashed && !geometry.dash_is_on(distance) {
+                    continue;
+                }
+                let mut cap = BezPath::new();
+                push_cap(&mut cap, style.line_cap, end, direction, half_width);
+                caps.push((cap, travelled + distance));
+            }
+        }
+        for (cap, distance) in caps {
+            let brush = rc.solid_brush(color_at(distance));
+            rc.fill(to_user * cap, &brush);
+        }
+        travelled += length;
+    }
+}
+
+/// The length of a polyline.
+fn polyline_length(points: &[Point], closed: bool) -> f64 {
+    let open = points
+        .windows(2)
+        .map(|w| (w[1] - w[0]).hypot())
+        .sum::<f64>();
+    if closed && points.len() > 1 {
+        open + (points[0] - points[points.len() - 1]).hypot()
+    } else {
+        open
+    }
+}
+
+/// Split a polyline into open pieces about `piece_length` long, each with
+/// the distances along the polyline where it starts and ends.
+///
+/// Pieces are never split at a vertex, where they would lose their join.
+/// The first piece of a closed polyline is added to the end of the last,
+/// unless the polyline is a single point. Each piece carries on under the
+/// next one by `overlap`, to hide the seams between them.
+fn split_polyline(
+    points: &[Point],
+    closed: bool,
+    piece_length: f64,
+    overlap: f64,
+) -> Vec<(f64, f64, Vec<Point>, bool)> {
+    let mut pieces = Vec::new();
+    let mut piece = vec![points[0]];
+    let mut start = 0.0;
+    // a closed polyline is split as soon as possible, for the first piece
+    // to be added to the end.
+    let mut next_cut = if closed { 0.0 } else { piece_length };
+    let mut travelled = 0.0;
+    let closing = closed.then(|| (points[points.len() - 1], points[0]));
+    let segments = points.windows(2).map(|w| (w[0], w[1])).chain(closing);
+    for (a, b) in segments {
+        let length = (b - a).hypot();
+        // keep cuts a little way from either end of the segment.
+        let margin = piece_length.min(length) * 0.25;
+        loop {
+            let cut = (next_cut - travelled).max(margin);
+            if cut > length - margin {
+                break;
+            }
+            let p = a.lerp(b, cut / length);
+            push_point(&mut piece, p);
+            let end = travelled + cut;
+            pieces.push((start, end, std::mem::replace(&mut piece, vec![p]), false));
+            start = end;
+            next_cut = end + piece_length;
+        }
+        push_point(&mut piece, b);
+        travelled += length;
+    }
+    if !closed {
+        pieces.push((start, travelled, piece, false));
+    } else if pieces.is_empty() {
+        if piece.len() > 1 && piece.first() == piece.last() {
+            piece.pop();
+        }
+        pieces.push((0.0, travelled, piece, true));
+    } else {
+        let (_, first_end, first, _) = pieces.remove(0);
+        for p in first {
+            push_point(&mut piece, p);
+        }
+        pieces.push((start, travelled + first_end, piece, false));
+    }
+    if overlap > 0.0 {
+        let n = pieces.len();
+        let overlapped = if closed { n } else { n - 1 };
+        for i in 0..overlapped {
+            let next = pieces[(i + 1) % n].2.clone();
+            extend_along(&mut pieces[i].2, &next, overlap);
+        }
+    }
+    pieces
+}
+
+/// Add the first `distance` of a polyline that starts at the end of
+/// `points` to them.
+fn extend_along(points: &mut Vec<Point>, next: &[Point], mut distance: f64) {
+    for w in next.windows(2) {
+        let length = (w[1] - w[0]).hypot();
+        if length >= distance {
+            push_point(points, w[0].lerp(w[1], distance / length));
+            return;
+        }
+        push_point(points, w[1]);
+        distance -= length;
+    }
+}
+
+/// Add a point to a polyline, unless it is the same as the last one.
+fn push_point(points: &mut Vec<Point>, p: Point) {
+    if points.last() != Some(&p) {
+        points.push(p);
+    }
+}
+
+/// Returns `true` if `p` is inside or on the edge of a convex polygon.
+fn convex_contains(points: &[Point], p: Point) -> bool {
+    let mut side = 0.0;
+    for (i, a) in points.iter().enumerate() {
+        let b = points[(i + 1) % points.len()];
+        let cross = (b - *a).cross(p - *a);
+        if cross != 0.0 {
+            if side == 0.0 {
+                side = cross.signum();
+            } else if cross.signum() != side {
+                return false;
+            }
+        }
+    }
+    true
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn quad_map_corners() {
+        let quad = [
+            Point::new(10., 0.),
+            Point::new(30., 5.),
+            Point::new(40., 40.),
+            Point::new(0., 30.),
+        ];
+        let map = QuadMap::new(quad).unwrap();
+        assert!(!map.is_affine());
+        let corners = [(0., 0.), (1., 0.), (1., 1.), (0., 1.)];
+        for (&(u, v), &corner) in corners.iter().zip(&quad) {
+            assert!(map.map(u, v).distance(corner) < 1e-9);
+        }
+
+        let square = [(0., 0.), (10., 0.), (10., 10.), (0., 10.)].map(Point::from);
+        let map = QuadMap::new(square).unwrap();
+        assert!(map.is_affine());
+        assert_eq!(map.map(0.5, 0.25), Point::new(5., 2.5));
+
+        // a bow tie and a quad with a dent aren't convex.
+        let [a, b, c, d] = square;
+        assert!(QuadMap::new([a, c, b, d]).is_none());
+        assert!(QuadMap::new([a, b, Point::new(2., 2.), d]).is_none());
+    }
+
     #[test]
     fn test_count_until_utf16() {
         // Notes on this input:
@@ -253,4 +1804,391 @@ mod tests {
 
         assert_eq!(count_until_utf16("", 0), None);
     }
+
+    #[test]
+    fn test_next_tab_stop() {
+        let stops = [10.0, 25.0];
+        assert_eq!(next_tab_stop(0.0, &stops, 40.0), 10.0);
+        assert_eq!(next_tab_stop(10.0, &stops, 40.0), 25.0);
+        assert_eq!(next_tab_stop(30.0, &stops, 40.0), 40.0);
+        assert_eq!(next_tab_stop(40.0, &stops, 40.0), 80.0);
+        assert_eq!(next_tab_stop(5.0, &[], 0.0), 5.0);
+    }
+
+    #[test]
+    fn test_merge_spans() {
+        let spans = [(20.0, 30.0), (0.0, 10.0), (10.0, 15.0), (25.0, 40.0)];
+        assert_eq!(merge_spans(spans), vec![(0.0, 15.0), (20.0, 40.0)]);
+        assert!(merge_spans(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn test_to_rgba8() {
+        let bgra = [1, 2, 3, 4, 5, 6, 7, 8];
+        let (rgba, format) = to_rgba8(&bgra, ImageFormat::BgraPremul);
+        assert_eq!(&*rgba, &[3, 2, 1, 4, 7, 6, 5, 8]);
+        assert_eq!(format, ImageFormat::RgbaPremul);
+
+        let rgba16 = [0xffffu16, 0, 0x8000, 0x0080]
+            .iter()
+            .flat_map(|x| x.to_ne_bytes())
+            .collect::<Vec<_>>();
+        let (rgba, format) = to_rgba8(&rgba16, ImageFormat::Rgba16);
+        assert_eq!(&*rgba, &[255, 0, 128, 0]);
+        assert_eq!(format, ImageFormat::RgbaSeparate);
+
+        let (rgb, format) = to_rgba8(&bgra[..6], ImageFormat::Rgb);
+        assert!(matches!(rgb, Cow::Borrowed(_)));
+        assert_eq!(format, ImageFormat::Rgb);
+    }
+
+    #[test]
+    fn test_pixel_conversions() {
+        // every channel with every alpha, plus a few pixels left over.
+        let pixels = (0..=255u8)
+            .flat_map(|a| (0..=255u8).map(move |x| [x, 255 - x, x / 2, a]))
+            .chain([[1, 2, 3, 4], [5, 6, 7, 8], [9, 10, 11, 12]])
+            .collect::<Vec<_>>();
+        let bytes = pixels.iter().flatten().copied().collect::<Vec<_>>();
+
+        let mut data = bytes.clone();
+        premultiply_rgba(&mut data);
+        for (px, out) in pixels.iter().zip(data.chunks_exact(4)) {
+            let a = px[3];
+            assert_eq!(
+                out,
+                [premul(px[0], a), premul(px[1], a), premul(px[2], a), a]
+            );
+        }
+
+        let mut data = bytes.clone();
+        unpremultiply_rgba(&mut data);
+        for (px, out) in pixels.iter().zip(data.chunks_exact(4)) {
+            let a = px[3];
+            if a == 0 {
+                assert_eq!(out, px);
+            } else {
+                assert_eq!(
+                    out,
+                    [
+                        unpremul(px[0], a),
+                        unpremul(px[1], a),
+                        unpremul(px[2], a),
+                        a
+                    ]
+                );
+            }
+        }
+
+        let mut data = bytes.clone();
+        swap_red_blue(&mut data);
+        for (px, out) in pixels.iter().zip(data.chunks_exact(4)) {
+            assert_eq!(out, [px[2], px[1], px[0], px[3]]);
+        }
+
+        let rgb = bytes[..999].to_vec();
+        let mut data = vec![0; 333 * 4 + 1];
+        rgb_to_rgba(&rgb, &mut data);
+        for (px, out) in rgb.chunks_exact(3).zip(data.chunks_exact(4)) {
+            assert_eq!(out, [px[0], px[1], px[2], 255]);
+        }
+        assert_eq!(data[333 * 4], 0);
+
+        let mut data = vec![0; 999 * 4];
+        gray_to_rgba(&bytes[..1000], &mut data);
+        for (&v, out) in bytes.iter().zip(data.chunks_exact(4)) {
+            assert_eq!(out, [v, v, v, 255]);
+        }
+    }
+
+    #[test]
+    fn mip_levels_average_pixels() {
+        // a 3 by 1 image of a white, black and white pixel.
+        let data = [255, 255, 255, 255, 0, 0, 0, 255, 255, 255, 255, 255];
+        let levels = mip_levels(3, 1, &data);
+        let sizes: Vec<_> = levels.iter().map(|(w, h, _)| (*w, *h)).collect();
+        assert_eq!(sizes, [(2, 1), (1, 1)]);
+        assert_eq!(levels[0].2, [128, 128, 128, 255, 255, 255, 255, 255]);
+        assert_eq!(levels[1].2, [192, 192, 192, 255]);
+        assert!(mip_levels(0, 4, &[]).is_empty());
+        assert!(mip_levels(1, 1, &data[..4]).is_empty());
+    }
+
+    #[test]
+    fn sub_image_views() {
+        let sheet = Rect::new(0., 0., 64., 32.);
+        let sprite = sub_image_view(sheet, Rect::new(16., 0., 32., 16.));
+        assert_eq!(sprite, Rect::new(16., 0., 32., 16.));
+        // sub-images of sub-images are in the space of the sub-image.
+        let part = sub_image_view(sprite, Rect::new(4.2, 8., 20., 15.6));
+        assert_eq!(part, Rect::new(20., 8., 32., 16.));
+        let outside = sub_image_view(sprite, Rect::new(-10., -10., -2., -2.));
+        assert_eq!(outside.area(), 0.0);
+    }
+
+    #[test]
+    fn test_nine_patch() {
+        let size = Size::new(30.0, 30.0);
+        let areas = nine_patch(
+            size,
+            Insets::uniform(10.0),
+            Rect::new(0.0, 0.0, 100.0, 50.0),
+        );
+        assert_eq!(areas.len(), 9);
+        assert_eq!(
+            areas[0],
+            (
+                Rect::new(0.0, 0.0, 10.0, 10.0),
+                Rect::new(0.0, 0.0, 10.0, 10.0)
+            )
+        );
+        assert_eq!(
+            areas[4],
+            (
+                Rect::new(10.0, 10.0, 20.0, 20.0),
+                Rect::new(10.0, 10.0, 90.0, 40.0)
+            )
+        );
+        assert_eq!(
+            areas[8],
+            (
+                Rect::new(20.0, 20.0, 30.0, 30.0),
+                Rect::new(90.0, 40.0, 100.0, 50.0)
+            )
+        );
+
+        // the corners are scaled down to fit, and the empty center is left out.
+        let areas = nine_patch(
+            size,
+            Insets::uniform(10.0),
+            Rect::new(0.0, 0.0, 10.0, 100.0),
+        );
+        assert_eq!(areas.len(), 6);
+        assert_eq!(areas[0].1, Rect::new(0.0, 0.0, 5.0, 10.0));
+        assert_eq!(areas[1].1, Rect::new(5.0, 0.0, 10.0, 10.0));
+
+        // insets larger than the image are fitted into it first.
+        let areas = nine_patch(size, Insets::new(0.0, 0.0, 60.0, 0.0), size.to_rect());
+        assert_eq!(areas, vec![(size.to_rect(), size.to_rect())]);
+    }
+
+    #[test]
+    fn test_fill_contains() {
+        use crate::kurbo::BezPath;
+
+        let transform = Affine::translate((100.0, 0.0)) * Affine::scale(2.0);
+        let rect = Rect::new(0.0, 0.0, 10.0, 10.0);
+        assert!(fill_contains(rect, transform, Point::new(110.0, 10.0)));
+        assert!(!fill_contains(rect, transform, Point::new(90.0, 10.0)));
+        assert!(!fill_contains(rect, Affine::scale(0.0), Point::ZERO));
+
+        let mut nested = BezPath::new();
+        nested.extend(rect.path_elements(0.1));
+        nested.extend(Rect::new(-10.0, -10.0, 20.0, 20.0).path_elements(0.1));
+        assert!(fill_contains(
+            &nested,
+            Affine::IDENTITY,
+            Point::new(5.0, 5.0)
+        ));
+        assert!(!fill_even_odd_contains(
+            &nested,
+            Affine::IDENTITY,
+            Point::new(5.0, 5.0)
+        ));
+        assert!(fill_even_odd_contains(
+            &nested,
+            Affine::IDENTITY,
+            Point::new(15.0, 5.0)
+        ));
+    }
+
+    #[test]
+    fn test_stroke_contains() {
+        use crate::kurbo::Line;
+
+        let line = Line::new((0.0, 0.0), (100.0, 0.0));
+        let hit = |style: &StrokeStyle, x, y| {
+            stroke_contains(line, Affine::IDENTITY, Point::new(x, y), 10.0, style)
+        };
+        let butt = StrokeStyle::new();
+        assert!(hit(&butt, 50.0, 4.0));
+        assert!(!hit(&butt, 50.0, 6.0));
+        assert!(!hit(&butt, -3.0, 0.0));
+        let square = StrokeStyle::new().line_cap(LineCap::Square);
+        assert!(hit(&square, -3.0, 4.0));
+        let round = StrokeStyle::new().line_cap(LineCap::Round);
+        assert!(hit(&round, -3.0, 3.0));
+        assert!(!hit(&round, -4.5, 4.5));
+
+        let dashed = StrokeStyle::new().dash_pattern(&[10.0, 10.0]);
+        assert!(hit(&dashed, 5.0, 0.0));
+        assert!(!hit(&dashed, 15.0, 0.0));
+        assert!(hit(&dashed, 25.0, 0.0));
+        let offset = StrokeStyle::new()
+            .dash_pattern(&[10.0, 10.0])
+            .dash_offset(5.0);
+        assert!(hit(&offset, 2.0, 0.0));
+        assert!(!hit(&offset, 7.0, 0.0));
+        assert!(hit(&offset, 17.0, 0.0));
+
+        // the ends of the line keep the line cap.
+        let round_dashes = dashed.clone().dash_cap(LineCap::Round);
+        assert!(!hit(&round_dashes, -3.0, 0.0));
+        assert!(hit(&round_dashes, 13.0, 0.0));
+        assert!(!hit(&round_dashes, 13.0, 4.5));
+        let butt_dashes = round.dash_pattern(&[10.0, 10.0]).dash_cap(LineCap::Butt);
+        assert!(hit(&butt_dashes, -3.0, 0.0));
+        assert!(!hit(&butt_dashes, 13.0, 0.0));
+
+        // the last dash of a closed path carries on into the first.
+        let rect = Rect::new(0.0, 0.0, 100.0, 100.0);
+        let around = StrokeStyle::new()
+            .dash_pattern(&[10.0, 10.0])
+            .dash_offset(5.0);
+        let point = Point::new(-4.0, -4.0);
+        assert!(stroke_contains(
+            rect,
+            Affine::IDENTITY,
+            point,
+            10.0,
+            &around
+        ));
+    }
+
+    #[test]
+    fn test_dash() {
+        let starts = |path: &BezPath| -> Vec<Point> {
+            path.elements()
+                .iter()
+                .filter_map(|el| match el {
+                    PathEl::MoveTo(p) => Some(*p),
+                    _ => None,
+                })
+                .collect()
+        };
+        let line = kurbo::Line::new((0.0, 0.0), (10.0, 0.0));
+        let dashed = dash(line, &[2.0, 3.0], 1.0);
+        let xs: Vec<f64> = starts(&dashed).iter().map(|p| p.x).collect();
+        assert_eq!(xs, vec![0.0, 4.0, 9.0]);
+        assert!((dashed.perimeter(1e-3) - 4.0).abs() < 1e-6);
+
+        // dashes of zero length are dots.
+        let dotted = dash(line, &[0.0, 4.0], 0.0);
+        assert_eq!(dotted.elements().len(), 6);
+        assert_eq!(dotted.elements()[5], PathEl::LineTo(Point::new(8.0, 0.0)));
+
+        // the dash over the start of a closed path is one dash.
+        let rect = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let dashed = dash(rect, &[5.0, 5.0], 2.5);
+        let expected = [(0.0, 2.5), (7.5, 0.0), (10.0, 7.5), (2.5, 10.0)];
+        assert_eq!(starts(&dashed), expected.map(Point::from));
+        assert!((dashed.perimeter(1e-3) - 20.0).abs() < 1e-6);
+        assert_eq!(dash(rect, &[50.0, 5.0], 0.0), rect.into_path(0.1));
+
+        // curves are split, not flattened.
+        let circle = kurbo::Circle::new((0.0, 0.0), 10.0).into_path(1e-3);
+        let dashed = dash(&circle, &[circle.perimeter(1e-9) / 40.0], 0.0);
+        assert!(dashed
+            .elements()
+            .iter()
+            .all(|el| !matches!(el, PathEl::LineTo(_))));
+        let half = circle.perimeter(1e-6) / 2.0;
+        assert!((dashed.perimeter(1e-6) - half).abs() < 1e-3);
+
+        assert_eq!(dash(line, &[], 0.0), line.into_path(0.1));
+        assert_eq!(dash(line, &[1.0, -1.0], 0.0), line.into_path(0.1));
+    }
+
+    #[test]
+    fn test_split_polyline() {
+        use crate::GradientStops;
+
+        let corner = [
+            Point::new(0.0, 0.0),
+            Point::new(10.0, 0.0),
+            Point::new(10.0, 10.0),
+        ];
+        let pieces = split_polyline(&corner, false, 5.0, 0.0);
+        // the cut that would be at the corner is moved past it.
+        let cuts: Vec<_> = pieces.iter().map(|piece| piece.1).collect();
+        assert_eq!(cuts, vec![5.0, 11.25, 16.25, 20.0]);
+        assert_eq!(
+            pieces[1].2,
+            vec![
+                corner[1] - Vec2::new(5.0, 0.0),
+                corner[1],
+                Point::new(10.0, 1.25)
+            ]
+        );
+
+        let overlapped = split_polyline(&corner, false, 5.0, 1.0);
+        assert_eq!(overlapped[0].2.last(), Some(&Point::new(6.0, 0.0)));
+        assert_eq!(overlapped[3].2, pieces[3].2);
+
+        // the start of a closed polyline is inside its last piece.
+        let pieces = split_polyline(&corner, true, 100.0, 0.0);
+        assert_eq!(pieces.len(), 1);
+        let (start, end, points, closed) = &pieces[0];
+        assert_eq!((*start, *closed), (2.5, false));
+        assert!((end - (22.5 + 200f64.sqrt())).abs() < 1e-9);
+        assert_eq!(points[0], Point::new(2.5, 0.0));
+        assert_eq!(&points[3..], &[Point::ZERO, Point::new(2.5, 0.0)]);
+
+        assert_eq!(gradient_color(&[], 0.5), Color::TRANSPARENT);
+        let stops = (Color::BLACK, Color::WHITE).to_vec();
+        assert_eq!(gradient_color(&stops, -1.0), Color::BLACK);
+        assert_eq!(gradient_color(&stops, 0.5), Color::grey(0.5));
+    }
+
+    #[test]
+    fn test_stroke_joins() {
+        use crate::kurbo::BezPath;
+
+        let mut corner = BezPath::new();
+        corner.move_to((0.0, 0.0));
+        corner.line_to((100.0, 0.0));
+        corner.line_to((100.0, 100.0));
+        let hit = |join, x, y| {
+            let style = StrokeStyle::new().line_join(join);
+            stroke_contains(&corner, Affine::IDENTITY, Point::new(x, y), 10.0, &style)
+        };
+        let miter = LineJoin::Miter { limit: 10.0 };
+        assert!(hit(miter, 104.0, -4.0));
+        assert!(!hit(LineJoin::Miter { limit: 1.0 }, 104.0, -4.0));
+        assert!(!hit(LineJoin::Bevel, 104.0, -4.0));
+        assert!(hit(LineJoin::Bevel, 101.0, -1.0));
+        assert!(hit(LineJoin::Round, 103.0, -3.0));
+        assert!(!hit(LineJoin::Round, 104.0, -4.0));
+    }
+
+    #[test]
+    fn test_stroke_scale() {
+        use crate::kurbo::Line;
+
+        let line = Line::new((0.0, 0.0), (10.0, 0.0));
+        let transform = Affine::scale(10.0);
+        let scaled = StrokeStyle::new();
+        let fixed = StrokeStyle::new().scale(false);
+        assert!(stroke_contains(
+            line,
+            transform,
+            Point::new(50.0, 9.0),
+            2.0,
+            &scaled
+        ));
+        assert!(!stroke_contains(
+            line,
+            transform,
+            Point::new(50.0, 1.1),
+            2.0,
+            &fixed
+        ));
+        assert!(stroke_contains(
+            line,
+            transform,
+            Point::new(50.0, 0.9),
+            2.0,
+            &fixed
+        ));
+    }
 }
@@ -0,0 +1,331 @@
+//! Software versions of [`ImageFilter`]s, for backends that can't apply
+//! them natively.
+
+use crate::kurbo::{Rect, Size, Vec2};
+use crate::{Color, ImageFilter};
+
+/// How far blur kernels reach, in standard deviations.
+const KERNEL_REACH: f64 = 3.0;
+
+/// The most pixels that a blur kernel reaches, however large its radius.
+const MAX_KERNEL_REACH: usize = 1024;
+
+/// Pixels that [`ImageFilter`]s were applied to, by [`filter_pixels`].
+#[derive(Debug, Clone)]
+pub struct FilteredPixels {
+    /// The width of the pixels.
+    pub width: usize,
+    /// The height of the pixels.
+    pub height: usize,
+    /// The pixels, as premultiplied RGBA.
+    pub data: Vec<u8>,
+    /// Where the pixels are, in the pixels of the image that was filtered.
+    ///
+    /// Filters that spread, such as blurs, make this larger than the image.
+    pub rect: Rect,
+}
+
+impl FilteredPixels {
+    /// Where to draw the pixels, when the image that was filtered, of
+    /// `image_size`, is drawn into `dst_rect`.
+    pub fn dst_rect(&self, image_size: Size, dst_rect: Rect) -> Rect {
+        let sx = dst_rect.width() / image_size.width;
+        let sy = dst_rect.height() / image_size.height;
+        Rect::new(
+            dst_rect.x0 + self.rect.x0 * sx,
+            dst_rect.y0 + self.rect.y0 * sy,
+            dst_rect.x0 + self.rect.x1 * sx,
+            dst_rect.y0 + self.rect.y1 * sy,
+        )
+    }
+}
+
+/// Apply `filters`, in order, to an image of premultiplied RGBA pixels.
+///
+/// `scale` is the number of pixels of the image in each unit of the user
+/// space that the lengths of the filters are in, along each axis; for an
+/// image that is drawn into a rect, that is the size of the image divided
+/// by the size of the rect. Shadows are moved by whole pixels.
+pub fn filter_pixels(
+    width: usize,
+    height: usize,
+    data: &[u8],
+    filters: &[ImageFilter],
+    scale: Vec2,
+) -> FilteredPixels {
+    let scale = Vec2::new(scale.x.abs(), scale.y.abs());
+    let mut pixels = Pixels {
+        x: 0,
+        y: 0,
+        width,
+        height,
+        data: data[..width * height * 4]
+            .iter()
+            .map(|&byte| byte as f32 * (1.0 / 255.0))
+            .collect(),
+    };
+    for filter in filters {
+        pixels = match *filter {
+            ImageFilter::Blur { radius } => {
+                pixels.blurred(radius.abs() * scale.x, radius.abs() * scale.y)
+            }
+            ImageFilter::DropShadow {
+                offset,
+                radius,
+                color,
+            } => {
+                let offset = (offset.x * scale.x, offset.y * scale.y);
+                let shadow = pixels
+                    .tinted(color)
+                    .blurred(radius.abs() * scale.x, radius.abs() * scale.y)
+                    .moved(offset.0.round() as isize, offset.1.round() as isize);
+                pixels.over(&shadow)
+            }
+            ImageFilter::ColorMatrix(matrix) => pixels.color_matrix(&matrix),
+        };
+    }
+    FilteredPixels {
+        width: pixels.width,
+        height: pixels.height,
+        data: pixels
+            .data
+            .iter()
+            .map(|&value| (value.clamp(0.0, 1.0) * 255.0).round() as u8)
+            .collect(),
+        rect: Rect::new(
+            pixels.x as f64,
+            pixels.y as f64,
+            (pixels.x + pixels.width as isize) as f64,
+            (pixels.y + pixels.height as isize) as f64,
+        ),
+    }
+}
+
+/// Premultiplied RGBA pixels, from `0.0` to `1.0`, at a position in the
+/// pixels of the image.
+struct Pixels {
+    x: isize,
+    y: isize,
+    width: usize,
+    height: usize,
+    data: Vec<f32>,
+}
+
+impl Pixels {
+    fn empty(x: isize, y: isize, width: usize, height: usize) -> Pixels {
+        Pixels {
+            x,
+            y,
+            width,
+            height,
+            data: vec![0.0; width * height * 4],
+        }
+    }
+
+    fn pixel(&self, col: usize, row: usize) -> &[f32] {
+        &self.data[(row * self.width + col) * 4..][..4]
+    }
+
+    /// Blurred, with the standard deviations `sigma_x` and `sigma_y`, in
+    /// pixels, into pixels that are larger by as far as the blur reaches.
+    fn blurred(&self, sigma_x: f64, sigma_y: f64) -> Pixels {
+        let (kernel_x, kernel_y) = (kernel(sigma_x), kernel(sigma_y));
+        let (reach_x, reach_y) = (kernel_x.len() / 2, kernel_y.len() / 2);
+
+        let mut wide = Pixels::empty(
+            self.x - reach_x as isize,
+            self.y,
+            self.width + 2 * reach_x,
+            self.height,
+        );
+        for row in 0..self.height {
+            for col in 0..self.width {
+                let src = self.pixel(col, row);
+                if src[3] == 0.0 && src[..3].iter().all(|&c| c == 0.0) {
+                    continue;
+                }
+                for (k, &weight) in kernel_x.iter().enumerate() {
+                    let dst = (row * wide.width + col + k) * 4;
+                    for (value, &c) in wide.data[dst..dst + 4].iter_mut().zip(src) {
+                        *value += c * weight;
+                    }
+                }
+            }
+        }
+
+        let mut blurred = Pixels::empty(
+            wide.x,
+            self.y - reach_y as isize,
+            wide.width,
+            self.height + 2 * reach_y,
+        );
+        for row in 0..wide.height {
+            for col in 0..wide.width {
+                let src = wide.pixel(col, row);
+                if src[3] == 0.0 && src[..3].iter().all(|&c| c == 0.0) {
+                    continue;
+                }
+                for (k, &weight) in kernel_y.iter().enumerate() {
+                    let dst = ((row + k) * blurred.width + col) * 4;
+                    for (value, &c) in blurred.data[dst..dst + 4].iter_mut().zip(src) {
+                        *value += c * weight;
+                    }
+                }
+            }
+        }
+        blurred
+    }
+
+    /// The alpha of the pixels, in `color`.
+    fn tinted(&self, color: Color) -> Pixels {
+        let (r, g, b, a) = color.as_rgba();
+        let premul = [(r * a) as f32, (g * a) as f32, (b * a) as f32, a as f32];
+        let mut data = Vec::with_capacity(self.data.len());
+        for px in self.data.chunks_exact(4) {
+            data.extend(premul.iter().map(|&c| c * px[3]));
+        }
+        Pixels { data, ..*self }
+    }
+
+    fn moved(self, dx: isize, dy: isize) -> Pixels {
+        Pixels {
+            x: self.x + dx,
+            y: self.y + dy,
+            ..self
+        }
+    }
+
+    /// These pixels drawn over `below`, into pixels that cover both.
+    fn over(&self, below: &Pixels) -> Pixels {
+        let x = self.x.min(below.x);
+        let y = self.y.min(below.y);
+        let x1 = (self.x + self.width as isize).max(below.x + below.width as isize);
+        let y1 = (self.y + self.height as isize).max(below.y + below.height as isize);
+        let mut result = Pixels::empty(x, y, (x1 - x) as usize, (y1 - y) as usize);
+        result.draw(below);
+        result.draw(self);
+        result
+    }
+
+    /// Draw `src` over these pixels, which must cover all of it.
+    fn draw(&mut self, src: &Pixels) {
+        for row in 0..src.height {
+            let dst_row = (src.y - self.y) as usize + row;
+            let dst_col = (src.x - self.x) as usize;
+            let start = (dst_row * self.width + dst_col) * 4;
+            let dst = &mut self.data[start..start + src.width * 4];
+            let src = &src.data[row * src.width * 4..][..src.width * 4];
+            for (dst, src) in dst.chunks_exact_mut(4).zip(src.chunks_exact(4)) {
+                let keep = 1.0 - src[3];
+                for c in 0..4 {
+                    dst[c] = src[c] + dst[c] * keep;
+                }
+            }
+        }
+    }
+
+    fn color_matrix(mut self, matrix: &[f64; 20]) -> Pixels {
+        for px in self.data.chunks_exact_mut(4) {
+            let a = px[3];
+            let color = if a > 0.0 {
+                [px[0] / a, px[1] / a, px[2] / a, a]
+            } else {
+                [0.0; 4]
+            };
+            let mut result = [0.0f32; 4];
+            for (value, row) in result.iter_mut().zip(matrix.chunks_exact(5)) {
+                let sum = row[4] + (0..4).map(|i| row[i] * color[i] as f64).sum::<f64>();
+                *value = (sum as f32).clamp(0.0, 1.0);
+            }
+            let a = result[3];
+            px.copy_from_slice(&[result[0] * a, result[1] * a, result[2] * a, a]);
+        }
+        self
+    }
+}
+
+/// The weights of a Gaussian blur with the standard deviation `sigma`, from
+/// as far as it reaches on one side to as far on the other.
+fn kernel(sigma: f64) -> Vec<f32> {
+    if !(sigma > 0.0 && sigma.is_finite()) {
+        return vec![1.0];
+    }
+    let reach = ((KERNEL_REACH * sigma).ceil() as usize).min(MAX_KERNEL_REACH);
+    let weights: Vec<f64> = (0..=2 * reach)
+        .map(|i| {
+            let d = i as f64 - reach as f64;
+            (-d * d / (2.0 * sigma * sigma)).exp()
+        })
+        .collect();
+    let total: f64 = weights.iter().sum();
+    weights.iter().map(|w| (w / total) as f32).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A white square of `size` pixels, in the middle of a transparent
+    /// border of `border` pixels.
+    fn square(size: usize, border: usize) -> (usize, Vec<u8>) {
+        let width = size + 2 * border;
+        let mut data = vec![0; width * width * 4];
+        for row in border..border + size {
+            for col in border..border + size {
+                data[(row * width + col) * 4..][..4].copy_from_slice(&[255; 4]);
+            }
+        }
+        (width, data)
+    }
+
+    fn alpha_sum(pixels: &FilteredPixels) -> f64 {
+        pixels.data.chunks_exact(4).map(|px| px[3] as f64).sum()
+    }
+
+    #[test]
+    fn blur_spreads_and_keeps_coverage() {
+        let (width, data) = square(4, 0);
+        let filters = [ImageFilter::blur(1.0)];
+        let blurred = filter_pixels(width, width, &data, &filters, Vec2::new(2.0, 2.0));
+        assert_eq!(blurred.rect, Rect::new(-6.0, -6.0, 10.0, 10.0));
+        assert_eq!((blurred.width, blurred.height), (16, 16));
+        let before = 16.0 * 255.0;
+        assert!((alpha_sum(&blurred) - before).abs() < before * 0.01);
+        // the corner is faint, and the center is still mostly covered.
+        assert_eq!(blurred.data[3], 0);
+        assert!(blurred.data[(8 * 16 + 8) * 4 + 3] > 64);
+    }
+
+    #[test]
+    fn drop_shadow_is_under_the_image() {
+        let (width, data) = square(2, 0);
+        let filters = [ImageFilter::drop_shadow((3.0, 0.0), 0.0, Color::BLACK)];
+        let shadowed = filter_pixels(width, width, &data, &filters, Vec2::new(1.0, 1.0));
+        assert_eq!(shadowed.rect, Rect::new(0.0, 0.0, 5.0, 2.0));
+        let px = |col: usize| &shadowed.data[col * 4..][..4];
+        assert_eq!(px(0), [255; 4]);
+        assert_eq!(px(2), [0; 4]);
+        assert_eq!(px(3), [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn color_matrices() {
+        let data = [255, 0, 0, 255, 0, 0, 128, 128];
+        let gray = filter_pixels(
+            2,
+            1,
+            &data,
+            &[ImageFilter::saturate(0.0)],
+            Vec2::new(1., 1.),
+        );
+        assert_eq!(gray.data[..4], [54, 54, 54, 255]);
+        assert_eq!(gray.data[4..], [9, 9, 9, 128]);
+
+        let faded = filter_pixels(2, 1, &data, &[ImageFilter::opacity(0.5)], Vec2::new(1., 1.));
+        assert_eq!(faded.data, [128, 0, 0, 128, 0, 0, 64, 64]);
+        assert_eq!(
+            faded.dst_rect(Size::new(2., 1.), Rect::new(10., 10., 14., 11.)),
+            Rect::new(10., 10., 14., 11.)
+        );
+    }
+}
@@ -0,0 +1,304 @@
+//! Drawing gradients into pixels, for backends that dither them in
+//! software.
+
+use crate::kurbo::{Affine, Point};
+use crate::{Color, FixedGradient, GradientInterpolation, GradientStop};
+
+use super::gradient_rgba;
+
+/// The position of `point` along `gradient`, where the first stop is at 0
+/// and the last at 1.
+///
+/// Points before the start and past the end are outside of that range;
+/// gradients pad, so that they have the colors of the nearest stop. Radial
+/// gradients whose origin is outside of their circle only cover a cone,
+/// and points outside of it are at 0.
+pub fn gradient_position(gradient: &FixedGradient, point: Point) -> f64 {
+    match gradient {
+        FixedGradient::Linear(linear) => {
+            let d = linear.end - linear.start;
+            let len2 = d.hypot2();
+            if len2 > 0.0 {
+                (point - linear.start).dot(d) / len2
+            } else {
+                0.0
+            }
+        }
+        FixedGradient::Radial(radial) => {
+            // the point is on the circle that is `t` of the way from the
+            // origin, with a radius of 0, to the gradient's circle, where
+            // `|p - t * e| = t * radius`.
+            let p = point - (radial.center + radial.origin_offset);
+            let e = -radial.origin_offset;
+            let a = e.hypot2() - radial.radius * radial.radius;
+            let b = p.dot(e);
+            let c = p.hypot2();
+            if a.abs() < 1e-9 {
+                return if b > 0.0 { c / (2.0 * b) } else { 0.0 };
+            }
+            let disc = b * b - a * c;
+            if disc < 0.0 {
+                return 0.0;
+            }
+            let root = disc.sqrt();
+            ((b + root) / a).max((b - root) / a)
+        }
+    }
+}
+
+/// Draw `gradient` into `width` by `height` pixels of premultiplied RGBA,
+/// with its colors dithered.
+///
+/// The pixels are those of a target from (`x`, `y`), and `to_gradient` maps
+/// the target's pixels, whose centers are at half pixels, to the space of
+/// the gradient. The colors are rounded to 8 bits with a 4×4 ordered
+/// dither, which is aligned to the target, so that the pixels of regions
+/// that are drawn separately line up.
+pub fn dithered_gradient_pixels(
+    gradient: &FixedGradient,
+    to_gradient: Affine,
+    x: i32,
+    y: i32,
+    width: usize,
+    height: usize,
+) -> Vec<u8> {
+    let stops = match gradient {
+        FixedGradient::Linear(linear) => &linear.stops,
+        FixedGradient::Radial(radial) => &radial.stops,
+    };
+    let mut data = vec![0u8; width * height * 4];
+    if width == 0 {
+        return data;
+    }
+    for (row, pixels) in data.chunks_exact_mut(width * 4).enumerate() {
+        let py = y + row as i32;
+        for (col, px) in pixels.chunks_exact_mut(4).enumerate() {
+            let px_x = x + col as i32;
+            let point = to_gradient * Point::new(px_x as f64 + 0.5, py as f64 + 0.5);
+            let t = gradient_position(gradient, point);
+            let [r, g, b, a] = match gradient_rgba(stops, t as f32) {
+                Some(rgba) => rgba,
+                None => return data,
+            };
+            let threshold = (bayer(px_x, py) as f64 + 0.5) / 16.0 - 0.5;
+            let round = |c: f64| (c * 255.0 + threshold).round().clamp(0.0, 255.0) as u8;
+            let alpha = round(a);
+            for (c, value) in px.iter_mut().zip([r * a, g * a, b * a]) {
+                *c = round(value).min(alpha);
+            }
+            px[3] = alpha;
+        }
+    }
+    data
+}
+
+/// The number of pieces that the span between two stops is split into, to
+/// mix their colors in another space.
+const INTERPOLATION_STEPS: usize = 16;
+
+/// `gradient`, with its colors mixed in the space of `interpolation`.
+///
+/// Backends mix the colors of gradients in sRGB. This approximates mixing in
+/// another space with stops between those of `gradient`, whose colors are
+/// mixed in that space, so that a backend can draw it as it draws other
+/// gradients. Gradients in sRGB are returned as they are.
+pub fn interpolated_gradient(
+    gradient: FixedGradient,
+    interpolation: GradientInterpolation,
+) -> FixedGradient {
+    match gradient {
+        FixedGradient::Linear(mut linear) => {
+            linear.stops = interpolated_stops(&linear.stops, interpolation);
+            FixedGradient::Linear(linear)
+        }
+        FixedGradient::Radial(mut radial) => {
+            radial.stops = interpolated_stops(&radial.stops, interpolation);
+            FixedGradient::Radial(radial)
+        }
+    }
+}
+
+/// A conversion of the RGB channels of a color between spaces.
+type ColorConversion = fn([f64; 3]) -> [f64; 3];
+
+/// `stops`, with stops between them whose colors are mixed in the space of
+/// `interpolation`.
+///
+/// Adjacent stops of the same color, or at the same position, have nothing
+/// to mix, so no stops are added between them.
+pub fn interpolated_stops(
+    stops: &[GradientStop],
+    interpolation: GradientInterpolation,
+) -> Vec<GradientStop> {
+    let (to_space, from_space): (ColorConversion, ColorConversion) = match interpolation {
+        GradientInterpolation::Srgb => return stops.to_vec(),
+        GradientInterpolation::LinearRgb => (srgb_to_linear, linear_to_srgb),
+        GradientInterpolation::Oklab => (
+            |c| linear_to_oklab(srgb_to_linear(c)),
+            |c| linear_to_srgb(oklab_to_linear(c)),
+        ),
+    };
+    let mut result = Vec::with_capacity(stops.len() * INTERPOLATION_STEPS);
+    for pair in stops.windows(2) {
+        let (start, end) = (&pair[0], &pair[1]);
+        result.push(start.clone());
+        if start.color == end.color || start.pos >= end.pos {
+            continue;
+        }
+        let (r0, g0, b0, a0) = start.color.as_rgba();
+        let (r1, g1, b1, a1) = end.color.as_rgba();
+        let (c0, c1) = (to_space([r0, g0, b0]), to_space([r1, g1, b1]));
+        for i in 1..INTERPOLATION_STEPS {
+            let t = i as f64 / INTERPOLATION_STEPS as f64;
+            let [r, g, b] = from_space([0, 1, 2].map(|j| c0[j] + (c1[j] - c0[j]) * t));
+            result.push(GradientStop {
+                pos: start.pos + (end.pos - start.pos) * t as f32,
+                color: Color::rgba(r, g, b, a0 + (a1 - a0) * t),
+            });
+        }
+    }
+    result.extend(stops.last().cloned());
+    result
+}
+
+fn srgb_to_linear(c: [f64; 3]) -> [f64; 3] {
+    c.map(|c| {
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    })
+}
+
+fn linear_to_srgb(c: [f64; 3]) -> [f64; 3] {
+    c.map(|c| {
+        if c <= 0.0031308 {
+            c * 12.92
+        } else {
+            1.055 * c.powf(1.0 / 2.4) - 0.055
+        }
+    })
+}
+
+/// Linear sRGB to OKLab, from <https://bottosson.github.io/posts/oklab/>.
+fn linear_to_oklab([r, g, b]: [f64; 3]) -> [f64; 3] {
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+    [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ]
+}
+
+fn oklab_to_linear([l, a, b]: [f64; 3]) -> [f64; 3] {
+    let l_ = (l + 0.3963377774 * a + 0.2158037573 * b).powi(3);
+    let m_ = (l - 0.1055613458 * a - 0.0638541728 * b).powi(3);
+    let s_ = (l - 0.0894841775 * a - 1.2914855480 * b).powi(3);
+    [
+        4.0767416621 * l_ - 3.3077115913 * m_ + 0.2309699292 * s_,
+        -1.2684380046 * l_ + 2.6097574011 * m_ - 0.3413193965 * s_,
+        -0.0041960863 * l_ - 0.7034186147 * m_ + 1.7076147010 * s_,
+    ]
+}
+
+/// The threshold of the 4×4 Bayer matrix at a pixel, from 0 to 15.
+fn bayer(x: i32, y: i32) -> u32 {
+    let (x, y) = (x as u32 & 3, y as u32 & 3);
+    let v = x ^ y;
+    ((v & 1) << 3) | ((y & 1) << 2) | (v & 2) | ((y & 2) >> 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kurbo::Vec2;
+    use crate::{FixedLinearGradient, FixedRadialGradient, GradientStops};
+
+    #[test]
+    fn bayer_matrix() {
+        let rows: Vec<Vec<u32>> = (0..4)
+            .map(|y| (0..4).map(|x| bayer(x, y)).collect())
+            .collect();
+        assert_eq!(
+            rows,
+            [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]]
+        );
+        assert_eq!(bayer(-1, -4), bayer(3, 0));
+    }
+
+    #[test]
+    fn radial_positions() {
+        let stops = (Color::BLACK, Color::WHITE).to_vec();
+        let centered = FixedGradient::Radial(FixedRadialGradient {
+            center: Point::new(10., 10.),
+            origin_offset: Vec2::ZERO,
+            radius: 5.,
+            stops: stops.clone(),
+        });
+        assert_eq!(gradient_position(&centered, Point::new(10., 10.)), 0.0);
+        assert_eq!(gradient_position(&centered, Point::new(13., 14.)), 1.0);
+        let focused = FixedGradient::Radial(FixedRadialGradient {
+            center: Point::new(10., 10.),
+            origin_offset: Vec2::new(-2., 0.),
+            radius: 5.,
+            stops,
+        });
+        assert_eq!(gradient_position(&focused, Point::new(8., 10.)), 0.0);
+        assert!((gradient_position(&focused, Point::new(15., 10.)) - 1.0).abs() < 1e-9);
+        assert!((gradient_position(&focused, Point::new(5., 10.)) - 1.0).abs() < 1e-9);
+        assert!((gradient_position(&focused, Point::new(11.5, 10.)) - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn dithering_keeps_the_average_color() {
+        // a step of a sixteenth of 8 bits, over 16 pixels.
+        let gradient = FixedGradient::Linear(FixedLinearGradient {
+            start: Point::new(0., 0.),
+            end: Point::new(16., 0.),
+            stops: (Color::rgb8(10, 10, 10), Color::rgb8(11, 11, 11)).to_vec(),
+        });
+        let data = dithered_gradient_pixels(&gradient, Affine::IDENTITY, 0, 0, 16, 4);
+        assert!(data.chunks_exact(4).all(|px| px[3] == 255));
+        let values: Vec<u8> = data.chunks_exact(4).map(|px| px[0]).collect();
+        assert!(values.iter().all(|&v| v == 10 || v == 11));
+        let average = values.iter().map(|&v| v as f64).sum::<f64>() / values.len() as f64;
+        assert!((average - 10.5).abs() < 0.1, "{}", average);
+        // without dithering, the left and right halves would each be solid.
+        let left = &values[..8];
+        assert!(left.contains(&10) && left.contains(&11));
+    }
+
+    #[test]
+    fn interpolated_stops_keep_saturation() {
+        let stops = (Color::rgb8(255, 0, 0), Color::rgb8(0, 255, 0)).to_vec();
+        assert_eq!(
+            interpolated_stops(&stops, GradientInterpolation::Srgb).len(),
+            2
+        );
+        for interpolation in [
+            GradientInterpolation::LinearRgb,
+            GradientInterpolation::Oklab,
+        ] {
+            let result = interpolated_stops(&stops, interpolation);
+            assert_eq!(result.len(), INTERPOLATION_STEPS + 1);
+            assert_eq!(result.first().unwrap().color, stops[0].color);
+            assert_eq!(result.last().unwrap().color, stops[1].color);
+            assert!(result.windows(2).all(|w| w[0].pos < w[1].pos));
+            // mixed in sRGB, the middle is (128, 128, 0).
+            let middle = &result[INTERPOLATION_STEPS / 2];
+            assert_eq!(middle.pos, 0.5);
+            let (r, g, _, a) = middle.color.as_rgba8();
+            assert!(r > 160 && g > 160, "{:?}: {} {}", interpolation, r, g);
+            assert_eq!(a, 255);
+        }
+        // nothing is added between stops of the same color.
+        let flat = (Color::WHITE, Color::WHITE).to_vec();
+        assert_eq!(
+            interpolated_stops(&flat, GradientInterpolation::Oklab).len(),
+            2
+        );
+    }
+}
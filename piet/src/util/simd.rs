@@ -0,0 +1,345 @@
+//! Vectorized versions of the pixel conversions in [`util`](super).
+//!
+//! Each function converts as many whole blocks of pixels as it can from the
+//! start of the buffers, and returns how many pixels it converted; the caller
+//! converts the rest one at a time. The results are exactly the same as those
+//! of [`premul`](super::premul) and [`unpremul`](super::unpremul).
+//!
+//! SSE2 is always available on x86_64, and SSSE3 is detected at runtime. On
+//! wasm32 these are used when the crate is built with the `simd128` target
+//! feature. Everywhere else nothing is converted here.
+
+#[cfg(target_arch = "x86_64")]
+pub(super) use x86::*;
+
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+pub(super) use wasm::*;
+
+#[cfg(not(any(
+    target_arch = "x86_64",
+    all(target_arch = "wasm32", target_feature = "simd128")
+)))]
+pub(super) use fallback::*;
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    /// Premultiply whole blocks of 4 RGBA pixels.
+    pub fn premultiply_rgba(data: &mut [u8]) -> usize {
+        let mut blocks = data.chunks_exact_mut(16);
+        let count = blocks.len();
+        // SAFETY: SSE2 is part of x86_64, and each block is 16 bytes.
+        unsafe {
+            let zero = _mm_setzero_si128();
+            let alpha = _mm_set1_epi32(0xff00_0000_u32 as i32);
+            let round = _mm_set1_epi16(0x80);
+            for block in &mut blocks {
+                let ptr = block.as_mut_ptr() as *mut __m128i;
+                let v = _mm_loadu_si128(ptr);
+                let lo = premultiply_u16(_mm_unpacklo_epi8(v, zero), round);
+                let hi = premultiply_u16(_mm_unpackhi_epi8(v, zero), round);
+                let color = _mm_packus_epi16(lo, hi);
+                let out = _mm_or_si128(_mm_and_si128(alpha, v), _mm_andnot_si128(alpha, color));
+                _mm_storeu_si128(ptr, out);
+            }
+        }
+        count * 4
+    }
+
+    /// Premultiply two pixels with 16-bit channels.
+    #[inline(always)]
+    unsafe fn premultiply_u16(px: __m128i, round: __m128i) -> __m128i {
+        let a = _mm_shufflehi_epi16(_mm_shufflelo_epi16(px, 0xff), 0xff);
+        let y = _mm_mullo_epi16(px, a);
+        let y = _mm_add_epi16(_mm_add_epi16(y, _mm_srli_epi16(y, 8)), round);
+        _mm_srli_epi16(y, 8)
+    }
+
+    /// Unpremultiply whole blocks of 4 RGBA pixels.
+    pub fn unpremultiply_rgba(data: &mut [u8]) -> usize {
+        let mut blocks = data.chunks_exact_mut(16);
+        let count = blocks.len();
+        // SAFETY: SSE2 is part of x86_64, and each block is 16 bytes.
+        unsafe {
+            let zero = _mm_setzero_si128();
+            let alpha = _mm_set1_epi32(0xff00_0000_u32 as i32);
+            for block in &mut blocks {
+                let ptr = block.as_mut_ptr() as *mut __m128i;
+                let v = _mm_loadu_si128(ptr);
+                let lo = _mm_unpacklo_epi8(v, zero);
+                let hi = _mm_unpackhi_epi8(v, zero);
+                let lo = _mm_packs_epi32(
+                    unpremultiply_u32(_mm_unpacklo_epi16(lo, zero)),
+                    unpremultiply_u32(_mm_unpackhi_epi16(lo, zero)),
+                );
+                let hi = _mm_packs_epi32(
+                    unpremultiply_u32(_mm_unpacklo_epi16(hi, zero)),
+                    unpremultiply_u32(_mm_unpackhi_epi16(hi, zero)),
+                );
+                let color = _mm_packus_epi16(lo, hi);
+                // transparent pixels are left as they are.
+                let transparent = _mm_cmpeq_epi32(_mm_and_si128(v, alpha), zero);
+                let keep = _mm_or_si128(alpha, transparent);
+                let out = _mm_or_si128(_mm_and_si128(keep, v), _mm_andnot_si128(keep, color));
+                _mm_storeu_si128(ptr, out);
+            }
+        }
+        count * 4
+    }
+
+    /// Unpremultiply one pixel with 32-bit channels.
+    ///
+    /// The quotient is exact after truncation, since it is at least `1 / a`
+    /// away from the next integer when it isn't one.
+    #[inline(always)]
+    unsafe fn unpremultiply_u32(px: __m128i) -> __m128i {
+        let a = _mm_shuffle_epi32(px, 0xff);
+        let x = _mm_sub_epi32(_mm_slli_epi32(px, 8), px);
+        let x = _mm_add_epi32(x, _mm_srli_epi32(a, 1));
+        let y = _mm_div_ps(_mm_cvtepi32_ps(x), _mm_cvtepi32_ps(a));
+        _mm_cvttps_epi32(_mm_min_ps(y, _mm_set1_ps(255.0)))
+    }
+
+    /// Swap the red and blue channels of whole blocks of 4 pixels.
+    pub fn swap_red_blue(data: &mut [u8]) -> usize {
+        let mut blocks = data.chunks_exact_mut(16);
+        let count = blocks.len();
+        // SAFETY: SSE2 is part of x86_64, and each block is 16 bytes.
+        unsafe {
+            let green_alpha = _mm_set1_epi32(0xff00_ff00_u32 as i32);
+            let low = _mm_set1_epi32(0xff);
+            for block in &mut blocks {
+                let ptr = block.as_mut_ptr() as *mut __m128i;
+                let v = _mm_loadu_si128(ptr);
+                let red = _mm_slli_epi32(_mm_and_si128(v, low), 16);
+                let blue = _mm_and_si128(_mm_srli_epi32(v, 16), low);
+                let out = _mm_or_si128(_mm_and_si128(v, green_alpha), _mm_or_si128(red, blue));
+                _mm_storeu_si128(ptr, out);
+            }
+        }
+        count * 4
+    }
+
+    /// Expand RGB pixels to opaque RGBA, in blocks of 4 pixels.
+    ///
+    /// Each block is read with 16-byte loads, so the last 4 bytes of `src`
+    /// are always left for the caller.
+    pub fn rgb_to_rgba(src: &[u8], dst: &mut [u8]) -> usize {
+        if is_x86_feature_detected!("ssse3") {
+            // SAFETY: SSSE3 was just detected.
+            unsafe { rgb_to_rgba_ssse3(src, dst) }
+        } else {
+            0
+        }
+    }
+
+    #[target_feature(enable = "ssse3")]
+    unsafe fn rgb_to_rgba_ssse3(src: &[u8], dst: &mut [u8]) -> usize {
+        let count = (src.len().saturating_sub(4) / 12).min(dst.len() / 16);
+        let shuffle = _mm_setr_epi8(0, 1, 2, -1, 3, 4, 5, -1, 6, 7, 8, -1, 9, 10, 11, -1);
+        let alpha = _mm_set1_epi32(0xff00_0000_u32 as i32);
+        for i in 0..count {
+            let v = _mm_loadu_si128(src.as_ptr().add(i * 12) as *const __m128i);
+            let out = _mm_or_si128(_mm_shuffle_epi8(v, shuffle), alpha);
+            _mm_storeu_si128(dst.as_mut_ptr().add(i * 16) as *mut __m128i, out);
+        }
+        count * 4
+    }
+
+    /// Expand grayscale pixels to opaque RGBA, in blocks of 16 pixels.
+    pub fn gray_to_rgba(src: &[u8], dst: &mut [u8]) -> usize {
+        let count = (src.len() / 16).min(dst.len() / 64);
+        // SAFETY: SSE2 is part of x86_64, and the blocks are in bounds.
+        unsafe {
+            let opaque = _mm_set1_epi8(-1);
+            for i in 0..count {
+                let v = _mm_loadu_si128(src.as_ptr().add(i * 16) as *const __m128i);
+                let out = dst.as_mut_ptr().add(i * 64) as *mut __m128i;
+                let gg_lo = _mm_unpacklo_epi8(v, v);
+                let gg_hi = _mm_unpackhi_epi8(v, v);
+                let ga_lo = _mm_unpacklo_epi8(v, opaque);
+                let ga_hi = _mm_unpackhi_epi8(v, opaque);
+                _mm_storeu_si128(out, _mm_unpacklo_epi16(gg_lo, ga_lo));
+                _mm_storeu_si128(out.add(1), _mm_unpackhi_epi16(gg_lo, ga_lo));
+                _mm_storeu_si128(out.add(2), _mm_unpacklo_epi16(gg_hi, ga_hi));
+                _mm_storeu_si128(out.add(3), _mm_unpackhi_epi16(gg_hi, ga_hi));
+            }
+        }
+        count * 16
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+mod wasm {
+    use std::arch::wasm32::*;
+
+    /// Premultiply whole blocks of 4 RGBA pixels.
+    pub fn premultiply_rgba(data: &mut [u8]) -> usize {
+        let mut blocks = data.chunks_exact_mut(16);
+        let count = blocks.len();
+        let alpha = u32x4_splat(0xff00_0000);
+        for block in &mut blocks {
+            let ptr = block.as_mut_ptr() as *mut v128;
+            // SAFETY: each block is 16 bytes, and wasm allows unaligned loads.
+            let v = unsafe { v128_load(ptr) };
+            let lo = premultiply_u16(u16x8_extend_low_u8x16(v));
+            let hi = premultiply_u16(u16x8_extend_high_u8x16(v));
+            let out = v128_bitselect(v, u8x16_narrow_i16x8(lo, hi), alpha);
+            unsafe { v128_store(ptr, out) };
+        }
+        count * 4
+    }
+
+    /// Premultiply two pixels with 16-bit channels.
+    #[inline(always)]
+    fn premultiply_u16(px: v128) -> v128 {
+        let a = i16x8_shuffle::<3, 3, 3, 3, 7, 7, 7, 7>(px, px);
+        let y = i16x8_mul(px, a);
+        let y = u16x8_add(u16x8_add(y, u16x8_shr(y, 8)), u16x8_splat(0x80));
+        u16x8_shr(y, 8)
+    }
+
+    /// Unpremultiply whole blocks of 4 RGBA pixels.
+    pub fn unpremultiply_rgba(data: &mut [u8]) -> usize {
+        let mut blocks = data.chunks_exact_mut(16);
+        let count = blocks.len();
+        let alpha = u32x4_splat(0xff00_0000);
+        for block in &mut blocks {
+            let ptr = block.as_mut_ptr() as *mut v128;
+            // SAFETY: each block is 16 bytes, and wasm allows unaligned loads.
+            let v = unsafe { v128_load(ptr) };
+            let lo = u16x8_extend_low_u8x16(v);
+            let hi = u16x8_extend_high_u8x16(v);
+            let lo = u16x8_narrow_i32x4(
+                unpremultiply_u32(u32x4_extend_low_u16x8(lo)),
+                unpremultiply_u32(u32x4_extend_high_u16x8(lo)),
+            );
+            let hi = u16x8_narrow_i32x4(
+                unpremultiply_u32(u32x4_extend_low_u16x8(hi)),
+                unpremultiply_u32(u32x4_extend_high_u16x8(hi)),
+            );
+            let color = u8x16_narrow_i16x8(lo, hi);
+            // transparent pixels are left as they are.
+            let transparent = u32x4_eq(v128_and(v, alpha), u32x4_splat(0));
+            let out = v128_bitselect(v, color, v128_or(alpha, transparent));
+            unsafe { v128_store(ptr, out) };
+        }
+        count * 4
+    }
+
+    /// Unpremultiply one pixel with 32-bit channels.
+    ///
+    /// The quotient is exact after truncation, since it is at least `1 / a`
+    /// away from the next integer when it isn't one.
+    #[inline(always)]
+    fn unpremultiply_u32(px: v128) -> v128 {
+        let a = i32x4_shuffle::<3, 3, 3, 3>(px, px);
+        let x = u32x4_add(u32x4_mul(px, u32x4_splat(255)), u32x4_shr(a, 1));
+        let y = f32x4_div(f32x4_convert_u32x4(x), f32x4_convert_u32x4(a));
+        u32x4_trunc_sat_f32x4(f32x4_pmin(y, f32x4_splat(255.0)))
+    }
+
+    /// Swap the red and blue channels of whole blocks of 4 pixels.
+    pub fn swap_red_blue(data: &mut [u8]) -> usize {
+        let mut blocks = data.chunks_exact_mut(16);
+        let count = blocks.len();
+        for block in &mut blocks {
+            let ptr = block.as_mut_ptr() as *mut v128;
+            // SAFETY: each block is 16 bytes, and wasm allows unaligned loads.
+            let v = unsafe { v128_load(ptr) };
+            let out = i8x16_shuffle::<2, 1, 0, 3, 6, 5, 4, 7, 10, 9, 8, 11, 14, 13, 12, 15>(v, v);
+            unsafe { v128_store(ptr, out) };
+        }
+        count * 4
+    }
+
+    /// Expand RGB pixels to opaque RGBA, in blocks of 4 pixels.
+    ///
+    /// Each block is read with 16-byte loads, so the last 4 bytes of `src`
+    /// are always left for the caller.
+    pub fn rgb_to_rgba(src: &[u8], dst: &mut [u8]) -> usize {
+        let count = (src.len().saturating_sub(4) / 12).min(dst.len() / 16);
+        let opaque = u8x16_splat(0xff);
+        for i in 0..count {
+            // SAFETY: the blocks are in bounds, and wasm allows unaligned loads.
+            unsafe {
+                let v = v128_load(src.as_ptr().add(i * 12) as *const v128);
+                let out = i8x16_shuffle::<0, 1, 2, 16, 3, 4, 5, 16, 6, 7, 8, 16, 9, 10, 11, 16>(
+                    v, opaque,
+                );
+                v128_store(dst.as_mut_ptr().add(i * 16) as *mut v128, out);
+            }
+        }
+        count * 4
+    }
+
+    /// Expand grayscale pixels to opaque RGBA, in blocks of 16 pixels.
+    pub fn gray_to_rgba(src: &[u8], dst: &mut [u8]) -> usize {
+        let count = (src.len() / 16).min(dst.len() / 64);
+        let opaque = u8x16_splat(0xff);
+        for i in 0..count {
+            // SAFETY: the blocks are in bounds, and wasm allows unaligned loads.
+            unsafe {
+                let v = v128_load(src.as_ptr().add(i * 16) as *const v128);
+                let out = dst.as_mut_ptr().add(i * 64) as *mut v128;
+                let a =
+                    i8x16_shuffle::<0, 0, 0, 16, 1, 1, 1, 16, 2, 2, 2, 16, 3, 3, 3, 16>(v, opaque);
+                let b =
+                    i8x16_shuffle::<4, 4, 4, 16, 5, 5, 5, 16, 6, 6, 6, 16, 7, 7, 7, 16>(v, opaque);
+                let c = i8x16_shuffle::<8, 8, 8, 16, 9, 9, 9, 16, 10, 10, 10, 16, 11, 11, 11, 16>(
+                    v, opaque,
+                );
+                let d = i8x16_shuffle::<
+                    12,
+                    12,
+                    12,
+                    16,
+                    13,
+                    13,
+                    13,
+                    16,
+                    14,
+                    14,
+                    14,
+                    16,
+                    15,
+                    15,
+                    15,
+                    16,
+                >(v, opaque);
+                v128_store(out, a);
+                v128_store(out.add(1), b);
+                v128_store(out.add(2), c);
+                v128_store(out.add(3), d);
+            }
+        }
+        count * 16
+    }
+}
+
+#[cfg(not(any(
+    target_arch = "x86_64",
+    all(target_arch = "wasm32", target_feature = "simd128")
+)))]
+mod fallback {
+    pub fn premultiply_rgba(_data: &mut [u8]) -> usize {
+        0
+    }
+
+    pub fn unpremultiply_rgba(_data: &mut [u8]) -> usize {
+        0
+    }
+
+    pub fn swap_red_blue(_data: &mut [u8]) -> usize {
+        0
+    }
+
+    pub fn rgb_to_rgba(_src: &[u8], _dst: &mut [u8]) -> usize {
+        0
+    }
+
+    pub fn gray_to_rgba(_src: &[u8], _dst: &mut [u8]) -> usize {
+        0
+    }
+}